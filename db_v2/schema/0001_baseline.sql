@@ -79,6 +79,36 @@ CREATE TABLE IF NOT EXISTS grp (
                                     created_at datetime not null default(current_timestamp), 
                                     "info" text not null default '{}') without rowid;
 INSERT OR IGNORE INTO grp VALUES(X'018f255622fb73ee9afdbbcdc0cc387b',X'018f255622f77778a006702ca5c23714','Default',NULL,'2024-04-28 15:32:33','{}');
+CREATE TABLE IF NOT EXISTS peer_grp (
+                                    peer blob primary key not null,
+                                    grp blob not null,
+                                    created_at datetime not null default(current_timestamp)
+) without rowid;
+CREATE TABLE IF NOT EXISTS grp_strategy (
+                                    grp blob primary key not null,
+                                    strategy blob,
+                                    modified_at datetime not null default(current_timestamp)
+) without rowid;
+CREATE TABLE IF NOT EXISTS peer_status_history (
+                                    guid blob primary key not null,
+                                    peer blob not null,
+                                    online tinyint not null,
+                                    created_at datetime not null default(current_timestamp)
+) without rowid;
+CREATE TABLE IF NOT EXISTS peer_uuid_conflict (
+                                    id varchar(100) not null,
+                                    uuid blob not null,
+                                    count integer not null default 1,
+                                    first_seen datetime not null default(current_timestamp),
+                                    last_seen datetime not null default(current_timestamp),
+                                    CONSTRAINT constraint_peer_uuid_conflict PRIMARY KEY (id, uuid)
+) without rowid;
+CREATE TABLE IF NOT EXISTS peer_avatar (
+                                    id varchar(100) primary key not null,
+                                    content_type varchar(30) not null,
+                                    data blob not null,
+                                    updated_at datetime not null default(current_timestamp)
+) without rowid;
 CREATE TABLE IF NOT EXISTS user_data (
                                     "user" blob not null,
                                     type varchar(30) not null,
@@ -120,6 +150,54 @@ CREATE TABLE IF NOT EXISTS audit_console (
                                     iop smallint not null,
                                     info text not null
 );
+CREATE TABLE IF NOT EXISTS audit_client_conn (
+                                    guid blob primary key not null,
+                                    peer varchar(100) not null,
+                                    conn_id integer not null,
+                                    session_id varchar(30),
+                                    type tinyint,
+                                    remote varchar(100),
+                                    remote_name varchar(100),
+                                    ip varchar(100),
+                                    created_at datetime not null default(strftime('%Y-%m-%d %H:%M:%f', 'now')),
+                                    opened_at datetime,
+                                    closed_at datetime,
+                                    duration integer,
+                                    info text not null
+);
+CREATE TABLE IF NOT EXISTS audit_client_file (
+                                    guid blob primary key not null,
+                                    peer varchar(100) not null,
+                                    remote varchar(100),
+                                    type tinyint not null,
+                                    path text not null,
+                                    is_file tinyint not null,
+                                    ip varchar(100),
+                                    files integer not null default 0,
+                                    size integer not null default 0,
+                                    created_at datetime not null default(strftime('%Y-%m-%d %H:%M:%f', 'now')),
+                                    info text not null
+);
+CREATE TABLE IF NOT EXISTS audit_client_alarm (
+                                    guid blob primary key not null,
+                                    peer varchar(100) not null,
+                                    type integer not null,
+                                    severity varchar(20) not null,
+                                    remote varchar(100),
+                                    remote_name varchar(100),
+                                    ip varchar(100),
+                                    created_at datetime not null default(strftime('%Y-%m-%d %H:%M:%f', 'now')),
+                                    info text not null
+);
+CREATE TABLE IF NOT EXISTS admin_audit (
+                                    guid blob primary key not null,
+                                    actor blob not null,
+                                    action varchar(100) not null,
+                                    route varchar(200) not null,
+                                    target varchar(200),
+                                    details text not null,
+                                    created_at datetime not null default(strftime('%Y-%m-%d %H:%M:%f', 'now'))
+);
 CREATE TABLE IF NOT EXISTS user_third_auth (
                                     "user" blob not null,
                                     type varchar(30) not null,
@@ -140,6 +218,15 @@ CREATE TABLE IF NOT EXISTS strategy (
                                     status tinyint not null default 0
 ) without rowid;
 INSERT OR IGNORE INTO strategy VALUES(X'018f255623167a02b31c5599e7cd5b5e',X'018f255622f77778a006702ca5c23714','Default','2024-04-28 15:32:33','2024-04-28 15:32:33','{}',0);
+CREATE TABLE IF NOT EXISTS strategies (
+                                    guid blob primary key not null,
+                                    name varchar(100) not null,
+                                    settings JSON not null default '{}',
+                                    is_default boolean not null default 0,
+                                    created_at datetime not null default(current_timestamp),
+                                    modified_at datetime not null default(current_timestamp)
+) without rowid;
+INSERT OR IGNORE INTO strategies(guid, name, settings, is_default) VALUES(X'018f255623167a02b31c5599e7cd5b5e','Default','{}',1);
 CREATE TABLE IF NOT EXISTS cross_grp (
     incoming blob not null,
     outgoing blob not null,
@@ -150,6 +237,27 @@ CREATE TABLE IF NOT EXISTS settings (
     key varchar(100) primary key not null,
     value text not null
 ) without rowid;
+CREATE TABLE IF NOT EXISTS software_release (
+                                    guid blob primary key not null,
+                                    platform varchar(30) not null,
+                                    channel varchar(30) not null default 'stable',
+                                    version varchar(100) not null,
+                                    key varchar(500) not null,
+                                    size integer not null,
+                                    sha256 varchar(64) not null,
+                                    uploaded_by blob not null,
+                                    created_at datetime not null default(strftime('%Y-%m-%d %H:%M:%f', 'now'))
+) without rowid;
+CREATE TABLE IF NOT EXISTS user_release_channel (
+                                    user blob primary key not null,
+                                    channel varchar(30),
+                                    modified_at datetime not null default(current_timestamp)
+) without rowid;
+CREATE TABLE IF NOT EXISTS grp_release_channel (
+                                    grp blob primary key not null,
+                                    channel varchar(30),
+                                    modified_at datetime not null default(current_timestamp)
+) without rowid;
 CREATE TABLE IF NOT EXISTS "custom_client" (
                                     "guid" blob primary key not null,
                                     "team" blob not null,
@@ -203,6 +311,8 @@ CREATE UNIQUE INDEX IF NOT EXISTS index_peer_id on peer (id);
 CREATE INDEX IF NOT EXISTS index_peer_user on peer ("user");
 CREATE INDEX IF NOT EXISTS index_peer_created_at on peer (created_at);
 CREATE INDEX IF NOT EXISTS index_peer_status on peer (status);
+CREATE INDEX IF NOT EXISTS index_peer_grp_grp on peer_grp (grp);
+CREATE INDEX IF NOT EXISTS index_peer_status_history_peer_created_at on peer_status_history (peer, created_at);
 CREATE UNIQUE INDEX IF NOT EXISTS index_user_name on "user" (name);
 CREATE UNIQUE INDEX IF NOT EXISTS index_user_email on "user" (email);
 CREATE INDEX IF NOT EXISTS index_user_group on "user" (grp);
@@ -225,13 +335,25 @@ CREATE INDEX IF NOT EXISTS index_audit_alarm_type on audit_alarm (type);
 CREATE INDEX IF NOT EXISTS index_audit_console_created_at on audit_console (created_at);
 CREATE INDEX IF NOT EXISTS index_audit_console_operator on audit_console (operator);
 CREATE INDEX IF NOT EXISTS index_audit_console_type on audit_console (type);
+CREATE UNIQUE INDEX IF NOT EXISTS uniq_audit_client_conn_peer_conn_id on audit_client_conn (peer, conn_id);
+CREATE INDEX IF NOT EXISTS index_audit_client_conn_created_at on audit_client_conn (created_at);
+CREATE INDEX IF NOT EXISTS index_audit_client_file_peer on audit_client_file (peer);
+CREATE INDEX IF NOT EXISTS index_audit_client_file_created_at on audit_client_file (created_at);
+CREATE INDEX IF NOT EXISTS index_audit_client_alarm_peer on audit_client_alarm (peer);
+CREATE INDEX IF NOT EXISTS index_audit_client_alarm_created_at on audit_client_alarm (created_at);
+CREATE INDEX IF NOT EXISTS index_admin_audit_created_at on admin_audit (created_at);
+CREATE INDEX IF NOT EXISTS index_admin_audit_actor on admin_audit (actor);
+CREATE INDEX IF NOT EXISTS index_software_release_channel_platform_created_at on software_release (channel, platform, created_at);
 CREATE UNIQUE INDEX IF NOT EXISTS uniq_user_third_auth_type_identifer on user_third_auth (type, identifier);
 CREATE INDEX IF NOT EXISTS index_strategy_name on strategy (name);
 CREATE INDEX IF NOT EXISTS index_strategy_team on strategy (team);
+CREATE UNIQUE INDEX IF NOT EXISTS uniq_strategies_name on strategies (name);
+CREATE UNIQUE INDEX IF NOT EXISTS uniq_strategies_default on strategies (is_default) WHERE is_default = 1;
 CREATE INDEX IF NOT EXISTS index_cross_grp_incoming on cross_grp (incoming);
 CREATE INDEX IF NOT EXISTS index_cross_grp_outgoing on cross_grp (outgoing);
 CREATE INDEX IF NOT EXISTS index_user_strategy ON user (strategy);
 CREATE INDEX IF NOT EXISTS index_peer_strategy ON peer (strategy);
+CREATE INDEX IF NOT EXISTS index_grp_strategy_strategy ON grp_strategy (strategy);
 CREATE INDEX IF NOT EXISTS index_audit_alarm_user ON audit_alarm (user);
 CREATE INDEX IF NOT EXISTS index_audit_alarm_device ON audit_alarm (device);
 CREATE UNIQUE INDEX IF NOT EXISTS "uniq_custom_client_name" on "custom_client" ("team", "name");
@@ -0,0 +1,3 @@
+-- the users created by an OAuth login have a random password, they can only log in with a linked provider
+-- until they set a password
+ALTER TABLE user ADD COLUMN password_login BOOLEAN NOT NULL DEFAULT 1;
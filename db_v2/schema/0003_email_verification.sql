@@ -0,0 +1,13 @@
+-- the users prove they own their email by following a link sent to it, the flag is cleared when the email changes
+ALTER TABLE user ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT 0;
+
+-- the tokens of the links sent by /api/user/email/verify-request, a user has at most one pending token
+CREATE TABLE IF NOT EXISTS email_verification (
+    token TEXT PRIMARY KEY NOT NULL,
+    "user" BLOB NOT NULL,
+    email TEXT NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at DATETIME NOT NULL,
+    FOREIGN KEY ("user") REFERENCES user (guid) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_email_verification_user ON email_verification ("user");
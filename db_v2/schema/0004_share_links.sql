@@ -0,0 +1,20 @@
+-- time limited links granting the users who redeem them the access to a peer, see /api/peer/<id>/share-link
+CREATE TABLE IF NOT EXISTS share_link (
+    guid BLOB PRIMARY KEY NOT NULL,
+    peer_id TEXT NOT NULL,
+    created_by BLOB NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at DATETIME NOT NULL,
+    FOREIGN KEY (created_by) REFERENCES user (guid) ON DELETE CASCADE
+);
+CREATE INDEX IF NOT EXISTS idx_share_link_peer ON share_link (peer_id);
+
+-- the users who redeemed a link, they can control the peer until the link expires
+CREATE TABLE IF NOT EXISTS share_link_grant (
+    link BLOB NOT NULL,
+    "user" BLOB NOT NULL,
+    redeemed_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (link, "user"),
+    FOREIGN KEY (link) REFERENCES share_link (guid) ON DELETE CASCADE,
+    FOREIGN KEY ("user") REFERENCES user (guid) ON DELETE CASCADE
+);
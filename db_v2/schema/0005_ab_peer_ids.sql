@@ -0,0 +1,27 @@
+-- the peers of the address books were saved with the id typed in the clients, e.g. "123 456 789",
+-- they are looked up by their canonical id without the spaces
+-- a peer whose canonical id is already in its address book keeps its id, it can be merged from the console,
+-- so does a peer whose canonical id is shared with a peer of a lower guid in the same address book
+UPDATE ab_peer
+SET id = REPLACE(REPLACE(REPLACE(REPLACE(REPLACE(id, ' ', ''), char(9), ''), char(10), ''), char(13), ''), char(160), ''),
+    info = CASE
+        WHEN json_valid(info) THEN json_set(
+            info,
+            '$.id',
+            REPLACE(REPLACE(REPLACE(REPLACE(REPLACE(id, ' ', ''), char(9), ''), char(10), ''), char(13), ''), char(160), '')
+        )
+        ELSE info
+    END
+WHERE id IS NOT NULL
+    AND id <> REPLACE(REPLACE(REPLACE(REPLACE(REPLACE(id, ' ', ''), char(9), ''), char(10), ''), char(13), ''), char(160), '')
+    AND NOT EXISTS (
+        SELECT 1 FROM ab_peer AS other
+        WHERE other.ab = ab_peer.ab
+            AND (
+                other.id = REPLACE(REPLACE(REPLACE(REPLACE(REPLACE(ab_peer.id, ' ', ''), char(9), ''), char(10), ''), char(13), ''), char(160), '')
+                OR (
+                    other.guid < ab_peer.guid
+                    AND REPLACE(REPLACE(REPLACE(REPLACE(REPLACE(other.id, ' ', ''), char(9), ''), char(10), ''), char(13), ''), char(160), '') = REPLACE(REPLACE(REPLACE(REPLACE(REPLACE(ab_peer.id, ' ', ''), char(9), ''), char(10), ''), char(13), ''), char(160), '')
+                )
+            )
+    );
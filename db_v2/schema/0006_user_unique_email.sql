@@ -0,0 +1,17 @@
+-- the names of the users are unique since the baseline, the emails were unique with their case
+-- and a single user could be created without email
+CREATE UNIQUE INDEX IF NOT EXISTS index_user_name ON user (name);
+
+-- the emails differing only by their case are cleared, but on the oldest user, before they become unique
+UPDATE user
+SET email = NULL, email_verified = 0
+WHERE email IS NOT NULL
+    AND email <> ''
+    AND EXISTS (
+        SELECT 1 FROM user AS other
+        WHERE lower(other.email) = lower(user.email)
+            AND (other.created_at < user.created_at OR (other.created_at = user.created_at AND other.guid < user.guid))
+    );
+
+DROP INDEX IF EXISTS index_user_email;
+CREATE UNIQUE INDEX IF NOT EXISTS index_user_email ON user (lower(email)) WHERE email <> '';
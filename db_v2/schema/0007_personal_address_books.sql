@@ -0,0 +1,16 @@
+-- the personal address books had the same name, only the first user got one
+-- the users without a personal address book get one named after them,
+-- or after their guid too when a shared address book already has the name
+INSERT INTO ab (guid, name, owner, personal, info)
+SELECT
+    randomblob(16),
+    CASE
+        WHEN EXISTS (SELECT 1 FROM ab AS other WHERE other.name = user.name || '''s Personal Address Book')
+        THEN user.name || '''s Personal Address Book (' || lower(hex(user.guid)) || ')'
+        ELSE user.name || '''s Personal Address Book'
+    END,
+    user.guid,
+    1,
+    '{}'
+FROM user
+WHERE NOT EXISTS (SELECT 1 FROM ab WHERE ab.owner = user.guid AND ab.personal = 1);
@@ -19,6 +19,7 @@ pub mod oauth_provider;
 use serde::{Deserialize, Serialize};
 use std::{fs, str::FromStr};
 mod errors;
+pub use errors::Oauth2Error;
 
 #[derive(Deserialize, Serialize, Clone, Debug)]
 pub struct ProviderConfig {
@@ -117,6 +118,18 @@ pub fn get_providers_config_from_file(config_file: &str) -> Vec<ProviderConfig>
     config.provider
 }
 
+/// Read the providers config from a config file, without creating it
+///
+/// # Returns
+/// The providers config, or the reason why the file cannot be read or parsed
+pub fn read_providers_config_file(config_file: &str) -> Result<Vec<ProviderConfig>, String> {
+    let config_file_content =
+        fs::read_to_string(config_file).map_err(|e| format!("{}: {}", config_file, e))?;
+    let config: Config =
+        toml::from_str(&config_file_content).map_err(|e| format!("{}: {}", config_file, e))?;
+    Ok(config.provider)
+}
+
 /// Get the name of the provider config file
 /// from the OAUTH2_CONFIG_FILE environment variable or
 /// default to "oauth2.toml"
@@ -15,12 +15,22 @@
 // along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
 use aws_config::{BehaviorVersion, ConfigLoader};
 use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::config::retry::RetryConfig;
+use aws_sdk_s3::config::timeout::TimeoutConfig;
+use aws_sdk_s3::config::Credentials;
+use aws_sdk_s3::error::DisplayErrorContext;
+use aws_sdk_s3::primitives::ByteStream;
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart};
 use aws_sdk_s3::{config::Region, Client};
 use regex::Regex;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
-use std::time::Duration;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct S3Config {
@@ -46,6 +56,109 @@ pub struct S3Config {
     pub osxarm64_key: String,
     #[serde(rename = "IOSKey")]
     pub ioskey: String,
+    #[serde(rename = "LinuxX64Key", default)]
+    pub linux_x64_key: Option<String>,
+    #[serde(rename = "LinuxArm64Key", default)]
+    pub linux_arm64_key: Option<String>,
+    #[serde(rename = "AndroidKey", default)]
+    pub android_key: Option<String>,
+    /// Release keys of the beta channel, `[s3config.Beta]`
+    #[serde(rename = "Beta", default)]
+    pub beta: Option<ChannelKeys>,
+}
+
+/// Release keys of a channel other than the stable one, with the names of the keys of `[s3config]`
+/// They are all optional, a platform without a key gets its stable release
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ChannelKeys {
+    #[serde(rename = "Windows64Key", default)]
+    pub windows64_key: Option<String>,
+    #[serde(rename = "Windows32Key", default)]
+    pub windows32_key: Option<String>,
+    #[serde(rename = "OSXKey", default)]
+    pub osxkey: Option<String>,
+    #[serde(rename = "OSXArm64Key", default)]
+    pub osxarm64_key: Option<String>,
+    #[serde(rename = "IOSKey", default)]
+    pub ioskey: Option<String>,
+    #[serde(rename = "LinuxX64Key", default)]
+    pub linux_x64_key: Option<String>,
+    #[serde(rename = "LinuxArm64Key", default)]
+    pub linux_arm64_key: Option<String>,
+    #[serde(rename = "AndroidKey", default)]
+    pub android_key: Option<String>,
+}
+
+impl ChannelKeys {
+    /// Get the release key of a platform of `SOFTWARE_KEYS`, None if it is unknown, missing or empty
+    pub fn release_key(&self, platform: &str) -> Option<&str> {
+        let key = match platform {
+            "w64" => &self.windows64_key,
+            "w32" => &self.windows32_key,
+            "osx" => &self.osxkey,
+            "osx-arm64" => &self.osxarm64_key,
+            "ios" => &self.ioskey,
+            "linux-x86_64" => &self.linux_x64_key,
+            "linux-aarch64" => &self.linux_arm64_key,
+            "android" => &self.android_key,
+            _ => return None,
+        };
+        key.as_deref().filter(|key| !key.is_empty())
+    }
+}
+
+/// Platforms served by `/api/software/client-download-link/<key>`
+pub const SOFTWARE_KEYS: [&str; 8] = [
+    "w64",
+    "w32",
+    "osx",
+    "osx-arm64",
+    "ios",
+    "linux-x86_64",
+    "linux-aarch64",
+    "android",
+];
+
+impl S3Config {
+    /// Get the release key of a platform of `SOFTWARE_KEYS`
+    ///
+    /// # Returns
+    /// None if the platform is unknown, Some(None) if its release key is missing or empty
+    pub fn release_key(&self, platform: &str) -> Option<Option<&str>> {
+        let key = match platform {
+            "w64" => Some(&self.windows64_key),
+            "w32" => Some(&self.windows32_key),
+            "osx" => Some(&self.osxkey),
+            "osx-arm64" => Some(&self.osxarm64_key),
+            "ios" => Some(&self.ioskey),
+            "linux-x86_64" => self.linux_x64_key.as_ref(),
+            "linux-aarch64" => self.linux_arm64_key.as_ref(),
+            "android" => self.android_key.as_ref(),
+            _ => return None,
+        };
+        Some(key.map(String::as_str).filter(|key| !key.is_empty()))
+    }
+
+    /// Get the release key of a platform in a channel, `stable` or `beta`, without falling back to the stable release
+    ///
+    /// # Returns
+    /// None if the platform or the channel is unknown, Some(None) if the channel has no release for the platform
+    pub fn channel_release_key(&self, channel: &str, platform: &str) -> Option<Option<&str>> {
+        match channel {
+            "stable" => self.release_key(platform),
+            "beta" if SOFTWARE_KEYS.contains(&platform) => {
+                Some(self.beta.as_ref().and_then(|keys| keys.release_key(platform)))
+            }
+            _ => None,
+        }
+    }
+
+    /// The configured release keys, in the order of `SOFTWARE_KEYS`
+    fn release_keys(&self) -> impl Iterator<Item = &str> {
+        SOFTWARE_KEYS
+            .into_iter()
+            .filter_map(|platform| self.release_key(platform).flatten())
+    }
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -104,19 +217,65 @@ pub async fn parse_config(config: &str) -> Result<Config, Box<dyn Error>> {
 /// # Returns
 /// The s3config
 pub async fn get_s3_config_file() -> Result<Config, Box<dyn Error>> {
-    let config_filename =
-        std::env::var("S3CONFIG_FILE").unwrap_or_else(|_| "s3config.toml".to_string());
-    // If file does not exist create it
-    if !std::path::Path::new(&config_filename).exists() {
+    open_s3_config_file(&s3_config_filename()).await
+}
+
+/// The name of the s3 config file, the S3CONFIG_FILE environment variable or "s3config.toml"
+fn s3_config_filename() -> String {
+    std::env::var("S3CONFIG_FILE").unwrap_or_else(|_| "s3config.toml".to_string())
+}
+
+/// Read an S3 configuration file, it is created with the sample configuration if it does not exist
+///
+/// # Returns
+/// The configuration, or the reason why the file cannot be created, read or parsed
+async fn open_s3_config_file(config_filename: &str) -> Result<Config, Box<dyn Error>> {
+    if !std::path::Path::new(config_filename).exists() {
         log::error!("S3 config file does not exist, creating it, we recommend you to fill it with your own values, you can change the file path by setting the S3CONFIG_FILE environment variable.");
         let s3_config = include_str!("../../../s3config.toml");
-        fs::write(&config_filename, s3_config).expect("Failed to write s3 config file");
+        fs::write(config_filename, s3_config)
+            .map_err(|e| format!("cannot create {}: {}", config_filename, e))?;
     }
-    let config_file_content =
-        fs::read_to_string(config_filename).expect("Failed to read s3 config file");
+    let config_file_content = fs::read_to_string(config_filename)
+        .map_err(|e| format!("cannot read {}: {}", config_filename, e))?;
     parse_config(&config_file_content).await
 }
 
+/// Load the S3 configuration from the file named by the S3CONFIG_FILE environment variable
+/// Unlike `get_s3_config_file`, the config file is not created if it does not exist and nothing panics
+///
+/// # Returns
+/// The configuration, or the reason why it cannot be used
+pub fn load_s3_config() -> Result<Config, String> {
+    read_s3_config(&s3_config_filename())
+}
+
+/// Read an S3 configuration file and check that it gives the endpoint, the bucket and the keys
+fn read_s3_config(config_filename: &str) -> Result<Config, String> {
+    let config_file_content = fs::read_to_string(config_filename)
+        .map_err(|e| format!("cannot read {}: {}", config_filename, e))?;
+    let config = toml::from_str::<Config>(&config_file_content)
+        .map_err(|e| format!("{} is not valid: {}", config_filename, e))?;
+    let s3config = &config.s3config;
+    let missing = [
+        ("Endpoint", &s3config.endpoint),
+        ("Bucket", &s3config.bucket),
+        ("AccessKey", &s3config.access_key),
+        ("SecretKey", &s3config.secret_key),
+    ]
+    .into_iter()
+    .find(|(_, value)| value.is_empty());
+    if let Some((key, _)) = missing {
+        return Err(format!("{} is empty in {}", key, config_filename));
+    }
+    Ok(config)
+}
+
+/// Validity of the signed release URLs
+pub const RELEASE_URL_EXPIRES_IN_SECS: u64 = 900;
+/// A cached signed release URL is signed again when less than this validity remains
+const RELEASE_URL_RESIGN_MARGIN_SECS: u64 = 600;
+
 pub async fn get_signed_release_url_with_config(
     config: Config,
     key: &str,
@@ -128,11 +287,428 @@ pub async fn get_signed_release_url_with_config(
         config.s3config.region.as_str(),
         config.s3config.bucket.as_str(),
         key,
-        900,
+        RELEASE_URL_EXPIRES_IN_SECS,
     )
     .await
 }
 
+/// S3 configuration read from a file, with the modification time of the file
+struct CachedConfig {
+    path: PathBuf,
+    modified: SystemTime,
+    config: Arc<Config>,
+}
+
+/// A signed release URL and the time when it expires
+struct SignedUrl {
+    url: String,
+    expires_at: Instant,
+}
+
+/// Slot of a release key, locked while its URL is signed so that concurrent requests wait for a single signature
+type SignedUrlSlot = Arc<tokio::sync::Mutex<Option<SignedUrl>>>;
+
+/// Size and checksum of a release, each of them is None when it is not known
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReleaseChecksum {
+    pub size: Option<u64>,
+    /// lowercase hex encoded sha256
+    pub sha256: Option<String>,
+}
+
+/// Metadata of a release object giving its sha256, sent as `x-amz-meta-sha256`
+pub const RELEASE_SHA256_METADATA: &str = "sha256";
+/// Extension of the object next to a release giving its sha256, in the format of `sha256sum`
+pub const RELEASE_SHA256_SIDECAR: &str = ".sha256";
+
+/// Parse a sha256, alone or followed by a file name as written by `sha256sum`
+///
+/// # Returns
+/// The lowercase sha256, or None if it is not 64 hex digits
+pub fn parse_sha256(checksum: &str) -> Option<String> {
+    let sha256 = checksum.split_whitespace().next()?;
+    (sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit())).then(|| sha256.to_lowercase())
+}
+
+/// Get the size and the sha256 of a release object
+/// The sha256 is read from the `sha256` metadata of the object, or else from the `<key>.sha256` object
+///
+/// # Returns
+/// The size and the checksum, their fields are None when they are not available,
+/// or an error if the object cannot be read
+pub async fn fetch_release_checksum(config: &Config, key: &str) -> Result<ReleaseChecksum, String> {
+    let s3config = &config.s3config;
+    let client = Client::from_conf(client_config(s3config).build());
+    let object = client
+        .head_object()
+        .bucket(s3config.bucket.as_str())
+        .key(key)
+        .send()
+        .await
+        .map_err(|e| format!("HEAD {} failed: {}", key, DisplayErrorContext(&e)))?;
+    let size = object.content_length().and_then(|size| u64::try_from(size).ok());
+    let metadata = object
+        .metadata()
+        .and_then(|metadata| metadata.get(RELEASE_SHA256_METADATA))
+        .and_then(|sha256| parse_sha256(sha256));
+    if metadata.is_some() {
+        return Ok(ReleaseChecksum { size, sha256: metadata });
+    }
+    let sidecar_key = format!("{}{}", key, RELEASE_SHA256_SIDECAR);
+    let sha256 = match client
+        .get_object()
+        .bucket(s3config.bucket.as_str())
+        .key(sidecar_key.as_str())
+        .send()
+        .await
+    {
+        Ok(sidecar) => {
+            let sidecar = sidecar
+                .body
+                .collect()
+                .await
+                .map_err(|e| format!("GET {} failed: {}", sidecar_key, e))?;
+            parse_sha256(&String::from_utf8_lossy(&sidecar.into_bytes()))
+        }
+        Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => None,
+        Err(e) => return Err(format!("GET {} failed: {}", sidecar_key, DisplayErrorContext(&e))),
+    };
+    Ok(ReleaseChecksum { size, sha256 })
+}
+
+/// Cache of the S3 configuration and of the signed release URLs
+///
+/// The configuration is read again when the modification time of its file changes,
+/// which also drops the signed URLs.
+/// A signed URL is reused until less than `RELEASE_URL_RESIGN_MARGIN_SECS` of validity remain
+pub struct ReleaseCache {
+    config_file: PathBuf,
+    config: Mutex<Option<CachedConfig>>,
+    urls: Mutex<HashMap<String, SignedUrlSlot>>,
+    checksums: Mutex<HashMap<String, ReleaseChecksum>>,
+    resign_margin: Duration,
+}
+
+impl Default for ReleaseCache {
+    fn default() -> Self {
+        ReleaseCache {
+            config_file: PathBuf::from(s3_config_filename()),
+            config: Mutex::new(None),
+            urls: Mutex::new(HashMap::new()),
+            checksums: Mutex::new(HashMap::new()),
+            resign_margin: Duration::from_secs(RELEASE_URL_RESIGN_MARGIN_SECS),
+        }
+    }
+}
+
+impl ReleaseCache {
+    /// Read the S3 configuration from `config_file` instead of the file named by the S3CONFIG_FILE environment variable
+    pub fn with_config_file(mut self, config_file: impl Into<PathBuf>) -> Self {
+        self.config_file = config_file.into();
+        self
+    }
+
+    /// Get the S3 configuration from the file named by the S3CONFIG_FILE environment variable, like `load_s3_config`
+    ///
+    /// # Returns
+    /// The configuration, or the reason why it cannot be used
+    pub fn config(&self) -> Result<Arc<Config>, String> {
+        self.config_from(self.config_file.clone())
+    }
+
+    fn config_from(&self, path: PathBuf) -> Result<Arc<Config>, String> {
+        let modified = fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        let mut cached = self.config.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(cached) = cached.as_ref() {
+            if cached.path == path && cached.modified == modified {
+                return Ok(cached.config.clone());
+            }
+        }
+        let config = Arc::new(read_s3_config(&path.to_string_lossy())?);
+        *cached = Some(CachedConfig {
+            path,
+            modified,
+            config: config.clone(),
+        });
+        // the URLs signed with the previous configuration may point to other releases
+        self.urls.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        self.checksums.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        Ok(config)
+    }
+
+    /// Get a signed URL of a release key, signed again when it is about to expire
+    ///
+    /// # Returns
+    /// The URL, or the reason why it cannot be signed
+    pub async fn signed_url(&self, config: &Config, key: &str) -> Result<String, String> {
+        self.signed_url_with(key, || async {
+            get_signed_release_url_with_config(config.clone(), key)
+                .await
+                .map_err(|e| e.to_string())
+        })
+        .await
+    }
+
+    async fn signed_url_with<F, Fut>(&self, key: &str, sign: F) -> Result<String, String>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<String, String>>,
+    {
+        let slot = self
+            .urls
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        let mut signed = slot.lock().await;
+        if let Some(signed) = signed.as_ref() {
+            if signed.expires_at.saturating_duration_since(Instant::now()) > self.resign_margin {
+                return Ok(signed.url.clone());
+            }
+        }
+        let signed_at = Instant::now();
+        let url = sign().await?;
+        *signed = Some(SignedUrl {
+            url: url.clone(),
+            expires_at: signed_at + Duration::from_secs(RELEASE_URL_EXPIRES_IN_SECS),
+        });
+        Ok(url)
+    }
+
+    /// Get the size and the sha256 of a release key, like `fetch_release_checksum`
+    /// They are kept until the configuration changes, even when they are not available,
+    /// the failures are logged and not kept
+    pub async fn checksum(&self, config: &Config, key: &str) -> ReleaseChecksum {
+        if let Some(checksum) = self.checksums.lock().unwrap_or_else(|e| e.into_inner()).get(key) {
+            return checksum.clone();
+        }
+        match fetch_release_checksum(config, key).await {
+            Ok(checksum) => {
+                self.checksums
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .insert(key.to_string(), checksum.clone());
+                checksum
+            }
+            Err(e) => {
+                log::error!("checksum of {}: {}", key, e);
+                ReleaseChecksum::default()
+            }
+        }
+    }
+
+    /// Drop the cached configuration, signed URLs and checksums
+    pub fn clear(&self) {
+        *self.config.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        self.urls.lock().unwrap_or_else(|e| e.into_inner()).clear();
+        self.checksums.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+}
+
+/// Configuration of an S3 client using the credentials of the S3 configuration
+/// Unlike `get_signed_release_url_with_config`, the environment is not modified
+fn client_config(s3config: &S3Config) -> aws_sdk_s3::config::Builder {
+    let credentials = Credentials::new(
+        s3config.access_key.as_str(),
+        s3config.secret_key.as_str(),
+        None,
+        None,
+        "s3config",
+    );
+    aws_sdk_s3::Config::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new(s3config.region.clone()))
+        .endpoint_url(s3config.endpoint.as_str())
+        .credentials_provider(credentials)
+}
+
+/// Size of the parts of the multipart uploads, S3 requires at least 5 MiB for every part but the last one
+pub const RELEASE_UPLOAD_PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Key of an uploaded release in the bucket, e.g. `releases/w64/1.2.6/sctgdesk-1.2.6-x86_64.exe`
+/// A new key is used for every upload so that the release in use is never overwritten
+pub fn release_upload_key(platform: &str, version: &str, file_name: &str) -> String {
+    format!("releases/{}/{}/{}", platform, version, file_name)
+}
+
+/// Upload of a release to the bucket, streamed by parts
+///
+/// The data is buffered until a part is complete, a release smaller than a part is sent with a single PUT.
+/// Nothing is visible under the key until `finish` succeeds, a failed upload must be dropped with `abort`
+pub struct ReleaseUpload {
+    client: Client,
+    bucket: String,
+    key: String,
+    upload_id: Option<String>,
+    parts: Vec<CompletedPart>,
+    buffer: Vec<u8>,
+    part_size: usize,
+}
+
+impl ReleaseUpload {
+    pub fn new(config: &Config, key: &str) -> Self {
+        ReleaseUpload {
+            client: Client::from_conf(client_config(&config.s3config).build()),
+            bucket: config.s3config.bucket.clone(),
+            key: key.to_string(),
+            upload_id: None,
+            parts: Vec::new(),
+            buffer: Vec::new(),
+            part_size: RELEASE_UPLOAD_PART_SIZE,
+        }
+    }
+
+    /// Key of the release in the bucket
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    /// Add data to the release, the complete parts are uploaded
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        self.buffer.extend_from_slice(data);
+        while self.buffer.len() >= self.part_size {
+            let part = self.buffer.drain(..self.part_size).collect();
+            self.upload_part(part).await?;
+        }
+        Ok(())
+    }
+
+    async fn upload_part(&mut self, part: Vec<u8>) -> Result<(), String> {
+        let upload_id = match &self.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => {
+                let upload = self
+                    .client
+                    .create_multipart_upload()
+                    .bucket(self.bucket.as_str())
+                    .key(self.key.as_str())
+                    .send()
+                    .await
+                    .map_err(|e| format!("cannot start the upload of {}: {}", self.key, DisplayErrorContext(&e)))?;
+                let upload_id = upload
+                    .upload_id()
+                    .ok_or_else(|| format!("no upload id was given for {}", self.key))?
+                    .to_string();
+                self.upload_id = Some(upload_id.clone());
+                upload_id
+            }
+        };
+        let part_number = self.parts.len() as i32 + 1;
+        let uploaded = self
+            .client
+            .upload_part()
+            .bucket(self.bucket.as_str())
+            .key(self.key.as_str())
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(part))
+            .send()
+            .await
+            .map_err(|e| format!("cannot upload the part {} of {}: {}", part_number, self.key, DisplayErrorContext(&e)))?;
+        self.parts.push(
+            CompletedPart::builder()
+                .part_number(part_number)
+                .set_e_tag(uploaded.e_tag().map(str::to_string))
+                .build(),
+        );
+        Ok(())
+    }
+
+    /// Send the remaining data and publish the release under its key
+    pub async fn finish(&mut self) -> Result<(), String> {
+        let Some(upload_id) = self.upload_id.clone() else {
+            let release = std::mem::take(&mut self.buffer);
+            self.client
+                .put_object()
+                .bucket(self.bucket.as_str())
+                .key(self.key.as_str())
+                .body(ByteStream::from(release))
+                .send()
+                .await
+                .map_err(|e| format!("cannot upload {}: {}", self.key, DisplayErrorContext(&e)))?;
+            return Ok(());
+        };
+        if !self.buffer.is_empty() {
+            let part = std::mem::take(&mut self.buffer);
+            self.upload_part(part).await?;
+        }
+        self.client
+            .complete_multipart_upload()
+            .bucket(self.bucket.as_str())
+            .key(self.key.as_str())
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(self.parts.clone()))
+                    .build(),
+            )
+            .send()
+            .await
+            .map_err(|e| format!("cannot complete the upload of {}: {}", self.key, DisplayErrorContext(&e)))?;
+        self.upload_id = None;
+        Ok(())
+    }
+
+    /// Drop the parts already uploaded, so that the bucket does not keep an incomplete release
+    pub async fn abort(self) {
+        let Some(upload_id) = self.upload_id else {
+            return;
+        };
+        let res = self
+            .client
+            .abort_multipart_upload()
+            .bucket(self.bucket.as_str())
+            .key(self.key.as_str())
+            .upload_id(upload_id)
+            .send()
+            .await;
+        if let Err(e) = res {
+            log::error!("cannot abort the upload of {}: {}", self.key, DisplayErrorContext(&e));
+        }
+    }
+}
+
+/// Check the S3 configuration with a HEAD request on the first release key
+/// Nothing is downloaded and the environment is not modified
+///
+/// # Returns
+/// A description of the object found, or the reason of the failure
+pub async fn check_release_key(config: &Config, timeout: Duration) -> Result<String, String> {
+    let s3config = &config.s3config;
+    let key = s3config
+        .release_keys()
+        .next()
+        .ok_or_else(|| "No release key is configured".to_string())?;
+    let client_config = client_config(s3config)
+        .retry_config(RetryConfig::disabled())
+        .timeout_config(TimeoutConfig::builder().operation_timeout(timeout).build())
+        .build();
+    let client = Client::from_conf(client_config);
+    match client
+        .head_object()
+        .bucket(s3config.bucket.as_str())
+        .key(key)
+        .send()
+        .await
+    {
+        Ok(object) => Ok(format!(
+            "{} found in bucket {} ({} bytes)",
+            key,
+            s3config.bucket,
+            object.content_length().unwrap_or_default()
+        )),
+        Err(e) => Err(format!(
+            "HEAD {} in bucket {} failed: {}",
+            key,
+            s3config.bucket,
+            DisplayErrorContext(&e)
+        )),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -171,6 +747,408 @@ mod tests {
 
         assert_eq!(status, StatusCode::OK);
     }
+
+    #[tokio::test]
+    async fn test_open_s3_config_file_errors() {
+        let dir = std::env::temp_dir().join(format!("sctgdesk-test-{}", std::process::id()));
+        // the directory of the file does not exist, it cannot be created
+        let missing = dir.join("missing").join("s3config.toml");
+        let error = open_s3_config_file(missing.to_str().unwrap()).await.unwrap_err();
+        assert!(error.to_string().starts_with("cannot create"), "{}", error);
+        fs::create_dir_all(&dir).unwrap();
+        let invalid = dir.join("s3config.toml");
+        fs::write(&invalid, "[s3config\n").unwrap();
+        assert!(open_s3_config_file(invalid.to_str().unwrap()).await.is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn mock_config(endpoint: &str) -> Config {
+        toml::from_str(&format!(
+            "[s3config]\nPage = \"\"\nEndpoint = \"{}\"\nRegion = \"eu-west-1\"\nAccessKey = \"key\"\nSecretKey = \"secret\"\n\
+             Bucket = \"releases\"\nWindows64Key = \"\"\nWindows32Key = \"sctgdesk-1.2.6-i686.exe\"\nOSXKey = \"\"\nOSXArm64Key = \"\"\nIOSKey = \"\"\n",
+            endpoint
+        ))
+        .unwrap()
+    }
+
+    /// Answer every request of the connection with `status`, return the first request line
+    async fn mock_s3(listener: tokio::net::TcpListener, status: &'static str) -> String {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while !String::from_utf8_lossy(&request).contains("\r\n\r\n") {
+            let read = socket.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..read]);
+        }
+        let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nx-amz-request-id: mock\r\n\r\n", status);
+        socket.write_all(response.as_bytes()).await.unwrap();
+        String::from_utf8_lossy(&request).lines().next().unwrap_or_default().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_check_release_key() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = mock_config(&format!("http://{}", listener.local_addr().unwrap()));
+        let server = tokio::spawn(mock_s3(listener, "200 OK"));
+        let result = check_release_key(&config, Duration::from_secs(5)).await;
+        assert!(result.unwrap().starts_with("sctgdesk-1.2.6-i686.exe found in bucket releases"));
+        // the first configured key is checked with a HEAD, path style on an ip endpoint
+        assert_eq!(server.await.unwrap(), "HEAD /releases/sctgdesk-1.2.6-i686.exe HTTP/1.1");
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = mock_config(&format!("http://{}", listener.local_addr().unwrap()));
+        let server = tokio::spawn(mock_s3(listener, "404 Not Found"));
+        let error = check_release_key(&config, Duration::from_secs(5)).await.unwrap_err();
+        assert!(error.starts_with("HEAD sctgdesk-1.2.6-i686.exe in bucket releases failed"));
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn test_read_s3_config() {
+        let config_file = std::env::temp_dir().join(format!("s3config-test-{}.toml", std::process::id()));
+        let config_filename = config_file.to_str().unwrap();
+        let error = read_s3_config(config_filename).err().unwrap();
+        assert!(error.starts_with(&format!("cannot read {}", config_filename)));
+
+        fs::write(&config_file, "[s3config\nPage = ").unwrap();
+        let error = read_s3_config(config_filename).err().unwrap();
+        assert!(error.starts_with(&format!("{} is not valid", config_filename)));
+
+        let valid = include_str!("../../../s3config.toml");
+        let without_bucket: String = valid
+            .lines()
+            .filter(|line| !line.starts_with("Bucket"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        fs::write(&config_file, without_bucket).unwrap();
+        let error = read_s3_config(config_filename).err().unwrap();
+        assert!(error.contains("missing field `Bucket`"));
+
+        fs::write(&config_file, valid.replace("Bucket = \"aezoz24elapn\"", "Bucket = \"\"")).unwrap();
+        assert_eq!(
+            read_s3_config(config_filename).err().unwrap(),
+            format!("Bucket is empty in {}", config_filename)
+        );
+
+        fs::write(&config_file, valid).unwrap();
+        assert_eq!(read_s3_config(config_filename).unwrap().s3config.bucket, "aezoz24elapn");
+        fs::remove_file(&config_file).unwrap();
+    }
+
+    #[test]
+    fn test_release_cache_config() {
+        let config_file = std::env::temp_dir().join(format!("s3config-cache-test-{}.toml", std::process::id()));
+        let cache = ReleaseCache::default();
+        assert!(cache.config_from(config_file.clone()).is_err());
+
+        let valid = include_str!("../../../s3config.toml");
+        fs::write(&config_file, valid).unwrap();
+        let config = cache.config_from(config_file.clone()).unwrap();
+        assert_eq!(config.s3config.bucket, "aezoz24elapn");
+        assert!(Arc::ptr_eq(&config, &cache.config_from(config_file.clone()).unwrap()));
+
+        // the file is read again when its modification time changes
+        fs::write(&config_file, valid.replace("aezoz24elapn", "releases")).unwrap();
+        let file = fs::File::options().write(true).open(&config_file).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10)).unwrap();
+        assert_eq!(cache.config_from(config_file.clone()).unwrap().s3config.bucket, "releases");
+
+        fs::remove_file(&config_file).unwrap();
+        assert!(cache.config_from(config_file).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_release_cache_signed_url() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let signatures = AtomicUsize::new(0);
+        let sign = || async {
+            let signature = signatures.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(format!("https://s3.example.org/release?signature={}", signature))
+        };
+        let cache = ReleaseCache::default();
+        // concurrent requests during a cache miss share a single signature
+        let (first, second) = tokio::join!(cache.signed_url_with("osx", sign), cache.signed_url_with("osx", sign));
+        assert_eq!(first.unwrap(), "https://s3.example.org/release?signature=0");
+        assert_eq!(second.unwrap(), "https://s3.example.org/release?signature=0");
+        assert_eq!(signatures.load(Ordering::SeqCst), 1);
+        // the keys are signed apart
+        assert!(cache.signed_url_with("w64", sign).await.unwrap().ends_with("signature=1"));
+
+        cache.clear();
+        assert!(cache.signed_url_with("osx", sign).await.unwrap().ends_with("signature=2"));
+
+        // the URLs about to expire are signed again
+        let cache = ReleaseCache {
+            resign_margin: Duration::from_secs(RELEASE_URL_EXPIRES_IN_SECS),
+            ..Default::default()
+        };
+        assert!(cache.signed_url_with("osx", sign).await.unwrap().ends_with("signature=3"));
+        assert!(cache.signed_url_with("osx", sign).await.unwrap().ends_with("signature=4"));
+        // the failures are not cached
+        let failure = || async { Err::<String, String>("no credentials".to_string()) };
+        assert!(cache.signed_url_with("ios", failure).await.is_err());
+        assert!(cache.signed_url_with("ios", sign).await.unwrap().ends_with("signature=5"));
+    }
+
+    /// Answer the requests of an upload, the parts numbered `failing_part` fail
+    /// Every request is recorded with the size of its body
+    async fn mock_s3_upload(
+        listener: tokio::net::TcpListener,
+        failing_part: Option<&'static str>,
+        requests: Arc<Mutex<Vec<(String, usize)>>>,
+    ) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let requests = requests.clone();
+            tokio::spawn(async move {
+                let mut received = Vec::new();
+                let mut buffer = [0u8; 4096];
+                loop {
+                    let head_end = loop {
+                        if let Some(end) = received.windows(4).position(|window| window == b"\r\n\r\n") {
+                            break end + 4;
+                        }
+                        let read = socket.read(&mut buffer).await.unwrap_or(0);
+                        if read == 0 {
+                            return;
+                        }
+                        received.extend_from_slice(&buffer[..read]);
+                    };
+                    let head = String::from_utf8_lossy(&received[..head_end]).to_string();
+                    let content_length = head
+                        .lines()
+                        .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|l| l.trim().to_string()))
+                        .and_then(|length| length.parse::<usize>().ok())
+                        .unwrap_or(0);
+                    while received.len() < head_end + content_length {
+                        let read = socket.read(&mut buffer).await.unwrap();
+                        received.extend_from_slice(&buffer[..read]);
+                    }
+                    received.drain(..head_end + content_length);
+                    let request_line = head.lines().next().unwrap_or_default().to_string();
+                    requests.lock().unwrap().push((request_line.clone(), content_length));
+                    let (status, body) = if failing_part.is_some_and(|part| request_line.contains(&format!("partNumber={}&", part))) {
+                        ("500 Internal Server Error", String::new())
+                    } else if request_line.starts_with("POST") && request_line.contains("?uploads") {
+                        ("200 OK", "<InitiateMultipartUploadResult><Bucket>releases</Bucket><Key>k</Key><UploadId>mock-upload</UploadId></InitiateMultipartUploadResult>".to_string())
+                    } else if request_line.starts_with("POST") {
+                        ("200 OK", "<CompleteMultipartUploadResult><Bucket>releases</Bucket><Key>k</Key><ETag>\"mock\"</ETag></CompleteMultipartUploadResult>".to_string())
+                    } else if request_line.starts_with("DELETE") {
+                        ("204 No Content", String::new())
+                    } else {
+                        ("200 OK", String::new())
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\nContent-Length: {}\r\nETag: \"mock\"\r\nx-amz-request-id: mock\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    socket.write_all(response.as_bytes()).await.unwrap();
+                }
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_release_upload() {
+        let release: Vec<u8> = (0..25u8).collect();
+        let key = release_upload_key("w64", "1.2.6", "sctgdesk-1.2.6-x86_64.exe");
+        assert_eq!(key, "releases/w64/1.2.6/sctgdesk-1.2.6-x86_64.exe");
+        let upload = |failing_part: Option<&'static str>, part_size: usize| {
+            let key = key.clone();
+            let release = release.clone();
+            async move {
+                let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+                let config = mock_config(&format!("http://{}", listener.local_addr().unwrap()));
+                let requests = Arc::new(Mutex::new(Vec::new()));
+                let server = tokio::spawn(mock_s3_upload(listener, failing_part, requests.clone()));
+                let mut upload = ReleaseUpload {
+                    part_size,
+                    ..ReleaseUpload::new(&config, &key)
+                };
+                let mut result = Ok(());
+                for chunk in release.chunks(7) {
+                    result = upload.write(chunk).await;
+                    if result.is_err() {
+                        break;
+                    }
+                }
+                if result.is_ok() {
+                    result = upload.finish().await;
+                }
+                if result.is_err() {
+                    upload.abort().await;
+                }
+                server.abort();
+                let requests = requests.lock().unwrap().clone();
+                (result, requests)
+            }
+        };
+
+        // a release smaller than a part is sent with a single PUT
+        let (result, requests) = upload(None, RELEASE_UPLOAD_PART_SIZE).await;
+        result.unwrap();
+        assert_eq!(
+            requests,
+            vec![("PUT /releases/releases/w64/1.2.6/sctgdesk-1.2.6-x86_64.exe?x-id=PutObject HTTP/1.1".to_string(), 25)]
+        );
+
+        // a larger release is sent by parts
+        let (result, requests) = upload(None, 10).await;
+        result.unwrap();
+        let sizes: Vec<usize> = requests.iter().map(|(_, size)| *size).collect();
+        assert!(requests[0].0.starts_with("POST /releases/releases/w64/1.2.6/sctgdesk-1.2.6-x86_64.exe?uploads"));
+        assert!(requests[1].0.contains("partNumber=1&"));
+        assert!(requests[3].0.contains("partNumber=3&"));
+        assert!(requests[4].0.contains("uploadId=mock-upload"));
+        assert_eq!(sizes[1..4], [10, 10, 5]);
+        assert_eq!(requests.len(), 5);
+
+        // a failed part drops the upload
+        let (result, requests) = upload(Some("2"), 10).await;
+        assert!(result.unwrap_err().starts_with("cannot upload the part 2"));
+        let (abort, _) = requests.last().unwrap();
+        assert!(abort.starts_with("DELETE /releases/releases/w64/1.2.6/sctgdesk-1.2.6-x86_64.exe?"));
+        assert!(abort.contains("uploadId=mock-upload"));
+        assert!(!requests.iter().any(|(request, _)| request.starts_with("POST") && request.contains("uploadId=")));
+    }
+
+    /// Answer the requests without body with the response given by `respond` for their request line
+    async fn mock_s3_objects(listener: tokio::net::TcpListener, respond: fn(&str) -> String) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        loop {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut received = Vec::new();
+                let mut buffer = [0u8; 1024];
+                loop {
+                    let Some(end) = received.windows(4).position(|window| window == b"\r\n\r\n") else {
+                        let read = socket.read(&mut buffer).await.unwrap_or(0);
+                        if read == 0 {
+                            return;
+                        }
+                        received.extend_from_slice(&buffer[..read]);
+                        continue;
+                    };
+                    let head: Vec<u8> = received.drain(..end + 4).collect();
+                    let request_line = String::from_utf8_lossy(&head).lines().next().unwrap_or_default().to_string();
+                    socket.write_all(respond(&request_line).as_bytes()).await.unwrap();
+                }
+            });
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_release_checksum() {
+        const SHA256: &str = "9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08";
+        let respond = |request_line: &str| {
+            let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+            let path = path.split('?').next().unwrap_or_default();
+            let (headers, body) = match path {
+                // the checksum is given by the metadata
+                "/releases/with-metadata.exe" => (
+                    format!("Content-Length: 2048\r\nx-amz-meta-sha256: {}\r\n", SHA256),
+                    String::new(),
+                ),
+                // the checksum is given by a sidecar object
+                "/releases/with-sidecar.exe" => ("Content-Length: 4096\r\n".to_string(), String::new()),
+                "/releases/with-sidecar.exe.sha256" => {
+                    let body = format!("{}  with-sidecar.exe\n", SHA256.to_lowercase());
+                    (format!("Content-Length: {}\r\n", body.len()), body)
+                }
+                "/releases/without-checksum.exe" => ("Content-Length: 1024\r\n".to_string(), String::new()),
+                _ => {
+                    let body = "<Error><Code>NoSuchKey</Code><Message>not found</Message></Error>".to_string();
+                    return format!(
+                        "HTTP/1.1 404 Not Found\r\nContent-Type: application/xml\r\nContent-Length: {}\r\nx-amz-request-id: mock\r\n\r\n{}",
+                        body.len(),
+                        body
+                    );
+                }
+            };
+            let body = if request_line.starts_with("HEAD") { String::new() } else { body };
+            format!("HTTP/1.1 200 OK\r\n{}x-amz-request-id: mock\r\n\r\n{}", headers, body)
+        };
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = mock_config(&format!("http://{}", listener.local_addr().unwrap()));
+        let server = tokio::spawn(mock_s3_objects(listener, respond));
+
+        let sha256 = Some(SHA256.to_lowercase());
+        let checksum = fetch_release_checksum(&config, "with-metadata.exe").await.unwrap();
+        assert_eq!(checksum, ReleaseChecksum { size: Some(2048), sha256: sha256.clone() });
+        let checksum = fetch_release_checksum(&config, "with-sidecar.exe").await.unwrap();
+        assert_eq!(checksum, ReleaseChecksum { size: Some(4096), sha256: sha256.clone() });
+        // the checksum is never guessed
+        let checksum = fetch_release_checksum(&config, "without-checksum.exe").await.unwrap();
+        assert_eq!(checksum, ReleaseChecksum { size: Some(1024), sha256: None });
+        assert!(fetch_release_checksum(&config, "missing.exe").await.is_err());
+
+        // the cache keeps the checksums, not the failures
+        let cache = ReleaseCache::default();
+        assert_eq!(cache.checksum(&config, "with-sidecar.exe").await.sha256, sha256);
+        assert_eq!(cache.checksum(&config, "missing.exe").await, ReleaseChecksum::default());
+        assert_eq!(cache.checksums.lock().unwrap().len(), 1);
+        server.abort();
+    }
+
+    #[test]
+    fn test_parse_sha256() {
+        let sha256 = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+        assert_eq!(parse_sha256(sha256).as_deref(), Some(sha256));
+        assert_eq!(parse_sha256(&format!("{}  sctgdesk.exe\n", sha256.to_uppercase())).as_deref(), Some(sha256));
+        assert_eq!(parse_sha256(""), None);
+        assert_eq!(parse_sha256(&sha256[1..]), None);
+        assert_eq!(parse_sha256(&sha256.replace('f', "g")), None);
+    }
+
+    #[test]
+    fn test_release_key() {
+        let mut config = mock_config("http://127.0.0.1").s3config;
+        assert_eq!(config.release_key("w32"), Some(Some("sctgdesk-1.2.6-i686.exe")));
+        // empty and missing keys are not configured
+        assert_eq!(config.release_key("w64"), Some(None));
+        assert_eq!(config.release_key("android"), Some(None));
+        assert_eq!(config.release_key("win"), None);
+        config.linux_arm64_key = Some("sctgdesk-1.2.6-aarch64.deb".to_string());
+        assert_eq!(config.release_key("linux-aarch64"), Some(Some("sctgdesk-1.2.6-aarch64.deb")));
+        assert_eq!(
+            config.release_keys().collect::<Vec<&str>>(),
+            vec!["sctgdesk-1.2.6-i686.exe", "sctgdesk-1.2.6-aarch64.deb"]
+        );
+        for platform in SOFTWARE_KEYS {
+            assert!(config.release_key(platform).is_some());
+        }
+    }
+
+    #[test]
+    fn test_channel_release_key() {
+        let mut config = mock_config("http://127.0.0.1").s3config;
+        assert_eq!(config.channel_release_key("stable", "w32"), Some(Some("sctgdesk-1.2.6-i686.exe")));
+        assert_eq!(config.channel_release_key("beta", "w32"), Some(None));
+        config = toml::from_str::<Config>(&format!(
+            "[s3config]\nPage = \"\"\nEndpoint = \"\"\nRegion = \"\"\nAccessKey = \"\"\nSecretKey = \"\"\nBucket = \"\"\n\
+             Windows64Key = \"\"\nWindows32Key = \"{}\"\nOSXKey = \"\"\nOSXArm64Key = \"\"\nIOSKey = \"\"\n\
+             [s3config.Beta]\nWindows32Key = \"sctgdesk-1.3.0-beta.1-i686.exe\"\nOSXKey = \"\"\n",
+            "sctgdesk-1.2.6-i686.exe"
+        ))
+        .unwrap()
+        .s3config;
+        assert_eq!(config.channel_release_key("beta", "w32"), Some(Some("sctgdesk-1.3.0-beta.1-i686.exe")));
+        assert_eq!(config.channel_release_key("stable", "w32"), Some(Some("sctgdesk-1.2.6-i686.exe")));
+        // empty and missing beta keys are not configured
+        assert_eq!(config.channel_release_key("beta", "osx"), Some(None));
+        assert_eq!(config.channel_release_key("beta", "android"), Some(None));
+        assert_eq!(config.channel_release_key("beta", "win"), None);
+        assert_eq!(config.channel_release_key("nightly", "w32"), None);
+    }
 }
 
 pub async fn extract_version() -> Result<String, Box<dyn std::error::Error>> {
@@ -179,17 +1157,8 @@ pub async fn extract_version() -> Result<String, Box<dyn std::error::Error>> {
     // Create a regex to match the version number
     let re = Regex::new(r"\d+\.\d+\.\d+")?;
 
-    // List all keys containing the version number
-    let keys = [
-        &config.s3config.windows64_key,
-        &config.s3config.windows32_key,
-        &config.s3config.osxkey,
-        &config.s3config.osxarm64_key,
-        &config.s3config.ioskey,
-    ];
-
     // Extract the version number from the keys
-    for key in &keys {
+    for key in config.s3config.release_keys() {
         if let Some(captures) = re.captures(key) {
             return Ok(String::from(captures.get(0).unwrap().as_str()));
         }
@@ -36,6 +36,11 @@ pub struct AuthenticatedUserInfo {
     pub access_token: Token,
 }
 
+/// User authenticated for the current request, cached in the request by the authentication guards
+/// It is None when no guard authenticated a user
+#[derive(Debug, Clone, Default)]
+pub struct RequestUserId(pub Option<UserId>);
+
 #[derive(Debug)]
 pub struct AuthenticatedUser<T> {
     pub info: AuthenticatedUserInfo,
@@ -66,6 +71,7 @@ impl<'r, T> FromRequest<'r> for AuthenticatedUser<T> where T: FromRequest<'r, Er
             .ok_or(Outcome::Forward(Status::Unauthorized))
         );
 
+        request.local_cache(|| RequestUserId(Some(access_token_info.user_id.clone())));
         let authenticated_user = AuthenticatedUser {
             info: AuthenticatedUserInfo {
                 session_id: access_token_info.session_id,
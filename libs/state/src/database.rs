@@ -15,33 +15,61 @@
 // along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
 use crate::password::UserPasswordInfo;
 use crate::types;
+use crate::migrations::{self, Migration, MIGRATIONS};
+use crate::types::{DatabaseOptions, DatabasePoolStats};
 use crate::UserId;
+use rocket::futures::{Stream, StreamExt};
+use rocket::response::stream::stream;
 use serde::Serialize;
 use sqlx::{
     pool::PoolConnection,
-    sqlite::{Sqlite, SqliteConnectOptions, SqliteJournalMode, SqlitePool},
-    QueryBuilder,
+    sqlite::{
+        Sqlite, SqliteConnectOptions, SqliteConnection, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteRow,
+        SqliteSynchronous,
+    },
+    ConnectOptions, QueryBuilder, Row,
 };
 use std::env;
 use std::path::Path;
-use utils::guid_into_uuid;
+use std::time::Duration;
+use utils::{guid_into_uuid, uuid_into_guid};
+use utils::peer_status::PEER_OFFLINE_AFTER_SECS;
 use utils::types::AddressBook;
 use utils::AbPeer;
 use utils::AbRule;
 use utils::AbTag;
+use utils::AbTagStats;
+use utils::cursor::PageCursor;
+use utils::strategy::StrategyOrder;
+use utils::audit::{AuditCategory, AuditEvent, AuditFilter, AuditRecord, ConnAuditAction};
+use utils::{AuditAdmin, AuditAlarm, AuditConn, AuditFile, AuditLogin};
 use utils::CpuCount;
+use utils::{OsSummary, PeersSummary};
 use utils::Group;
+use utils::{EmailVerificationError, LinkedProvider, ShareLinkError, UnlinkProviderError};
 use utils::Peer;
+use utils::{PeerAccess, PeerAccessEntry, PeerAccessGrant, PeerAccessSource, AB_RULE_FULL};
+use utils::{PeerEvent, PeerEventType};
+use utils::PeerExport;
+use utils::{ReleaseChannel, SoftwareRelease};
+use utils::{PeersBulkAction, PeersBulkResult, PeersBulkStatus};
 use utils::Platform;
+use utils::Strategy;
 use utils::UpdateUserRequest;
+use utils::{AddressBookPermission, AdminPermission, GroupMembership, PermissionQuotas, UserPermissions};
+use utils::UserField;
 use utils::UserListResponse;
+use utils::UserStatus;
+use utils::{DailyBytes, OsCount, StatisticsResponse};
 
 use base64::prelude::{Engine as _, BASE64_STANDARD};
 
 use uuid::Uuid;
 
+#[derive(Clone)]
 pub struct Database {
     pool: SqlitePool,
+    max_connections: u32,
 }
 
 pub struct DatabaseConnection {
@@ -51,6 +79,7 @@ pub struct DatabaseConnection {
 pub struct DatabaseUserInfo {
     pub active: bool,
     pub admin: bool,
+    pub email_verified: bool,
 }
 
 #[derive(Serialize, Debug)]
@@ -60,6 +89,40 @@ pub struct DatabaseUserPasswordInfo {
     pub user_id: UserId,
 }
 
+/// audit_console type for the operations on peers
+const AUDIT_CONSOLE_TYPE_PEER: i64 = 2;
+/// audit_console iop for the assignment of a peer to a user
+const AUDIT_CONSOLE_IOP_ASSIGN: i64 = 1;
+/// audit_console iop for a bulk operation on peers
+const AUDIT_CONSOLE_IOP_BULK: i64 = 2;
+
+/// The status of a user read from the database
+/// An unknown value is a disabled user, the login only accepts the normal status
+fn user_status(status: i32) -> UserStatus {
+    UserStatus::try_from(status).unwrap_or_else(|e| {
+        log::warn!("user_status: {}", e);
+        UserStatus::Disabled
+    })
+}
+
+/// The field of a user refused by its unique index, when another user took it since the conflicts were checked
+pub(crate) fn user_unique_violation(error: &sqlx::Error) -> Option<UserField> {
+    let sqlx::Error::Database(error) = error else {
+        return None;
+    };
+    let message = error.message();
+    if !message.starts_with("UNIQUE constraint failed") {
+        return None;
+    }
+    if message.contains("email") {
+        Some(UserField::Email)
+    } else if message.contains("name") {
+        Some(UserField::Name)
+    } else {
+        None
+    }
+}
+
 macro_rules! unwrap_or_return_tuple {
     ($first:expr, $opt:expr) => {
         match $opt {
@@ -70,17 +133,59 @@ macro_rules! unwrap_or_return_tuple {
 }
 
 impl Database {
-    pub async fn open<P: AsRef<Path>>(db_filename: P) -> Self {
+    pub async fn open_with_options<P: AsRef<Path>>(db_filename: P, options: &DatabaseOptions) -> Self {
         let db_opts = SqliteConnectOptions::new()
             .filename(db_filename.as_ref())
             .journal_mode(SqliteJournalMode::Wal)
+            // safe with WAL, a crash can only lose the last transactions
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(Duration::from_millis(options.busy_timeout_ms))
+            .statement_cache_capacity(options.statement_cache_size)
             .create_if_missing(true);
 
-        let pool = SqlitePool::connect_with(db_opts).await.unwrap();
+        let pool = SqlitePoolOptions::new()
+            .max_connections(options.pool_size.max(1))
+            .acquire_timeout(Duration::from_secs(options.acquire_timeout_secs))
+            .connect_with(db_opts)
+            .await
+            .unwrap();
 
         Self::init_db(&pool).await;
 
-        Self { pool }
+        let journal_mode = sqlx::query_scalar::<_, String>("PRAGMA journal_mode")
+            .fetch_one(&pool)
+            .await
+            .unwrap_or_else(|e| e.to_string());
+        log::info!(
+            "sqlite journal mode: {}, pool of {} connections, busy timeout {} ms",
+            journal_mode,
+            options.pool_size.max(1),
+            options.busy_timeout_ms
+        );
+
+        Self {
+            pool,
+            max_connections: options.pool_size.max(1),
+        }
+    }
+
+    pub fn pool_stats(&self) -> DatabasePoolStats {
+        DatabasePoolStats {
+            size: self.pool.size(),
+            idle: self.pool.num_idle() as u32,
+            max: self.max_connections,
+        }
+    }
+
+    /// Begin a transaction which writes
+    ///
+    /// SQLite does not wait for the write lock in a transaction which already read, it fails with `SQLITE_BUSY`
+    /// whatever the busy timeout. The transaction takes the write lock with an empty write before anything else,
+    /// so it waits for the other writers like the single statements do. Sqlx 0.6 cannot issue `BEGIN IMMEDIATE`.
+    async fn begin_write(&self) -> Result<sqlx::Transaction<'static, Sqlite>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM settings WHERE 0").execute(&mut tx).await?;
+        Ok(tx)
     }
 
     async fn init_db(pool: &SqlitePool) {
@@ -110,12 +215,19 @@ impl Database {
             migrator.run(pool).await.unwrap();
         }
 
-        // query_file! macro use path relative to Cargo.toml
-        sqlx::query_file!("../../db_v2/create/db.sql")
-            .execute(&mut conn)
-            .await
-            .unwrap();
-        // Load and run migrations
+        if let Err(e) = migrations::run_migrations(&mut conn, MIGRATIONS).await {
+            panic!("Cannot migrate the database: {}", e);
+        }
+    }
+
+    /// The migrations which would be applied on the database, without applying them
+    pub async fn pending_migrations<P: AsRef<Path>>(db_filename: P) -> Result<Vec<Migration>, String> {
+        let db_opts = SqliteConnectOptions::new()
+            .filename(db_filename.as_ref())
+            .create_if_missing(true);
+        let mut conn: SqliteConnection = db_opts.connect().await.map_err(|e| e.to_string())?;
+        let current = migrations::schema_version(&mut conn).await.map_err(|e| e.to_string())?;
+        migrations::pending_migrations(current, MIGRATIONS)
     }
 
     pub async fn find_user_by_name(
@@ -137,7 +249,8 @@ impl Database {
                 guid,
                 status,
                 role,
-                email
+                email,
+                email_verified
             FROM
                 user
             WHERE
@@ -155,6 +268,7 @@ impl Database {
         let dbi = DatabaseUserInfo {
             active: res.status == 1,
             admin: res.role == 1,
+            email_verified: res.email_verified,
         };
 
         (conn, Some((user_id, email, dbi)))
@@ -263,6 +377,41 @@ impl Database {
         Some(ab)
     }
 
+    /// Get the legacy address books of several users in one query
+    ///
+    /// # Returns
+    /// The users having an address book with it, the others are left out
+    pub async fn get_legacy_address_books(&self, user_ids: &[UserId]) -> Option<Vec<(UserId, AddressBook)>> {
+        if user_ids.is_empty() {
+            return Some(Vec::new());
+        }
+        let mut conn = self.pool.acquire().await.unwrap();
+        let mut query_builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new("SELECT user_guid, ab FROM ab_legacy WHERE user_guid IN (");
+        let mut separated = query_builder.separated(", ");
+        for user_id in user_ids {
+            separated.push_bind(user_id);
+        }
+        separated.push_unseparated(")");
+        let res = query_builder.build().fetch_all(&mut conn).await;
+        if let Err(e) = &res {
+            log::error!("get_legacy_address_books error: {:?}", e);
+            return None;
+        }
+        let address_books = res
+            .unwrap()
+            .into_iter()
+            .map(|row| {
+                let ab = AddressBook {
+                    ab: row.get("ab"),
+                    ..Default::default()
+                };
+                (row.get("user_guid"), ab)
+            })
+            .collect();
+        Some(address_books)
+    }
+
     // Update user password
     // first check if password is correct
     // if correct, update password
@@ -291,13 +440,14 @@ impl Database {
             return None;
         }
         let new_password_hashed = UserPasswordInfo::hash_password(new_password.as_str());
-        let mut tx = self.pool.begin().await.unwrap();
+        let mut tx = self.begin_write().await.ok()?;
         let res = sqlx::query!(
             r#"
             UPDATE
                 user
             SET
-                password = ?
+                password = ?,
+                password_login = 1
             WHERE
                 guid = ?
         "#,
@@ -318,13 +468,14 @@ impl Database {
     // reset user password
     pub async fn reset_user_password(&self, username: String, new_password: String) -> Option<()> {
         let new_password_hashed = UserPasswordInfo::hash_password(new_password.as_str());
-        let mut tx = self.pool.begin().await.unwrap();
+        let mut tx = self.begin_write().await.ok()?;
         let res = sqlx::query!(
             r#"
             UPDATE
                 user
             SET
-                password = ?
+                password = ?,
+                password_login = 1
             WHERE
                 name = ?
         "#,
@@ -346,7 +497,7 @@ impl Database {
         &self,
         mut values: Vec<(UserId, AddressBook)>,
     ) -> Option<()> {
-        let mut tx = self.pool.begin().await.unwrap();
+        let mut tx = self.begin_write().await.ok()?;
 
         let mut query_builder: QueryBuilder<Sqlite> = QueryBuilder::new(
             // Note the trailing space; most calls to `QueryBuilder` don't automatically insert
@@ -435,7 +586,7 @@ impl Database {
         admin: bool,
     ) -> Option<UserId> {
         let password_hashed = UserPasswordInfo::hash_password(password.as_str());
-        let mut tx = self.pool.begin().await.unwrap();
+        let mut tx = self.begin_write().await.ok()?;
         let _res = sqlx::query!(
             r#"
             INSERT INTO
@@ -461,7 +612,39 @@ impl Database {
     }
 
     pub async fn delete_user(&self, user_id: UserId) -> Option<()> {
-        let mut tx = self.pool.begin().await.unwrap();
+        let mut tx = self.begin_write().await.ok()?;
+        // keep the peers but remove their assignment
+        sqlx::query!(r#"UPDATE peer SET user = NULL WHERE user = ?"#, user_id)
+            .execute(&mut tx)
+            .await
+            .ok()?;
+        sqlx::query!(r#"DELETE FROM user_data WHERE "user" = ?"#, user_id)
+            .execute(&mut tx)
+            .await
+            .ok()?;
+        sqlx::query!(r#"DELETE FROM user_third_auth WHERE "user" = ?"#, user_id)
+            .execute(&mut tx)
+            .await
+            .ok()?;
+        sqlx::query!(r#"DELETE FROM email_verification WHERE "user" = ?"#, user_id)
+            .execute(&mut tx)
+            .await
+            .ok()?;
+        sqlx::query!(
+            r#"
+            DELETE FROM share_link_grant
+            WHERE "user" = ? OR link IN (SELECT guid FROM share_link WHERE created_by = ?)
+        "#,
+            user_id,
+            user_id
+        )
+        .execute(&mut tx)
+        .await
+        .ok()?;
+        sqlx::query!(r#"DELETE FROM share_link WHERE created_by = ?"#, user_id)
+            .execute(&mut tx)
+            .await
+            .ok()?;
         let res = sqlx::query!(
             r#"
             DELETE FROM
@@ -482,8 +665,11 @@ impl Database {
         Some(())
     }
 
-    pub async fn update_systeminfo(&self, systeminfo: utils::SystemInfo) -> Option<()> {
-        let mut tx = self.pool.begin().await.unwrap();
+    pub async fn update_systeminfo(
+        &self,
+        systeminfo: utils::SystemInfo,
+    ) -> Option<Vec<PeerEvent>> {
+        let mut tx = self.begin_write().await.ok()?;
         let mut systeminfo = systeminfo;
         let uuid = systeminfo.uuid.clone().unwrap();
 
@@ -497,11 +683,12 @@ impl Database {
             );
             // get old info (if any for keeping ip setted by hbbs)
             let res = sqlx::query!(
-                r#"SELECT info as "info!: String" FROM peer WHERE uuid = ?"#,
+                r#"SELECT id, info as "info!: String", datetime('now') as "now!: String" FROM peer WHERE uuid = ?"#,
                 uuid_decoded
             )
             .fetch_one(&mut tx)
             .await;
+            let mut events = Vec::new();
             if res.is_err() {
                 log::debug!("peer select error: {:?}", res);
                 return None;
@@ -510,6 +697,14 @@ impl Database {
                 let old_systeminfo: utils::SystemInfo =
                     rocket::serde::json::from_str(&res.info).unwrap();
                 systeminfo.ip = old_systeminfo.ip.clone();
+                // hbbs only stores the ip, the first system info completes the registration
+                if old_systeminfo.hostname.is_none() {
+                    events.push(PeerEvent {
+                        event_type: PeerEventType::PeerRegistered,
+                        id: res.id,
+                        created_at: res.now,
+                    });
+                }
             }
             let systeminfo_string = rocket::serde::json::to_string(&systeminfo).unwrap();
             log::debug!("systeminfo_string: {:?}", systeminfo_string);
@@ -526,1206 +721,5075 @@ impl Database {
             if res == 0 {
                 return None;
             } else {
-                return Some(());
+                return Some(events);
             }
         }
-        Some(())
-    }
-
-    pub async fn update_heartbeat(&self, heartbeat: utils::HeartbeatRequest) -> Option<()> {
-        let uuid = heartbeat.uuid.clone();
-        let uuid_decoded = BASE64_STANDARD.decode(uuid);
-        if uuid_decoded.is_ok() {
-            let uuid_decoded = uuid_decoded.unwrap();
-            log::debug!(
-                "uuid_decoded: {:?} {:?}",
-                uuid_decoded,
-                String::from_utf8(uuid_decoded.clone())
-            );
-            let res = sqlx::query!(
-                r#"UPDATE peer SET last_online = current_timestamp WHERE uuid = ?"#,
-                uuid_decoded
-            )
-            .execute(&self.pool)
-            .await;
-            if res.is_err() {
-                log::debug!("update_heartbeat error: {:?}", res);
-                return None;
-            }
-            let res = res.unwrap().rows_affected();
-
-            if res == 0 {
-                return None;
-            } else {
-                log::debug!("update_heartbeat row affected: {:?}", res);
-                return Some(());
-            }
-        }
-        None
+        Some(Vec::new())
     }
 
-    /// Get user for oauth2 flow
-    /// if the user does not exist, create it with status=0 and role=0
-    /// if environment variable OAUTH2_CREATE_USER is set to 1, create the user with status=1 and role=0
-    /// if the user exists return its guid
+    /// Check that the uuid reported by a client is the one registered for its id
+    /// A mismatch usually means that the disk of a machine was cloned, the registered peer is kept
+    /// and the conflict is recorded for the admins
     ///
     /// # Arguments
-    /// * `id` - peer id
-    /// * `uuid` - peer uuid
+    /// * `id` - peer id reported by the client
+    /// * `uuid` - peer uuid (base64) reported by the client
     ///
     /// # Returns
-    /// Option<(UserId, name: String,DatabaseUserInfo)>
-    ///
-    pub async fn get_user_for_oauth2(
-        &self,
-        id: String,
-        email: String,
-        uuid: String,
-    ) -> Option<(UserId, String, DatabaseUserInfo)> {
-        let mut conn = DatabaseConnection {
-            conn: self.pool.acquire().await.unwrap(),
+    /// Option<bool> - false if the id is registered with another uuid
+    pub async fn check_peer_uuid(&self, id: &str, uuid: &str) -> Option<bool> {
+        let uuid_decoded = BASE64_STANDARD.decode(uuid).ok()?;
+        let mut conn = self.pool.acquire().await.unwrap();
+        let res = sqlx::query!(r#"SELECT uuid FROM peer WHERE id = ?"#, id)
+            .fetch_optional(&mut conn)
+            .await;
+        if res.is_err() {
+            log::error!("check_peer_uuid error: {:?}", res);
+            return None;
+        }
+        let peer = match res.unwrap() {
+            Some(peer) => peer,
+            None => return Some(true),
         };
-        let status = { env::var("OAUTH2_CREATE_USER").unwrap_or("0".to_string()) == "1" };
-        let ab_guid = Uuid::new_v4().as_bytes().to_vec();
-        let user_guid = Uuid::new_v4().as_bytes().to_vec();
-        let random_password = Uuid::new_v4().to_string();
-        let hashed_random_password = UserPasswordInfo::hash_password(random_password.as_str());
-        log::debug!(
-            "user: {:?}/{:?} has random_password: {:?}",
-            uuid,
-            id,
-            random_password
-        );
-        let name = format!("{}'s Personal Address Book", id);
+        if peer.uuid == uuid_decoded {
+            return Some(true);
+        }
+        log::warn!("peer {} is claimed by another machine (uuid {})", id, uuid);
         let res = sqlx::query!(
             r#"
-            INSERT OR IGNORE INTO user(guid, grp, team, status, role, name, email, password)
-                VALUES (?,
-                    (SELECT guid FROM grp  WHERE name = 'Default'),
-                    (SELECT guid FROM team  WHERE name = 'Default'), ?, 0, ?, ?, ?);
-            INSERT OR IGNORE INTO ab(guid, name, owner, personal, info)
-                VALUES (?,?,?,1,'{}');
-            "#,
-            user_guid,
-            status,
+            INSERT INTO peer_uuid_conflict (id, uuid) VALUES (?, ?)
+            ON CONFLICT (id, uuid) DO UPDATE SET
+                count = count + 1,
+                last_seen = current_timestamp
+        "#,
             id,
-            email,
-            hashed_random_password,
-            ab_guid,
-            name,
-            user_guid
+            uuid_decoded
         )
-        .execute(&mut conn.conn)
+        .execute(&mut conn)
         .await;
         if res.is_err() {
-            log::error!(
-                "get_user_for_oauth2 error while creating/getting user: {:?}",
-                res
-            );
+            log::error!("check_peer_uuid error: {:?}", res);
         }
+        Some(false)
+    }
+
+    /// Get the recorded uuid conflicts, the most recent first
+    pub async fn get_peer_uuid_conflicts(&self) -> Option<Vec<utils::PeerUuidConflict>> {
+        let mut conn = self.pool.acquire().await.unwrap();
         let res = sqlx::query!(
-            r#"  
-            SELECT guid, status, role, name FROM user WHERE name = ?;
-            "#,
-            id
+            r#"
+            SELECT
+                id,
+                uuid,
+                count,
+                first_seen as "first_seen!: String",
+                last_seen as "last_seen!: String"
+            FROM
+                peer_uuid_conflict
+            ORDER BY
+                last_seen DESC
+        "#
         )
-        .fetch_one(&mut conn.conn)
+        .fetch_all(&mut conn)
         .await;
-
         if res.is_err() {
-            log::error!(
-                "get_user_for_oauth2 error while creating/getting user: {:?}",
-                res
-            );
+            log::error!("get_peer_uuid_conflicts error: {:?}", res);
             return None;
         }
-        let res = res.unwrap();
-        let user_id: UserId = res.guid;
-        let dbi = DatabaseUserInfo {
-            active: res.status == 1,
-            admin: res.role == 1,
-        };
-        Some((user_id, res.name, dbi))
+        let conflicts = res
+            .unwrap()
+            .into_iter()
+            .map(|row| utils::PeerUuidConflict {
+                id: row.id,
+                uuid: BASE64_STANDARD.encode(row.uuid),
+                count: row.count,
+                first_seen: row.first_seen,
+                last_seen: row.last_seen,
+            })
+            .collect();
+        Some(conflicts)
     }
 
-    pub async fn get_personal_address_book(&self, user_id: UserId) {
+    /// Forget the uuid conflicts of a peer once they were dealt with
+    ///
+    /// # Returns
+    /// Option<u64> - the number of conflicts deleted
+    pub async fn delete_peer_uuid_conflicts(&self, id: &str) -> Option<u64> {
         let mut conn = self.pool.acquire().await.unwrap();
+        let res = sqlx::query!(r#"DELETE FROM peer_uuid_conflict WHERE id = ?"#, id)
+            .execute(&mut conn)
+            .await;
+        if res.is_err() {
+            log::error!("delete_peer_uuid_conflicts error: {:?}", res);
+            return None;
+        }
+        Some(res.unwrap().rows_affected())
+    }
 
-        let _res = sqlx::query!(
+    /// Store the image of a peer, replacing the previous one
+    ///
+    /// # Returns
+    /// Option<bool> - false if the peer does not exist
+    pub async fn set_peer_avatar(&self, id: &str, content_type: &str, data: &[u8]) -> Option<bool> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let res = sqlx::query!(
             r#"
-            SELECT
-                guid
-            FROM
-                ab
-            WHERE
-                owner = ?
-                AND personal = 1
+            INSERT INTO peer_avatar (id, content_type, data)
+            SELECT ?, ?, ? WHERE EXISTS (SELECT 1 FROM peer WHERE id = ?)
+            ON CONFLICT (id) DO UPDATE SET
+                content_type = excluded.content_type,
+                data = excluded.data,
+                updated_at = current_timestamp
         "#,
-            user_id
+            id,
+            content_type,
+            data,
+            id
         )
-        .fetch_one(&mut conn)
-        .await
-        .ok();
+        .execute(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("set_peer_avatar error: {:?}", res);
+            return None;
+        }
+        Some(res.unwrap().rows_affected() > 0)
     }
 
-    /// Get the personal address book guid for a user
-    /// if the user is inactive (status != 1) return None
-    pub async fn get_ab_personal_guid(&self, user_id: UserId) -> Option<String> {
+    /// Get the image of a peer
+    ///
+    /// # Returns
+    /// Option<utils::PeerAvatar> - None if the peer has no image
+    pub async fn get_peer_avatar(&self, id: &str) -> Option<utils::PeerAvatar> {
         let mut conn = self.pool.acquire().await.unwrap();
-
         let res = sqlx::query!(
             r#"
             SELECT
-                a.guid
+                content_type,
+                data,
+                updated_at as "updated_at!: String"
             FROM
-                ab as a, user as u
+                peer_avatar
             WHERE
-                u.guid = ?
-                AND a.personal = 1
-                AND a.owner = u.guid
-                AND u.status = 1
+                id = ?
         "#,
-            user_id
+            id
         )
-        .fetch_one(&mut conn)
+        .fetch_optional(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("get_ab_personal_guid error: {:?}", res);
+            log::error!("get_peer_avatar error: {:?}", res);
             return None;
         }
+        res.unwrap().map(|row| utils::PeerAvatar {
+            content_type: row.content_type,
+            data: row.data,
+            updated_at: row.updated_at,
+        })
+    }
 
-        let res = res.unwrap();
-        let guid_u8: Result<[u8; 16], _> = res.guid.try_into();
-        if guid_u8.is_err() {
-            log::error!("get_ab_personal_guid error: {:?}", guid_u8);
+    /// Store an audit record sent by a client
+    ///
+    /// # Arguments
+    /// * `event` - the parsed audit record
+    /// * `info` - the whole record as JSON, unknown fields included
+    pub async fn add_client_audit(&self, event: &AuditEvent, info: &str) -> Option<()> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let guid = Uuid::new_v4().as_bytes().to_vec();
+        let res = match event {
+            AuditEvent::Conn(conn_audit) => {
+                // the records of a connection are merged in a single row whatever their order,
+                // the first open and the first close win
+                let is_open = conn_audit.action == ConnAuditAction::Open;
+                let is_close = conn_audit.action == ConnAuditAction::Close;
+                sqlx::query!(
+                    r#"
+                    INSERT INTO audit_client_conn (guid, peer, conn_id, session_id, type, remote, remote_name, ip, opened_at, closed_at, duration, info)
+                    VALUES (
+                        ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8,
+                        CASE
+                            WHEN ?9 THEN strftime('%Y-%m-%d %H:%M:%f', 'now')
+                            WHEN ?10 AND ?11 IS NOT NULL THEN strftime('%Y-%m-%d %H:%M:%f', 'now', '-' || ?11 || ' seconds')
+                        END,
+                        CASE WHEN ?10 THEN strftime('%Y-%m-%d %H:%M:%f', 'now') END,
+                        CASE WHEN ?10 THEN ?11 END,
+                        ?12
+                    )
+                    ON CONFLICT (peer, conn_id) DO UPDATE SET
+                        session_id = COALESCE(excluded.session_id, session_id),
+                        type = COALESCE(excluded.type, type),
+                        remote = COALESCE(excluded.remote, remote),
+                        remote_name = COALESCE(excluded.remote_name, remote_name),
+                        ip = COALESCE(ip, excluded.ip),
+                        opened_at = COALESCE(opened_at, excluded.opened_at),
+                        closed_at = COALESCE(closed_at, excluded.closed_at),
+                        duration = COALESCE(
+                            duration,
+                            excluded.duration,
+                            CAST(ROUND((julianday(excluded.closed_at) - julianday(opened_at)) * 86400) AS INTEGER)
+                        ),
+                        info = json_patch(info, excluded.info)
+                "#,
+                    guid,
+                    conn_audit.peer,
+                    conn_audit.conn_id,
+                    conn_audit.session_id,
+                    conn_audit.conn_type,
+                    conn_audit.remote,
+                    conn_audit.remote_name,
+                    conn_audit.ip,
+                    is_open,
+                    is_close,
+                    conn_audit.duration,
+                    info
+                )
+                .execute(&mut conn)
+                .await
+            }
+            AuditEvent::File(file_audit) => {
+                sqlx::query!(
+                    r#"
+                    INSERT INTO audit_client_file (guid, peer, remote, type, path, is_file, ip, files, size, info)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+                    guid,
+                    file_audit.peer,
+                    file_audit.remote,
+                    file_audit.direction,
+                    file_audit.path,
+                    file_audit.is_file,
+                    file_audit.ip,
+                    file_audit.files,
+                    file_audit.size,
+                    info
+                )
+                .execute(&mut conn)
+                .await
+            }
+            AuditEvent::Alarm(alarm_audit) => {
+                let severity = alarm_audit.severity.as_str();
+                sqlx::query!(
+                    r#"
+                    INSERT INTO audit_client_alarm (guid, peer, type, severity, remote, remote_name, ip, info)
+                    VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+                "#,
+                    guid,
+                    alarm_audit.peer,
+                    alarm_audit.alarm_type,
+                    severity,
+                    alarm_audit.remote,
+                    alarm_audit.remote_name,
+                    alarm_audit.ip,
+                    info
+                )
+                .execute(&mut conn)
+                .await
+            }
+        };
+        if res.is_err() {
+            log::error!("add_client_audit error: {:?}", res);
             return None;
         }
-        let guid_u8: [u8; 16] = guid_u8.unwrap();
-        let guid = Uuid::from_bytes(guid_u8).to_string();
-        Some(guid)
+        Some(())
     }
 
-    /// Add a peer to the address book
-    pub async fn add_peer_to_ab(&self, ab: &str, ab_peer: AbPeer) -> Option<()> {
+    /// Build a query on the audit records
+    /// `source` is a SELECT returning the records with a `peer`, a `user_name` and a `date_column` column,
+    /// the filters of `filter` are applied on top of it
+    /// If `count` is true the query returns the number of matching records in `total`, else the requested page
+    fn audit_query<'a>(
+        source: &str,
+        date_column: &str,
+        filter: &'a AuditFilter,
+        count: bool,
+    ) -> QueryBuilder<'a, Sqlite> {
+        let select = if count { "COUNT(*) as total" } else { "*" };
+        let mut query_builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new(format!("SELECT {} FROM ({}) audit WHERE 1 = 1", select, source));
+        if let Some(from) = &filter.from {
+            query_builder.push(format!(" AND audit.{} >= ", date_column));
+            query_builder.push_bind(from);
+        }
+        if let Some(to) = &filter.to {
+            query_builder.push(format!(" AND audit.{} <= ", date_column));
+            query_builder.push_bind(to);
+        }
+        if let Some(peer) = &filter.peer {
+            query_builder.push(" AND audit.peer = ");
+            query_builder.push_bind(peer);
+        }
+        if let Some(user) = &filter.user {
+            query_builder.push(" AND audit.user_name = ");
+            query_builder.push_bind(user);
+        }
+        if !count {
+            query_builder.push(format!(" ORDER BY audit.{} DESC LIMIT ", date_column));
+            query_builder.push_bind(filter.page_size);
+            query_builder.push(" OFFSET ");
+            query_builder.push_bind(filter.offset());
+        }
+        query_builder
+    }
+
+    /// Run the count and the page queries of an audit query
+    async fn fetch_audit_page(
+        &self,
+        source: &str,
+        date_column: &str,
+        filter: &AuditFilter,
+    ) -> Option<(u32, Vec<SqliteRow>)> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let ab_guid = Uuid::parse_str(ab);
-        if ab_guid.is_err() {
-            log::error!("add_peer_to_ab error: {:?}", ab_guid);
+        let res = Self::audit_query(source, date_column, filter, true)
+            .build()
+            .fetch_one(&mut conn)
+            .await;
+        if let Err(e) = &res {
+            log::error!("fetch_audit_page error: {:?}", e);
             return None;
         }
-        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
-        let ab_peer_guid = Uuid::new_v4().as_bytes().to_vec();
-        let ab_peer_json = rocket::serde::json::to_string(&ab_peer).unwrap();
-        let res = sqlx::query!(
-            r#"
-            -- must be improved !
-            DELETE FROM ab_peer WHERE ab = ? AND id = ?;
-            INSERT OR IGNORE INTO ab_peer (
-                guid,
-                ab,
-                peer,
-                id,
-                note,
-                created_at,
-                info
-                )
-                VALUES (?, ?, (select guid from peer where id = ?), ?, ?, current_timestamp, ?)
-        "#,
-            ab_guid,
-            ab_peer.id,
-            ab_peer_guid,
-            ab_guid,
-            ab_peer.id,
-            ab_peer.id,
-            "",
-            ab_peer_json
-        )
-        .execute(&mut conn)
-        .await;
-        if res.is_err() {
-            log::error!("add_peer_to_ab error: {:?}", res);
+        let total: i64 = res.unwrap().try_get("total").ok()?;
+        let res = Self::audit_query(source, date_column, filter, false)
+            .build()
+            .fetch_all(&mut conn)
+            .await;
+        if let Err(e) = &res {
+            log::error!("fetch_audit_page error: {:?}", e);
             return None;
         }
-        Some(())
+        Some((total as u32, res.unwrap()))
     }
 
-    pub async fn get_peers_from_ab(&self, ab: &str) -> Option<Vec<AbPeer>> {
+    /// Query of the audit records of a category, filtered and paged by `audit_query`, and its date column
+    /// The peers and the users are resolved in the query, a deleted peer or user keeps its id and is flagged
+    fn audit_source(category: AuditCategory) -> (String, &'static str) {
+        // the alias given in the personal address book of the user the peer is assigned to, else its device name
+        let peer_columns = r#"
+                    COALESCE(
+                        (SELECT json_extract(ab_peer.info, '$.alias') FROM ab_peer
+                            JOIN ab ON ab_peer.ab = ab.guid
+                            WHERE ab.owner = peer.user AND ab.personal = 1
+                                AND ab_peer.id = peer.id AND ab_peer.deleted_at IS NULL
+                            LIMIT 1),
+                        json_extract(CASE WHEN json_valid(peer.info) THEN peer.info END, '$.hostname')
+                    ) as peer_name,
+                    peer.guid IS NULL as peer_deleted,
+                    peer.user as user_guid,
+                    peer.user IS NOT NULL AND user.guid IS NULL as user_deleted,
+                    user.name as user_name
+        "#;
+        match category {
+            AuditCategory::Conn => (
+                format!(
+                    r#"
+                SELECT
+                    audit_client_conn.*,
+                    COALESCE(audit_client_conn.opened_at, audit_client_conn.created_at) as start_time,
+                    {}
+                FROM
+                    audit_client_conn
+                    LEFT JOIN peer ON peer.id = audit_client_conn.peer
+                    LEFT JOIN user ON user.guid = peer.user
+            "#,
+                    peer_columns
+                ),
+                "start_time",
+            ),
+            AuditCategory::File => (
+                format!(
+                    r#"
+                SELECT
+                    audit_client_file.*,
+                    {}
+                FROM
+                    audit_client_file
+                    LEFT JOIN peer ON peer.id = audit_client_file.peer
+                    LEFT JOIN user ON user.guid = peer.user
+            "#,
+                    peer_columns
+                ),
+                "created_at",
+            ),
+            AuditCategory::Alarm => (
+                format!(
+                    r#"
+                SELECT
+                    audit_client_alarm.*,
+                    {}
+                FROM
+                    audit_client_alarm
+                    LEFT JOIN peer ON peer.id = audit_client_alarm.peer
+                    LEFT JOIN user ON user.guid = peer.user
+            "#,
+                    peer_columns
+                ),
+                "created_at",
+            ),
+            // the logins are not related to a peer
+            AuditCategory::Login => (
+                r#"
+                SELECT
+                    session.created_at,
+                    session.expiry_at,
+                    NULL as peer,
+                    session.user as user_guid,
+                    user.guid IS NULL as user_deleted,
+                    user.name as user_name,
+                    json_extract(CASE WHEN json_valid(session.info) THEN session.info END, '$.ip') as ip,
+                    json_extract(CASE WHEN json_valid(session.info) THEN session.info END, '$.name') as device_name,
+                    json_extract(CASE WHEN json_valid(session.info) THEN session.info END, '$.os') as os,
+                    json_extract(CASE WHEN json_valid(session.info) THEN session.info END, '$.type') as client_type
+                FROM
+                    session
+                    LEFT JOIN user ON user.guid = session.user
+            "#
+                .to_string(),
+                "created_at",
+            ),
+            // the target of an operation is filtered as its peer
+            AuditCategory::Admin => (
+                r#"
+                SELECT
+                    admin_audit.action,
+                    admin_audit.route,
+                    admin_audit.target as peer,
+                    admin_audit.details,
+                    admin_audit.created_at,
+                    admin_audit.actor,
+                    user.guid IS NULL as actor_deleted,
+                    user.name as user_name
+                FROM
+                    admin_audit
+                    LEFT JOIN user ON user.guid = admin_audit.actor
+            "#
+                .to_string(),
+                "created_at",
+            ),
+        }
+    }
+
+    /// Guid of the user of an audit row as a uuid, None if the row has no user
+    fn audit_user_id(row: &SqliteRow) -> Option<Option<String>> {
+        let guid: Option<Vec<u8>> = row.try_get("user_guid").ok()?;
+        Some(guid.and_then(guid_into_uuid))
+    }
+
+    fn audit_record_from_row(category: AuditCategory, row: &SqliteRow) -> Option<AuditRecord> {
+        let record = match category {
+            AuditCategory::Conn => AuditRecord::Conn(AuditConn {
+                peer: row.try_get("peer").ok()?,
+                conn_id: row.try_get("conn_id").ok()?,
+                session_id: row.try_get("session_id").ok()?,
+                conn_type: row.try_get("type").ok()?,
+                remote: row.try_get("remote").ok()?,
+                remote_name: row.try_get("remote_name").ok()?,
+                ip: row.try_get("ip").ok()?,
+                peer_name: row.try_get("peer_name").ok()?,
+                peer_deleted: row.try_get("peer_deleted").ok()?,
+                user: row.try_get("user_name").ok()?,
+                user_id: Self::audit_user_id(row)?,
+                user_deleted: row.try_get("user_deleted").ok()?,
+                start: row.try_get("start_time").ok()?,
+                end: row.try_get("closed_at").ok()?,
+                duration: row.try_get("duration").ok()?,
+            }),
+            AuditCategory::File => AuditRecord::File(AuditFile {
+                peer: row.try_get("peer").ok()?,
+                remote: row.try_get("remote").ok()?,
+                direction: row.try_get("type").ok()?,
+                path: row.try_get("path").ok()?,
+                is_file: row.try_get("is_file").ok()?,
+                ip: row.try_get("ip").ok()?,
+                files: row.try_get("files").ok()?,
+                size: row.try_get("size").ok()?,
+                peer_name: row.try_get("peer_name").ok()?,
+                peer_deleted: row.try_get("peer_deleted").ok()?,
+                user: row.try_get("user_name").ok()?,
+                user_id: Self::audit_user_id(row)?,
+                user_deleted: row.try_get("user_deleted").ok()?,
+                created_at: row.try_get("created_at").ok()?,
+            }),
+            AuditCategory::Alarm => AuditRecord::Alarm(AuditAlarm {
+                peer: row.try_get("peer").ok()?,
+                alarm_type: row.try_get("type").ok()?,
+                severity: row.try_get("severity").ok()?,
+                remote: row.try_get("remote").ok()?,
+                remote_name: row.try_get("remote_name").ok()?,
+                ip: row.try_get("ip").ok()?,
+                peer_name: row.try_get("peer_name").ok()?,
+                peer_deleted: row.try_get("peer_deleted").ok()?,
+                user: row.try_get("user_name").ok()?,
+                user_id: Self::audit_user_id(row)?,
+                user_deleted: row.try_get("user_deleted").ok()?,
+                created_at: row.try_get("created_at").ok()?,
+            }),
+            AuditCategory::Login => AuditRecord::Login(AuditLogin {
+                user: row.try_get("user_name").ok()?,
+                user_id: Self::audit_user_id(row)?,
+                user_deleted: row.try_get("user_deleted").ok()?,
+                ip: row.try_get("ip").ok()?,
+                device_name: row.try_get("device_name").ok()?,
+                os: row.try_get("os").ok()?,
+                client_type: row.try_get("client_type").ok()?,
+                created_at: row.try_get("created_at").ok()?,
+                expiry_at: row.try_get("expiry_at").ok()?,
+            }),
+            AuditCategory::Admin => {
+                let details: String = row.try_get("details").ok()?;
+                let actor_id: Vec<u8> = row.try_get("actor").ok()?;
+                AuditRecord::Admin(AuditAdmin {
+                    actor: row.try_get("user_name").ok()?,
+                    actor_id: guid_into_uuid(actor_id)?,
+                    actor_deleted: row.try_get("actor_deleted").ok()?,
+                    action: row.try_get("action").ok()?,
+                    route: row.try_get("route").ok()?,
+                    target: row.try_get("peer").ok()?,
+                    details: serde_json::from_str(&details).unwrap_or(serde_json::Value::Null),
+                    created_at: row.try_get("created_at").ok()?,
+                })
+            }
+        };
+        Some(record)
+    }
+
+    /// Get a page of the audit records of a category, the most recent first, and the number of matching records
+    async fn get_audit_records(
+        &self,
+        category: AuditCategory,
+        filter: &AuditFilter,
+    ) -> Option<(u32, Vec<AuditRecord>)> {
+        let (source, date_column) = Self::audit_source(category);
+        let (total, rows) = self.fetch_audit_page(&source, date_column, filter).await?;
+        let records = rows
+            .iter()
+            .map(|row| Self::audit_record_from_row(category, row))
+            .collect::<Option<Vec<AuditRecord>>>();
+        if records.is_none() {
+            log::error!("get_audit_records error: unexpected {} row", category.as_str());
+        }
+        Some((total, records?))
+    }
+
+    /// Get the connections recorded by the peers, the most recent first
+    /// The duration of a connection is known once it is closed
+    pub async fn get_audit_conns(&self, filter: &AuditFilter) -> Option<(u32, Vec<AuditConn>)> {
+        let (total, records) = self.get_audit_records(AuditCategory::Conn, filter).await?;
+        let conns = records
+            .into_iter()
+            .filter_map(|record| match record {
+                AuditRecord::Conn(conn) => Some(conn),
+                _ => None,
+            })
+            .collect();
+        Some((total, conns))
+    }
+
+    /// Get the file transfers recorded by the peers, the most recent first
+    pub async fn get_audit_files(&self, filter: &AuditFilter) -> Option<(u32, Vec<AuditFile>)> {
+        let (total, records) = self.get_audit_records(AuditCategory::File, filter).await?;
+        let files = records
+            .into_iter()
+            .filter_map(|record| match record {
+                AuditRecord::File(file) => Some(file),
+                _ => None,
+            })
+            .collect();
+        Some((total, files))
+    }
+
+    /// Get the alarms raised by the peers, the most recent first
+    pub async fn get_audit_alarms(&self, filter: &AuditFilter) -> Option<(u32, Vec<AuditAlarm>)> {
+        let (total, records) = self.get_audit_records(AuditCategory::Alarm, filter).await?;
+        let alarms = records
+            .into_iter()
+            .filter_map(|record| match record {
+                AuditRecord::Alarm(alarm) => Some(alarm),
+                _ => None,
+            })
+            .collect();
+        Some((total, alarms))
+    }
+
+    /// Count the audit records of a category matching the filters
+    pub async fn count_audit(&self, category: AuditCategory, filter: &AuditFilter) -> Option<u32> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let ab_guid = Uuid::parse_str(ab);
-        if ab_guid.is_err() {
-            log::error!("get_peers_from_ab error: {:?}", ab_guid);
+        let (source, date_column) = Self::audit_source(category);
+        let res = Self::audit_query(&source, date_column, filter, true)
+            .build()
+            .fetch_one(&mut conn)
+            .await;
+        if let Err(e) = &res {
+            log::error!("count_audit error: {:?}", e);
             return None;
         }
-        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let total: i64 = res.unwrap().try_get("total").ok()?;
+        Some(total as u32)
+    }
+
+    /// Stream the audit records of a category matching the filters for the export, one row at a time
+    /// The records are in the order of the audit queries and limited to the page of the filter
+    pub fn export_audit(
+        &self,
+        category: AuditCategory,
+        filter: AuditFilter,
+    ) -> impl Stream<Item = AuditRecord> + Send + '_ {
+        stream! {
+            let mut conn = self.pool.acquire().await.unwrap();
+            let (source, date_column) = Self::audit_source(category);
+            let mut query_builder = Self::audit_query(&source, date_column, &filter, false);
+            let mut rows = query_builder.build().fetch(&mut conn);
+            while let Some(row) = rows.next().await {
+                let record = match row {
+                    Ok(row) => Self::audit_record_from_row(category, &row),
+                    Err(e) => {
+                        log::error!("export_audit error: {:?}", e);
+                        break;
+                    }
+                };
+                match record {
+                    Some(record) => yield record,
+                    None => {
+                        log::error!("export_audit error: unexpected {} row", category.as_str());
+                        break;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Record an operation of an admin
+    ///
+    /// # Arguments
+    /// * `details` - the parameters of the operation as JSON, already redacted
+    pub async fn add_admin_audit(
+        &self,
+        actor: UserId,
+        action: &str,
+        route: &str,
+        target: Option<&str>,
+        details: &str,
+    ) -> Option<()> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let guid = Uuid::new_v4().as_bytes().to_vec();
         let res = sqlx::query!(
             r#"
-            SELECT
-                info
-            FROM
-                ab_peer
-            WHERE
-                ab_peer.ab = ?
+            INSERT INTO admin_audit (guid, actor, action, route, target, details)
+            VALUES (?, ?, ?, ?, ?, ?)
         "#,
-            ab_guid
+            guid,
+            actor,
+            action,
+            route,
+            target,
+            details
         )
-        .fetch_all(&mut conn)
+        .execute(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("get_peers_from_ab error: {:?}", res);
+            log::error!("add_admin_audit error: {:?}", res);
             return None;
         }
-        let res = res.unwrap();
-        let mut ab_peers = Vec::new();
-        for row in res {
-            let ab_peer: AbPeer = rocket::serde::json::from_str(&row.info).unwrap();
-            log::debug!("ab_peer: {:?}", ab_peer);
-            ab_peers.push(ab_peer);
+        Some(())
+    }
+
+    /// Get the operations of the admins, the most recent first
+    /// The `peer` filter applies to the target of the operations and the `user` filter to the admin
+    pub async fn get_admin_audits(&self, filter: &AuditFilter) -> Option<(u32, Vec<AuditAdmin>)> {
+        let (source, date_column) = Self::audit_source(AuditCategory::Admin);
+        let (total, rows) = self.fetch_audit_page(&source, date_column, filter).await?;
+        let audits = rows
+            .iter()
+            .map(|row| match Self::audit_record_from_row(AuditCategory::Admin, row)? {
+                AuditRecord::Admin(admin) => Some(admin),
+                _ => None,
+            })
+            .collect::<Option<Vec<AuditAdmin>>>();
+        if audits.is_none() {
+            log::error!("get_admin_audits error: unexpected row");
         }
-        Some(ab_peers)
+        Some((total, audits?))
     }
 
-    pub async fn delete_peer_from_ab(&self, ab: &str, id: &str) -> Option<()> {
+    /// Delete the audit records of a category created before `horizon`
+    /// The records are deleted by batches of `batch_size`, each batch in its own transaction,
+    /// so that the database is never locked for long
+    ///
+    /// # Arguments
+    /// * `horizon` - UTC date formatted as `%Y-%m-%d %H:%M:%S%.3f`, the newer records are kept
+    ///
+    /// # Returns
+    /// Option<u64> - the number of records deleted
+    pub async fn purge_audit(&self, category: AuditCategory, horizon: &str, batch_size: u32) -> Option<u64> {
+        let tables: &[(&str, &str)] = match category {
+            AuditCategory::Conn => &[("audit_client_conn", "guid")],
+            AuditCategory::File => &[("audit_client_file", "guid")],
+            AuditCategory::Alarm => &[("audit_client_alarm", "guid")],
+            AuditCategory::Login => &[("session", "id")],
+            AuditCategory::Admin => &[("admin_audit", "guid"), ("audit_console", "guid")],
+        };
+        let mut purged = 0;
+        for (table, key) in tables {
+            purged += self.purge_audit_table(table, key, horizon, batch_size).await?;
+        }
+        Some(purged)
+    }
+
+    /// Delete the rows of an audit table created before `horizon`, by batches of `batch_size`
+    async fn purge_audit_table(&self, table: &str, key: &str, horizon: &str, batch_size: u32) -> Option<u64> {
+        let sql = format!(
+            "DELETE FROM {table} WHERE {key} IN (SELECT {key} FROM {table} WHERE created_at < ? LIMIT ?)",
+            table = table,
+            key = key
+        );
+        let mut purged = 0;
+        loop {
+            let mut tx = self.begin_write().await.ok()?;
+            let res = sqlx::query(&sql)
+                .bind(horizon)
+                .bind(batch_size)
+                .execute(&mut tx)
+                .await;
+            if res.is_err() {
+                log::error!("purge_audit error: {:?}", res);
+                return None;
+            }
+            let deleted = res.unwrap().rows_affected();
+            let res = tx.commit().await;
+            if res.is_err() {
+                log::error!("purge_audit error: {:?}", res);
+                return None;
+            }
+            purged += deleted;
+            if deleted < batch_size as u64 {
+                return Some(purged);
+            }
+        }
+    }
+
+    /// Get a server setting
+    pub async fn get_setting(&self, key: &str) -> Option<String> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let ab_guid = Uuid::parse_str(ab);
-        if ab_guid.is_err() {
-            log::error!("delete_peer_from_ab error: {:?}", ab_guid);
+        let res = sqlx::query!(r#"SELECT value FROM settings WHERE key = ?"#, key)
+            .fetch_optional(&mut conn)
+            .await;
+        if res.is_err() {
+            log::error!("get_setting error: {:?}", res);
             return None;
         }
-        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        res.unwrap().map(|row| row.value)
+    }
+
+    /// Create or replace a server setting
+    pub async fn set_setting(&self, key: &str, value: &str) -> Option<()> {
+        let mut conn = self.pool.acquire().await.unwrap();
         let res = sqlx::query!(
-            r#"
-            DELETE FROM ab_peer WHERE ab = ? AND id = ?
-        "#,
-            ab_guid,
-            id
+            r#"INSERT INTO settings (key, value) VALUES (?, ?) ON CONFLICT (key) DO UPDATE SET value = excluded.value"#,
+            key,
+            value
         )
         .execute(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("delete_peer_from_ab error: {:?}", res);
+            log::error!("set_setting error: {:?}", res);
             return None;
         }
         Some(())
     }
 
-    pub async fn get_ab_peer(&self, ab: &str, id: &str) -> Option<AbPeer> {
+    /// Get a data blob of a user
+    ///
+    /// # Arguments
+    ///
+    /// * `user_id` - guid of the user
+    /// * `data_type` - kind of the data, like `ui_settings`
+    ///
+    /// # Returns
+    ///
+    /// Option<String> - None if the user has no such data
+    pub async fn get_user_data(&self, user_id: UserId, data_type: &str) -> Option<String> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let ab_guid = Uuid::parse_str(ab);
-        if ab_guid.is_err() {
-            log::error!("get_ab_peer error: {:?}", ab_guid);
-            return None;
-        }
-        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
         let res = sqlx::query!(
-            r#"
-            SELECT
-                info
-            FROM
-                ab_peer
-            WHERE
-                ab_peer.ab = ? AND ab_peer.id = ?
-        "#,
-            ab_guid,
-            id
+            r#"SELECT data FROM user_data WHERE "user" = ? AND type = ?"#,
+            user_id,
+            data_type
         )
-        .fetch_one(&mut conn)
+        .fetch_optional(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("get_ab_peer error: {:?}", res);
+            log::error!("get_user_data error: {:?}", res);
             return None;
         }
-        let res = res.unwrap();
-        let ab_peer: AbPeer = rocket::serde::json::from_str(&res.info).unwrap();
-        Some(ab_peer)
+        res.unwrap().map(|row| row.data)
     }
 
-    pub async fn add_tag_to_ab(&self, ab: &str, tag: AbTag) -> Option<()> {
+    /// Create or replace a data blob of a user
+    ///
+    /// # Returns
+    ///
+    /// Option<()> - None if the user does not exist
+    pub async fn set_user_data(&self, user_id: UserId, data_type: &str, data: &str) -> Option<()> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let ab_guid = Uuid::parse_str(ab);
-        if ab_guid.is_err() {
-            log::error!("add_tag_to_ab error: {:?}", ab_guid);
-            return None;
-        }
-        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
         let res = sqlx::query!(
             r#"
-            DELETE FROM ab_tag WHERE ab = ? AND name = ?;
-            INSERT OR IGNORE INTO ab_tag (ab, name, color) VALUES (?, ?, ?)
+            INSERT INTO user_data ("user", type, data, updated_at)
+                SELECT guid, ?, ?, current_timestamp FROM user WHERE guid = ?
+                ON CONFLICT ("user", type) DO UPDATE SET data = excluded.data, updated_at = excluded.updated_at
         "#,
-            ab_guid,
-            tag.name,
-            ab_guid,
-            tag.name,
-            tag.color
+            data_type,
+            data,
+            user_id
         )
         .execute(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("add_tag_to_ab error: {:?}", res);
+            log::error!("set_user_data error: {:?}", res);
+            return None;
+        }
+        if res.unwrap().rows_affected() == 0 {
             return None;
         }
         Some(())
     }
 
-    pub async fn get_ab_tags(&self, ab: &str) -> Option<Vec<AbTag>> {
+    /// Record a release uploaded to the bucket, it becomes the latest release of its platform
+    pub async fn add_software_release(
+        &self,
+        platform: &str,
+        channel: ReleaseChannel,
+        version: &str,
+        key: &str,
+        size: u64,
+        sha256: &str,
+        uploaded_by: UserId,
+    ) -> Option<()> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let ab_guid = Uuid::parse_str(ab);
-        if ab_guid.is_err() {
-            log::error!("get_ab_tags error: {:?}", ab_guid);
-            return None;
-        }
-        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let guid = Uuid::new_v4().as_bytes().to_vec();
+        let channel = channel.as_str();
+        let size = size as i64;
         let res = sqlx::query!(
             r#"
-            SELECT
-                name,
-                color
-            FROM
-                ab_tag
-            WHERE
-                ab = ?
+            INSERT INTO software_release (guid, platform, channel, version, key, size, sha256, uploaded_by)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
         "#,
-            ab_guid
+            guid,
+            platform,
+            channel,
+            version,
+            key,
+            size,
+            sha256,
+            uploaded_by
         )
-        .fetch_all(&mut conn)
+        .execute(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("get_ab_tags error: {:?}", res);
+            log::error!("add_software_release error: {:?}", res);
             return None;
         }
-        let res = res.unwrap();
-        let mut ab_tags = Vec::new();
-        for row in res {
-            let ab_tag = AbTag {
-                name: row.name,
-                color: row.color as u32,
-            };
-            ab_tags.push(ab_tag);
-        }
-        Some(ab_tags)
+        Some(())
     }
 
-    pub async fn get_ab_tag(&self, ab: &str, tag: &str) -> Option<AbTag> {
+    /// Get the latest uploaded release of each platform in a channel
+    /// With `max`, SQLite takes the other columns from the most recent row of each platform
+    pub async fn get_latest_software_releases(&self, channel: ReleaseChannel) -> Option<Vec<SoftwareRelease>> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let ab_guid = Uuid::parse_str(ab);
-        if ab_guid.is_err() {
-            log::error!("get_ab_tag error: {:?}", ab_guid);
-            return None;
-        }
-        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let channel_name = channel.as_str();
         let res = sqlx::query!(
             r#"
             SELECT
-                name,
-                color
+                software_release.platform,
+                software_release.version,
+                software_release.key,
+                software_release.size,
+                software_release.sha256,
+                max(software_release.created_at) as "created_at!: String",
+                user.name as "uploaded_by?"
             FROM
-                ab_tag
-            WHERE
-                ab = ?
-                AND name = ?
+                software_release
+                LEFT JOIN user ON user.guid = software_release.uploaded_by
+            WHERE software_release.channel = ?
+            GROUP BY software_release.platform
+            ORDER BY software_release.platform
         "#,
-            ab_guid,
-            tag
+            channel_name
         )
         .fetch_all(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("get_ab_tags error: {:?}", res);
+            log::error!("get_latest_software_releases error: {:?}", res);
             return None;
         }
-        let res = res.unwrap();
-        if res.len() == 0 {
-            return None;
-        }
-        let ab_tag = AbTag {
-            name: res[0].name.clone(),
-            color: res[0].color as u32,
-        };
-        Some(ab_tag)
+        let releases = res
+            .unwrap()
+            .into_iter()
+            .map(|row| SoftwareRelease {
+                platform: row.platform,
+                channel,
+                version: row.version,
+                key: row.key,
+                size: row.size as u64,
+                sha256: row.sha256,
+                uploaded_by: row.uploaded_by.unwrap_or_default(),
+                created_at: row.created_at,
+            })
+            .collect();
+        Some(releases)
     }
 
-    pub async fn rename_ab_tag(&self, ab: &str, old_name: &str, tag: AbTag) -> Option<()> {
+    /// Assign a release channel to a user
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - uuid of the user in string format
+    /// * `channel` - the channel, None to remove the assignment
+    ///
+    /// # Returns
+    ///
+    /// Option<()> - None if the user does not exist
+    pub async fn assign_user_release_channel(&self, user: &str, channel: Option<ReleaseChannel>) -> Option<()> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let ab_guid = Uuid::parse_str(ab);
-        if ab_guid.is_err() {
-            log::error!("rename_ab_tag error: {:?}", ab_guid);
-            return None;
-        }
-        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let user_guid = uuid_into_guid(user)?;
+        let channel = channel.map(|channel| channel.as_str());
         let res = sqlx::query!(
             r#"
-            UPDATE ab_tag SET name = ?, color = ? WHERE ab = ? AND name = ?
+            INSERT INTO user_release_channel(user, channel, modified_at)
+                SELECT guid, ?, current_timestamp FROM user WHERE guid = ?
+                ON CONFLICT(user) DO UPDATE SET channel = excluded.channel, modified_at = excluded.modified_at
         "#,
-            tag.name,
-            tag.color,
-            ab_guid,
-            old_name
+            channel,
+            user_guid
         )
         .execute(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("rename_ab_tag error: {:?}", res);
+            log::error!("assign_user_release_channel error: {:?}", res);
+            return None;
+        }
+        if res.unwrap().rows_affected() == 0 {
             return None;
         }
         Some(())
     }
 
-    pub async fn delete_tag_from_ab(&self, ab: &str, tag: &str) -> Option<()> {
-        let mut conn = self.pool.acquire().await.unwrap();
-        let ab_guid = Uuid::parse_str(ab);
-        if ab_guid.is_err() {
-            log::error!("delete_tag_from_ab error: {:?}", ab_guid);
-            return None;
-        }
-        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+    /// Assign a release channel to a group, it applies to the users of the group without their own channel
+    ///
+    /// # Arguments
+    ///
+    /// * `group` - uuid of the group in string format
+    /// * `channel` - the channel, None to remove the assignment
+    ///
+    /// # Returns
+    ///
+    /// Option<()> - None if the group does not exist
+    pub async fn assign_group_release_channel(&self, group: &str, channel: Option<ReleaseChannel>) -> Option<()> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let group_guid = uuid_into_guid(group)?;
+        let channel = channel.map(|channel| channel.as_str());
         let res = sqlx::query!(
             r#"
-            DELETE FROM ab_tag WHERE ab = ? AND name = ?
+            INSERT INTO grp_release_channel(grp, channel, modified_at)
+                SELECT guid, ?, current_timestamp FROM grp WHERE guid = ?
+                ON CONFLICT(grp) DO UPDATE SET channel = excluded.channel, modified_at = excluded.modified_at
         "#,
-            ab_guid,
-            tag
+            channel,
+            group_guid
         )
         .execute(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("delete_tag_from_ab error: {:?}", res);
+            log::error!("assign_group_release_channel error: {:?}", res);
+            return None;
+        }
+        if res.unwrap().rows_affected() == 0 {
             return None;
         }
         Some(())
     }
 
-    pub async fn add_user(
-        &self,
-        name: String,
-        password: String,
-        email: String,
-        is_admin: bool,
-        group_name: String,
-    ) -> Option<()> {
+    /// Get the release channel of a user, its own one or else the one of its group
+    ///
+    /// # Returns
+    ///
+    /// Option<Option<String>> - None on error, Some(None) if no channel is assigned
+    pub async fn get_user_release_channel(&self, user_id: UserId) -> Option<Option<String>> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let user_guid = Uuid::new_v4().as_bytes().to_vec();
-        // Check if the group exists
+        let res = sqlx::query!(
+            r#"
+            SELECT coalesce(user_release_channel.channel, grp_release_channel.channel) as "channel?: String"
+            FROM user
+                LEFT JOIN user_release_channel ON user_release_channel.user = user.guid
+                LEFT JOIN grp_release_channel ON grp_release_channel.grp = user.grp
+            WHERE user.guid = ?
+        "#,
+            user_id
+        )
+        .fetch_optional(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_user_release_channel error: {:?}", res);
+            return None;
+        }
+        Some(res.unwrap().and_then(|row| row.channel))
+    }
+
+    /// Update the last online time of a peer
+    /// If `user_id` is provided and the peer is not assigned yet, the peer is assigned to this user
+    ///
+    /// # Returns
+    /// Option<Vec<PeerEvent>> - the status transitions recorded by this heartbeat
+    pub async fn update_heartbeat(
+        &self,
+        heartbeat: utils::HeartbeatRequest,
+        user_id: Option<UserId>,
+    ) -> Option<Vec<PeerEvent>> {
+        let uuid = heartbeat.uuid.clone();
+        let uuid_decoded = BASE64_STANDARD.decode(uuid);
+        if uuid_decoded.is_ok() {
+            let uuid_decoded = uuid_decoded.unwrap();
+            log::debug!(
+                "uuid_decoded: {:?} {:?}",
+                uuid_decoded,
+                String::from_utf8(uuid_decoded.clone())
+            );
+            let mut tx = self.begin_write().await.ok()?;
+            let events = Self::record_peer_online(&mut tx, &uuid_decoded)
+                .await
+                .unwrap_or_default();
+            let res = sqlx::query!(
+                r#"UPDATE peer SET last_online = current_timestamp WHERE uuid = ?"#,
+                uuid_decoded
+            )
+            .execute(&mut tx)
+            .await;
+            if res.is_err() {
+                log::debug!("update_heartbeat error: {:?}", res);
+                return None;
+            }
+            let res = res.unwrap().rows_affected();
+            tx.commit().await.ok()?;
+
+            if res == 0 {
+                return None;
+            } else {
+                log::debug!("update_heartbeat row affected: {:?}", res);
+            }
+            if let Some(user_id) = user_id {
+                self.assign_unassigned_peer(&uuid_decoded, user_id).await;
+            }
+            return Some(events);
+        }
+        None
+    }
+
+    /// Record the online transition of a peer in its status history
+    /// Only the transitions are stored, not every heartbeat.
+    /// If the peer stopped beating without being swept, its offline transition is recorded first.
+    ///
+    /// # Returns
+    /// Option<Vec<PeerEvent>> - the recorded transitions, empty if the peer was already online
+    async fn record_peer_online(
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        uuid: &Vec<u8>,
+    ) -> Option<Vec<PeerEvent>> {
+        let offline_after = format!("+{} seconds", PEER_OFFLINE_AFTER_SECS);
+        let stale_before = format!("-{} seconds", PEER_OFFLINE_AFTER_SECS);
         let res = sqlx::query!(
             r#"
             SELECT
-                guid
+                guid,
+                id,
+                last_online < datetime('now', ?) as "stale!: bool",
+                datetime(last_online, ?) as "offline_at!: String",
+                datetime('now') as "now!: String",
+                (SELECT online FROM peer_status_history h
+                    WHERE h.peer = peer.guid
+                    ORDER BY created_at DESC LIMIT 1) as "online: bool"
             FROM
-                grp
+                peer
             WHERE
-                name = ?
+                uuid = ?
         "#,
-            group_name
+            stale_before,
+            offline_after,
+            uuid
         )
-        .fetch_all(&mut conn)
+        .fetch_optional(&mut *tx)
         .await;
         if res.is_err() {
-            log::error!("add_user error: {:?}", res);
+            log::error!("record_peer_online error: {:?}", res);
             return None;
         }
-        let res = res.unwrap();
-        if res.len() == 0 {
-            return None;
+        let peer = res.unwrap()?;
+        let mut events = Vec::new();
+        if peer.online == Some(true) {
+            if !peer.stale {
+                return Some(events);
+            }
+            Self::add_peer_status(tx, &peer.guid, false, Some(peer.offline_at.clone())).await?;
+            events.push(PeerEvent {
+                event_type: PeerEventType::PeerOffline,
+                id: peer.id.clone(),
+                created_at: peer.offline_at,
+            });
         }
-        let group_guid: Vec<u8> = res[0].guid.clone();
-        let ab_guid = Uuid::new_v4().as_bytes().to_vec();
-        let password_hashed = UserPasswordInfo::hash_password(password.as_str());
+        Self::add_peer_status(tx, &peer.guid, true, None).await?;
+        events.push(PeerEvent {
+            event_type: PeerEventType::PeerOnline,
+            id: peer.id,
+            created_at: peer.now,
+        });
+        Some(events)
+    }
+
+    async fn add_peer_status(
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        peer: &Vec<u8>,
+        online: bool,
+        created_at: Option<String>,
+    ) -> Option<()> {
+        let guid = Uuid::new_v4().as_bytes().to_vec();
         let res = sqlx::query!(
             r#"
-            INSERT OR IGNORE INTO user(guid, grp, team, status, role, name, password, email)
-                VALUES (?,
-                    ?,
-                    (SELECT guid FROM team  WHERE name = 'Default'), 1, ?, ?, ?, ?);
-            INSERT OR IGNORE INTO ab(guid, name, owner, personal, info)
-                VALUES (?,"Personal Address Book",?,1,'{}');
-            "#,
-            user_guid,
-            group_guid,
-            is_admin,
-            name,
-            password_hashed,
-            email,
-            ab_guid,
-            user_guid
+            INSERT INTO peer_status_history (guid, peer, online, created_at)
+            VALUES (?, ?, ?, COALESCE(?, current_timestamp))
+        "#,
+            guid,
+            peer,
+            online,
+            created_at
         )
-        .execute(&mut conn)
+        .execute(&mut *tx)
         .await;
         if res.is_err() {
-            log::error!("add_user error: {:?}", res);
+            log::error!("add_peer_status error: {:?}", res);
             return None;
         }
         Some(())
     }
 
-    /// Change user status
-    /// if status is 1, the user is active
-    /// if status is 0, the user is inactive
-    pub async fn user_change_status(&self, uuid: &str, status: u32) -> Option<()> {
-        let mut conn = self.pool.acquire().await.unwrap();
-        let guid = Uuid::parse_str(uuid);
-        if guid.is_err() {
-            log::error!("change_user_status error: {:?}", guid);
-            return None;
-        }
-        let guid = guid.unwrap().as_bytes().to_vec();
+    /// Mark offline the peers which stopped sending heartbeats
+    /// The offline transition is dated from the last heartbeat plus the offline delay,
+    /// so the history stays accurate even if the sweep runs late
+    ///
+    /// # Returns
+    /// Option<Vec<PeerEvent>> - the offline transitions recorded by the sweep
+    pub async fn sweep_offline_peers(&self) -> Option<Vec<PeerEvent>> {
+        let mut tx = self.begin_write().await.ok()?;
+        let offline_after = format!("+{} seconds", PEER_OFFLINE_AFTER_SECS);
+        let stale_before = format!("-{} seconds", PEER_OFFLINE_AFTER_SECS);
         let res = sqlx::query!(
             r#"
-            UPDATE
-                user
-            SET
-                status = ?
+            SELECT
+                guid,
+                id,
+                datetime(last_online, ?) as "offline_at!: String"
+            FROM
+                peer
             WHERE
-                guid = ?
+                last_online < datetime('now', ?)
+                AND (SELECT online FROM peer_status_history h
+                    WHERE h.peer = peer.guid
+                    ORDER BY created_at DESC LIMIT 1) = 1
         "#,
-            status,
-            guid
+            offline_after,
+            stale_before
         )
-        .execute(&mut conn)
+        .fetch_all(&mut tx)
         .await;
         if res.is_err() {
-            log::error!("change_user_status error: {:?}", res);
+            log::error!("sweep_offline_peers error: {:?}", res);
             return None;
         }
-        Some(())
+        let mut events = Vec::new();
+        for peer in res.unwrap() {
+            Self::add_peer_status(&mut tx, &peer.guid, false, Some(peer.offline_at.clone()))
+                .await?;
+            events.push(PeerEvent {
+                event_type: PeerEventType::PeerOffline,
+                id: peer.id,
+                created_at: peer.offline_at,
+            });
+        }
+        tx.commit().await.ok()?;
+        Some(events)
     }
 
-    pub async fn get_all_users(
+    /// Delete the status history older than `retention_days`, by batches of `batch_size`
+    /// The last transition of each peer is kept so its current state stays known
+    pub async fn purge_peer_status_history(&self, retention_days: u32, batch_size: u32) -> Option<u64> {
+        let older_than = format!("-{} days", retention_days);
+        let mut purged = 0;
+        loop {
+            let mut tx = self.begin_write().await.ok()?;
+            let res = sqlx::query!(
+                r#"
+                DELETE FROM
+                    peer_status_history
+                WHERE
+                    guid IN (SELECT old.guid FROM peer_status_history old
+                        WHERE old.created_at < datetime('now', ?)
+                            AND old.created_at < (SELECT MAX(h.created_at) FROM peer_status_history h
+                                WHERE h.peer = old.peer)
+                        LIMIT ?)
+            "#,
+                older_than,
+                batch_size
+            )
+            .execute(&mut tx)
+            .await;
+            if res.is_err() {
+                log::error!("purge_peer_status_history error: {:?}", res);
+                return None;
+            }
+            let deleted = res.unwrap().rows_affected();
+            let res = tx.commit().await;
+            if res.is_err() {
+                log::error!("purge_peer_status_history error: {:?}", res);
+                return None;
+            }
+            purged += deleted;
+            if deleted < batch_size as u64 {
+                return Some(purged);
+            }
+        }
+    }
+
+    /// Get the status history of a peer in the [from, to] window
+    ///
+    /// # Arguments
+    /// * `id` - peer id
+    /// * `from` - start of the window in sqlite format
+    /// * `to` - end of the window in sqlite format
+    ///
+    /// # Returns
+    /// Option<(bool, Vec<(String, bool)>)> - the state at `from` and the transitions in the window,
+    /// None if the peer does not exist
+    pub async fn get_peer_status_history(
         &self,
-        name: Option<&str>,
-        email: Option<&str>,
-        current: u32,
-        page_size: u32,
-    ) -> Option<Vec<UserListResponse>> {
+        id: &str,
+        from: &str,
+        to: &str,
+    ) -> Option<(bool, Vec<(String, bool)>)> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let mut email_filter = "%";
-        let mut name_filter = "%";
-        if email.is_some() {
-            email_filter = email.unwrap();
-        }
-        if name.is_some() {
-            name_filter = name.unwrap();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                guid,
+                (SELECT online FROM peer_status_history h
+                    WHERE h.peer = peer.guid AND h.created_at <= ?
+                    ORDER BY created_at DESC LIMIT 1) as "online: bool"
+            FROM
+                peer
+            WHERE
+                id = ?
+        "#,
+            from,
+            id
+        )
+        .fetch_optional(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_peer_status_history error: {:?}", res);
+            return None;
         }
-        let current = {
-            if current < 1 {
-                1
-            } else {
-                current
-            }
-        };
-        let offset = ((current - 1) * page_size) as i32;
-        let page_size = page_size as i32;
-
+        let peer = res.unwrap()?;
         let res = sqlx::query!(
             r#"
             SELECT
-                user.guid as id,
-                user.status as "active!: i32",
-                user.role as "admin!: bool",
-                user.name as username,
-                user.email as email,
-                user.note as note,
-                grp.name as group_name
+                created_at as "created_at!: String",
+                online as "online!: bool"
             FROM
-                user
-                LEFT JOIN grp
-                    ON user.grp = grp.guid
+                peer_status_history
             WHERE
-                user.name LIKE ?
-                AND user.email LIKE ?
-            LIMIT ?
-            OFFSET ?
+                peer = ? AND created_at > ? AND created_at < ?
+            ORDER BY
+                created_at
         "#,
-            name_filter,
-            email_filter,
-            page_size,
-            offset
+            peer.guid,
+            from,
+            to
         )
         .fetch_all(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("get_all_users error: {:?}", res);
+            log::error!("get_peer_status_history error: {:?}", res);
             return None;
         }
-        let res = res.unwrap();
-        let mut users: Vec<UserListResponse> = Vec::new();
-        for row in res {
-            let guid_u8: Result<[u8; 16], _> = row.id.try_into();
-            if guid_u8.is_err() {
-                log::error!("get_ab_personal_guid error: {:?}", guid_u8);
-                return None;
-            }
-            let guid_u8: [u8; 16] = guid_u8.unwrap();
-            let guid = Uuid::from_bytes(guid_u8).to_string();
-            let user = UserListResponse {
-                guid: guid,
-                name: row.username,
-                email: row.email.unwrap_or("".to_string()),
-                note: row.note,
-                status: row.active,
-                is_admin: row.admin,
-                group_name: row.group_name.unwrap_or("Defaut".to_string()),
-            };
-            users.push(user);
-        }
-        Some(users)
+        let transitions = res
+            .unwrap()
+            .into_iter()
+            .map(|row| (row.created_at, row.online))
+            .collect();
+        Some((peer.online.unwrap_or(false), transitions))
     }
 
-    pub async fn user_update(
-        &self,
-        user_id: UserId,
-        user_parameters: UpdateUserRequest,
-    ) -> Option<()> {
-        let mut conn = self.pool.acquire().await.unwrap();
-        let mut query = "UPDATE user SET ".to_string();
-        let mut query_params = Vec::new();
-        if user_parameters.name.is_some() && user_parameters.name.clone().unwrap().len() > 0 {
-            query.push_str("name = ?, ");
-            query_params.push(user_parameters.name.unwrap());
+    async fn assign_unassigned_peer(&self, uuid: &Vec<u8>, user_id: UserId) -> Option<()> {
+        let mut tx = self.begin_write().await.ok()?;
+        let res = sqlx::query!(
+            r#"UPDATE peer SET user = ? WHERE uuid = ? AND user IS NULL RETURNING id as "id!: String""#,
+            user_id,
+            uuid
+        )
+        .fetch_optional(&mut tx)
+        .await;
+        if res.is_err() {
+            log::error!("assign_unassigned_peer error: {:?}", res);
+            return None;
+        }
+        let id = res.unwrap()?.id;
+        Self::add_peer_assignment_audit(&mut tx, &id, None, Some(user_id.clone()), user_id)
+            .await?;
+        tx.commit().await.ok()?;
+        Some(())
+    }
+
+    /// Get user for oauth2 flow
+    /// if the user does not exist, create it with status=0 and role=0
+    /// if environment variable OAUTH2_CREATE_USER is set to 1, create the user with status=1 and role=0
+    /// if the user exists return its guid
+    ///
+    /// # Arguments
+    /// * `id` - peer id
+    /// * `uuid` - peer uuid
+    ///
+    /// # Returns
+    /// Option<(UserId, name: String,DatabaseUserInfo)>
+    ///
+    pub async fn get_user_for_oauth2(
+        &self,
+        id: String,
+        email: String,
+        uuid: String,
+    ) -> Option<(UserId, String, DatabaseUserInfo)> {
+        let mut conn = DatabaseConnection {
+            conn: self.pool.acquire().await.unwrap(),
+        };
+        let status = { env::var("OAUTH2_CREATE_USER").unwrap_or("0".to_string()) == "1" };
+        let ab_guid = Uuid::new_v4().as_bytes().to_vec();
+        let user_guid = Uuid::new_v4().as_bytes().to_vec();
+        let random_password = Uuid::new_v4().to_string();
+        let hashed_random_password = UserPasswordInfo::hash_password(random_password.as_str());
+        // the random password is never logged, the user sets a password to log in without the provider
+        log::debug!("get_user_for_oauth2: {:?}/{:?}", uuid, id);
+        let name = format!("{}'s Personal Address Book", id);
+        let res = sqlx::query!(
+            r#"
+            INSERT OR IGNORE INTO user(guid, grp, team, status, role, name, email, password, password_login)
+                VALUES (?,
+                    (SELECT guid FROM grp  WHERE name = 'Default'),
+                    (SELECT guid FROM team  WHERE name = 'Default'), ?, 0, ?, ?, ?, 0);
+            INSERT OR IGNORE INTO ab(guid, name, owner, personal, info)
+                VALUES (?,?,?,1,'{}');
+            "#,
+            user_guid,
+            status,
+            id,
+            email,
+            hashed_random_password,
+            ab_guid,
+            name,
+            user_guid
+        )
+        .execute(&mut conn.conn)
+        .await;
+        if res.is_err() {
+            log::error!(
+                "get_user_for_oauth2 error while creating/getting user: {:?}",
+                res
+            );
+        }
+        let res = sqlx::query!(
+            r#"  
+            SELECT guid, status, role, name, email_verified FROM user WHERE name = ?;
+            "#,
+            id
+        )
+        .fetch_one(&mut conn.conn)
+        .await;
+
+        if res.is_err() {
+            log::error!(
+                "get_user_for_oauth2 error while creating/getting user: {:?}",
+                res
+            );
+            return None;
+        }
+        let res = res.unwrap();
+        let user_id: UserId = res.guid;
+        let dbi = DatabaseUserInfo {
+            active: res.status == 1,
+            admin: res.role == 1,
+            email_verified: res.email_verified,
+        };
+        Some((user_id, res.name, dbi))
+    }
+
+    /// Record the login of the user with the OAuth provider `op`, the link is created or its last use updated
+    pub async fn link_third_auth(&self, user_id: &UserId, op: &str, identifier: &str, email: &str) -> Option<()> {
+        let info = serde_json::json!({ "email": email }).to_string();
+        let mut tx = self.begin_write().await.ok()?;
+        sqlx::query!(
+            r#"
+            INSERT INTO user_third_auth("user", type, identifier, info, updated_at)
+                VALUES (?, ?, ?, ?, CURRENT_TIMESTAMP)
+            ON CONFLICT("user", type) DO UPDATE SET
+                identifier = excluded.identifier,
+                info = excluded.info,
+                updated_at = CURRENT_TIMESTAMP
+        "#,
+            user_id,
+            op,
+            identifier,
+            info
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| log::error!("link_third_auth: {}", e))
+        .ok()?;
+        tx.commit().await.ok()?;
+        Some(())
+    }
+
+    /// The OAuth providers linked to the user, by name
+    ///
+    /// # Returns
+    /// Whether the user can log in with a password and the providers, without their `enabled` flag which is not known here,
+    /// None if the user does not exist
+    pub async fn get_linked_providers(&self, user_id: &UserId) -> Option<(bool, Vec<LinkedProvider>)> {
+        let mut conn = self.pool.acquire().await.ok()?;
+        let user = sqlx::query!(
+            r#"
+            SELECT password_login FROM user WHERE guid = ?
+        "#,
+            user_id
+        )
+        .fetch_optional(&mut conn)
+        .await
+        .ok()??;
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                type AS op,
+                identifier,
+                info,
+                created_at AS "linked_at: String",
+                updated_at AS "last_used_at: String"
+            FROM
+                user_third_auth
+            WHERE
+                "user" = ?
+            ORDER BY
+                type
+        "#,
+            user_id
+        )
+        .fetch_all(&mut conn)
+        .await
+        .ok()?;
+        let providers = rows
+            .into_iter()
+            .map(|row| LinkedProvider {
+                op: row.op,
+                identifier: row.identifier,
+                email: serde_json::from_str::<serde_json::Value>(&row.info)
+                    .ok()
+                    .and_then(|info| info.get("email").and_then(|email| email.as_str()).map(str::to_string))
+                    .unwrap_or_default(),
+                enabled: false,
+                linked_at: row.linked_at,
+                last_used_at: row.last_used_at,
+            })
+            .collect();
+        Some((user.password_login, providers))
+    }
+
+    /// Unlink the OAuth provider `op` from the user
+    /// The last login method of the user is kept, the provider is not unlinked if the user has no password and no other provider
+    pub async fn unlink_third_auth(&self, user_id: &UserId, op: &str) -> Option<Result<(), UnlinkProviderError>> {
+        let mut tx = self.begin_write().await.ok()?;
+        let user = sqlx::query!(
+            r#"
+            SELECT
+                password_login,
+                (SELECT COUNT(*) FROM user_third_auth WHERE "user" = user.guid) AS "linked!: i64",
+                (SELECT COUNT(*) FROM user_third_auth WHERE "user" = user.guid AND type = ?) AS "found!: i64"
+            FROM
+                user
+            WHERE
+                guid = ?
+        "#,
+            op,
+            user_id
+        )
+        .fetch_optional(&mut tx)
+        .await
+        .ok()??;
+        if user.found == 0 {
+            return Some(Err(UnlinkProviderError::NotLinked));
+        }
+        if !user.password_login && user.linked <= 1 {
+            return Some(Err(UnlinkProviderError::LastLoginMethod));
+        }
+        sqlx::query!(
+            r#"
+            DELETE FROM user_third_auth WHERE "user" = ? AND type = ?
+        "#,
+            user_id,
+            op
+        )
+        .execute(&mut tx)
+        .await
+        .ok()?;
+        tx.commit().await.ok()?;
+        Some(Ok(()))
+    }
+
+    /// The email of the user and whether it is verified, None if the user does not exist
+    pub async fn get_user_email(&self, user_id: &UserId) -> Option<(Option<String>, bool)> {
+        let mut conn = self.pool.acquire().await.ok()?;
+        let user = sqlx::query!(
+            r#"
+            SELECT email, email_verified FROM user WHERE guid = ?
+        "#,
+            user_id
+        )
+        .fetch_optional(&mut conn)
+        .await
+        .ok()??;
+        Some((user.email, user.email_verified))
+    }
+
+    /// Store the token of a link verifying the email of the user, valid for `ttl`
+    /// The previous pending token of the user is replaced
+    ///
+    /// # Returns
+    /// The email to send the link to and the expiration of the token,
+    /// Err if the user has no email or it is already verified, None if the user does not exist
+    pub async fn create_email_verification(
+        &self,
+        user_id: &UserId,
+        token: &str,
+        ttl: Duration,
+    ) -> Option<Result<(String, String), EmailVerificationError>> {
+        let mut tx = self.begin_write().await.ok()?;
+        let user = sqlx::query!(
+            r#"
+            SELECT email, email_verified FROM user WHERE guid = ?
+        "#,
+            user_id
+        )
+        .fetch_optional(&mut tx)
+        .await
+        .ok()??;
+        let email = match user.email.filter(|email| !email.is_empty()) {
+            Some(email) => email,
+            None => return Some(Err(EmailVerificationError::NoEmail)),
+        };
+        if user.email_verified {
+            return Some(Err(EmailVerificationError::AlreadyVerified));
+        }
+        sqlx::query!(
+            r#"
+            DELETE FROM email_verification WHERE "user" = ?
+        "#,
+            user_id
+        )
+        .execute(&mut tx)
+        .await
+        .ok()?;
+        let ttl = format!("+{} seconds", ttl.as_secs());
+        let expires_at = sqlx::query_scalar!(
+            r#"
+            INSERT INTO email_verification(token, "user", email, expires_at)
+                VALUES (?, ?, ?, datetime(CURRENT_TIMESTAMP, ?))
+            RETURNING expires_at AS "expires_at!: String"
+        "#,
+            token,
+            user_id,
+            email,
+            ttl
+        )
+        .fetch_one(&mut tx)
+        .await
+        .map_err(|e| log::error!("create_email_verification: {}", e))
+        .ok()?;
+        tx.commit().await.ok()?;
+        Some(Ok((email, expires_at)))
+    }
+
+    /// Mark the email of the user who received the token as verified, the token can only be used once
+    ///
+    /// # Returns
+    /// The verified email, Err if the token is unknown or expired
+    pub async fn verify_email(&self, token: &str) -> Option<Result<String, EmailVerificationError>> {
+        let mut tx = self.begin_write().await.ok()?;
+        let verification = sqlx::query!(
+            r#"
+            DELETE FROM email_verification WHERE token = ?
+            RETURNING
+                "user" AS user_id,
+                email AS "email!: String",
+                expires_at > CURRENT_TIMESTAMP AS "valid!: bool"
+        "#,
+            token
+        )
+        .fetch_optional(&mut tx)
+        .await
+        .ok()?;
+        let Some(verification) = verification else {
+            return Some(Err(EmailVerificationError::InvalidToken));
+        };
+        if !verification.valid {
+            // the expired token is removed all the same
+            tx.commit().await.ok()?;
+            return Some(Err(EmailVerificationError::Expired));
+        }
+        let res = sqlx::query!(
+            r#"
+            UPDATE user SET email_verified = 1 WHERE guid = ? AND email = ? COLLATE NOCASE
+        "#,
+            verification.user_id,
+            verification.email
+        )
+        .execute(&mut tx)
+        .await
+        .ok()?;
+        tx.commit().await.ok()?;
+        if res.rows_affected() == 0 {
+            return Some(Err(EmailVerificationError::InvalidToken));
+        }
+        Some(Ok(verification.email))
+    }
+
+    /// Create a share link of a peer, expiring at `expires_at` seconds since the epoch
+    ///
+    /// # Returns
+    /// The guid of the link and its expiration
+    pub async fn create_share_link(
+        &self,
+        peer_id: &str,
+        created_by: &UserId,
+        expires_at: i64,
+    ) -> Option<(String, String)> {
+        let guid = Uuid::new_v4();
+        let guid_bytes = guid.as_bytes().to_vec();
+        let mut tx = self.begin_write().await.ok()?;
+        let expires_at = sqlx::query_scalar!(
+            r#"
+            INSERT INTO share_link(guid, peer_id, created_by, expires_at)
+                VALUES (?, ?, ?, datetime(?, 'unixepoch'))
+            RETURNING expires_at AS "expires_at!: String"
+        "#,
+            guid_bytes,
+            peer_id,
+            created_by,
+            expires_at
+        )
+        .fetch_one(&mut tx)
+        .await
+        .map_err(|e| log::error!("create_share_link: {}", e))
+        .ok()?;
+        tx.commit().await.ok()?;
+        Some((guid.to_string(), expires_at))
+    }
+
+    /// Grant the user the access to the peer of a share link until the link expires
+    /// A user redeeming a link twice keeps a single grant
+    ///
+    /// # Returns
+    /// The expiration of the access, Err if the link does not exist, is not a link of the peer or has expired
+    pub async fn redeem_share_link(
+        &self,
+        link: &str,
+        peer_id: &str,
+        user_id: &UserId,
+    ) -> Option<Result<String, ShareLinkError>> {
+        let Ok(link) = Uuid::parse_str(link) else {
+            return Some(Err(ShareLinkError::Invalid));
+        };
+        let link = link.as_bytes().to_vec();
+        let mut tx = self.begin_write().await.ok()?;
+        let share_link = sqlx::query!(
+            r#"
+            SELECT
+                expires_at AS "expires_at!: String",
+                expires_at > CURRENT_TIMESTAMP AS "valid!: bool"
+            FROM
+                share_link
+            WHERE
+                guid = ? AND peer_id = ?
+        "#,
+            link,
+            peer_id
+        )
+        .fetch_optional(&mut tx)
+        .await
+        .ok()?;
+        let Some(share_link) = share_link else {
+            return Some(Err(ShareLinkError::Invalid));
+        };
+        if !share_link.valid {
+            return Some(Err(ShareLinkError::Expired));
+        }
+        sqlx::query!(
+            r#"
+            INSERT INTO share_link_grant(link, "user") VALUES (?, ?)
+            ON CONFLICT(link, "user") DO UPDATE SET redeemed_at = CURRENT_TIMESTAMP
+        "#,
+            link,
+            user_id
+        )
+        .execute(&mut tx)
+        .await
+        .map_err(|e| log::error!("redeem_share_link: {}", e))
+        .ok()?;
+        tx.commit().await.ok()?;
+        Some(Ok(share_link.expires_at))
+    }
+
+    /// Get the peers shared with a user by the share links it redeemed, while the links are valid
+    /// The peers are not stored in an address book, they are gone when the links expire
+    pub async fn get_share_link_peers(&self, user_id: &UserId) -> Option<Vec<AbPeer>> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                share_link.peer_id AS "id!: String",
+                (SELECT json_extract(CASE WHEN json_valid(peer.info) THEN peer.info END, '$.hostname')
+                    FROM peer WHERE peer.id = share_link.peer_id) AS "hostname: String"
+            FROM
+                share_link_grant
+                JOIN share_link
+                    ON share_link.guid = share_link_grant.link
+            WHERE
+                share_link_grant.user = ?
+                AND share_link.expires_at > CURRENT_TIMESTAMP
+            GROUP BY share_link.peer_id
+            ORDER BY share_link.peer_id
+        "#,
+            user_id
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_share_link_peers error: {:?}", res);
+            return None;
+        }
+        let peers = res
+            .unwrap()
+            .into_iter()
+            .map(|row| AbPeer {
+                id: row.id,
+                hostname: row.hostname,
+                ..Default::default()
+            })
+            .collect();
+        Some(peers)
+    }
+
+    pub async fn get_personal_address_book(&self, user_id: UserId) {
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        let _res = sqlx::query!(
+            r#"
+            SELECT
+                guid
+            FROM
+                ab
+            WHERE
+                owner = ?
+                AND personal = 1
+        "#,
+            user_id
+        )
+        .fetch_one(&mut conn)
+        .await
+        .ok();
+    }
+
+    /// Get the personal address book guid for a user
+    /// if the user is inactive (status != 1) return None
+    pub async fn get_ab_personal_guid(&self, user_id: UserId) -> Option<String> {
+        let mut conn = self.pool.acquire().await.unwrap();
+
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                a.guid
+            FROM
+                ab as a, user as u
+            WHERE
+                u.guid = ?
+                AND a.personal = 1
+                AND a.owner = u.guid
+                AND u.status = 1
+        "#,
+            user_id
+        )
+        .fetch_one(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_ab_personal_guid error: {:?}", res);
+            return None;
+        }
+
+        let res = res.unwrap();
+        let guid_u8: Result<[u8; 16], _> = res.guid.try_into();
+        if guid_u8.is_err() {
+            log::error!("get_ab_personal_guid error: {:?}", guid_u8);
+            return None;
+        }
+        let guid_u8: [u8; 16] = guid_u8.unwrap();
+        let guid = Uuid::from_bytes(guid_u8).to_string();
+        Some(guid)
+    }
+
+    /// Get the guid of the personal address book of a user, even if the user is disabled
+    pub async fn get_owner_ab_personal_guid(&self, user_id: UserId) -> Option<String> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let res = sqlx::query!(
+            r#"
+            SELECT guid FROM ab WHERE owner = ? AND personal = 1
+        "#,
+            user_id
+        )
+        .fetch_optional(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_owner_ab_personal_guid error: {:?}", res);
+            return None;
+        }
+        guid_into_uuid(res.unwrap()?.guid)
+    }
+
+    /// Add a peer to the address book
+    /// A peer already in the address book is replaced and its version is bumped
+    pub async fn add_peer_to_ab(&self, ab: &str, mut ab_peer: AbPeer) -> Option<()> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("add_peer_to_ab error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                COALESCE(json_extract(info, '$.version'), 0) AS "version!: i64"
+            FROM
+                ab_peer
+            WHERE
+                ab = ? AND id = ?
+        "#,
+            ab_guid,
+            ab_peer.id
+        )
+        .fetch_optional(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("add_peer_to_ab error: {:?}", res);
+            return None;
+        }
+        ab_peer.version = Some(res.unwrap().map_or(0, |row| row.version as u64 + 1));
+        let ab_peer_guid = Uuid::new_v4().as_bytes().to_vec();
+        let ab_peer_json = rocket::serde::json::to_string(&ab_peer).unwrap();
+        let res = sqlx::query!(
+            r#"
+            -- must be improved !
+            DELETE FROM ab_peer WHERE ab = ? AND id = ?;
+            INSERT OR IGNORE INTO ab_peer (
+                guid,
+                ab,
+                peer,
+                id,
+                note,
+                created_at,
+                info
+                )
+                VALUES (?, ?, (select guid from peer where id = ?), ?, ?, current_timestamp, ?)
+        "#,
+            ab_guid,
+            ab_peer.id,
+            ab_peer_guid,
+            ab_guid,
+            ab_peer.id,
+            ab_peer.id,
+            "",
+            ab_peer_json
+        )
+        .execute(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("add_peer_to_ab error: {:?}", res);
+            return None;
+        }
+        Some(())
+    }
+
+    pub async fn get_peers_from_ab(&self, ab: &str) -> Option<Vec<AbPeer>> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("get_peers_from_ab error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                info
+            FROM
+                ab_peer
+            WHERE
+                ab_peer.ab = ?
+        "#,
+            ab_guid
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_peers_from_ab error: {:?}", res);
+            return None;
+        }
+        let res = res.unwrap();
+        let mut ab_peers = Vec::new();
+        for row in res {
+            let ab_peer: AbPeer = rocket::serde::json::from_str(&row.info).unwrap();
+            ab_peers.push(ab_peer);
+        }
+        Some(ab_peers)
+    }
+
+    /// Get the page `current` (from 1) of the peers of an address book ordered by creation date
+    ///
+    /// # Returns
+    /// The peers of the page and the number of peers of the address book
+    pub async fn get_peers_offset_from_ab(&self, ab: &str, current: u32, page_size: u32) -> Option<(Vec<AbPeer>, u32)> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("get_peers_offset_from_ab error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let limit = page_size as i64;
+        let offset = (current.max(1) as i64 - 1) * limit;
+        let total = sqlx::query_scalar!(r#"SELECT COUNT(*) as "total!: u32" FROM ab_peer WHERE ab = ?"#, ab_guid)
+            .fetch_one(&mut conn)
+            .await;
+        if total.is_err() {
+            log::error!("get_peers_offset_from_ab error: {:?}", total);
+            return None;
+        }
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                info
+            FROM
+                ab_peer
+            WHERE
+                ab_peer.ab = ?
+            ORDER BY ab_peer.created_at, ab_peer.guid
+            LIMIT ? OFFSET ?
+        "#,
+            ab_guid,
+            limit,
+            offset
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_peers_offset_from_ab error: {:?}", res);
+            return None;
+        }
+        let ab_peers = res
+            .unwrap()
+            .iter()
+            .map(|row| rocket::serde::json::from_str(&row.info).ok())
+            .collect::<Option<Vec<AbPeer>>>();
+        if ab_peers.is_none() {
+            log::error!("get_peers_offset_from_ab error: invalid peer info");
+        }
+        Some((ab_peers?, total.unwrap()))
+    }
+
+    /// Get a page of the peers of an address book ordered by creation date, the page starts after `after`
+    /// The peers added during a traversal are listed at its end
+    ///
+    /// # Returns
+    /// The peers and the cursor of the next page, None if it is the last page
+    pub async fn get_peers_page_from_ab(
+        &self,
+        ab: &str,
+        after: Option<&PageCursor>,
+        page_size: u32,
+    ) -> Option<(Vec<AbPeer>, Option<PageCursor>)> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("get_peers_page_from_ab error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let after_created_at = after.map(|cursor| cursor.created_at.clone());
+        let after_guid = after.map(|cursor| cursor.guid.clone());
+        // one more row tells if there is a next page
+        let limit = page_size as i64 + 1;
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                guid,
+                created_at as "created_at!: String",
+                info
+            FROM
+                ab_peer
+            WHERE
+                ab_peer.ab = ?1
+                AND (?2 IS NULL OR (ab_peer.created_at, ab_peer.guid) > (?2, ?3))
+            ORDER BY ab_peer.created_at, ab_peer.guid
+            LIMIT ?4
+        "#,
+            ab_guid,
+            after_created_at,
+            after_guid,
+            limit
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_peers_page_from_ab error: {:?}", res);
+            return None;
+        }
+        let mut rows = res.unwrap();
+        let has_next = rows.len() > page_size as usize;
+        rows.truncate(page_size as usize);
+        let next = match rows.last() {
+            Some(row) if has_next => Some(PageCursor {
+                created_at: row.created_at.clone(),
+                guid: row.guid.clone(),
+            }),
+            _ => None,
+        };
+        let ab_peers = rows
+            .iter()
+            .map(|row| rocket::serde::json::from_str(&row.info).ok())
+            .collect::<Option<Vec<AbPeer>>>();
+        if ab_peers.is_none() {
+            log::error!("get_peers_page_from_ab error: invalid peer info");
+        }
+        Some((ab_peers?, next))
+    }
+
+    pub async fn delete_peer_from_ab(&self, ab: &str, id: &str) -> Option<()> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("delete_peer_from_ab error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let res = sqlx::query!(
+            r#"
+            DELETE FROM ab_peer WHERE ab = ? AND id = ?
+        "#,
+            ab_guid,
+            id
+        )
+        .execute(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("delete_peer_from_ab error: {:?}", res);
+            return None;
+        }
+        Some(())
+    }
+
+    /// Update a peer of the address book in place and bump its version
+    /// With an expected version the peer is only updated if its version is still this one
+    ///
+    /// # Returns
+    /// Option<bool> - false if the peer was changed or removed meanwhile
+    pub async fn update_ab_peer(
+        &self,
+        ab: &str,
+        mut ab_peer: AbPeer,
+        expected_version: Option<u64>,
+    ) -> Option<bool> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("update_ab_peer error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        ab_peer.version = None;
+        let ab_peer_json = rocket::serde::json::to_string(&ab_peer).unwrap();
+        let expected_version = expected_version.map(|version| version as i64);
+        let res = sqlx::query!(
+            r#"
+            UPDATE ab_peer
+            SET
+                peer = (select guid from peer where id = ?),
+                info = json_set(?, '$.version', COALESCE(json_extract(info, '$.version'), 0) + 1)
+            WHERE ab = ? AND id = ? AND (? IS NULL OR COALESCE(json_extract(info, '$.version'), 0) = ?)
+        "#,
+            ab_peer.id,
+            ab_peer_json,
+            ab_guid,
+            ab_peer.id,
+            expected_version,
+            expected_version
+        )
+        .execute(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("update_ab_peer error: {:?}", res);
+            return None;
+        }
+        Some(res.unwrap().rows_affected() > 0)
+    }
+
+    pub async fn get_ab_peer(&self, ab: &str, id: &str) -> Option<AbPeer> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("get_ab_peer error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                info
+            FROM
+                ab_peer
+            WHERE
+                ab_peer.ab = ? AND ab_peer.id = ?
+        "#,
+            ab_guid,
+            id
+        )
+        .fetch_one(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_ab_peer error: {:?}", res);
+            return None;
+        }
+        let res = res.unwrap();
+        let ab_peer: AbPeer = rocket::serde::json::from_str(&res.info).unwrap();
+        Some(ab_peer)
+    }
+
+    pub async fn add_tag_to_ab(&self, ab: &str, tag: AbTag) -> Option<()> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("add_tag_to_ab error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let res = sqlx::query!(
+            r#"
+            DELETE FROM ab_tag WHERE ab = ? AND name = ?;
+            INSERT OR IGNORE INTO ab_tag (ab, name, color) VALUES (?, ?, ?)
+        "#,
+            ab_guid,
+            tag.name,
+            ab_guid,
+            tag.name,
+            tag.color
+        )
+        .execute(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("add_tag_to_ab error: {:?}", res);
+            return None;
+        }
+        Some(())
+    }
+
+    pub async fn get_ab_tags(&self, ab: &str) -> Option<Vec<AbTag>> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("get_ab_tags error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                name,
+                color
+            FROM
+                ab_tag
+            WHERE
+                ab = ?
+        "#,
+            ab_guid
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_ab_tags error: {:?}", res);
+            return None;
+        }
+        let res = res.unwrap();
+        let mut ab_tags = Vec::new();
+        for row in res {
+            let ab_tag = AbTag {
+                name: row.name,
+                color: row.color as u32,
+            };
+            ab_tags.push(ab_tag);
+        }
+        Some(ab_tags)
+    }
+
+    /// Get the tags of an address book with the number of peers using each of them
+    pub async fn get_ab_tags_stats(&self, ab: &str) -> Option<Vec<AbTagStats>> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("get_ab_tags_stats error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                ab_tag.name,
+                ab_tag.color,
+                COUNT(peer_tag.tag) as "peers!: i64"
+            FROM
+                ab_tag
+                LEFT JOIN (
+                    SELECT
+                        ab_peer.ab,
+                        json_each.value as tag
+                    FROM
+                        ab_peer,
+                        json_each(ab_peer.info, '$.tags')
+                    WHERE
+                        ab_peer.deleted_at IS NULL
+                        AND json_valid(ab_peer.info)
+                ) peer_tag ON peer_tag.ab = ab_tag.ab AND peer_tag.tag = ab_tag.name
+            WHERE
+                ab_tag.ab = ?
+            GROUP BY
+                ab_tag.name,
+                ab_tag.color
+            ORDER BY
+                ab_tag.name
+        "#,
+            ab_guid
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_ab_tags_stats error: {:?}", res);
+            return None;
+        }
+        let ab_tags = res
+            .unwrap()
+            .into_iter()
+            .map(|row| AbTagStats {
+                name: row.name,
+                color: row.color as u32,
+                peers: row.peers as u32,
+            })
+            .collect();
+        Some(ab_tags)
+    }
+
+    pub async fn get_ab_tag(&self, ab: &str, tag: &str) -> Option<AbTag> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("get_ab_tag error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                name,
+                color
+            FROM
+                ab_tag
+            WHERE
+                ab = ?
+                AND name = ?
+        "#,
+            ab_guid,
+            tag
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_ab_tags error: {:?}", res);
+            return None;
+        }
+        let res = res.unwrap();
+        if res.len() == 0 {
+            return None;
+        }
+        let ab_tag = AbTag {
+            name: res[0].name.clone(),
+            color: res[0].color as u32,
+        };
+        Some(ab_tag)
+    }
+
+    /// Rename a tag and update the peers of the address book which have it
+    /// A peer having both the old and the new name keeps a single one
+    pub async fn rename_ab_tag(&self, ab: &str, old_name: &str, tag: AbTag) -> Option<()> {
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("rename_ab_tag error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let mut tx = self.begin_write().await.ok()?;
+        // the first statement writes, so the transaction holds the write lock
+        // before reading the peers and no concurrent update of the peers can be lost
+        let res = sqlx::query!(
+            r#"
+            UPDATE ab_tag SET name = ?, color = ? WHERE ab = ? AND name = ?
+        "#,
+            tag.name,
+            tag.color,
+            ab_guid,
+            old_name
+        )
+        .execute(&mut tx)
+        .await;
+        if res.is_err() {
+            log::error!("rename_ab_tag error: {:?}", res);
+            return None;
+        }
+        if tag.name != old_name {
+            Self::replace_ab_peers_tag(&mut tx, &ab_guid, old_name, Some(&tag.name)).await?;
+        }
+        tx.commit().await.ok()?;
+        Some(())
+    }
+
+    /// Replace a tag by another one in the tags of the peers of an address book, or remove it if `new_name` is None
+    /// A peer having both names keeps a single one
+    async fn replace_ab_peers_tag(
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        ab_guid: &Vec<u8>,
+        old_name: &str,
+        new_name: Option<&str>,
+    ) -> Option<()> {
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                ab_peer.id,
+                ab_peer.info
+            FROM
+                ab_peer
+            WHERE
+                ab_peer.ab = ?
+                AND json_valid(ab_peer.info)
+                AND EXISTS (SELECT 1 FROM json_each(ab_peer.info, '$.tags') WHERE json_each.value = ?)
+        "#,
+            ab_guid,
+            old_name
+        )
+        .fetch_all(&mut *tx)
+        .await;
+        if res.is_err() {
+            log::error!("replace_ab_peers_tag error: {:?}", res);
+            return None;
+        }
+        for row in res.unwrap() {
+            let mut ab_peer: AbPeer = rocket::serde::json::from_str(&row.info).ok()?;
+            let mut tags: Vec<String> = Vec::new();
+            for peer_tag in ab_peer.tags.unwrap_or_default() {
+                let peer_tag = match new_name {
+                    Some(new_name) if peer_tag == old_name => new_name.to_string(),
+                    None if peer_tag == old_name => continue,
+                    _ => peer_tag,
+                };
+                if !tags.contains(&peer_tag) {
+                    tags.push(peer_tag);
+                }
+            }
+            ab_peer.tags = Some(tags);
+            let ab_peer_json = rocket::serde::json::to_string(&ab_peer).unwrap();
+            let res = sqlx::query!(
+                r#"UPDATE ab_peer SET info = ? WHERE ab = ? AND id = ?"#,
+                ab_peer_json,
+                ab_guid,
+                row.id
+            )
+            .execute(&mut *tx)
+            .await;
+            if res.is_err() {
+                log::error!("replace_ab_peers_tag error: {:?}", res);
+                return None;
+            }
+        }
+        Some(())
+    }
+
+    /// Add and remove tags on several peers of an address book in one transaction
+    /// The peers which are not in the address book are ignored
+    ///
+    /// # Arguments
+    /// * `ab` - address book guid
+    /// * `peer_ids` - ids of the peers to update
+    /// * `add_tags` - tags to add to each peer
+    /// * `remove_tags` - tags to remove from each peer
+    ///
+    /// # Returns
+    /// Option<u32> - the number of peers whose tags changed, None if a tag does not exist in the address book
+    pub async fn update_ab_peers_tags(
+        &self,
+        ab: &str,
+        peer_ids: &[String],
+        add_tags: &[String],
+        remove_tags: &[String],
+    ) -> Option<u32> {
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("update_ab_peers_tags error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let mut tx = self.begin_write().await.ok()?;
+        let res = sqlx::query!(r#"SELECT name FROM ab_tag WHERE ab = ?"#, ab_guid)
+            .fetch_all(&mut tx)
+            .await;
+        if res.is_err() {
+            log::error!("update_ab_peers_tags error: {:?}", res);
+            return None;
+        }
+        let ab_tags: Vec<String> = res.unwrap().into_iter().map(|row| row.name).collect();
+        let unknown_tag = add_tags
+            .iter()
+            .chain(remove_tags.iter())
+            .find(|tag| !ab_tags.contains(tag));
+        if unknown_tag.is_some() {
+            log::error!("update_ab_peers_tags unknown tag: {:?}", unknown_tag);
+            return None;
+        }
+
+        let mut modified = 0;
+        for id in peer_ids {
+            let res = sqlx::query!(
+                r#"SELECT info FROM ab_peer WHERE ab = ? AND id = ?"#,
+                ab_guid,
+                id
+            )
+            .fetch_optional(&mut tx)
+            .await;
+            if res.is_err() {
+                log::error!("update_ab_peers_tags error: {:?}", res);
+                return None;
+            }
+            let row = match res.unwrap() {
+                Some(row) => row,
+                None => continue,
+            };
+            let mut ab_peer: AbPeer = rocket::serde::json::from_str(&row.info).ok()?;
+            let old_tags = ab_peer.tags.clone().unwrap_or_default();
+            let mut tags: Vec<String> = old_tags
+                .iter()
+                .filter(|tag| !remove_tags.contains(tag))
+                .cloned()
+                .collect();
+            for tag in add_tags {
+                if !tags.contains(tag) {
+                    tags.push(tag.clone());
+                }
+            }
+            if tags == old_tags {
+                continue;
+            }
+            ab_peer.tags = Some(tags);
+            let ab_peer_json = rocket::serde::json::to_string(&ab_peer).unwrap();
+            let res = sqlx::query!(
+                r#"UPDATE ab_peer SET info = ? WHERE ab = ? AND id = ?"#,
+                ab_peer_json,
+                ab_guid,
+                id
+            )
+            .execute(&mut tx)
+            .await;
+            if res.is_err() {
+                log::error!("update_ab_peers_tags error: {:?}", res);
+                return None;
+            }
+            modified += 1;
+        }
+        tx.commit().await.ok()?;
+        Some(modified)
+    }
+
+    /// Merge duplicates of a peer of an address book into it and delete them, in one transaction
+    /// The kept peer gets the union of the tags, and the values of the duplicates for its empty fields and note
+    ///
+    /// # Arguments
+    /// * `ab` - address book guid
+    /// * `keep_id` - id of the peer which is kept
+    /// * `merge_ids` - ids of the duplicates
+    ///
+    /// # Returns
+    /// Option<AbPeer> - the kept peer once merged, None if one of the peers is not in the address book
+    pub async fn merge_ab_peers(&self, ab: &str, keep_id: &str, merge_ids: &[String]) -> Option<AbPeer> {
+        let ab_guid = uuid_into_guid(ab)?;
+        let mut tx = self.begin_write().await.ok()?;
+        let mut peers = Vec::new();
+        for id in std::iter::once(keep_id).chain(merge_ids.iter().map(String::as_str)) {
+            let res = sqlx::query!(
+                r#"SELECT info, note FROM ab_peer WHERE ab = ? AND id = ?"#,
+                ab_guid,
+                id
+            )
+            .fetch_optional(&mut tx)
+            .await;
+            if res.is_err() {
+                log::error!("merge_ab_peers error: {:?}", res);
+                return None;
+            }
+            let row = res.unwrap();
+            if row.is_none() {
+                log::debug!("merge_ab_peers: {} is not in the address book", id);
+                return None;
+            }
+            let row = row.unwrap();
+            let ab_peer: AbPeer = rocket::serde::json::from_str(&row.info).ok()?;
+            peers.push((ab_peer, row.note));
+        }
+        let mut peers = peers.into_iter();
+        let (mut kept, mut note) = peers.next()?;
+        for (duplicate, duplicate_note) in peers {
+            kept.merge_from(&duplicate);
+            if note.as_deref().map_or(true, str::is_empty) {
+                note = duplicate_note;
+            }
+        }
+        for id in merge_ids {
+            let res = sqlx::query!(
+                r#"DELETE FROM ab_peer WHERE ab = ? AND id = ?"#,
+                ab_guid,
+                id
+            )
+            .execute(&mut tx)
+            .await;
+            if res.is_err() {
+                log::error!("merge_ab_peers error: {:?}", res);
+                return None;
+            }
+        }
+        let ab_peer_json = rocket::serde::json::to_string(&kept).unwrap();
+        let res = sqlx::query!(
+            r#"UPDATE ab_peer SET info = ?, note = ? WHERE ab = ? AND id = ?"#,
+            ab_peer_json,
+            note,
+            ab_guid,
+            keep_id
+        )
+        .execute(&mut tx)
+        .await;
+        if res.is_err() {
+            log::error!("merge_ab_peers error: {:?}", res);
+            return None;
+        }
+        tx.commit().await.ok()?;
+        Some(kept)
+    }
+
+    /// Delete some tags from an address book and remove them from the tags of its peers, in one transaction
+    pub async fn delete_ab_tags(&self, ab: &str, tags: &[String]) -> Option<()> {
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("delete_ab_tags error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let mut tx = self.begin_write().await.ok()?;
+        for tag in tags {
+            let res = sqlx::query!(
+                r#"
+                DELETE FROM ab_tag WHERE ab = ? AND name = ?
+            "#,
+                ab_guid,
+                tag
+            )
+            .execute(&mut tx)
+            .await;
+            if res.is_err() {
+                log::error!("delete_ab_tags error: {:?}", res);
+                return None;
+            }
+            Self::replace_ab_peers_tag(&mut tx, &ab_guid, tag, None).await?;
+        }
+        tx.commit().await.ok()?;
+        Some(())
+    }
+
+    /// Find whether the name or the email of a user is already used by another user
+    /// The emails are compared case-insensitively, `except` is the guid of the user being updated
+    ///
+    /// # Returns
+    /// The field already used, None if both are free
+    async fn find_user_conflict(
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        name: Option<&str>,
+        email: Option<&str>,
+        except: Option<&Vec<u8>>,
+    ) -> Option<Option<UserField>> {
+        let except = except.cloned().unwrap_or_default();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                name
+            FROM
+                user
+            WHERE
+                guid != ?
+                AND (name = ? OR lower(email) = lower(?))
+            LIMIT 1
+            "#,
+            except,
+            name,
+            email
+        )
+        .fetch_optional(&mut *tx)
+        .await;
+        if res.is_err() {
+            log::error!("find_user_conflict error: {:?}", res);
+            return None;
+        }
+        Some(res.unwrap().map(|row| {
+            if Some(row.name.as_str()) == name {
+                UserField::Name
+            } else {
+                UserField::Email
+            }
+        }))
+    }
+
+    /// Add a user with its personal address book
+    ///
+    /// # Returns
+    /// Err with the field already used by another user if the name or the email is not unique
+    pub async fn add_user(
+        &self,
+        name: String,
+        password: String,
+        email: String,
+        is_admin: bool,
+        group_name: String,
+    ) -> Option<Result<(), UserField>> {
+        let mut tx = self.begin_write().await.ok()?;
+        let user_guid = Uuid::new_v4().as_bytes().to_vec();
+        let conflicting_email = Some(email.as_str()).filter(|email| !email.is_empty());
+        if let Some(field) =
+            Self::find_user_conflict(&mut tx, Some(&name), conflicting_email, None).await?
+        {
+            return Some(Err(field));
+        }
+        // Check if the group exists
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                guid
+            FROM
+                grp
+            WHERE
+                name = ?
+        "#,
+            group_name
+        )
+        .fetch_all(&mut tx)
+        .await;
+        if res.is_err() {
+            log::error!("add_user error: {:?}", res);
+            return None;
+        }
+        let res = res.unwrap();
+        if res.len() == 0 {
+            return None;
+        }
+        let group_guid: Vec<u8> = res[0].guid.clone();
+        let ab_guid = Uuid::new_v4().as_bytes().to_vec();
+        // address book names are unique
+        let ab_name = format!("{}'s Personal Address Book", name);
+        let password_hashed = UserPasswordInfo::hash_password(password.as_str());
+        let res = sqlx::query!(
+            r#"
+            INSERT INTO user(guid, grp, team, status, role, name, password, email)
+                VALUES (?,
+                    ?,
+                    (SELECT guid FROM team  WHERE name = 'Default'), 1, ?, ?, ?, ?);
+            INSERT OR IGNORE INTO ab(guid, name, owner, personal, info)
+                VALUES (?,?,?,1,'{}');
+            "#,
+            user_guid,
+            group_guid,
+            is_admin,
+            name,
+            password_hashed,
+            email,
+            ab_guid,
+            ab_name,
+            user_guid
+        )
+        .execute(&mut tx)
+        .await;
+        if let Err(e) = &res {
+            if let Some(field) = user_unique_violation(e) {
+                return Some(Err(field));
+            }
+            log::error!("add_user error: {:?}", res);
+            return None;
+        }
+        tx.commit().await.ok()?;
+        Some(Ok(()))
+    }
+
+    /// Change user status
+    pub async fn user_change_status(&self, uuid: &str, status: UserStatus) -> Option<()> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let guid = Uuid::parse_str(uuid);
+        if guid.is_err() {
+            log::error!("change_user_status error: {:?}", guid);
+            return None;
+        }
+        let guid = guid.unwrap().as_bytes().to_vec();
+        let status = i32::from(status);
+        let res = sqlx::query!(
+            r#"
+            UPDATE
+                user
+            SET
+                status = ?
+            WHERE
+                guid = ?
+        "#,
+            status,
+            guid
+        )
+        .execute(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("change_user_status error: {:?}", res);
+            return None;
+        }
+        Some(())
+    }
+
+    /// Check whether setting the status of a user changes it
+    ///
+    /// # Returns
+    /// Option<bool> - false if the user does not exist or already has the status
+    pub async fn user_status_changes(&self, uuid: &str, status: UserStatus) -> Option<bool> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let guid = uuid_into_guid(uuid)?;
+        let status = i32::from(status);
+        let res = sqlx::query!(
+            r#"SELECT COUNT(*) as "changed!: i64" FROM user WHERE guid = ? AND status != ?"#,
+            guid,
+            status
+        )
+        .fetch_one(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("user_status_changes error: {:?}", res);
+            return None;
+        }
+        Some(res.unwrap().changed > 0)
+    }
+
+    pub async fn get_all_users(
+        &self,
+        name: Option<&str>,
+        email: Option<&str>,
+        status: Option<UserStatus>,
+        current: u32,
+        page_size: u32,
+    ) -> Option<Vec<UserListResponse>> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let mut email_filter = "%";
+        let mut name_filter = "%";
+        if email.is_some() {
+            email_filter = email.unwrap();
+        }
+        if name.is_some() {
+            name_filter = name.unwrap();
+        }
+        let current = {
+            if current < 1 {
+                1
+            } else {
+                current
+            }
+        };
+        let offset = i64::from(current - 1).saturating_mul(i64::from(page_size));
+        let page_size = i64::from(page_size);
+        let status = status.map(i32::from);
+
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                user.guid as id,
+                user.status as "active!: i32",
+                user.role as "admin!: bool",
+                user.name as username,
+                user.email as email,
+                user.note as note,
+                user.email_verified as email_verified,
+                grp.name as group_name
+            FROM
+                user
+                LEFT JOIN grp
+                    ON user.grp = grp.guid
+            WHERE
+                user.name LIKE ?
+                AND user.email LIKE ?
+                AND (? IS NULL OR user.status = ?)
+            LIMIT ?
+            OFFSET ?
+        "#,
+            name_filter,
+            email_filter,
+            status,
+            status,
+            page_size,
+            offset
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_all_users error: {:?}", res);
+            return None;
+        }
+        let res = res.unwrap();
+        let mut users: Vec<UserListResponse> = Vec::new();
+        for row in res {
+            let guid_u8: Result<[u8; 16], _> = row.id.try_into();
+            if guid_u8.is_err() {
+                log::error!("get_ab_personal_guid error: {:?}", guid_u8);
+                return None;
+            }
+            let guid_u8: [u8; 16] = guid_u8.unwrap();
+            let guid = Uuid::from_bytes(guid_u8).to_string();
+            let user = UserListResponse {
+                guid: guid,
+                name: row.username,
+                email: row.email.unwrap_or("".to_string()),
+                note: row.note,
+                status: user_status(row.active),
+                is_admin: row.admin,
+                group_name: row.group_name.unwrap_or("Defaut".to_string()),
+                email_verified: row.email_verified,
+            };
+            users.push(user);
+        }
+        Some(users)
+    }
+
+    /// Get a page of users ordered by creation date, the page starts after `after`
+    /// The users inserted during a traversal are listed at its end
+    ///
+    /// # Returns
+    /// The users and the cursor of the next page, None if it is the last page
+    pub async fn get_users_page(
+        &self,
+        name: Option<&str>,
+        email: Option<&str>,
+        after: Option<&PageCursor>,
+        page_size: u32,
+    ) -> Option<(Vec<UserListResponse>, Option<PageCursor>)> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let name_filter = name.unwrap_or("%");
+        let email_filter = email.unwrap_or("%");
+        let after_created_at = after.map(|cursor| cursor.created_at.clone());
+        let after_guid = after.map(|cursor| cursor.guid.clone());
+        // one more row tells if there is a next page
+        let limit = page_size as i64 + 1;
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                user.guid as id,
+                user.created_at as "created_at!: String",
+                user.status as "active!: i32",
+                user.role as "admin!: bool",
+                user.name as username,
+                user.email as email,
+                user.note as note,
+                user.email_verified as email_verified,
+                grp.name as group_name
+            FROM
+                user
+                LEFT JOIN grp
+                    ON user.grp = grp.guid
+            WHERE
+                user.name LIKE ?1
+                AND user.email LIKE ?2
+                AND (?3 IS NULL OR (user.created_at, user.guid) > (?3, ?4))
+            ORDER BY user.created_at, user.guid
+            LIMIT ?5
+        "#,
+            name_filter,
+            email_filter,
+            after_created_at,
+            after_guid,
+            limit
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_users_page error: {:?}", res);
+            return None;
+        }
+        let mut rows = res.unwrap();
+        let has_next = rows.len() > page_size as usize;
+        rows.truncate(page_size as usize);
+        let next = match rows.last() {
+            Some(row) if has_next => Some(PageCursor {
+                created_at: row.created_at.clone(),
+                guid: row.id.clone(),
+            }),
+            _ => None,
+        };
+        let mut users: Vec<UserListResponse> = Vec::new();
+        for row in rows {
+            let user = UserListResponse {
+                guid: guid_into_uuid(row.id)?,
+                name: row.username,
+                email: row.email.unwrap_or("".to_string()),
+                note: row.note,
+                status: user_status(row.active),
+                is_admin: row.admin,
+                group_name: row.group_name.unwrap_or("Defaut".to_string()),
+                email_verified: row.email_verified,
+            };
+            users.push(user);
+        }
+        Some((users, next))
+    }
+
+    /// Update a user
+    ///
+    /// # Returns
+    /// Err with the field already used by another user if the new name or the new email is not unique
+    pub async fn user_update(
+        &self,
+        user_id: UserId,
+        user_parameters: UpdateUserRequest,
+    ) -> Option<Result<(), UserField>> {
+        let mut tx = self.begin_write().await.ok()?;
+        let name = user_parameters.name.as_deref().filter(|name| !name.is_empty());
+        let email = user_parameters.email.as_deref().filter(|email| !email.is_empty());
+        if name.is_some() || email.is_some() {
+            if let Some(field) =
+                Self::find_user_conflict(&mut tx, name, email, Some(&user_id)).await?
+            {
+                return Some(Err(field));
+            }
+        }
+        let mut query = "UPDATE user SET ".to_string();
+        let mut query_params = Vec::new();
+        if user_parameters.name.is_some() && user_parameters.name.clone().unwrap().len() > 0 {
+            query.push_str("name = ?, ");
+            query_params.push(user_parameters.name.unwrap());
         }
         if user_parameters.email.is_some() && user_parameters.email.clone().unwrap().len() > 0 {
-            query.push_str("email = ?, ");
-            query_params.push(user_parameters.email.unwrap());
+            // a new email must be verified again, an unchanged one keeps its verification
+            query.push_str("email_verified = email_verified AND email IS ? COLLATE NOCASE, email = ?, ");
+            let email = user_parameters.email.unwrap();
+            query_params.push(email.clone());
+            query_params.push(email);
+        }
+        if user_parameters.note.is_some() && user_parameters.note.clone().unwrap().len() > 0 {
+            query.push_str("note = ?, ");
+            query_params.push(user_parameters.note.unwrap());
+        }
+        if user_parameters.password.is_some()
+            && user_parameters.confirm_password.is_some()
+            && user_parameters.password.clone().unwrap().len() > 0
+            && user_parameters.confirm_password.clone().unwrap().len() > 0
+        {
+            let password = user_parameters.password.unwrap();
+            let confirm_password = user_parameters.confirm_password.unwrap();
+            if password == confirm_password {
+                let password_hashed = UserPasswordInfo::hash_password(password.as_str());
+                query.push_str("password = ?, password_login = 1, ");
+                query_params.push(password_hashed);
+            }
+        }
+        if let Some(status) = user_parameters.status {
+            query.push_str("status = ?, ");
+            query_params.push(i32::from(status).to_string());
+        }
+        if let Some(is_admin) = user_parameters.is_admin {
+            query.push_str("role = ?, ");
+            query_params.push(if is_admin { "1" } else { "0" }.to_string());
+        }
+        query.pop();
+        query.pop();
+
+        query.push_str(" WHERE guid = ?");
+
+        log::debug!("query: {:?}", query);
+        let mut res = sqlx::query(&query);
+        for param in query_params {
+            res = res.bind(param);
+        }
+        let res = res.bind(&user_id).execute(&mut tx).await;
+        if let Err(e) = &res {
+            if let Some(field) = user_unique_violation(e) {
+                return Some(Err(field));
+            }
+            log::error!("user_update error: {:?}", res);
+            return None;
+        }
+        // the links sent to a previous email cannot verify the new one
+        sqlx::query!(
+            r#"
+            DELETE FROM email_verification
+            WHERE "user" = ? AND email IS NOT (SELECT email FROM user WHERE guid = ?) COLLATE NOCASE
+        "#,
+            user_id,
+            user_id
+        )
+        .execute(&mut tx)
+        .await
+        .ok()?;
+        tx.commit().await.ok()?;
+        Some(Ok(()))
+    }
+
+    /// Get all peers, or only the peers assigned to `user_id` if it is provided
+    pub async fn get_all_peers(&self, user_id: Option<UserId>) -> Option<Vec<Peer>> {
+        self.get_peers(user_id, None).await
+    }
+
+    /// Get a peer by its id
+    pub async fn get_peer(&self, id: &str) -> Option<Peer> {
+        self.get_peers(None, Some(id)).await?.into_iter().next()
+    }
+
+    /// Get the peers, filtered on the user they are assigned to and on their id
+    /// The strategy name is the one of the effective strategy of the peer
+    async fn get_peers(&self, user_id: Option<UserId>, id: Option<&str>) -> Option<Vec<Peer>> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                peer.guid,
+                peer.id,
+                peer.uuid,
+                peer.status,
+                peer.info as "info!: String",
+                peer.last_online  as "last_online!: String",
+                peer.user as "user: Vec<u8>",
+                user.name as "user_name: String",
+                grp.name as "group_name: String",
+                COALESCE(
+                    (SELECT name FROM strategies WHERE guid = peer.strategy),
+                    (SELECT name FROM strategies WHERE guid = user.strategy),
+                    (SELECT name FROM strategies WHERE is_default = 1),
+                    '-'
+                ) as "strategy_name!: String"
+            FROM
+                peer
+                LEFT JOIN user ON peer.user = user.guid
+                LEFT JOIN peer_grp ON peer.guid = peer_grp.peer
+                LEFT JOIN grp ON peer_grp.grp = grp.guid
+            WHERE
+                (? IS NULL OR peer.user = ?)
+                AND (? IS NULL OR peer.id = ?)
+        "#,
+            user_id,
+            user_id,
+            id,
+            id
+        )
+        .fetch_all(&mut conn)
+        .await
+        .ok()?;
+
+        let mut peers: Vec<Peer> = Vec::new();
+        for row in res {
+            let uuid = guid_into_uuid(row.guid).unwrap_or("".to_string());
+            let peer_info = serde_json::from_str::<utils::PeerInfo>(&row.info);
+            if peer_info.is_err() {
+                log::error!("get_all_peers error: {:?}", peer_info);
+                return None;
+            }
+            let peer_info = peer_info.unwrap();
+            peers.push(Peer {
+                id: row.id,
+                guid: uuid,
+                info: peer_info,
+                last_online: row.last_online.into(),
+                status: row.status as i32,
+                strategy_name: row.strategy_name,
+                user: row.user.and_then(guid_into_uuid),
+                user_name: row.user_name,
+                group_name: row.group_name,
+            });
+        }
+        Some(peers)
+    }
+
+    /// Stream the peers for the export, one row at a time
+    /// The alias is the one given in the personal address book of the user the peer is assigned to
+    /// A read error is yielded as the last item, the export is incomplete
+    ///
+    /// # Arguments
+    /// * `user_id` - only export the peers assigned to this user
+    pub fn export_peers(
+        &self,
+        user_id: Option<UserId>,
+    ) -> impl Stream<Item = Result<PeerExport, String>> + Send + '_ {
+        stream! {
+            let mut conn = match self.pool.acquire().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    log::error!("export_peers error: {:?}", e);
+                    yield Err(e.to_string());
+                    return;
+                }
+            };
+            let stale_before = format!("-{} seconds", PEER_OFFLINE_AFTER_SECS);
+            let mut rows = sqlx::query!(
+                r#"
+                SELECT
+                    peer.id,
+                    peer.info as "info!: String",
+                    peer.last_online as "last_online!: String",
+                    peer.last_online >= datetime('now', ?) as "online!: bool",
+                    (SELECT json_extract(ab_peer.info, '$.alias') FROM ab_peer
+                        JOIN ab ON ab_peer.ab = ab.guid
+                        WHERE ab.owner = peer.user AND ab.personal = 1
+                            AND ab_peer.id = peer.id AND ab_peer.deleted_at IS NULL
+                        LIMIT 1) as "alias: String",
+                    user.name as "user_name: String",
+                    grp.name as "group_name: String"
+                FROM
+                    peer
+                    LEFT JOIN user ON peer.user = user.guid
+                    LEFT JOIN peer_grp ON peer.guid = peer_grp.peer
+                    LEFT JOIN grp ON peer_grp.grp = grp.guid
+                WHERE
+                    ? IS NULL OR peer.user = ?
+                ORDER BY
+                    peer.id
+            "#,
+                stale_before,
+                user_id,
+                user_id
+            )
+            .fetch(&mut conn);
+            while let Some(row) = rows.next().await {
+                let row = match row {
+                    Ok(row) => row,
+                    Err(e) => {
+                        log::error!("export_peers error: {:?}", e);
+                        yield Err(e.to_string());
+                        break;
+                    }
+                };
+                let info = serde_json::from_str::<utils::PeerInfo>(&row.info).ok();
+                let info = info.as_ref();
+                yield Ok(PeerExport {
+                    id: row.id,
+                    alias: row.alias,
+                    device_name: info.and_then(|info| info.hostname.clone()),
+                    os: info.and_then(|info| info.os.clone()),
+                    version: info.and_then(|info| info.version.clone()),
+                    user: row.user_name,
+                    group: row.group_name,
+                    last_online: row.last_online,
+                    online: row.online,
+                });
+            }
+        }
+    }
+
+    /// Assign a peer to a user (or remove the assignment if `user_id` is None)
+    /// The change is recorded in the console audit with `operator` as the author
+    ///
+    /// # Arguments
+    /// * `id` - peer id
+    /// * `user_id` - the user to assign the peer to
+    /// * `operator` - the user who made the change
+    ///
+    /// # Returns
+    /// Option<()> - None if the peer or the user does not exist
+    pub async fn assign_peer_to_user(
+        &self,
+        id: &str,
+        user_id: Option<UserId>,
+        operator: UserId,
+    ) -> Option<()> {
+        let mut tx = self.begin_write().await.ok()?;
+        if let Some(user_id) = user_id.as_ref() {
+            let res = sqlx::query!(r#"SELECT guid FROM user WHERE guid = ?"#, user_id)
+                .fetch_optional(&mut tx)
+                .await;
+            if res.is_err() {
+                log::error!("assign_peer_to_user error: {:?}", res);
+                return None;
+            }
+            res.unwrap()?;
+        }
+        let res = sqlx::query!(
+            r#"SELECT user as "user: Vec<u8>" FROM peer WHERE id = ?"#,
+            id
+        )
+        .fetch_optional(&mut tx)
+        .await;
+        if res.is_err() {
+            log::error!("assign_peer_to_user error: {:?}", res);
+            return None;
+        }
+        let old_user_id = res.unwrap()?.user;
+        let res = sqlx::query!(r#"UPDATE peer SET user = ? WHERE id = ?"#, user_id, id)
+            .execute(&mut tx)
+            .await;
+        if res.is_err() {
+            log::error!("assign_peer_to_user error: {:?}", res);
+            return None;
+        }
+        Self::add_peer_assignment_audit(&mut tx, id, old_user_id, user_id, operator).await?;
+        tx.commit().await.ok()?;
+        Some(())
+    }
+
+    async fn add_peer_assignment_audit(
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        id: &str,
+        old_user_id: Option<UserId>,
+        new_user_id: Option<UserId>,
+        operator: UserId,
+    ) -> Option<()> {
+        if old_user_id == new_user_id {
+            return Some(());
+        }
+        let info = serde_json::json!({
+            "id": id,
+            "old_user": old_user_id.and_then(guid_into_uuid),
+            "new_user": new_user_id.and_then(guid_into_uuid),
+        });
+        Self::add_console_audit(tx, AUDIT_CONSOLE_IOP_ASSIGN, operator, info).await
+    }
+
+    /// Record an operation on peers in the console audit
+    async fn add_console_audit(
+        tx: &mut sqlx::Transaction<'_, Sqlite>,
+        iop: i64,
+        operator: UserId,
+        info: serde_json::Value,
+    ) -> Option<()> {
+        let guid = Uuid::new_v4().as_bytes().to_vec();
+        let info = info.to_string();
+        let res = sqlx::query!(
+            r#"
+            INSERT INTO audit_console (guid, type, operator, iop, info)
+            VALUES (?, ?, ?, ?, ?)
+        "#,
+            guid,
+            AUDIT_CONSOLE_TYPE_PEER,
+            operator,
+            iop,
+            info
+        )
+        .execute(&mut *tx)
+        .await;
+        if res.is_err() {
+            log::error!("add_console_audit error: {:?}", res);
+            return None;
+        }
+        Some(())
+    }
+
+    /// Apply the same action to several peers in a single transaction
+    /// The whole batch is recorded in one console audit entry
+    ///
+    /// # Arguments
+    /// * `action` - the action to apply
+    /// * `ids` - the peer ids
+    /// * `group` - the group guid for the `set_group` action
+    /// * `operator` - the user who made the change
+    /// * `dry_run` - roll the transaction back instead of committing it, nothing is audited
+    ///
+    /// # Returns
+    /// Option<Vec<PeersBulkResult>> - the result for each peer id
+    pub async fn bulk_peers(
+        &self,
+        action: PeersBulkAction,
+        ids: Vec<String>,
+        group: Option<Vec<u8>>,
+        operator: UserId,
+        dry_run: bool,
+    ) -> Option<Vec<PeersBulkResult>> {
+        let mut tx = self.begin_write().await.ok()?;
+        if let Some(group) = group.as_ref() {
+            let res = sqlx::query!(r#"SELECT guid FROM grp WHERE guid = ?"#, group)
+                .fetch_optional(&mut tx)
+                .await;
+            if res.is_err() {
+                log::error!("bulk_peers error: {:?}", res);
+                return None;
+            }
+            res.unwrap()?;
+        }
+        let mut results = Vec::new();
+        for id in ids.iter() {
+            let peer = sqlx::query!(r#"SELECT guid FROM peer WHERE id = ?"#, id)
+                .fetch_optional(&mut tx)
+                .await;
+            if peer.is_err() {
+                log::error!("bulk_peers error: {:?}", peer);
+                return None;
+            }
+            let peer = peer.unwrap();
+            if peer.is_none() {
+                results.push(PeersBulkResult {
+                    id: id.clone(),
+                    result: PeersBulkStatus::NotFound,
+                });
+                continue;
+            }
+            let peer_guid = peer.unwrap().guid;
+            let res = match action {
+                PeersBulkAction::Delete => sqlx::query!(
+                    r#"
+                    DELETE FROM peer_grp WHERE peer = ?;
+                    DELETE FROM peer_avatar WHERE id = ?;
+                    DELETE FROM peer WHERE guid = ?;
+                "#,
+                    peer_guid,
+                    id,
+                    peer_guid
+                )
+                .execute(&mut tx)
+                .await
+                .map(|_| ()),
+                PeersBulkAction::SetGroup => sqlx::query!(
+                    r#"INSERT OR REPLACE INTO peer_grp (peer, grp) VALUES (?, ?)"#,
+                    peer_guid,
+                    group
+                )
+                .execute(&mut tx)
+                .await
+                .map(|_| ()),
+                PeersBulkAction::Disable => {
+                    sqlx::query!(r#"UPDATE peer SET status = 0 WHERE guid = ?"#, peer_guid)
+                        .execute(&mut tx)
+                        .await
+                        .map(|_| ())
+                }
+            };
+            let result = if res.is_err() {
+                log::error!("bulk_peers error on {}: {:?}", id, res);
+                PeersBulkStatus::Refused
+            } else {
+                PeersBulkStatus::Ok
+            };
+            results.push(PeersBulkResult {
+                id: id.clone(),
+                result,
+            });
+        }
+        if dry_run {
+            tx.rollback().await.ok()?;
+            return Some(results);
+        }
+        let info = serde_json::json!({
+            "action": action,
+            "ids": ids,
+            "group": group.and_then(guid_into_uuid),
+        });
+        Self::add_console_audit(&mut tx, AUDIT_CONSOLE_IOP_BULK, operator, info).await?;
+        tx.commit().await.ok()?;
+        Some(results)
+    }
+
+    pub async fn get_groups(&self, offset: u32, page_size: u32) -> Option<Vec<Group>> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                guid,
+                team,
+                name,
+                note,
+                created_at as "created_at!: String",
+                info as "info!: String",
+                (SELECT strategy FROM grp_strategy WHERE grp_strategy.grp = grp.guid) as "strategy: Vec<u8>"
+            FROM
+                grp
+            LIMIT ?
+            OFFSET ?
+        "#,
+            page_size,
+            offset
+        )
+        .fetch_all(&mut conn)
+        .await
+        .ok()?;
+        let mut groups: Vec<Group> = Vec::new();
+        for row in res {
+            let guid = guid_into_uuid(row.guid).unwrap_or("".to_string());
+            let team = guid_into_uuid(row.team).unwrap_or("".to_string());
+            groups.push(Group {
+                guid: guid,
+                name: row.name,
+                team: team,
+                note: row.note,
+                created_at: row.created_at.into(),
+                access_to: Vec::<String>::new(),
+                accessed_from: Vec::<String>::new(),
+                info: row.info,
+                strategy: row.strategy.and_then(guid_into_uuid),
+            });
+        }
+        Some(groups)
+    }
+
+    /// Delete a shared address book, all rules associated with it and all peers associated with it
+    ///
+    /// # Arguments
+    ///
+    /// * `guid` - address book uuid in string format
+    ///
+    /// # Returns
+    ///
+    /// Option<()>
+    pub async fn delete_shared_address_book(&self, guid: &str) -> Option<()> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = Uuid::parse_str(guid);
+        if ab_guid.is_err() {
+            log::error!("delete_ab error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        // personal address books are never deleted here
+        let res = sqlx::query!(
+            r#"
+            BEGIN;
+            DELETE FROM ab_peer WHERE ab IN (SELECT guid FROM ab WHERE guid = ? AND personal = 0);
+            DELETE FROM ab_tag WHERE ab IN (SELECT guid FROM ab WHERE guid = ? AND personal = 0);
+            DELETE FROM ab_rule WHERE ab IN (SELECT guid FROM ab WHERE guid = ? AND personal = 0);
+            DELETE FROM ab WHERE guid = ? AND personal = 0;
+            COMMIT;
+            "#,
+            ab_guid,ab_guid,ab_guid,ab_guid
+        )
+        .execute(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("delete_ab error: {:?}", res);
+            return None;
+        }
+        Some(())
+    }
+
+    /// Count the peers of a shared address book
+    ///
+    /// # Arguments
+    ///
+    /// * `guid` - address book uuid in string format
+    ///
+    /// # Returns
+    ///
+    /// Option<u32> - None if the address book does not exist or is a personal one
+    pub async fn count_shared_address_book_peers(&self, guid: &str) -> Option<u32> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = uuid_into_guid(guid)?;
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM ab_peer WHERE ab_peer.ab = ab.guid) as "peers!: u32"
+            FROM
+                ab
+            WHERE
+                guid = ? AND personal = 0
+        "#,
+            ab_guid
+        )
+        .fetch_optional(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("count_shared_address_book_peers error: {:?}", res);
+            return None;
+        }
+        Some(res.unwrap()?.peers)
+    }
+
+    /// Get the rule of a user on an address book
+    /// The owner of an address book has full control on it,
+    /// the other users get the highest rule granted to them or to their group
+    ///
+    /// # Arguments
+    /// * `ab` - address book uuid in string format
+    /// * `user_id` - the user
+    ///
+    /// # Returns
+    /// Option<u32> - 0: no access, 1: read, 2: write, 3: full control
+    pub async fn get_ab_rule_for_user(&self, ab: &str, user_id: UserId) -> Option<u32> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("get_ab_rule_for_user error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                MAX(rule) as "rule: i64"
+            FROM
+                (SELECT 3 as rule FROM ab WHERE guid = ? AND owner = ?
+                UNION ALL
+                SELECT rule FROM ab_rule
+                WHERE ab = ? AND (user = ? OR grp IN (SELECT grp FROM user WHERE guid = ?)))
+        "#,
+            ab_guid,
+            user_id,
+            ab_guid,
+            user_id,
+            user_id
+        )
+        .fetch_one(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_ab_rule_for_user error: {:?}", res);
+            return None;
+        }
+        Some(res.unwrap().rule.unwrap_or(0) as u32)
+    }
+
+    /// Get the shared address books the user owns or was granted a rule on, with the access of the user as in `get_ab_rule_for_user`
+    pub async fn get_shared_address_books(&self, user_id: UserId) -> Option<Vec<AddressBook>> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                ab.guid,
+                ab.name,
+                ab.owner,
+                COALESCE(MaxRule.rule, 0) as rule
+            FROM
+                ab
+            JOIN
+                (SELECT ab, MAX(rule) as rule
+                FROM
+                    (SELECT guid as ab, 3 as rule FROM ab WHERE owner = ?
+                    UNION ALL
+                    SELECT ab, rule FROM ab_rule
+                    WHERE user = ? OR grp IN (SELECT grp FROM user WHERE guid = ?))
+                GROUP BY ab) as MaxRule
+            ON ab.guid = MaxRule.ab
+            WHERE
+                personal = 0
+        "#,
+            user_id,
+            user_id,
+            user_id
+        )
+        .fetch_all(&mut conn)
+        .await
+        .ok()?;
+        let mut address_books: Vec<AddressBook> = Vec::new();
+        for row in res {
+            let access_level = row.rule.unwrap_or(0) as u32;
+            address_books.push(AddressBook {
+                ab: guid_into_uuid(row.guid).unwrap_or("".to_string()),
+                name: Some(row.name),
+                owner: Some(row.owner),
+                rule: Some(access_level),
+                ..Default::default()
+            });
+        }
+        Some(address_books)
+    }
+
+    /// Get the permissions of a user: its role, its group and its access to the shared address books
+    /// The access to an address book is computed as in `get_ab_rule_for_user`, the quotas are left to the caller
+    ///
+    /// # Arguments
+    /// * `user_id` - the user
+    ///
+    /// # Returns
+    /// Option<UserPermissions> - None if the user does not exist
+    pub async fn get_user_permissions(&self, user_id: &UserId) -> Option<UserPermissions> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let user = sqlx::query!(
+            r#"
+            SELECT
+                user.name,
+                user.role as "admin!: bool",
+                grp.guid as "group_guid: Vec<u8>",
+                grp.name as "group_name: String"
+            FROM
+                user
+                LEFT JOIN grp
+                    ON user.grp = grp.guid
+            WHERE
+                user.guid = ?
+        "#,
+            user_id
+        )
+        .fetch_optional(&mut conn)
+        .await;
+        if user.is_err() {
+            log::error!("get_user_permissions error: {:?}", user);
+            return None;
+        }
+        let user = user.unwrap()?;
+        let address_books = sqlx::query!(
+            r#"
+            SELECT
+                ab.guid,
+                ab.name,
+                MAX(rules.rule) as "rule!: i64"
+            FROM
+                ab
+            JOIN
+                (SELECT guid as ab, 3 as rule FROM ab WHERE owner = ?1
+                UNION ALL
+                SELECT ab, rule FROM ab_rule
+                WHERE user = ?1 OR grp IN (SELECT grp FROM user WHERE guid = ?1)) as rules
+            ON ab.guid = rules.ab
+            WHERE
+                ab.personal = 0
+            GROUP BY ab.guid
+            ORDER BY ab.name
+        "#,
+            user_id
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if address_books.is_err() {
+            log::error!("get_user_permissions error: {:?}", address_books);
+            return None;
+        }
+        let groups = match (user.group_guid.and_then(guid_into_uuid), user.group_name) {
+            (Some(guid), Some(name)) => vec![GroupMembership { guid, name }],
+            _ => Vec::new(),
+        };
+        let address_books = address_books
+            .unwrap()
+            .into_iter()
+            .filter(|row| row.rule > 0)
+            .map(|row| AddressBookPermission {
+                guid: guid_into_uuid(row.guid).unwrap_or_default(),
+                name: row.name,
+                rule: row.rule as u32,
+            })
+            .collect();
+        Some(UserPermissions {
+            guid: guid_into_uuid(user_id.clone()).unwrap_or_default(),
+            name: user.name,
+            is_admin: user.admin,
+            groups,
+            address_books,
+            admin: if user.admin {
+                AdminPermission::ALL.to_vec()
+            } else {
+                Vec::new()
+            },
+            quotas: PermissionQuotas::default(),
+        })
+    }
+
+    /// Get the users and the groups allowed to control a peer
+    ///
+    /// The access comes from the assignment of the peer, the admin role and the shared address books containing the peer,
+    /// through their owner or their rules. The personal address books grant nothing.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - id of the peer
+    ///
+    /// # Returns
+    ///
+    /// Option<PeerAccess> - None if the peer does not exist
+    pub async fn get_peer_access(&self, id: &str) -> Option<PeerAccess> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let peer = sqlx::query!(
+            r#"
+            SELECT
+                user.guid as "guid?: Vec<u8>",
+                user.name as "name?: String"
+            FROM
+                peer
+                LEFT JOIN user
+                    ON user.guid = peer.user
+            WHERE
+                peer.id = ?
+        "#,
+            id
+        )
+        .fetch_optional(&mut conn)
+        .await;
+        if peer.is_err() {
+            log::error!("get_peer_access error: {:?}", peer);
+            return None;
+        }
+        let peer = peer.unwrap()?;
+        let admins = sqlx::query!(r#"SELECT guid, name FROM user WHERE role = 1 ORDER BY name"#)
+            .fetch_all(&mut conn)
+            .await;
+        if admins.is_err() {
+            log::error!("get_peer_access error: {:?}", admins);
+            return None;
+        }
+        let ab_owners = sqlx::query!(
+            r#"
+            SELECT
+                ab.guid,
+                ab.name,
+                user.guid as user_guid,
+                user.name as user_name
+            FROM
+                ab
+                JOIN user
+                    ON user.guid = ab.owner
+            WHERE
+                ab.personal = 0
+                AND ab.guid IN (SELECT ab FROM ab_peer WHERE id = ? AND deleted_at IS NULL)
+            ORDER BY ab.name
+        "#,
+            id
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if ab_owners.is_err() {
+            log::error!("get_peer_access error: {:?}", ab_owners);
+            return None;
+        }
+        let ab_rules = sqlx::query!(
+            r#"
+            SELECT
+                ab.guid,
+                ab.name,
+                ab_rule.rule,
+                user.guid as "user_guid?: Vec<u8>",
+                user.name as "user_name?: String",
+                grp.guid as "grp_guid?: Vec<u8>",
+                grp.name as "grp_name?: String"
+            FROM
+                ab_rule
+                JOIN ab
+                    ON ab.guid = ab_rule.ab
+                LEFT JOIN user
+                    ON user.guid = ab_rule.user
+                LEFT JOIN grp
+                    ON grp.guid = ab_rule.grp
+            WHERE
+                ab.personal = 0
+                AND ab_rule.rule > 0
+                AND ab.guid IN (SELECT ab FROM ab_peer WHERE id = ? AND deleted_at IS NULL)
+            ORDER BY ab.name
+        "#,
+            id
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if ab_rules.is_err() {
+            log::error!("get_peer_access error: {:?}", ab_rules);
+            return None;
+        }
+        let share_grants = sqlx::query!(
+            r#"
+            SELECT
+                user.guid,
+                user.name,
+                MAX(share_link.expires_at) as "expires_at!: String"
+            FROM
+                share_link_grant
+                JOIN share_link
+                    ON share_link.guid = share_link_grant.link
+                JOIN user
+                    ON user.guid = share_link_grant.user
+            WHERE
+                share_link.peer_id = ?
+                AND share_link.expires_at > CURRENT_TIMESTAMP
+            GROUP BY user.guid
+        "#,
+            id
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if share_grants.is_err() {
+            log::error!("get_peer_access error: {:?}", share_grants);
+            return None;
+        }
+
+        fn grant(entries: &mut Vec<PeerAccessEntry>, guid: Vec<u8>, name: String, grant: PeerAccessGrant) {
+            let guid = guid_into_uuid(guid).unwrap_or_default();
+            match entries.iter_mut().find(|entry| entry.guid == guid) {
+                Some(entry) => entry.grants.push(grant),
+                None => entries.push(PeerAccessEntry {
+                    guid,
+                    name,
+                    grants: vec![grant],
+                }),
+            }
+        }
+        let address_book = |guid: Vec<u8>, name: String, rule: i64| {
+            Some(AddressBookPermission {
+                guid: guid_into_uuid(guid).unwrap_or_default(),
+                name,
+                rule: rule as u32,
+            })
+        };
+        let mut users = Vec::new();
+        let mut groups = Vec::new();
+        if let (Some(guid), Some(name)) = (peer.guid, peer.name) {
+            let owner = PeerAccessGrant {
+                source: PeerAccessSource::Owner,
+                address_book: None,
+                expires_at: None,
+            };
+            grant(&mut users, guid, name, owner);
+        }
+        for admin in admins.unwrap() {
+            let admin_grant = PeerAccessGrant {
+                source: PeerAccessSource::Admin,
+                address_book: None,
+                expires_at: None,
+            };
+            grant(&mut users, admin.guid, admin.name, admin_grant);
+        }
+        for row in ab_owners.unwrap() {
+            let owner = PeerAccessGrant {
+                source: PeerAccessSource::AddressBookOwner,
+                address_book: address_book(row.guid, row.name, AB_RULE_FULL as i64),
+                expires_at: None,
+            };
+            grant(&mut users, row.user_guid, row.user_name, owner);
+        }
+        for row in ab_rules.unwrap() {
+            let rule = PeerAccessGrant {
+                source: PeerAccessSource::AddressBookRule,
+                address_book: address_book(row.guid, row.name, row.rule),
+                expires_at: None,
+            };
+            match (row.user_guid, row.user_name, row.grp_guid, row.grp_name) {
+                (Some(guid), Some(name), _, _) => grant(&mut users, guid, name, rule),
+                (_, _, Some(guid), Some(name)) => grant(&mut groups, guid, name, rule),
+                _ => {}
+            }
+        }
+        for row in share_grants.unwrap() {
+            let share = PeerAccessGrant {
+                source: PeerAccessSource::ShareLink,
+                address_book: None,
+                expires_at: Some(row.expires_at),
+            };
+            grant(&mut users, row.guid, row.name, share);
+        }
+        users.sort_by(|a, b| a.name.cmp(&b.name));
+        groups.sort_by(|a, b| a.name.cmp(&b.name));
+        Some(PeerAccess {
+            id: id.to_string(),
+            users,
+            groups,
+        })
+    }
+
+    pub async fn get_ab_rules(&self, offset: u32, page_size: u32, ab: &str) -> Option<Vec<AbRule>> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let ab_guid = Uuid::parse_str(ab);
+        if ab_guid.is_err() {
+            log::error!("get_ab_rules error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                r.guid,
+                u.name as username,
+                g.name as groupname,
+                r.rule
+            FROM
+                ab_rule r
+            LEFT JOIN
+                user u ON u.guid = r.user
+            LEFT JOIN
+                grp g ON g.guid = r.grp
+            WHERE
+                ab = ?
+        "#,
+            ab_guid
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_ab_rules error: {:?}", res);
+            return None;
+        }
+        let res = res.unwrap();
+        let mut ab_rules = Vec::new();
+        for row in res {
+            let uuid = guid_into_uuid(row.guid).unwrap_or("".to_string());
+            let user = row.username;
+            let group = row.groupname;
+            let ab_rule = AbRule {
+                user: user,
+                group: group,
+                rule: row.rule as u32,
+                guid: uuid,
+            };
+            ab_rules.push(ab_rule);
+        }
+        Some(ab_rules)
+    }
+
+    pub async fn delete_ab_rule(&self, rule: &str) -> Option<()> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let rule_guid = Uuid::parse_str(rule);
+        if rule_guid.is_err() {
+            log::error!("delete_ab_rule error: {:?}", rule_guid);
+            return None;
+        }
+        let rule_guid = rule_guid.unwrap().as_bytes().to_vec();
+        let res = sqlx::query!(
+            r#"
+            DELETE FROM ab_rule WHERE guid = ?
+        "#,
+            rule_guid
+        )
+        .execute(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("delete_ab_rule error: {:?}", res);
+            return None;
+        }
+        Some(())
+    }
+
+    /// Check whether a rule of an address book exists
+    ///
+    /// # Returns
+    /// Option<bool> - false if the rule does not exist
+    pub async fn ab_rule_exists(&self, rule: &str) -> Option<bool> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let rule_guid = uuid_into_guid(rule)?;
+        let res = sqlx::query!(
+            r#"SELECT COUNT(*) as "count!: i64" FROM ab_rule WHERE guid = ?"#,
+            rule_guid
+        )
+        .fetch_one(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("ab_rule_exists error: {:?}", res);
+            return None;
+        }
+        Some(res.unwrap().count > 0)
+    }
+
+    pub async fn add_ab_rule(&self, rule: AbRule) -> Option<()> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let rule_guid = Uuid::new_v4().as_bytes().to_vec();
+        let ab_guid = Uuid::parse_str(&rule.guid);
+        if ab_guid.is_err() {
+            log::error!("add_ab_rule error: {:?}", ab_guid);
+            return None;
+        }
+        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let user_guid = if (rule.user.is_some()) {
+            let uuid = Uuid::parse_str(&rule.user.unwrap());
+            if (uuid.is_err()) {
+                None
+            } else {
+                Some(uuid.unwrap().as_bytes().to_vec())
+            }
+        } else {
+            None
+        };
+
+        let group_guid = if (rule.group.is_some()) {
+            let uuid = Uuid::parse_str(&rule.group.unwrap());
+            if (uuid.is_err()) {
+                None
+            } else {
+                Some(uuid.unwrap().as_bytes().to_vec())
+            }
+        } else {
+            None
+        };
+
+        let res = sqlx::query!(
+            r#"
+            INSERT OR IGNORE INTO ab_rule (guid, ab, user, grp, rule)
+                VALUES (?, ?, ?, ?, ?)
+        "#,
+            rule_guid,
+            ab_guid,
+            user_guid,
+            group_guid,
+            rule.rule
+        )
+        .execute(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("add_ab_rule error: {:?}", res);
+            return None;
+        }
+        Some(())
+    }
+
+    pub async fn get_peers_count(&self, platform: Platform) -> u32 {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let filter = match platform {
+            Platform::Windows => "windows%",
+            Platform::Linux => "linux%",
+            Platform::MacOS => "macos%",
+            Platform::Android => "android%",
+            Platform::All => "%",
+            _ => "unknown%",
+        };
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                COUNT(*) as count
+            FROM
+                peer
+            WHERE
+                json_extract(info,'$.os') like ?
+        "#,
+            filter
+        )
+        .fetch_one(&mut conn)
+        .await
+        .ok();
+        if res.is_none() {
+            return 0;
+        }
+        let res = res.unwrap();
+        res.count as u32
+    }
+
+    /// Get the statistics shown on the console dashboard
+    pub async fn get_statistics(&self) -> Option<StatisticsResponse> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let stale_before = format!("-{} seconds", PEER_OFFLINE_AFTER_SECS);
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                (SELECT COUNT(*) FROM user) as "users_total!: i64",
+                (SELECT COUNT(DISTINCT user) FROM session
+                    WHERE created_at >= datetime('now', '-30 days')) as "users_active!: i64",
+                (SELECT COUNT(*) FROM peer) as "devices_total!: i64",
+                (SELECT COUNT(*) FROM peer
+                    WHERE last_online >= datetime('now', ?)) as "devices_online!: i64",
+                (SELECT COUNT(*) FROM session
+                    WHERE created_at >= date('now')) as "logins_today!: i64",
+                (SELECT COUNT(*) FROM ab WHERE personal = 1) as "address_books_personal!: i64",
+                (SELECT COUNT(*) FROM ab WHERE personal = 0) as "address_books_shared!: i64"
+        "#,
+            stale_before
+        )
+        .fetch_one(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_statistics error: {:?}", res);
+            return None;
+        }
+        let counts = res.unwrap();
+
+        // the os is reported as "windows / Windows 11 Pro", only the family is kept
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                COALESCE(lower(trim(CASE
+                    WHEN instr(json_extract(info, '$.os'), '/') > 0
+                    THEN substr(json_extract(info, '$.os'), 1, instr(json_extract(info, '$.os'), '/') - 1)
+                    ELSE json_extract(info, '$.os')
+                END)), 'unknown') as "os!: String",
+                COUNT(*) as "total!: i64"
+            FROM
+                peer
+            GROUP BY
+                1
+            ORDER BY
+                2 DESC
+        "#
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_statistics error: {:?}", res);
+            return None;
         }
-        if user_parameters.note.is_some() && user_parameters.note.clone().unwrap().len() > 0 {
-            query.push_str("note = ?, ");
-            query_params.push(user_parameters.note.unwrap());
+        let devices_by_os = res
+            .unwrap()
+            .into_iter()
+            .map(|row| OsCount {
+                os: row.os,
+                total: row.total as u32,
+            })
+            .collect();
+
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                date(created_at) as "day!: String",
+                SUM(size) as "bytes!: i64"
+            FROM
+                audit_client_file
+            WHERE
+                created_at >= date('now', '-29 days')
+            GROUP BY
+                1
+            ORDER BY
+                1
+        "#
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_statistics error: {:?}", res);
+            return None;
         }
-        if user_parameters.password.is_some()
-            && user_parameters.confirm_password.is_some()
-            && user_parameters.password.clone().unwrap().len() > 0
-            && user_parameters.confirm_password.clone().unwrap().len() > 0
-        {
-            let password = user_parameters.password.unwrap();
-            let confirm_password = user_parameters.confirm_password.unwrap();
-            if password == confirm_password {
-                let password_hashed = UserPasswordInfo::hash_password(password.as_str());
-                query.push_str("password = ?, ");
-                query_params.push(password_hashed);
+        let file_bytes_by_day = res
+            .unwrap()
+            .into_iter()
+            .map(|row| DailyBytes {
+                day: row.day,
+                bytes: row.bytes,
+            })
+            .collect();
+
+        Some(StatisticsResponse {
+            users_total: counts.users_total as u32,
+            users_active: counts.users_active as u32,
+            devices_total: counts.devices_total as u32,
+            devices_online: counts.devices_online as u32,
+            devices_by_os,
+            logins_today: counts.logins_today as u32,
+            address_books_personal: counts.address_books_personal as u32,
+            address_books_shared: counts.address_books_shared as u32,
+            file_bytes_by_day,
+        })
+    }
+
+    /// Record a login in the session table
+    ///
+    /// # Arguments
+    /// * `user_id` - the user who logged in
+    /// * `ttl_secs` - lifetime of the session
+    pub async fn add_session(&self, user_id: UserId, ttl_secs: i64) -> Option<()> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let id = Uuid::new_v4().to_string();
+        let expiry = format!("+{} seconds", ttl_secs);
+        let res = sqlx::query!(
+            r#"
+            INSERT INTO session (id, ttl_secs, user, info, expiry_at)
+            VALUES (?, ?, ?, '{}', datetime('now', ?))
+        "#,
+            id,
+            ttl_secs,
+            user_id,
+            expiry
+        )
+        .execute(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("add_session error: {:?}", res);
+            return None;
+        }
+        Some(())
+    }
+
+    /// Count the peers online and offline per os family
+    pub async fn get_peers_summary(&self) -> Option<PeersSummary> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let stale_before = format!("-{} seconds", PEER_OFFLINE_AFTER_SECS);
+        // the os is reported as "windows / Windows 11 Pro", only the family is kept
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                COALESCE(lower(trim(CASE
+                    WHEN instr(json_extract(info, '$.os'), '/') > 0
+                    THEN substr(json_extract(info, '$.os'), 1, instr(json_extract(info, '$.os'), '/') - 1)
+                    ELSE json_extract(info, '$.os')
+                END)), 'unknown') as "os!: String",
+                COUNT(*) as "total!: i64",
+                COALESCE(SUM(last_online >= datetime('now', ?)), 0) as "online!: i64"
+            FROM
+                peer
+            GROUP BY
+                1
+            ORDER BY
+                2 DESC, 1
+        "#,
+            stale_before
+        )
+        .fetch_all(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_peers_summary error: {:?}", res);
+            return None;
+        }
+        let mut summary = PeersSummary::default();
+        for row in res.unwrap() {
+            let (total, online) = (row.total as u32, row.online as u32);
+            summary.total += total;
+            summary.online += online;
+            summary.offline += total - online;
+            summary.by_os.push(OsSummary {
+                os: row.os,
+                total,
+                online,
+                offline: total - online,
+            });
+        }
+        Some(summary)
+    }
+
+    pub async fn get_cpus_count(&self) -> Vec<CpuCount> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let res = sqlx::query!(
+            r#"
+            SELECT 
+                COALESCE(trim(json_extract(info,'$.cpu')),"unknown") as cpu, 
+                COUNT(*) AS machine_count 
+            FROM 
+                peer 
+            GROUP BY 
+                cpu;
+        "#
+        )
+        .fetch_all(&mut conn)
+        .await
+        .ok();
+        if res.is_none() {
+            return Vec::new();
+        }
+        let res = res.unwrap();
+        let mut cpu_counts = Vec::new();
+        for row in res {
+            let cpu = row.cpu;
+            let total = row.machine_count as u32;
+            cpu_counts.push(CpuCount { cpu, total });
+        }
+        cpu_counts
+    }
+
+    /// Get a group given its guid
+    ///
+    /// # Arguments
+    ///
+    /// * `guid` - group guid in string format
+    ///
+    /// # Returns
+    ///
+    /// Option<Group>
+    pub async fn get_group(&self, guid: &str) -> Option<Group> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let group_guid = Uuid::parse_str(guid);
+        if group_guid.is_err() {
+            log::error!("get_group error: {:?}", group_guid);
+            return None;
+        }
+        let group_guid = group_guid.unwrap().as_bytes().to_vec();
+        let res = sqlx::query!(
+            r#"
+            SELECT
+                guid,
+                team,
+                name,
+                note,
+                created_at as "created_at!: String",
+                info as "info!: String",
+                (SELECT strategy FROM grp_strategy WHERE grp_strategy.grp = grp.guid) as "strategy: Vec<u8>"
+            FROM
+                grp
+            WHERE
+                guid = ?
+        "#,
+            group_guid
+        )
+        .fetch_one(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_group error: {:?}", res);
+            return None;
+        }
+        let res = res.unwrap();
+        let guid = guid_into_uuid(res.guid).unwrap_or("".to_string());
+        let team = guid_into_uuid(res.team).unwrap_or("".to_string());
+        Some(Group {
+            guid: guid,
+            name: res.name,
+            team: team,
+            note: res.note,
+            created_at: res.created_at.into(),
+            access_to: Vec::<String>::new(),
+            accessed_from: Vec::<String>::new(),
+            info: res.info,
+            strategy: res.strategy.and_then(guid_into_uuid),
+        })
+    }
+
+    /// Create a new group
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - group name
+    /// * `team` - team name ( must exist in the database )
+    /// * `note` - group note
+    /// * `strategy` - uuid of the strategy applied to the members of the group in string format
+    ///
+    /// # Returns
+    ///
+    /// Option<()>
+    pub async fn create_group(
+        &self,
+        name: &str,
+        team: &str,
+        note: &str,
+        strategy: Option<&str>,
+    ) -> Option<()> {
+        let mut tx = self.begin_write().await.ok()?;
+        let group_guid = Uuid::new_v4().as_bytes().to_vec();
+        let strategy_guid = match strategy {
+            Some(strategy) => Some(uuid_into_guid(strategy)?),
+            None => None,
+        };
+
+        let res = sqlx::query!(
+            r#"
+            INSERT OR IGNORE INTO grp(guid, team, name, note, created_at, info)
+                VALUES (?, (SELECT guid FROM team  WHERE name = ?) , ?, ?, current_timestamp, '{}')
+        "#,
+            group_guid,
+            team,
+            name,
+            note
+        )
+        .execute(&mut tx)
+        .await;
+        if res.is_err() {
+            log::error!("create_group error: {:?}", res);
+            return None;
+        }
+        if res.unwrap().rows_affected() > 0 && strategy_guid.is_some() {
+            let res = sqlx::query!(
+                r#"
+                INSERT INTO grp_strategy(grp, strategy, modified_at) VALUES (?, ?, current_timestamp)
+            "#,
+                group_guid,
+                strategy_guid
+            )
+            .execute(&mut tx)
+            .await;
+            if res.is_err() {
+                log::error!("create_group error: {:?}", res);
+                return None;
             }
         }
-        if let Some(status) = user_parameters.status {
-            query.push_str("status = ?, ");
-            query_params.push(status.to_string());
+        tx.commit().await.ok()?;
+        Some(())
+    }
+
+    /// Update a group
+    ///
+    /// # Arguments
+    ///
+    /// * `guid` - uuid of the group in string format
+    /// * `name` - group name
+    /// * `team` - team name ( must exist in the database )
+    /// * `note` - group note
+    ///
+    /// # Returns
+    ///
+    /// Option<()>
+    pub async fn update_group(&self, guid: &str, name: &str, team: &str, note: &str) -> Option<()> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let group_guid = Uuid::parse_str(guid);
+        if group_guid.is_err() {
+            log::error!("update_group error: {:?}", group_guid);
+            return None;
         }
-        if let Some(is_admin) = user_parameters.is_admin {
-            query.push_str("role = ?, ");
-            query_params.push(if is_admin { "1" } else { "0" }.to_string());
+        let group_guid = group_guid.unwrap().as_bytes().to_vec();
+
+        let res = sqlx::query!(
+            r#"
+            UPDATE grp SET team = (SELECT guid FROM team  WHERE name = ?), name = ?, note = ?, created_at = current_timestamp
+                WHERE guid = ?
+        "#,
+            team,
+            name,
+            note,
+            group_guid
+        )
+        .execute(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("update_group error: {:?}", res);
+            return None;
+        }
+        Some(())
+    }
+
+    /// Delete a group
+    ///
+    /// # Arguments
+    ///
+    /// * `guid` - uuid of the group in string format
+    ///
+    /// # Returns
+    ///
+    /// Option<()>
+    pub async fn delete_group(&self, guid: &str) -> Option<()> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let group_guid = Uuid::parse_str(guid);
+        if group_guid.is_err() {
+            log::error!("delete_group error: {:?}", group_guid);
+            return None;
         }
-        query.pop();
-        query.pop();
-
-        query.push_str(" WHERE guid = ?");
+        let group_guid = group_guid.unwrap().as_bytes().to_vec();
 
-        log::debug!("query: {:?}", query);
-        let mut res = sqlx::query(&query);
-        for param in query_params {
-            res = res.bind(param);
-        }
-        let res = res.bind(user_id).execute(&mut conn).await;
+        let res = sqlx::query!(
+            r#"
+            DELETE FROM grp_strategy WHERE grp = ?;
+            DELETE FROM grp WHERE guid = ?;
+        "#,
+            group_guid,
+            group_guid
+        )
+        .execute(&mut conn)
+        .await;
         if res.is_err() {
-            log::error!("user_update error: {:?}", res);
+            log::error!("delete_group error: {:?}", res);
             return None;
         }
         Some(())
     }
 
-    pub async fn get_all_peers(&self) -> Option<Vec<Peer>> {
+    /// Build a query on the strategies whose name contains `q`, case-insensitively
+    /// If `count` is true the query returns the number of matching strategies in `total`, else the requested page
+    fn strategies_query<'a>(
+        q: Option<&'a str>,
+        order: StrategyOrder,
+        offset: u32,
+        page_size: u32,
+        count: bool,
+    ) -> QueryBuilder<'a, Sqlite> {
+        let select = if count {
+            "COUNT(*) as total"
+        } else {
+            "guid, name, settings, is_default, created_at, modified_at"
+        };
+        let mut query_builder: QueryBuilder<Sqlite> =
+            QueryBuilder::new(format!("SELECT {} FROM strategies WHERE 1 = 1", select));
+        if let Some(q) = q {
+            query_builder.push(" AND instr(lower(name), lower(");
+            query_builder.push_bind(q);
+            query_builder.push(")) > 0");
+        }
+        if !count {
+            query_builder.push(format!(" ORDER BY {} LIMIT ", order.order_by()));
+            query_builder.push_bind(page_size);
+            query_builder.push(" OFFSET ");
+            query_builder.push_bind(offset);
+        }
+        query_builder
+    }
+
+    /// Get a page of the strategies
+    ///
+    /// # Arguments
+    ///
+    /// * `q` - text the names contain, None for all the strategies
+    /// * `order` - order of the strategies
+    /// * `offset` - number of strategies skipped
+    /// * `page_size` - maximum number of strategies returned
+    ///
+    /// # Returns
+    ///
+    /// Option<Vec<Strategy>>
+    pub async fn get_strategies(
+        &self,
+        q: Option<&str>,
+        order: StrategyOrder,
+        offset: u32,
+        page_size: u32,
+    ) -> Option<Vec<Strategy>> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let res = sqlx::query!(
-            r#"
-            SELECT
-                guid,
-                id,
-                uuid,
-                status,
-                info as "info!: String",
-                last_online  as "last_online!: String"
-            FROM
-                peer
-        "#
-        )
-        .fetch_all(&mut conn)
-        .await
-        .ok()?;
+        let res = Self::strategies_query(q, order, offset, page_size, false)
+            .build()
+            .fetch_all(&mut conn)
+            .await;
+        if let Err(e) = &res {
+            log::error!("get_strategies error: {:?}", e);
+            return None;
+        }
+        let strategies = res
+            .unwrap()
+            .into_iter()
+            .map(|row| Strategy {
+                guid: guid_into_uuid(row.get("guid")).unwrap_or_default(),
+                name: row.get("name"),
+                settings: serde_json::from_str(row.get("settings")).unwrap_or_else(|_| serde_json::json!({})),
+                is_default: row.get("is_default"),
+                created_at: row.get("created_at"),
+                modified_at: row.get("modified_at"),
+            })
+            .collect();
+        Some(strategies)
+    }
 
-        let mut peers: Vec<Peer> = Vec::new();
-        for row in res {
-            let uuid = guid_into_uuid(row.guid).unwrap_or("".to_string());
-            let peer_info = serde_json::from_str::<utils::PeerInfo>(&row.info);
-            if peer_info.is_err() {
-                log::error!("get_all_peers error: {:?}", peer_info);
-                return None;
-            }
-            let peer_info = peer_info.unwrap();
-            peers.push(Peer {
-                id: row.id,
-                guid: uuid,
-                info: peer_info,
-                last_online: row.last_online.into(),
-                status: row.status as i32,
-                strategy_name: "-".to_string(),
-            });
+    /// Count the strategies whose name contains `q`, case-insensitively
+    ///
+    /// # Returns
+    ///
+    /// Option<u32>
+    pub async fn count_strategies(&self, q: Option<&str>) -> Option<u32> {
+        let mut conn = self.pool.acquire().await.unwrap();
+        let res = Self::strategies_query(q, StrategyOrder::Default, 0, 0, true)
+            .build()
+            .fetch_one(&mut conn)
+            .await;
+        if let Err(e) = &res {
+            log::error!("count_strategies error: {:?}", e);
+            return None;
         }
-        Some(peers)
+        let total: i64 = res.unwrap().try_get("total").ok()?;
+        Some(total as u32)
     }
 
-    pub async fn get_groups(&self, offset: u32, page_size: u32) -> Option<Vec<Group>> {
+    /// Get a strategy
+    ///
+    /// # Arguments
+    ///
+    /// * `guid` - uuid of the strategy in string format
+    ///
+    /// # Returns
+    ///
+    /// Option<Strategy> - None if the strategy does not exist
+    pub async fn get_strategy(&self, guid: &str) -> Option<Strategy> {
         let mut conn = self.pool.acquire().await.unwrap();
+        let strategy_guid = uuid_into_guid(guid)?;
         let res = sqlx::query!(
             r#"
             SELECT
                 guid,
-                team,
                 name,
-                note,
+                settings as "settings!: String",
+                is_default as "is_default!: bool",
                 created_at as "created_at!: String",
-                info as "info!: String"
+                modified_at as "modified_at!: String"
             FROM
-                grp
-            LIMIT ?
-            OFFSET ?
+                strategies
+            WHERE
+                guid = ?
         "#,
-            page_size,
-            offset
+            strategy_guid
         )
-        .fetch_all(&mut conn)
-        .await
-        .ok()?;
-        let mut groups: Vec<Group> = Vec::new();
-        for row in res {
-            let guid = guid_into_uuid(row.guid).unwrap_or("".to_string());
-            let team = guid_into_uuid(row.team).unwrap_or("".to_string());
-            groups.push(Group {
-                guid: guid,
-                name: row.name,
-                team: team,
-                note: row.note,
-                created_at: row.created_at.into(),
-                access_to: Vec::<String>::new(),
-                accessed_from: Vec::<String>::new(),
-                info: row.info,
-            });
+        .fetch_optional(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_strategy error: {:?}", res);
+            return None;
         }
-        Some(groups)
+        let row = res.unwrap()?;
+        Some(Strategy {
+            guid: guid_into_uuid(row.guid).unwrap_or_default(),
+            name: row.name,
+            settings: serde_json::from_str(&row.settings).unwrap_or_else(|_| serde_json::json!({})),
+            is_default: row.is_default,
+            created_at: row.created_at,
+            modified_at: row.modified_at,
+        })
     }
 
-    /// Delete a shared address book, all rules associated with it and all peers associated with it
+    /// Create a strategy
+    /// If it is the default strategy, the previous default one loses the flag in the same transaction
     ///
     /// # Arguments
     ///
-    /// * `guid` - address book uuid in string format
+    /// * `name` - strategy name, it must be unique
+    /// * `settings` - client options in JSON format
+    /// * `is_default` - true to make the new strategy the default one
     ///
     /// # Returns
     ///
-    /// Option<()>
-    pub async fn delete_shared_address_book(&self, guid: &str) -> Option<()> {
-        let mut conn = self.pool.acquire().await.unwrap();
-        let ab_guid = Uuid::parse_str(guid);
-        if ab_guid.is_err() {
-            log::error!("delete_ab error: {:?}", ab_guid);
-            return None;
+    /// Option<String> - uuid of the new strategy, None if the name is already used
+    pub async fn add_strategy(&self, name: &str, settings: &str, is_default: bool) -> Option<String> {
+        let mut tx = self.begin_write().await.ok()?;
+        let strategy_guid = Uuid::new_v4().as_bytes().to_vec();
+        if is_default {
+            let res = sqlx::query!(
+                r#"
+                UPDATE strategies SET is_default = 0, modified_at = current_timestamp
+                    WHERE is_default = 1
+            "#
+            )
+            .execute(&mut tx)
+            .await;
+            if res.is_err() {
+                log::error!("add_strategy error: {:?}", res);
+                return None;
+            }
         }
-        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
         let res = sqlx::query!(
             r#"
-            BEGIN;
-            DELETE FROM ab_peer WHERE ab = ?;
-            DELETE FROM ab_rule WHERE ab = ?;
-            DELETE FROM ab WHERE guid = ?;
-            COMMIT;
-            "#,
-            ab_guid,ab_guid,ab_guid
+            INSERT INTO strategies(guid, name, settings, is_default, created_at, modified_at)
+                VALUES (?, ?, ?, ?, current_timestamp, current_timestamp)
+        "#,
+            strategy_guid,
+            name,
+            settings,
+            is_default
         )
-        .execute(&mut conn)
+        .execute(&mut tx)
         .await;
         if res.is_err() {
-            log::error!("delete_ab error: {:?}", res);
+            log::error!("add_strategy error: {:?}", res);
             return None;
         }
-        Some(())
+        tx.commit().await.ok()?;
+        guid_into_uuid(strategy_guid)
     }
 
-    pub async fn get_shared_address_books(&self, user_id: UserId) -> Option<Vec<AddressBook>> {
-        let mut conn = self.pool.acquire().await.unwrap();
+    /// Copy the settings of a strategy into a new strategy, which is not the default one
+    /// When the name is already used, the first free name with a numeric suffix is taken: `name 2`, `name 3`...
+    ///
+    /// # Arguments
+    ///
+    /// * `guid` - uuid of the copied strategy in string format
+    /// * `name` - name of the copy, None for `<name> (copy)`
+    ///
+    /// # Returns
+    ///
+    /// Option<String> - uuid of the new strategy, None if the copied strategy does not exist
+    pub async fn duplicate_strategy(&self, guid: &str, name: Option<&str>) -> Option<String> {
+        let source_guid = uuid_into_guid(guid)?;
+        let mut tx = self.begin_write().await.ok()?;
         let res = sqlx::query!(
             r#"
             SELECT
-                ab.guid,
-                ab.name,
-                ab.owner,
-                COALESCE(MaxRule.rule, 0) as rule
+                name,
+                settings as "settings!: String"
             FROM
-                ab
-            JOIN
-                (SELECT ab, MAX(rule) as rule
-                FROM ab_rule
-                WHERE user = ? OR grp IN (SELECT grp FROM user WHERE guid = ?)
-                GROUP BY ab) as MaxRule
-            ON ab.guid = MaxRule.ab
+                strategies
             WHERE
-                personal = 0
+                guid = ?
         "#,
-            user_id,
-            user_id
+            source_guid
         )
-        .fetch_all(&mut conn)
-        .await
-        .ok()?;
-        let mut address_books: Vec<AddressBook> = Vec::new();
-        for row in res {
-            let access_level = row.rule.unwrap_or(0) as u32;
-            address_books.push(AddressBook {
-                ab: guid_into_uuid(row.guid).unwrap_or("".to_string()),
-                name: Some(row.name),
-                owner: Some(row.owner),
-                rule: Some(access_level),
-                ..Default::default()
-            });
-        }
-        Some(address_books)
-    }
-
-    pub async fn get_ab_rules(&self, offset: u32, page_size: u32, ab: &str) -> Option<Vec<AbRule>> {
-        let mut conn = self.pool.acquire().await.unwrap();
-        let ab_guid = Uuid::parse_str(ab);
-        if ab_guid.is_err() {
-            log::error!("get_ab_rules error: {:?}", ab_guid);
+        .fetch_optional(&mut tx)
+        .await;
+        if res.is_err() {
+            log::error!("duplicate_strategy error: {:?}", res);
             return None;
         }
-        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
+        let source = res.unwrap()?;
+        let base = match name {
+            Some(name) => name.to_string(),
+            None => format!("{} (copy)", source.name),
+        };
+        let mut candidate = base.clone();
+        let mut suffix = 1;
+        loop {
+            let res = sqlx::query!(
+                r#"SELECT COUNT(*) as "used!: i64" FROM strategies WHERE name = ?"#,
+                candidate
+            )
+            .fetch_one(&mut tx)
+            .await;
+            if res.is_err() {
+                log::error!("duplicate_strategy error: {:?}", res);
+                return None;
+            }
+            if res.unwrap().used == 0 {
+                break;
+            }
+            suffix += 1;
+            candidate = format!("{} {}", base, suffix);
+        }
+        let strategy_guid = Uuid::new_v4().as_bytes().to_vec();
         let res = sqlx::query!(
             r#"
-            SELECT
-                r.guid,
-                u.name as username,
-                g.name as groupname,
-                r.rule
-            FROM
-                ab_rule r
-            LEFT JOIN
-                user u ON u.guid = r.user
-            LEFT JOIN
-                grp g ON g.guid = r.grp
-            WHERE
-                ab = ?
+            INSERT INTO strategies(guid, name, settings, is_default, created_at, modified_at)
+                VALUES (?, ?, ?, 0, current_timestamp, current_timestamp)
         "#,
-            ab_guid
+            strategy_guid,
+            candidate,
+            source.settings
         )
-        .fetch_all(&mut conn)
+        .execute(&mut tx)
         .await;
         if res.is_err() {
-            log::error!("get_ab_rules error: {:?}", res);
+            log::error!("duplicate_strategy error: {:?}", res);
             return None;
         }
-        let res = res.unwrap();
-        let mut ab_rules = Vec::new();
-        for row in res {
-            let uuid = guid_into_uuid(row.guid).unwrap_or("".to_string());
-            let user = row.username;
-            let group = row.groupname;
-            let ab_rule = AbRule {
-                user: user,
-                group: group,
-                rule: row.rule as u32,
-                guid: uuid,
-            };
-            ab_rules.push(ab_rule);
+        tx.commit().await.ok()?;
+        guid_into_uuid(strategy_guid)
+    }
+
+    /// Update a strategy
+    /// If it becomes the default strategy, the previous default one loses the flag in the same transaction
+    ///
+    /// # Arguments
+    ///
+    /// * `guid` - uuid of the strategy in string format
+    /// * `name` - new name, None to keep the current one
+    /// * `settings` - new client options in JSON format, None to keep the current ones
+    /// * `is_default` - true to make the strategy the default one, false to keep its flag
+    ///
+    /// # Returns
+    ///
+    /// Option<()> - None if the strategy does not exist or if the name is already used
+    pub async fn update_strategy(
+        &self,
+        guid: &str,
+        name: Option<&str>,
+        settings: Option<&str>,
+        is_default: bool,
+    ) -> Option<()> {
+        let strategy_guid = uuid_into_guid(guid)?;
+        let mut tx = self.begin_write().await.ok()?;
+        if is_default {
+            let res = sqlx::query!(
+                r#"
+                UPDATE strategies SET is_default = 0, modified_at = current_timestamp
+                    WHERE is_default = 1 AND guid <> ?
+            "#,
+                strategy_guid
+            )
+            .execute(&mut tx)
+            .await;
+            if res.is_err() {
+                log::error!("update_strategy error: {:?}", res);
+                return None;
+            }
         }
-        Some(ab_rules)
+        let res = sqlx::query!(
+            r#"
+            UPDATE strategies SET
+                name = COALESCE(?, name),
+                settings = COALESCE(?, settings),
+                is_default = (is_default OR ?),
+                modified_at = current_timestamp
+            WHERE guid = ?
+        "#,
+            name,
+            settings,
+            is_default,
+            strategy_guid
+        )
+        .execute(&mut tx)
+        .await;
+        if res.is_err() {
+            log::error!("update_strategy error: {:?}", res);
+            return None;
+        }
+        if res.unwrap().rows_affected() == 0 {
+            return None;
+        }
+        tx.commit().await.ok()?;
+        Some(())
     }
 
-    pub async fn delete_ab_rule(&self, rule: &str) -> Option<()> {
-        let mut conn = self.pool.acquire().await.unwrap();
-        let rule_guid = Uuid::parse_str(rule);
-        if rule_guid.is_err() {
-            log::error!("delete_ab_rule error: {:?}", rule_guid);
-            return None;
+    /// Delete a strategy, the default strategy is never deleted
+    ///
+    /// # Arguments
+    ///
+    /// * `guid` - uuid of the strategy in string format
+    /// * `cascade` - remove the assignments of the strategy in the same transaction,
+    ///   the peers and the users fall back to the next strategy of the resolution
+    ///
+    /// # Returns
+    ///
+    /// Option<()> - None if the strategy does not exist or is the default one
+    pub async fn delete_strategy(&self, guid: &str, cascade: bool) -> Option<()> {
+        let strategy_guid = uuid_into_guid(guid)?;
+        let mut tx = self.begin_write().await.ok()?;
+        if cascade {
+            let res = sqlx::query!(
+                r#"
+                UPDATE peer SET strategy = NULL WHERE strategy = ?;
+                UPDATE user SET strategy = NULL WHERE strategy = ?;
+                UPDATE grp_strategy SET strategy = NULL, modified_at = current_timestamp WHERE strategy = ?;
+            "#,
+                strategy_guid,
+                strategy_guid,
+                strategy_guid
+            )
+            .execute(&mut tx)
+            .await;
+            if res.is_err() {
+                log::error!("delete_strategy error: {:?}", res);
+                return None;
+            }
         }
-        let rule_guid = rule_guid.unwrap().as_bytes().to_vec();
         let res = sqlx::query!(
             r#"
-            DELETE FROM ab_rule WHERE guid = ?
+            DELETE FROM strategies WHERE guid = ? AND is_default = 0
         "#,
-            rule_guid
+            strategy_guid
         )
-        .execute(&mut conn)
+        .execute(&mut tx)
         .await;
         if res.is_err() {
-            log::error!("delete_ab_rule error: {:?}", res);
+            log::error!("delete_strategy error: {:?}", res);
+            return None;
+        }
+        if res.unwrap().rows_affected() == 0 {
             return None;
         }
+        tx.commit().await.ok()?;
         Some(())
     }
 
-    pub async fn add_ab_rule(&self, rule: AbRule) -> Option<()> {
+    /// Get the default strategy
+    ///
+    /// # Returns
+    ///
+    /// Option<Strategy>
+    pub async fn get_default_strategy(&self) -> Option<Strategy> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let rule_guid = Uuid::new_v4().as_bytes().to_vec();
-        let ab_guid = Uuid::parse_str(&rule.guid);
-        if ab_guid.is_err() {
-            log::error!("add_ab_rule error: {:?}", ab_guid);
-            return None;
-        }
-        let ab_guid = ab_guid.unwrap().as_bytes().to_vec();
-        let user_guid = if (rule.user.is_some()) {
-            let uuid = Uuid::parse_str(&rule.user.unwrap());
-            if (uuid.is_err()) {
-                None
-            } else {
-                Some(uuid.unwrap().as_bytes().to_vec())
-            }
-        } else {
-            None
-        };
-
-        let group_guid = if (rule.group.is_some()) {
-            let uuid = Uuid::parse_str(&rule.group.unwrap());
-            if (uuid.is_err()) {
-                None
-            } else {
-                Some(uuid.unwrap().as_bytes().to_vec())
-            }
-        } else {
-            None
-        };
-
         let res = sqlx::query!(
             r#"
-            INSERT OR IGNORE INTO ab_rule (guid, ab, user, grp, rule)
-                VALUES (?, ?, ?, ?, ?)
-        "#,
-            rule_guid,
-            ab_guid,
-            user_guid,
-            group_guid,
-            rule.rule
+            SELECT guid FROM strategies WHERE is_default = 1
+        "#
         )
-        .execute(&mut conn)
+        .fetch_optional(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("add_ab_rule error: {:?}", res);
+            log::error!("get_default_strategy error: {:?}", res);
             return None;
         }
-        Some(())
+        let guid = guid_into_uuid(res.unwrap()?.guid)?;
+        self.get_strategy(guid.as_str()).await
     }
 
-    pub async fn get_peers_count(&self, platform: Platform) -> u32 {
+    /// Get the strategies assigned to a peer and to the user it is assigned to
+    /// The assignments to a strategy which no longer exists are ignored
+    ///
+    /// # Arguments
+    ///
+    /// * `peer_id` - id of the peer
+    ///
+    /// # Returns
+    ///
+    /// Option<(Option<String>, Option<String>)> - uuids of the device and of the user strategies, None if the peer does not exist
+    pub async fn get_peer_strategies(&self, peer_id: &str) -> Option<(Option<String>, Option<String>)> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let filter = match platform {
-            Platform::Windows => "windows%",
-            Platform::Linux => "linux%",
-            Platform::MacOS => "macos%",
-            Platform::Android => "android%",
-            Platform::All => "%",
-            _ => "unknown%",
-        };
         let res = sqlx::query!(
             r#"
             SELECT
-                COUNT(*) as count
+                (SELECT guid FROM strategies WHERE guid = peer.strategy) as "device: Vec<u8>",
+                (SELECT strategies.guid FROM user JOIN strategies ON strategies.guid = user.strategy
+                    WHERE user.guid = peer.user) as "user: Vec<u8>"
             FROM
                 peer
             WHERE
-                json_extract(info,'$.os') like ?
+                peer.id = ?
         "#,
-            filter
+            peer_id
         )
-        .fetch_one(&mut conn)
-        .await
-        .ok();
-        if res.is_none() {
-            return 0;
+        .fetch_optional(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("get_peer_strategies error: {:?}", res);
+            return None;
         }
-        let res = res.unwrap();
-        res.count as u32
+        let row = res.unwrap()?;
+        Some((
+            row.device.and_then(guid_into_uuid),
+            row.user.and_then(guid_into_uuid),
+        ))
     }
 
-    pub async fn get_cpus_count(&self) -> Vec<CpuCount> {
+    /// Assign a strategy to a peer
+    ///
+    /// # Arguments
+    ///
+    /// * `peer_id` - id of the peer
+    /// * `strategy` - uuid of the strategy in string format, None to remove the assignment
+    ///
+    /// # Returns
+    ///
+    /// Option<()> - None if the peer does not exist
+    pub async fn assign_peer_strategy(&self, peer_id: &str, strategy: Option<&str>) -> Option<()> {
         let mut conn = self.pool.acquire().await.unwrap();
+        let strategy_guid = match strategy {
+            Some(strategy) => Some(uuid_into_guid(strategy)?),
+            None => None,
+        };
         let res = sqlx::query!(
             r#"
-            SELECT 
-                COALESCE(trim(json_extract(info,'$.cpu')),"unknown") as cpu, 
-                COUNT(*) AS machine_count 
-            FROM 
-                peer 
-            GROUP BY 
-                cpu;
-        "#
+            UPDATE peer SET strategy = ? WHERE id = ?
+        "#,
+            strategy_guid,
+            peer_id
         )
-        .fetch_all(&mut conn)
-        .await
-        .ok();
-        if res.is_none() {
-            return Vec::new();
+        .execute(&mut conn)
+        .await;
+        if res.is_err() {
+            log::error!("assign_peer_strategy error: {:?}", res);
+            return None;
         }
-        let res = res.unwrap();
-        let mut cpu_counts = Vec::new();
-        for row in res {
-            let cpu = row.cpu;
-            let total = row.machine_count as u32;
-            cpu_counts.push(CpuCount { cpu, total });
+        if res.unwrap().rows_affected() == 0 {
+            return None;
         }
-        cpu_counts
+        Some(())
     }
 
-    /// Get a group given its guid
+    /// Assign a strategy to a user, it applies to the peers of the user without their own strategy
     ///
     /// # Arguments
     ///
-    /// * `guid` - group guid in string format
+    /// * `user` - uuid of the user in string format
+    /// * `strategy` - uuid of the strategy in string format, None to remove the assignment
     ///
     /// # Returns
     ///
-    /// Option<Group>
-    pub async fn get_group(&self, guid: &str) -> Option<Group> {
+    /// Option<()> - None if the user does not exist
+    pub async fn assign_user_strategy(&self, user: &str, strategy: Option<&str>) -> Option<()> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let group_guid = Uuid::parse_str(guid);
-        if group_guid.is_err() {
-            log::error!("get_group error: {:?}", group_guid);
-            return None;
-        }
-        let group_guid = group_guid.unwrap().as_bytes().to_vec();
+        let user_guid = uuid_into_guid(user)?;
+        let strategy_guid = match strategy {
+            Some(strategy) => Some(uuid_into_guid(strategy)?),
+            None => None,
+        };
         let res = sqlx::query!(
             r#"
-            SELECT
-                guid,
-                team,
-                name,
-                note,
-                created_at as "created_at!: String",
-                info as "info!: String"
-            FROM
-                grp
-            WHERE
-                guid = ?
+            UPDATE user SET strategy = ? WHERE guid = ?
         "#,
-            group_guid
+            strategy_guid,
+            user_guid
         )
-        .fetch_one(&mut conn)
+        .execute(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("get_group error: {:?}", res);
+            log::error!("assign_user_strategy error: {:?}", res);
             return None;
         }
-        let res = res.unwrap();
-        let guid = guid_into_uuid(res.guid).unwrap_or("".to_string());
-        let team = guid_into_uuid(res.team).unwrap_or("".to_string());
-        Some(Group {
-            guid: guid,
-            name: res.name,
-            team: team,
-            note: res.note,
-            created_at: res.created_at.into(),
-            access_to: Vec::<String>::new(),
-            accessed_from: Vec::<String>::new(),
-            info: res.info,
-        })
+        if res.unwrap().rows_affected() == 0 {
+            return None;
+        }
+        Some(())
     }
 
-    /// Create a new group
+    /// Assign a strategy to a group, it applies to the peers of the group and to the peers of its users
+    /// The modification date of the assignment is updated so that the clients pick the change up
     ///
     /// # Arguments
     ///
-    /// * `name` - group name
-    /// * `team` - team name ( must exist in the database )
-    /// * `note` - group note
+    /// * `group` - uuid of the group in string format
+    /// * `strategy` - uuid of the strategy in string format, None to remove the assignment
     ///
     /// # Returns
     ///
-    /// Option<()>
-    pub async fn create_group(&self, name: &str, team: &str, note: &str) -> Option<()> {
+    /// Option<()> - None if the group does not exist
+    pub async fn assign_group_strategy(&self, group: &str, strategy: Option<&str>) -> Option<()> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let group_guid = Uuid::new_v4().as_bytes().to_vec();
-
+        let group_guid = uuid_into_guid(group)?;
+        let strategy_guid = match strategy {
+            Some(strategy) => Some(uuid_into_guid(strategy)?),
+            None => None,
+        };
         let res = sqlx::query!(
             r#"
-            INSERT OR IGNORE INTO grp(guid, team, name, note, created_at, info)
-                VALUES (?, (SELECT guid FROM team  WHERE name = ?) , ?, ?, current_timestamp, '{}')
+            INSERT INTO grp_strategy(grp, strategy, modified_at)
+                SELECT guid, ?, current_timestamp FROM grp WHERE guid = ?
+                ON CONFLICT(grp) DO UPDATE SET strategy = excluded.strategy, modified_at = excluded.modified_at
         "#,
-            group_guid,
-            team,
-            name,
-            note
+            strategy_guid,
+            group_guid
         )
         .execute(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("create_group error: {:?}", res);
+            log::error!("assign_group_strategy error: {:?}", res);
+            return None;
+        }
+        if res.unwrap().rows_affected() == 0 {
             return None;
         }
         Some(())
     }
 
-    /// Update a group
+    /// Get the strategies assigned to the group of the user of a peer and to the group of the peer, in this order
+    /// The assignments are returned even when they were removed, their modification date tells when
     ///
     /// # Arguments
     ///
-    /// * `guid` - uuid of the group in string format
-    /// * `name` - group name
-    /// * `team` - team name ( must exist in the database )
-    /// * `note` - group note
+    /// * `peer_id` - id of the peer
     ///
     /// # Returns
     ///
-    /// Option<()>
-    pub async fn update_group(&self, guid: &str, name: &str, team: &str, note: &str) -> Option<()> {
+    /// Option<Vec<(Option<String>, String)>> - uuid of the strategy and modification date of each assignment
+    pub async fn get_peer_group_strategies(&self, peer_id: &str) -> Option<Vec<(Option<String>, String)>> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let group_guid = Uuid::parse_str(guid);
-        if group_guid.is_err() {
-            log::error!("update_group error: {:?}", group_guid);
-            return None;
-        }
-        let group_guid = group_guid.unwrap().as_bytes().to_vec();
-
         let res = sqlx::query!(
             r#"
-            UPDATE grp SET team = (SELECT guid FROM team  WHERE name = ?), name = ?, note = ?, created_at = current_timestamp
-                WHERE guid = ?
+            SELECT grp_strategy.strategy as "strategy: Vec<u8>", grp_strategy.modified_at as "modified_at!: String", 0 as "rank!: i64"
+                FROM peer
+                JOIN user ON user.guid = peer.user
+                JOIN grp_strategy ON grp_strategy.grp = user.grp
+                WHERE peer.id = ?1
+            UNION ALL
+            SELECT grp_strategy.strategy as "strategy: Vec<u8>", grp_strategy.modified_at as "modified_at!: String", 1 as "rank!: i64"
+                FROM peer
+                JOIN peer_grp ON peer_grp.peer = peer.guid
+                JOIN grp_strategy ON grp_strategy.grp = peer_grp.grp
+                WHERE peer.id = ?1
+            ORDER BY 3
         "#,
-            team,
-            name,
-            note,
-            group_guid
+            peer_id
         )
-        .execute(&mut conn)
+        .fetch_all(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("update_group error: {:?}", res);
+            log::error!("get_peer_group_strategies error: {:?}", res);
             return None;
         }
-        Some(())
+        Some(
+            res.unwrap()
+                .into_iter()
+                .map(|row| (row.strategy.and_then(guid_into_uuid), row.modified_at))
+                .collect(),
+        )
     }
 
-    /// Delete a group
+    /// Count the peers, the users and the groups a strategy is assigned to
     ///
     /// # Arguments
     ///
-    /// * `guid` - uuid of the group in string format
+    /// * `guid` - uuid of the strategy in string format
     ///
     /// # Returns
     ///
-    /// Option<()>
-    pub async fn delete_group(&self, guid: &str) -> Option<()> {
+    /// Option<u32>
+    pub async fn count_strategy_assignments(&self, guid: &str) -> Option<u32> {
         let mut conn = self.pool.acquire().await.unwrap();
-        let group_guid = Uuid::parse_str(guid);
-        if group_guid.is_err() {
-            log::error!("delete_group error: {:?}", group_guid);
-            return None;
-        }
-        let group_guid = group_guid.unwrap().as_bytes().to_vec();
-
+        let strategy_guid = uuid_into_guid(guid)?;
         let res = sqlx::query!(
             r#"
-            DELETE FROM grp WHERE guid = ?
+            SELECT
+                (SELECT COUNT(*) FROM peer WHERE strategy = ?1)
+                + (SELECT COUNT(*) FROM user WHERE strategy = ?1)
+                + (SELECT COUNT(*) FROM grp_strategy WHERE strategy = ?1) as "total!: u32"
         "#,
-            group_guid
+            strategy_guid
         )
-        .execute(&mut conn)
+        .fetch_one(&mut conn)
         .await;
         if res.is_err() {
-            log::error!("delete_group error: {:?}", res);
+            log::error!("count_strategy_assignments error: {:?}", res);
             return None;
         }
-        Some(())
+        Some(res.unwrap().total)
     }
 
     /// Create a shared address book for a group with one default rule for its owner
@@ -14,6 +14,7 @@
 // You should have received a copy of the Affero General Public License
 // along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
 mod database;
+mod migrations;
 mod state;
 mod bearer;
 mod password;
@@ -21,7 +22,8 @@ mod password;
 pub use utils::{UserId, SessionId};
 
 pub use state::ApiState;
+pub use migrations::Migration;
 pub use password::UserPasswordInfo;
-pub use bearer::{AuthenticatedUser, AuthenticatedAdmin};
+pub use bearer::{AuthenticatedUser, AuthenticatedAdmin, RequestUserId};
 
 pub mod types;
@@ -0,0 +1,152 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use sqlx::sqlite::SqliteConnection;
+use sqlx::Connection;
+
+/// A versioned change of the schema of the database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migration {
+    pub version: i64,
+    pub description: &'static str,
+    pub sql: &'static str,
+}
+
+/// The migrations embedded in the binary, in increasing versions
+///
+/// A schema change is a new file in `db_v2/schema` added at the end of this list, an applied migration is never modified.
+/// The baseline is the schema created before the versioning, it only creates what is missing so it is applied
+/// on the existing databases too.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "baseline",
+        sql: include_str!("../../../db_v2/schema/0001_baseline.sql"),
+    },
+    Migration {
+        version: 2,
+        description: "password login",
+        sql: include_str!("../../../db_v2/schema/0002_password_login.sql"),
+    },
+    Migration {
+        version: 3,
+        description: "email verification",
+        sql: include_str!("../../../db_v2/schema/0003_email_verification.sql"),
+    },
+    Migration {
+        version: 4,
+        description: "share links",
+        sql: include_str!("../../../db_v2/schema/0004_share_links.sql"),
+    },
+    Migration {
+        version: 5,
+        description: "address book peer ids",
+        sql: include_str!("../../../db_v2/schema/0005_ab_peer_ids.sql"),
+    },
+    Migration {
+        version: 6,
+        description: "user unique email",
+        sql: include_str!("../../../db_v2/schema/0006_user_unique_email.sql"),
+    },
+    Migration {
+        version: 7,
+        description: "personal address books",
+        sql: include_str!("../../../db_v2/schema/0007_personal_address_books.sql"),
+    },
+];
+
+/// Version of the schema of the database, 0 if it has never been migrated
+pub async fn schema_version(conn: &mut SqliteConnection) -> Result<i64, sqlx::Error> {
+    let versioned: i64 =
+        sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'schema_version'")
+            .fetch_one(&mut *conn)
+            .await?;
+    if versioned == 0 {
+        return Ok(0);
+    }
+    sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM schema_version")
+        .fetch_one(&mut *conn)
+        .await
+}
+
+/// The migrations to apply on a database at the `current` version
+///
+/// # Returns
+/// An error if the database has been migrated by a newer binary, it must not be used by this one
+pub fn pending_migrations(current: i64, migrations: &[Migration]) -> Result<Vec<Migration>, String> {
+    let latest = migrations.last().map(|migration| migration.version).unwrap_or(0);
+    if current > latest {
+        return Err(format!(
+            "the database schema is at version {} but this server only knows the versions up to {}, upgrade the server",
+            current, latest
+        ));
+    }
+    Ok(migrations
+        .iter()
+        .filter(|migration| migration.version > current)
+        .copied()
+        .collect())
+}
+
+/// Apply the pending migrations in a single transaction
+///
+/// # Returns
+/// The version of the schema after the migrations
+pub async fn run_migrations(conn: &mut SqliteConnection, migrations: &[Migration]) -> Result<i64, String> {
+    let current = schema_version(conn).await.map_err(|e| e.to_string())?;
+    let pending = pending_migrations(current, migrations)?;
+    if pending.is_empty() {
+        log::info!("database schema at version {}", current);
+        return Ok(current);
+    }
+    // the migrations may create the tables in any order, the pragma cannot be changed inside a transaction
+    sqlx::query("PRAGMA foreign_keys = OFF").execute(&mut *conn).await.map_err(|e| e.to_string())?;
+    let res = apply_migrations(conn, &pending).await;
+    sqlx::query("PRAGMA foreign_keys = ON").execute(&mut *conn).await.map_err(|e| e.to_string())?;
+    res?;
+    let version = pending.last().map(|migration| migration.version).unwrap_or(current);
+    log::info!("database schema migrated from version {} to {}", current, version);
+    Ok(version)
+}
+
+async fn apply_migrations(conn: &mut SqliteConnection, pending: &[Migration]) -> Result<(), String> {
+    let mut tx = conn.begin().await.map_err(|e| e.to_string())?;
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY NOT NULL,
+            description TEXT NOT NULL,
+            applied_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )
+    "#,
+    )
+    .execute(&mut tx)
+    .await
+    .map_err(|e| e.to_string())?;
+    for migration in pending {
+        log::info!("applying migration {} ({})", migration.version, migration.description);
+        sqlx::query(migration.sql)
+            .execute(&mut tx)
+            .await
+            .map_err(|e| format!("migration {} ({}) failed: {}", migration.version, migration.description, e))?;
+        sqlx::query("INSERT INTO schema_version (version, description) VALUES (?, ?)")
+            .bind(migration.version)
+            .bind(migration.description)
+            .execute(&mut tx)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    tx.commit().await.map_err(|e| e.to_string())
+}
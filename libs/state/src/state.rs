@@ -14,7 +14,9 @@
 // You should have received a copy of the Affero General Public License
 // along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
 use crate::database::DatabaseUserInfo;
+use crate::migrations::Migration;
 use crate::types;
+use crate::types::{DatabaseOptions, DatabasePoolStats};
 use crate::{
     bearer::AuthenticatedUserInfo, database::Database, password::UserPasswordInfo, SessionId,
     UserId,
@@ -22,17 +24,32 @@ use crate::{
 use std::{
     collections::HashMap,
     default::Default,
+    env,
     path::Path,
     sync::atomic::{AtomicU64, Ordering},
-    time::SystemTime,
+    time::{Duration, Instant, SystemTime},
 };
 
 use oauth2::ProviderConfig;
-
-use tokio::sync::RwLock;
+use rocket::futures::Stream;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sha2::{Digest, Sha256};
+use tokio::sync::{broadcast, RwLock};
+use utils::audit::{
+    redact_audit_details, AdminAction, AuditCategory, AuditEvent, AuditFilter, AuditRecord,
+    ForwardedAudit, AUDIT_FORWARD_ADMIN, AUDIT_RETENTION_DEFAULT_DAYS, AUDIT_RETENTION_MAX_DAYS,
+};
+use utils::cursor::PageCursor;
+use utils::get_host::get_public_base_url;
+use utils::strategy::{merge_strategy_settings, EffectiveSettings, StrategyOrder};
+use utils::guid_into_uuid;
+use utils::peer_status::PEER_OFFLINE_AFTER_SECS;
+use utils::ui_settings::merge_ui_settings;
 use utils::{
-    AbPeer, AbRule, AbTag, AddUserRequest, AddressBook, CpuCount, Group, OidcState, Peer, Platform,
-    Token, UpdateUserRequest, UserListResponse,
+    AbPeer, AbRule, AbTag, AbTagStats, AuditAdmin, AuditAlarm, AuditConn, AuditFile, AuditRetention, AddUserRequest, AddressBook, ClientVersions, CpuCount, EffectiveStrategy, EmailVerificationError, Group, HeartbeatDirective, MaintenanceNotice, OidcSession, OidcSessionStatus, OidcState, Peer,
+    LinkedProvider, PeerAccess, PeerEvent, PeerExport, PeerTransferError, PeerAvatar, PeerTransferResponse, PeerUuidConflict, UnlinkProviderError, PeersBulkAction, PeersSummary, PeersBulkResult, Platform, ShareLinkError,
+    ReleaseChannel, SoftwareRelease, StatisticsResponse, Strategy, StrategySource, Token, UpdateUserRequest, UserField, UserListResponse, UserPermissions, UserStatus,
 };
 
 pub struct ApiState {
@@ -44,14 +61,51 @@ pub struct ApiState {
     oidc_sessions: RwLock<HashMap<String, OidcState>>,
     db: Database,
     oauth2_providers: RwLock<Vec<ProviderConfig>>,
+    /// the providers config file, set by the `OAUTH2_CONFIG_FILE` environment variable
+    oauth2_config_file: String,
+    /// the url the clients use to reach the server, set by the `PUBLIC_BASE_URL` environment variable
+    public_base_url: Option<String>,
+    peer_events: broadcast::Sender<PeerEvent>,
+    audit_records: broadcast::Sender<ForwardedAudit>,
+    maintenance_notice: RwLock<Option<MaintenanceNotice>>,
+    /// tokens unused for longer are revoked, set by the `IDLE_TIMEOUT_SECONDS` environment variable
+    idle_timeout: Option<u64>,
+    /// two peers of an address book cannot have the same alias, set by the `AB_UNIQUE_ALIASES` environment variable
+    unique_aliases: bool,
+    /// last peers summary and when it was computed
+    peers_summary: RwLock<Option<(Instant, PeersSummary)>>,
+    /// the heartbeats of a peer closer than this are not recorded, set by the `HEARTBEAT_MIN_INTERVAL` environment variable
+    heartbeat_min_interval: Duration,
+    /// last recorded heartbeat of each peer by id and uuid
+    last_heartbeats: RwLock<HashMap<(String, String), Instant>>,
 }
 
+/// Default minimum interval between two recorded heartbeats of a peer
+const HEARTBEAT_MIN_INTERVAL_SECS: u64 = 5;
+
+/// The peers summary is computed again when it is older
+const PEERS_SUMMARY_CACHE_SECS: u64 = 10;
+
+/// Number of peer events kept for each subscriber
+/// A subscriber lagging behind loses the oldest events instead of buffering them
+const PEER_EVENTS_CAPACITY: usize = 256;
+
+/// Number of persisted audit records queued for the audit forwarder
+/// When the forwarder lags behind, the oldest records are dropped
+const AUDIT_RECORDS_CAPACITY: usize = 1024;
+
 #[derive(Debug, Clone)]
 pub struct AccessTokenInfo {
     pub session_id: SessionId,
     pub user_id: UserId,
+    /// last time the token authenticated a request, in seconds since the epoch
+    pub last_used: u64,
 }
 
+/// Interval between two updates of the last use of a token
+/// It is shortened for the idle timeouts shorter than twice this interval
+const TOKEN_LAST_USED_REFRESH_SECS: u64 = 60;
+
 #[derive(Debug, Default)]
 struct SessionsState {
     counter: SessionId,
@@ -80,6 +134,34 @@ pub struct AddressBookInfo {
 
 const MAINTENANCE_INTERVAL_IN_SECS: u64 = 60;
 
+/// Lifetime of the logins recorded in the session table
+const SESSION_TTL_SECS: i64 = 30 * 24 * 3600;
+
+/// Version of a strategy sent to the clients: its last modification in seconds since the epoch
+/// Version of the configuration of a peer sent in the heartbeat directives
+///
+/// It is a fingerprint of the strategies applied to the peer with their versions, of the time the strategies
+/// of its groups were assigned and of the merged settings: assigning an older strategy or removing an assignment
+/// changes it too. It fits in the i64 the clients store and it is never 0, the version of a client without strategy.
+fn config_version(layers: &[(StrategySource, Strategy)], assigned: u64, settings: &serde_json::Value) -> u64 {
+    let mut hasher = Sha256::new();
+    for (source, strategy) in layers {
+        hasher.update(format!("{:?}/{}/{}\n", source, strategy.guid, strategy.modified_at));
+    }
+    hasher.update(assigned.to_be_bytes());
+    hasher.update(settings.to_string());
+    let digest = hasher.finalize();
+    let mut version = [0u8; 8];
+    version.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(version) & i64::MAX as u64).max(1)
+}
+
+/// Seconds since the epoch of a date stored by SQLite, 0 if it cannot be parsed
+fn datetime_version(datetime: &str) -> u64 {
+    NaiveDateTime::parse_from_str(datetime, "%Y-%m-%d %H:%M:%S")
+        .map_or(0, |datetime| datetime.and_utc().timestamp().max(0) as u64)
+}
+
 fn secs_from_epoch() -> u64 {
     SystemTime::now()
         .duration_since(SystemTime::UNIX_EPOCH)
@@ -87,9 +169,136 @@ fn secs_from_epoch() -> u64 {
         .as_secs()
 }
 
+/// Number of characters of the session codes listed by `get_oidc_sessions`
+const OIDC_CODE_PREFIX_LEN: usize = 8;
+
+/// Key of the audit retention in the settings table
+const AUDIT_RETENTION_SETTING: &str = "audit_retention";
+
+/// Key of the client versions in the settings table
+const CLIENT_VERSIONS_SETTING: &str = "client_versions";
+
+/// Key of the default UI settings of the console in the settings table
+const UI_DEFAULTS_SETTING: &str = "ui_defaults";
+
+/// Type of the UI settings of a user in the user_data table
+const UI_SETTINGS_USER_DATA: &str = "ui_settings";
+
+/// Type of the settings synced by the RustDesk clients of a user in the user_data table
+const CLIENT_SETTINGS_USER_DATA: &str = "client_settings";
+
+/// Interval between two purges of the expired audit records
+const AUDIT_PURGE_INTERVAL_SECS: u64 = 3600;
+
+/// Number of audit records deleted in each transaction of the purge
+const AUDIT_PURGE_BATCH_SIZE: u32 = 500;
+
+/// Interval between two purges of the old peer status history
+const PEER_STATUS_HISTORY_PURGE_INTERVAL_SECS: u64 = 3600;
+
+/// Number of status transitions deleted in each transaction of the purge
+const PEER_STATUS_HISTORY_PURGE_BATCH_SIZE: u32 = 500;
+
+/// The retention of the categories until an admin sets them, from `AUDIT_RETENTION_DAYS`
+/// 0 keeps the records forever
+fn default_audit_retention(days: Option<&str>) -> AuditRetention {
+    match days.map(|days| days.trim().parse::<u32>()) {
+        Some(Ok(days)) if days <= AUDIT_RETENTION_MAX_DAYS => AuditRetention::uniform(days),
+        Some(_) => {
+            log::warn!(
+                "AUDIT_RETENTION_DAYS must be a number of days between 0 and {}, {} is used",
+                AUDIT_RETENTION_MAX_DAYS,
+                AUDIT_RETENTION_DEFAULT_DAYS
+            );
+            AuditRetention::default()
+        }
+        None => AuditRetention::default(),
+    }
+}
+
+async fn read_audit_retention(db: &Database) -> AuditRetention {
+    db.get_setting(AUDIT_RETENTION_SETTING)
+        .await
+        .and_then(|value| serde_json::from_str::<AuditRetention>(&value).ok())
+        .filter(|retention| retention.validate().is_ok())
+        .unwrap_or_else(|| default_audit_retention(env::var("AUDIT_RETENTION_DAYS").ok().as_deref()))
+}
+
+/// Clock of the audit purge
+/// It never runs faster than the monotonic clock, so a system clock jumping forward
+/// cannot move the purge horizon over records which are still within their retention
+#[derive(Default)]
+struct PurgeClock {
+    last: Option<(Instant, DateTime<Utc>)>,
+}
+
+impl PurgeClock {
+    fn now(&mut self, instant: Instant, wall: DateTime<Utc>) -> DateTime<Utc> {
+        let now = match self.last {
+            Some((last_instant, last_now)) => {
+                let elapsed = chrono::Duration::from_std(instant.duration_since(last_instant))
+                    .unwrap_or_else(|_| chrono::Duration::zero());
+                wall.min(last_now + elapsed)
+            }
+            None => wall,
+        };
+        self.last = Some((instant, now));
+        now
+    }
+}
+
+/// Delete the audit records older than their retention at `now`
+///
+/// # Returns
+/// u64 - the number of records deleted
+async fn purge_audits(db: &Database, now: DateTime<Utc>) -> u64 {
+    let retention = read_audit_retention(db).await;
+    let mut total = 0;
+    for category in AuditCategory::ALL {
+        let days = retention.days(category);
+        if days == 0 {
+            continue;
+        }
+        let horizon = now - chrono::Duration::days(days as i64);
+        let horizon = horizon.format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+        if let Some(purged) = db.purge_audit(category, &horizon, AUDIT_PURGE_BATCH_SIZE).await {
+            if purged > 0 {
+                log::info!("purge_audits: {} {} records older than {} deleted", purged, category.as_str(), horizon);
+            }
+            total += purged;
+        }
+    }
+    total
+}
+
+/// Record the offline transitions of the peers which stopped beating and publish them
+async fn sweep_offline_peers(db: &Database, peer_events: &broadcast::Sender<PeerEvent>) {
+    if let Some(events) = db.sweep_offline_peers().await {
+        for event in events {
+            // an error only means that nobody is listening
+            let _ = peer_events.send(event);
+        }
+    }
+}
+
 impl ApiState {
     pub async fn new_with_db<P: AsRef<Path>>(db_filename: P) -> Self {
-        let db = Database::open(db_filename).await;
+        Self::new_with_db_options(db_filename, &DatabaseOptions::default()).await
+    }
+
+    /// The schema migrations which would be applied when the database is opened, nothing is written
+    ///
+    /// # Returns
+    /// An error if the database cannot be read or is newer than this server
+    pub async fn pending_migrations<P: AsRef<Path>>(db_filename: P) -> Result<Vec<Migration>, String> {
+        Database::pending_migrations(db_filename).await
+    }
+
+    /// Open the database with the given connection pool settings
+    pub async fn new_with_db_options<P: AsRef<Path>>(db_filename: P, options: &DatabaseOptions) -> Self {
+        let db = Database::open_with_options(db_filename, options).await;
+        let (peer_events, _) = broadcast::channel(PEER_EVENTS_CAPACITY);
+        let (audit_records, _) = broadcast::channel(AUDIT_RECORDS_CAPACITY);
         Self {
             last_maintenance_time: AtomicU64::new(0),
             access_tokens: Default::default(),
@@ -99,9 +308,91 @@ impl ApiState {
             db,
             oidc_sessions: Default::default(),
             oauth2_providers: Default::default(),
+            oauth2_config_file: oauth2::get_providers_config_file(),
+            public_base_url: get_public_base_url(),
+            peer_events,
+            audit_records,
+            maintenance_notice: Default::default(),
+            idle_timeout: env::var("IDLE_TIMEOUT_SECONDS")
+                .ok()
+                .and_then(|secs| secs.parse::<u64>().ok())
+                .filter(|secs| *secs > 0),
+            unique_aliases: matches!(env::var("AB_UNIQUE_ALIASES").as_deref(), Ok("true") | Ok("1")),
+            peers_summary: Default::default(),
+            heartbeat_min_interval: Duration::from_secs(
+                env::var("HEARTBEAT_MIN_INTERVAL")
+                    .ok()
+                    .and_then(|secs| secs.parse::<u64>().ok())
+                    .unwrap_or(HEARTBEAT_MIN_INTERVAL_SECS),
+            ),
+            last_heartbeats: Default::default(),
+        }
+    }
+
+    /// Replace the minimum interval between two recorded heartbeats of a peer, zero records all of them
+    pub fn with_heartbeat_min_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_min_interval = interval;
+        self
+    }
+
+    /// Read the OAuth2 providers from `config_file` instead of the file named by the `OAUTH2_CONFIG_FILE` environment variable
+    pub fn with_oauth2_config_file(mut self, config_file: &str) -> Self {
+        self.oauth2_config_file = config_file.to_string();
+        self
+    }
+
+    /// The providers config file
+    pub fn oauth2_config_file(&self) -> &str {
+        &self.oauth2_config_file
+    }
+
+    /// Replace the url the clients use to reach the server, None for the host the requests are sent to
+    pub fn with_public_base_url(mut self, public_base_url: Option<&str>) -> Self {
+        self.public_base_url = public_base_url.map(|url| url.trim_end_matches('/').to_string());
+        self
+    }
+
+    /// The url the clients use to reach the server, without the trailing slash
+    pub fn public_base_url(&self) -> Option<&str> {
+        self.public_base_url.as_deref()
+    }
+
+    /// Subscribe to the peer status events
+    pub fn subscribe_peer_events(&self) -> broadcast::Receiver<PeerEvent> {
+        self.peer_events.subscribe()
+    }
+
+    fn publish_peer_events(&self, events: Vec<PeerEvent>) {
+        for event in events {
+            // an error only means that nobody is listening
+            let _ = self.peer_events.send(event);
         }
     }
 
+    /// Number of audit records published and not yet received by every forwarder
+    pub fn audit_queue_depth(&self) -> usize {
+        self.audit_records.len()
+    }
+
+    /// Number of access tokens in use
+    pub async fn count_access_tokens(&self) -> usize {
+        self.access_tokens.read().await.len()
+    }
+
+    pub fn database_pool_stats(&self) -> DatabasePoolStats {
+        self.db.pool_stats()
+    }
+
+    /// Subscribe to the audit records once they are persisted
+    pub fn subscribe_audit_records(&self) -> broadcast::Receiver<ForwardedAudit> {
+        self.audit_records.subscribe()
+    }
+
+    fn publish_audit_record(&self, category: &str, record: serde_json::Value) {
+        // an error only means that no forwarder is listening
+        let _ = self.audit_records.send(ForwardedAudit::new(category, record));
+    }
+
     pub async fn maintenance_flush_address_books(&self) {
         let mut state_address_books = self.address_books.write().await;
 
@@ -124,6 +415,86 @@ impl ApiState {
 
     pub async fn maintenance(&self) {
         self.maintenance_flush_address_books().await;
+        self.purge_idle_tokens().await;
+        self.purge_last_heartbeats().await;
+    }
+
+    /// Forget the heartbeats older than the minimum interval, they no longer throttle anything
+    async fn purge_last_heartbeats(&self) {
+        let interval = self.heartbeat_min_interval;
+        self.last_heartbeats
+            .write()
+            .await
+            .retain(|_, last| last.elapsed() < interval);
+    }
+
+    /// Record a heartbeat of a peer unless the previous one is closer than the minimum interval
+    ///
+    /// # Returns
+    /// None if the heartbeat must be recorded, or else the minimum interval in seconds
+    pub async fn throttle_heartbeat(&self, peer_id: &str, uuid: &str) -> Option<u64> {
+        if self.heartbeat_min_interval.is_zero() {
+            return None;
+        }
+        let now = Instant::now();
+        let mut last_heartbeats = self.last_heartbeats.write().await;
+        let key = (peer_id.to_string(), uuid.to_string());
+        if let Some(last) = last_heartbeats.get(&key) {
+            if now.duration_since(*last) < self.heartbeat_min_interval {
+                return Some(self.heartbeat_min_interval.as_secs().max(1));
+            }
+        }
+        last_heartbeats.insert(key, now);
+        None
+    }
+
+    /// Revoke the tokens unused for longer than the idle timeout
+    pub async fn purge_idle_tokens(&self) {
+        let Some(idle_timeout) = self.idle_timeout else {
+            return;
+        };
+        let now = secs_from_epoch();
+        let idle: Vec<AuthenticatedUserInfo> = self
+            .access_tokens
+            .read()
+            .await
+            .iter()
+            .filter(|(_, info)| now.saturating_sub(info.last_used) > idle_timeout)
+            .map(|(token, info)| AuthenticatedUserInfo {
+                session_id: info.session_id,
+                user_id: info.user_id.clone(),
+                access_token: token.clone(),
+            })
+            .collect();
+        for user in idle.iter() {
+            self.user_logout(user).await;
+        }
+        if !idle.is_empty() {
+            log::debug!("purge_idle_tokens: {} tokens revoked", idle.len());
+        }
+    }
+
+    /// Revoke all the access tokens, every user has to log in again
+    /// The token `keep` is not revoked when given
+    /// It returns the number of revoked tokens
+    pub async fn revoke_all_sessions(&self, keep: Option<&Token>) -> usize {
+        let revoked: Vec<AuthenticatedUserInfo> = self
+            .access_tokens
+            .read()
+            .await
+            .iter()
+            .filter(|(token, _)| Some(*token) != keep)
+            .map(|(token, info)| AuthenticatedUserInfo {
+                session_id: info.session_id,
+                user_id: info.user_id.clone(),
+                access_token: token.clone(),
+            })
+            .collect();
+        for user in revoked.iter() {
+            self.user_logout(user).await;
+        }
+        log::info!("revoke_all_sessions: {} tokens revoked", revoked.len());
+        revoked.len()
     }
 
     pub async fn check_maintenance(&self) {
@@ -139,6 +510,80 @@ impl ApiState {
         }
     }
 
+    /// Announce a maintenance to the clients, None to cancel it
+    /// It returns None if the maintenance has already started
+    pub async fn set_maintenance_notice(&self, notice: Option<MaintenanceNotice>) -> Option<()> {
+        if notice.as_ref().is_some_and(|notice| notice.at <= secs_from_epoch()) {
+            return None;
+        }
+        *self.maintenance_notice.write().await = notice;
+        Some(())
+    }
+
+    /// Get the announced maintenance, None if there is none or if it has started
+    pub async fn get_maintenance_notice(&self) -> Option<MaintenanceNotice> {
+        self.maintenance_notice
+            .read()
+            .await
+            .clone()
+            .filter(|notice| notice.at > secs_from_epoch())
+    }
+
+    /// Compute the directives sent back to a client in the heartbeat answer
+    ///
+    /// # Arguments
+    ///
+    /// * `peer_id` - id of the peer
+    /// * `modified_at` - version of the configuration applied by the client,
+    ///   None when the client does not take the `config_changed` directives
+    /// * `token_rejected` - true if the client sent a token which is no longer valid
+    pub async fn heartbeat_directives(
+        &self,
+        peer_id: &str,
+        modified_at: Option<u64>,
+        token_rejected: bool,
+    ) -> Vec<HeartbeatDirective> {
+        let mut directives = Vec::new();
+        if token_rejected {
+            directives.push(HeartbeatDirective::Relogin);
+        }
+        if let Some(modified_at) = modified_at {
+            if let Some(directive) = self.config_directive(peer_id, modified_at).await {
+                directives.push(directive);
+            }
+        }
+        if let Some(notice) = self.get_maintenance_notice().await {
+            directives.push(HeartbeatDirective::MaintenanceSoon {
+                at: notice.at,
+                message: notice.message,
+            });
+        }
+        directives
+    }
+
+    /// The `config_changed` directive of a peer, None when the client applies the current configuration
+    async fn config_directive(&self, peer_id: &str, modified_at: u64) -> Option<HeartbeatDirective> {
+        let (layers, assigned) = match self.get_strategy_layers(peer_id).await {
+            Some(layers) => layers,
+            None => (
+                self.db
+                    .get_default_strategy()
+                    .await
+                    .map(|strategy| vec![(StrategySource::Default, strategy)])
+                    .unwrap_or_default(),
+                0,
+            ),
+        };
+        let (_, strategy) = layers.last()?;
+        let settings = serde_json::to_value(merge_strategy_settings(&layers).settings).unwrap_or_default();
+        let version = config_version(&layers, assigned, &settings);
+        (version != modified_at).then(|| HeartbeatDirective::ConfigChanged {
+            version,
+            strategy: strategy.name.clone(),
+            settings,
+        })
+    }
+
     pub async fn user_login<'s>(
         &self,
         username: &String,
@@ -185,8 +630,8 @@ impl ApiState {
             utils::UserInfo {
                 name: username.to_string(),
                 email,
+                email_verified: db_user_info.email_verified,
                 admin: db_user_info.admin,
-                ..Default::default()
             },
             access_token,
         ))
@@ -194,6 +639,10 @@ impl ApiState {
 
     async fn get_access_token(&self, user_id: Vec<u8>, username: &String, is_admin: bool) -> Token {
         let access_token = Token::new_random();
+        if self.db.add_session(user_id.clone(), SESSION_TTL_SECS).await.is_some() {
+            let record = serde_json::json!({ "user": username, "admin": is_admin });
+            self.publish_audit_record(AuditCategory::Login.as_str(), record);
+        }
 
         let mut state_access_tokens = self.access_tokens.write().await;
         let mut state_sessions = self.sessions.write().await;
@@ -225,6 +674,7 @@ impl ApiState {
         let access_token_info = AccessTokenInfo {
             session_id,
             user_id,
+            last_used: secs_from_epoch(),
         };
 
         let _ = state_sessions.sessions.insert(session_id, session_info);
@@ -232,10 +682,29 @@ impl ApiState {
         access_token
     }
 
+    /// Find the session of a token and record its use
+    /// It returns None if the token is unknown or has been idle for longer than the idle timeout,
+    /// the idle tokens are removed by the maintenance
     pub async fn find_session(&self, access_token: &Token) -> Option<AccessTokenInfo> {
+        let now = secs_from_epoch();
         let state_access_tokens = self.access_tokens.read().await;
-
-        state_access_tokens.get(access_token).map(|t| t.clone())
+        let info = state_access_tokens.get(access_token)?.clone();
+        drop(state_access_tokens);
+        // without idle timeout the last use is not needed
+        let Some(idle_timeout) = self.idle_timeout else {
+            return Some(info);
+        };
+        let idle = now.saturating_sub(info.last_used);
+        if idle > idle_timeout {
+            return None;
+        }
+        // the last use is only written when it is stale, most requests only take the read lock
+        if idle >= TOKEN_LAST_USED_REFRESH_SECS.min(idle_timeout / 2) {
+            if let Some(info) = self.access_tokens.write().await.get_mut(access_token) {
+                info.last_used = now;
+            }
+        }
+        Some(info)
     }
 
     pub async fn get_user_address_book(&self, user_id: UserId) -> Option<AddressBook> {
@@ -264,6 +733,28 @@ impl ApiState {
         Some(ab)
     }
 
+    /// Get the address books of several users, the ones not in memory are read in one query
+    ///
+    /// # Returns
+    /// The address book of each user having one
+    pub async fn get_users_address_books(&self, user_ids: &[UserId]) -> Option<HashMap<UserId, AddressBook>> {
+        let mut address_books = HashMap::new();
+        let mut missing = Vec::new();
+        {
+            let state_address_books = self.address_books.read().await;
+            for user_id in user_ids {
+                match state_address_books.get(user_id) {
+                    Some(abi) => {
+                        address_books.insert(user_id.clone(), abi.address_book.clone());
+                    }
+                    None => missing.push(user_id.clone()),
+                }
+            }
+        }
+        address_books.extend(self.db.get_legacy_address_books(&missing).await?);
+        Some(address_books)
+    }
+
     pub async fn set_user_address_book(
         &self,
         user_id: UserId,
@@ -397,11 +888,294 @@ impl ApiState {
 
     pub async fn update_systeminfo(&self, systeminfo: utils::SystemInfo) -> Option<()> {
         // must be written in the database immediately because peer is mainly used by hbbs
-        self.db.update_systeminfo(systeminfo).await
+        let events = self.db.update_systeminfo(systeminfo).await?;
+        self.publish_peer_events(events);
+        Some(())
+    }
+
+    /// Check that the uuid reported by a client is the one registered for its id
+    /// Returns false, and records the conflict, if the id is already claimed by another machine
+    pub async fn check_peer_uuid(&self, id: &str, uuid: &str) -> bool {
+        self.db.check_peer_uuid(id, uuid).await.unwrap_or(true)
+    }
+
+    /// Get the recorded uuid conflicts
+    pub async fn get_peer_uuid_conflicts(&self) -> Option<Vec<PeerUuidConflict>> {
+        self.db.get_peer_uuid_conflicts().await
+    }
+
+    /// Forget the uuid conflicts of a peer
+    pub async fn delete_peer_uuid_conflicts(&self, id: &str) -> Option<u64> {
+        self.db.delete_peer_uuid_conflicts(id).await
+    }
+
+    /// Store the image of a peer
+    /// Returns Some(false) if the peer does not exist
+    pub async fn set_peer_avatar(&self, id: &str, content_type: &str, data: &[u8]) -> Option<bool> {
+        self.db.set_peer_avatar(id, content_type, data).await
+    }
+
+    /// Get the image of a peer
+    pub async fn get_peer_avatar(&self, id: &str) -> Option<PeerAvatar> {
+        self.db.get_peer_avatar(id).await
+    }
+
+    /// Store an audit record sent by a client
+    /// The insert runs on its own task so the client gets its answer without waiting for the database
+    pub fn record_client_audit(&self, event: AuditEvent, info: String) {
+        let db = self.db.clone();
+        let audit_records = self.audit_records.clone();
+        tokio::spawn(async move {
+            if db.add_client_audit(&event, &info).await.is_some() {
+                let record = serde_json::to_value(&event).unwrap_or_default();
+                let _ = audit_records.send(ForwardedAudit::new(event.category().as_str(), record));
+            }
+        });
+    }
+
+    /// Get a page of the connections recorded by the peers and the number of matching connections
+    pub async fn get_audit_conns(&self, filter: &AuditFilter) -> Option<(u32, Vec<AuditConn>)> {
+        self.db.get_audit_conns(filter).await
+    }
+
+    /// Get a page of the file transfers recorded by the peers and the number of matching transfers
+    pub async fn get_audit_files(&self, filter: &AuditFilter) -> Option<(u32, Vec<AuditFile>)> {
+        self.db.get_audit_files(filter).await
+    }
+
+    /// Get a page of the alarms raised by the peers and the number of matching alarms
+    pub async fn get_audit_alarms(&self, filter: &AuditFilter) -> Option<(u32, Vec<AuditAlarm>)> {
+        self.db.get_audit_alarms(filter).await
+    }
+
+    /// Record an operation of an admin and forward it to the audit sinks
+    /// The sensitive values of `details` are redacted before being stored
+    ///
+    /// # Arguments
+    /// * `actor` - the admin
+    /// * `target` - identifier of the user, group, peer... the operation applies to
+    pub async fn audit_admin_action(
+        &self,
+        actor: &UserId,
+        action: AdminAction,
+        target: Option<&str>,
+        details: serde_json::Value,
+    ) -> Option<()> {
+        let details = redact_audit_details(details);
+        self.db
+            .add_admin_audit(
+                actor.clone(),
+                action.as_str(),
+                action.route(),
+                target,
+                &details.to_string(),
+            )
+            .await?;
+        let record = serde_json::json!({
+            "actor": guid_into_uuid(actor.clone()),
+            "action": action.as_str(),
+            "route": action.route(),
+            "target": target,
+            "details": details,
+        });
+        self.publish_audit_record(AUDIT_FORWARD_ADMIN, record);
+        Some(())
+    }
+
+    /// Get a page of the operations of the admins and the number of matching operations
+    pub async fn get_admin_audits(&self, filter: &AuditFilter) -> Option<(u32, Vec<AuditAdmin>)> {
+        self.db.get_admin_audits(filter).await
+    }
+
+    /// Count the audit records of a category matching the filters
+    pub async fn count_audit(&self, category: AuditCategory, filter: &AuditFilter) -> Option<u32> {
+        self.db.count_audit(category, filter).await
+    }
+
+    /// Stream the audit records of a category matching the filters, the most recent first
+    pub fn export_audit(
+        &self,
+        category: AuditCategory,
+        filter: AuditFilter,
+    ) -> impl Stream<Item = AuditRecord> + Send + '_ {
+        self.db.export_audit(category, filter)
+    }
+
+    /// Get the number of days the audit records of each category are kept
+    pub async fn get_audit_retention(&self) -> AuditRetention {
+        read_audit_retention(&self.db).await
+    }
+
+    /// Change the number of days the audit records of each category are kept
+    /// The values are stored in the settings table and used by the next purge
+    pub async fn set_audit_retention(&self, retention: &AuditRetention) -> Option<()> {
+        let value = serde_json::to_string(retention).ok()?;
+        self.db.set_setting(AUDIT_RETENTION_SETTING, &value).await
+    }
+
+    /// Get the client versions set by an admin, the fields left empty fall back to the Rocket configuration
+    pub async fn get_client_versions(&self) -> ClientVersions {
+        self.db
+            .get_setting(CLIENT_VERSIONS_SETTING)
+            .await
+            .and_then(|value| serde_json::from_str::<ClientVersions>(&value).ok())
+            .unwrap_or_default()
+    }
+
+    /// Store the client versions, they must be validated
+    pub async fn set_client_versions(&self, versions: &ClientVersions) -> Option<()> {
+        let value = serde_json::to_string(versions).ok()?;
+        self.db.set_setting(CLIENT_VERSIONS_SETTING, &value).await
+    }
+
+    /// Get the UI settings stored by a user, without the defaults
+    pub async fn get_user_ui_settings(&self, user_id: UserId) -> Option<serde_json::Value> {
+        self.db
+            .get_user_data(user_id, UI_SETTINGS_USER_DATA)
+            .await
+            .and_then(|value| serde_json::from_str(&value).ok())
+    }
+
+    /// Store the UI settings of a user, their size must be checked
+    /// Returns None if the user does not exist
+    pub async fn set_user_ui_settings(&self, user_id: UserId, settings: &serde_json::Value) -> Option<()> {
+        let value = serde_json::to_string(settings).ok()?;
+        self.db.set_user_data(user_id, UI_SETTINGS_USER_DATA, &value).await
+    }
+
+    /// Get the settings synced by the RustDesk clients of a user
+    pub async fn get_user_client_settings(&self, user_id: UserId) -> Option<serde_json::Value> {
+        self.db
+            .get_user_data(user_id, CLIENT_SETTINGS_USER_DATA)
+            .await
+            .and_then(|value| serde_json::from_str(&value).ok())
+    }
+
+    /// Store the settings synced by the RustDesk clients of a user, their size must be checked
+    /// Returns None if the user does not exist
+    pub async fn set_user_client_settings(&self, user_id: UserId, settings: &serde_json::Value) -> Option<()> {
+        let value = serde_json::to_string(settings).ok()?;
+        self.db.set_user_data(user_id, CLIENT_SETTINGS_USER_DATA, &value).await
+    }
+
+    /// Get the default UI settings set by an admin
+    pub async fn get_ui_defaults(&self) -> Option<serde_json::Value> {
+        self.db
+            .get_setting(UI_DEFAULTS_SETTING)
+            .await
+            .and_then(|value| serde_json::from_str(&value).ok())
+    }
+
+    /// Store the default UI settings, their size must be checked
+    pub async fn set_ui_defaults(&self, defaults: &serde_json::Value) -> Option<()> {
+        let value = serde_json::to_string(defaults).ok()?;
+        self.db.set_setting(UI_DEFAULTS_SETTING, &value).await
+    }
+
+    /// Get the UI settings of a user merged over the defaults
+    pub async fn get_ui_settings(&self, user_id: UserId) -> serde_json::Value {
+        merge_ui_settings(self.get_ui_defaults().await, self.get_user_ui_settings(user_id).await)
+    }
+
+    /// Record a release uploaded to the bucket, the download links of its platform in its channel point to it from now on
+    pub async fn add_software_release(
+        &self,
+        platform: &str,
+        channel: ReleaseChannel,
+        version: &str,
+        key: &str,
+        size: u64,
+        sha256: &str,
+        uploaded_by: UserId,
+    ) -> Option<()> {
+        self.db
+            .add_software_release(platform, channel, version, key, size, sha256, uploaded_by)
+            .await
+    }
+
+    /// Get the latest uploaded release of each platform in a channel
+    pub async fn get_latest_software_releases(&self, channel: ReleaseChannel) -> Vec<SoftwareRelease> {
+        self.db.get_latest_software_releases(channel).await.unwrap_or_default()
+    }
+
+    /// Assign a release channel to a user, None to remove the assignment
+    /// It returns None if the user does not exist
+    pub async fn assign_user_release_channel(&self, user: &str, channel: Option<ReleaseChannel>) -> Option<()> {
+        self.db.assign_user_release_channel(user, channel).await
+    }
+
+    /// Assign a release channel to a group, None to remove the assignment
+    /// It returns None if the group does not exist
+    pub async fn assign_group_release_channel(&self, group: &str, channel: Option<ReleaseChannel>) -> Option<()> {
+        self.db.assign_group_release_channel(group, channel).await
+    }
+
+    /// Get the release channel of a user: its own one, else the one of its group, else the stable channel
+    pub async fn get_user_release_channel(&self, user_id: &UserId) -> ReleaseChannel {
+        self.db
+            .get_user_release_channel(user_id.clone())
+            .await
+            .flatten()
+            .and_then(|channel| channel.parse().ok())
+            .unwrap_or_default()
+    }
+
+    /// Start the background task purging the expired audit records every `AUDIT_PURGE_INTERVAL_SECS`
+    pub fn spawn_audit_purge(&self) {
+        let db = self.db.clone();
+        tokio::spawn(async move {
+            let mut clock = PurgeClock::default();
+            let mut ticker = tokio::time::interval(Duration::from_secs(AUDIT_PURGE_INTERVAL_SECS));
+            loop {
+                ticker.tick().await;
+                let now = clock.now(Instant::now(), Utc::now());
+                purge_audits(&db, now).await;
+            }
+        });
+    }
+
+    pub async fn update_heartbeat(
+        &self,
+        heartbeat: utils::HeartbeatRequest,
+        user_id: Option<UserId>,
+    ) -> Option<()> {
+        let events = self.db.update_heartbeat(heartbeat, user_id).await?;
+        self.publish_peer_events(events);
+        Some(())
+    }
+
+    /// Start the background task recording the offline transitions of the peers which stopped beating
+    /// every `PEER_OFFLINE_AFTER_SECS`, and purging the old status history every `PEER_STATUS_HISTORY_PURGE_INTERVAL_SECS`
+    /// The retention is set by the `PEER_STATUS_HISTORY_RETENTION_DAYS` environment variable (90 days by default)
+    pub fn spawn_peer_status_maintenance(&self) {
+        let db = self.db.clone();
+        let peer_events = self.peer_events.clone();
+        let retention_days = env::var("PEER_STATUS_HISTORY_RETENTION_DAYS")
+            .ok()
+            .and_then(|days| days.parse::<u32>().ok())
+            .unwrap_or(90);
+        tokio::spawn(async move {
+            let mut sweep = tokio::time::interval(Duration::from_secs(PEER_OFFLINE_AFTER_SECS as u64));
+            let mut purge =
+                tokio::time::interval(Duration::from_secs(PEER_STATUS_HISTORY_PURGE_INTERVAL_SECS));
+            loop {
+                tokio::select! {
+                    _ = sweep.tick() => sweep_offline_peers(&db, &peer_events).await,
+                    _ = purge.tick() => {
+                        let purged = db
+                            .purge_peer_status_history(retention_days, PEER_STATUS_HISTORY_PURGE_BATCH_SIZE)
+                            .await;
+                        if let Some(purged) = purged.filter(|purged| *purged > 0) {
+                            log::info!("purge_peer_status_history: {} transitions deleted", purged);
+                        }
+                    }
+                }
+            }
+        });
     }
 
-    pub async fn update_heartbeat(&self, heartbeat: utils::HeartbeatRequest) -> Option<()> {
-        self.db.update_heartbeat(heartbeat).await
+    async fn sweep_offline_peers(&self) {
+        sweep_offline_peers(&self.db, &self.peer_events).await;
     }
 
     pub async fn get_oauth2_config(&self, config_file: &str) -> Option<Vec<ProviderConfig>> {
@@ -420,6 +1194,65 @@ impl ApiState {
         Some(oauth2_providers.clone())
     }
 
+    /// Read the providers config file again and replace the cached providers
+    /// The cached providers are kept if the file cannot be read or parsed
+    ///
+    /// # Returns
+    /// The number of providers loaded, or the reason why the file was rejected
+    pub async fn reload_oauth2_config(&self, config_file: &str) -> Result<usize, String> {
+        let providers = oauth2::read_providers_config_file(config_file)?;
+        let mut oauth2_providers = self.oauth2_providers.write().await;
+        *oauth2_providers = providers;
+        Ok(oauth2_providers.len())
+    }
+
+    /// The OAuth providers the user has logged in with
+    /// A provider is enabled if it is in the providers config file, it is read without being created when not loaded yet
+    ///
+    /// # Returns
+    /// Whether the user can log in with a password and the providers, None if the user does not exist
+    pub async fn get_linked_providers(&self, user_id: &UserId, config_file: &str) -> Option<(bool, Vec<LinkedProvider>)> {
+        let (password_login, mut providers) = self.db.get_linked_providers(user_id).await?;
+        let mut configured = self.oauth2_providers.read().await.clone();
+        if configured.is_empty() {
+            configured = oauth2::read_providers_config_file(config_file).unwrap_or_default();
+        }
+        for provider in providers.iter_mut() {
+            provider.enabled = configured.iter().any(|config| config.op == provider.op);
+        }
+        Some((password_login, providers))
+    }
+
+    /// Unlink the OAuth provider `op` from the user, unless it is their last login method
+    pub async fn unlink_provider(&self, user_id: &UserId, op: &str) -> Option<Result<(), UnlinkProviderError>> {
+        self.db.unlink_third_auth(user_id, op).await
+    }
+
+    /// The email of the user and whether it is verified, None if the user does not exist
+    pub async fn get_user_email(&self, user_id: &UserId) -> Option<(Option<String>, bool)> {
+        self.db.get_user_email(user_id).await
+    }
+
+    /// Issue the token of a link verifying the email of the user, valid for `ttl`
+    /// It replaces the previous token of the user
+    ///
+    /// # Returns
+    /// The token, the email to send it to and its expiration, Err if the user has no email or it is already verified
+    pub async fn issue_email_verification(
+        &self,
+        user_id: &UserId,
+        ttl: Duration,
+    ) -> Option<Result<(String, String, String), EmailVerificationError>> {
+        let token = Token::new_random().to_base64();
+        let res = self.db.create_email_verification(user_id, &token, ttl).await?;
+        Some(res.map(|(email, expires_at)| (token, email, expires_at)))
+    }
+
+    /// Verify the email the token was sent to
+    pub async fn verify_email(&self, token: &str) -> Option<Result<String, EmailVerificationError>> {
+        self.db.verify_email(token).await
+    }
+
     pub async fn insert_oidc_session(
         &self,
         uuid_code: String,
@@ -438,8 +1271,33 @@ impl ApiState {
         oidc_sessions.get(&uuid_code).map(|s| s.clone())
     }
 
-    /// Exchange code for tokens
-    ///
+    /// List the OIDC sessions in flight, the oldest first
+    /// Only the first characters of the session codes are given and the tokens are never given
+    pub async fn get_oidc_sessions(&self) -> Vec<OidcSession> {
+        let oidc_sessions = self.oidc_sessions.read().await;
+        let mut sessions = oidc_sessions
+            .iter()
+            .map(|(uuid_code, oidc_session)| OidcSession {
+                code: uuid_code.chars().take(OIDC_CODE_PREFIX_LEN).collect(),
+                provider: oidc_session
+                    .provider
+                    .as_ref()
+                    .map(|provider| format!("{:?}", provider.get_provider_type()))
+                    .unwrap_or_default(),
+                age: oidc_session.created_at.elapsed().as_secs(),
+                state: if oidc_session.auth_token.is_some() {
+                    OidcSessionStatus::Exchanged
+                } else {
+                    OidcSessionStatus::Pending
+                },
+            })
+            .collect::<Vec<_>>();
+        sessions.sort_by(|a, b| b.age.cmp(&a.age));
+        sessions
+    }
+
+    /// Exchange code for tokens
+    ///
     /// This function exchanges a code obtained from an oauth2 provider
     /// for an access token.
     /// If the oauth2 provider supports it, also an id token is exchanged.
@@ -486,7 +1344,7 @@ impl ApiState {
                     oidc_session.id.clone()
                 });
                 oidc_session.email = Some(access_token.email.clone());
-                log::debug!("oidc_session_exchange_code {:?}", oidc_session.auth_token);
+                log::debug!("oidc_session_exchange_code: {} logged in", oidc_session.id);
                 return Some(access_token.access_token);
             }
         }
@@ -530,7 +1388,7 @@ impl ApiState {
         if oidc_session.auth_token.is_some() {
             let res = self
                 .db
-                .get_user_for_oauth2(name, email, oidc_session.uuid.clone())
+                .get_user_for_oauth2(name.clone(), email.clone(), oidc_session.uuid.clone())
                 .await;
             if res.is_none() {
                 log::debug!("oidc_check_session user not found");
@@ -541,6 +1399,11 @@ impl ApiState {
                 log::debug!("oidc_check_session user not active");
                 return None;
             }
+            if !oidc_session.op.is_empty() {
+                self.db
+                    .link_third_auth(&uuid_vec, &oidc_session.op, &name, &email)
+                    .await;
+            }
             let token = self
                 .get_access_token(uuid_vec, &username, db_user_info.admin)
                 .await;
@@ -561,11 +1424,58 @@ impl ApiState {
         self.db.add_peer_to_ab(ab, ab_peer).await
     }
 
+    /// Update a peer of an address book and bump its version
+    /// With an expected version the peer is only updated if its version is still this one
+    ///
+    /// # Returns
+    /// Option<bool> - false if the peer was changed or removed meanwhile
+    pub async fn update_ab_peer(&self, ab: &str, ab_peer: AbPeer, expected_version: Option<u64>) -> Option<bool> {
+        self.db.update_ab_peer(ab, ab_peer, expected_version).await
+    }
+
     /// Get all peers from an address book
     pub async fn get_ab_peers(&self, ab: &str) -> Option<Vec<AbPeer>> {
         self.db.get_peers_from_ab(ab).await
     }
 
+    /// Find another peer of the address book using the alias of the peer `id`, when `AB_UNIQUE_ALIASES` is enabled
+    /// The aliases are compared without the surrounding spaces and the case
+    ///
+    /// # Returns
+    /// The id of the peer already using the alias
+    pub async fn find_ab_alias_conflict(&self, ab: &str, id: &str, alias: Option<&str>) -> Option<String> {
+        if !self.unique_aliases {
+            return None;
+        }
+        let alias = alias.map(str::trim).filter(|alias| !alias.is_empty())?.to_lowercase();
+        self.get_ab_peers(ab)
+            .await?
+            .into_iter()
+            .find(|peer| {
+                peer.id != id
+                    && peer
+                        .alias
+                        .as_deref()
+                        .is_some_and(|other| other.trim().to_lowercase() == alias)
+            })
+            .map(|peer| peer.id)
+    }
+
+    /// Get the page `current` (from 1) of the peers of an address book and the number of peers of the address book
+    pub async fn get_ab_peers_offset(&self, ab: &str, current: u32, page_size: u32) -> Option<(Vec<AbPeer>, u32)> {
+        self.db.get_peers_offset_from_ab(ab, current, page_size).await
+    }
+
+    /// Get a page of the peers of an address book, starting after `after`, and the cursor of the next page
+    pub async fn get_ab_peers_page(
+        &self,
+        ab: &str,
+        after: Option<&PageCursor>,
+        page_size: u32,
+    ) -> Option<(Vec<AbPeer>, Option<PageCursor>)> {
+        self.db.get_peers_page_from_ab(ab, after, page_size).await
+    }
+
     /// Delete a peer in an address book
     pub async fn delete_ab_peer(&self, ab: &str, peers_to_delete: Vec<String>) -> Option<()> {
         for peer in peers_to_delete {
@@ -589,6 +1499,11 @@ impl ApiState {
         self.db.get_ab_tags(ab).await
     }
 
+    /// Get the tags of an address book with their usage count
+    pub async fn get_ab_tags_stats(&self, ab: &str) -> Option<Vec<AbTagStats>> {
+        self.db.get_ab_tags_stats(ab).await
+    }
+
     /// Get a tag from an address book
     pub async fn get_ab_tag(&self, ab: &str, tag: &str) -> Option<AbTag> {
         let ab_tag = self.db.get_ab_tag(ab, tag).await;
@@ -604,17 +1519,38 @@ impl ApiState {
         self.db.rename_ab_tag(ab, old_name, tag).await
     }
 
-    /// Delete some tags from an address book
+    /// Add and remove tags on several peers of an address book in one transaction
+    /// Returns the number of peers modified, None if a tag does not exist in the address book
+    pub async fn update_ab_peers_tags(
+        &self,
+        ab: &str,
+        peer_ids: &[String],
+        add_tags: &[String],
+        remove_tags: &[String],
+    ) -> Option<u32> {
+        self.db
+            .update_ab_peers_tags(ab, peer_ids, add_tags, remove_tags)
+            .await
+    }
+
+    /// Merge duplicates of a peer of an address book into it and delete them
+    /// It returns the kept peer, or None if one of the peers is not in the address book
+    pub async fn merge_ab_peers(&self, ab: &str, keep_id: &str, merge_ids: &[String]) -> Option<AbPeer> {
+        self.db.merge_ab_peers(ab, keep_id, merge_ids).await
+    }
+
+    /// Delete some tags from an address book and remove them from its peers
     pub async fn delete_ab_tags(&self, ab: &str, tags_to_delete: Vec<String>) -> Option<()> {
-        for tag in tags_to_delete {
-            self.db.delete_tag_from_ab(ab, tag.as_str()).await;
-        }
-        Some(())
+        self.db.delete_ab_tags(ab, &tags_to_delete).await
     }
 
     /// Add a user
     /// This function is used to add a user to the database
-    pub async fn add_user(&self, user_parameters: AddUserRequest) -> Option<()> {
+    /// Err gives the field already used by another user, the name or the email
+    pub async fn add_user(
+        &self,
+        user_parameters: AddUserRequest,
+    ) -> Option<Result<(), UserField>> {
         self.db
             .add_user(
                 user_parameters.name,
@@ -627,33 +1563,157 @@ impl ApiState {
     }
 
     /// Change user status
-    pub async fn user_change_status(&self, user: &str, disable: bool) -> Option<()> {
-        self.db.user_change_status(user, disable as u32).await
+    pub async fn user_change_status(&self, user: &str, status: UserStatus) -> Option<()> {
+        self.db.user_change_status(user, status).await
+    }
+
+    /// Check whether `user_change_status` with the same arguments changes the status of a user
+    /// It returns false if the user does not exist or already has the status
+    pub async fn user_status_changes(&self, user: &str, status: UserStatus) -> Option<bool> {
+        self.db.user_status_changes(user, status).await
     }
 
-    /// Get all users
+    /// Get all users, only those with `status` when given
     pub async fn get_all_users(
         &self,
         name: Option<&str>,
         email: Option<&str>,
+        status: Option<UserStatus>,
         current: u32,
         page_size: u32,
     ) -> Option<Vec<UserListResponse>> {
-        self.db.get_all_users(name, email, current, page_size).await
+        self.db.get_all_users(name, email, status, current, page_size).await
+    }
+
+    /// Get a page of users, starting after `after`, and the cursor of the next page
+    pub async fn get_users_page(
+        &self,
+        name: Option<&str>,
+        email: Option<&str>,
+        after: Option<&PageCursor>,
+        page_size: u32,
+    ) -> Option<(Vec<UserListResponse>, Option<PageCursor>)> {
+        self.db.get_users_page(name, email, after, page_size).await
     }
 
     /// Update a user
+    /// Err gives the field already used by another user, the name or the email
     pub async fn user_update(
         &self,
         user_id: UserId,
         user_parameters: UpdateUserRequest,
-    ) -> Option<()> {
+    ) -> Option<Result<(), UserField>> {
         self.db.user_update(user_id, user_parameters).await
     }
 
     /// Get all peers
-    pub async fn get_all_peers(&self) -> Option<Vec<Peer>> {
-        self.db.get_all_peers().await
+    /// If `user_id` is provided, only the peers assigned to this user are returned
+    pub async fn get_peer(&self, id: &str) -> Option<Peer> {
+        self.db.get_peer(id).await
+    }
+
+    pub async fn get_all_peers(&self, user_id: Option<UserId>) -> Option<Vec<Peer>> {
+        self.db.get_all_peers(user_id).await
+    }
+
+    /// Assign a peer to a user, or remove its assignment if `user_id` is None
+    pub async fn assign_peer_to_user(
+        &self,
+        id: &str,
+        user_id: Option<UserId>,
+        operator: UserId,
+    ) -> Option<()> {
+        self.db.assign_peer_to_user(id, user_id, operator).await
+    }
+
+    /// Transfer a peer to a new owner, e.g. when an employee leaves
+    /// The peer is assigned to the user and moved from the personal address book of its previous owner,
+    /// even disabled, to the one of the new owner with its alias, tags and password
+    /// With `dry_run` the transfer is computed but nothing is changed
+    ///
+    /// # Returns
+    /// The transfer, the reason why it was refused, or None on a database error
+    pub async fn transfer_peer(
+        &self,
+        id: &str,
+        user_id: UserId,
+        operator: UserId,
+        dry_run: bool,
+    ) -> Option<Result<PeerTransferResponse, PeerTransferError>> {
+        let Some(peer) = self.get_peer(id).await else {
+            return Some(Err(PeerTransferError::PeerNotFound));
+        };
+        let Some(ab) = self.get_ab_personal_guid(user_id.clone()).await else {
+            return Some(Err(PeerTransferError::UserNotFound));
+        };
+        let previous_ab = match peer.user.as_deref().and_then(utils::uuid_into_guid) {
+            Some(previous_user_id) => self.db.get_owner_ab_personal_guid(previous_user_id).await,
+            None => None,
+        };
+        let previous_entry = match previous_ab.as_deref() {
+            Some(previous_ab) => self
+                .get_ab_peers(previous_ab)
+                .await?
+                .into_iter()
+                .find(|ab_peer| ab_peer.id == peer.id),
+            None => None,
+        };
+        let transfer = PeerTransferResponse {
+            id: peer.id.clone(),
+            previous_user: peer.user.clone(),
+            user: guid_into_uuid(user_id.clone())?,
+            ab: ab.clone(),
+        };
+        if dry_run {
+            return Some(Ok(transfer));
+        }
+        let ab_peer = previous_entry.unwrap_or_else(|| AbPeer {
+            id: peer.id.clone(),
+            hostname: peer.info.hostname.clone(),
+            username: peer.info.username.clone(),
+            ..Default::default()
+        });
+        self.db
+            .assign_peer_to_user(&peer.id, Some(user_id.clone()), operator)
+            .await?;
+        self.add_ab_peer(&ab, ab_peer).await?;
+        if let Some(previous_ab) = previous_ab.filter(|previous_ab| *previous_ab != ab) {
+            self.db.delete_peer_from_ab(&previous_ab, &peer.id).await?;
+        }
+        Some(Ok(transfer))
+    }
+
+    /// Apply an action to several peers in a single transaction
+    /// With `dry_run` the results are computed but nothing is changed
+    pub async fn bulk_peers(
+        &self,
+        action: PeersBulkAction,
+        ids: Vec<String>,
+        group: Option<Vec<u8>>,
+        operator: UserId,
+        dry_run: bool,
+    ) -> Option<Vec<PeersBulkResult>> {
+        self.db.bulk_peers(action, ids, group, operator, dry_run).await
+    }
+
+    /// Stream the peers for the export, a read error ends the stream
+    pub fn export_peers(
+        &self,
+        user_id: Option<UserId>,
+    ) -> impl Stream<Item = Result<PeerExport, String>> + Send + '_ {
+        self.db.export_peers(user_id)
+    }
+
+    /// Get the status history of a peer in the [from, to] window
+    /// The offline peers are swept first so the current state is accurate
+    pub async fn get_peer_status_history(
+        &self,
+        id: &str,
+        from: &str,
+        to: &str,
+    ) -> Option<(bool, Vec<(String, bool)>)> {
+        self.sweep_offline_peers().await;
+        self.db.get_peer_status_history(id, from, to).await
     }
 
     /// Get groups
@@ -666,6 +1726,50 @@ impl ApiState {
         self.db.get_shared_address_books(user_id).await
     }
 
+    /// Get the rule of a user on an address book (0: none, 1: read, 2: write, 3: full control)
+    pub async fn get_ab_rule_for_user(&self, ab: &str, user_id: UserId) -> u32 {
+        self.db.get_ab_rule_for_user(ab, user_id).await.unwrap_or(0)
+    }
+
+    /// Get the permissions of a user computed from its role, its group and the address book rules
+    /// It returns None if the user does not exist
+    pub async fn get_user_permissions(&self, user_id: &UserId) -> Option<UserPermissions> {
+        self.db.get_user_permissions(user_id).await
+    }
+
+    /// Create a share link of a peer, expiring at `expires_at` seconds since the epoch
+    ///
+    /// # Returns
+    /// The guid of the link and its expiration
+    pub async fn create_share_link(&self, peer_id: &str, created_by: &UserId, expires_at: i64) -> Option<(String, String)> {
+        self.db.create_share_link(peer_id, created_by, expires_at).await
+    }
+
+    /// Grant the user the access to the peer of a share link until the link expires
+    pub async fn redeem_share_link(
+        &self,
+        link: &str,
+        peer_id: &str,
+        user_id: &UserId,
+    ) -> Option<Result<String, ShareLinkError>> {
+        self.db.redeem_share_link(link, peer_id, user_id).await
+    }
+
+    /// Get the peers shared with a user by the share links it redeemed, until the links expire
+    pub async fn get_share_link_peers(&self, user_id: &UserId) -> Option<Vec<AbPeer>> {
+        self.db.get_share_link_peers(user_id).await
+    }
+
+    /// Get the users and the groups allowed to control a peer
+    pub async fn get_peer_access(&self, id: &str) -> Option<PeerAccess> {
+        self.db.get_peer_access(id).await
+    }
+
+    /// Check that a user has at least the `rule` access level on an address book
+    pub async fn check_ab_rule(&self, ab: &str, user_id: UserId, rule: u32) -> bool {
+        self.get_ab_rule_for_user(ab, user_id).await >= rule
+    }
+
     pub async fn get_ab_rules(&self, offset: u32, page_size: u32, ab: &str) -> Option<Vec<AbRule>> {
         self.db.get_ab_rules(offset, page_size, ab).await
     }
@@ -674,6 +1778,11 @@ impl ApiState {
         self.db.delete_ab_rule(rule).await
     }
 
+    /// Check whether a rule of an address book exists
+    pub async fn ab_rule_exists(&self, rule: &str) -> Option<bool> {
+        self.db.ab_rule_exists(rule).await
+    }
+
     pub async fn add_ab_rule(&self, rule: AbRule) -> Option<()> {
         self.db.add_ab_rule(rule).await
     }
@@ -682,12 +1791,35 @@ impl ApiState {
         self.db.get_peers_count(platform).await
     }
 
+    /// Get the statistics shown on the console dashboard
+    pub async fn get_statistics(&self) -> Option<StatisticsResponse> {
+        self.db.get_statistics().await
+    }
+
+    /// Get the peers grouped by online status, the summary is cached for `PEERS_SUMMARY_CACHE_SECS` seconds
+    pub async fn get_peers_summary(&self) -> Option<PeersSummary> {
+        if let Some((computed_at, summary)) = self.peers_summary.read().await.as_ref() {
+            if computed_at.elapsed() < Duration::from_secs(PEERS_SUMMARY_CACHE_SECS) {
+                return Some(summary.clone());
+            }
+        }
+        let summary = self.db.get_peers_summary().await?;
+        *self.peers_summary.write().await = Some((Instant::now(), summary.clone()));
+        Some(summary)
+    }
+
     pub async fn get_cpus_count(&self) -> Vec<CpuCount> {
         self.db.get_cpus_count().await
     }
 
-    pub async fn create_group(&self, name: &str, team: &str, note: &str) -> Option<()> {
-        self.db.create_group(name, team, note).await
+    pub async fn create_group(
+        &self,
+        name: &str,
+        team: &str,
+        note: &str,
+        strategy: Option<&str>,
+    ) -> Option<()> {
+        self.db.create_group(name, team, note, strategy).await
     }
 
     pub async fn update_group(&self, guid: &str, name: &str, team: &str, note: &str) -> Option<()> {
@@ -702,6 +1834,141 @@ impl ApiState {
         self.db.delete_group(guid).await
     }
 
+    /// Get a page of the strategies and the total number of strategies
+    ///
+    /// # Arguments
+    ///
+    /// * `q` - text the names contain, case-insensitively, None for all the strategies
+    /// * `order` - order of the strategies
+    /// * `offset` - number of strategies skipped
+    /// * `page_size` - maximum number of strategies returned
+    pub async fn get_strategies(
+        &self,
+        q: Option<&str>,
+        order: StrategyOrder,
+        offset: u32,
+        page_size: u32,
+    ) -> Option<(u32, Vec<Strategy>)> {
+        let total = self.db.count_strategies(q).await?;
+        let strategies = self.db.get_strategies(q, order, offset, page_size).await?;
+        Some((total, strategies))
+    }
+
+    pub async fn get_strategy(&self, guid: &str) -> Option<Strategy> {
+        self.db.get_strategy(guid).await
+    }
+
+    /// Create a strategy, its settings must have been validated
+    /// It returns the new strategy
+    pub async fn add_strategy(&self, name: &str, settings: &serde_json::Value, is_default: bool) -> Option<Strategy> {
+        let guid = self
+            .db
+            .add_strategy(name, settings.to_string().as_str(), is_default)
+            .await?;
+        self.db.get_strategy(guid.as_str()).await
+    }
+
+    /// Copy a strategy without its assignments nor its default flag
+    /// It returns the new strategy, None if the copied strategy does not exist
+    pub async fn duplicate_strategy(&self, guid: &str, name: Option<&str>) -> Option<Strategy> {
+        let guid = self.db.duplicate_strategy(guid, name).await?;
+        self.db.get_strategy(guid.as_str()).await
+    }
+
+    /// Update a strategy, its settings must have been validated
+    /// It returns the updated strategy
+    pub async fn update_strategy(
+        &self,
+        guid: &str,
+        name: Option<&str>,
+        settings: Option<&serde_json::Value>,
+        is_default: bool,
+    ) -> Option<Strategy> {
+        let settings = settings.map(|settings| settings.to_string());
+        self.db
+            .update_strategy(guid, name, settings.as_deref(), is_default)
+            .await?;
+        self.db.get_strategy(guid).await
+    }
+
+    /// Delete a strategy, None if it does not exist or is the default strategy
+    /// With `cascade`, its assignments are removed, otherwise they must have been checked
+    pub async fn delete_strategy(&self, guid: &str, cascade: bool) -> Option<()> {
+        self.db.delete_strategy(guid, cascade).await
+    }
+
+    /// Count the peers, the users and the groups a strategy is assigned to
+    pub async fn count_strategy_assignments(&self, guid: &str) -> Option<u32> {
+        self.db.count_strategy_assignments(guid).await
+    }
+
+    /// Assign a strategy to a peer, None to remove the assignment
+    /// It returns None if the peer does not exist
+    pub async fn assign_peer_strategy(&self, peer_id: &str, strategy: Option<&str>) -> Option<()> {
+        self.db.assign_peer_strategy(peer_id, strategy).await
+    }
+
+    /// Assign a strategy to a user, None to remove the assignment
+    /// It returns None if the user does not exist
+    pub async fn assign_user_strategy(&self, user: &str, strategy: Option<&str>) -> Option<()> {
+        self.db.assign_user_strategy(user, strategy).await
+    }
+
+    /// Assign a strategy to a group, None to remove the assignment
+    /// It returns None if the group does not exist
+    pub async fn assign_group_strategy(&self, group: &str, strategy: Option<&str>) -> Option<()> {
+        self.db.assign_group_strategy(group, strategy).await
+    }
+
+    /// Get the strategies applied to a peer, from the least to the most specific one:
+    /// the default strategy, the one of the group of its user, the one of the group of the device,
+    /// the one of its user and the one of the device
+    /// The version returned with them is the last change of the group assignments, which the
+    /// modification dates of the strategies do not reflect
+    /// It returns None if the peer does not exist
+    async fn get_strategy_layers(
+        &self,
+        peer_id: &str,
+    ) -> Option<(Vec<(StrategySource, Strategy)>, u64)> {
+        let (device, user) = self.db.get_peer_strategies(peer_id).await?;
+        let groups = self.db.get_peer_group_strategies(peer_id).await?;
+        let mut layers = Vec::new();
+        if let Some(strategy) = self.db.get_default_strategy().await {
+            layers.push((StrategySource::Default, strategy));
+        }
+        let mut version = 0;
+        let mut guids = Vec::new();
+        for (guid, modified_at) in groups {
+            version = version.max(datetime_version(&modified_at));
+            guids.push((StrategySource::Group, guid));
+        }
+        guids.push((StrategySource::User, user));
+        guids.push((StrategySource::Device, device));
+        for (source, guid) in guids {
+            if let Some(guid) = guid {
+                if let Some(strategy) = self.db.get_strategy(guid.as_str()).await {
+                    layers.push((source, strategy));
+                }
+            }
+        }
+        Some((layers, version))
+    }
+
+    /// Get the strategy applied to a peer: the one of the device, else the one of its user,
+    /// else the one of the group of the device, else the one of the group of its user, else the default one
+    /// It returns None if the peer does not exist
+    pub async fn get_effective_strategy(&self, peer_id: &str) -> Option<EffectiveStrategy> {
+        let (source, strategy) = self.get_strategy_layers(peer_id).await?.0.pop()?;
+        Some(EffectiveStrategy { source, strategy })
+    }
+
+    /// Get the settings applied to a peer, each option taken from the most specific strategy setting it
+    /// It returns None if the peer does not exist
+    pub async fn get_effective_settings(&self, peer_id: &str) -> Option<EffectiveSettings> {
+        let (layers, _) = self.get_strategy_layers(peer_id).await?;
+        Some(merge_strategy_settings(&layers))
+    }
+
     /// Add a shared address book given its name and its owner
     /// It returns the guid of the shared address book
     ///
@@ -722,6 +1989,11 @@ impl ApiState {
         self.db.delete_shared_address_book(guid).await
     }
 
+    /// Count the peers of a shared address book, None if it does not exist or is a personal one
+    pub async fn count_shared_address_book_peers(&self, guid: &str) -> Option<u32> {
+        self.db.count_shared_address_book_peers(guid).await
+    }
+
     pub async fn delete_shared_address_books(&self, shareds:Vec<String>) -> Option<()> {
         for shared in shareds {
             self.db.delete_shared_address_book(shared.as_str()).await;
@@ -732,3 +2004,1783 @@ impl ApiState {
         self.db.update_shared_address_book(guid, name).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::futures::StreamExt;
+    use sqlx::Connection;
+    use utils::{OsSummary, PeerEventType, PeersBulkStatus, AB_RULE_FULL, AB_RULE_READ, AB_RULE_WRITE};
+    use uuid::Uuid;
+
+    fn test_db_file() -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("sctgdesk-test-{}.sqlite3", Uuid::new_v4()))
+    }
+
+    async fn test_state() -> ApiState {
+        ApiState::new_with_db(test_db_file()).await
+    }
+
+    /// Register a peer as hbbs does, the api server never creates them
+    async fn add_test_peer(db_file: &Path, id: &str, user: Option<&UserId>) {
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO peer(guid, id, uuid, pk, user) VALUES (?, ?, ?, x'', ?)")
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .bind(id)
+            .bind(id.as_bytes().to_vec())
+            .bind(user)
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_schema_migrations() {
+        let db_file = test_db_file();
+        let latest = crate::migrations::MIGRATIONS.last().unwrap().version;
+        // dry run on a new database, everything is pending and nothing is applied
+        let pending = ApiState::pending_migrations(&db_file).await.unwrap();
+        assert_eq!(pending, crate::migrations::MIGRATIONS.to_vec());
+        assert_eq!(ApiState::pending_migrations(&db_file).await.unwrap().len(), pending.len());
+
+        let state = ApiState::new_with_db(&db_file).await;
+        assert!(state.db.find_user_by_name("admin").await.1.is_some());
+        assert!(ApiState::pending_migrations(&db_file).await.unwrap().is_empty());
+        drop(state);
+        // opening again applies nothing
+        ApiState::new_with_db(&db_file).await;
+
+        let mut conn = sqlx::SqliteConnection::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        assert_eq!(crate::migrations::schema_version(&mut conn).await.unwrap(), latest);
+
+        // a failing migration is rolled back with the migrations applied before it
+        let migrations = [
+            crate::migrations::MIGRATIONS.to_vec(),
+            vec![
+                crate::migrations::Migration { version: latest + 1, description: "table", sql: "CREATE TABLE t1 (id INTEGER)" },
+                crate::migrations::Migration { version: latest + 2, description: "broken", sql: "CREATE TABLE t1 (id INTEGER)" },
+            ],
+        ]
+        .concat();
+        assert!(crate::migrations::run_migrations(&mut conn, &migrations).await.is_err());
+        assert_eq!(crate::migrations::schema_version(&mut conn).await.unwrap(), latest);
+        let tables: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM sqlite_master WHERE name = 't1'")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(tables, 0);
+
+        // a database migrated by a newer server is refused
+        sqlx::query("INSERT INTO schema_version (version, description) VALUES (?, 'future')")
+            .bind(latest + 1)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        assert!(ApiState::pending_migrations(&db_file).await.is_err());
+        assert!(tokio::spawn(ApiState::new_with_db(db_file)).await.is_err());
+
+        // spellings of the same id in one address book do not break the startup
+        let db_file = test_db_file();
+        let mut conn = sqlx::SqliteConnection::connect(&format!("sqlite://{}?mode=rwc", db_file.display()))
+            .await
+            .unwrap();
+        let before = crate::migrations::MIGRATIONS.iter().position(|migration| migration.version == 5).unwrap();
+        crate::migrations::run_migrations(&mut conn, &crate::migrations::MIGRATIONS[..before])
+            .await
+            .unwrap();
+        let ab = Uuid::new_v4().as_bytes().to_vec();
+        for id in ["123 456 789", "123456 789", "12 3456789"] {
+            sqlx::query("INSERT INTO ab_peer (guid, ab, id, info) VALUES (?, ?, ?, json_object('id', ?3))")
+                .bind(Uuid::new_v4().as_bytes().to_vec())
+                .bind(&ab)
+                .bind(id)
+                .execute(&mut conn)
+                .await
+                .unwrap();
+        }
+        drop(conn);
+        let state = ApiState::new_with_db(&db_file).await;
+        assert!(ApiState::pending_migrations(&db_file).await.unwrap().is_empty());
+        drop(state);
+        let mut conn = sqlx::SqliteConnection::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        let ids: Vec<String> = sqlx::query_scalar("SELECT id FROM ab_peer ORDER BY guid")
+            .fetch_all(&mut conn)
+            .await
+            .unwrap();
+        // the peer with the lowest guid takes the canonical id, the others keep theirs
+        assert_eq!(ids[0], "123456789");
+        assert!(ids[1..].iter().all(|id| id.contains(' ')));
+    }
+
+    #[tokio::test]
+    async fn test_ab_peer_ids_migration() {
+        let db_file = test_db_file();
+        let mut conn = sqlx::SqliteConnection::connect(&format!("sqlite://{}?mode=rwc", db_file.display()))
+            .await
+            .unwrap();
+        let before = crate::migrations::MIGRATIONS.iter().position(|migration| migration.version == 5).unwrap();
+        crate::migrations::run_migrations(&mut conn, &crate::migrations::MIGRATIONS[..before])
+            .await
+            .unwrap();
+        let ab = Uuid::new_v4().as_bytes().to_vec();
+        for id in ["123 456 789", "987 654 321", "987654321", "10.0.0.5:21118"] {
+            sqlx::query("INSERT INTO ab_peer (guid, ab, id, info) VALUES (?, ?, ?, json_object('id', ?3))")
+                .bind(Uuid::new_v4().as_bytes().to_vec())
+                .bind(&ab)
+                .bind(id)
+                .execute(&mut conn)
+                .await
+                .unwrap();
+        }
+        crate::migrations::run_migrations(&mut conn, crate::migrations::MIGRATIONS)
+            .await
+            .unwrap();
+        let peers: Vec<(String, String)> =
+            sqlx::query_as("SELECT id, json_extract(info, '$.id') FROM ab_peer ORDER BY id")
+                .fetch_all(&mut conn)
+                .await
+                .unwrap();
+        let peers: Vec<(&str, &str)> = peers.iter().map(|(id, info)| (id.as_str(), info.as_str())).collect();
+        // a peer whose canonical id is already used keeps its id
+        assert_eq!(
+            peers,
+            vec![
+                ("10.0.0.5:21118", "10.0.0.5:21118"),
+                ("123456789", "123456789"),
+                ("987 654 321", "987 654 321"),
+                ("987654321", "987654321"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_personal_ab_migration() {
+        let db_file = test_db_file();
+        let mut conn = sqlx::SqliteConnection::connect(&format!("sqlite://{}?mode=rwc", db_file.display()))
+            .await
+            .unwrap();
+        let before = crate::migrations::MIGRATIONS.iter().position(|migration| migration.version == 7).unwrap();
+        crate::migrations::run_migrations(&mut conn, &crate::migrations::MIGRATIONS[..before])
+            .await
+            .unwrap();
+        // the users created while the personal address books had the same name have none
+        for name in ["alice", "bob"] {
+            sqlx::query(
+                "INSERT INTO user(guid, grp, team, status, role, name, password)
+                SELECT ?, grp, team, 1, 0, ?, password FROM user WHERE name = 'admin'",
+            )
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .bind(name)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        }
+        // a shared address book already has the name of the one of bob
+        sqlx::query(
+            "INSERT INTO ab(guid, name, owner, personal, info)
+            SELECT ?, 'bob''s Personal Address Book', guid, 0, '{}' FROM user WHERE name = 'admin'",
+        )
+        .bind(Uuid::new_v4().as_bytes().to_vec())
+        .execute(&mut conn)
+        .await
+        .unwrap();
+        crate::migrations::run_migrations(&mut conn, crate::migrations::MIGRATIONS)
+            .await
+            .unwrap();
+        let abs: Vec<(String, String)> = sqlx::query_as(
+            "SELECT user.name, ab.name FROM user JOIN ab ON ab.owner = user.guid AND ab.personal = 1 ORDER BY user.name",
+        )
+        .fetch_all(&mut conn)
+        .await
+        .unwrap();
+        assert_eq!(abs.len(), 3);
+        assert_eq!(abs[0], ("admin".to_string(), "admin's Personal Address Book".to_string()));
+        assert_eq!(abs[1], ("alice".to_string(), "alice's Personal Address Book".to_string()));
+        assert!(abs[2].1.starts_with("bob's Personal Address Book ("));
+
+        // the state finds the personal address books created by the migration
+        let bob: Vec<u8> = sqlx::query_scalar("SELECT guid FROM user WHERE name = 'bob'")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        drop(conn);
+        let state = ApiState::new_with_db(&db_file).await;
+        assert!(state.get_ab_personal_guid(bob).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_oauth_user_password_login() {
+        let state = test_state().await;
+        let (user_id, _, _) = state
+            .db
+            .get_user_for_oauth2("oauthonly".to_string(), "oauthonly@example.org".to_string(), "uuid".to_string())
+            .await
+            .unwrap();
+        state.db.link_third_auth(&user_id, "github", "oauthonly", "oauthonly@example.org").await.unwrap();
+        let (password_login, providers) = state.get_linked_providers(&user_id, "missing-oauth2.toml").await.unwrap();
+        assert!(!password_login);
+        assert_eq!(providers.len(), 1);
+        assert_eq!(state.unlink_provider(&user_id, "github").await, Some(Err(UnlinkProviderError::LastLoginMethod)));
+
+        // a second provider or a password can replace it
+        state.db.link_third_auth(&user_id, "dex", "oauthonly", "oauthonly@example.org").await.unwrap();
+        assert_eq!(state.unlink_provider(&user_id, "github").await, Some(Ok(())));
+        assert_eq!(state.unlink_provider(&user_id, "dex").await, Some(Err(UnlinkProviderError::LastLoginMethod)));
+        state.db.reset_user_password("oauthonly".to_string(), "password".to_string()).await.unwrap();
+        assert_eq!(state.unlink_provider(&user_id, "dex").await, Some(Ok(())));
+        assert_eq!(state.get_linked_providers(&user_id, "missing-oauth2.toml").await, Some((true, vec![])));
+    }
+
+    #[tokio::test]
+    async fn test_email_verification() {
+        let state = test_state().await;
+        let (_, user_id) = add_test_user(&state, "verifier").await;
+        let ttl = Duration::from_secs(3600);
+        assert_eq!(state.get_user_email(&user_id).await, Some((Some("verifier@example.org".to_string()), false)));
+
+        // a new token replaces the previous one
+        let (first, _, _) = state.issue_email_verification(&user_id, ttl).await.unwrap().unwrap();
+        let (token, email, expires_at) = state.issue_email_verification(&user_id, ttl).await.unwrap().unwrap();
+        assert_ne!(first, token);
+        assert_eq!(email, "verifier@example.org");
+        assert!(!expires_at.is_empty());
+        assert_eq!(state.verify_email(&first).await, Some(Err(EmailVerificationError::InvalidToken)));
+
+        assert_eq!(state.verify_email(&token).await, Some(Ok("verifier@example.org".to_string())));
+        assert_eq!(state.get_user_email(&user_id).await, Some((Some("verifier@example.org".to_string()), true)));
+        let users = state.get_all_users(Some("verifier"), None, None, 1, 10).await.unwrap();
+        assert!(users[0].email_verified);
+        // the token is used once
+        assert_eq!(state.verify_email(&token).await, Some(Err(EmailVerificationError::InvalidToken)));
+        assert_eq!(
+            state.issue_email_verification(&user_id, ttl).await,
+            Some(Err(EmailVerificationError::AlreadyVerified))
+        );
+
+        // a new email must be verified again, the links sent to the previous one are invalid
+        let (_, other_id) = add_test_user(&state, "mover").await;
+        let (token, _, _) = state.issue_email_verification(&other_id, ttl).await.unwrap().unwrap();
+        let update = |email: &str| UpdateUserRequest {
+            uuid: String::new(),
+            name: None,
+            password: None,
+            confirm_password: None,
+            email: Some(email.to_string()),
+            is_admin: None,
+            group_name: None,
+            note: None,
+            status: None,
+        };
+        state.user_update(other_id.clone(), update("moved@example.org")).await.unwrap().unwrap();
+        assert_eq!(state.verify_email(&token).await, Some(Err(EmailVerificationError::InvalidToken)));
+        state.user_update(user_id.clone(), update("Verifier@Example.org")).await.unwrap().unwrap();
+        assert_eq!(state.get_user_email(&user_id).await, Some((Some("Verifier@Example.org".to_string()), true)));
+        state.user_update(user_id.clone(), update("renamed@example.org")).await.unwrap().unwrap();
+        assert_eq!(state.get_user_email(&user_id).await, Some((Some("renamed@example.org".to_string()), false)));
+    }
+
+    #[tokio::test]
+    async fn test_email_verification_expired() {
+        let state = test_state().await;
+        let (_, user_id) = add_test_user(&state, "late").await;
+        let (token, _, _) = state
+            .issue_email_verification(&user_id, Duration::ZERO)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(state.verify_email(&token).await, Some(Err(EmailVerificationError::Expired)));
+        assert_eq!(state.get_user_email(&user_id).await, Some((Some("late@example.org".to_string()), false)));
+        // the expired token is removed
+        assert_eq!(state.verify_email(&token).await, Some(Err(EmailVerificationError::InvalidToken)));
+    }
+
+    async fn add_test_user(state: &ApiState, name: &str) -> (String, UserId) {
+        state
+            .add_user(AddUserRequest {
+                name: name.to_string(),
+                password: "password".to_string(),
+                confirm_password: "password".to_string(),
+                email: format!("{}@example.org", name),
+                is_admin: false,
+                group_name: "Default".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let users = state.get_all_users(Some(name), None, None, 1, 10).await.unwrap();
+        let guid = users[0].guid.clone();
+        let user_id = Uuid::parse_str(guid.as_str()).unwrap().as_bytes().to_vec();
+        (guid, user_id)
+    }
+
+    #[tokio::test]
+    async fn test_shared_ab_read_only_member_cannot_write() {
+        let state = test_state().await;
+        let (owner, owner_id) = add_test_user(&state, "owner").await;
+        let (reader, reader_id) = add_test_user(&state, "reader").await;
+        let (_, stranger_id) = add_test_user(&state, "stranger").await;
+
+        let ab = state
+            .add_shared_address_book("team ab", owner.as_str())
+            .await
+            .unwrap();
+        state
+            .add_ab_rule(AbRule {
+                guid: ab.clone(),
+                user: Some(reader),
+                group: None,
+                rule: AB_RULE_READ,
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(state.get_ab_rule_for_user(&ab, owner_id.clone()).await, AB_RULE_FULL);
+        assert!(state.check_ab_rule(&ab, owner_id.clone(), AB_RULE_WRITE).await);
+
+        assert!(state.check_ab_rule(&ab, reader_id.clone(), AB_RULE_READ).await);
+        assert!(!state.check_ab_rule(&ab, reader_id.clone(), AB_RULE_WRITE).await);
+
+        assert_eq!(state.get_ab_rule_for_user(&ab, stranger_id.clone()).await, 0);
+        assert!(!state.check_ab_rule(&ab, stranger_id.clone(), AB_RULE_READ).await);
+
+        // the owner lists the address book with full control even without a rule
+        for rule in state.get_ab_rules(0, 10, &ab).await.unwrap() {
+            state.delete_ab_rule(&rule.guid).await.unwrap();
+        }
+        // the default shared address book is granted to the group of the test users
+        let listed = |user_id: UserId| {
+            let (state, ab) = (&state, &ab);
+            async move {
+                state
+                    .get_shared_address_books(user_id)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .filter(|shared| shared.ab == *ab)
+                    .map(|shared| (shared.ab, shared.rule))
+                    .collect::<Vec<_>>()
+            }
+        };
+        assert_eq!(listed(owner_id).await, vec![(ab.clone(), Some(AB_RULE_FULL))]);
+        assert!(listed(reader_id).await.is_empty());
+        assert!(listed(stranger_id).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_user_cannot_modify_another_user_ab() {
+        let state = test_state().await;
+        let (_, alice_id) = add_test_user(&state, "alice").await;
+        let (_, bob_id) = add_test_user(&state, "bob").await;
+
+        let alice_ab = state.get_ab_personal_guid(alice_id.clone()).await.unwrap();
+        let bob_ab = state.get_ab_personal_guid(bob_id.clone()).await.unwrap();
+        assert_ne!(alice_ab, bob_ab);
+
+        assert!(state.check_ab_rule(&alice_ab, alice_id.clone(), AB_RULE_WRITE).await);
+        assert!(!state.check_ab_rule(&alice_ab, bob_id.clone(), AB_RULE_READ).await);
+        assert!(!state.check_ab_rule(&alice_ab, bob_id.clone(), AB_RULE_WRITE).await);
+        assert!(!state.check_ab_rule(&bob_ab, alice_id, AB_RULE_WRITE).await);
+        // an unknown or invalid address book grants nothing
+        assert!(!state.check_ab_rule("not-a-guid", bob_id, AB_RULE_READ).await);
+    }
+
+    async fn ab_peer_tags(state: &ApiState, ab: &str, id: &str) -> Vec<String> {
+        state.get_ab_peer(ab, id).await.unwrap().tags.unwrap_or_default()
+    }
+
+    #[tokio::test]
+    async fn test_bulk_tag_ab_peers() {
+        let state = test_state().await;
+        let (_, user_id) = add_test_user(&state, "tagger").await;
+        let ab = state.get_ab_personal_guid(user_id).await.unwrap();
+        for tag in ["office", "home"] {
+            state
+                .add_ab_tag(&ab, AbTag { name: tag.to_string(), color: 0 })
+                .await
+                .unwrap();
+        }
+        let ids: Vec<String> = ["111111111", "222222222", "333333333"]
+            .iter()
+            .map(|id| id.to_string())
+            .collect();
+        for id in &ids {
+            state
+                .add_ab_peer(&ab, AbPeer { id: id.clone(), ..Default::default() })
+                .await
+                .unwrap();
+        }
+
+        let office = vec!["office".to_string()];
+        let modified = state.update_ab_peers_tags(&ab, &ids, &office, &[]).await;
+        assert_eq!(modified, Some(3));
+        for id in &ids {
+            assert_eq!(ab_peer_tags(&state, &ab, id).await, office);
+        }
+
+        // removing from a subset only touches these peers, an unknown peer is ignored
+        let subset = vec![ids[0].clone(), "999999999".to_string()];
+        let modified = state.update_ab_peers_tags(&ab, &subset, &[], &office).await;
+        assert_eq!(modified, Some(1));
+        assert!(ab_peer_tags(&state, &ab, &ids[0]).await.is_empty());
+        assert_eq!(ab_peer_tags(&state, &ab, &ids[1]).await, office);
+
+        // a tag which does not exist in the address book is refused
+        let unknown = vec!["unknown".to_string()];
+        assert_eq!(state.update_ab_peers_tags(&ab, &ids, &unknown, &[]).await, None);
+        assert_eq!(ab_peer_tags(&state, &ab, &ids[1]).await, office);
+    }
+
+    #[tokio::test]
+    async fn test_merge_ab_peers() {
+        let state = test_state().await;
+        let (_, user_id) = add_test_user(&state, "merger").await;
+        let ab = state.get_ab_personal_guid(user_id).await.unwrap();
+        let tags = |tags: &[&str]| Some(tags.iter().map(|tag| tag.to_string()).collect::<Vec<String>>());
+        let peers = [
+            ("111111111", "", "", tags(&["office", "linux"])),
+            ("222222222", "old laptop", "laptop", tags(&["home", "linux"])),
+            ("333333333", "older laptop", "", tags(&["travel"])),
+        ];
+        for (id, alias, hostname, tags) in peers {
+            let ab_peer = AbPeer {
+                id: id.to_string(),
+                alias: Some(alias.to_string()),
+                hostname: Some(hostname.to_string()),
+                tags,
+                ..Default::default()
+            };
+            state.add_ab_peer(&ab, ab_peer).await.unwrap();
+        }
+
+        // one unknown peer and nothing changes
+        let merge_ids = vec!["222222222".to_string(), "999999999".to_string()];
+        assert!(state.merge_ab_peers(&ab, "111111111", &merge_ids).await.is_none());
+        assert!(state.get_ab_peer(&ab, "222222222").await.is_some());
+
+        let merge_ids = vec!["222222222".to_string(), "333333333".to_string()];
+        let kept = state.merge_ab_peers(&ab, "111111111", &merge_ids).await.unwrap();
+        assert_eq!(kept.tags, tags(&["office", "linux", "home", "travel"]));
+        // the first duplicate fills the empty fields
+        assert_eq!(kept.alias.as_deref(), Some("old laptop"));
+        assert_eq!(kept.hostname.as_deref(), Some("laptop"));
+        let stored = state.get_ab_peer(&ab, "111111111").await.unwrap();
+        assert_eq!(stored.tags, kept.tags);
+        assert!(state.get_ab_peer(&ab, "222222222").await.is_none());
+        assert!(state.get_ab_peer(&ab, "333333333").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ab_tags_stats() {
+        let state = test_state().await;
+        let (_, user_id) = add_test_user(&state, "tagstats").await;
+        let ab = state.get_ab_personal_guid(user_id).await.unwrap();
+        for tag in ["office", "home", "unused"] {
+            state
+                .add_ab_tag(&ab, AbTag { name: tag.to_string(), color: 0 })
+                .await
+                .unwrap();
+        }
+        let peers = [
+            ("111111111", vec!["office", "home"]),
+            ("222222222", vec!["office"]),
+            ("333333333", vec![]),
+        ];
+        for (id, tags) in peers {
+            let tags = tags.iter().map(|tag| tag.to_string()).collect();
+            state
+                .add_ab_peer(&ab, AbPeer { id: id.to_string(), tags: Some(tags), ..Default::default() })
+                .await
+                .unwrap();
+        }
+
+        let stats = state.get_ab_tags_stats(&ab).await.unwrap();
+        let counts: Vec<(&str, u32)> = stats.iter().map(|tag| (tag.name.as_str(), tag.peers)).collect();
+        assert_eq!(counts, vec![("home", 1), ("office", 2), ("unused", 0)]);
+    }
+
+    #[tokio::test]
+    async fn test_rename_ab_tag_updates_peers() {
+        let state = test_state().await;
+        let (_, user_id) = add_test_user(&state, "renamer").await;
+        let ab = state.get_ab_personal_guid(user_id).await.unwrap();
+        for tag in ["office", "home"] {
+            state
+                .add_ab_tag(&ab, AbTag { name: tag.to_string(), color: 0 })
+                .await
+                .unwrap();
+        }
+        // a client may set a tag which has no record in the address book
+        let peers = [
+            ("111111111", vec!["office", "home"]),
+            ("222222222", vec!["office", "work"]),
+            ("333333333", vec!["home"]),
+        ];
+        for (id, tags) in peers {
+            let tags = tags.iter().map(|tag| tag.to_string()).collect();
+            state
+                .add_ab_peer(&ab, AbPeer { id: id.to_string(), tags: Some(tags), ..Default::default() })
+                .await
+                .unwrap();
+        }
+
+        state
+            .rename_ab_tag(&ab, "office", AbTag { name: "desk".to_string(), color: 0 })
+            .await
+            .unwrap();
+        assert_eq!(ab_peer_tags(&state, &ab, "111111111").await, vec!["desk", "home"]);
+        assert_eq!(ab_peer_tags(&state, &ab, "222222222").await, vec!["desk", "work"]);
+        assert_eq!(ab_peer_tags(&state, &ab, "333333333").await, vec!["home"]);
+
+        // the peer has both names, it keeps only one
+        state
+            .rename_ab_tag(&ab, "desk", AbTag { name: "work".to_string(), color: 0 })
+            .await
+            .unwrap();
+        assert_eq!(ab_peer_tags(&state, &ab, "222222222").await, vec!["work"]);
+        assert_eq!(ab_peer_tags(&state, &ab, "111111111").await, vec!["work", "home"]);
+        let names: Vec<String> = state.get_ab_tags(&ab).await.unwrap().into_iter().map(|tag| tag.name).collect();
+        assert!(!names.contains(&"desk".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_statistics_count_logins() {
+        let state = test_state().await;
+        let before = state.get_statistics().await.unwrap();
+        add_test_user(&state, "stats").await;
+
+        let password = UserPasswordInfo::from_password("password");
+        let login = state.user_login(&"stats".to_string(), password, false).await;
+        assert!(login.is_some());
+
+        let after = state.get_statistics().await.unwrap();
+        assert_eq!(after.users_total, before.users_total + 1);
+        assert_eq!(after.users_active, before.users_active + 1);
+        assert_eq!(after.logins_today, before.logins_today + 1);
+        assert_eq!(after.address_books_personal, before.address_books_personal + 1);
+    }
+
+    #[tokio::test]
+    async fn test_store_client_audits() {
+        let state = test_state().await;
+        let records = [
+            (Some("conn"), r#"{"id":"123456789","uuid":"AQI=","conn_id":3,"session_id":42,"ip":"10.0.0.1","action":"new"}"#),
+            (Some("conn"), r#"{"id":"123456789","uuid":"AQI=","conn_id":3,"session_id":42,"peer":["987654321","bob"],"type":0}"#),
+            (Some("conn"), r#"{"id":"123456789","uuid":"AQI=","conn_id":3,"session_id":42,"action":"close"}"#),
+            (Some("file"), r#"{"id":"123456789","uuid":"AQI=","peer_id":"987654321","type":1,"path":"/tmp/a","is_file":true,"info":"{}"}"#),
+            (Some("alarm"), r#"{"id":"123456789","uuid":"AQI=","typ":1,"info":"{\"ip\":\"10.0.0.3\"}"}"#),
+        ];
+        for (kind, record) in records {
+            let request: utils::AuditRequest = serde_json::from_str(record).unwrap();
+            let event = utils::audit::parse_audit(kind, &request).unwrap();
+            assert!(state.db.add_client_audit(&event, record).await.is_some());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_client_audits() {
+        let state = test_state().await;
+        let records = [
+            (Some("conn"), r#"{"id":"111111111","conn_id":1,"session_id":7,"ip":"10.0.0.1","action":"new"}"#),
+            (Some("conn"), r#"{"id":"111111111","conn_id":1,"peer":["987654321","bob"],"type":0}"#),
+            (Some("conn"), r#"{"id":"111111111","conn_id":1,"action":"close"}"#),
+            (Some("conn"), r#"{"id":"111111111","conn_id":2,"action":"new"}"#),
+            (Some("conn"), r#"{"id":"222222222","conn_id":1,"action":"new"}"#),
+            (Some("file"), r#"{"id":"111111111","peer_id":"987654321","type":0,"path":"/tmp/a","is_file":true}"#),
+        ];
+        for (kind, record) in records {
+            let request: utils::AuditRequest = serde_json::from_str(record).unwrap();
+            let event = utils::audit::parse_audit(kind, &request).unwrap();
+            state.db.add_client_audit(&event, record).await.unwrap();
+        }
+
+        let filter = AuditFilter::new(None, None, Some("111111111".to_string()), None, None, None);
+        let (total, conns) = state.get_audit_conns(&filter).await.unwrap();
+        assert_eq!(total, 2);
+        let closed = conns.iter().find(|conn| conn.conn_id == 1).unwrap();
+        assert_eq!(closed.remote.as_deref(), Some("987654321"));
+        assert_eq!(closed.session_id.as_deref(), Some("7"));
+        assert!(closed.end.is_some());
+        assert_eq!(closed.duration, Some(0));
+        let open = conns.iter().find(|conn| conn.conn_id == 2).unwrap();
+        assert_eq!(open.duration, None);
+
+        // pagination keeps the total of the matching connections
+        let filter = AuditFilter::new(None, None, None, None, Some(2), Some(2));
+        let (total, conns) = state.get_audit_conns(&filter).await.unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(conns.len(), 1);
+
+        let filter = AuditFilter::new(Some("2000-01-01".to_string()), Some("2000-01-02".to_string()), None, None, None, None);
+        assert_eq!(state.get_audit_conns(&filter).await.unwrap().0, 0);
+
+        let (total, files) = state.get_audit_files(&AuditFilter::new(None, None, None, None, None, None)).await.unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(files[0].path, "/tmp/a");
+        assert!(files[0].is_file);
+    }
+
+    async fn add_test_audits(state: &ApiState, records: &[&str]) {
+        for record in records {
+            let request: utils::AuditRequest = serde_json::from_str(record).unwrap();
+            let event = utils::audit::parse_audit(Some("conn"), &request).unwrap();
+            let info = serde_json::to_string(&request).unwrap();
+            state.db.add_client_audit(&event, &info).await.unwrap();
+        }
+    }
+
+    async fn audit_conns_of(state: &ApiState, peer: &str) -> Vec<AuditConn> {
+        let filter = AuditFilter::new(None, None, Some(peer.to_string()), None, None, None);
+        state.get_audit_conns(&filter).await.unwrap().1
+    }
+
+    #[tokio::test]
+    async fn test_client_audit_conn_close() {
+        let state = test_state().await;
+        let open = r#"{"id":"111111111","conn_id":1,"session_id":7,"ip":"10.0.0.1","action":"new"}"#;
+        let auth = r#"{"id":"111111111","conn_id":1,"peer":["987654321","bob"],"type":0}"#;
+        let close = r#"{"id":"111111111","conn_id":1,"action":"close","duration":42}"#;
+
+        // in order
+        add_test_audits(&state, &[open, auth, close]).await;
+        let conns = audit_conns_of(&state, "111111111").await;
+        assert_eq!(conns.len(), 1);
+        assert_eq!(conns[0].remote.as_deref(), Some("987654321"));
+        assert_eq!(conns[0].session_id.as_deref(), Some("7"));
+        assert!(conns[0].end.is_some());
+        assert_eq!(conns[0].duration, Some(42));
+
+        // a duplicated close does not change the connection
+        let end = conns[0].end.clone();
+        add_test_audits(&state, &[r#"{"id":"111111111","conn_id":1,"action":"close","duration":99}"#]).await;
+        let conns = audit_conns_of(&state, "111111111").await;
+        assert_eq!(conns.len(), 1);
+        assert_eq!(conns[0].end, end);
+        assert_eq!(conns[0].duration, Some(42));
+
+        // the close arrives before the open
+        let close = r#"{"id":"222222222","conn_id":1,"action":"close","duration":60}"#;
+        let open = r#"{"id":"222222222","conn_id":1,"session_id":8,"ip":"10.0.0.2","action":"new"}"#;
+        add_test_audits(&state, &[close, open]).await;
+        let conns = audit_conns_of(&state, "222222222").await;
+        assert_eq!(conns.len(), 1);
+        assert_eq!(conns[0].session_id.as_deref(), Some("8"));
+        assert_eq!(conns[0].ip.as_deref(), Some("10.0.0.2"));
+        assert_eq!(conns[0].duration, Some(60));
+        assert!(conns[0].start < conns[0].end.clone().unwrap());
+
+        // without a duration it is computed from the open and close times
+        let open = r#"{"id":"333333333","conn_id":5,"action":"new"}"#;
+        let close = r#"{"id":"333333333","conn_id":5,"action":"close"}"#;
+        add_test_audits(&state, &[open, close]).await;
+        let conns = audit_conns_of(&state, "333333333").await;
+        assert_eq!(conns.len(), 1);
+        assert_eq!(conns[0].duration, Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_file_audit_bytes_per_day() {
+        let state = test_state().await;
+        let records = [
+            r#"{"id":"111111111","peer_id":"987654321","type":0,"path":"/home/bob/été 文件.txt","is_file":true,"info":"{\"files\":[[\"été 文件.txt\",1000]]}"}"#,
+            r#"{"id":"222222222","peer_id":"987654321","type":1,"path":"C:\\data","is_file":false,"info":"{\"num\":2,\"files\":[[\"a\",20],[\"b\",3]]}"}"#,
+        ];
+        for record in records {
+            let request: utils::AuditRequest = serde_json::from_str(record).unwrap();
+            let event = utils::audit::parse_audit(None, &request).unwrap();
+            state.db.add_client_audit(&event, record).await.unwrap();
+        }
+
+        let filter = AuditFilter::new(None, None, Some("111111111".to_string()), None, None, None);
+        let (total, files) = state.get_audit_files(&filter).await.unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(files[0].path, "/home/bob/été 文件.txt");
+        assert_eq!(files[0].direction, 0);
+        assert_eq!(files[0].size, 1000);
+
+        let statistics = state.get_statistics().await.unwrap();
+        assert_eq!(statistics.file_bytes_by_day.len(), 1);
+        assert_eq!(statistics.file_bytes_by_day[0].bytes, 1023);
+    }
+
+    #[tokio::test]
+    async fn test_alarm_audit_severity() {
+        let state = test_state().await;
+        let records = [
+            r#"{"id":"111111111","typ":1,"info":"{\"ip\":\"10.0.0.3\",\"id\":\"987654321\",\"name\":\"bob\"}"}"#,
+            r#"{"id":"111111111","typ":0,"info":"{\"ip\":\"10.0.0.4\"}"}"#,
+        ];
+        for record in records {
+            let request: utils::AuditRequest = serde_json::from_str(record).unwrap();
+            let event = utils::audit::parse_audit(Some("alarm"), &request).unwrap();
+            state.db.add_client_audit(&event, record).await.unwrap();
+        }
+        let filter = AuditFilter::new(None, None, Some("111111111".to_string()), None, None, None);
+        let (total, alarms) = state.get_audit_alarms(&filter).await.unwrap();
+        assert_eq!(total, 2);
+        let attempts = alarms.iter().find(|alarm| alarm.alarm_type == 1).unwrap();
+        assert_eq!(attempts.severity, "critical");
+        assert_eq!(attempts.remote.as_deref(), Some("987654321"));
+        assert_eq!(attempts.remote_name.as_deref(), Some("bob"));
+        let whitelist = alarms.iter().find(|alarm| alarm.alarm_type == 0).unwrap();
+        assert_eq!(whitelist.severity, "warning");
+        assert_eq!(whitelist.ip.as_deref(), Some("10.0.0.4"));
+    }
+
+    #[test]
+    fn test_purge_clock() {
+        let mut clock = PurgeClock::default();
+        let start = Instant::now();
+        let wall = Utc::now();
+        assert_eq!(clock.now(start, wall), wall);
+
+        // the system clock jumps a year forward while an hour elapsed
+        let later = start + Duration::from_secs(3600);
+        let now = clock.now(later, wall + chrono::Duration::days(365));
+        assert_eq!(now, wall + chrono::Duration::hours(1));
+
+        // the system clock goes back
+        let now = clock.now(later + Duration::from_secs(3600), wall - chrono::Duration::days(1));
+        assert_eq!(now, wall - chrono::Duration::days(1));
+    }
+
+    #[tokio::test]
+    async fn test_purge_audits() {
+        let state = test_state().await;
+        assert_eq!(state.get_audit_retention().await, AuditRetention::default());
+        add_test_audits(
+            &state,
+            &[
+                r#"{"id":"222222222","conn_id":1,"action":"new"}"#,
+                r#"{"id":"222222222","conn_id":2,"action":"new"}"#,
+                r#"{"id":"222222222","conn_id":3,"action":"new"}"#,
+            ],
+        )
+        .await;
+
+        // the records newer than the horizon are never touched
+        purge_audits(&state.db, Utc::now()).await;
+        assert_eq!(audit_conns_of(&state, "222222222").await.len(), 3);
+
+        let retention = AuditRetention {
+            conn_days: 30,
+            ..AuditRetention::default()
+        };
+        state.set_audit_retention(&retention).await.unwrap();
+        assert_eq!(state.get_audit_retention().await, retention);
+        purge_audits(&state.db, Utc::now() + chrono::Duration::days(29)).await;
+        assert_eq!(audit_conns_of(&state, "222222222").await.len(), 3);
+
+        // one record per transaction
+        let horizon = (Utc::now() + chrono::Duration::days(31)).format("%Y-%m-%d %H:%M:%S%.3f").to_string();
+        let purged = state.db.purge_audit(AuditCategory::Conn, &horizon, 1).await;
+        assert_eq!(purged, Some(3));
+        assert!(audit_conns_of(&state, "222222222").await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_peer_status_maintenance() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        add_test_peer(&db_file, "111111111", None).await;
+        add_test_peer(&db_file, "222222222", None).await;
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        for (id, online, age) in [
+            ("111111111", 1, "-100 days"),
+            ("111111111", 0, "-99 days"),
+            ("111111111", 1, "-98 days"),
+            ("111111111", 0, "-97 days"),
+            ("111111111", 1, "-1 days"),
+            ("222222222", 1, "-100 days"),
+        ] {
+            sqlx::query(
+                "INSERT INTO peer_status_history(guid, peer, online, created_at)
+                 SELECT randomblob(16), guid, ?, datetime('now', ?) FROM peer WHERE id = ?",
+            )
+            .bind(online)
+            .bind(age)
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+            sqlx::query("UPDATE peer SET last_online = datetime('now', ?) WHERE id = ?")
+                .bind(age)
+                .bind(id)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let history_of = |id: &'static str| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query_scalar::<_, i64>(
+                    "SELECT COUNT(*) FROM peer_status_history JOIN peer ON peer_status_history.peer = peer.guid WHERE peer.id = ?",
+                )
+                .bind(id)
+                .fetch_one(&pool)
+                .await
+                .unwrap()
+            }
+        };
+
+        // one transition per transaction, the last one of each peer is kept
+        assert_eq!(state.db.purge_peer_status_history(90, 1).await, Some(4));
+        assert_eq!(history_of("111111111").await, 1);
+        assert_eq!(history_of("222222222").await, 1);
+        assert_eq!(state.db.purge_peer_status_history(90, 1).await, Some(0));
+
+        // the peers online in the history which stopped beating are published offline
+        let mut events = state.subscribe_peer_events();
+        sweep_offline_peers(&state.db, &state.peer_events).await;
+        let mut offline = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            assert_eq!(event.event_type, PeerEventType::PeerOffline);
+            offline.push(event.id);
+        }
+        offline.sort();
+        assert_eq!(offline, ["111111111", "222222222"]);
+        sweep_offline_peers(&state.db, &state.peer_events).await;
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_purge_audits_window() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        add_test_audits(
+            &state,
+            &[
+                r#"{"id":"333333333","conn_id":1,"action":"new"}"#,
+                r#"{"id":"333333333","conn_id":2,"action":"new"}"#,
+                r#"{"id":"333333333","conn_id":3,"action":"new"}"#,
+            ],
+        )
+        .await;
+        let (_, admin_id) = add_test_user(&state, "purgeadmin").await;
+        state
+            .db
+            .add_admin_audit(admin_id.clone(), "delete", "/api/peers/333333333", Some("333333333"), "{}")
+            .await
+            .unwrap();
+        // the first connection and the admin operation are older than the window
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query("UPDATE audit_client_conn SET created_at = datetime('now', '-40 days') WHERE conn_id = 1")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("UPDATE admin_audit SET created_at = datetime('now', '-40 days') WHERE actor = ?")
+            .bind(admin_id.clone())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let admin_audits_of = |actor: UserId| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query("SELECT guid FROM admin_audit WHERE actor = ?")
+                    .bind(actor)
+                    .fetch_all(&pool)
+                    .await
+                    .unwrap()
+                    .len()
+            }
+        };
+
+        // 0 keeps the records forever
+        state.set_audit_retention(&AuditRetention::uniform(0)).await.unwrap();
+        assert_eq!(purge_audits(&state.db, Utc::now()).await, 0);
+        assert_eq!(audit_conns_of(&state, "333333333").await.len(), 3);
+        assert_eq!(admin_audits_of(admin_id.clone()).await, 1);
+
+        // the seeded sessions are older than the window too
+        let retention = AuditRetention {
+            login_days: 0,
+            ..AuditRetention::uniform(30)
+        };
+        state.set_audit_retention(&retention).await.unwrap();
+        assert_eq!(purge_audits(&state.db, Utc::now()).await, 2);
+        let conns = audit_conns_of(&state, "333333333").await;
+        assert_eq!(conns.len(), 2);
+        assert!(conns.iter().all(|conn| conn.conn_id != 1));
+        assert_eq!(admin_audits_of(admin_id).await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_login_audit_outlives_session() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let (_, user_id) = add_test_user(&state, "oldlogin").await;
+        state.db.add_session(user_id.clone(), SESSION_TTL_SECS).await.unwrap();
+        // the login is 40 days old, its session expired 10 days ago
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query(
+            "UPDATE session SET created_at = datetime('now', '-40 days'), expiry_at = datetime('now', '-10 days') WHERE user = ?",
+        )
+        .bind(&user_id)
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        // the login records are only deleted by the 90 days retention
+        purge_audits(&state.db, Utc::now()).await;
+        assert_eq!(purge_audits(&state.db, Utc::now()).await, 0);
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM session WHERE user = ?")
+            .bind(&user_id)
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_default_audit_retention() {
+        assert_eq!(default_audit_retention(None), AuditRetention::default());
+        assert_eq!(default_audit_retention(Some("30")), AuditRetention::uniform(30));
+        assert_eq!(default_audit_retention(Some("0")), AuditRetention::uniform(0));
+        assert_eq!(default_audit_retention(Some("forever")), AuditRetention::default());
+        assert_eq!(default_audit_retention(Some("100000")), AuditRetention::default());
+    }
+
+    #[tokio::test]
+    async fn test_delete_ab_tags_untags_peers() {
+        let state = test_state().await;
+        let (_, user_id) = add_test_user(&state, "untagger").await;
+        let ab = state.get_ab_personal_guid(user_id).await.unwrap();
+        for tag in ["office", "work", "home"] {
+            state
+                .add_ab_tag(&ab, AbTag { name: tag.to_string(), color: 0 })
+                .await
+                .unwrap();
+        }
+        let peers = [
+            ("111111111", vec!["office", "home"]),
+            ("222222222", vec!["work", "office"]),
+            ("333333333", vec!["home"]),
+        ];
+        for (id, tags) in peers {
+            let tags = tags.iter().map(|tag| tag.to_string()).collect();
+            state
+                .add_ab_peer(&ab, AbPeer { id: id.to_string(), tags: Some(tags), ..Default::default() })
+                .await
+                .unwrap();
+        }
+
+        state
+            .delete_ab_tags(&ab, vec!["office".to_string(), "work".to_string()])
+            .await
+            .unwrap();
+        for id in ["111111111", "222222222", "333333333"] {
+            let tags = ab_peer_tags(&state, &ab, id).await;
+            assert!(!tags.contains(&"office".to_string()) && !tags.contains(&"work".to_string()));
+        }
+        assert_eq!(ab_peer_tags(&state, &ab, "111111111").await, vec!["home"]);
+        assert!(ab_peer_tags(&state, &ab, "222222222").await.is_empty());
+        let names: Vec<String> = state.get_ab_tags(&ab).await.unwrap().into_iter().map(|tag| tag.name).collect();
+        assert_eq!(names, vec!["home"]);
+    }
+
+    #[tokio::test]
+    async fn test_audit_admin_action_redacts_details() {
+        let state = test_state().await;
+        let (_, admin_id) = add_test_user(&state, "auditor").await;
+        let mut forwarded = state.subscribe_audit_records();
+        state
+            .audit_admin_action(
+                &admin_id,
+                AdminAction::UserCreate,
+                Some("bob"),
+                serde_json::json!({"name": "bob", "password": "hunter2", "confirm-password": "hunter2"}),
+            )
+            .await
+            .unwrap();
+        state
+            .audit_admin_action(&admin_id, AdminAction::GroupDelete, Some("group"), serde_json::json!({}))
+            .await
+            .unwrap();
+
+        let filter = AuditFilter::new(None, None, Some("bob".to_string()), Some("auditor".to_string()), None, None);
+        let (total, audits) = state.get_admin_audits(&filter).await.unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(audits[0].actor.as_deref(), Some("auditor"));
+        assert_eq!(audits[0].action, "user.create");
+        assert_eq!(audits[0].route, "POST /api/user");
+        assert_eq!(
+            audits[0].details,
+            serde_json::json!({"name": "bob", "password": "[redacted]", "confirm-password": "[redacted]"})
+        );
+        let (total, _) = state.get_admin_audits(&AuditFilter::new(None, None, None, None, None, None)).await.unwrap();
+        assert_eq!(total, 2);
+
+        let record = forwarded.try_recv().unwrap();
+        assert_eq!(record.category, AUDIT_FORWARD_ADMIN);
+        assert!(!record.record.to_string().contains("hunter2"));
+    }
+
+    #[tokio::test]
+    async fn test_strategies_keep_exactly_one_default() {
+        let state = test_state().await;
+        let (total, strategies) = state.get_strategies(None, StrategyOrder::Default, 0, u32::MAX).await.unwrap();
+        assert_eq!(total, 1);
+        let seeded = strategies[0].clone();
+        assert!(seeded.is_default);
+        let defaults = |strategies: Vec<Strategy>| {
+            strategies
+                .into_iter()
+                .filter(|strategy| strategy.is_default)
+                .map(|strategy| strategy.name)
+                .collect::<Vec<String>>()
+        };
+
+        let settings = serde_json::json!({"enable-clipboard": "N", "option-of-a-newer-client": {"a": 1}});
+        let locked = state.add_strategy("Locked", &settings, true).await.unwrap();
+        assert!(locked.is_default);
+        assert_eq!(locked.settings, settings);
+        let open = state.add_strategy("Open", &serde_json::json!({}), false).await.unwrap();
+        assert!(state.add_strategy("Open", &serde_json::json!({}), false).await.is_none());
+        let (total, strategies) = state.get_strategies(None, StrategyOrder::Default, 0, u32::MAX).await.unwrap();
+        assert_eq!(total, 3);
+        assert_eq!(defaults(strategies.clone()), vec!["Locked"]);
+        assert_eq!(strategies[0].name, "Locked");
+
+        // marking another default moves the flag, keeping the other fields
+        let open = state
+            .update_strategy(&open.guid, None, None, true)
+            .await
+            .unwrap();
+        assert!(open.is_default);
+        assert_eq!(open.name, "Open");
+        let (_, strategies) = state.get_strategies(None, StrategyOrder::Default, 0, u32::MAX).await.unwrap();
+        assert_eq!(defaults(strategies), vec!["Open"]);
+        assert!(state.update_strategy(&locked.guid, Some("Open"), None, false).await.is_none());
+
+        // the default strategy is never deleted
+        assert!(state.delete_strategy(&open.guid, true).await.is_none());
+        assert!(state.delete_strategy(&seeded.guid, false).await.is_some());
+        assert!(state.get_strategy(&seeded.guid).await.is_none());
+        let (total, strategies) = state.get_strategies(None, StrategyOrder::Default, 1, 1).await.unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(strategies.len(), 1);
+        assert_eq!(strategies[0].name, "Locked");
+    }
+
+    #[tokio::test]
+    async fn test_effective_strategy_resolution() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let (user, user_id) = add_test_user(&state, "assignee").await;
+        add_test_peer(&db_file, "111111111", Some(&user_id)).await;
+        add_test_peer(&db_file, "222222222", None).await;
+        let source = |peer_id: &'static str| {
+            let state = &state;
+            async move {
+                let effective = state.get_effective_strategy(peer_id).await.unwrap();
+                (effective.source, effective.strategy.name)
+            }
+        };
+        assert_eq!(source("111111111").await, (StrategySource::Default, "Default".to_string()));
+        assert!(state.get_effective_strategy("333333333").await.is_none());
+
+        let user_strategy = state
+            .add_strategy("Users", &serde_json::json!({"enable-audio": "N"}), false)
+            .await
+            .unwrap();
+        let device_strategy = state
+            .add_strategy("Devices", &serde_json::json!({"enable-clipboard": "N"}), false)
+            .await
+            .unwrap();
+        state.assign_user_strategy(&user, Some(&user_strategy.guid)).await.unwrap();
+        assert_eq!(source("111111111").await, (StrategySource::User, "Users".to_string()));
+        assert_eq!(source("222222222").await, (StrategySource::Default, "Default".to_string()));
+        state
+            .assign_peer_strategy("111111111", Some(&device_strategy.guid))
+            .await
+            .unwrap();
+        assert_eq!(source("111111111").await, (StrategySource::Device, "Devices".to_string()));
+        assert_eq!(state.get_peer("111111111").await.unwrap().strategy_name, "Devices");
+        assert!(state.assign_peer_strategy("333333333", None).await.is_none());
+
+        // the heartbeat pushes the settings until the client acknowledges their version
+        let directives = state.heartbeat_directives("111111111", Some(0), false).await;
+        let version = match &directives[..] {
+            [HeartbeatDirective::ConfigChanged { version, strategy, settings }] => {
+                assert_eq!(strategy, "Devices");
+                // the settings of the levels are merged
+                assert_eq!(settings["enable-clipboard"], "N");
+                assert_eq!(settings["enable-audio"], "N");
+                assert_eq!(settings["enable-keyboard"], "Y");
+                *version
+            }
+            directives => panic!("unexpected directives {:?}", directives),
+        };
+        assert!(state.heartbeat_directives("111111111", Some(version), false).await.is_empty());
+        let effective = state.get_effective_settings("111111111").await.unwrap();
+        assert_eq!(effective.provenance["enable-audio"].source, StrategySource::User);
+        assert_eq!(effective.provenance["enable-clipboard"].source, StrategySource::Device);
+        assert_eq!(effective.provenance["enable-keyboard"].source, StrategySource::Builtin);
+        assert!(state.get_effective_settings("333333333").await.is_none());
+
+        // deleting with cascade falls back to the next level
+        assert_eq!(state.count_strategy_assignments(&device_strategy.guid).await, Some(1));
+        state.delete_strategy(&device_strategy.guid, true).await.unwrap();
+        assert_eq!(source("111111111").await, (StrategySource::User, "Users".to_string()));
+        state.assign_user_strategy(&user, None).await.unwrap();
+        assert_eq!(source("111111111").await, (StrategySource::Default, "Default".to_string()));
+        assert_eq!(state.count_strategy_assignments(&user_strategy.guid).await, Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_config_version_follows_the_assignments() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        add_test_peer(&db_file, "111111111", None).await;
+        let older = state
+            .add_strategy("Older", &serde_json::json!({"enable-clipboard": "N"}), false)
+            .await
+            .unwrap();
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query("UPDATE strategies SET modified_at = '2024-01-01 00:00:00' WHERE name = 'Older'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let version = |directives: Vec<HeartbeatDirective>| match &directives[..] {
+            [HeartbeatDirective::ConfigChanged { version, .. }] => *version,
+            directives => panic!("unexpected directives {:?}", directives),
+        };
+
+        // the Default strategy is updated after the older one and acknowledged by the client
+        let default = state.db.get_default_strategy().await.unwrap();
+        state
+            .update_strategy(&default.guid, None, Some(&serde_json::json!({"enable-audio": "N"})), true)
+            .await
+            .unwrap();
+        // the clients which do not take the directive never get it
+        assert!(state.heartbeat_directives("111111111", None, false).await.is_empty());
+        let acknowledged = version(state.heartbeat_directives("111111111", Some(0), false).await);
+        assert!(acknowledged > 0 && acknowledged <= i64::MAX as u64);
+        assert!(state.heartbeat_directives("111111111", Some(acknowledged), false).await.is_empty());
+
+        // assigning the older strategy to the device is a change
+        state.assign_peer_strategy("111111111", Some(&older.guid)).await.unwrap();
+        let assigned = version(state.heartbeat_directives("111111111", Some(acknowledged), false).await);
+        assert_ne!(assigned, acknowledged);
+        assert!(state.heartbeat_directives("111111111", Some(assigned), false).await.is_empty());
+
+        // and removing the assignment too, back to the acknowledged configuration
+        state.assign_peer_strategy("111111111", None).await.unwrap();
+        assert_eq!(version(state.heartbeat_directives("111111111", Some(assigned), false).await), acknowledged);
+    }
+
+    #[tokio::test]
+    async fn test_group_strategy_inheritance() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let (user, user_id) = add_test_user(&state, "accountant").await;
+        add_test_peer(&db_file, "111111111", Some(&user_id)).await;
+        add_test_peer(&db_file, "222222222", None).await;
+        let source = |peer_id: &'static str| {
+            let state = &state;
+            async move {
+                let effective = state.get_effective_strategy(peer_id).await.unwrap();
+                (effective.source, effective.strategy.name)
+            }
+        };
+        let locked = state
+            .add_strategy("Locked", &serde_json::json!({"enable-clipboard": "N"}), false)
+            .await
+            .unwrap();
+        let everyone = state
+            .add_strategy("Everyone", &serde_json::json!({"enable-audio": "N"}), false)
+            .await
+            .unwrap();
+        state
+            .create_group("Finance", "Default", "", Some(&locked.guid))
+            .await
+            .unwrap();
+        let groups = state.get_groups(0, u32::MAX).await.unwrap();
+        let finance = groups.iter().find(|group| group.name == "Finance").unwrap();
+        assert_eq!(finance.strategy.as_deref(), Some(locked.guid.as_str()));
+        let finance = finance.guid.clone();
+        let default = groups.iter().find(|group| group.name == "Default").unwrap();
+        assert_eq!(default.strategy, None);
+        let default = default.guid.clone();
+        let finance_guid = Uuid::parse_str(&finance).unwrap().as_bytes().to_vec();
+        let ids = vec!["222222222".to_string()];
+        state
+            .bulk_peers(PeersBulkAction::SetGroup, ids, Some(finance_guid.clone()), user_id.clone(), false)
+            .await
+            .unwrap();
+        assert_eq!(source("222222222").await, (StrategySource::Group, "Locked".to_string()));
+
+        // the group of the user applies to its peers, the group of the device takes precedence
+        state.assign_group_strategy(&default, Some(&everyone.guid)).await.unwrap();
+        assert_eq!(source("111111111").await, (StrategySource::Group, "Everyone".to_string()));
+        let ids = vec!["111111111".to_string()];
+        state
+            .bulk_peers(PeersBulkAction::SetGroup, ids, Some(finance_guid), user_id, false)
+            .await
+            .unwrap();
+        assert_eq!(source("111111111").await, (StrategySource::Group, "Locked".to_string()));
+        let effective = state.get_effective_settings("111111111").await.unwrap();
+        assert!(!effective.settings.enable_audio);
+        assert!(!effective.settings.enable_clipboard);
+        assert_eq!(effective.provenance["enable-audio"].strategy.as_deref(), Some("Everyone"));
+        assert_eq!(effective.provenance["enable-clipboard"].strategy.as_deref(), Some("Locked"));
+
+        // the strategy of the user takes precedence over the ones of the groups
+        state.assign_user_strategy(&user, Some(&everyone.guid)).await.unwrap();
+        assert_eq!(source("111111111").await, (StrategySource::User, "Everyone".to_string()));
+
+        // changing the strategy of a group is a new version even when the strategies did not change
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query(
+            "UPDATE strategies SET modified_at = '2024-01-01 00:00:00';
+            UPDATE grp_strategy SET modified_at = '2024-01-01 00:00:00';",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let version = |directives: Vec<HeartbeatDirective>| match &directives[..] {
+            [HeartbeatDirective::ConfigChanged { version, .. }] => *version,
+            directives => panic!("unexpected directives {:?}", directives),
+        };
+        let acknowledged = version(state.heartbeat_directives("222222222", Some(0), false).await);
+        assert!(state.heartbeat_directives("222222222", Some(acknowledged), false).await.is_empty());
+        state.assign_group_strategy(&finance, Some(&locked.guid)).await.unwrap();
+        assert_ne!(version(state.heartbeat_directives("222222222", Some(acknowledged), false).await), acknowledged);
+
+        // deleting with cascade removes the assignments of the groups
+        assert_eq!(state.count_strategy_assignments(&locked.guid).await, Some(1));
+        state.delete_strategy(&locked.guid, true).await.unwrap();
+        assert_eq!(source("222222222").await, (StrategySource::Default, "Default".to_string()));
+        assert_eq!(state.get_group(&finance).await.unwrap().strategy, None);
+        assert!(state.assign_group_strategy(&Uuid::new_v4().to_string(), None).await.is_none());
+        state.delete_group(&finance).await.unwrap();
+        assert_eq!(source("111111111").await, (StrategySource::User, "Everyone".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_users_cursor_traversal_with_inserts() {
+        let state = test_state().await;
+        for i in 0..7 {
+            add_test_user(&state, &format!("paged{}", i)).await;
+        }
+        let mut seen: Vec<String> = Vec::new();
+        let mut after: Option<PageCursor> = None;
+        let mut inserted = 0;
+        loop {
+            let (users, next) = state
+                .get_users_page(Some("paged%"), None, after.as_ref(), 3)
+                .await
+                .unwrap();
+            assert!(users.len() <= 3);
+            seen.extend(users.into_iter().map(|user| user.name));
+            // rows inserted during the traversal must not shift the pages
+            if inserted < 2 {
+                add_test_user(&state, &format!("paged-late{}", inserted)).await;
+                inserted += 1;
+            }
+            match next {
+                Some(next) => after = Some(next),
+                None => break,
+            }
+        }
+        let mut unique = seen.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), seen.len());
+        for i in 0..7 {
+            assert!(seen.contains(&format!("paged{}", i)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ab_peers_cursor_traversal_with_inserts() {
+        let state = test_state().await;
+        let (_, user_id) = add_test_user(&state, "pager").await;
+        let ab = state.get_ab_personal_guid(user_id).await.unwrap();
+        for i in 0..5 {
+            let peer = AbPeer { id: format!("10000000{}", i), ..Default::default() };
+            state.add_ab_peer(&ab, peer).await.unwrap();
+        }
+        let mut seen: Vec<String> = Vec::new();
+        let mut after: Option<PageCursor> = None;
+        let mut inserted = 0;
+        loop {
+            let (peers, next) = state.get_ab_peers_page(&ab, after.as_ref(), 2).await.unwrap();
+            seen.extend(peers.into_iter().map(|peer| peer.id));
+            if inserted < 2 {
+                let peer = AbPeer { id: format!("20000000{}", inserted), ..Default::default() };
+                state.add_ab_peer(&ab, peer).await.unwrap();
+                inserted += 1;
+            }
+            match next {
+                Some(next) => after = Some(next),
+                None => break,
+            }
+        }
+        let mut unique = seen.clone();
+        unique.sort();
+        unique.dedup();
+        assert_eq!(unique.len(), seen.len());
+        for i in 0..5 {
+            assert!(seen.contains(&format!("10000000{}", i)));
+        }
+        assert!(state.get_ab_peers_page(&ab, None, 0).await.unwrap().1.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_audits_flag_deleted_peers_and_users() {
+        let state = test_state().await;
+        add_test_audits(&state, &[r#"{"id":"333333333","conn_id":1,"action":"new"}"#]).await;
+        let conns = audit_conns_of(&state, "333333333").await;
+        assert_eq!(conns[0].peer, "333333333");
+        assert!(conns[0].peer_deleted);
+        assert_eq!(conns[0].peer_name, None);
+        assert!(!conns[0].user_deleted);
+
+        let (guid, user_id) = add_test_user(&state, "gone").await;
+        let password = UserPasswordInfo::from_password("password");
+        assert!(state.user_login(&"gone".to_string(), password, false).await.is_some());
+        state
+            .audit_admin_action(&user_id, AdminAction::GroupDelete, Some("group"), serde_json::json!({}))
+            .await
+            .unwrap();
+        state.ui_delete_user(user_id).await.unwrap();
+
+        let filter = AuditFilter::new(None, None, None, None, None, None);
+        let records: Vec<AuditRecord> = state.export_audit(AuditCategory::Login, filter.clone()).collect().await;
+        let login = records
+            .into_iter()
+            .find_map(|record| match record {
+                AuditRecord::Login(login) if login.user_id.as_deref() == Some(guid.as_str()) => Some(login),
+                _ => None,
+            })
+            .unwrap();
+        assert!(login.user_deleted);
+        assert_eq!(login.user, None);
+
+        let (_, audits) = state.get_admin_audits(&filter).await.unwrap();
+        assert_eq!(audits[0].actor_id, guid);
+        assert!(audits[0].actor_deleted);
+        assert_eq!(audits[0].actor, None);
+    }
+
+    #[tokio::test]
+    async fn test_user_name_and_email_are_unique() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let (_, alice_id) = add_test_user(&state, "alice").await;
+        add_test_user(&state, "bob").await;
+        let new_user = |name: &str, email: &str| AddUserRequest {
+            name: name.to_string(),
+            password: "password".to_string(),
+            confirm_password: "password".to_string(),
+            email: email.to_string(),
+            is_admin: false,
+            group_name: "Default".to_string(),
+        };
+
+        let res = state.add_user(new_user("alice", "other@example.org")).await;
+        assert_eq!(res, Some(Err(UserField::Name)));
+        let res = state.add_user(new_user("carol", "Alice@Example.ORG")).await;
+        assert_eq!(res, Some(Err(UserField::Email)));
+        // the users without email do not conflict
+        assert_eq!(state.add_user(new_user("dave", "")).await, Some(Ok(())));
+        assert_eq!(state.add_user(new_user("erin", "")).await, Some(Ok(())));
+
+        // the unique indexes refuse the users added behind the checks
+        let mut conn = sqlx::SqliteConnection::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        let insert = |name: &'static str, email: &'static str| {
+            sqlx::query(
+                "INSERT INTO user(guid, grp, team, status, role, name, password, email)
+                 SELECT ?, grp, team, 1, 0, ?, '', ? FROM user LIMIT 1",
+            )
+                .bind(Uuid::new_v4().as_bytes().to_vec())
+                .bind(name)
+                .bind(email)
+        };
+        let e = insert("frank", "ALICE@example.org").execute(&mut conn).await.unwrap_err();
+        assert_eq!(crate::database::user_unique_violation(&e), Some(UserField::Email));
+        let e = insert("alice", "frank@example.org").execute(&mut conn).await.unwrap_err();
+        assert_eq!(crate::database::user_unique_violation(&e), Some(UserField::Name));
+
+        let update = |name: Option<&str>, email: Option<&str>| UpdateUserRequest {
+            uuid: String::new(),
+            name: name.map(str::to_string),
+            password: None,
+            confirm_password: None,
+            email: email.map(str::to_string),
+            is_admin: None,
+            group_name: None,
+            note: None,
+            status: None,
+        };
+        let res = state
+            .user_update(alice_id.clone(), update(None, Some("BOB@example.org")))
+            .await;
+        assert_eq!(res, Some(Err(UserField::Email)));
+        let res = state.user_update(alice_id.clone(), update(Some("bob"), None)).await;
+        assert_eq!(res, Some(Err(UserField::Name)));
+        // keeping its own email, whatever the case, is not a conflict
+        let res = state
+            .user_update(alice_id, update(Some("alice"), Some("ALICE@example.org")))
+            .await;
+        assert_eq!(res, Some(Ok(())));
+        let users = state.get_all_users(Some("alice"), None, None, 1, 10).await.unwrap();
+        assert_eq!(users[0].email, "ALICE@example.org");
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_and_search_strategies() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        add_test_peer(&db_file, "111111111", None).await;
+        let (_, default) = state
+            .get_strategies(None, StrategyOrder::Default, 0, u32::MAX)
+            .await
+            .unwrap();
+        let default = default.into_iter().next().unwrap();
+        let locked = state
+            .add_strategy("Locked", &serde_json::json!({"enable-clipboard": "N"}), false)
+            .await
+            .unwrap();
+        state
+            .assign_peer_strategy("111111111", Some(&locked.guid))
+            .await
+            .unwrap();
+
+        let copy = state.duplicate_strategy(&default.guid, None).await.unwrap();
+        assert_eq!(copy.name, "Default (copy)");
+        assert!(!copy.is_default);
+        assert_eq!(copy.settings, default.settings);
+        let copy = state.duplicate_strategy(&default.guid, None).await.unwrap();
+        assert_eq!(copy.name, "Default (copy) 2");
+        let copy = state.duplicate_strategy(&locked.guid, Some("Locked")).await.unwrap();
+        assert_eq!(copy.name, "Locked 2");
+        assert_eq!(copy.settings, serde_json::json!({"enable-clipboard": "N"}));
+        // the assignments stay on the copied strategy
+        assert_eq!(state.count_strategy_assignments(&copy.guid).await, Some(0));
+        assert_eq!(state.count_strategy_assignments(&locked.guid).await, Some(1));
+        assert!(state.duplicate_strategy(&Uuid::new_v4().to_string(), None).await.is_none());
+
+        let names = |strategies: Vec<Strategy>| {
+            strategies
+                .into_iter()
+                .map(|strategy| strategy.name)
+                .collect::<Vec<String>>()
+        };
+        let (total, strategies) = state
+            .get_strategies(None, StrategyOrder::Default, 0, u32::MAX)
+            .await
+            .unwrap();
+        assert_eq!(total, 5);
+        assert_eq!(
+            names(strategies),
+            vec!["Default", "Default (copy)", "Default (copy) 2", "Locked", "Locked 2"]
+        );
+        let (total, strategies) = state
+            .get_strategies(Some("COPY"), StrategyOrder::NameDesc, 0, 1)
+            .await
+            .unwrap();
+        assert_eq!(total, 2);
+        assert_eq!(names(strategies), vec!["Default (copy) 2"]);
+        let (total, strategies) = state
+            .get_strategies(Some("%"), StrategyOrder::Name, 0, u32::MAX)
+            .await
+            .unwrap();
+        assert_eq!((total, strategies.len()), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_changes_nothing() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file.clone()).await;
+        let (guid, user_id) = add_test_user(&state, "operator").await;
+        add_test_peer(&db_file, "111111111", None).await;
+        add_test_peer(&db_file, "222222222", None).await;
+        let ids = vec![
+            "111111111".to_string(),
+            "222222222".to_string(),
+            "333333333".to_string(),
+        ];
+
+        let results = state
+            .bulk_peers(PeersBulkAction::Delete, ids.clone(), None, user_id.clone(), true)
+            .await
+            .unwrap();
+        let statuses: Vec<PeersBulkStatus> = results.iter().map(|result| result.result).collect();
+        assert_eq!(
+            statuses,
+            vec![PeersBulkStatus::Ok, PeersBulkStatus::Ok, PeersBulkStatus::NotFound]
+        );
+        assert!(state.get_peer("111111111").await.is_some());
+        assert!(state.get_peer("222222222").await.is_some());
+
+        state
+            .bulk_peers(PeersBulkAction::Delete, ids, None, user_id, false)
+            .await
+            .unwrap();
+        assert!(state.get_peer("111111111").await.is_none());
+
+        // a new user is active
+        assert_eq!(state.user_status_changes(&guid, UserStatus::Normal).await, Some(false));
+        assert_eq!(state.user_status_changes(&guid, UserStatus::Disabled).await, Some(true));
+        assert_eq!(
+            state.user_status_changes(&Uuid::new_v4().to_string(), UserStatus::Disabled).await,
+            Some(false)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idle_tokens_are_revoked() {
+        let mut state = test_state().await;
+        state.idle_timeout = Some(600);
+        add_test_user(&state, "idle").await;
+        add_test_user(&state, "busy").await;
+        let mut tokens = Vec::new();
+        for name in ["idle", "busy"] {
+            let password = UserPasswordInfo::from_password("password");
+            let (_, token) = state.user_login(&name.to_string(), password, false).await.unwrap();
+            tokens.push(token);
+        }
+        let set_last_used = |token: &Token, idle: u64| {
+            let state = &state;
+            let token = token.clone();
+            async move {
+                let mut access_tokens = state.access_tokens.write().await;
+                access_tokens.get_mut(&token).unwrap().last_used = secs_from_epoch() - idle;
+            }
+        };
+        set_last_used(&tokens[0], 601).await;
+        set_last_used(&tokens[1], 599).await;
+        assert!(state.find_session(&tokens[0]).await.is_none());
+        // using the token restarts the idle period
+        assert!(state.find_session(&tokens[1]).await.is_some());
+        let last_used = state.access_tokens.read().await[&tokens[1]].last_used;
+        assert!(last_used >= secs_from_epoch() - 1);
+
+        state.purge_idle_tokens().await;
+        let access_tokens = state.access_tokens.read().await;
+        assert!(!access_tokens.contains_key(&tokens[0]));
+        assert!(access_tokens.contains_key(&tokens[1]));
+        drop(access_tokens);
+        assert_eq!(state.users.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_peers_summary() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        for id in ["100000001", "100000002", "100000003", "100000004"] {
+            add_test_peer(&db_file, id, None).await;
+        }
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        // two recent heartbeats, a stale one and a peer which never reported its sysinfo
+        for (id, os, last_online) in [
+            ("100000001", "windows / Windows 11 Pro", "datetime('now')"),
+            ("100000002", "Windows / Windows 10", "datetime('now', '-10 minutes')"),
+            ("100000003", "linux / Ubuntu 24.04", "datetime('now', '-5 seconds')"),
+        ] {
+            sqlx::query(&format!(
+                "UPDATE peer SET info = json_object('os', ?), last_online = {} WHERE id = ?",
+                last_online
+            ))
+            .bind(os)
+            .bind(id)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let summary = state.get_peers_summary().await.unwrap();
+        assert_eq!((summary.total, summary.online, summary.offline), (4, 2, 2));
+        assert_eq!(
+            summary.by_os,
+            vec![
+                OsSummary { os: "windows".to_string(), total: 2, online: 1, offline: 1 },
+                OsSummary { os: "linux".to_string(), total: 1, online: 1, offline: 0 },
+                OsSummary { os: "unknown".to_string(), total: 1, online: 0, offline: 1 },
+            ]
+        );
+
+        // the summary is cached for a few seconds
+        add_test_peer(&db_file, "100000005", None).await;
+        assert_eq!(state.get_peers_summary().await.unwrap().total, 4);
+        *state.peers_summary.write().await = None;
+        assert_eq!(state.get_peers_summary().await.unwrap().total, 5);
+    }
+
+    #[tokio::test]
+    async fn test_software_releases() {
+        let state = test_state().await;
+        let (_, user_id) = add_test_user(&state, "publisher").await;
+        assert!(state.get_latest_software_releases(ReleaseChannel::Stable).await.is_empty());
+        let uploads = [
+            ("w64", ReleaseChannel::Stable, "1.2.5"),
+            ("osx", ReleaseChannel::Stable, "1.2.5"),
+            ("w64", ReleaseChannel::Stable, "1.2.6"),
+            ("w64", ReleaseChannel::Beta, "1.3.0-beta.1"),
+        ];
+        for (platform, channel, version) in uploads {
+            let key = format!("releases/{}/{}/sctgdesk", platform, version);
+            state
+                .add_software_release(platform, channel, version, &key, 1024, "00ff", user_id.clone())
+                .await
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let releases = state.get_latest_software_releases(ReleaseChannel::Stable).await;
+        let latest: Vec<(&str, &str)> = releases
+            .iter()
+            .map(|release| (release.platform.as_str(), release.version.as_str()))
+            .collect();
+        assert_eq!(latest, vec![("osx", "1.2.5"), ("w64", "1.2.6")]);
+        assert_eq!(releases[1].key, "releases/w64/1.2.6/sctgdesk");
+        assert_eq!(releases[1].channel, ReleaseChannel::Stable);
+        assert_eq!(releases[1].size, 1024);
+        assert_eq!(releases[1].uploaded_by, "publisher");
+
+        // the channels are kept apart
+        let releases = state.get_latest_software_releases(ReleaseChannel::Beta).await;
+        assert_eq!(releases.len(), 1);
+        assert_eq!(releases[0].version, "1.3.0-beta.1");
+        assert_eq!(releases[0].channel, ReleaseChannel::Beta);
+    }
+
+    #[tokio::test]
+    async fn test_ab_alias_conflict() {
+        let mut state = test_state().await;
+        let (_, user_id) = add_test_user(&state, "aliases").await;
+        let ab = state.get_ab_personal_guid(user_id.clone()).await.unwrap();
+        let (_, other_user_id) = add_test_user(&state, "otheraliases").await;
+        let other_ab = state.get_ab_personal_guid(other_user_id).await.unwrap();
+        for (ab, id) in [(&ab, "111111111"), (&other_ab, "222222222")] {
+            let ab_peer = AbPeer {
+                id: id.to_string(),
+                alias: Some("Office PC".to_string()),
+                ..Default::default()
+            };
+            state.add_ab_peer(ab, ab_peer).await.unwrap();
+        }
+
+        // not enforced by default
+        assert_eq!(state.find_ab_alias_conflict(&ab, "333333333", Some("Office PC")).await, None);
+
+        state.unique_aliases = true;
+        assert_eq!(
+            state.find_ab_alias_conflict(&ab, "333333333", Some(" office pc ")).await,
+            Some("111111111".to_string())
+        );
+        // a peer keeps its own alias and the other address books are not checked
+        assert_eq!(state.find_ab_alias_conflict(&ab, "111111111", Some("Office PC")).await, None);
+        assert_eq!(state.find_ab_alias_conflict(&other_ab, "222222222", Some("Office PC")).await, None);
+        assert_eq!(state.find_ab_alias_conflict(&ab, "333333333", Some("Laptop")).await, None);
+        assert_eq!(state.find_ab_alias_conflict(&ab, "333333333", None).await, None);
+        assert_eq!(state.find_ab_alias_conflict(&ab, "333333333", Some("")).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_release_channel_assignment() {
+        let state = test_state().await;
+        let (tester, tester_id) = add_test_user(&state, "tester").await;
+        let (_, colleague_id) = add_test_user(&state, "colleague").await;
+        assert_eq!(state.get_user_release_channel(&tester_id).await, ReleaseChannel::Stable);
+
+        // the channel of the group applies to its users
+        let groups = state.get_groups(0, u32::MAX).await.unwrap();
+        let default = groups.iter().find(|group| group.name == "Default").unwrap().guid.clone();
+        state
+            .assign_group_release_channel(&default, Some(ReleaseChannel::Beta))
+            .await
+            .unwrap();
+        assert_eq!(state.get_user_release_channel(&tester_id).await, ReleaseChannel::Beta);
+        assert_eq!(state.get_user_release_channel(&colleague_id).await, ReleaseChannel::Beta);
+
+        // the channel of the user takes precedence
+        state
+            .assign_user_release_channel(&tester, Some(ReleaseChannel::Stable))
+            .await
+            .unwrap();
+        assert_eq!(state.get_user_release_channel(&tester_id).await, ReleaseChannel::Stable);
+        state.assign_user_release_channel(&tester, None).await.unwrap();
+        assert_eq!(state.get_user_release_channel(&tester_id).await, ReleaseChannel::Beta);
+        state.assign_group_release_channel(&default, None).await.unwrap();
+        assert_eq!(state.get_user_release_channel(&tester_id).await, ReleaseChannel::Stable);
+
+        let unknown = Uuid::new_v4().to_string();
+        assert_eq!(state.assign_user_release_channel(&unknown, Some(ReleaseChannel::Beta)).await, None);
+        assert_eq!(state.assign_group_release_channel(&unknown, Some(ReleaseChannel::Beta)).await, None);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_writes_do_not_fail_busy() {
+        use base64::prelude::{Engine as _, BASE64_STANDARD};
+        use rocket::futures::future::join_all;
+
+        let db_file = test_db_file();
+        let options = DatabaseOptions {
+            pool_size: 16,
+            ..Default::default()
+        };
+        let state = ApiState::new_with_db_options(&db_file, &options).await;
+        let ids: Vec<String> = (0..8).map(|i| format!("70000000{}", i)).collect();
+        for id in &ids {
+            add_test_peer(&db_file, id, None).await;
+        }
+        let mut abs = Vec::new();
+        for i in 0..4 {
+            let (_, user_id) = add_test_user(&state, &format!("busy{}", i)).await;
+            let ab = state.get_ab_personal_guid(user_id).await.unwrap();
+            for id in &ids {
+                let peer = AbPeer {
+                    id: id.clone(),
+                    ..AbPeer::default_test()
+                };
+                state.add_ab_peer(&ab, peer).await.unwrap();
+            }
+            abs.push(ab);
+        }
+
+        let heartbeats = (0..200).map(|i| {
+            let id = &ids[i % ids.len()];
+            let heartbeat = utils::HeartbeatRequest {
+                id: id.clone(),
+                modified_at: 0,
+                uuid: BASE64_STANDARD.encode(id),
+                ver: 0,
+            };
+            state.update_heartbeat(heartbeat, None)
+        });
+        let updates = (0..200).map(|i| {
+            let peer = AbPeer {
+                id: ids[i % ids.len()].clone(),
+                alias: Some(format!("alias {}", i)),
+                ..AbPeer::default_test()
+            };
+            state.update_ab_peer(&abs[i % abs.len()], peer, None)
+        });
+        let reads = (0..50).map(|_| state.get_all_users(None, None, None, 1, 100));
+        let (heartbeats, updates, reads) = tokio::join!(join_all(heartbeats), join_all(updates), join_all(reads));
+        assert!(heartbeats.iter().all(Option::is_some), "a heartbeat failed");
+        assert!(updates.iter().all(|update| *update == Some(true)), "an address book update failed");
+        assert!(reads.iter().all(Option::is_some), "a user list failed");
+    }
+}
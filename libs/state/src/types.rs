@@ -14,7 +14,7 @@ use rocket_okapi::JsonSchema;
 //
 // You should have received a copy of the Affero General Public License
 // along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use utils::UserId;
 
 #[derive(Debug, Clone, Serialize)]
@@ -25,4 +25,40 @@ pub struct UserInfo {
     pub username: String,
     pub password: String,
     pub address_book: String,
-}
\ No newline at end of file
+}
+/// Settings of the sqlite connection pool
+///
+/// The database runs in WAL mode, the readers never wait for the writer
+/// and the writers wait up to `busy_timeout_ms` for each other instead of failing with `SQLITE_BUSY`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DatabaseOptions {
+    /// maximum number of connections of the pool
+    pub pool_size: u32,
+    /// seconds a request waits for a free connection
+    pub acquire_timeout_secs: u64,
+    /// milliseconds a connection waits for the write lock
+    pub busy_timeout_ms: u64,
+    /// number of prepared statements cached by each connection
+    pub statement_cache_size: usize,
+}
+
+impl Default for DatabaseOptions {
+    fn default() -> Self {
+        DatabaseOptions {
+            pool_size: 10,
+            acquire_timeout_secs: 30,
+            busy_timeout_ms: 5000,
+            statement_cache_size: 100,
+        }
+    }
+}
+
+/// Connections of the sqlite pool at a point in time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DatabasePoolStats {
+    /// connections opened, idle or in use
+    pub size: u32,
+    pub idle: u32,
+    pub max: u32,
+}
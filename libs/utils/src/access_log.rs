@@ -0,0 +1,185 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Severity of an access log line, from the status of the response
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AccessLogLevel {
+    Info,
+    /// client errors, 4xx
+    Warn,
+    /// server errors, 5xx
+    Error,
+}
+
+impl AccessLogLevel {
+    pub fn for_status(status: u16) -> Self {
+        match status {
+            500.. => AccessLogLevel::Error,
+            400..=499 => AccessLogLevel::Warn,
+            _ => AccessLogLevel::Info,
+        }
+    }
+
+    /// Parse `info`, `warn` or `error`, whatever the case
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "info" => Some(AccessLogLevel::Info),
+            "warn" | "warning" => Some(AccessLogLevel::Warn),
+            "error" => Some(AccessLogLevel::Error),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AccessLogLevel::Info => "INFO",
+            AccessLogLevel::Warn => "WARN",
+            AccessLogLevel::Error => "ERROR",
+        }
+    }
+}
+
+/// Format of the access log lines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccessLogFormat {
+    /// one JSON object per line
+    #[default]
+    Json,
+    /// `key=value` pairs after the timestamp, the level, the method, the path and the status
+    Text,
+}
+
+impl AccessLogFormat {
+    /// Parse `json` or `text`, whatever the case
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "json" => Some(AccessLogFormat::Json),
+            "text" => Some(AccessLogFormat::Text),
+            _ => None,
+        }
+    }
+}
+
+/// A line of the access log
+/// Neither the query string nor the body of the request are logged, they may hold secrets
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AccessLogEntry {
+    /// end of the request, RFC 3339 in UTC
+    pub timestamp: String,
+    pub level: AccessLogLevel,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub latency_ms: f64,
+    pub client_ip: Option<String>,
+    /// uuid of the authenticated user
+    pub user_id: Option<String>,
+    pub request_id: String,
+}
+
+impl AccessLogEntry {
+    /// The line in the text format, the values which are not known are `-`
+    pub fn to_text(&self) -> String {
+        format!(
+            "{} {} {} {} {} latency_ms={:.3} client_ip={} user_id={} request_id={}",
+            self.timestamp,
+            self.level.as_str(),
+            self.method,
+            self.path,
+            self.status,
+            self.latency_ms,
+            self.client_ip.as_deref().unwrap_or("-"),
+            self.user_id.as_deref().unwrap_or("-"),
+            self.request_id
+        )
+    }
+}
+
+/// Timestamp of the access log lines: now, RFC 3339 in UTC with milliseconds
+pub fn access_log_timestamp() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+/// Parse a comma separated list of paths, e.g. `/api/health, /metrics, /ui/`
+/// The paths are normalized with a leading slash and without a trailing one
+pub fn parse_excluded_paths(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|path| path.trim().trim_matches('/'))
+        .filter(|path| !path.is_empty())
+        .map(|path| format!("/{}", path))
+        .collect()
+}
+
+/// Whether the path is one of the `excluded` paths or below one of them
+/// `/ui` excludes `/ui` and `/ui/index.html` but not `/uinfo`
+pub fn is_excluded_path(path: &str, excluded: &[String]) -> bool {
+    excluded.iter().any(|excluded| {
+        path.strip_prefix(excluded.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_log_level() {
+        assert_eq!(AccessLogLevel::for_status(200), AccessLogLevel::Info);
+        assert_eq!(AccessLogLevel::for_status(304), AccessLogLevel::Info);
+        assert_eq!(AccessLogLevel::for_status(404), AccessLogLevel::Warn);
+        assert_eq!(AccessLogLevel::for_status(503), AccessLogLevel::Error);
+        assert_eq!(AccessLogLevel::parse(" WARN "), Some(AccessLogLevel::Warn));
+        assert_eq!(AccessLogLevel::parse("debug"), None);
+        assert!(AccessLogLevel::Error > AccessLogLevel::Warn);
+        assert_eq!(AccessLogFormat::parse("Text"), Some(AccessLogFormat::Text));
+        assert_eq!(AccessLogFormat::parse("xml"), None);
+    }
+
+    #[test]
+    fn test_excluded_paths() {
+        let excluded = parse_excluded_paths(" /api/heartbeat, metrics/ ,, /ui/ ");
+        assert_eq!(excluded, vec!["/api/heartbeat", "/metrics", "/ui"]);
+        assert!(is_excluded_path("/metrics", &excluded));
+        assert!(is_excluded_path("/ui/assets/index.js", &excluded));
+        assert!(!is_excluded_path("/uinfo", &excluded));
+        assert!(!is_excluded_path("/api/login", &excluded));
+        assert!(!is_excluded_path("/metrics", &[]));
+    }
+
+    #[test]
+    fn test_access_log_text() {
+        let entry = AccessLogEntry {
+            timestamp: "2024-05-01T12:00:00.000Z".to_string(),
+            level: AccessLogLevel::Warn,
+            method: "GET".to_string(),
+            path: "/api/peer/123".to_string(),
+            status: 404,
+            latency_ms: 1.5,
+            client_ip: Some("192.0.2.10".to_string()),
+            user_id: None,
+            request_id: "id".to_string(),
+        };
+        assert_eq!(
+            entry.to_text(),
+            "2024-05-01T12:00:00.000Z WARN GET /api/peer/123 404 latency_ms=1.500 client_ip=192.0.2.10 user_id=- request_id=id"
+        );
+    }
+}
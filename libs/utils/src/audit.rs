@@ -0,0 +1,810 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use chrono::{NaiveDateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::peer_export::csv_field;
+use crate::{AuditAdmin, AuditAlarm, AuditConn, AuditFile, AuditLogin, AuditRequest};
+
+/// Action of a connection audit
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnAuditAction {
+    /// the remote peer connected
+    #[serde(rename = "new")]
+    Open,
+    /// the remote peer was authorized
+    Auth,
+    /// the connection was closed
+    Close,
+}
+
+impl ConnAuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnAuditAction::Open => "new",
+            ConnAuditAction::Auth => "auth",
+            ConnAuditAction::Close => "close",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConnAudit {
+    pub peer: String,
+    pub conn_id: i64,
+    pub session_id: Option<String>,
+    pub action: ConnAuditAction,
+    #[serde(rename = "type")]
+    pub conn_type: Option<i64>,
+    pub remote: Option<String>,
+    pub remote_name: Option<String>,
+    pub ip: Option<String>,
+    /// duration in seconds, given by the close record
+    pub duration: Option<i64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileAudit {
+    pub peer: String,
+    pub remote: Option<String>,
+    /// 0 send, 1 receive
+    pub direction: i64,
+    /// path of the file or directory, stored verbatim
+    pub path: String,
+    pub is_file: bool,
+    pub ip: Option<String>,
+    /// number of files transferred
+    pub files: i64,
+    /// total size of the files in bytes
+    pub size: i64,
+}
+
+/// Types of the alarms sent by the RustDesk clients
+pub const ALARM_IP_WHITELIST: i64 = 0;
+pub const ALARM_EXCEED_THIRTY_ATTEMPTS: i64 = 1;
+pub const ALARM_SIX_ATTEMPTS_WITHIN_ONE_MINUTE: i64 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlarmSeverity {
+    Info,
+    Warning,
+    Critical,
+}
+
+impl AlarmSeverity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AlarmSeverity::Info => "info",
+            AlarmSeverity::Warning => "warning",
+            AlarmSeverity::Critical => "critical",
+        }
+    }
+
+    pub fn parse(severity: &str) -> Option<Self> {
+        match severity.to_lowercase().as_str() {
+            "info" => Some(AlarmSeverity::Info),
+            "warning" => Some(AlarmSeverity::Warning),
+            "critical" => Some(AlarmSeverity::Critical),
+            _ => None,
+        }
+    }
+
+    /// Severity of an alarm type when the client does not give one
+    pub fn of_alarm_type(alarm_type: i64) -> Self {
+        match alarm_type {
+            ALARM_EXCEED_THIRTY_ATTEMPTS => AlarmSeverity::Critical,
+            _ => AlarmSeverity::Warning,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AlarmAudit {
+    pub peer: String,
+    #[serde(rename = "type")]
+    pub alarm_type: i64,
+    pub severity: AlarmSeverity,
+    /// id of the remote peer which triggered the alarm
+    pub remote: Option<String>,
+    /// name of the remote peer which triggered the alarm
+    pub remote_name: Option<String>,
+    pub ip: Option<String>,
+}
+
+/// An audit record sent by a client, parsed into its real kind
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum AuditEvent {
+    Conn(ConnAudit),
+    File(FileAudit),
+    Alarm(AlarmAudit),
+}
+
+impl AuditEvent {
+    pub fn category(&self) -> AuditCategory {
+        match self {
+            AuditEvent::Conn(_) => AuditCategory::Conn,
+            AuditEvent::File(_) => AuditCategory::File,
+            AuditEvent::Alarm(_) => AuditCategory::Alarm,
+        }
+    }
+}
+
+/// Category of the forwarded operations made by the admins, they have no retention
+pub const AUDIT_FORWARD_ADMIN: &str = "admin";
+
+/// An audit record once persisted, as sent to the audit forwarder sinks
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ForwardedAudit {
+    /// `conn`, `file`, `alarm`, `login` or `admin`
+    pub category: String,
+    /// RFC 3339 UTC
+    pub created_at: String,
+    pub record: Value,
+}
+
+impl ForwardedAudit {
+    pub fn new(category: &str, record: Value) -> Self {
+        ForwardedAudit {
+            category: category.to_string(),
+            created_at: Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            record,
+        }
+    }
+
+    /// Format the record as a RFC 5424 syslog message, facility "log audit"
+    /// The message is the JSON of the record, its severity follows the severity of the alarms
+    pub fn syslog_message(&self, hostname: &str) -> String {
+        // facility 13 is log audit
+        let facility = 13;
+        let severity = match self.record.get("severity").and_then(Value::as_str) {
+            Some("critical") => 2,
+            Some("warning") => 4,
+            _ if self.category == AUDIT_FORWARD_ADMIN => 5,
+            _ => 6,
+        };
+        let hostname = if hostname.is_empty() { "-" } else { hostname };
+        format!(
+            "<{}>1 {} {} sctgdesk-api-server - {} - {}",
+            facility * 8 + severity,
+            self.created_at,
+            hostname,
+            self.category,
+            serde_json::to_string(self).unwrap_or_default()
+        )
+    }
+}
+
+/// Operations of the admins recorded in the admin audit trail
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AdminAction {
+    UserCreate,
+    UserUpdate,
+    UserEnable,
+    UserDisable,
+    GroupCreate,
+    GroupUpdate,
+    GroupDelete,
+    PeerAssign,
+    PeerTransfer,
+    PeerAvatarUpdate,
+    PeersBulk,
+    PeerConflictsDelete,
+    OidcReload,
+    AuditRetentionUpdate,
+    AbRuleAdd,
+    AbRuleDelete,
+    AbSharedAdd,
+    AbSharedDelete,
+    AbSharedUpdate,
+    StrategyCreate,
+    StrategyUpdate,
+    StrategyDelete,
+    MaintenanceNotice,
+    PeerStrategyAssign,
+    UserStrategyAssign,
+    GroupStrategyAssign,
+    SoftwareCacheClear,
+    ClientVersionsUpdate,
+    SoftwareReleaseUpload,
+    UserReleaseChannelAssign,
+    GroupReleaseChannelAssign,
+    SessionsRevokeAll,
+    UiDefaultsUpdate,
+    PeerShareLinkCreate,
+    PeerShareLinkRedeem,
+}
+
+impl AdminAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AdminAction::UserCreate => "user.create",
+            AdminAction::UserUpdate => "user.update",
+            AdminAction::UserEnable => "user.enable",
+            AdminAction::UserDisable => "user.disable",
+            AdminAction::GroupCreate => "group.create",
+            AdminAction::GroupUpdate => "group.update",
+            AdminAction::GroupDelete => "group.delete",
+            AdminAction::PeerAssign => "peer.assign",
+            AdminAction::PeerTransfer => "peer.transfer",
+            AdminAction::PeerAvatarUpdate => "peer.avatar.update",
+            AdminAction::PeersBulk => "peers.bulk",
+            AdminAction::PeerConflictsDelete => "peer.conflicts.delete",
+            AdminAction::OidcReload => "oidc.reload",
+            AdminAction::AuditRetentionUpdate => "audit.retention.update",
+            AdminAction::AbRuleAdd => "ab.rule.add",
+            AdminAction::AbRuleDelete => "ab.rule.delete",
+            AdminAction::AbSharedAdd => "ab.shared.add",
+            AdminAction::AbSharedDelete => "ab.shared.delete",
+            AdminAction::AbSharedUpdate => "ab.shared.update",
+            AdminAction::StrategyCreate => "strategy.create",
+            AdminAction::StrategyUpdate => "strategy.update",
+            AdminAction::StrategyDelete => "strategy.delete",
+            AdminAction::MaintenanceNotice => "maintenance.notice",
+            AdminAction::PeerStrategyAssign => "peer.strategy.assign",
+            AdminAction::UserStrategyAssign => "user.strategy.assign",
+            AdminAction::GroupStrategyAssign => "group.strategy.assign",
+            AdminAction::SoftwareCacheClear => "software.cache.clear",
+            AdminAction::ClientVersionsUpdate => "software.version.update",
+            AdminAction::SoftwareReleaseUpload => "software.release.upload",
+            AdminAction::UserReleaseChannelAssign => "user.release_channel.assign",
+            AdminAction::GroupReleaseChannelAssign => "group.release_channel.assign",
+            AdminAction::SessionsRevokeAll => "sessions.revoke_all",
+            AdminAction::UiDefaultsUpdate => "settings.ui.defaults.update",
+            AdminAction::PeerShareLinkCreate => "peer.share_link.create",
+            AdminAction::PeerShareLinkRedeem => "peer.share_link.redeem",
+        }
+    }
+
+    /// The route of the operation
+    pub fn route(&self) -> &'static str {
+        match self {
+            AdminAction::UserCreate => "POST /api/user",
+            AdminAction::UserUpdate => "PUT /api/user",
+            AdminAction::UserEnable | AdminAction::UserDisable => "POST /api/enable-users",
+            AdminAction::GroupCreate => "POST /api/group",
+            AdminAction::GroupUpdate => "PUT /api/group",
+            AdminAction::GroupDelete => "DELETE /api/group/<guid>",
+            AdminAction::PeerAssign => "PUT /api/peer/<id>",
+            AdminAction::PeerTransfer => "POST /api/peer/<id>/transfer",
+            AdminAction::PeerAvatarUpdate => "PUT /api/peer/<id>/avatar",
+            AdminAction::PeersBulk => "POST /api/peers/bulk",
+            AdminAction::PeerConflictsDelete => "DELETE /api/peers/conflicts/<id>",
+            AdminAction::OidcReload => "POST /api/oidc/reload",
+            AdminAction::AuditRetentionUpdate => "PUT /api/audit/retention",
+            AdminAction::AbRuleAdd => "POST /api/ab/rule",
+            AdminAction::AbRuleDelete => "DELETE /api/ab/rule",
+            AdminAction::AbSharedAdd => "POST /api/ab/shared/add",
+            AdminAction::AbSharedDelete => "DELETE /api/ab/shared",
+            AdminAction::AbSharedUpdate => "PUT /api/ab/shared/update/profile",
+            AdminAction::StrategyCreate => "POST /api/strategy",
+            AdminAction::StrategyUpdate => "PUT /api/strategy/<id>",
+            AdminAction::StrategyDelete => "DELETE /api/strategy/<id>",
+            AdminAction::MaintenanceNotice => "PUT /api/admin/maintenance",
+            AdminAction::PeerStrategyAssign => "PUT /api/peer/<id>/strategy",
+            AdminAction::UserStrategyAssign => "PUT /api/users/<id>/strategy",
+            AdminAction::GroupStrategyAssign => "PUT /api/group/<guid>/strategy",
+            AdminAction::SoftwareCacheClear => "DELETE /api/software/cache",
+            AdminAction::ClientVersionsUpdate => "PUT /api/software/version",
+            AdminAction::SoftwareReleaseUpload => "POST /api/software/releases",
+            AdminAction::UserReleaseChannelAssign => "PUT /api/users/<id>/release-channel",
+            AdminAction::GroupReleaseChannelAssign => "PUT /api/group/<guid>/release-channel",
+            AdminAction::SessionsRevokeAll => "POST /api/admin/revoke-all-sessions",
+            AdminAction::UiDefaultsUpdate => "PUT /api/settings/ui/defaults",
+            AdminAction::PeerShareLinkCreate => "POST /api/peer/<id>/share-link",
+            AdminAction::PeerShareLinkRedeem => "GET /api/share/<token>",
+        }
+    }
+}
+
+/// Replacement of the sensitive values in the details of the admin audit records
+pub const AUDIT_REDACTED: &str = "[redacted]";
+
+/// Redact the sensitive values of the details of an admin audit record
+/// The values of the keys containing `password`, `secret` or `token`, whatever their case, are replaced,
+/// at any depth
+pub fn redact_audit_details(details: Value) -> Value {
+    match details {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| {
+                    let lower = key.to_lowercase();
+                    if ["password", "secret", "token"]
+                        .iter()
+                        .any(|sensitive| lower.contains(sensitive))
+                    {
+                        (key, Value::String(AUDIT_REDACTED.to_string()))
+                    } else {
+                        (key, redact_audit_details(value))
+                    }
+                })
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(redact_audit_details).collect()),
+        value => value,
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    if value.is_empty() {
+        None
+    } else {
+        Some(value.to_string())
+    }
+}
+
+/// The clients send `info` as a JSON encoded string, decode it when possible
+pub fn audit_info(info: &Option<Value>) -> Value {
+    match info {
+        Some(Value::String(info)) => {
+            serde_json::from_str(info).unwrap_or_else(|_| Value::String(info.clone()))
+        }
+        Some(info) => info.clone(),
+        None => Value::Null,
+    }
+}
+
+/// Parse an audit record
+///
+/// # Arguments
+/// * `kind` - `conn`, `file` or `alarm` as given in the url, guessed from the fields if None
+/// * `request` - the audit record
+///
+/// # Returns
+/// None if the kind is unknown or if a mandatory field is missing
+pub fn parse_audit(kind: Option<&str>, request: &AuditRequest) -> Option<AuditEvent> {
+    let kind = match kind {
+        Some(kind) => kind,
+        None if request.typ.is_some() => "alarm",
+        None if request.path.is_some() || request.peer_id.is_some() => "file",
+        None => "conn",
+    };
+    let peer = non_empty(&request.id)?;
+    let info = audit_info(&request.info);
+    let info_ip = info.get("ip").and_then(Value::as_str).and_then(non_empty);
+    let ip = non_empty(&request.ip).or(info_ip);
+    match kind {
+        "conn" => {
+            let action = match request.action.as_str() {
+                "new" => ConnAuditAction::Open,
+                "close" => ConnAuditAction::Close,
+                "" if request.peer.is_some() => ConnAuditAction::Auth,
+                _ => return None,
+            };
+            let remote = request.peer.as_ref().and_then(|peer| peer.first().cloned());
+            let remote_name = request.peer.as_ref().and_then(|peer| peer.get(1).cloned());
+            Some(AuditEvent::Conn(ConnAudit {
+                peer,
+                conn_id: request.conn_id?,
+                session_id: request.session_id.map(|session_id| session_id.to_string()),
+                action,
+                conn_type: request.type_,
+                remote,
+                remote_name,
+                ip,
+                duration: request.duration,
+            }))
+        }
+        "file" => {
+            // the files are listed in the info as [name, size] pairs
+            let files = info.get("files").and_then(Value::as_array);
+            let size = files
+                .map(|files| {
+                    files
+                        .iter()
+                        .filter_map(|file| file.get(1).and_then(Value::as_i64))
+                        .sum()
+                })
+                .unwrap_or(0);
+            let count = info
+                .get("num")
+                .and_then(Value::as_i64)
+                .or(files.map(|files| files.len() as i64))
+                .unwrap_or(0);
+            Some(AuditEvent::File(FileAudit {
+                peer,
+                remote: request.peer_id.clone(),
+                direction: request.type_.unwrap_or(0),
+                path: request.path.clone()?,
+                is_file: request.is_file.unwrap_or(true),
+                ip,
+                files: count,
+                size,
+            }))
+        }
+        "alarm" => {
+            let alarm_type = request.typ?;
+            let severity = request
+                .extra
+                .get("severity")
+                .and_then(Value::as_str)
+                .and_then(AlarmSeverity::parse)
+                .unwrap_or_else(|| AlarmSeverity::of_alarm_type(alarm_type));
+            let info_str = |key: &str| info.get(key).and_then(Value::as_str).and_then(non_empty);
+            Some(AuditEvent::Alarm(AlarmAudit {
+                peer,
+                alarm_type,
+                severity,
+                remote: info_str("id"),
+                remote_name: info_str("name"),
+                ip,
+            }))
+        }
+        _ => None,
+    }
+}
+
+/// Default number of audit records per page
+pub const AUDIT_DEFAULT_PAGE_SIZE: u32 = 20;
+
+/// Filters of the audit queries
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuditFilter {
+    /// keep the records created at or after this date or time
+    pub from: Option<String>,
+    /// keep the records created at or before this date or time
+    pub to: Option<String>,
+    /// keep the records of this peer id
+    pub peer: Option<String>,
+    /// keep the records of the peers assigned to this user name
+    pub user: Option<String>,
+    /// page number, starting at 1
+    pub current: u32,
+    pub page_size: u32,
+}
+
+impl AuditFilter {
+    /// Build the filters from the query parameters, empty parameters are ignored
+    pub fn new(
+        from: Option<String>,
+        to: Option<String>,
+        peer: Option<String>,
+        user: Option<String>,
+        current: Option<u32>,
+        page_size: Option<u32>,
+    ) -> Self {
+        let non_empty = |value: Option<String>| value.filter(|value| !value.is_empty());
+        AuditFilter {
+            from: non_empty(from),
+            // a date alone includes the whole day
+            to: non_empty(to).map(|to| {
+                if to.len() == 10 {
+                    format!("{} 23:59:59.999", to)
+                } else {
+                    to
+                }
+            }),
+            peer: non_empty(peer),
+            user: non_empty(user),
+            current: current.unwrap_or(1).max(1),
+            page_size: page_size.filter(|size| *size > 0).unwrap_or(AUDIT_DEFAULT_PAGE_SIZE),
+        }
+    }
+
+    pub fn offset(&self) -> u32 {
+        (self.current - 1) * self.page_size
+    }
+}
+
+/// Default number of days the audit records are kept, when neither an admin nor `AUDIT_RETENTION_DAYS` set it
+pub const AUDIT_RETENTION_DEFAULT_DAYS: u32 = 90;
+/// Longest retention accepted, about ten years
+pub const AUDIT_RETENTION_MAX_DAYS: u32 = 3650;
+
+/// Categories of audit records, each one has its own retention
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuditCategory {
+    Conn,
+    File,
+    Alarm,
+    /// the sessions opened by the users
+    Login,
+    /// the operations of the admins, with the operations on the peers recorded by the console
+    Admin,
+}
+
+impl AuditCategory {
+    pub const ALL: [AuditCategory; 5] = [
+        AuditCategory::Conn,
+        AuditCategory::File,
+        AuditCategory::Alarm,
+        AuditCategory::Login,
+        AuditCategory::Admin,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditCategory::Conn => "conn",
+            AuditCategory::File => "file",
+            AuditCategory::Alarm => "alarm",
+            AuditCategory::Login => "login",
+            AuditCategory::Admin => "admin",
+        }
+    }
+
+    pub fn parse(category: &str) -> Option<Self> {
+        AuditCategory::ALL
+            .into_iter()
+            .find(|known| known.as_str() == category)
+    }
+
+    /// Columns of the CSV export, in the order of `AuditRecord::csv_record`
+    pub fn csv_header(&self) -> &'static str {
+        match self {
+            AuditCategory::Conn => {
+                "peer,conn_id,session_id,type,remote,remote_name,ip,user,start,end,duration\r\n"
+            }
+            AuditCategory::File => {
+                "peer,remote,direction,path,is_file,ip,files,size,user,created_at\r\n"
+            }
+            AuditCategory::Alarm => {
+                "peer,type,severity,remote,remote_name,ip,user,created_at\r\n"
+            }
+            AuditCategory::Login => "user,ip,device_name,os,type,created_at,expiry_at\r\n",
+            AuditCategory::Admin => "actor,action,route,target,details,created_at\r\n",
+        }
+    }
+}
+
+/// Largest number of records of an audit export
+pub const AUDIT_EXPORT_MAX_ROWS: u32 = 1_000_000;
+
+/// An audit record of any category, as exported
+#[derive(Debug, Clone)]
+pub enum AuditRecord {
+    Conn(AuditConn),
+    File(AuditFile),
+    Alarm(AuditAlarm),
+    Login(AuditLogin),
+    Admin(AuditAdmin),
+}
+
+/// Convert a date stored by sqlite (UTC, `YYYY-MM-DD HH:MM:SS[.SSS]`) to RFC 3339
+/// The date is returned unchanged if it cannot be parsed
+pub fn rfc3339_utc(date: &str) -> String {
+    match NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S%.f") {
+        Ok(date) => date.and_utc().to_rfc3339_opts(SecondsFormat::Millis, true),
+        Err(_) => date.to_string(),
+    }
+}
+
+impl AuditRecord {
+    /// Format the record as a CSV record terminated by CRLF, the dates in RFC 3339 UTC
+    pub fn csv_record(&self) -> String {
+        let text = |value: &Option<String>| value.clone().unwrap_or_default();
+        let number = |value: Option<i64>| value.map(|value| value.to_string()).unwrap_or_default();
+        let date = |value: &Option<String>| value.as_deref().map(rfc3339_utc).unwrap_or_default();
+        let fields = match self {
+            AuditRecord::Conn(conn) => vec![
+                conn.peer.clone(),
+                conn.conn_id.to_string(),
+                text(&conn.session_id),
+                number(conn.conn_type),
+                text(&conn.remote),
+                text(&conn.remote_name),
+                text(&conn.ip),
+                text(&conn.user),
+                rfc3339_utc(&conn.start),
+                date(&conn.end),
+                number(conn.duration),
+            ],
+            AuditRecord::File(file) => vec![
+                file.peer.clone(),
+                text(&file.remote),
+                file.direction.to_string(),
+                file.path.clone(),
+                file.is_file.to_string(),
+                text(&file.ip),
+                file.files.to_string(),
+                file.size.to_string(),
+                text(&file.user),
+                rfc3339_utc(&file.created_at),
+            ],
+            AuditRecord::Alarm(alarm) => vec![
+                alarm.peer.clone(),
+                alarm.alarm_type.to_string(),
+                alarm.severity.clone(),
+                text(&alarm.remote),
+                text(&alarm.remote_name),
+                text(&alarm.ip),
+                text(&alarm.user),
+                rfc3339_utc(&alarm.created_at),
+            ],
+            AuditRecord::Login(login) => vec![
+                text(&login.user),
+                text(&login.ip),
+                text(&login.device_name),
+                text(&login.os),
+                text(&login.client_type),
+                rfc3339_utc(&login.created_at),
+                rfc3339_utc(&login.expiry_at),
+            ],
+            AuditRecord::Admin(admin) => vec![
+                admin.actor.clone().unwrap_or_else(|| admin.actor_id.clone()),
+                admin.action.clone(),
+                admin.route.clone(),
+                text(&admin.target),
+                admin.details.to_string(),
+                rfc3339_utc(&admin.created_at),
+            ],
+        };
+        let mut record = fields
+            .iter()
+            .map(|field| csv_field(field))
+            .collect::<Vec<String>>()
+            .join(",");
+        record.push_str("\r\n");
+        record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(json: &str) -> AuditRequest {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn test_parse_conn_audit() {
+        let open = request(
+            r#"{"id":"123456789","uuid":"AQI=","conn_id":3,"session_id":42,"ip":"10.0.0.1","action":"new"}"#,
+        );
+        let event = parse_audit(Some("conn"), &open).unwrap();
+        let AuditEvent::Conn(conn) = event else { panic!("not a connection") };
+        assert_eq!(conn.action, ConnAuditAction::Open);
+        assert_eq!(conn.conn_id, 3);
+        assert_eq!(conn.session_id.as_deref(), Some("42"));
+        assert_eq!(conn.ip.as_deref(), Some("10.0.0.1"));
+
+        let auth = request(
+            r#"{"id":"123456789","uuid":"AQI=","conn_id":3,"session_id":42,"peer":["987654321","bob"],"type":0}"#,
+        );
+        let AuditEvent::Conn(conn) = parse_audit(None, &auth).unwrap() else { panic!("not a connection") };
+        assert_eq!(conn.action, ConnAuditAction::Auth);
+        assert_eq!(conn.remote.as_deref(), Some("987654321"));
+        assert_eq!(conn.remote_name.as_deref(), Some("bob"));
+        assert_eq!(conn.conn_type, Some(0));
+    }
+
+    #[test]
+    fn test_parse_file_and_alarm_audit() {
+        let file = request(
+            r#"{"id":"123456789","uuid":"AQI=","peer_id":"987654321","type":1,"path":"C:\\Users\\été 文件","is_file":false,"info":"{\"ip\":\"10.0.0.2\",\"name\":\"bob\",\"num\":2,\"files\":[[\"a.txt\",100],[\"b.txt\",23]]}"}"#,
+        );
+        let AuditEvent::File(file) = parse_audit(None, &file).unwrap() else { panic!("not a file") };
+        assert_eq!(file.direction, 1);
+        assert_eq!(file.path, "C:\\Users\\été 文件");
+        assert_eq!(file.ip.as_deref(), Some("10.0.0.2"));
+        assert!(!file.is_file);
+        assert_eq!(file.files, 2);
+        assert_eq!(file.size, 123);
+
+        let alarm = request(r#"{"id":"123456789","uuid":"AQI=","typ":1,"info":"{\"ip\":\"10.0.0.3\",\"id\":\"987654321\"}"}"#);
+        let AuditEvent::Alarm(alarm) = parse_audit(Some("alarm"), &alarm).unwrap() else { panic!("not an alarm") };
+        assert_eq!(alarm.alarm_type, 1);
+        assert_eq!(alarm.severity, AlarmSeverity::Critical);
+        assert_eq!(alarm.remote.as_deref(), Some("987654321"));
+        assert_eq!(alarm.ip.as_deref(), Some("10.0.0.3"));
+
+        // the severity sent by the client wins, an unknown one is ignored
+        let alarm = request(r#"{"id":"123456789","typ":2,"severity":"Critical"}"#);
+        assert!(alarm.extra.contains_key("severity"));
+        let AuditEvent::Alarm(alarm) = parse_audit(None, &alarm).unwrap() else { panic!("not an alarm") };
+        assert_eq!(alarm.severity, AlarmSeverity::Critical);
+        let alarm = request(r#"{"id":"123456789","typ":2,"severity":"high"}"#);
+        let AuditEvent::Alarm(alarm) = parse_audit(None, &alarm).unwrap() else { panic!("not an alarm") };
+        assert_eq!(alarm.severity, AlarmSeverity::Warning);
+
+        assert!(parse_audit(Some("unknown"), &request(r#"{"id":"123456789"}"#)).is_none());
+        assert!(parse_audit(Some("conn"), &request(r#"{"id":"123456789","action":"new"}"#)).is_none());
+    }
+
+    #[test]
+    fn test_audit_filter() {
+        let filter = AuditFilter::new(
+            Some("2024-05-01".to_string()),
+            Some("2024-05-02".to_string()),
+            Some(String::new()),
+            None,
+            Some(3),
+            None,
+        );
+        assert_eq!(filter.from.as_deref(), Some("2024-05-01"));
+        assert_eq!(filter.to.as_deref(), Some("2024-05-02 23:59:59.999"));
+        assert_eq!(filter.peer, None);
+        assert_eq!(filter.offset(), 2 * AUDIT_DEFAULT_PAGE_SIZE);
+
+        let filter = AuditFilter::new(None, None, None, None, Some(0), Some(0));
+        assert_eq!(filter.current, 1);
+        assert_eq!(filter.offset(), 0);
+    }
+
+    #[test]
+    fn test_rfc3339_utc() {
+        assert_eq!(rfc3339_utc("2024-05-01 12:00:00"), "2024-05-01T12:00:00.000Z");
+        assert_eq!(rfc3339_utc("2024-05-01 12:00:00.125"), "2024-05-01T12:00:00.125Z");
+        assert_eq!(rfc3339_utc("yesterday"), "yesterday");
+    }
+
+    #[test]
+    fn test_audit_csv_record() {
+        let conn = AuditRecord::Conn(AuditConn {
+            peer: "123456789".to_string(),
+            conn_id: 7,
+            session_id: None,
+            conn_type: Some(0),
+            remote: Some("987654321".to_string()),
+            remote_name: Some("Bob, \"the admin\"".to_string()),
+            ip: Some("10.0.0.1".to_string()),
+            peer_name: None,
+            peer_deleted: true,
+            user: None,
+            user_id: None,
+            user_deleted: false,
+            start: "2024-05-01 12:00:00.000".to_string(),
+            end: None,
+            duration: None,
+        });
+        assert_eq!(
+            conn.csv_record(),
+            "123456789,7,,0,987654321,\"Bob, \"\"the admin\"\"\",10.0.0.1,,2024-05-01T12:00:00.000Z,,\r\n"
+        );
+    }
+
+    #[test]
+    fn test_syslog_message() {
+        let audit = ForwardedAudit {
+            category: "alarm".to_string(),
+            created_at: "2024-05-01T12:00:00.000Z".to_string(),
+            record: serde_json::json!({"peer": "123456789", "severity": "critical"}),
+        };
+        assert_eq!(
+            audit.syslog_message("desk"),
+            "<106>1 2024-05-01T12:00:00.000Z desk sctgdesk-api-server - alarm - \
+             {\"category\":\"alarm\",\"created_at\":\"2024-05-01T12:00:00.000Z\",\"record\":{\"peer\":\"123456789\",\"severity\":\"critical\"}}"
+        );
+        let login = ForwardedAudit::new("login", serde_json::json!({"user": "admin"}));
+        assert!(login.syslog_message("").starts_with("<110>1 "));
+        assert!(login.syslog_message("").contains(" - sctgdesk-api-server - login - "));
+    }
+
+    #[test]
+    fn test_redact_audit_details() {
+        let details = serde_json::json!({
+            "name": "bob",
+            "password": "secret1",
+            "confirm-password": "secret1",
+            "provider": {"client_id": "id", "Client_Secret": "s3cr3t"},
+            "sessions": [{"access_token": "abc", "ip": "10.0.0.1"}],
+        });
+        assert_eq!(
+            redact_audit_details(details),
+            serde_json::json!({
+                "name": "bob",
+                "password": "[redacted]",
+                "confirm-password": "[redacted]",
+                "provider": {"client_id": "id", "Client_Secret": "[redacted]"},
+                "sessions": [{"access_token": "[redacted]", "ip": "10.0.0.1"}],
+            })
+        );
+        assert_eq!(AdminAction::UserDisable.route(), "POST /api/enable-users");
+    }
+}
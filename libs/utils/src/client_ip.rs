@@ -0,0 +1,189 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::env;
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome, Request};
+use rocket_okapi::gen::OpenApiGenerator;
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
+use rocket_okapi::OpenApiError;
+
+/// Parse a comma separated list of trusted proxies
+/// Each entry can be a CIDR (10.0.0.0/8) or a single address (127.0.0.1)
+/// Invalid entries are ignored
+pub fn parse_trusted_proxies(trusted_proxies: &str) -> Vec<IpNet> {
+    trusted_proxies
+        .split(',')
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let net = entry
+                .parse::<IpNet>()
+                .or_else(|_| entry.parse::<IpAddr>().map(IpNet::from));
+            if net.is_err() {
+                log::warn!("TRUSTED_PROXIES: ignoring invalid entry {}", entry);
+            }
+            net.ok()
+        })
+        .collect()
+}
+
+/// Get the trusted proxies from the `TRUSTED_PROXIES` environment variable
+/// No proxy is trusted by default
+pub fn get_trusted_proxies() -> Vec<IpNet> {
+    parse_trusted_proxies(env::var("TRUSTED_PROXIES").unwrap_or_default().as_str())
+}
+
+pub(crate) fn is_trusted(ip: &IpAddr, trusted_proxies: &[IpNet]) -> bool {
+    trusted_proxies.iter().any(|net| net.contains(ip))
+}
+
+/// Resolve the real client ip
+///
+/// The `X-Forwarded-For` and `X-Real-IP` headers are only used when the immediate peer
+/// is a trusted proxy, otherwise the socket address is returned.
+/// `X-Forwarded-For` is read from right to left and the first address which is not
+/// a trusted proxy is the client address.
+///
+/// # Arguments
+/// * `remote` - the socket address of the immediate peer
+/// * `forwarded_for` - the `X-Forwarded-For` header value
+/// * `real_ip` - the `X-Real-IP` header value
+/// * `trusted_proxies` - the trusted proxies
+pub fn resolve_client_ip(
+    remote: Option<IpAddr>,
+    forwarded_for: Option<&str>,
+    real_ip: Option<&str>,
+    trusted_proxies: &[IpNet],
+) -> Option<IpAddr> {
+    let remote = remote?;
+    if !is_trusted(&remote, trusted_proxies) {
+        return Some(remote);
+    }
+    if let Some(forwarded_for) = forwarded_for {
+        let chain: Vec<IpAddr> = forwarded_for
+            .split(',')
+            .filter_map(|ip| ip.trim().parse::<IpAddr>().ok())
+            .collect();
+        if let Some(ip) = chain
+            .iter()
+            .rev()
+            .find(|ip| !is_trusted(ip, trusted_proxies))
+        {
+            return Some(*ip);
+        }
+        if let Some(ip) = chain.first() {
+            return Some(*ip);
+        }
+    }
+    if let Some(ip) = real_ip.and_then(|ip| ip.trim().parse::<IpAddr>().ok()) {
+        return Some(ip);
+    }
+    Some(remote)
+}
+
+/// # ClientIp
+///
+/// Request guard resolving the real client ip, taking the trusted proxies into account
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClientIp(pub IpAddr);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ClientIp {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let headers = request.headers();
+        let ip = resolve_client_ip(
+            request.remote().map(|remote| remote.ip()),
+            headers.get_one("X-Forwarded-For"),
+            headers.get_one("X-Real-IP"),
+            &get_trusted_proxies(),
+        );
+        match ip {
+            Some(ip) => Outcome::Success(ClientIp(ip)),
+            None => Outcome::Forward(Status::BadRequest),
+        }
+    }
+}
+
+impl OpenApiFromRequest<'_> for ClientIp {
+    fn from_request_input(
+        _: &mut OpenApiGenerator,
+        _: String,
+        _: bool,
+    ) -> Result<RequestHeaderInput, OpenApiError> {
+        Ok(RequestHeaderInput::None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn test_parse_trusted_proxies() {
+        let trusted = parse_trusted_proxies("10.0.0.0/8, 127.0.0.1,invalid,,::1");
+        assert_eq!(trusted.len(), 3);
+        assert!(is_trusted(&ip("10.1.2.3"), &trusted));
+        assert!(is_trusted(&ip("127.0.0.1"), &trusted));
+        assert!(is_trusted(&ip("::1"), &trusted));
+        assert!(!is_trusted(&ip("192.168.1.1"), &trusted));
+    }
+
+    #[test]
+    fn test_untrusted_proxy_headers_are_ignored() {
+        let trusted = parse_trusted_proxies("10.0.0.0/8");
+        let res = resolve_client_ip(
+            Some(ip("203.0.113.7")),
+            Some("198.51.100.1"),
+            Some("198.51.100.2"),
+            &trusted,
+        );
+        assert_eq!(res, Some(ip("203.0.113.7")));
+        // nothing is trusted by default
+        let res = resolve_client_ip(Some(ip("10.0.0.1")), Some("198.51.100.1"), None, &[]);
+        assert_eq!(res, Some(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn test_trusted_proxy_forwarded_for() {
+        let trusted = parse_trusted_proxies("10.0.0.0/8");
+        // the client may spoof the leftmost entries, the last untrusted hop wins
+        let res = resolve_client_ip(
+            Some(ip("10.0.0.1")),
+            Some("1.2.3.4, 198.51.100.1, 10.0.0.2"),
+            None,
+            &trusted,
+        );
+        assert_eq!(res, Some(ip("198.51.100.1")));
+    }
+
+    #[test]
+    fn test_trusted_proxy_real_ip() {
+        let trusted = parse_trusted_proxies("10.0.0.0/8");
+        let res = resolve_client_ip(Some(ip("10.0.0.1")), None, Some("198.51.100.1"), &trusted);
+        assert_eq!(res, Some(ip("198.51.100.1")));
+        let res = resolve_client_ip(Some(ip("10.0.0.1")), None, Some("garbage"), &trusted);
+        assert_eq!(res, Some(ip("10.0.0.1")));
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::env;
+
+use rocket::http::{Cookie, SameSite};
+
+/// Security attributes of the cookies set by the server
+/// They are read from the `COOKIE_SECURE` and `COOKIE_SAMESITE` environment variables,
+/// `Secure` and `SameSite=Lax` by default
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CookieSettings {
+    pub secure: bool,
+    pub same_site: SameSite,
+}
+
+impl Default for CookieSettings {
+    fn default() -> Self {
+        Self {
+            secure: true,
+            same_site: SameSite::Lax,
+        }
+    }
+}
+
+impl CookieSettings {
+    /// Parse the cookie settings
+    ///
+    /// # Arguments
+    /// * `secure` - `true` or `false`, `true` by default
+    /// * `same_site` - `strict`, `lax` or `none`, `lax` by default
+    ///
+    /// # Returns
+    /// An error if a value is invalid or if `SameSite=None` is requested without `Secure`
+    pub fn parse(secure: Option<&str>, same_site: Option<&str>) -> Result<Self, String> {
+        let secure = match secure.map(|secure| secure.trim().to_lowercase()).as_deref() {
+            None | Some("") => true,
+            Some("true") | Some("1") | Some("yes") => true,
+            Some("false") | Some("0") | Some("no") => false,
+            Some(secure) => return Err(format!("invalid COOKIE_SECURE value: {}", secure)),
+        };
+        let same_site = match same_site
+            .map(|same_site| same_site.trim().to_lowercase())
+            .as_deref()
+        {
+            None | Some("") | Some("lax") => SameSite::Lax,
+            Some("strict") => SameSite::Strict,
+            Some("none") => SameSite::None,
+            Some(same_site) => {
+                return Err(format!("invalid COOKIE_SAMESITE value: {}", same_site))
+            }
+        };
+        if same_site == SameSite::None && !secure {
+            return Err("COOKIE_SAMESITE=none requires COOKIE_SECURE=true".to_string());
+        }
+        Ok(Self { secure, same_site })
+    }
+
+    /// Read the cookie settings from the `COOKIE_SECURE` and `COOKIE_SAMESITE` environment variables
+    pub fn from_env() -> Result<Self, String> {
+        Self::parse(
+            env::var("COOKIE_SECURE").ok().as_deref(),
+            env::var("COOKIE_SAMESITE").ok().as_deref(),
+        )
+    }
+
+    /// Build an http only cookie with the configured security attributes
+    pub fn build<'c>(&self, name: &'c str, value: String) -> Cookie<'c> {
+        Cookie::build((name, value))
+            .path("/")
+            .http_only(true)
+            .secure(self.secure)
+            .same_site(self.same_site)
+            .build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_cookie_settings() {
+        let settings = CookieSettings::parse(None, None).unwrap();
+        assert_eq!(settings, CookieSettings::default());
+        assert!(settings.secure);
+        assert_eq!(settings.same_site, SameSite::Lax);
+    }
+
+    #[test]
+    fn test_cookie_settings_validation() {
+        let settings = CookieSettings::parse(Some("false"), Some("Strict")).unwrap();
+        assert!(!settings.secure);
+        assert_eq!(settings.same_site, SameSite::Strict);
+        assert!(CookieSettings::parse(Some("true"), Some("none")).is_ok());
+        assert!(CookieSettings::parse(Some("false"), Some("none")).is_err());
+        assert!(CookieSettings::parse(Some("maybe"), None).is_err());
+        assert!(CookieSettings::parse(None, Some("relaxed")).is_err());
+    }
+}
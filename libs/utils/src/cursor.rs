@@ -0,0 +1,89 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD};
+use uuid::Uuid;
+
+/// Position in a listing paged by cursor
+/// The rows are ordered by creation date then by guid, so a row inserted during a traversal is
+/// listed after the rows already returned and no row is returned twice
+#[derive(Debug, Clone, PartialEq)]
+pub struct PageCursor {
+    /// creation date of the last row returned
+    pub created_at: String,
+    /// guid of the last row returned
+    pub guid: Vec<u8>,
+}
+
+impl PageCursor {
+    /// Encode the cursor as an opaque URL safe string
+    pub fn encode(&self) -> String {
+        let guid = Uuid::from_slice(&self.guid).unwrap_or_default();
+        BASE64_URL_SAFE_NO_PAD.encode(format!("{}|{}", self.created_at, guid.simple()))
+    }
+
+    /// Decode a cursor returned by `encode`
+    ///
+    /// # Returns
+    /// None if the cursor is not valid
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let cursor = BASE64_URL_SAFE_NO_PAD.decode(cursor).ok()?;
+        let cursor = String::from_utf8(cursor).ok()?;
+        let (created_at, guid) = cursor.rsplit_once('|')?;
+        let guid = Uuid::parse_str(guid).ok()?;
+        Some(PageCursor {
+            created_at: created_at.to_string(),
+            guid: guid.as_bytes().to_vec(),
+        })
+    }
+}
+
+/// Parse the `after` query parameter of the listings paged by cursor
+///
+/// # Returns
+/// Ok(None) for an empty cursor, which starts a traversal, or an error if the cursor is not valid
+pub fn parse_after(after: &str) -> Result<Option<PageCursor>, String> {
+    if after.is_empty() {
+        return Ok(None);
+    }
+    PageCursor::decode(after)
+        .map(Some)
+        .ok_or_else(|| "Invalid cursor".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_cursor_round_trip() {
+        let cursor = PageCursor {
+            created_at: "2024-05-01 12:00:00".to_string(),
+            guid: Uuid::new_v4().as_bytes().to_vec(),
+        };
+        let encoded = cursor.encode();
+        assert!(encoded
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(PageCursor::decode(&encoded), Some(cursor));
+    }
+
+    #[test]
+    fn test_parse_after() {
+        assert_eq!(parse_after(""), Ok(None));
+        assert!(parse_after("not a cursor").is_err());
+        assert!(parse_after(&BASE64_URL_SAFE_NO_PAD.encode("2024-05-01|zz")).is_err());
+    }
+}
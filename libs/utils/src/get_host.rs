@@ -14,6 +14,93 @@
 // You should have received a copy of the Affero General Public License
 // along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
 use std::collections::HashMap;
+use std::env;
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use rocket::request::Request;
+
+use crate::client_ip::{get_trusted_proxies, is_trusted};
+
+/// Collect the request headers with lowercased names and values
+/// `X-Forwarded-Proto` is only kept when the immediate peer is a trusted proxy
+pub fn request_headers(request: &Request<'_>) -> HashMap<String, String> {
+    let mut headers: HashMap<String, String> = HashMap::new();
+    request.headers().iter().for_each(|k| {
+        headers.insert(k.name.to_string().to_lowercase(), k.value.to_string().to_lowercase());
+    });
+    filter_forwarded_proto(
+        headers,
+        request.remote().map(|remote| remote.ip()),
+        &get_trusted_proxies(),
+    )
+}
+
+/// Drop the `X-Forwarded-Proto` header unless the immediate peer is a trusted proxy
+/// A client talking directly to the server cannot choose the scheme of the generated urls
+pub fn filter_forwarded_proto(
+    mut headers: HashMap<String, String>,
+    remote: Option<IpAddr>,
+    trusted_proxies: &[IpNet],
+) -> HashMap<String, String> {
+    let trusted = remote.map_or(false, |remote| is_trusted(&remote, trusted_proxies));
+    if !trusted && headers.remove("x-forwarded-proto").is_some() {
+        log::debug!("ignoring X-Forwarded-Proto from untrusted peer {:?}", remote);
+    }
+    headers
+}
+
+/// Get the `FORCE_HTTPS_CALLBACKS` environment variable
+/// When set to `true` or `1` the callback urls always use https
+pub fn get_force_https_callbacks() -> bool {
+    let value = env::var("FORCE_HTTPS_CALLBACKS").unwrap_or_default();
+    matches!(value.trim().to_lowercase().as_str(), "true" | "1")
+}
+
+/// Get the `PUBLIC_BASE_URL` environment variable, the url the clients use to reach the server
+/// e.g. `https://desk.example.org/rustdesk`, without the trailing slash
+pub fn get_public_base_url() -> Option<String> {
+    parse_public_base_url(&env::var("PUBLIC_BASE_URL").unwrap_or_default())
+}
+
+fn parse_public_base_url(value: &str) -> Option<String> {
+    let value = value.trim().trim_end_matches('/');
+    if value.is_empty() {
+        return None;
+    }
+    Some(value.to_string())
+}
+
+/// Normalize the path the server is mounted at, e.g. `sctgdesk/` gives `/sctgdesk`
+/// The root gives an empty path
+pub fn normalize_base_path(value: &str) -> String {
+    let segments: Vec<&str> = value
+        .trim()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    if segments.is_empty() {
+        return String::new();
+    }
+    format!("/{}", segments.join("/"))
+}
+
+/// Get the url the clients use to reach the server, `PUBLIC_BASE_URL` or else the host the request was sent to
+pub fn get_public_host(headers: HashMap<String, String>, public_base_url: Option<String>) -> String {
+    public_base_url.unwrap_or_else(|| get_host(headers))
+}
+
+/// Get the host to use in the callback urls given to the OAuth2 providers
+/// With `force_https` the scheme is always https, for servers behind a TLS terminating proxy
+pub fn get_callback_host(headers: HashMap<String, String>, force_https: bool) -> String {
+    let host = get_host(headers);
+    if force_https {
+        if let Some(host) = host.strip_prefix("http://") {
+            return format!("https://{}", host);
+        }
+    }
+    host
+}
 
 pub fn get_host(headers: HashMap<String, String>) -> String {
     // Default to http
@@ -21,7 +108,10 @@ pub fn get_host(headers: HashMap<String, String>) -> String {
 
     // Check if the headers contain the X-Forwarded-Proto header
     if let Some(proto_in_headers) = headers.get("x-forwarded-proto") {
-        proto = proto_in_headers.to_string();
+        // a chain of proxies may append their own value, the first one is the client facing scheme
+        if let Some(first) = proto_in_headers.split(',').map(|p| p.trim()).find(|p| !p.is_empty()) {
+            proto = first.to_string();
+        }
     }
 
     // Check if the headers contain the X-Forwarded-Host header
@@ -35,4 +125,87 @@ pub fn get_host(headers: HashMap<String, String>) -> String {
     }
 
     "".to_string()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client_ip::parse_trusted_proxies;
+
+    fn test_headers(forwarded_proto: Option<&str>) -> HashMap<String, String> {
+        let mut headers = HashMap::new();
+        headers.insert("host".to_string(), "rustdesk.example.org".to_string());
+        if let Some(proto) = forwarded_proto {
+            headers.insert("x-forwarded-proto".to_string(), proto.to_string());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_forwarded_proto_from_trusted_proxy() {
+        let trusted = parse_trusted_proxies("10.0.0.0/8");
+        let headers = filter_forwarded_proto(
+            test_headers(Some("https")),
+            Some("10.0.0.1".parse().unwrap()),
+            &trusted,
+        );
+        assert_eq!(
+            format!("{}/api/oidc/callback", get_callback_host(headers, false)),
+            "https://rustdesk.example.org/api/oidc/callback"
+        );
+        let headers = filter_forwarded_proto(
+            test_headers(Some("https, http")),
+            Some("10.0.0.1".parse().unwrap()),
+            &trusted,
+        );
+        assert_eq!(get_host(headers), "https://rustdesk.example.org");
+    }
+
+    #[test]
+    fn test_forwarded_proto_from_untrusted_peer_is_ignored() {
+        let trusted = parse_trusted_proxies("10.0.0.0/8");
+        let headers = filter_forwarded_proto(
+            test_headers(Some("https")),
+            Some("203.0.113.7".parse().unwrap()),
+            &trusted,
+        );
+        assert_eq!(get_callback_host(headers, false), "http://rustdesk.example.org");
+        // nothing is trusted by default
+        let headers =
+            filter_forwarded_proto(test_headers(Some("https")), Some("10.0.0.1".parse().unwrap()), &[]);
+        assert_eq!(get_callback_host(headers, false), "http://rustdesk.example.org");
+    }
+
+    #[test]
+    fn test_force_https_callbacks() {
+        assert_eq!(
+            format!("{}/api/oidc/callback", get_callback_host(test_headers(None), true)),
+            "https://rustdesk.example.org/api/oidc/callback"
+        );
+        assert_eq!(
+            get_callback_host(test_headers(Some("https")), true),
+            "https://rustdesk.example.org"
+        );
+        assert_eq!(get_callback_host(HashMap::new(), true), "");
+    }
+
+    #[test]
+    fn test_public_host() {
+        assert_eq!(parse_public_base_url(" https://desk.example.org/rustdesk/ "), Some("https://desk.example.org/rustdesk".to_string()));
+        assert_eq!(parse_public_base_url(""), None);
+        assert_eq!(
+            get_public_host(test_headers(None), Some("https://desk.example.org".to_string())),
+            "https://desk.example.org"
+        );
+        assert_eq!(get_public_host(test_headers(None), None), "http://rustdesk.example.org");
+    }
+
+    #[test]
+    fn test_base_path() {
+        assert_eq!(normalize_base_path(""), "");
+        assert_eq!(normalize_base_path(" / "), "");
+        assert_eq!(normalize_base_path("sctgdesk"), "/sctgdesk");
+        assert_eq!(normalize_base_path("/sctgdesk/"), "/sctgdesk");
+        assert_eq!(normalize_base_path("//tools//sctgdesk"), "/tools/sctgdesk");
+    }
+}
@@ -19,6 +19,19 @@ pub mod address_book;
 
 pub mod types;
 pub mod get_host;
+pub mod client_ip;
+pub mod peer_status;
+pub mod peer_export;
+pub mod cookie;
+pub mod audit;
+pub mod peer_id;
+pub mod cursor;
+pub mod strategy;
+pub mod access_log;
+pub mod semver;
+pub mod login_reply;
+pub mod tag_color;
+pub mod ui_settings;
 
 pub use tokens::Token;
 pub use bearer::{BearerAuthToken, CookieAuthToken, MixedAuthToken, IntoToken};
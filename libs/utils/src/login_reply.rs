@@ -0,0 +1,83 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::cmp::Ordering;
+
+use serde::Serialize;
+
+use crate::semver::compare_versions;
+use crate::{LoginReply, Token, UserInfo};
+
+/// First client version reading the token of the login reply from `access_token`
+pub const ACCESS_TOKEN_MIN_CLIENT_VERSION: &str = "1.1.9";
+
+/// Shape of the login reply sent to a client
+///
+/// | Client version (`X-Client-Version`)              | Shape     | Token field    |
+/// |--------------------------------------------------|-----------|----------------|
+/// | none, not a version, `1.1.9` or newer            | `Current` | `access_token` |
+/// | older than `1.1.9`                               | `Legacy`  | `token`        |
+///
+/// The other fields, `type` and `user`, are the same in both shapes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LoginReplyShape {
+    #[default]
+    Current,
+    Legacy,
+}
+
+impl LoginReplyShape {
+    /// The shape expected by a client from its version, the current one when the version is unknown
+    pub fn from_client_version(version: Option<&str>) -> Self {
+        match version.and_then(|version| compare_versions(version.trim(), ACCESS_TOKEN_MIN_CLIENT_VERSION)) {
+            Some(Ordering::Less) => LoginReplyShape::Legacy,
+            _ => LoginReplyShape::Current,
+        }
+    }
+}
+
+/// Login reply of the clients older than `1.1.9`, the token is in `token`
+#[derive(Serialize, Debug)]
+pub struct LegacyLoginReply {
+    #[serde(rename = "type")]
+    pub response_type: String,
+    pub user: UserInfo,
+    pub token: Token,
+}
+
+impl From<LoginReply> for LegacyLoginReply {
+    fn from(reply: LoginReply) -> Self {
+        LegacyLoginReply {
+            response_type: reply.response_type,
+            user: reply.user,
+            token: reply.access_token,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_reply_shape() {
+        assert_eq!(LoginReplyShape::from_client_version(None), LoginReplyShape::Current);
+        assert_eq!(LoginReplyShape::from_client_version(Some("nightly")), LoginReplyShape::Current);
+        assert_eq!(LoginReplyShape::from_client_version(Some("1.1.9")), LoginReplyShape::Current);
+        assert_eq!(LoginReplyShape::from_client_version(Some("1.2.6")), LoginReplyShape::Current);
+        assert_eq!(LoginReplyShape::from_client_version(Some(" 1.1.8 ")), LoginReplyShape::Legacy);
+        assert_eq!(LoginReplyShape::from_client_version(Some("1.1.9-beta.1")), LoginReplyShape::Legacy);
+    }
+}
@@ -0,0 +1,112 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use crate::PeerExport;
+
+/// Supported formats of the peers export
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeerExportFormat {
+    Csv,
+    Json,
+}
+
+impl PeerExportFormat {
+    /// Parse the `format` query parameter, CSV is the default
+    pub fn parse(format: Option<&str>) -> Option<Self> {
+        match format.map(|format| format.to_lowercase()).as_deref() {
+            None | Some("csv") => Some(PeerExportFormat::Csv),
+            Some("json") => Some(PeerExportFormat::Json),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PeerExportFormat::Csv => "csv",
+            PeerExportFormat::Json => "json",
+        }
+    }
+}
+
+pub const PEER_EXPORT_CSV_HEADER: &str =
+    "id,alias,device_name,os,version,user,group,last_online,online\r\n";
+
+/// Last line of a CSV export interrupted by a database error
+pub const PEER_EXPORT_CSV_INTERRUPTED: &str = "# export interrupted, the file is incomplete\r\n";
+
+/// End of a JSON export interrupted by a database error, the array is left unclosed so the file does not parse
+pub const PEER_EXPORT_JSON_INTERRUPTED: &str = "\n/* export interrupted, the file is incomplete */";
+
+/// Quote a CSV field when it contains a separator, a quote or a line break (RFC 4180)
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Format a peer as a CSV record, terminated by CRLF
+pub fn peer_export_csv_record(peer: &PeerExport) -> String {
+    let fields = [
+        peer.id.as_str(),
+        peer.alias.as_deref().unwrap_or(""),
+        peer.device_name.as_deref().unwrap_or(""),
+        peer.os.as_deref().unwrap_or(""),
+        peer.version.as_deref().unwrap_or(""),
+        peer.user.as_deref().unwrap_or(""),
+        peer.group.as_deref().unwrap_or(""),
+        peer.last_online.as_str(),
+        if peer.online { "true" } else { "false" },
+    ];
+    let mut record = fields
+        .iter()
+        .map(|field| csv_field(field))
+        .collect::<Vec<String>>()
+        .join(",");
+    record.push_str("\r\n");
+    record
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_format() {
+        assert_eq!(PeerExportFormat::parse(None), Some(PeerExportFormat::Csv));
+        assert_eq!(PeerExportFormat::parse(Some("JSON")), Some(PeerExportFormat::Json));
+        assert_eq!(PeerExportFormat::parse(Some("xlsx")), None);
+    }
+
+    #[test]
+    fn test_csv_record_quoting() {
+        let peer = PeerExport {
+            id: "123456789".to_string(),
+            alias: Some("Front desk, \"main\"".to_string()),
+            device_name: Some("desk\npc".to_string()),
+            os: Some("windows".to_string()),
+            version: None,
+            user: Some("admin".to_string()),
+            group: None,
+            last_online: "2024-05-01 12:00:00".to_string(),
+            online: true,
+        };
+        assert_eq!(
+            peer_export_csv_record(&peer),
+            "123456789,\"Front desk, \"\"main\"\"\",\"desk\npc\",windows,,admin,,2024-05-01 12:00:00,true\r\n"
+        );
+    }
+}
@@ -0,0 +1,109 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+/// Shortest id accepted by the RustDesk clients
+pub const PEER_ID_MIN_LENGTH: usize = 6;
+/// Longest id accepted by the RustDesk clients
+pub const PEER_ID_MAX_LENGTH: usize = 16;
+
+/// Canonical form of a peer id
+/// The clients display the ids with spaces ("123 456 789"), they are removed so a device is always
+/// tracked under the same id
+///
+/// # Returns
+/// The canonical id or an error if it is not a valid RustDesk id (6 to 16 letters, digits, `_` or `-`)
+pub fn normalize_peer_id(id: &str) -> Result<String, String> {
+    let id: String = id.chars().filter(|c| !c.is_whitespace()).collect();
+    if id.len() < PEER_ID_MIN_LENGTH || id.len() > PEER_ID_MAX_LENGTH {
+        return Err(format!(
+            "Invalid peer id {}: it must have {} to {} characters",
+            id, PEER_ID_MIN_LENGTH, PEER_ID_MAX_LENGTH
+        ));
+    }
+    if !id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(format!(
+            "Invalid peer id {}: only letters, digits, '_' and '-' are allowed",
+            id
+        ));
+    }
+    Ok(id)
+}
+
+/// Longest id of an address book peer, a host name and a port
+pub const AB_PEER_ID_MAX_LENGTH: usize = 255;
+
+/// Canonical form of the id of an address book peer
+/// Besides the RustDesk ids, the address books hold the direct access entries: an IP address or a host name
+/// with an optional port, e.g. `192.168.1.10:21118`, `[fe80::1]:21118` or `office.example.org`
+///
+/// # Returns
+/// The id without the spaces or an error if it is neither a RustDesk id nor an address
+pub fn normalize_ab_peer_id(id: &str) -> Result<String, String> {
+    if let Ok(id) = normalize_peer_id(id) {
+        return Ok(id);
+    }
+    let id: String = id.chars().filter(|c| !c.is_whitespace()).collect();
+    if id.is_empty() || id.len() > AB_PEER_ID_MAX_LENGTH {
+        return Err(format!(
+            "Invalid peer id {}: it must have 1 to {} characters",
+            id, AB_PEER_ID_MAX_LENGTH
+        ));
+    }
+    if !id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ':' | '[' | ']'))
+    {
+        return Err(format!(
+            "Invalid peer id {}: only letters, digits, '_', '-', '.', ':', '[' and ']' are allowed",
+            id
+        ));
+    }
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_peer_id() {
+        assert_eq!(normalize_peer_id("123 456 789"), normalize_peer_id("123456789"));
+        assert_eq!(normalize_peer_id(" 123\u{a0}456 789\n").unwrap(), "123456789");
+        assert_eq!(normalize_peer_id("my-office_pc").unwrap(), "my-office_pc");
+    }
+
+    #[test]
+    fn test_reject_invalid_peer_id() {
+        assert!(normalize_peer_id("").is_err());
+        assert!(normalize_peer_id("12 34").is_err());
+        assert!(normalize_peer_id("12345678901234567").is_err());
+        assert!(normalize_peer_id("123;456789").is_err());
+        assert!(normalize_peer_id("1234é6789").is_err());
+    }
+
+    #[test]
+    fn test_normalize_ab_peer_id() {
+        assert_eq!(normalize_ab_peer_id("123 456 789").unwrap(), "123456789");
+        assert_eq!(normalize_ab_peer_id("192.168.1.10:21118").unwrap(), "192.168.1.10:21118");
+        assert_eq!(normalize_ab_peer_id("[fe80::1]:21118").unwrap(), "[fe80::1]:21118");
+        assert_eq!(normalize_ab_peer_id("pc").unwrap(), "pc");
+        assert_eq!(normalize_ab_peer_id(" office.example.org ").unwrap(), "office.example.org");
+        assert!(normalize_ab_peer_id("").is_err());
+        assert!(normalize_ab_peer_id("10.0.0.1;rm").is_err());
+        assert!(normalize_ab_peer_id(&"a".repeat(256)).is_err());
+        // the heartbeats still only accept the RustDesk ids
+        assert!(normalize_peer_id("192.168.1.10:21118").is_err());
+    }
+}
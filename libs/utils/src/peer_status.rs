@@ -0,0 +1,200 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+
+use crate::{PeerHistoryResponse, PeerStatusInterval};
+
+/// A peer is considered offline when no heartbeat was received during this delay
+pub const PEER_OFFLINE_AFTER_SECS: i64 = 60;
+
+/// Format used by sqlite `current_timestamp`
+pub const SQLITE_DATETIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Parse a date time either in sqlite format (`2024-05-01 12:00:00`, UTC)
+/// or in RFC 3339 format (`2024-05-01T14:00:00+02:00`)
+pub fn parse_datetime(datetime: &str) -> Option<NaiveDateTime> {
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(datetime, SQLITE_DATETIME_FORMAT) {
+        return Some(datetime);
+    }
+    DateTime::parse_from_rfc3339(datetime)
+        .ok()
+        .map(|datetime| datetime.naive_utc())
+}
+
+pub fn format_datetime(datetime: &NaiveDateTime) -> String {
+    datetime.format(SQLITE_DATETIME_FORMAT).to_string()
+}
+
+/// Build the online/offline intervals of a peer in the [from, to] window
+///
+/// # Arguments
+/// * `initial_online` - the state of the peer at `from`
+/// * `transitions` - the transitions (timestamp, online) sorted by timestamp
+/// * `from` - start of the window
+/// * `to` - end of the window
+pub fn compute_peer_status_intervals(
+    initial_online: bool,
+    transitions: &[(NaiveDateTime, bool)],
+    from: NaiveDateTime,
+    to: NaiveDateTime,
+) -> Vec<PeerStatusInterval> {
+    let mut intervals = Vec::new();
+    if to <= from {
+        return intervals;
+    }
+    let mut start = from;
+    let mut online = initial_online;
+    for (timestamp, new_online) in transitions {
+        if *timestamp <= from {
+            online = *new_online;
+            continue;
+        }
+        if *timestamp >= to {
+            break;
+        }
+        if *new_online == online {
+            continue;
+        }
+        intervals.push(PeerStatusInterval {
+            from: format_datetime(&start),
+            to: format_datetime(timestamp),
+            online,
+        });
+        start = *timestamp;
+        online = *new_online;
+    }
+    intervals.push(PeerStatusInterval {
+        from: format_datetime(&start),
+        to: format_datetime(&to),
+        online,
+    });
+    intervals
+}
+
+/// Percentage of the intervals during which the peer was online
+pub fn compute_availability(intervals: &[PeerStatusInterval]) -> f64 {
+    let mut total = 0;
+    let mut online = 0;
+    for interval in intervals {
+        let (Some(from), Some(to)) = (parse_datetime(&interval.from), parse_datetime(&interval.to))
+        else {
+            continue;
+        };
+        let duration = (to - from).num_seconds();
+        total += duration;
+        if interval.online {
+            online += duration;
+        }
+    }
+    if total == 0 {
+        return 0.0;
+    }
+    (online as f64) * 100.0 / (total as f64)
+}
+
+/// Normalize the window of a history request in sqlite format
+/// `to` defaults to now and `from` defaults to 24 hours before `to`
+/// Returns None if a date is invalid or if `from` is not before `to`
+pub fn history_window(from: Option<&str>, to: Option<&str>) -> Option<(String, String)> {
+    let to = match to {
+        Some(to) => parse_datetime(to)?,
+        None => Utc::now().naive_utc(),
+    };
+    let from = match from {
+        Some(from) => parse_datetime(from)?,
+        None => to - Duration::hours(24),
+    };
+    if from >= to {
+        return None;
+    }
+    Some((format_datetime(&from), format_datetime(&to)))
+}
+
+/// Build the history of a peer from its state at `from` and its transitions in the window
+pub fn build_peer_history(
+    id: &str,
+    initial_online: bool,
+    transitions: &[(String, bool)],
+    from: &str,
+    to: &str,
+) -> Option<PeerHistoryResponse> {
+    let transitions: Vec<(NaiveDateTime, bool)> = transitions
+        .iter()
+        .filter_map(|(timestamp, online)| Some((parse_datetime(timestamp)?, *online)))
+        .collect();
+    let intervals = compute_peer_status_intervals(
+        initial_online,
+        &transitions,
+        parse_datetime(from)?,
+        parse_datetime(to)?,
+    );
+    Some(PeerHistoryResponse {
+        id: id.to_string(),
+        from: from.to_string(),
+        to: to.to_string(),
+        availability: compute_availability(&intervals),
+        intervals,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dt(datetime: &str) -> NaiveDateTime {
+        parse_datetime(datetime).unwrap()
+    }
+
+    #[test]
+    fn test_parse_datetime() {
+        assert_eq!(dt("2024-05-01T14:00:00+02:00"), dt("2024-05-01 12:00:00"));
+        assert!(parse_datetime("yesterday").is_none());
+    }
+
+    #[test]
+    fn test_intervals_and_availability() {
+        let transitions = vec![
+            (dt("2024-05-01 08:00:00"), true),
+            (dt("2024-05-01 12:00:00"), false),
+            (dt("2024-05-01 18:00:00"), true),
+            (dt("2024-05-02 08:00:00"), false),
+        ];
+        let intervals = compute_peer_status_intervals(
+            false,
+            &transitions,
+            dt("2024-05-01 10:00:00"),
+            dt("2024-05-01 20:00:00"),
+        );
+        assert_eq!(intervals.len(), 3);
+        assert!(intervals[0].online);
+        assert_eq!(intervals[0].from, "2024-05-01 10:00:00");
+        assert_eq!(intervals[0].to, "2024-05-01 12:00:00");
+        assert!(!intervals[1].online);
+        assert!(intervals[2].online);
+        assert_eq!(intervals[2].to, "2024-05-01 20:00:00");
+        // 4 hours online out of 10
+        assert_eq!(compute_availability(&intervals), 40.0);
+    }
+
+    #[test]
+    fn test_intervals_without_transition() {
+        let intervals =
+            compute_peer_status_intervals(true, &[], dt("2024-05-01 10:00:00"), dt("2024-05-01 11:00:00"));
+        assert_eq!(intervals.len(), 1);
+        assert_eq!(compute_availability(&intervals), 100.0);
+        assert!(compute_peer_status_intervals(true, &[], dt("2024-05-01 11:00:00"), dt("2024-05-01 10:00:00")).is_empty());
+    }
+}
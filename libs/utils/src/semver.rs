@@ -0,0 +1,233 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// Identifier of a pre-release, e.g. `beta` or `2` in `1.3.0-beta.2`
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum PreRelease {
+    Numeric(u64),
+    Alphanumeric(String),
+}
+
+impl Ord for PreRelease {
+    /// The numeric identifiers are compared numerically and have a lower precedence than the alphanumeric ones
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (PreRelease::Numeric(a), PreRelease::Numeric(b)) => a.cmp(b),
+            (PreRelease::Numeric(_), PreRelease::Alphanumeric(_)) => Ordering::Less,
+            (PreRelease::Alphanumeric(_), PreRelease::Numeric(_)) => Ordering::Greater,
+            (PreRelease::Alphanumeric(a), PreRelease::Alphanumeric(b)) => a.cmp(b),
+        }
+    }
+}
+
+impl PartialOrd for PreRelease {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A semantic version, `major.minor.patch[-pre.release][+build]`
+///
+/// The versions are ordered by precedence as defined by semver.org: a pre-release is older than its release
+/// and the build metadata is ignored, so `1.3.0+build.5` is equal to `1.3.0`
+#[derive(Debug, Clone)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+    pre: Vec<PreRelease>,
+    build: Option<String>,
+}
+
+impl Version {
+    /// true if the version is a pre-release, e.g. `1.3.0-rc.1`
+    pub fn is_pre_release(&self) -> bool {
+        !self.pre.is_empty()
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    /// Parse a version, a leading `v` is accepted as in the release tags
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{} is not a valid version, e.g. 1.2.6 or 1.3.0-beta.1", version);
+        let trimmed = version.trim();
+        let trimmed = trimmed.strip_prefix('v').unwrap_or(trimmed);
+        let (trimmed, build) = match trimmed.split_once('+') {
+            Some((trimmed, build)) => {
+                let valid = build
+                    .split('.')
+                    .all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'));
+                if !valid {
+                    return Err(invalid());
+                }
+                (trimmed, Some(build.to_string()))
+            }
+            None => (trimmed, None),
+        };
+        let (core, pre) = match trimmed.split_once('-') {
+            Some((core, pre)) => (core, Some(pre)),
+            None => (trimmed, None),
+        };
+        let numbers = core
+            .split('.')
+            .map(|number| {
+                // no leading zero, as in semver
+                if number.is_empty() || (number.len() > 1 && number.starts_with('0')) {
+                    return None;
+                }
+                number.parse::<u64>().ok()
+            })
+            .collect::<Option<Vec<u64>>>()
+            .ok_or_else(invalid)?;
+        let [major, minor, patch] = numbers[..] else {
+            return Err(invalid());
+        };
+        let pre = match pre {
+            Some(pre) => pre
+                .split('.')
+                .map(|identifier| {
+                    if identifier.is_empty()
+                        || !identifier.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                    {
+                        return None;
+                    }
+                    if identifier.chars().all(|c| c.is_ascii_digit()) {
+                        if identifier.len() > 1 && identifier.starts_with('0') {
+                            return None;
+                        }
+                        identifier.parse::<u64>().ok().map(PreRelease::Numeric)
+                    } else {
+                        Some(PreRelease::Alphanumeric(identifier.to_string()))
+                    }
+                })
+                .collect::<Option<Vec<PreRelease>>>()
+                .ok_or_else(invalid)?,
+            None => Vec::new(),
+        };
+        Ok(Version {
+            major,
+            minor,
+            patch,
+            pre,
+            build,
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)?;
+        for (i, identifier) in self.pre.iter().enumerate() {
+            let separator = if i == 0 { '-' } else { '.' };
+            match identifier {
+                PreRelease::Numeric(n) => write!(f, "{}{}", separator, n)?,
+                PreRelease::Alphanumeric(s) => write!(f, "{}{}", separator, s)?,
+            }
+        }
+        if let Some(build) = &self.build {
+            write!(f, "+{}", build)?;
+        }
+        Ok(())
+    }
+}
+
+impl Ord for Version {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            .then_with(|| match (self.pre.is_empty(), other.pre.is_empty()) {
+                (true, true) => Ordering::Equal,
+                // a pre-release is older than its release
+                (true, false) => Ordering::Greater,
+                (false, true) => Ordering::Less,
+                // a shorter set of identifiers is older when they are otherwise equal
+                (false, false) => self.pre.cmp(&other.pre),
+            })
+    }
+}
+
+impl PartialOrd for Version {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Version {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Version {}
+
+/// Compare two versions by precedence
+///
+/// # Returns
+/// None if one of them is not a valid version
+pub fn compare_versions(a: &str, b: &str) -> Option<Ordering> {
+    Some(a.parse::<Version>().ok()?.cmp(&b.parse::<Version>().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        let version: Version = "v1.3.0-beta.2+build.7".parse().unwrap();
+        assert_eq!((version.major, version.minor, version.patch), (1, 3, 0));
+        assert!(version.is_pre_release());
+        assert_eq!(version.to_string(), "1.3.0-beta.2+build.7");
+        assert_eq!("1.2.6".parse::<Version>().unwrap().to_string(), "1.2.6");
+        for invalid in ["", "1.2", "1.2.3.4", "01.2.3", "1.2.x", "1.2.3-", "1.2.3-beta..1", "1.2.3-01", "1.2.3+", "1.2.3+a..b"] {
+            assert!(invalid.parse::<Version>().is_err(), "{} should be rejected", invalid);
+        }
+    }
+
+    #[test]
+    fn test_version_precedence() {
+        // the order of semver.org
+        let ordered = [
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.0.1",
+            "1.2.0",
+            "1.10.0",
+            "2.0.0",
+        ];
+        for pair in ordered.windows(2) {
+            assert_eq!(compare_versions(pair[0], pair[1]), Some(Ordering::Less), "{} < {}", pair[0], pair[1]);
+            assert_eq!(compare_versions(pair[1], pair[0]), Some(Ordering::Greater));
+        }
+        // the build metadata does not change the precedence
+        assert_eq!(compare_versions("1.3.0+build.5", "1.3.0"), Some(Ordering::Equal));
+        assert_eq!(compare_versions("1.3.0-rc.1+linux", "1.3.0-rc.1+windows"), Some(Ordering::Equal));
+        assert_eq!(compare_versions("1.3.0-rc.1+build", "1.3.0"), Some(Ordering::Less));
+        assert_eq!(compare_versions("v1.2.6", "1.2.6"), Some(Ordering::Equal));
+        assert_eq!(compare_versions("1.2.6", "latest"), None);
+    }
+}
@@ -0,0 +1,541 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::collections::BTreeMap;
+
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::JsonSchema;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::{Map, Value};
+
+use crate::{Strategy, StrategySource};
+
+/// Values accepted by a strategy option
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StrategyOptionKind {
+    /// `Y` or `N` as in the RustDesk options, a JSON boolean is accepted too
+    Flag,
+    /// one of the listed strings
+    Choice(&'static [&'static str]),
+    /// an integer in the inclusive range, as a number or as a string
+    Integer(i64, i64),
+    /// any string
+    Text,
+}
+
+/// Client options known in the strategy settings
+/// The other keys are stored untouched, newer clients may understand them
+pub const STRATEGY_OPTIONS: &[(&str, StrategyOptionKind)] = &[
+    ("enable-keyboard", StrategyOptionKind::Flag),
+    ("enable-clipboard", StrategyOptionKind::Flag),
+    ("enable-file-transfer", StrategyOptionKind::Flag),
+    ("enable-audio", StrategyOptionKind::Flag),
+    ("enable-tunnel", StrategyOptionKind::Flag),
+    ("enable-remote-restart", StrategyOptionKind::Flag),
+    ("enable-record-session", StrategyOptionKind::Flag),
+    ("enable-block-input", StrategyOptionKind::Flag),
+    ("enable-lan-discovery", StrategyOptionKind::Flag),
+    ("enable-abr", StrategyOptionKind::Flag),
+    ("allow-remote-config-modification", StrategyOptionKind::Flag),
+    ("allow-auto-disconnect", StrategyOptionKind::Flag),
+    ("allow-only-conn-window-open", StrategyOptionKind::Flag),
+    ("allow-remove-wallpaper", StrategyOptionKind::Flag),
+    ("direct-server", StrategyOptionKind::Flag),
+    ("direct-access-port", StrategyOptionKind::Integer(1, 65535)),
+    ("auto-disconnect-timeout", StrategyOptionKind::Integer(1, 1440)),
+    ("approve-mode", StrategyOptionKind::Choice(&["", "password", "click"])),
+    (
+        "verification-method",
+        StrategyOptionKind::Choice(&[
+            "use-temporary-password",
+            "use-permanent-password",
+            "use-both-passwords",
+        ]),
+    ),
+    (
+        "temporary-password-length",
+        StrategyOptionKind::Choice(&["6", "8", "10"]),
+    ),
+    ("whitelist", StrategyOptionKind::Text),
+];
+
+impl StrategyOptionKind {
+    fn accepts(&self, value: &Value) -> bool {
+        match (self, value) {
+            (StrategyOptionKind::Flag, Value::Bool(_)) => true,
+            (StrategyOptionKind::Flag, Value::String(s)) => s == "Y" || s == "N",
+            (StrategyOptionKind::Choice(choices), Value::String(s)) => choices.contains(&s.as_str()),
+            (StrategyOptionKind::Integer(min, max), Value::Number(n)) => {
+                n.as_i64().map_or(false, |n| n >= *min && n <= *max)
+            }
+            (StrategyOptionKind::Integer(min, max), Value::String(s)) => {
+                s.parse::<i64>().map_or(false, |n| n >= *min && n <= *max)
+            }
+            (StrategyOptionKind::Text, Value::String(_)) => true,
+            _ => false,
+        }
+    }
+
+    fn expected(&self) -> String {
+        match self {
+            StrategyOptionKind::Flag => "Y or N".to_string(),
+            StrategyOptionKind::Choice(choices) => format!("one of {:?}", choices),
+            StrategyOptionKind::Integer(min, max) => format!("an integer from {} to {}", min, max),
+            StrategyOptionKind::Text => "a string".to_string(),
+        }
+    }
+}
+
+/// Order of the strategy list
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StrategyOrder {
+    /// the default strategy first, then by name
+    #[default]
+    Default,
+    Name,
+    NameDesc,
+    CreatedAt,
+    CreatedAtDesc,
+    ModifiedAt,
+    ModifiedAtDesc,
+}
+
+impl StrategyOrder {
+    /// Parse the `order` parameter: `name`, `created_at` or `modified_at`,
+    /// prefixed with `-` for the descending order
+    pub fn parse(order: &str) -> Result<Self, String> {
+        match order {
+            "" => Ok(StrategyOrder::Default),
+            "name" => Ok(StrategyOrder::Name),
+            "-name" => Ok(StrategyOrder::NameDesc),
+            "created_at" => Ok(StrategyOrder::CreatedAt),
+            "-created_at" => Ok(StrategyOrder::CreatedAtDesc),
+            "modified_at" => Ok(StrategyOrder::ModifiedAt),
+            "-modified_at" => Ok(StrategyOrder::ModifiedAtDesc),
+            _ => Err(format!(
+                "Invalid order {}, expected name, created_at or modified_at, optionally prefixed with -",
+                order
+            )),
+        }
+    }
+
+    /// ORDER BY clause of the strategies table, the ties are ordered by name
+    pub fn order_by(&self) -> &'static str {
+        match self {
+            StrategyOrder::Default => "is_default DESC, name",
+            StrategyOrder::Name => "name",
+            StrategyOrder::NameDesc => "name DESC",
+            StrategyOrder::CreatedAt => "created_at, name",
+            StrategyOrder::CreatedAtDesc => "created_at DESC, name",
+            StrategyOrder::ModifiedAt => "modified_at, name",
+            StrategyOrder::ModifiedAtDesc => "modified_at DESC, name",
+        }
+    }
+}
+
+/// Validate the settings of a strategy against the known options
+///
+/// # Arguments
+/// * `allow_unknown` - keep the options which are not known instead of rejecting them,
+///   newer clients may understand them
+///
+/// # Returns
+/// Ok if the settings are a JSON object whose known options have valid values,
+/// otherwise the list of the invalid options
+pub fn validate_strategy_settings(settings: &Value, allow_unknown: bool) -> Result<(), String> {
+    let settings = settings
+        .as_object()
+        .ok_or("strategy settings must be a JSON object")?;
+    let invalid = settings
+        .iter()
+        .filter_map(|(name, value)| match option_kind(name) {
+            Some(kind) if !kind.accepts(value) => {
+                Some(format!("{} must be {}", name, kind.expected()))
+            }
+            None if !allow_unknown => Some(format!("{} is not a known option", name)),
+            _ => None,
+        })
+        .collect::<Vec<String>>();
+    if invalid.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("invalid strategy settings: {}", invalid.join(", ")))
+    }
+}
+
+fn option_kind(name: &str) -> Option<StrategyOptionKind> {
+    STRATEGY_OPTIONS
+        .iter()
+        .find(|(option, _)| *option == name)
+        .map(|(_, kind)| *kind)
+}
+
+/// `Y` or `N` in the RustDesk options
+mod flag {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &bool, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(if *value { "Y" } else { "N" })
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::Bool(value) => Ok(value),
+            Value::String(value) if value == "Y" => Ok(true),
+            Value::String(value) if value == "N" => Ok(false),
+            value => Err(D::Error::custom(format!("expected Y or N, got {}", value))),
+        }
+    }
+}
+
+/// An integer given as a number or as a string
+fn integer<'de, D: Deserializer<'de>, T: TryFrom<i64>>(deserializer: D) -> Result<T, D::Error> {
+    let value = match Value::deserialize(deserializer)? {
+        Value::Number(value) => value.as_i64(),
+        Value::String(value) => value.parse::<i64>().ok(),
+        _ => None,
+    };
+    value
+        .and_then(|value| T::try_from(value).ok())
+        .ok_or_else(|| D::Error::custom("expected an integer"))
+}
+
+/// The client settings of a strategy with their types
+/// The options left out take the default value of the RustDesk client
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct StrategySettings {
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub enable_keyboard: bool,
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub enable_clipboard: bool,
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub enable_file_transfer: bool,
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub enable_audio: bool,
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub enable_tunnel: bool,
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub enable_remote_restart: bool,
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub enable_record_session: bool,
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub enable_block_input: bool,
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub enable_lan_discovery: bool,
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub enable_abr: bool,
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub allow_remote_config_modification: bool,
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub allow_auto_disconnect: bool,
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub allow_only_conn_window_open: bool,
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub allow_remove_wallpaper: bool,
+    #[serde(with = "flag")]
+    #[schemars(with = "String")]
+    pub direct_server: bool,
+    #[serde(deserialize_with = "integer")]
+    pub direct_access_port: u16,
+    /// minutes without activity before the connection is closed, when `allow-auto-disconnect` is set
+    #[serde(deserialize_with = "integer")]
+    pub auto_disconnect_timeout: u32,
+    /// empty for both the password and the click, `password` or `click`
+    pub approve_mode: String,
+    pub verification_method: String,
+    pub temporary_password_length: String,
+    /// IP addresses or networks allowed to connect, separated by commas, empty for all
+    pub whitelist: String,
+    /// the options which are not known, kept for the newer clients
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+impl Default for StrategySettings {
+    fn default() -> Self {
+        StrategySettings {
+            enable_keyboard: true,
+            enable_clipboard: true,
+            enable_file_transfer: true,
+            enable_audio: true,
+            enable_tunnel: true,
+            enable_remote_restart: true,
+            enable_record_session: true,
+            enable_block_input: true,
+            enable_lan_discovery: true,
+            enable_abr: true,
+            allow_remote_config_modification: false,
+            allow_auto_disconnect: false,
+            allow_only_conn_window_open: false,
+            allow_remove_wallpaper: false,
+            direct_server: false,
+            direct_access_port: 21118,
+            auto_disconnect_timeout: 10,
+            approve_mode: String::new(),
+            verification_method: "use-both-passwords".to_string(),
+            temporary_password_length: "6".to_string(),
+            whitelist: String::new(),
+            other: BTreeMap::new(),
+        }
+    }
+}
+
+/// Where the value of an option of the effective settings comes from
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct SettingProvenance {
+    pub source: StrategySource,
+    /// name of the strategy setting the option, None when no strategy sets it
+    pub strategy: Option<String>,
+}
+
+/// The settings applied to a peer, merged from its strategies, with the origin of each option
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct EffectiveSettings {
+    pub settings: StrategySettings,
+    pub provenance: BTreeMap<String, SettingProvenance>,
+}
+
+/// Merge the settings of the strategies applied to a peer
+///
+/// # Arguments
+/// * `layers` - the strategies from the least to the most specific one, usually
+///   the default strategy, then the ones of the group, of the user and of the device
+///
+/// # Returns
+/// Each option takes the value of the most specific strategy setting it, the options
+/// set by none take their default value. The invalid values stored before the validation
+/// of the settings are ignored.
+pub fn merge_strategy_settings(layers: &[(StrategySource, Strategy)]) -> EffectiveSettings {
+    let mut merged = Map::new();
+    let mut provenance = STRATEGY_OPTIONS
+        .iter()
+        .map(|(name, _)| {
+            (
+                name.to_string(),
+                SettingProvenance {
+                    source: StrategySource::Builtin,
+                    strategy: None,
+                },
+            )
+        })
+        .collect::<BTreeMap<String, SettingProvenance>>();
+    for (source, strategy) in layers {
+        let Some(settings) = strategy.settings.as_object() else {
+            continue;
+        };
+        for (name, value) in settings {
+            if option_kind(name).is_some_and(|kind| !kind.accepts(value)) {
+                continue;
+            }
+            merged.insert(name.clone(), value.clone());
+            provenance.insert(
+                name.clone(),
+                SettingProvenance {
+                    source: *source,
+                    strategy: Some(strategy.name.clone()),
+                },
+            );
+        }
+    }
+    EffectiveSettings {
+        settings: serde_json::from_value(Value::Object(merged)).unwrap_or_default(),
+        provenance,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn strategy(name: &str, settings: Value) -> Strategy {
+        Strategy {
+            guid: String::new(),
+            name: name.to_string(),
+            settings,
+            is_default: false,
+            created_at: String::new(),
+            modified_at: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_validate_strategy_settings() {
+        assert!(validate_strategy_settings(&json!({}), false).is_ok());
+        let settings = json!({
+            "enable-clipboard": "N",
+            "enable-audio": false,
+            "direct-access-port": "21118",
+            "auto-disconnect-timeout": 10,
+            "verification-method": "use-permanent-password",
+            "whitelist": "10.0.0.0/8",
+            "custom-option-of-a-newer-client": [1, 2]
+        });
+        assert!(validate_strategy_settings(&settings, true).is_ok());
+        let err = validate_strategy_settings(&settings, false).unwrap_err();
+        assert_eq!(
+            err,
+            "invalid strategy settings: custom-option-of-a-newer-client is not a known option"
+        );
+        assert!(validate_strategy_settings(&json!([]), true).is_err());
+
+        let err = validate_strategy_settings(
+            &json!({
+                "enable-clipboard": "yes",
+                "direct-access-port": 70000,
+                "approve-mode": "never",
+                "enable-audio": "Y"
+            }),
+            true,
+        )
+        .unwrap_err();
+        assert!(err.contains("enable-clipboard must be Y or N"));
+        assert!(err.contains("direct-access-port must be an integer from 1 to 65535"));
+        assert!(err.contains("approve-mode must be one of"));
+        assert!(!err.contains("enable-audio"));
+    }
+
+    #[test]
+    fn test_parse_strategy_order() {
+        assert_eq!(StrategyOrder::parse(""), Ok(StrategyOrder::Default));
+        assert_eq!(StrategyOrder::parse("name"), Ok(StrategyOrder::Name));
+        assert_eq!(StrategyOrder::parse("-modified_at"), Ok(StrategyOrder::ModifiedAtDesc));
+        assert!(StrategyOrder::parse("name; DROP TABLE strategies").is_err());
+    }
+
+    #[test]
+    fn test_strategy_settings_match_the_known_options() {
+        let defaults = serde_json::to_value(StrategySettings::default()).unwrap();
+        let mut names = defaults.as_object().unwrap().keys().cloned().collect::<Vec<_>>();
+        let mut options = STRATEGY_OPTIONS
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect::<Vec<_>>();
+        names.sort();
+        options.sort();
+        assert_eq!(names, options);
+        // the defaults are valid settings and survive a round trip
+        assert!(validate_strategy_settings(&defaults, false).is_ok());
+        let parsed: StrategySettings = serde_json::from_value(defaults).unwrap();
+        assert_eq!(parsed, StrategySettings::default());
+
+        let parsed: StrategySettings = serde_json::from_value(json!({
+            "enable-audio": false,
+            "enable-clipboard": "N",
+            "direct-access-port": "2000",
+            "x-new": 1
+        }))
+        .unwrap();
+        assert!(!parsed.enable_audio);
+        assert!(!parsed.enable_clipboard);
+        assert!(parsed.enable_keyboard);
+        assert_eq!(parsed.direct_access_port, 2000);
+        assert_eq!(parsed.other.get("x-new"), Some(&json!(1)));
+    }
+
+    #[test]
+    fn test_merge_strategy_settings_precedence() {
+        // every combination of the levels setting the same option, the most specific one wins
+        let levels = [
+            (StrategySource::Default, "5"),
+            (StrategySource::Group, "6"),
+            (StrategySource::User, "7"),
+            (StrategySource::Device, "8"),
+        ];
+        for mask in 0..(1 << levels.len()) {
+            let strategies = levels
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| mask & (1 << i) != 0)
+                .map(|(_, (source, timeout))| {
+                    (
+                        *source,
+                        strategy(
+                            &format!("{:?}", source),
+                            json!({ "auto-disconnect-timeout": timeout }),
+                        ),
+                    )
+                })
+                .collect::<Vec<_>>();
+            let effective = merge_strategy_settings(&strategies);
+            let provenance = &effective.provenance["auto-disconnect-timeout"];
+            match strategies.last() {
+                Some((source, strategy)) => {
+                    let expected = strategy.settings["auto-disconnect-timeout"]
+                        .as_str()
+                        .unwrap()
+                        .parse::<u32>()
+                        .unwrap();
+                    assert_eq!(effective.settings.auto_disconnect_timeout, expected);
+                    assert_eq!(provenance.source, *source);
+                    assert_eq!(provenance.strategy.as_deref(), Some(strategy.name.as_str()));
+                }
+                None => {
+                    assert_eq!(effective.settings, StrategySettings::default());
+                    assert_eq!(provenance.source, StrategySource::Builtin);
+                    assert_eq!(provenance.strategy, None);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_merge_strategy_settings_per_option() {
+        let default = strategy(
+            "Default",
+            json!({"enable-audio": "N", "enable-clipboard": "N", "whitelist": "10.0.0.0/8"}),
+        );
+        let group = strategy("Finance", json!({"enable-clipboard": "Y", "x-new": true}));
+        let user = strategy("Staff", json!({}));
+        // stored before the settings were validated
+        let device = strategy("Kiosk", json!({"whitelist": 12, "enable-audio": "Y"}));
+        let effective = merge_strategy_settings(&[
+            (StrategySource::Default, default),
+            (StrategySource::Group, group),
+            (StrategySource::User, user),
+            (StrategySource::Device, device),
+        ]);
+        assert!(effective.settings.enable_audio);
+        assert!(effective.settings.enable_clipboard);
+        assert_eq!(effective.settings.whitelist, "10.0.0.0/8");
+        assert_eq!(effective.settings.other.get("x-new"), Some(&json!(true)));
+        let source = |name: &str| {
+            let provenance = &effective.provenance[name];
+            (provenance.source, provenance.strategy.clone().unwrap_or_default())
+        };
+        assert_eq!(source("enable-audio"), (StrategySource::Device, "Kiosk".to_string()));
+        assert_eq!(source("enable-clipboard"), (StrategySource::Group, "Finance".to_string()));
+        assert_eq!(source("whitelist"), (StrategySource::Default, "Default".to_string()));
+        assert_eq!(source("x-new"), (StrategySource::Group, "Finance".to_string()));
+        assert_eq!(source("enable-keyboard"), (StrategySource::Builtin, String::new()));
+        assert_eq!(effective.provenance.len(), STRATEGY_OPTIONS.len() + 1);
+    }
+}
@@ -0,0 +1,133 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use rocket_okapi::okapi::schemars;
+use rocket_okapi::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Default colors of the tags, ARGB values as stored by the RustDesk clients
+pub const TAG_PALETTE: [u32; 14] = [
+    0xFFF44336, // red
+    0xFFE91E63, // pink
+    0xFF9C27B0, // purple
+    0xFF673AB7, // deep purple
+    0xFF3F51B5, // indigo
+    0xFF2196F3, // blue
+    0xFF00BCD4, // cyan
+    0xFF009688, // teal
+    0xFF4CAF50, // green
+    0xFFCDDC39, // lime
+    0xFFFFC107, // amber
+    0xFFFF9800, // orange
+    0xFF795548, // brown
+    0xFF9E9E9E, // grey
+];
+
+/// Color of a tag as sent by the clients
+///
+/// The RustDesk clients send an ARGB number, the web console a `#RRGGBB` or `#RGB` string,
+/// a number lower than the size of `TAG_PALETTE` is an index in the palette
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(untagged)]
+pub enum TagColor {
+    Value(u32),
+    Hex(String),
+}
+
+impl TagColor {
+    /// ARGB value of the color
+    ///
+    /// # Returns
+    /// The color, or an error if it is not a valid hex color, or a fully transparent ARGB value which would not be visible
+    pub fn argb(&self) -> Result<u32, String> {
+        match self {
+            TagColor::Value(index) if (*index as usize) < TAG_PALETTE.len() => Ok(TAG_PALETTE[*index as usize]),
+            TagColor::Value(argb) if *argb > 0x00FFFFFF => Ok(*argb),
+            TagColor::Value(value) => Err(format!(
+                "Invalid tag color {}: it must be a palette index lower than {} or an ARGB value with an alpha",
+                value,
+                TAG_PALETTE.len()
+            )),
+            TagColor::Hex(hex) => parse_hex_color(hex),
+        }
+    }
+}
+
+/// Parse a `#RRGGBB` or `#RGB` color into an opaque ARGB value
+fn parse_hex_color(color: &str) -> Result<u32, String> {
+    let invalid = || format!("Invalid tag color {}: it must be #RRGGBB or #RGB", color);
+    let digits = color.strip_prefix('#').ok_or_else(invalid)?;
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let rgb = match digits.len() {
+        6 => u32::from_str_radix(digits, 16).map_err(|_| invalid())?,
+        // each digit is doubled, #F80 is #FF8800
+        3 => digits
+            .chars()
+            .filter_map(|c| c.to_digit(16))
+            .fold(0, |rgb, digit| (rgb << 8) | (digit * 0x11)),
+        _ => return Err(invalid()),
+    };
+    Ok(0xFF000000 | rgb)
+}
+
+/// Color of a tag created without one
+/// It is picked in `TAG_PALETTE` from the name of the tag, so a tag keeps its color when it is recreated
+pub fn default_tag_color(name: &str) -> u32 {
+    // FNV-1a, stable between the builds unlike the std hasher
+    let hash = name
+        .bytes()
+        .fold(0x811C9DC5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193));
+    TAG_PALETTE[hash as usize % TAG_PALETTE.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_tag_color() {
+        assert_eq!(TagColor::Hex("#FF0000".to_string()).argb(), Ok(0xFFFF0000));
+        assert_eq!(TagColor::Hex("#2196f3".to_string()).argb(), Ok(0xFF2196F3));
+        assert_eq!(TagColor::Hex("#F80".to_string()).argb(), Ok(0xFFFF8800));
+        assert_eq!(TagColor::Value(4288585374).argb(), Ok(4288585374));
+        assert_eq!(TagColor::Value(5).argb(), Ok(TAG_PALETTE[5]));
+        let color: TagColor = serde_json::from_str("\"#00ff00\"").unwrap();
+        assert_eq!(color.argb(), Ok(0xFF00FF00));
+        let color: TagColor = serde_json::from_str("4278190335").unwrap();
+        assert_eq!(color.argb(), Ok(0xFF0000FF));
+    }
+
+    #[test]
+    fn test_invalid_tag_color() {
+        for color in ["", "red", "FF0000", "#FF00", "#GG0000", "#FF00000", "#+F0000"] {
+            assert!(TagColor::Hex(color.to_string()).argb().is_err(), "{} should be rejected", color);
+        }
+        assert!(TagColor::Value(TAG_PALETTE.len() as u32).argb().is_err());
+        assert!(TagColor::Value(0x00FFFFFF).argb().is_err());
+    }
+
+    #[test]
+    fn test_default_tag_color() {
+        assert_eq!(default_tag_color("office"), default_tag_color("office"));
+        assert!(TAG_PALETTE.contains(&default_tag_color("office")));
+        assert!(TAG_PALETTE.contains(&default_tag_color("")));
+        // the colors are spread over the palette
+        let colors: std::collections::HashSet<u32> =
+            ["office", "home", "servers", "laptops", "family", "customers"].iter().map(|name| default_tag_color(name)).collect();
+        assert!(colors.len() > 1);
+    }
+}
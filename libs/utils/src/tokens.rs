@@ -36,9 +36,10 @@ impl Token {
     }
 
     pub fn from_str<S: AsRef<str>>(str: S) -> Result<Self, base64::DecodeError> {
-        let bytes = BASE64_URL_SAFE_NO_PAD.decode(str.as_ref()).unwrap();
-        let mut buf = [0u8; TOKEN_LENGTH];
-        buf.copy_from_slice(&bytes);
+        let bytes = BASE64_URL_SAFE_NO_PAD.decode(str.as_ref())?;
+        let buf: [u8; TOKEN_LENGTH] = bytes
+            .try_into()
+            .map_err(|bytes: Vec<u8>| base64::DecodeError::InvalidLength(bytes.len()))?;
         Ok(Self(buf))
     }
 }
@@ -13,9 +13,10 @@
 //
 // You should have received a copy of the Affero General Public License
 // along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::sync::Arc;
+use std::time::Instant;
 
 use oauth2::oauth_provider::OAuthProvider;
 use rocket_okapi::okapi::schemars;
@@ -23,6 +24,9 @@ use rocket_okapi::JsonSchema;
 use serde::de::Visitor;
 use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::audit::{AuditCategory, AUDIT_RETENTION_DEFAULT_DAYS, AUDIT_RETENTION_MAX_DAYS};
+use crate::semver::Version;
+use crate::tag_color::{default_tag_color, TagColor};
 use crate::Token;
 
 pub type SessionId = u64;
@@ -124,7 +128,7 @@ pub struct SystemInfo {
     pub ip: Option<String>,
 }
 
-#[derive(Deserialize, Debug, JsonSchema)]
+#[derive(Deserialize, JsonSchema)]
 pub struct LoginRequest {
     pub username: String,
     pub password: String,
@@ -132,11 +136,26 @@ pub struct LoginRequest {
     pub uuid: String,
 }
 
+/// The password is never printed
+impl std::fmt::Debug for LoginRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LoginRequest")
+            .field("username", &self.username)
+            .field("password", &"***")
+            .field("id", &self.id)
+            .field("uuid", &self.uuid)
+            .finish()
+    }
+}
+
 #[derive(Deserialize, Serialize, Debug, JsonSchema, Clone, Default)]
 pub struct UserInfo {
     pub name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub email: Option<String>,
+    /// whether the user proved they own their email, see `/api/user/email/verify-request`
+    #[serde(default)]
+    pub email_verified: bool,
     pub admin: bool
 }
 
@@ -170,19 +189,56 @@ pub struct AbRequest {
     pub data: String,
 }
 
-#[derive(Deserialize, Debug, JsonSchema)]
+/// Audit record sent by the clients to `/api/audit/<typ>`
+/// The fields depend on the kind of audit (connection, file transfer or alarm),
+/// the unknown ones are kept in `extra`
+#[derive(Deserialize, Serialize, Debug, Default, JsonSchema)]
 pub struct AuditRequest {
     #[serde(default)]
     #[serde(rename = "Id")]
     pub id_: usize,
     #[serde(default)]
     pub action: String,
+    /// id of the peer sending the audit
     #[serde(default)]
     pub id: String,
     #[serde(default)]
     pub ip: String,
     #[serde(default)]
     pub uuid: String,
+    /// connection: id of the connection on the peer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub conn_id: Option<i64>,
+    /// connection: id of the session of the remote peer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_id: Option<u64>,
+    /// connection: duration of the connection in seconds, sent with the close record
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<i64>,
+    /// connection: id and name of the remote peer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer: Option<Vec<String>>,
+    /// connection: type of the connection, file transfer: direction (0 send, 1 receive)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename = "type")]
+    pub type_: Option<i64>,
+    /// file transfer: id of the remote peer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub peer_id: Option<String>,
+    /// file transfer: path of the file or directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// file transfer: true for a file, false for a directory
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_file: Option<bool>,
+    /// alarm: type of the alarm
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub typ: Option<i64>,
+    /// file transfer and alarm: details, usually a JSON string
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub info: Option<serde_json::Value>,
+    #[serde(flatten)]
+    pub extra: HashMap<String, serde_json::Value>,
 }
 
 // {
@@ -205,6 +261,21 @@ pub struct AbGetResponse {
     pub data: String,
 }
 
+/// Users whose address books are fetched by an admin
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct AbBatchGetRequest {
+    /// guids of the users
+    pub users: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+pub struct AbBatchGetResponse {
+    /// address book of each user, keyed by the guid of the user, in the format of `/api/ab/get`
+    pub data: BTreeMap<String, String>,
+    /// guids of the users without address book
+    pub not_found: Vec<String>,
+}
+
 #[derive(Serialize, Debug, JsonSchema)]
 pub struct AbPersonal {
     pub guid: String,
@@ -296,6 +367,10 @@ pub struct AbPeer {
         serialize_with = "from_bool_to_str"
     )]
     pub same_server: Option<bool>,
+    /// Optimistic concurrency version of the peer, bumped on each change, a peer without version is at 0
+    /// When sent with an update the peer is only updated if its version is still this one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<u64>,
 }
 impl Default for AbPeer {
     fn default() -> Self {
@@ -313,6 +388,7 @@ impl Default for AbPeer {
             rdp_username: Some("".to_string()),
             login_name: Some("".to_string()),
             same_server: None,
+            version: None,
         }
     }
 }
@@ -333,8 +409,42 @@ impl AbPeer {
             rdp_username: Some("".to_string()),
             login_name: Some("user".to_string()),
             same_server: None,
+            version: None,
         }
     }
+
+    /// Complete the peer with the information of a duplicate: the tags become the union of both,
+    /// the fields empty here take the value of the duplicate, the other ones are kept
+    pub fn merge_from(&mut self, duplicate: &AbPeer) {
+        fn fill(field: &mut Option<String>, duplicate: &Option<String>) {
+            let empty = |value: &Option<String>| value.as_deref().map_or(true, str::is_empty);
+            if empty(field) && !empty(duplicate) {
+                *field = duplicate.clone();
+            }
+        }
+        fill(&mut self.hash, &duplicate.hash);
+        fill(&mut self.password, &duplicate.password);
+        fill(&mut self.username, &duplicate.username);
+        fill(&mut self.hostname, &duplicate.hostname);
+        fill(&mut self.platform, &duplicate.platform);
+        fill(&mut self.alias, &duplicate.alias);
+        fill(&mut self.rdp_port, &duplicate.rdp_port);
+        fill(&mut self.rdp_username, &duplicate.rdp_username);
+        fill(&mut self.login_name, &duplicate.login_name);
+        if self.force_always_relay.is_none() {
+            self.force_always_relay = duplicate.force_always_relay;
+        }
+        if self.same_server.is_none() {
+            self.same_server = duplicate.same_server;
+        }
+        let mut tags = self.tags.take().unwrap_or_default();
+        for tag in duplicate.tags.iter().flatten() {
+            if !tags.contains(tag) {
+                tags.push(tag.clone());
+            }
+        }
+        self.tags = Some(tags);
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
@@ -351,18 +461,90 @@ impl Default for AbTag {
     }
 }
 
+/// Tag sent to `/api/ab/tag/add` and `/api/ab/tag/update`
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AbTagRequest {
+    pub name: String,
+    /// `#RRGGBB`, `#RGB`, an ARGB value or an index in the palette, a default color is assigned when it is missing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<TagColor>,
+}
+
+impl AbTagRequest {
+    /// Validate the color of the tag
+    pub fn into_tag(self) -> Result<AbTag, String> {
+        let color = match &self.color {
+            Some(color) => color.argb()?,
+            None => default_tag_color(&self.name),
+        };
+        Ok(AbTag {
+            name: self.name,
+            color,
+        })
+    }
+}
+
+/// A tag of an address book with the number of peers using it
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AbTagStats {
+    pub name: String,
+    pub color: u32,
+    /// number of peers of the address book having this tag
+    pub peers: u32,
+}
+
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct AbTagRenameRequest {
     pub old: String,
     pub new: String,
 }
 
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AbPeersTagRequest {
+    /// ids of the peers to update
+    pub peer_ids: Vec<String>,
+    /// tags to add to each peer
+    #[serde(default)]
+    pub add_tags: Vec<String>,
+    /// tags to remove from each peer
+    #[serde(default)]
+    pub remove_tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AbPeersTagResponse {
+    /// number of peers whose tags changed
+    pub modified: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AbPeersMergeRequest {
+    /// id of the peer which is kept
+    pub keep_id: String,
+    /// ids of the duplicates merged into the kept peer then deleted
+    pub merge_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct AbPeersMergeResponse {
+    /// number of duplicates deleted
+    pub merged: u32,
+    /// the kept peer once merged
+    pub peer: AbPeer,
+}
+
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct AbPeersResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
     pub total: u32,
     pub data: Vec<AbPeer>,
+    /// cursor of the next page, only when the peers are paged with `after` and more peers remain
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    /// number of peers per page used by the server, `pageSize` once clamped
+    #[serde(default)]
+    pub page_size: u32,
 }
 impl Default for AbPeersResponse {
     fn default() -> Self {
@@ -370,6 +552,8 @@ impl Default for AbPeersResponse {
             error: None,
             total: 0,
             data: Vec::new(),
+            next_cursor: None,
+            page_size: 0,
         }
     }
 }
@@ -379,6 +563,8 @@ impl AbPeersResponse {
             error: None,
             total: 1,
             data: vec![AbPeer::default_test()],
+            next_cursor: None,
+            page_size: 0,
         }
     }
 }
@@ -391,6 +577,73 @@ pub struct HeartbeatRequest {
     pub ver: u32,
 }
 
+/// A directive sent back to a client in the heartbeat answer
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HeartbeatDirective {
+    /// the token sent by the client is no longer valid, the user must log in again
+    Relogin,
+    /// the effective strategy of the peer differs from the `modified_at` sent by the client,
+    /// `version` is the one to send back once `settings` are applied
+    ConfigChanged {
+        version: u64,
+        /// name of the effective strategy
+        strategy: String,
+        settings: serde_json::Value,
+    },
+    /// the server goes into maintenance at `at`, in seconds since the epoch
+    MaintenanceSoon { at: u64, message: Option<String> },
+    /// the client is older than the minimum client version, the heartbeat was ignored
+    UpgradeRequired(ClientUpgradeResponse),
+    /// the heartbeat came sooner than the minimum interval after the previous one and was not recorded,
+    /// the client should not beat more often than every `interval` seconds
+    SlowDown { interval: u64 },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct HeartbeatResponse {
+    /// `OK`, or the reason why the heartbeat was ignored: `INVALID_ID`, `UUID_MISMATCH` or `CLIENT_TOO_OLD`
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub directives: Vec<HeartbeatDirective>,
+}
+
+/// Answer to a client older than the minimum client version
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct ClientUpgradeResponse {
+    /// message shown to the user
+    pub error: String,
+    /// oldest client version still supported
+    pub min_version: String,
+    /// page where the client can be downloaded
+    pub url: String,
+}
+
+/// A maintenance announced to the clients in the heartbeat answers until it starts
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct MaintenanceNotice {
+    /// start of the maintenance in seconds since the epoch
+    pub at: u64,
+    pub message: Option<String>,
+}
+
+/// Request to log every user out
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub struct RevokeAllSessionsRequest {
+    /// must be true, guards against an accidental call
+    #[serde(default)]
+    pub confirm: bool,
+    /// keep the session of the calling admin
+    #[serde(default)]
+    pub keep_current: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct RevokeAllSessionsResponse {
+    /// number of revoked access tokens
+    pub revoked: usize,
+}
+
 #[derive(Debug, Deserialize, JsonSchema)]
 pub struct SystemInfoRequest {
     pub cpu: String,
@@ -410,6 +663,168 @@ pub struct UsersResponse {
     pub data: String,
 }
 
+/// Field of a user which must be unique
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum UserField {
+    Name,
+    Email,
+}
+
+/// Answer when a user cannot be added or updated because its name or email is already used
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct UserConflictResponse {
+    pub error: String,
+    /// the field already used by another user
+    pub field: UserField,
+}
+
+/// Operation reserved to the admins
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminPermission {
+    /// add, update, enable and disable the users
+    ManageUsers,
+    /// add, update and delete the groups
+    ManageGroups,
+    /// list, update, export and delete the peers
+    ManagePeers,
+    /// add, update, delete and assign the strategies
+    ManageStrategies,
+    /// read and export the audits, set their retention
+    ReadAudits,
+    /// maintenance, OIDC providers and integration checks
+    ManageServer,
+}
+
+impl AdminPermission {
+    pub const ALL: [AdminPermission; 6] = [
+        AdminPermission::ManageUsers,
+        AdminPermission::ManageGroups,
+        AdminPermission::ManagePeers,
+        AdminPermission::ManageStrategies,
+        AdminPermission::ReadAudits,
+        AdminPermission::ManageServer,
+    ];
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct GroupMembership {
+    pub guid: String,
+    pub name: String,
+}
+
+/// Access of a user to a shared address book
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct AddressBookPermission {
+    pub guid: String,
+    pub name: String,
+    /// highest rule granted to the user or to its group, 3 (full control) for the owner
+    pub rule: u32,
+}
+
+/// Why a user or a group can control a peer
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerAccessSource {
+    /// the peer is assigned to the user
+    Owner,
+    /// the user is an admin
+    Admin,
+    /// the user owns a shared address book containing the peer
+    AddressBookOwner,
+    /// a rule of a shared address book containing the peer grants access to the user or to the group
+    AddressBookRule,
+    /// the user redeemed a share link of the peer which has not expired
+    ShareLink,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct PeerAccessGrant {
+    pub source: PeerAccessSource,
+    /// the shared address book containing the peer, for the address book grants
+    pub address_book: Option<AddressBookPermission>,
+    /// end of the access, for the share link grants
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+}
+
+/// Request of `POST /api/peer/<id>/share-link`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+pub struct ShareLinkRequest {
+    /// validity of the link in seconds, one hour when not given
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+/// A share link of a peer, the token is only given when the link is created
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ShareLinkResponse {
+    pub guid: String,
+    pub peer_id: String,
+    pub token: String,
+    /// the link to send to the recipient, `GET /api/share/<token>`
+    pub url: String,
+    pub expires_at: String,
+}
+
+/// Access to a peer granted by redeeming a share link
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct ShareLinkRedeemResponse {
+    pub msg: String,
+    pub peer_id: String,
+    /// end of the access
+    pub expires_at: String,
+}
+
+/// Reason why a share link cannot be redeemed
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ShareLinkError {
+    /// the token is malformed, its signature is wrong or the link does not exist anymore
+    Invalid,
+    Expired,
+}
+
+/// A user or a group allowed to control a peer, with all the grants it holds
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct PeerAccessEntry {
+    pub guid: String,
+    pub name: String,
+    pub grants: Vec<PeerAccessGrant>,
+}
+
+/// The users and the groups allowed to control a peer
+/// The members of a listed group are not repeated in the users
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct PeerAccess {
+    pub id: String,
+    pub users: Vec<PeerAccessEntry>,
+    pub groups: Vec<PeerAccessEntry>,
+}
+
+/// Limits applying to the requests of a user, None when it cannot make them
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, JsonSchema)]
+pub struct PermissionQuotas {
+    /// maximum number of peers in one `/api/peers/bulk` request
+    pub peers_bulk_max_size: Option<u32>,
+}
+
+/// What a user can do, computed from its role, its group and the address book rules
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct UserPermissions {
+    pub guid: String,
+    pub name: String,
+    pub is_admin: bool,
+    /// the groups of the user, a user belongs to one group
+    pub groups: Vec<GroupMembership>,
+    /// the shared address books the user can access
+    pub address_books: Vec<AddressBookPermission>,
+    /// the operations the user can make as an admin
+    pub admin: Vec<AdminPermission>,
+    pub quotas: PermissionQuotas,
+}
+
 #[derive(Serialize, Deserialize, Debug, JsonSchema)]
 pub struct User {
     name: String,
@@ -441,7 +856,7 @@ pub struct UpdateUserRequest {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub note: Option<String>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub status: Option<i32>,
+    pub status: Option<UserStatus>,
 }
 impl Default for UpdateUserRequest {
     fn default() -> Self {
@@ -496,31 +911,68 @@ pub struct AuthQueryParams {
     pub uuid: String,
 }
 
-// OIDC response
-#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+/// Status of a user, stored in the `status` column of the `user` table
+/// It is a number on the wire, as the RustDesk clients expect:
+/// * `1` Normal, the user can log in
+/// * `0` Disabled, the user cannot log in
+/// * `-1` Unverified, the user cannot log in until it is verified
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "i32", into = "i32")]
 #[repr(i32)]
-pub enum OidcUserStatus {
+pub enum UserStatus {
     Disabled = 0,
+    #[default]
     Normal = 1,
     Unverified = -1,
 }
-impl Default for OidcUserStatus {
-    fn default() -> Self {
-        OidcUserStatus::Normal
+
+impl From<UserStatus> for i32 {
+    fn from(status: UserStatus) -> Self {
+        status as i32
     }
 }
-impl Into<i32> for OidcUserStatus {
-    fn into(self) -> i32 {
-        self as i32
+
+impl From<UserStatus> for i64 {
+    fn from(status: UserStatus) -> Self {
+        status as i64
     }
 }
 
-impl Into<i64> for OidcUserStatus {
-    fn into(self) -> i64 {
-        self as i64
+impl TryFrom<i32> for UserStatus {
+    type Error = String;
+
+    fn try_from(status: i32) -> Result<Self, Self::Error> {
+        match status {
+            0 => Ok(UserStatus::Disabled),
+            1 => Ok(UserStatus::Normal),
+            -1 => Ok(UserStatus::Unverified),
+            _ => Err(format!("invalid user status {}, expected 1, 0 or -1", status)),
+        }
     }
 }
 
+impl JsonSchema for UserStatus {
+    fn schema_name() -> String {
+        "UserStatus".to_string()
+    }
+
+    fn json_schema(_: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        schemars::schema::SchemaObject {
+            metadata: Some(Box::new(schemars::schema::Metadata {
+                description: Some("1 normal, 0 disabled, -1 unverified".to_string()),
+                ..Default::default()
+            })),
+            instance_type: Some(schemars::schema::InstanceType::Integer.into()),
+            enum_values: Some(vec![1.into(), 0.into(), (-1).into()]),
+            ..Default::default()
+        }
+        .into()
+    }
+}
+
+// OIDC response
+pub type OidcUserStatus = UserStatus;
+
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct OidcResponse {
     pub access_token: String,
@@ -561,6 +1013,10 @@ pub struct OidcState {
     pub provider: Option<Arc<dyn OAuthProvider>>,
     pub name: Option<String>,
     pub email: Option<String>,
+    /// op of the provider, as in the providers config file
+    pub op: String,
+    /// when the auth request was made
+    pub created_at: Instant,
 }
 impl Default for OidcState {
     fn default() -> Self {
@@ -574,10 +1030,98 @@ impl Default for OidcState {
             provider: None,
             name: None,
             email: None,
+            op: "".to_string(),
+            created_at: Instant::now(),
         }
     }
 }
 
+/// Progress of an OIDC session
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OidcSessionStatus {
+    /// the provider has not called back yet, or the code exchange failed
+    Pending,
+    /// the code was exchanged for a token, the client has not fetched it yet
+    Exchanged,
+}
+
+/// An OIDC session in flight, without its secrets
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct OidcSession {
+    /// first characters of the session code
+    pub code: String,
+    /// provider type, as in the providers config file
+    pub provider: String,
+    /// seconds since the auth request
+    pub age: u64,
+    pub state: OidcSessionStatus,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct OidcSessionsResponse {
+    pub msg: String,
+    pub total: u32,
+    pub data: Vec<OidcSession>,
+}
+
+/// An OAuth provider the user has logged in with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct LinkedProvider {
+    /// op of the provider, as in the providers config file
+    pub op: String,
+    /// name of the user at the provider
+    pub identifier: String,
+    pub email: String,
+    /// false if the provider has been removed from the config file, the user cannot log in with it anymore
+    pub enabled: bool,
+    /// first login with the provider
+    pub linked_at: String,
+    /// last login with the provider
+    pub last_used_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct LinkedProvidersResponse {
+    pub msg: String,
+    /// false if the account was created by an OAuth login and has no password set
+    pub password_login: bool,
+    pub total: u32,
+    pub data: Vec<LinkedProvider>,
+}
+
+/// Reason why a provider cannot be unlinked
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum UnlinkProviderError {
+    NotLinked,
+    /// the user has no password and no other provider, they could not log in anymore
+    LastLoginMethod,
+}
+
+/// Reason why the email of a user cannot be verified
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EmailVerificationError {
+    /// the user has no email to verify
+    NoEmail,
+    AlreadyVerified,
+    /// the token is unknown, already used or was issued for a previous email of the user
+    InvalidToken,
+    Expired,
+}
+
+/// State of the verification of the email of a user
+#[derive(Serialize, Deserialize, Debug, JsonSchema)]
+pub struct EmailVerificationResponse {
+    pub msg: String,
+    pub email: String,
+    pub email_verified: bool,
+    /// expiration of the link sent, only when a link has just been sent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct OidcTokenResponse {
     pub access_token: String,
@@ -611,6 +1155,7 @@ pub struct AddUserRequest {
 #[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct EnableUserRequest {
     pub rows: Vec<String>,
+    /// true enables the users and false disables them, the web console relies on this historical meaning
     pub disable: bool,
 }
 
@@ -639,9 +1184,11 @@ pub struct UserListResponse {
     pub name: String,
     pub email: String,
     pub note: Option<String>,
-    pub status: i32,
+    pub status: UserStatus,
     pub group_name: String,
     pub is_admin: bool,
+    #[serde(default)]
+    pub email_verified: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, JsonSchema)]
@@ -649,19 +1196,204 @@ pub struct UserList {
     pub msg: String,
     pub total: u32,
     pub data: Vec<UserListResponse>,
+    /// cursor of the next page, only when the users are paged with `after` and more users remain
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    /// number of users per page used by the server, `pageSize` once clamped
+    #[serde(default)]
+    pub page_size: u32,
 }
 
 #[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct SoftwareResponse {
     pub url: String,
+    /// hex encoded sha256 of the release, only when a checksum is stored with it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// size of the release in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+}
+
+/// Answer when no download link can be given for a platform
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct SoftwareKeyError {
+    pub error: String,
+    /// the platforms the download links are served for
+    pub supported: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct SoftwareVersionResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub server: Option<String>,
+    /// latest client version, the clients offer to update when they are older
     #[serde(skip_serializing_if = "Option::is_none")]
     pub client: Option<String>,
+    /// oldest client version still supported
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_client: Option<String>,
+    /// download link endpoint of each platform with a configured release
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downloads: Option<BTreeMap<String, String>>,
+    /// release channel of the caller, the latest version and the download links are the ones of this channel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<ReleaseChannel>,
+}
+
+/// Update check sent by the RustDesk client to `/api/version/latest`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, JsonSchema)]
+#[serde(default)]
+pub struct VersionCheckRequest {
+    pub os: String,
+    pub os_version: String,
+    pub arch: String,
+    pub device_id: Vec<u8>,
+    /// kind of software, `rustdesk-client` for the client
+    pub typ: String,
+}
+
+/// Answer of `/api/version/latest`, in the format read by the RustDesk client
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct VersionCheckResponse {
+    /// page of the latest release, its last path segment is the latest version
+    pub url: String,
+    /// download link endpoint of each platform with a configured release
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub downloads: Option<BTreeMap<String, String>>,
+}
+
+/// Release channel of a client, the beta channel gets the pre-releases
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ReleaseChannel {
+    #[default]
+    Stable,
+    Beta,
+}
+
+impl ReleaseChannel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReleaseChannel::Stable => "stable",
+            ReleaseChannel::Beta => "beta",
+        }
+    }
+
+    /// Channels whose releases a client of this channel gets, from the preferred one,
+    /// the beta channel falls back to the stable releases of the platforms without a beta release
+    pub fn fallbacks(&self) -> &'static [ReleaseChannel] {
+        match self {
+            ReleaseChannel::Stable => &[ReleaseChannel::Stable],
+            ReleaseChannel::Beta => &[ReleaseChannel::Beta, ReleaseChannel::Stable],
+        }
+    }
+}
+
+impl std::str::FromStr for ReleaseChannel {
+    type Err = String;
+
+    fn from_str(channel: &str) -> Result<Self, Self::Err> {
+        match channel {
+            "stable" => Ok(ReleaseChannel::Stable),
+            "beta" => Ok(ReleaseChannel::Beta),
+            _ => Err(format!("{} is not a release channel, it must be stable or beta", channel)),
+        }
+    }
+}
+
+/// Assignment of a release channel to a user or a group
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AssignReleaseChannelRequest {
+    /// the channel, null to remove the assignment
+    pub channel: Option<ReleaseChannel>,
+}
+
+/// Log line sent by `/api/admin/logs/stream`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+pub struct LogLine {
+    /// RFC 3339 time in UTC
+    pub time: String,
+    /// `error`, `warn`, `info`, `debug` or `trace`
+    pub level: String,
+    /// module which logged the line
+    pub target: String,
+    pub message: String,
+}
+
+/// Release uploaded with `POST /api/software/releases`
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct SoftwareRelease {
+    /// platform of `SOFTWARE_KEYS`, e.g. `w64`
+    pub platform: String,
+    pub version: String,
+    pub channel: ReleaseChannel,
+    /// key of the release in the bucket
+    pub key: String,
+    /// size in bytes
+    pub size: u64,
+    /// hex encoded sha256 of the release
+    pub sha256: String,
+    /// name of the admin who uploaded it, empty if the admin was deleted
+    pub uploaded_by: String,
+    pub created_at: String,
+}
+
+/// Client versions announced by `/api/software/version/server`
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, JsonSchema)]
+pub struct ClientVersions {
+    /// latest client version, e.g. `1.2.6`
+    pub latest: Option<String>,
+    /// oldest client version still supported
+    pub minimum: Option<String>,
+}
+
+impl ClientVersions {
+    /// Check that the versions are semantic versions and that the minimum is not newer than the latest version
+    pub fn validate(&self) -> Result<(), String> {
+        let latest = self.latest.as_deref().map(str::parse::<Version>).transpose()?;
+        let minimum = self.minimum.as_deref().map(str::parse::<Version>).transpose()?;
+        if let (Some(latest), Some(minimum)) = (latest, minimum) {
+            if minimum > latest {
+                return Err(format!(
+                    "the minimum version {} is newer than the latest version {}",
+                    minimum, latest
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct BuildInfoResponse {
+    /// version of the server crate
+    pub version: String,
+    /// git commit the server was built from
+    pub git_hash: String,
+    /// build time in seconds since the Unix epoch
+    pub build_timestamp: u64,
+    /// cargo features enabled at build time
+    pub features: Vec<String>,
+}
+
+/// Features available on this server
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+pub struct CapabilitiesResponse {
+    /// at least one OAuth2 / OIDC provider is configured
+    pub oidc_enabled: bool,
+    /// the S3 storage is configured
+    pub s3_configured: bool,
+    /// the client downloads are available, they are served from the S3 storage
+    pub software_downloads: bool,
+    /// mail sending is not supported by this server
+    pub smtp_configured: bool,
+    /// two-factor authentication is not supported by this server
+    pub two_factor_auth: bool,
+    /// address books can be shared with other users and groups
+    pub shared_address_books: bool,
+    /// the web console is served by this server
+    pub web_console: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone, JsonSchema)]
@@ -696,6 +1428,550 @@ pub struct Peer{
     pub strategy_name: String,
     pub last_online: String,
     pub info: PeerInfo,
+    /// guid of the user the peer is assigned to
+    pub user: Option<String>,
+    /// name of the user the peer is assigned to
+    pub user_name: Option<String>,
+    /// name of the group of the peer
+    pub group_name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct PeerStatusInterval {
+    pub from: String,
+    pub to: String,
+    pub online: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct PeerHistoryResponse {
+    pub id: String,
+    pub from: String,
+    pub to: String,
+    /// percentage of the window during which the peer was online
+    pub availability: f64,
+    pub intervals: Vec<PeerStatusInterval>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct PeerUuidConflict {
+    /// id claimed by several machines
+    pub id: String,
+    /// uuid (base64) of the machine which is not the registered one
+    pub uuid: String,
+    /// number of rejected requests
+    pub count: i64,
+    pub first_seen: String,
+    pub last_seen: String,
+}
+
+/// Image shown by the console for a peer
+#[derive(Debug, Clone)]
+pub struct PeerAvatar {
+    /// `image/png` or `image/webp`
+    pub content_type: String,
+    pub data: Vec<u8>,
+    pub updated_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct PeerUuidConflictsResponse {
+    pub msg: String,
+    pub total: u32,
+    pub data: Vec<PeerUuidConflict>,
+}
+
+/// A connection to a peer, built from the audit records sent by the peer
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AuditConn {
+    /// id of the peer which was connected to
+    pub peer: String,
+    /// id of the connection on the peer
+    pub conn_id: i64,
+    /// id of the session of the remote peer
+    pub session_id: Option<String>,
+    /// type of the connection as reported by the peer
+    #[serde(rename = "type")]
+    pub conn_type: Option<i64>,
+    /// id of the remote peer which connected
+    pub remote: Option<String>,
+    /// name of the remote peer which connected
+    pub remote_name: Option<String>,
+    /// ip of the remote peer
+    pub ip: Option<String>,
+    /// alias of the peer in the personal address book of its user, else its device name
+    pub peer_name: Option<String>,
+    /// true if the peer was deleted since, only its id is known
+    pub peer_deleted: bool,
+    /// name of the user the peer is assigned to
+    pub user: Option<String>,
+    /// guid of the user the peer is assigned to
+    pub user_id: Option<String>,
+    /// true if the user was deleted since, only its guid is known
+    pub user_deleted: bool,
+    /// time of the first record of the connection
+    pub start: String,
+    /// time of the close record, None while the connection is open
+    pub end: Option<String>,
+    /// duration of the connection in seconds, None while the connection is open
+    pub duration: Option<i64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AuditConnsResponse {
+    pub msg: String,
+    /// number of connections matching the filters
+    pub total: u32,
+    pub data: Vec<AuditConn>,
+}
+
+/// A file transfer, as reported by the peer
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AuditFile {
+    /// id of the peer which reported the transfer
+    pub peer: String,
+    /// id of the remote peer
+    pub remote: Option<String>,
+    /// 0 if the peer sent the file, 1 if it received it
+    pub direction: i64,
+    /// path of the file or directory on the peer, as sent by the peer
+    pub path: String,
+    /// true for a file, false for a directory
+    pub is_file: bool,
+    /// ip of the remote peer
+    pub ip: Option<String>,
+    /// number of files transferred
+    pub files: i64,
+    /// total size of the files in bytes
+    pub size: i64,
+    /// alias of the peer in the personal address book of its user, else its device name
+    pub peer_name: Option<String>,
+    /// true if the peer was deleted since, only its id is known
+    pub peer_deleted: bool,
+    /// name of the user the peer is assigned to
+    pub user: Option<String>,
+    /// guid of the user the peer is assigned to
+    pub user_id: Option<String>,
+    /// true if the user was deleted since, only its guid is known
+    pub user_deleted: bool,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AuditFilesResponse {
+    pub msg: String,
+    /// number of file transfers matching the filters
+    pub total: u32,
+    pub data: Vec<AuditFile>,
+}
+
+/// An alarm raised by a peer, e.g. too many failed password attempts
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AuditAlarm {
+    /// id of the peer which raised the alarm
+    pub peer: String,
+    /// type of the alarm: 0 ip not whitelisted, 1 more than 30 failed attempts, 2 six failed attempts within a minute
+    #[serde(rename = "type")]
+    pub alarm_type: i64,
+    /// info, warning or critical
+    pub severity: String,
+    /// id of the remote peer which triggered the alarm
+    pub remote: Option<String>,
+    /// name of the remote peer which triggered the alarm
+    pub remote_name: Option<String>,
+    /// ip of the remote peer
+    pub ip: Option<String>,
+    /// alias of the peer in the personal address book of its user, else its device name
+    pub peer_name: Option<String>,
+    /// true if the peer was deleted since, only its id is known
+    pub peer_deleted: bool,
+    /// name of the user the peer is assigned to
+    pub user: Option<String>,
+    /// guid of the user the peer is assigned to
+    pub user_id: Option<String>,
+    /// true if the user was deleted since, only its guid is known
+    pub user_deleted: bool,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AuditAlarmsResponse {
+    pub msg: String,
+    /// number of alarms matching the filters
+    pub total: u32,
+    pub data: Vec<AuditAlarm>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct OidcReloadResponse {
+    pub msg: String,
+    /// number of providers loaded
+    pub providers: u32,
+    /// why the config file was rejected, the previous providers are kept in this case
+    pub errors: Vec<String>,
+}
+
+/// A login of a user, as recorded in its session
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AuditLogin {
+    pub user: Option<String>,
+    /// guid of the user
+    pub user_id: Option<String>,
+    /// true if the user was deleted since, only its guid is known
+    pub user_deleted: bool,
+    /// ip of the client
+    pub ip: Option<String>,
+    /// name of the device the user logged in from
+    pub device_name: Option<String>,
+    pub os: Option<String>,
+    /// type of the client, as reported when logging in
+    #[serde(rename = "type")]
+    pub client_type: Option<String>,
+    pub created_at: String,
+    pub expiry_at: String,
+}
+
+/// A strategy: client settings applied to the peers
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct Strategy {
+    pub guid: String,
+    pub name: String,
+    /// RustDesk client options, the unknown ones are kept as they were sent when they are allowed
+    pub settings: serde_json::Value,
+    /// true for the strategy applied when no other one is, there is exactly one
+    pub is_default: bool,
+    pub created_at: String,
+    pub modified_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub struct DuplicateStrategyRequest {
+    /// name of the copy, `<name> (copy)` by default
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct StrategiesResponse {
+    pub msg: String,
+    pub total: u32,
+    pub data: Vec<Strategy>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AddStrategyRequest {
+    pub name: String,
+    #[serde(default = "empty_json_object")]
+    pub settings: serde_json::Value,
+    /// make the new strategy the default one, the previous default loses the flag
+    #[serde(default)]
+    pub is_default: bool,
+}
+
+/// The fields left out are not changed
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct UpdateStrategyRequest {
+    pub name: Option<String>,
+    pub settings: Option<serde_json::Value>,
+    /// only true is accepted: the default strategy is changed by marking another one
+    pub is_default: Option<bool>,
+}
+
+fn empty_json_object() -> serde_json::Value {
+    serde_json::Value::Object(serde_json::Map::new())
+}
+
+/// Level at which the effective strategy of a peer is assigned
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StrategySource {
+    /// assigned to the peer itself
+    Device,
+    /// assigned to the user the peer is assigned to
+    User,
+    /// assigned to the group of the peer
+    Group,
+    /// the default strategy
+    Default,
+    /// no strategy, the default value of the client option
+    Builtin,
+}
+
+/// The strategy applied to a peer: the one of the device, else the one of its user, else the default one
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct EffectiveStrategy {
+    pub source: StrategySource,
+    pub strategy: Strategy,
+}
+
+/// Assignment of a strategy to a peer or a user
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AssignStrategyRequest {
+    /// guid of the strategy, null to remove the assignment
+    pub strategy: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+pub struct PeerDetail {
+    #[serde(flatten)]
+    pub peer: Peer,
+    pub effective_strategy: Option<EffectiveStrategy>,
+}
+
+/// An integration which can be checked by the admins
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum IntegrationTarget {
+    /// the SMTP server, checked with EHLO
+    Smtp,
+    /// the alarm webhook, checked with a signed ping
+    Webhook,
+    /// the S3 storage, checked with a HEAD on a release key
+    S3,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct IntegrationTestRequest {
+    pub target: IntegrationTarget,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct IntegrationTestResponse {
+    pub target: IntegrationTarget,
+    pub success: bool,
+    /// answer of the integration or reason of the failure
+    pub detail: String,
+}
+
+/// An operation of an admin, as recorded in the admin audit trail
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AuditAdmin {
+    /// name of the admin, none if the admin was deleted since
+    pub actor: Option<String>,
+    /// guid of the admin
+    pub actor_id: String,
+    /// true if the admin was deleted since, only its guid is known
+    pub actor_deleted: bool,
+    /// e.g. `user.create`
+    pub action: String,
+    /// e.g. `POST /api/user`
+    pub route: String,
+    /// identifier of the user, group, peer... the operation applies to
+    pub target: Option<String>,
+    /// parameters of the operation, the passwords and the secrets are redacted
+    pub details: serde_json::Value,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct AuditAdminResponse {
+    pub msg: String,
+    /// number of operations matching the filters
+    pub total: u32,
+    pub data: Vec<AuditAdmin>,
+}
+
+/// Number of days the audit records of each category are kept, 0 keeps them forever
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+pub struct AuditRetention {
+    pub conn_days: u32,
+    pub file_days: u32,
+    pub alarm_days: u32,
+    /// the sessions opened by the users, a value shorter than the session lifetime ends the older sessions
+    pub login_days: u32,
+    /// the operations of the admins, `AUDIT_RETENTION_DAYS` when it was saved before this retention existed
+    #[serde(default = "default_admin_days")]
+    pub admin_days: u32,
+}
+
+fn default_admin_days() -> u32 {
+    std::env::var("AUDIT_RETENTION_DAYS")
+        .ok()
+        .and_then(|days| days.trim().parse::<u32>().ok())
+        .filter(|days| *days <= AUDIT_RETENTION_MAX_DAYS)
+        .unwrap_or(AUDIT_RETENTION_DEFAULT_DAYS)
+}
+
+impl Default for AuditRetention {
+    fn default() -> Self {
+        AuditRetention::uniform(AUDIT_RETENTION_DEFAULT_DAYS)
+    }
+}
+
+impl AuditRetention {
+    /// The same retention for every category
+    pub fn uniform(days: u32) -> Self {
+        AuditRetention {
+            conn_days: days,
+            file_days: days,
+            alarm_days: days,
+            login_days: days,
+            admin_days: days,
+        }
+    }
+
+    pub fn days(&self, category: AuditCategory) -> u32 {
+        match category {
+            AuditCategory::Conn => self.conn_days,
+            AuditCategory::File => self.file_days,
+            AuditCategory::Alarm => self.alarm_days,
+            AuditCategory::Login => self.login_days,
+            AuditCategory::Admin => self.admin_days,
+        }
+    }
+
+    /// Check that every retention is at most `AUDIT_RETENTION_MAX_DAYS`
+    pub fn validate(&self) -> Result<(), String> {
+        for category in AuditCategory::ALL {
+            if self.days(category) > AUDIT_RETENTION_MAX_DAYS {
+                return Err(format!(
+                    "The {} retention must be between 0 (forever) and {} days",
+                    category.as_str(),
+                    AUDIT_RETENTION_MAX_DAYS
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct PeerExport {
+    pub id: String,
+    /// alias given to the peer in the personal address book of its user
+    pub alias: Option<String>,
+    pub device_name: Option<String>,
+    pub os: Option<String>,
+    pub version: Option<String>,
+    /// name of the user the peer is assigned to
+    pub user: Option<String>,
+    /// name of the group of the peer
+    pub group: Option<String>,
+    pub last_online: String,
+    pub online: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum PeerEventType {
+    PeerOnline,
+    PeerOffline,
+    PeerRegistered,
+}
+
+impl PeerEventType {
+    /// Name of the event in the Server-Sent Events stream
+    pub fn name(&self) -> &'static str {
+        match self {
+            PeerEventType::PeerOnline => "peer-online",
+            PeerEventType::PeerOffline => "peer-offline",
+            PeerEventType::PeerRegistered => "peer-registered",
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct PeerEvent {
+    #[serde(rename = "type")]
+    pub event_type: PeerEventType,
+    /// peer id
+    pub id: String,
+    /// date of the event in sqlite format
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PeersBulkAction {
+    Delete,
+    SetGroup,
+    Disable,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct PeersBulkRequest {
+    pub action: PeersBulkAction,
+    pub ids: Vec<String>,
+    /// guid of the group, required by the `set_group` action
+    pub group: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PeersBulkStatus {
+    Ok,
+    NotFound,
+    Refused,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct PeersBulkResult {
+    pub id: String,
+    pub result: PeersBulkStatus,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct PeersBulkResponse {
+    pub msg: String,
+    pub total: u32,
+    pub data: Vec<PeersBulkResult>,
+}
+
+/// What a deletion would remove, returned instead of applying it with `dry_run=true`
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct DryRunResponse {
+    /// always `dry run`
+    pub msg: String,
+    /// the number of records in `data`
+    pub total: u32,
+    /// the ids or the guids of the records that would be deleted
+    pub data: Vec<String>,
+    /// the details the admin audit would record
+    pub details: serde_json::Value,
+}
+
+impl DryRunResponse {
+    pub fn new(data: Vec<String>, details: serde_json::Value) -> Self {
+        DryRunResponse {
+            msg: "dry run".to_string(),
+            total: data.len() as u32,
+            data,
+            details,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct UpdatePeerRequest {
+    /// guid of the user to assign the peer to, null to remove the assignment
+    pub user: Option<String>,
+}
+
+/// Transfer of a peer to a new owner
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct PeerTransferRequest {
+    /// guid of the new owner
+    pub user: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct PeerTransferResponse {
+    pub id: String,
+    /// guid of the previous owner, null if the peer was not assigned
+    pub previous_user: Option<String>,
+    /// guid of the new owner
+    pub user: String,
+    /// guid of the personal address book of the new owner, the peer is in it
+    pub ab: String,
+}
+
+/// Reason why a peer cannot be transferred
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PeerTransferError {
+    PeerNotFound,
+    /// the user does not exist or is disabled
+    UserNotFound,
 }
 
 #[derive(Serialize, Deserialize, Clone, JsonSchema)]
@@ -716,6 +1992,9 @@ pub struct Group {
     pub accessed_from: Vec<String>,
     pub note: Option<String>,
     pub info: GroupInfo,
+    /// guid of the strategy applied to the members of the group, null for none
+    #[serde(default)]
+    pub strategy: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, JsonSchema)]
@@ -724,6 +2003,13 @@ pub struct AbSharedAddRequest {
     pub note: Option<String>,
 }
 
+/// Address book rule: read only access
+pub const AB_RULE_READ: u32 = 1;
+/// Address book rule: read and write access
+pub const AB_RULE_WRITE: u32 = 2;
+/// Address book rule: full control
+pub const AB_RULE_FULL: u32 = 3;
+
 #[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct AbRule {
     pub guid: String,
@@ -762,12 +2048,65 @@ pub struct CpuCount {
     pub total: u32,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct OsCount {
+    pub os: String,
+    pub total: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, JsonSchema)]
+pub struct StatisticsResponse {
+    pub users_total: u32,
+    /// users who logged in during the last 30 days
+    pub users_active: u32,
+    pub devices_total: u32,
+    /// devices which sent a heartbeat during the last minute
+    pub devices_online: u32,
+    pub devices_by_os: Vec<OsCount>,
+    /// logins since midnight (UTC)
+    pub logins_today: u32,
+    pub address_books_personal: u32,
+    pub address_books_shared: u32,
+    /// bytes transferred by the file transfers of the last 30 days, days without transfer are omitted
+    pub file_bytes_by_day: Vec<DailyBytes>,
+}
+
+/// Peers of an os family, online or not
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+pub struct OsSummary {
+    pub os: String,
+    pub total: u32,
+    pub online: u32,
+    pub offline: u32,
+}
+
+/// Peers grouped by online status
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, JsonSchema)]
+pub struct PeersSummary {
+    pub total: u32,
+    /// peers which sent a heartbeat during the last minute
+    pub online: u32,
+    pub offline: u32,
+    /// the os family reported by the sysinfo, `unknown` for the peers which never sent it
+    pub by_os: Vec<OsSummary>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct DailyBytes {
+    /// day (UTC) as YYYY-MM-DD
+    pub day: String,
+    pub bytes: i64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
 pub struct AddGoupRequest {
     pub name: String,
     pub note: String,
     pub allowed_outgoings: Vec<String>,
     pub allowed_incomings: Vec<String>,
+    /// guid of the strategy applied to the members of the group
+    #[serde(default)]
+    pub strategy: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
@@ -777,6 +2116,9 @@ pub struct UpdateGoupRequest {
     pub note: String,
     pub allowed_outgoings: Vec<String>,
     pub allowed_incomings: Vec<String>,
+    /// guid of the strategy applied to the members of the group, null keeps the current one
+    #[serde(default)]
+    pub strategy: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, Default)]
@@ -784,4 +2126,16 @@ pub struct AbSharedNameRequest {
     pub name: Option<String>,
     pub note: Option<String>,
     pub guid: String,
-}
\ No newline at end of file
+}
+/// Branding of the web console and of the API documentation
+#[derive(Debug, Serialize, Deserialize, Clone, JsonSchema)]
+pub struct BrandingResponse {
+    /// product name shown in the titles
+    pub product_name: String,
+    /// logo as a data url
+    pub logo: String,
+    /// CSS color of the highlighted elements
+    pub accent_color: String,
+    /// HTML of the footer
+    pub footer_html: String,
+}
@@ -0,0 +1,56 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use serde_json::Value;
+
+/// Largest UI settings blob of a user, and of the defaults
+pub const UI_SETTINGS_MAX_SIZE: usize = 4096;
+
+/// UI settings seen by a user of the console
+///
+/// The settings are opaque to the server, when both are objects the keys of the user
+/// replace the keys of the defaults, the other keys of the defaults are kept.
+/// Otherwise the settings of the user replace the defaults as a whole.
+pub fn merge_ui_settings(defaults: Option<Value>, user: Option<Value>) -> Value {
+    match (defaults, user) {
+        (Some(Value::Object(mut defaults)), Some(Value::Object(user))) => {
+            defaults.extend(user);
+            Value::Object(defaults)
+        }
+        (_, Some(user)) => user,
+        (Some(defaults), None) => defaults,
+        (None, None) => Value::Object(Default::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_merge_ui_settings() {
+        assert_eq!(merge_ui_settings(None, None), json!({}));
+        let defaults = json!({ "theme": "dark", "page_size": 20, "columns": ["id", "alias"] });
+        assert_eq!(merge_ui_settings(Some(defaults.clone()), None), defaults);
+        assert_eq!(
+            merge_ui_settings(Some(defaults.clone()), Some(json!({ "theme": "light", "columns": ["id"] }))),
+            json!({ "theme": "light", "page_size": 20, "columns": ["id"] })
+        );
+        // a user value which is not an object replaces the defaults
+        assert_eq!(merge_ui_settings(Some(defaults), Some(json!("compact"))), json!("compact"));
+        assert_eq!(merge_ui_settings(Some(json!(null)), Some(json!({ "theme": "light" }))), json!({ "theme": "light" }));
+    }
+}
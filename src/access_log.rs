@@ -0,0 +1,179 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::env;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Data, Request, Response};
+use state::RequestUserId;
+use utils::access_log::{
+    access_log_timestamp, is_excluded_path, parse_excluded_paths, AccessLogEntry, AccessLogFormat, AccessLogLevel,
+};
+use utils::client_ip::ClientIp;
+use utils::guid_into_uuid;
+use uuid::Uuid;
+
+/// Start and id of a request, cached in the request
+struct RequestStart {
+    at: Instant,
+    id: String,
+}
+
+impl RequestStart {
+    fn new() -> Self {
+        RequestStart {
+            at: Instant::now(),
+            id: Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+/// Access log writing one line per request
+///
+/// It is configured by the `ACCESS_LOG` environment variable: `stdout`, or the path of a file
+/// the lines are appended to. It is off when the variable is not set, is empty or is `off`.
+/// `ACCESS_LOG_FORMAT` chooses between `json` (default) and `text` lines, `ACCESS_LOG_LEVEL` the minimum level
+/// of the logged requests, `info` (default) for all of them, `warn` for the errors or `error` for the server errors,
+/// `ACCESS_LOG_EXCLUDE` is a comma separated list of paths not logged, with the paths below them.
+/// The id of each request is sent back in the `X-Request-Id` header of the response.
+pub struct AccessLog {
+    output: Option<Mutex<Box<dyn Write + Send>>>,
+    format: AccessLogFormat,
+    level: AccessLogLevel,
+    excluded_paths: Vec<String>,
+}
+
+impl AccessLog {
+    pub fn from_env() -> Self {
+        let mut access_log = Self::new(env::var("ACCESS_LOG").ok().as_deref());
+        if let Ok(format) = env::var("ACCESS_LOG_FORMAT") {
+            match AccessLogFormat::parse(&format) {
+                Some(format) => access_log = access_log.with_format(format),
+                None => log::warn!("ACCESS_LOG_FORMAT: ignoring invalid format {}", format),
+            }
+        }
+        if let Ok(level) = env::var("ACCESS_LOG_LEVEL") {
+            match AccessLogLevel::parse(&level) {
+                Some(level) => access_log = access_log.with_level(level),
+                None => log::warn!("ACCESS_LOG_LEVEL: ignoring invalid level {}", level),
+            }
+        }
+        let excluded_paths = parse_excluded_paths(&env::var("ACCESS_LOG_EXCLUDE").unwrap_or_default());
+        access_log.with_excluded_paths(excluded_paths)
+    }
+
+    pub fn new(target: Option<&str>) -> Self {
+        let output: Option<Box<dyn Write + Send>> = match target.map(str::trim) {
+            None | Some("") | Some("off") => None,
+            Some("stdout") => Some(Box::new(std::io::stdout())),
+            Some(path) => match OpenOptions::new().create(true).append(true).open(path) {
+                Ok(file) => Some(Box::new(file)),
+                Err(e) => {
+                    log::error!("access log: cannot open {}: {}", path, e);
+                    None
+                }
+            },
+        };
+        AccessLog {
+            output: output.map(Mutex::new),
+            format: AccessLogFormat::default(),
+            level: AccessLogLevel::Info,
+            excluded_paths: Vec::new(),
+        }
+    }
+
+    pub fn with_format(mut self, format: AccessLogFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Only log the requests of `level` or above
+    pub fn with_level(mut self, level: AccessLogLevel) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Do not log the requests to these paths or below them, normalized by `parse_excluded_paths`
+    pub fn with_excluded_paths(mut self, excluded_paths: Vec<String>) -> Self {
+        self.excluded_paths = excluded_paths;
+        self
+    }
+
+    fn write(&self, entry: &AccessLogEntry) {
+        let Some(output) = &self.output else {
+            return;
+        };
+        let line = match self.format {
+            AccessLogFormat::Json => match serde_json::to_string(entry) {
+                Ok(line) => line,
+                Err(_) => return,
+            },
+            AccessLogFormat::Text => entry.to_text(),
+        };
+        let mut output = output.lock().unwrap_or_else(|e| e.into_inner());
+        if let Err(e) = writeln!(output, "{}", line).and_then(|_| output.flush()) {
+            log::error!("access log: {}", e);
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for AccessLog {
+    fn info(&self) -> Info {
+        Info {
+            name: "Access log",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        if self.output.is_none() {
+            return;
+        }
+        request.local_cache(RequestStart::new);
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if self.output.is_none() {
+            return;
+        }
+        let start = request.local_cache(RequestStart::new);
+        response.set_header(Header::new("X-Request-Id", start.id.clone()));
+        let status = response.status().code;
+        let level = AccessLogLevel::for_status(status);
+        let path = request.uri().path().to_string();
+        if level < self.level || is_excluded_path(&path, &self.excluded_paths) {
+            return;
+        }
+        let client_ip = request.guard::<ClientIp>().await.succeeded();
+        let user_id = request.local_cache(RequestUserId::default).0.clone();
+        self.write(&AccessLogEntry {
+            timestamp: access_log_timestamp(),
+            level,
+            method: request.method().as_str().to_string(),
+            path,
+            status,
+            latency_ms: start.at.elapsed().as_secs_f64() * 1000.0,
+            client_ip: client_ip.map(|ClientIp(ip)| ip.to_string()),
+            user_id: user_id.and_then(guid_into_uuid),
+            request_id: start.id.clone(),
+        });
+    }
+}
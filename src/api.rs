@@ -13,17 +13,36 @@
 //
 // You should have received a copy of the Affero General Public License
 // along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::io::Write;
+use std::marker::PhantomData;
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rocket::futures::Stream;
+use rocket::http::ContentType;
+use rocket::response::stream::TextStream;
+use rocket::request::{FromRequest, Outcome};
 use rocket::{http::Status, response::Responder, Request, Response};
 use rocket_okapi::gen::OpenApiGenerator;
 use rocket_okapi::okapi::openapi3::Responses;
 use rocket_okapi::okapi::{schemars, Map};
+use rocket_okapi::request::{OpenApiFromRequest, RequestHeaderInput};
 use rocket_okapi::response::OpenApiResponderInner;
 use rocket_okapi::{JsonSchema, OpenApiError};
+use rocket::serde::json::Json;
+use rocket::tokio::fs::File;
+use rocket::tokio::io::AsyncReadExt;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use utils::login_reply::{LegacyLoginReply, LoginReplyShape};
+use utils::{ClientUpgradeResponse, DryRunResponse, HeartbeatResponse, LoginReply, PeerAvatar, UserConflictResponse, UserField};
 
 #[derive(Debug, JsonSchema)]
 pub enum ActionResponse {
     Empty,
     Error(String),
+    /// what the action would change, nothing was changed
+    DryRun(DryRunResponse),
 }
 
 impl<'r> Responder<'r, 'static> for ActionResponse {
@@ -38,6 +57,14 @@ impl<'r> Responder<'r, 'static> for ActionResponse {
                     .status(Status::Ok)
                     .ok()
             }
+            ActionResponse::DryRun(dry_run) => {
+                let body = rocket::serde::json::json!(dry_run).to_string();
+                Response::build()
+                    .header(rocket::http::ContentType::JSON)
+                    .sized_body(body.len(), std::io::Cursor::new(body))
+                    .status(Status::Ok)
+                    .ok()
+            }
         }
     }
 }
@@ -63,7 +90,8 @@ impl OpenApiResponderInner for ActionResponse {
             # [200 OK](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/200)  
             This response is given when the request is successful.  
             The body is empty if there is no error,  
-            The body contains a json object with the error {"error":"Error message"}   
+            The body contains a json object with the error {"error":"Error message"},  
+            with `dry_run=true` the body lists what the action would change {"msg":"dry run","total":1,"data":["..."],"details":{}}  
             "#.to_string(),
             ..Default::default()
         }));
@@ -72,4 +100,448 @@ impl OpenApiResponderInner for ActionResponse {
             ..Default::default()
         })
     }
-}
\ No newline at end of file
+}
+
+/// A file download streamed chunk by chunk
+pub struct DownloadStream<S> {
+    pub content_type: ContentType,
+    pub filename: String,
+    pub stream: S,
+}
+
+impl<'r, S: Stream<Item = String> + Send + 'r> Responder<'r, 'r> for DownloadStream<S> {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'r> {
+        let mut response = TextStream(self.stream).respond_to(request)?;
+        response.set_header(self.content_type);
+        response.set_raw_header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", self.filename),
+        );
+        Ok(response)
+    }
+}
+
+impl<S> OpenApiResponderInner for DownloadStream<S> {
+    fn responses(_generator: &mut OpenApiGenerator) -> Result<Responses, OpenApiError> {
+        use rocket_okapi::okapi::openapi3::{RefOr, Response as OpenApiReponse};
+
+        let mut responses = Map::new();
+        responses.insert(
+            "200".to_string(),
+            RefOr::Object(OpenApiReponse {
+                description: "\
+                # [200 OK](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/200)\n\
+                The body is the file to download, its name is given by the Content-Disposition header. \
+                ".to_string(),
+                ..Default::default()
+            }),
+        );
+        Ok(Responses {
+            responses,
+            ..Default::default()
+        })
+    }
+}
+
+/// A release file, or the byte range of it asked with a `Range` header, for the resumable downloads
+pub enum ReleaseFile {
+    Full {
+        content_type: ContentType,
+        file: File,
+        size: u64,
+    },
+    /// the file is positioned at `first`
+    Partial {
+        content_type: ContentType,
+        file: File,
+        size: u64,
+        first: u64,
+        last: u64,
+    },
+    /// the range asked is beyond the end of the file
+    Unsatisfiable { size: u64 },
+}
+
+impl<'r> Responder<'r, 'static> for ReleaseFile {
+    fn respond_to(self, _: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let mut response = Response::build();
+        response.raw_header("Accept-Ranges", "bytes");
+        match self {
+            ReleaseFile::Full {
+                content_type,
+                file,
+                size,
+            } => response.header(content_type).sized_body(size as usize, file),
+            ReleaseFile::Partial {
+                content_type,
+                file,
+                size,
+                first,
+                last,
+            } => {
+                let length = last - first + 1;
+                response
+                    .status(Status::PartialContent)
+                    .header(content_type)
+                    .raw_header("Content-Range", format!("bytes {}-{}/{}", first, last, size))
+                    .raw_header("Content-Length", length.to_string())
+                    .streamed_body(file.take(length))
+            }
+            ReleaseFile::Unsatisfiable { size } => response
+                .status(Status::RangeNotSatisfiable)
+                .raw_header("Content-Range", format!("bytes */{}", size)),
+        };
+        response.ok()
+    }
+}
+
+impl OpenApiResponderInner for ReleaseFile {
+    fn responses(_generator: &mut OpenApiGenerator) -> Result<Responses, OpenApiError> {
+        use rocket_okapi::okapi::openapi3::{RefOr, Response as OpenApiReponse};
+
+        let mut responses = Map::new();
+        responses.insert(
+            "200".to_string(),
+            RefOr::Object(OpenApiReponse {
+                description: "\
+                # [200 OK](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/200)\n\
+                The body is the release file. \
+                ".to_string(),
+                ..Default::default()
+            }),
+        );
+        responses.insert(
+            "206".to_string(),
+            RefOr::Object(OpenApiReponse {
+                description: "\
+                # [206 Partial Content](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/206)\n\
+                The body is the byte range of the release file asked with the Range header. \
+                ".to_string(),
+                ..Default::default()
+            }),
+        );
+        responses.insert(
+            "416".to_string(),
+            RefOr::Object(OpenApiReponse {
+                description: "\
+                # [416 Range Not Satisfiable](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/416)\n\
+                This response is given when the range asked is beyond the end of the file. \
+                ".to_string(),
+                ..Default::default()
+            }),
+        );
+        Ok(Responses {
+            responses,
+            ..Default::default()
+        })
+    }
+}
+
+/// Cache-Control of the images of the peers, they are revalidated with their ETag
+const PEER_AVATAR_CACHE_CONTROL: &str = "private, max-age=300";
+
+/// The image of a peer, answered with `304 Not Modified` when the `If-None-Match` header matches its ETag
+pub struct PeerAvatarFile(pub PeerAvatar);
+
+impl<'r> Responder<'r, 'static> for PeerAvatarFile {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let avatar = self.0;
+        let etag = format!("\"{}\"", hex::encode(&Sha256::digest(&avatar.data)[..16]));
+        let mut response = Response::build();
+        response
+            .raw_header("Cache-Control", PEER_AVATAR_CACHE_CONTROL)
+            .raw_header("ETag", etag.clone());
+        let not_modified = request
+            .headers()
+            .get_one("If-None-Match")
+            .is_some_and(|if_none_match| crate::webconsole::etag_matches(if_none_match, &etag));
+        if not_modified {
+            return response.status(Status::NotModified).ok();
+        }
+        let content_type = ContentType::parse_flexible(&avatar.content_type).unwrap_or(ContentType::Binary);
+        response
+            .header(content_type)
+            .sized_body(avatar.data.len(), std::io::Cursor::new(avatar.data))
+            .ok()
+    }
+}
+
+impl OpenApiResponderInner for PeerAvatarFile {
+    fn responses(_generator: &mut OpenApiGenerator) -> Result<Responses, OpenApiError> {
+        use rocket_okapi::okapi::openapi3::{RefOr, Response as OpenApiReponse};
+
+        let mut responses = Map::new();
+        responses.insert(
+            "200".to_string(),
+            RefOr::Object(OpenApiReponse {
+                description: "\
+                # [200 OK](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/200)\n\
+                The body is the PNG or WebP image of the peer. \
+                ".to_string(),
+                ..Default::default()
+            }),
+        );
+        responses.insert(
+            "304".to_string(),
+            RefOr::Object(OpenApiReponse {
+                description: "\
+                # [304 Not Modified](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/304)\n\
+                This response is given when the If-None-Match header matches the ETag of the image. \
+                ".to_string(),
+                ..Default::default()
+            }),
+        );
+        Ok(Responses {
+            responses,
+            ..Default::default()
+        })
+    }
+}
+
+/// Answers smaller than this are not worth compressing
+pub const GZIP_MIN_SIZE: usize = 1024;
+
+/// Check if an `Accept-Encoding` header value allows a gzip answer
+pub fn accepts_gzip(accept_encoding: &str) -> bool {
+    let mut any = false;
+    for coding in accept_encoding.split(',') {
+        let mut parts = coding.split(';');
+        let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let quality = parts
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .next()
+            .and_then(|quality| quality.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        match name.as_str() {
+            "gzip" | "x-gzip" => return quality > 0.0,
+            "*" => any = quality > 0.0,
+            _ => {}
+        }
+    }
+    any
+}
+
+/// Whether the client accepts a gzip answer, from its `Accept-Encoding` headers
+pub struct AcceptsGzip(pub bool);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for AcceptsGzip {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(AcceptsGzip(request.headers().get("Accept-Encoding").any(accepts_gzip)))
+    }
+}
+
+impl OpenApiFromRequest<'_> for AcceptsGzip {
+    fn from_request_input(_: &mut OpenApiGenerator, _: String, _: bool) -> Result<RequestHeaderInput, OpenApiError> {
+        Ok(RequestHeaderInput::None)
+    }
+}
+
+/// A JSON answer compressed with gzip when it is large and the client accepts it
+pub struct GzipJson<T> {
+    /// None when the answer cannot be serialized
+    body: Option<Vec<u8>>,
+    gzipped: bool,
+    answer: PhantomData<fn() -> T>,
+}
+
+impl<T: Serialize + Send + 'static> GzipJson<T> {
+    /// Serialize the answer and compress it, on a blocking thread since a large address book takes a while
+    pub async fn encode(answer: T, accepts_gzip: AcceptsGzip) -> Self {
+        let encoded = tokio::task::spawn_blocking(move || {
+            let body = serde_json::to_vec(&answer).map_err(|e| log::error!("GzipJson error: {:?}", e)).ok()?;
+            if !accepts_gzip.0 || body.len() < GZIP_MIN_SIZE {
+                return Some((body, false));
+            }
+            let mut encoder = GzEncoder::new(Vec::with_capacity(body.len() / 4), Compression::default());
+            let compressed = encoder
+                .write_all(&body)
+                .and_then(|_| encoder.finish())
+                .map_err(|e| log::error!("GzipJson error: {:?}", e))
+                .ok()?;
+            log::debug!(
+                "gzip {} -> {} bytes ({:.1}%)",
+                body.len(),
+                compressed.len(),
+                compressed.len() as f64 * 100.0 / body.len() as f64
+            );
+            Some((compressed, true))
+        })
+        .await
+        .ok()
+        .flatten();
+        GzipJson {
+            gzipped: encoded.as_ref().is_some_and(|(_, gzipped)| *gzipped),
+            body: encoded.map(|(body, _)| body),
+            answer: PhantomData,
+        }
+    }
+}
+
+impl<'r, T> Responder<'r, 'static> for GzipJson<T> {
+    fn respond_to(self, _: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let body = self.body.ok_or(Status::InternalServerError)?;
+        let mut response = Response::build();
+        response
+            .header(ContentType::JSON)
+            .raw_header("Vary", "Accept-Encoding");
+        if self.gzipped {
+            response.raw_header("Content-Encoding", "gzip");
+        }
+        response.sized_body(body.len(), std::io::Cursor::new(body)).ok()
+    }
+}
+
+impl<T: JsonSchema + Serialize + Send> OpenApiResponderInner for GzipJson<T> {
+    fn responses(generator: &mut OpenApiGenerator) -> Result<Responses, OpenApiError> {
+        Json::<T>::responses(generator)
+    }
+}
+
+/// The heartbeat answer
+/// It is the bare status, e.g. `OK`, as the clients always received, unless there are directives
+/// or the client asks for JSON with `Accept: application/json`
+pub struct HeartbeatReply(pub HeartbeatResponse);
+
+impl<'r> Responder<'r, 'static> for HeartbeatReply {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let accepts_json = request
+            .accept()
+            .is_some_and(|accept| accept.media_types().any(|media_type| media_type.is_json()));
+        let mut response = if accepts_json || !self.0.directives.is_empty() {
+            Json(self.0).respond_to(request)?
+        } else {
+            self.0.status.respond_to(request)?
+        };
+        response.set_raw_header("Vary", "Accept");
+        Ok(response)
+    }
+}
+
+impl OpenApiResponderInner for HeartbeatReply {
+    fn responses(generator: &mut OpenApiGenerator) -> Result<Responses, OpenApiError> {
+        Json::<HeartbeatResponse>::responses(generator)
+    }
+}
+
+/// The login answer
+/// Its shape depends on the version of the client in the `X-Client-Version` header, see `LoginReplyShape`:
+/// the clients older than 1.1.9 get the token in `token`, the others in `access_token`
+pub struct LoginResponse(pub LoginReply);
+
+impl<'r> Responder<'r, 'static> for LoginResponse {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let shape = LoginReplyShape::from_client_version(request.headers().get_one("X-Client-Version"));
+        let mut response = match shape {
+            LoginReplyShape::Current => Json(self.0).respond_to(request)?,
+            LoginReplyShape::Legacy => Json(LegacyLoginReply::from(self.0)).respond_to(request)?,
+        };
+        response.set_raw_header("Vary", "X-Client-Version");
+        Ok(response)
+    }
+}
+
+impl OpenApiResponderInner for LoginResponse {
+    fn responses(generator: &mut OpenApiGenerator) -> Result<Responses, OpenApiError> {
+        Json::<LoginReply>::responses(generator)
+    }
+}
+
+/// The errors of the user creation and update
+pub enum UserError {
+    Unauthorized,
+    /// the name or the email is already used by another user
+    Conflict(UserConflictResponse),
+}
+
+impl UserError {
+    pub fn conflict(field: UserField) -> Self {
+        let error = match field {
+            UserField::Name => "A user with this name already exists",
+            UserField::Email => "A user with this email already exists",
+        };
+        UserError::Conflict(UserConflictResponse {
+            error: error.to_string(),
+            field,
+        })
+    }
+}
+
+impl<'r> Responder<'r, 'static> for UserError {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        match self {
+            UserError::Unauthorized => Response::build().status(Status::Unauthorized).ok(),
+            UserError::Conflict(conflict) => {
+                let mut response = Json(conflict).respond_to(request)?;
+                response.set_status(Status::Conflict);
+                Ok(response)
+            }
+        }
+    }
+}
+
+impl OpenApiResponderInner for UserError {
+    fn responses(generator: &mut OpenApiGenerator) -> Result<Responses, OpenApiError> {
+        use rocket_okapi::okapi::openapi3::{RefOr, Response as OpenApiReponse};
+
+        let mut responses = Json::<UserConflictResponse>::responses(generator)?;
+        if let Some(conflict) = responses.responses.remove("200") {
+            responses.responses.insert("409".to_string(), conflict);
+        }
+        responses.responses.insert(
+            "401".to_string(),
+            RefOr::Object(OpenApiReponse {
+                description: "\
+                # [401 Unauthorized](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/401)\n\
+                This response is given when the user is not allowed to do the change or when it failed. \
+                ".to_string(),
+                ..Default::default()
+            }),
+        );
+        Ok(responses)
+    }
+}
+
+/// The errors of the login
+pub enum LoginError {
+    Unauthorized,
+    /// the client is older than the minimum client version
+    UpgradeRequired(ClientUpgradeResponse),
+}
+
+impl<'r> Responder<'r, 'static> for LoginError {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        match self {
+            LoginError::Unauthorized => Response::build().status(Status::Unauthorized).ok(),
+            LoginError::UpgradeRequired(upgrade) => {
+                let mut response = Json(upgrade).respond_to(request)?;
+                response.set_status(Status::UpgradeRequired);
+                Ok(response)
+            }
+        }
+    }
+}
+
+impl OpenApiResponderInner for LoginError {
+    fn responses(generator: &mut OpenApiGenerator) -> Result<Responses, OpenApiError> {
+        use rocket_okapi::okapi::openapi3::{RefOr, Response as OpenApiReponse};
+
+        let mut responses = Json::<ClientUpgradeResponse>::responses(generator)?;
+        if let Some(upgrade) = responses.responses.remove("200") {
+            responses.responses.insert("426".to_string(), upgrade);
+        }
+        responses.responses.insert(
+            "401".to_string(),
+            RefOr::Object(OpenApiReponse {
+                description: "\
+                # [401 Unauthorized](https://developer.mozilla.org/en-US/docs/Web/HTTP/Status/401)\n\
+                This response is given when the name or the password is not valid. \
+                ".to_string(),
+                ..Default::default()
+            }),
+        );
+        Ok(responses)
+    }
+}
@@ -0,0 +1,259 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::figment::Figment;
+use rocket::tokio::io::AsyncWriteExt;
+use rocket::tokio::net::{TcpStream, UdpSocket};
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::tokio::time::timeout;
+use rocket::{Orbit, Rocket};
+use state::ApiState;
+use utils::audit::ForwardedAudit;
+
+/// Timeout of the deliveries when `audit_forward_timeout` is not set
+const AUDIT_FORWARD_DEFAULT_TIMEOUT_SECS: u64 = 5;
+
+/// Port of the syslog server when the url has none
+const SYSLOG_DEFAULT_PORT: u16 = 514;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SyslogTransport {
+    Udp,
+    Tcp,
+}
+
+/// Syslog server receiving the audit records, e.g. `udp://siem.example.org:514` or `tcp://siem.example.org:601`
+#[derive(Debug, Clone, PartialEq)]
+struct SyslogTarget {
+    transport: SyslogTransport,
+    address: String,
+}
+
+impl SyslogTarget {
+    fn parse(url: &str) -> Option<Self> {
+        let url = url::Url::parse(url).ok()?;
+        let transport = match url.scheme() {
+            "udp" => SyslogTransport::Udp,
+            "tcp" => SyslogTransport::Tcp,
+            _ => return None,
+        };
+        let address = format!("{}:{}", url.host_str()?, url.port().unwrap_or(SYSLOG_DEFAULT_PORT));
+        Some(SyslogTarget { transport, address })
+    }
+}
+
+/// Counters of the forwarder, logged when the server stops
+#[derive(Default)]
+struct ForwarderStats {
+    forwarded: AtomicU64,
+    failed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+/// Forwarder sending every persisted audit record (connections, files, alarms, logins and admin operations)
+/// to a syslog server and/or a webhook
+///
+/// It is configured in the Rocket configuration (`ROCKET_<NAME>` environment variable or `Rocket.toml`):
+/// `audit_forward_syslog` is the url of the syslog server (`udp://` or `tcp://`, RFC 5424 messages),
+/// `audit_forward_webhook` the url receiving a POST with the JSON of each record,
+/// `audit_forward_timeout` the timeout in seconds of each delivery.
+///
+/// The records are queued in a small bounded queue, the oldest ones are dropped when the sinks
+/// are too slow so that they never slow down the API.
+/// The forwarder starts with the server and stops with it.
+pub struct AuditForwarder {
+    syslog: Option<SyslogTarget>,
+    webhook: Option<String>,
+    timeout: Duration,
+    stats: Arc<ForwarderStats>,
+}
+
+impl AuditForwarder {
+    pub fn from_figment(figment: &Figment) -> Self {
+        let value = |key: &str| {
+            figment
+                .extract_inner::<String>(key)
+                .ok()
+                .filter(|value| !value.is_empty())
+        };
+        let syslog = value("audit_forward_syslog").and_then(|url| {
+            let target = SyslogTarget::parse(&url);
+            if target.is_none() {
+                log::error!("audit forwarder: invalid syslog url {}", url);
+            }
+            target
+        });
+        let timeout = figment
+            .extract_inner::<u64>("audit_forward_timeout")
+            .unwrap_or(AUDIT_FORWARD_DEFAULT_TIMEOUT_SECS);
+        AuditForwarder {
+            syslog,
+            webhook: value("audit_forward_webhook"),
+            timeout: Duration::from_secs(timeout),
+            stats: Default::default(),
+        }
+    }
+}
+
+/// Connections to the sinks, owned by the forwarding task
+struct Sinks {
+    syslog: Option<SyslogTarget>,
+    webhook: Option<String>,
+    timeout: Duration,
+    hostname: String,
+    client: reqwest::Client,
+    udp: Option<UdpSocket>,
+    tcp: Option<TcpStream>,
+}
+
+impl Sinks {
+    async fn send_syslog(&mut self, target: &SyslogTarget, message: &str) -> Result<(), String> {
+        match target.transport {
+            SyslogTransport::Udp => {
+                if self.udp.is_none() {
+                    let socket = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| e.to_string())?;
+                    self.udp = Some(socket);
+                }
+                let socket = self.udp.as_ref().unwrap();
+                socket
+                    .send_to(message.as_bytes(), target.address.as_str())
+                    .await
+                    .map_err(|e| e.to_string())?;
+            }
+            SyslogTransport::Tcp => {
+                if self.tcp.is_none() {
+                    let stream = timeout(self.timeout, TcpStream::connect(target.address.as_str()))
+                        .await
+                        .map_err(|e| e.to_string())?
+                        .map_err(|e| e.to_string())?;
+                    self.tcp = Some(stream);
+                }
+                // octet counting framing (RFC 6587)
+                let frame = format!("{} {}", message.len(), message);
+                let stream = self.tcp.as_mut().unwrap();
+                let res = timeout(self.timeout, stream.write_all(frame.as_bytes())).await;
+                if !matches!(res, Ok(Ok(()))) {
+                    // reconnect on the next record
+                    self.tcp = None;
+                    return Err(match res {
+                        Ok(Err(e)) => e.to_string(),
+                        _ => "timeout".to_string(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_webhook(&self, url: &str, record: &ForwardedAudit) -> Result<(), String> {
+        let response = self
+            .client
+            .post(url)
+            .json(record)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("answered {}", response.status()));
+        }
+        Ok(())
+    }
+
+    async fn forward(&mut self, record: &ForwardedAudit, stats: &ForwarderStats) {
+        if let Some(target) = self.syslog.clone() {
+            let message = record.syslog_message(&self.hostname);
+            if let Err(e) = self.send_syslog(&target, &message).await {
+                let failed = stats.failed.fetch_add(1, Ordering::Relaxed) + 1;
+                log::warn!("audit forwarder: syslog {} failed: {} ({} failures)", target.address, e, failed);
+            }
+        }
+        if let Some(url) = self.webhook.clone() {
+            if let Err(e) = self.send_webhook(&url, record).await {
+                let failed = stats.failed.fetch_add(1, Ordering::Relaxed) + 1;
+                log::warn!("audit forwarder: webhook {} failed: {} ({} failures)", url, e, failed);
+            }
+        }
+        stats.forwarded.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for AuditForwarder {
+    fn info(&self) -> Info {
+        Info {
+            name: "Audit forwarder",
+            kind: Kind::Liftoff | Kind::Shutdown,
+        }
+    }
+
+    async fn on_liftoff(&self, rocket: &Rocket<Orbit>) {
+        if self.syslog.is_none() && self.webhook.is_none() {
+            return;
+        }
+        let state = match rocket.state::<ApiState>() {
+            Some(state) => state,
+            None => return,
+        };
+        let mut records = state.subscribe_audit_records();
+        let mut shutdown = rocket.shutdown();
+        let stats = self.stats.clone();
+        let mut sinks = Sinks {
+            syslog: self.syslog.clone(),
+            webhook: self.webhook.clone(),
+            timeout: self.timeout,
+            hostname: std::env::var("HOSTNAME").unwrap_or_default(),
+            client: reqwest::Client::builder()
+                .timeout(self.timeout)
+                .build()
+                .unwrap_or_default(),
+            udp: None,
+            tcp: None,
+        };
+        log::info!("audit forwarder: started");
+        rocket::tokio::spawn(async move {
+            loop {
+                select! {
+                    record = records.recv() => match record {
+                        Ok(record) => sinks.forward(&record, &stats).await,
+                        Err(RecvError::Lagged(skipped)) => {
+                            let dropped = stats.dropped.fetch_add(skipped, Ordering::Relaxed) + skipped;
+                            log::warn!("audit forwarder: sinks too slow, {} records dropped ({} in total)", skipped, dropped);
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = &mut shutdown => break,
+                }
+            }
+        });
+    }
+
+    async fn on_shutdown(&self, _rocket: &Rocket<Orbit>) {
+        if self.syslog.is_none() && self.webhook.is_none() {
+            return;
+        }
+        log::info!(
+            "audit forwarder: stopped, {} records forwarded, {} delivery failures, {} records dropped",
+            self.stats.forwarded.load(Ordering::Relaxed),
+            self.stats.failed.load(Ordering::Relaxed),
+            self.stats.dropped.load(Ordering::Relaxed)
+        );
+    }
+}
@@ -0,0 +1,120 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use rocket::figment::Figment;
+use sha2::Sha256;
+use utils::audit::AlarmAudit;
+
+/// Timeout of the webhook calls when `audit_webhook_timeout` is not set
+const AUDIT_WEBHOOK_DEFAULT_TIMEOUT_SECS: u64 = 5;
+/// Header carrying the signature of the body, `sha256=` followed by the hex HMAC-SHA256 of the body
+pub const AUDIT_WEBHOOK_SIGNATURE_HEADER: &str = "X-Sctgdesk-Signature";
+
+/// Webhook called with every alarm sent by the clients
+///
+/// The url is read from `audit_webhook` in the Rocket configuration
+/// (`ROCKET_AUDIT_WEBHOOK` environment variable or `Rocket.toml`),
+/// the timeout in seconds from `audit_webhook_timeout`
+/// and the secret used to sign the bodies from `audit_webhook_secret`
+pub struct AuditWebhook {
+    url: Option<String>,
+    secret: Option<String>,
+    client: reqwest::Client,
+}
+
+/// Signature of a webhook body with the shared secret
+pub fn webhook_signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+impl AuditWebhook {
+    pub fn from_figment(figment: &Figment) -> Self {
+        let url = figment
+            .extract_inner::<String>("audit_webhook")
+            .ok()
+            .filter(|url| !url.is_empty());
+        let secret = figment
+            .extract_inner::<String>("audit_webhook_secret")
+            .ok()
+            .filter(|secret| !secret.is_empty());
+        let timeout = figment
+            .extract_inner::<u64>("audit_webhook_timeout")
+            .unwrap_or(AUDIT_WEBHOOK_DEFAULT_TIMEOUT_SECS);
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(timeout))
+            .build()
+            .unwrap_or_default();
+        AuditWebhook { url, secret, client }
+    }
+
+    /// Build the POST of a body, signed when a secret is configured
+    fn post(&self, url: &str, body: &serde_json::Value) -> reqwest::RequestBuilder {
+        let body = body.to_string();
+        let mut request = self
+            .client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if let Some(secret) = &self.secret {
+            request = request.header(AUDIT_WEBHOOK_SIGNATURE_HEADER, webhook_signature(secret, body.as_bytes()));
+        }
+        request.body(body)
+    }
+
+    /// POST a ping to the webhook, to check its configuration without raising an alarm
+    ///
+    /// # Returns
+    /// A description of the answer of the webhook, or the reason of the failure
+    pub async fn ping(&self) -> Result<String, String> {
+        let url = self.url.as_deref().ok_or("audit_webhook is not set")?;
+        let body = serde_json::json!({ "event": "ping" });
+        let signed = if self.secret.is_some() {
+            "signed"
+        } else {
+            "not signed, audit_webhook_secret is not set"
+        };
+        match self.post(url, &body).send().await {
+            Ok(response) if response.status().is_success() => {
+                Ok(format!("{} answered {} to the ping ({})", url, response.status(), signed))
+            }
+            Ok(response) => Err(format!("{} answered {} to the ping ({})", url, response.status(), signed)),
+            Err(e) => Err(format!("{} failed: {}", url, e)),
+        }
+    }
+
+    /// POST the alarm to the webhook
+    /// The call runs on its own task, a slow or failing webhook never delays the client
+    pub fn notify(&self, alarm: &AlarmAudit) {
+        let url = match &self.url {
+            Some(url) => url.clone(),
+            None => return,
+        };
+        let body = serde_json::json!({ "event": "alarm", "alarm": alarm });
+        let request = self.post(url.as_str(), &body);
+        tokio::spawn(async move {
+            match request.send().await {
+                Ok(response) if !response.status().is_success() => {
+                    log::warn!("audit webhook {} answered {}", url, response.status());
+                }
+                Ok(_) => {}
+                Err(e) => log::warn!("audit webhook {} failed: {}", url, e),
+            }
+        });
+    }
+}
@@ -0,0 +1,67 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use rocket::figment::Figment;
+use rocket::Request;
+use utils::get_host::normalize_base_path;
+
+/// Path the server is mounted at, e.g. `/sctgdesk` to serve it at `https://example.com/sctgdesk/`
+///
+/// It is read from `base_path` in the Rocket configuration (`ROCKET_BASE_PATH=/sctgdesk`).
+/// It prefixes the API, the web console and the API documentation, the empty default mounts them at the root
+#[derive(Debug, Clone, Default)]
+pub struct BasePath(String);
+
+impl BasePath {
+    pub fn from_figment(figment: &Figment) -> Self {
+        Self::new(&figment.extract_inner::<String>("base_path").unwrap_or_default())
+    }
+
+    pub fn new(base_path: &str) -> Self {
+        BasePath(normalize_base_path(base_path))
+    }
+
+    /// The base path, empty or starting with `/` without trailing `/`
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Where to mount the routes of `path`, e.g. `/api/doc/` gives `/sctgdesk/api/doc/`
+    pub fn mount_point(&self, path: &str) -> String {
+        match (self.0.as_str(), path) {
+            ("", path) => path.to_string(),
+            (base_path, "/") => base_path.to_string(),
+            (base_path, path) => format!("{}{}", base_path, path),
+        }
+    }
+
+    /// The path of a request relative to the base path, None when it is outside of it
+    pub fn strip<'a>(base_path: &str, path: &'a str) -> Option<&'a str> {
+        match path.strip_prefix(base_path)? {
+            "" => Some("/"),
+            path if path.starts_with('/') => Some(path),
+            _ => None,
+        }
+    }
+
+    /// The base path of the server handling the request, empty when it is not managed
+    pub fn of_request(request: &Request<'_>) -> String {
+        request
+            .rocket()
+            .state::<BasePath>()
+            .map(|base_path| base_path.0.clone())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,157 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::path::Path;
+
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use rocket::figment::Figment;
+use utils::{include_png_as_base64, BrandingResponse};
+
+const DEFAULT_PRODUCT_NAME: &str = "SCTGDesk";
+const DEFAULT_ACCENT_COLOR: &str = "orangered";
+const DEFAULT_FOOTER_HTML: &str = "© 2024 SCTG. All rights reserved.";
+
+/// Branding of the web console and of the API documentation
+///
+/// The values are read from the `branding` table of the Rocket configuration
+/// (`Rocket.toml` or `ROCKET_BRANDING={product_name="Acme Desk"}`):
+/// `product_name`, `logo` (path of an image file, data url or base64 PNG), `accent_color` and `footer_html`.
+/// A missing value keeps the SCTGDesk one
+pub struct Branding {
+    /// Product name, None when it is not configured
+    product_name: Option<String>,
+    logo: String,
+    accent_color: String,
+    footer_html: String,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Branding {
+            product_name: None,
+            logo: include_png_as_base64!("../assets/logo.png"),
+            accent_color: DEFAULT_ACCENT_COLOR.to_string(),
+            footer_html: DEFAULT_FOOTER_HTML.to_string(),
+        }
+    }
+}
+
+/// Convert the configured logo to a data url
+///
+/// # Arguments
+/// * `logo` - a data url, the path of an image file or a base64 encoded PNG
+///
+/// # Returns
+/// None when the logo is neither a readable file nor valid base64
+fn logo_data_url(logo: &str) -> Option<String> {
+    if logo.starts_with("data:") {
+        return Some(logo.to_string());
+    }
+    let path = Path::new(logo);
+    if path.is_file() {
+        let content_type = match path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase())
+            .as_deref()
+        {
+            Some("svg") => "image/svg+xml",
+            Some("jpg") | Some("jpeg") => "image/jpeg",
+            Some("gif") => "image/gif",
+            Some("webp") => "image/webp",
+            _ => "image/png",
+        };
+        let content = std::fs::read(path).ok()?;
+        return Some(format!("data:{};base64,{}", content_type, BASE64_STANDARD.encode(content)));
+    }
+    BASE64_STANDARD
+        .decode(logo)
+        .ok()
+        .map(|_| format!("data:image/png;base64,{}", logo))
+}
+
+impl Branding {
+    pub fn from_figment(figment: &Figment) -> Self {
+        let value = |key: &str| {
+            figment
+                .extract_inner::<String>(&format!("branding.{}", key))
+                .ok()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+        };
+        let mut branding = Branding {
+            product_name: value("product_name"),
+            ..Default::default()
+        };
+        if let Some(logo) = value("logo") {
+            match logo_data_url(&logo) {
+                Some(logo) => branding.logo = logo,
+                None => log::warn!("branding logo {} is neither a file nor base64, the default logo is used", logo),
+            }
+        }
+        if let Some(accent_color) = value("accent_color") {
+            branding.accent_color = accent_color;
+        }
+        if let Some(footer_html) = value("footer_html") {
+            branding.footer_html = footer_html;
+        }
+        branding
+    }
+
+    /// The configured product name, None to keep the titles of the built web console
+    pub fn custom_product_name(&self) -> Option<&str> {
+        self.product_name.as_deref()
+    }
+
+    pub fn product_name(&self) -> &str {
+        self.product_name.as_deref().unwrap_or(DEFAULT_PRODUCT_NAME)
+    }
+
+    pub fn logo(&self) -> &str {
+        &self.logo
+    }
+
+    /// The footer slot of the API documentation
+    pub fn rapidoc_footer(&self) -> String {
+        format!(
+            r#"<p slot="footer" style="margin:0; padding:16px 36px; background-color:{}; color:#fff; text-align:center;">{}</p>"#,
+            self.accent_color, self.footer_html
+        )
+    }
+
+    pub fn response(&self) -> BrandingResponse {
+        BrandingResponse {
+            product_name: self.product_name().to_string(),
+            logo: self.logo.clone(),
+            accent_color: self.accent_color.clone(),
+            footer_html: self.footer_html.clone(),
+        }
+    }
+}
+
+/// Replace the `<title>` of an HTML page
+///
+/// # Returns
+/// None when the page has no title
+pub fn replace_html_title(html: &str, title: &str) -> Option<String> {
+    let start = html.find("<title>")? + "<title>".len();
+    let end = start + html[start..].find("</title>")?;
+    let title = title
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;");
+    Some(format!("{}{}{}", &html[..start], title, &html[end..]))
+}
+
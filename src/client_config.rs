@@ -0,0 +1,94 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::collections::BTreeMap;
+
+use rocket::figment::Figment;
+use serde::Serialize;
+
+/// Port of the id server when it is given without one
+const RENDEZVOUS_DEFAULT_PORT: u16 = 21116;
+
+/// Configuration of the RustDesk clients given by `/api/client-config/download`
+///
+/// The values are read from the Rocket configuration (`ROCKET_<NAME>` environment variable or `Rocket.toml`):
+/// `client_id_server`, `client_relay_server`, `client_key` and `client_api_server`.
+/// The id server and the api server default to the host the request was sent to,
+/// the relay server to the id server
+pub struct ClientConfig {
+    id_server: Option<String>,
+    relay_server: Option<String>,
+    key: Option<String>,
+    api_server: Option<String>,
+}
+
+/// Content of the `RustDesk2.toml` file of the clients
+#[derive(Serialize)]
+struct RustDeskConfig {
+    rendezvous_server: String,
+    nat_type: i32,
+    serial: i32,
+    options: BTreeMap<&'static str, String>,
+}
+
+impl ClientConfig {
+    pub fn from_figment(figment: &Figment) -> Self {
+        let value = |key: &str| {
+            figment
+                .extract_inner::<String>(key)
+                .ok()
+                .filter(|value| !value.is_empty())
+        };
+        ClientConfig {
+            id_server: value("client_id_server"),
+            relay_server: value("client_relay_server"),
+            key: value("client_key"),
+            api_server: value("client_api_server"),
+        }
+    }
+
+    /// Build the `RustDesk2.toml` file of a client
+    ///
+    /// # Arguments
+    /// * `host` - url the request was sent to, e.g. `https://desk.example.org:21114`
+    pub fn to_toml(&self, host: &str) -> String {
+        let host_name = url::Url::parse(host)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| host.to_string()))
+            .unwrap_or_default();
+        let id_server = self.id_server.clone().unwrap_or(host_name);
+        let relay_server = self.relay_server.clone().unwrap_or_else(|| id_server.clone());
+        let api_server = self.api_server.clone().unwrap_or_else(|| host.to_string());
+        let rendezvous_server = if id_server.contains(':') {
+            id_server.clone()
+        } else {
+            format!("{}:{}", id_server, RENDEZVOUS_DEFAULT_PORT)
+        };
+        let mut options = BTreeMap::new();
+        options.insert("custom-rendezvous-server", id_server);
+        options.insert("relay-server", relay_server);
+        options.insert("api-server", api_server);
+        if let Some(key) = &self.key {
+            options.insert("key", key.clone());
+        }
+        let config = RustDeskConfig {
+            rendezvous_server,
+            nat_type: 1,
+            serial: 0,
+            options,
+        };
+        toml::to_string(&config).unwrap_or_default()
+    }
+}
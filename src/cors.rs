@@ -0,0 +1,234 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::env;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::figment::Figment;
+use rocket::http::{Header, Method};
+use rocket::{Request, Response};
+
+/// How long the browsers cache a preflight answer when `CORS_MAX_AGE` is not set, in seconds
+pub const CORS_DEFAULT_MAX_AGE: u64 = 600;
+
+/// Methods allowed by the preflight answers when `cors_methods` is not set
+pub const CORS_DEFAULT_METHODS: &str = "POST, GET, PUT, DELETE, OPTIONS";
+
+/// Headers allowed by the preflight answers when `cors_headers` is not set
+pub const CORS_DEFAULT_HEADERS: &str = "*";
+
+/// An allowed origin, e.g. `https://console.example.org` or `https://*.example.org`
+#[derive(Debug, Clone, PartialEq)]
+enum OriginPattern {
+    Exact(String),
+    /// the subdomains of a domain, with the scheme before the `*` and the domain and the port after it
+    Subdomains { scheme: String, domain: String },
+}
+
+impl OriginPattern {
+    fn parse(pattern: &str) -> Option<Self> {
+        let pattern = pattern.trim().trim_end_matches('/').to_lowercase();
+        let (scheme, host) = pattern.split_once("://")?;
+        if scheme.is_empty() || host.is_empty() || host.contains('/') {
+            return None;
+        }
+        match host.strip_prefix("*.") {
+            Some(domain) if !domain.is_empty() && !domain.contains('*') => Some(OriginPattern::Subdomains {
+                scheme: format!("{}://", scheme),
+                domain: format!(".{}", domain),
+            }),
+            Some(_) => None,
+            None if host.contains('*') => None,
+            None => Some(OriginPattern::Exact(pattern)),
+        }
+    }
+
+    fn matches(&self, origin: &str) -> bool {
+        let origin = origin.to_lowercase();
+        match self {
+            OriginPattern::Exact(exact) => origin == *exact,
+            OriginPattern::Subdomains { scheme, domain } => origin
+                .strip_prefix(scheme.as_str())
+                .and_then(|host| host.strip_suffix(domain.as_str()))
+                .is_some_and(|subdomain| {
+                    !subdomain.is_empty()
+                        && !subdomain.starts_with('.')
+                        && subdomain
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                }),
+        }
+    }
+}
+
+/// A list setting of the Rocket configuration, given as a list or as a comma separated string
+fn figment_list(figment: &Figment, key: &str) -> Option<Vec<String>> {
+    let values = figment.extract_inner::<Vec<String>>(key).ok().or_else(|| {
+        figment
+            .extract_inner::<String>(key)
+            .ok()
+            .map(|values| values.split(',').map(str::to_string).collect())
+    })?;
+    let values: Vec<String> = values
+        .iter()
+        .map(|value| value.trim().to_string())
+        .filter(|value| !value.is_empty())
+        .collect();
+    (!values.is_empty()).then_some(values)
+}
+
+/// CORS headers of the responses
+///
+/// It is configured in the Rocket configuration (`ROCKET_<NAME>` environment variable or `Rocket.toml`):
+/// `cors_origins` lists the origins allowed to call the API, exact origins or `https://*.example.org`
+/// for the subdomains of a domain. The `Origin` of the request is sent back only when it is allowed,
+/// every origin is allowed with `*` when the list is not set.
+/// `cors_methods` and `cors_headers` are the methods and the headers allowed by the preflight answers,
+/// which are cached by the browsers for `CORS_MAX_AGE` seconds (600 by default).
+/// `cookie_auth` lets the browsers send the authentication cookie with the requests of the allowed origins,
+/// `Access-Control-Allow-Credentials` is never sent otherwise.
+pub struct CORS {
+    max_age: u64,
+    /// None allows every origin
+    origins: Option<Vec<OriginPattern>>,
+    methods: String,
+    headers: String,
+    cookie_auth: bool,
+}
+
+impl CORS {
+    pub fn from_figment(figment: &Figment) -> Self {
+        let mut cors = Self::new(env::var("CORS_MAX_AGE").ok().as_deref());
+        match figment_list(figment, "cors_origins") {
+            Some(origins) => cors = cors.with_origins(&origins),
+            None => log::warn!("cors_origins is not set, every origin is allowed to call the API"),
+        }
+        if let Some(methods) = figment_list(figment, "cors_methods") {
+            cors = cors.with_methods(&methods);
+        }
+        if let Some(headers) = figment_list(figment, "cors_headers") {
+            cors = cors.with_headers(&headers);
+        }
+        let cookie_auth = figment.extract_inner::<bool>("cookie_auth").unwrap_or(false);
+        if cookie_auth && cors.origins.is_none() {
+            log::warn!("cookie_auth needs cors_origins, the cookies are not sent to the API from the other origins");
+        }
+        cors.with_cookie_auth(cookie_auth)
+    }
+
+    pub fn new(max_age: Option<&str>) -> Self {
+        let max_age = match max_age.map(str::trim) {
+            None | Some("") => CORS_DEFAULT_MAX_AGE,
+            Some(max_age) => max_age.parse::<u64>().unwrap_or_else(|_| {
+                log::error!("CORS_MAX_AGE is not a number of seconds: {}", max_age);
+                CORS_DEFAULT_MAX_AGE
+            }),
+        };
+        CORS {
+            max_age,
+            origins: None,
+            methods: CORS_DEFAULT_METHODS.to_string(),
+            headers: CORS_DEFAULT_HEADERS.to_string(),
+            cookie_auth: false,
+        }
+    }
+
+    /// Only allow these origins, `*` allows every origin and the invalid patterns are ignored
+    pub fn with_origins<S: AsRef<str>>(mut self, origins: &[S]) -> Self {
+        if origins.iter().any(|origin| origin.as_ref().trim() == "*") {
+            self.origins = None;
+            return self;
+        }
+        let patterns = origins
+            .iter()
+            .filter_map(|origin| {
+                let pattern = OriginPattern::parse(origin.as_ref());
+                if pattern.is_none() {
+                    log::error!("cors_origins: ignoring invalid origin {}", origin.as_ref());
+                }
+                pattern
+            })
+            .collect();
+        self.origins = Some(patterns);
+        self
+    }
+
+    pub fn with_methods<S: AsRef<str>>(mut self, methods: &[S]) -> Self {
+        self.methods = methods.iter().map(|method| method.as_ref().trim().to_uppercase()).collect::<Vec<_>>().join(", ");
+        self
+    }
+
+    pub fn with_headers<S: AsRef<str>>(mut self, headers: &[S]) -> Self {
+        self.headers = headers.iter().map(|header| header.as_ref().trim()).collect::<Vec<_>>().join(", ");
+        self
+    }
+
+    pub fn with_cookie_auth(mut self, cookie_auth: bool) -> Self {
+        self.cookie_auth = cookie_auth;
+        self
+    }
+
+    /// How long the browsers cache a preflight answer, in seconds
+    pub fn max_age(&self) -> u64 {
+        self.max_age
+    }
+
+    /// The value of `Access-Control-Allow-Origin` for a request from `origin`, None when it is not allowed
+    fn allowed_origin(&self, origin: Option<&str>) -> Option<String> {
+        let Some(patterns) = &self.origins else {
+            return Some("*".to_string());
+        };
+        let origin = origin?;
+        patterns
+            .iter()
+            .any(|pattern| pattern.matches(origin))
+            .then(|| origin.to_string())
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for CORS {
+    fn info(&self) -> Info {
+        Info {
+            name: "Add CORS headers to responses",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if self.origins.is_some() {
+            // the answer depends on the origin, the caches must not share it
+            response.adjoin_header(Header::new("Vary", "Origin"));
+        }
+        let Some(allowed_origin) = self.allowed_origin(request.headers().get_one("Origin")) else {
+            return;
+        };
+        let credentials = self.cookie_auth && self.origins.is_some();
+        if request.method() == Method::Options {
+            // with the credentials the browsers do not read `*` as any header, the requested ones are allowed
+            let headers = match request.headers().get_one("Access-Control-Request-Headers") {
+                Some(requested) if credentials && self.headers == "*" => requested.to_string(),
+                _ => self.headers.clone(),
+            };
+            response.set_header(Header::new("Access-Control-Allow-Methods", self.methods.clone()));
+            response.set_header(Header::new("Access-Control-Allow-Headers", headers));
+            response.set_header(Header::new("Access-Control-Max-Age", self.max_age.to_string()));
+        }
+        response.set_header(Header::new("Access-Control-Allow-Origin", allowed_origin));
+        if credentials {
+            response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
+        }
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use rocket::figment::Figment;
+use state::types::DatabaseOptions;
+
+/// Database used when no location is configured, relative to the working directory
+pub const DEFAULT_DATABASE_PATH: &str = "db_v2.sqlite3";
+
+/// Location of the sqlite database
+///
+/// It is read from `database.url` in the Rocket configuration
+/// (`ROCKET_DATABASE={url="sqlite:///var/lib/sctgdesk/db_v2.sqlite3"}` or `Rocket.toml`), a plain path is also accepted.
+/// `DATABASE_PATH` is used when it is not set, `db_v2.sqlite3` in the working directory otherwise
+#[derive(Debug, Clone)]
+pub struct DatabasePath(PathBuf);
+
+impl DatabasePath {
+    pub fn from_figment(figment: &Figment) -> Self {
+        let path = figment
+            .extract_inner::<String>("database.url")
+            .ok()
+            .or_else(|| std::env::var("DATABASE_PATH").ok())
+            .and_then(|url| Self::parse(&url))
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_DATABASE_PATH));
+        DatabasePath(path)
+    }
+
+    /// Path of a `sqlite://` url or of a plain path, without the query of the url
+    ///
+    /// # Returns
+    /// None if the path is empty
+    pub fn parse(url: &str) -> Option<PathBuf> {
+        let path = url.trim();
+        let path = path
+            .strip_prefix("sqlite://")
+            .or_else(|| path.strip_prefix("sqlite:"))
+            .map(|path| path.split('?').next().unwrap_or_default())
+            .unwrap_or(path);
+        Some(PathBuf::from(path)).filter(|path| !path.as_os_str().is_empty())
+    }
+
+    pub fn as_path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Create the directories of the database and check that they can be written,
+    /// sqlite creates its journal files next to the database
+    ///
+    /// # Returns
+    /// The reason why the database cannot be used at this location
+    pub fn prepare(&self) -> Result<(), String> {
+        let dir = match self.0.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+        if self.0.exists() {
+            OpenOptions::new()
+                .append(true)
+                .open(&self.0)
+                .map_err(|e| format!("{} is not writable: {}", self.0.display(), e))?;
+        }
+        let probe = dir.join(format!(".sctgdesk-write-test-{}", std::process::id()));
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&probe)
+            .map_err(|e| format!("{} is not writable: {}", dir.display(), e))?;
+        let _ = fs::remove_file(&probe);
+        Ok(())
+    }
+}
+
+/// Settings of the connection pool of the database
+///
+/// They are read from the `database` table of the Rocket configuration
+/// (`ROCKET_DATABASE={pool_size=20,busy_timeout_ms=10000}` or `Rocket.toml`):
+/// `pool_size`, `acquire_timeout_secs`, `busy_timeout_ms` and `statement_cache_size`.
+/// A missing value keeps its default, invalid settings are all ignored
+pub fn database_options(figment: &Figment) -> DatabaseOptions {
+    if !figment.contains("database") {
+        return DatabaseOptions::default();
+    }
+    figment.extract_inner::<DatabaseOptions>("database").unwrap_or_else(|e| {
+        log::error!("the database settings are ignored: {}", e);
+        DatabaseOptions::default()
+    })
+}
@@ -22,13 +22,18 @@ use rocket_okapi::gen::OpenApiGenerator;
 use rocket_okapi::okapi::openapi3::RequestBody;
 use rocket_okapi::request::OpenApiFromData;
 use rocket_okapi::{JsonSchema,Result as OkapiResult};
+use utils::get_host::request_headers;
 use serde::Deserialize;
 
+use crate::base_path::BasePath;
+
 /// A wrapper around a JSON value that includes headers.
 #[derive(Debug)]
 pub struct ExtendedJson<T> {
     pub data: T,
     pub headers: HashMap<String, String>,
+    /// the path the server is mounted at, see `BasePath`
+    pub base_path: String,
 }
 
 #[derive(Debug)]
@@ -50,18 +55,14 @@ impl<T> ExtendedJson<T> {
 }
 
 impl<'r, T: Deserialize<'r>> ExtendedJson<T> {
-    fn from_str(s: &'r str, h: HashMap<String, String>) -> Result<Self, Error<'r>> {
+    fn from_str(s: &'r str, h: HashMap<String, String>, base_path: String) -> Result<Self, Error<'r>> {
         let data = serde_json::from_str(s).map_err(|e| Error::Parse(s, e))?;
         let headers = h;
-        Ok(Self { data, headers })
+        Ok(Self { data, headers, base_path })
     }
 
     async fn from_data(req: &'r Request<'_>, data: Data<'r>) -> Result<Self, Error<'r>> {
-        let headers_src = req.headers().clone();
-        let mut headers:HashMap<String, String> = HashMap::new();
-        headers_src.iter().for_each(|k|{
-            headers.insert(k.name.to_string().to_lowercase(),k.value.to_string().to_lowercase());
-        });
+        let headers = request_headers(req);
         let limit = req.limits().get("json").unwrap_or(Limits::JSON);
         let string = match data.open(limit).into_string().await {
             Ok(s) if s.is_complete() => s.into_inner(),
@@ -71,7 +72,7 @@ impl<'r, T: Deserialize<'r>> ExtendedJson<T> {
             },
             Err(e) => return Err(Error::Io(e)),
         };
-        Self::from_str(local_cache!(req, string), headers)
+        Self::from_str(local_cache!(req, string), headers, BasePath::of_request(req))
     }
 }
 
@@ -19,15 +19,37 @@ use rocket_okapi::request::OpenApiFromRequest;
 use rocket_okapi::gen::OpenApiGenerator;
 use rocket_okapi::request::RequestHeaderInput;
 use rocket_okapi::OpenApiError;
+use utils::get_host::{get_host, request_headers};
+
+use crate::base_path::BasePath;
 
 /// # ExtendedRequest
 /// 
 /// This struct is used to extend the Request struct from Rocket adding a headers field.
 /// this is needed for analysis of the headers in the request. Particularly useful for
 /// retrieving the host with utils::get_host::get_host()
+/// `X-Forwarded-Proto` is only kept when the request comes from a trusted proxy
 #[derive(Debug)]
 pub struct ExtendedRequest{
     pub headers: HashMap<String, String>,
+    /// the path the server is mounted at, see `BasePath`
+    pub base_path: String,
+}
+
+impl ExtendedRequest {
+    /// The url of the server as seen by the client, with its base path
+    pub fn host(&self) -> String {
+        format!("{}{}", get_host(self.headers.clone()), self.base_path)
+    }
+
+    /// True if the client asked for JSON with `Accept: application/json`
+    pub fn accepts_json(&self) -> bool {
+        self.headers.get("accept").is_some_and(|accept| {
+            accept
+                .split(',')
+                .any(|media_type| media_type.split(';').next().unwrap_or_default().trim() == "application/json")
+        })
+    }
 }
 
 /// # FromRequest for ExtendedRequest
@@ -38,12 +60,10 @@ impl<'r> FromRequest<'r> for ExtendedRequest {
     type Error = Infallible;
 
     async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
-        let headers_src = request.headers().clone();
-        let mut headers:HashMap<String, String> = HashMap::new();
-        headers_src.iter().for_each(|k|{
-            headers.insert(k.name.to_string().to_lowercase(),k.value.to_string().to_lowercase());
-        });
-        Outcome::Success(Self { headers })
+        Outcome::Success(Self {
+            headers: request_headers(request),
+            base_path: BasePath::of_request(request),
+        })
     }
 }
 
@@ -13,31 +13,63 @@
 //
 // You should have received a copy of the Affero General Public License
 // along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+mod access_log;
 mod api;
+mod audit_forwarder;
+mod audit_webhook;
+mod base_path;
+mod branding;
+mod client_config;
+mod cors;
+mod database_path;
 mod extended_json;
 mod extended_request;
-
-use std::collections::HashMap;
+mod log_stream;
+mod metrics;
+mod oidc_pages;
+mod releases;
+mod security_headers;
+mod share_link;
+mod smtp;
+mod vite_proxy;
+mod webconsole;
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
-use std::io::Cursor;
 use std::path::PathBuf;
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::Instant;
 
-use api::ActionResponse;
+use access_log::AccessLog;
+use base_path::BasePath;
+use database_path::{database_options, DatabasePath};
+use api::{AcceptsGzip, ActionResponse, DownloadStream, GzipJson, HeartbeatReply, LoginError, LoginResponse, PeerAvatarFile, ReleaseFile, UserError};
 use extended_json::ExtendedJson;
 use extended_request::ExtendedRequest;
+use metrics::{Metrics, MetricsScraper};
+pub use cors::{CORS, CORS_DEFAULT_MAX_AGE};
+use oidc_pages::oidc_callback_page;
+use security_headers::SecurityHeaders;
 use oauth2::oauth_provider::OAuthProvider;
 use oauth2::oauth_provider::OAuthProviderFactory;
-use rocket::fairing::{Fairing, Info, Kind};
 use rocket::form::validate::Len;
-use rocket::http::{ContentType, Header};
-use rocket::response::{Redirect, Responder};
-use rocket::{async_trait, delete, options, put, routes, uri};
-use rocket::{Request, Response};
+use rocket::http::{ContentType, Status};
+use rocket::futures::{Stream, StreamExt};
+use rocket::response::stream::{stream, Event, EventStream};
+use rocket::response::content::RawHtml;
+use rocket::response::Redirect;
+use rocket::tokio::select;
+use rocket::tokio::sync::broadcast::error::RecvError;
+use rocket::tokio::time::Duration;
+use rocket::data::{ByteUnit, Data};
+use rocket::Shutdown;
+use rocket::{catchers, delete, options, put, routes};
 
 use s3software::extract_version;
 use s3software::get_software_download_page;
-use s3software::{get_s3_config_file, get_signed_release_url_with_config};
+use s3software::{check_release_key, load_s3_config, release_upload_key, ReleaseCache, ReleaseChecksum, ReleaseUpload, SOFTWARE_KEYS};
 
 use state::{self};
 
@@ -46,20 +78,45 @@ use ui;
 use utils::guid_into_uuid;
 use utils::AbProfile;
 use utils::AbRule;
+use utils::{AB_RULE_READ, AB_RULE_WRITE};
 use utils::AbRuleAddRequest;
 use utils::AbRuleDeleteRequest;
 use utils::AbRulesResponse;
 use utils::AbSharedAddRequest;
 use utils::AbSharedNameRequest;
+use utils::{AbPeersMergeRequest, AbPeersMergeResponse, AbPeersTagRequest, AbPeersTagResponse};
+use utils::{BuildInfoResponse, CapabilitiesResponse};
 use utils::AddGoupRequest;
 use utils::CpuCount;
 use utils::PeersCountResponse;
+use utils::{DryRunResponse, PeersBulkAction, PeersBulkRequest, PeersBulkResponse};
+use utils::cookie::CookieSettings;
+use utils::peer_export::{
+    peer_export_csv_record, PeerExportFormat, PEER_EXPORT_CSV_HEADER, PEER_EXPORT_CSV_INTERRUPTED,
+    PEER_EXPORT_JSON_INTERRUPTED,
+};
+use utils::peer_status::{build_peer_history, history_window};
+use utils::{PeerDetail, PeerHistoryResponse};
+use utils::PeerAccess;
+use utils::{ShareLinkError, ShareLinkRedeemResponse, ShareLinkRequest, ShareLinkResponse};
+use utils::PeerUuidConflictsResponse;
 use utils::Platform;
+use utils::{PeersSummary, StatisticsResponse};
+use utils::{AddStrategyRequest, AssignStrategyRequest, DuplicateStrategyRequest, EffectiveStrategy, StrategiesResponse, Strategy, UpdateStrategyRequest};
 use utils::UpdateGoupRequest;
+use utils::{AssignReleaseChannelRequest, ClientUpgradeResponse, ClientVersions, ReleaseChannel};
+use utils::{PeerTransferError, PeerTransferRequest, PeerTransferResponse};
+use utils::{LinkedProvidersResponse, UnlinkProviderError};
+use utils::{EmailVerificationError, EmailVerificationResponse};
+use utils::{RevokeAllSessionsRequest, RevokeAllSessionsResponse};
+use utils::{VersionCheckRequest, VersionCheckResponse};
+use utils::LogLine;
+use utils::semver::{compare_versions, Version};
+use sha2::{Digest, Sha256};
 use utils::{
-    self, get_host::get_host, AbPeer, AbPeersResponse, AbPersonal, AbSettingsResponse,
-    AbSharedProfilesResponse, AbTag, BearerAuthToken, OidcAuthRequest, OidcAuthUrl, OidcResponse,
-    OidcState, OidcUser, OidcUserInfo, OidcUserStatus,
+    self, get_host::{get_callback_host, get_force_https_callbacks, get_host}, AbPeer, AbPeersResponse, AbPersonal, AbSettingsResponse,
+    AbSharedProfilesResponse, AbTag, AbTagRequest, AbTagStats, BearerAuthToken, OidcAuthRequest, OidcAuthUrl, OidcResponse,
+    OidcSessionsResponse, OidcState, OidcUser, OidcUserInfo, OidcUserStatus, UserStatus,
 };
 
 use base64::prelude::{Engine as _, BASE64_STANDARD};
@@ -68,44 +125,38 @@ use rocket::{
 };
 pub use state::{ApiState, UserPasswordInfo};
 use utils::{
-    include_png_as_base64, unwrap_or_return, uuid_into_guid, AbTagRenameRequest, AddUserRequest,
-    AddressBook, EnableUserRequest, GroupsResponse, OidcSettingsResponse, PeersResponse,
-    SoftwareResponse, SoftwareVersionResponse, UpdateUserRequest, UserList,
+    unwrap_or_return, uuid_into_guid, AbTagRenameRequest, AddUserRequest,
+    AddressBook, EnableUserRequest, GroupsResponse, IntegrationTarget, IntegrationTestRequest,
+    BrandingResponse, IntegrationTestResponse, OidcReloadResponse, OidcSettingsResponse, PeersResponse,
+    SoftwareKeyError, SoftwareRelease, SoftwareResponse, SoftwareVersionResponse, UpdatePeerRequest, UpdateUserRequest, UserList,
 };
+use audit_forwarder::AuditForwarder;
+use audit_webhook::AuditWebhook;
+use branding::Branding;
+use log_stream::{LogBuffer, LogSink};
+use client_config::ClientConfig;
+use releases::{parse_range, release_content_type, release_file_name, DefaultClientVersions, LocalReleases, ReleaseUploadSettings};
+use share_link::{now_secs, ShareLinkClaims, ShareLinks, SHARE_LINK_DEFAULT_TTL_SECS, SHARE_LINK_MAX_TTL_SECS};
+use smtp::SmtpSettings;
+use webconsole::{
+    webconsole_assets, webconsole_fallback, webconsole_vue, CachePolicy, StaticFileResponse, StaticFiles, STATIC_DIR,
+};
+use utils::audit::{AdminAction, AuditCategory, AuditEvent, AuditFilter, AUDIT_EXPORT_MAX_ROWS};
+use utils::cursor::parse_after;
+use utils::peer_id::{normalize_ab_peer_id, normalize_peer_id};
+use utils::strategy::{validate_strategy_settings, EffectiveSettings, StrategyOrder};
 use utils::{
-    AbGetResponse, AbRequest, AuditRequest, CurrentUserRequest, CurrentUserResponse,
-    HeartbeatRequest, LoginReply, LoginRequest, LogoutReply, UserInfo, UsersResponse,
+    AbBatchGetRequest, AbBatchGetResponse, AbGetResponse, AbRequest, AuditAdminResponse, AuditAlarmsResponse, AuditConnsResponse, AuditFilesResponse, AuditRequest, AuditRetention, CurrentUserRequest, CurrentUserResponse,
+    HeartbeatDirective, HeartbeatRequest, HeartbeatResponse, LoginReply, MaintenanceNotice, LoginRequest, LogoutReply, UserInfo, UserPermissions, UsersResponse,
 };
 
 type AuthenticatedUser = state::AuthenticatedUser<BearerAuthToken>;
 type AuthenticatedAdmin = state::AuthenticatedAdmin<BearerAuthToken>;
 
-use rocket_okapi::{openapi, openapi_get_routes, rapidoc::*, settings::UrlObject};
+use rocket_okapi::okapi::openapi3::{OpenApi, Server};
+use rocket_okapi::{openapi, openapi_get_routes_spec, rapidoc::*, settings::UrlObject};
 use uuid::Uuid;
 
-use include_dir::{include_dir, Dir};
-
-pub struct CORS;
-
-#[rocket::async_trait]
-impl Fairing for CORS {
-    fn info(&self) -> Info {
-        Info {
-            name: "Add CORS headers to responses",
-            kind: Kind::Response,
-        }
-    }
-
-    async fn on_response<'r>(&self, _request: &'r Request<'_>, response: &mut Response<'r>) {
-        response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
-        response.set_header(Header::new(
-            "Access-Control-Allow-Methods",
-            "POST, GET, PUT, DELETE, OPTIONS",
-        ));
-        response.set_header(Header::new("Access-Control-Allow-Headers", "*"));
-        response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
-    }
-}
 
 /// # Answers to OPTIONS requests
 #[openapi(tag = "Cors")]
@@ -114,87 +165,211 @@ async fn options(_path: PathBuf) -> Result<(), std::io::Error> {
     Ok(())
 }
 
+/// Log the schema migrations which would be applied on the database configured in `figment`, without applying them
+///
+/// # Returns
+/// An error if the database cannot be read or has been migrated by a newer server
+pub async fn dry_run_migrations(figment: &Figment) -> Result<(), String> {
+    LogSink::init(figment);
+    let database_path = DatabasePath::from_figment(figment);
+    let pending = ApiState::pending_migrations(database_path.as_path()).await?;
+    if pending.is_empty() {
+        log::info!("Database {} is up to date", database_path.as_path().display());
+    }
+    for migration in pending {
+        log::info!("Would apply migration {} ({})", migration.version, migration.description);
+    }
+    Ok(())
+}
+
 pub async fn build_rocket(figment: Figment) -> Rocket<Build> {
-    let state = ApiState::new_with_db("db_v2.sqlite3").await;
+    // before anything is logged and before Rocket installs its own logger
+    let log_buffer = LogSink::init(&figment);
+    let database_path = DatabasePath::from_figment(&figment);
+    if let Err(e) = database_path.prepare() {
+        panic!("Cannot open the database {}: {}", database_path.as_path().display(), e);
+    }
+    log::info!("Database: {}", database_path.as_path().display());
+    let state = ApiState::new_with_db_options(database_path.as_path(), &database_options(&figment)).await;
+    state.spawn_audit_purge();
+    state.spawn_peer_status_maintenance();
+    // every cookie set by the server must be built with these settings
+    let cookie_settings = CookieSettings::from_env().expect("Invalid cookie configuration");
+    let audit_webhook = AuditWebhook::from_figment(&figment);
+    let audit_forwarder = AuditForwarder::from_figment(&figment);
+    let client_config = ClientConfig::from_figment(&figment);
+    let smtp_settings = SmtpSettings::from_figment(&figment);
+    let share_links = ShareLinks::from_figment(&figment);
+    let local_releases = LocalReleases::from_figment(&figment);
+    if !local_releases.is_enabled() {
+        if let Err(e) = load_s3_config() {
+            log::warn!("Software downloads are not available, the S3 configuration cannot be used: {}", e);
+        }
+    }
+    let client_versions = DefaultClientVersions::from_figment(&figment);
+    let release_upload = ReleaseUploadSettings::from_figment(&figment);
+    let branding = Branding::from_figment(&figment);
+    let base_path = BasePath::from_figment(&figment);
+    let mut static_files = StaticFiles::new(&STATIC_DIR)
+        .with_base_path(base_path.as_str())
+        .with_cache_policy(CachePolicy::from_figment(&figment));
+    if let Some(product_name) = branding.custom_product_name() {
+        static_files = static_files.with_index_title(&format!("{} Web Console", product_name));
+    }
+    if let Ok(vite_base) = env::var("VITE_DEVELOPMENT") {
+        static_files = static_files.with_vite_proxy(vite_base);
+    }
 
+    // openapi.json is served by openapi_json, with the servers of the request
+    let (api_routes, openapi_spec) = openapi_get_routes_spec![
+        options,
+        login,
+        login_options,
+        ab_get,
+        ab_post,
+        ab_batch_get,
+        ab,
+        ab_put,
+        current_user,
+        audit,
+        audit_typ,
+        audit_conns,
+        audit_files,
+        audit_alarms,
+        audit_admin,
+        audit_export,
+        audit_retention,
+        audit_retention_update,
+        logout,
+        heartbeat,
+        sysinfo,
+        groups,
+        group_get,
+        group_add,
+        group_delete,
+        group_update,
+        users,
+        users_client,
+        user_add,
+        user_enable,
+        user_update,
+        peers,
+        peer_get,
+        peer_update,
+        peer_avatar_put,
+        peer_avatar_get,
+        peer_access,
+        peer_share_link,
+        share_link_redeem,
+        peer_transfer,
+        peers_bulk,
+        peer_history,
+        peers_events,
+        logs_stream,
+        peers_export,
+        peers_conflicts,
+        peers_conflicts_delete,
+        peers_count,
+        peers_cpus,
+        peers_summary,
+        statistics,
+        strategies,
+        strategy_get,
+        strategy_add,
+        strategy_duplicate,
+        strategy_update,
+        strategy_delete,
+        peer_strategy,
+        peer_effective_strategy,
+        user_strategy,
+        group_strategy,
+        user_permissions,
+        ui_settings,
+        ui_settings_update,
+        ui_defaults,
+        ui_defaults_update,
+        user_settings,
+        user_settings_update,
+        linked_providers,
+        linked_provider_delete,
+        email_verify_request,
+        email_verify,
+        oidc_auth,
+        oidc_state,
+        oidc_callback,
+        oidc_add,
+        oidc_get,
+        oidc_reload,
+        oidc_sessions,
+        test_integration,
+        maintenance_notice,
+        revoke_all_sessions,
+        ab_peer_add,
+        ab_peer_update,
+        ab_peer_delete,
+        ab_peers_tag,
+        ab_peers_merge,
+        ab_peers,
+        ab_personal,
+        ab_tags,
+        ab_tags_stats,
+        ab_tag_add,
+        ab_tag_update,
+        ab_tag_rename,
+        ab_tag_delete,
+        ab_shared,
+        ab_shared_add,
+        ab_shared_delete,
+        ab_shared_name,
+        ab_settings,
+        ab_rules,
+        ab_rule_add,
+        ab_rule_delete,
+        software,
+        software_cache_clear,
+        software_release_file,
+        software_version,
+        software_version_update,
+        user_release_channel,
+        group_release_channel,
+        software_release_upload,
+        version,
+        capabilities,
+        client_config_download,
+        software_releases_latest,
+        version_check,
+        version_check_get,
+        software_download,
+        software_releases_tag,
+        branding_settings,
+    ];
+
+    let metrics = Metrics::from_figment(&figment);
+    let cors = CORS::from_figment(&figment);
     let rocket = rocket::custom(figment)
-        .attach(CORS)
-        .mount(
-            "/",
-            openapi_get_routes![
-                options,
-                login,
-                login_options,
-                ab_get,
-                ab_post,
-                ab,
-                current_user,
-                audit,
-                logout,
-                heartbeat,
-                sysinfo,
-                groups,
-                group_get,
-                group_add,
-                group_delete,
-                group_update,
-                users,
-                users_client,
-                user_add,
-                user_enable,
-                user_update,
-                peers,
-                peers_count,
-                peers_cpus,
-                strategies,
-                oidc_auth,
-                oidc_state,
-                oidc_callback,
-                oidc_add,
-                oidc_get,
-                ab_peer_add,
-                ab_peer_update,
-                ab_peer_delete,
-                ab_peers,
-                ab_personal,
-                ab_tags,
-                ab_tag_add,
-                ab_tag_update,
-                ab_tag_rename,
-                ab_tag_delete,
-                ab_shared,
-                ab_shared_add,
-                ab_shared_delete,
-                ab_shared_name,
-                ab_settings,
-                ab_rules,
-                ab_rule_add,
-                ab_rule_delete,
-                software,
-                software_version,
-                software_releases_latest,
-                software_download,
-                software_releases_tag,
-                webconsole_index,
-                webconsole_index_html,
-                // webconsole_assets,
-            ],
-        )
-        .mount("/",routes![
+        .attach(cors)
+        .attach(SecurityHeaders::from_env())
+        .attach(AccessLog::from_env())
+        .attach(metrics.clone())
+        .attach(audit_forwarder)
+        .mount(base_path.mount_point("/"), api_routes)
+        .mount(base_path.mount_point("/"), routes![
             favicon,
             webconsole_vue,
-            openapi_snippet
+            webconsole_assets,
+            openapi_snippet,
+            openapi_json,
+            strategies_deprecated
         ])
+        .register(base_path.mount_point("/"), catchers![webconsole_fallback])
         .mount(
-            "/api/doc/",
+            base_path.mount_point("/api/doc/"),
             make_rapidoc(&RapiDocConfig {
-                title: Some("SCTGDesk API Doc".to_owned()),
+                title: Some(format!("{} API Doc", branding.product_name())),
                 custom_html: Some(include_str!("../rapidoc/index.html").to_owned()),
                 slots: SlotsConfig{
-                    logo: Some(include_png_as_base64!("../assets/logo.png")),
-                    footer: Some(r#"<p slot="footer" style="margin:0; padding:16px 36px; background-color:orangered; color:#fff; text-align:center;"> 
-                    © 2024 SCTG. All rights reserved.
-                  </p>"#.to_owned()),
+                    logo: Some(branding.logo().to_owned()),
+                    footer: Some(branding.rapidoc_footer()),
                     ..Default::default()
                 },
                 general: GeneralConfig {
@@ -211,7 +386,26 @@ pub async fn build_rocket(figment: Figment) -> Rocket<Build> {
                 ..Default::default()
             }),
         )
-        .manage(state);
+        .manage(state)
+        .manage(cookie_settings)
+        .manage(audit_webhook)
+        .manage(client_config)
+        .manage(smtp_settings)
+        .manage(share_links)
+        .manage(ReleaseCache::default())
+        .manage(local_releases)
+        .manage(client_versions)
+        .manage(release_upload)
+        .manage(static_files)
+        .manage(OpenApiSpec(openapi_spec))
+        .manage(branding)
+        .manage(log_buffer);
+    let rocket = if metrics.is_enabled() {
+        rocket.mount(base_path.mount_point("/"), routes![metrics_get])
+    } else {
+        rocket
+    };
+    let rocket = rocket.manage(base_path).manage(metrics);
 
     #[cfg(feature = "ui")]
     {
@@ -233,7 +427,11 @@ pub async fn build_rocket(figment: Figment) -> Rocket<Build> {
 /// ## Returns
 ///
 /// If successful, this function returns a `Json<LoginReply>` object, which includes the user's information and access token.  <br>
-/// If the user is not authorized, this function returns a `status::Unauthorized` error.  <br>
+/// The clients older than 1.1.9 in the `X-Client-Version` header get the token in `token` instead of `access_token`.  <br>
+/// If the user is not authorized, this function returns a `Status::Unauthorized` error.  <br>
+/// If the client is older than the minimum client version, this function returns a `Status::UpgradeRequired` error
+/// with the `ClientUpgradeResponse` asking to upgrade. The version is the one of the `X-Client-Version` header,
+/// or else the one of the last system info of the peer `id`, a client without version is accepted.  <br>
 ///
 /// ## Errors
 ///
@@ -242,9 +440,19 @@ pub async fn build_rocket(figment: Figment) -> Rocket<Build> {
 #[post("/api/login", format = "application/json", data = "<request>")]
 async fn login(
     state: &State<ApiState>,
+    default_versions: &State<DefaultClientVersions>,
+    extended_request: ExtendedRequest,
     request: Json<LoginRequest>,
-) -> Result<Json<LoginReply>, status::Unauthorized<()>> {
-    let status_forbidden = || status::Unauthorized::<()>(());
+) -> Result<LoginResponse, LoginError> {
+    let status_forbidden = || LoginError::Unauthorized;
+
+    let peer_id = normalize_peer_id(&request.id).ok();
+    if let Some(upgrade) =
+        check_client_version(state, default_versions, &extended_request, peer_id.as_deref()).await
+    {
+        log::debug!("login: {}", upgrade.error);
+        return Err(LoginError::UpgradeRequired(upgrade));
+    }
 
     let user_password_info = UserPasswordInfo::from_password(request.password.as_str());
     let (user, access_token) = state
@@ -258,11 +466,11 @@ async fn login(
         access_token,
     };
 
-    log::debug!("login: {:?}", request);
+    log::debug!("login: {} from {}", request.username, request.id);
 
     state.check_maintenance().await;
 
-    Ok(Json(reply))
+    Ok(LoginResponse(reply))
 }
 
 /// # Get the User's Legacy Address Book
@@ -279,6 +487,7 @@ async fn login(
 /// ## Returns
 ///
 /// If successful, this function returns a `Json<AbGetResponse>` object, which includes the legacy address book information.  <br>
+/// The answer is compressed with gzip if it is large and the client sends `Accept-Encoding: gzip`.  <br>
 /// If the user is not authorized, this function returns a `status::Unauthorized` error.  <br>
 ///
 /// ## Errors
@@ -290,8 +499,9 @@ async fn login(
 async fn ab_get(
     state: &State<ApiState>,
     user: AuthenticatedUser,
-) -> Result<Json<AbGetResponse>, status::Unauthorized<()>> {
-    ab_get_handler(state, user).await
+    accepts_gzip: AcceptsGzip,
+) -> Result<GzipJson<AbGetResponse>, status::Unauthorized<()>> {
+    ab_get_handler(state, user, accepts_gzip).await
 }
 
 /// # Get the User's Address Book
@@ -306,6 +516,7 @@ async fn ab_get(
 /// ## Returns
 ///
 /// If successful, this function returns a `Json<AbGetResponse>` object, which includes the address book information.  <br>
+/// The answer is compressed with gzip if it is large and the client sends `Accept-Encoding: gzip`.  <br>
 /// If the user is not authorized, this function returns a `status::Unauthorized` error.  <br>
 ///
 /// ## Errors
@@ -320,8 +531,75 @@ async fn ab_get(
 async fn ab_post(
     state: &State<ApiState>,
     user: AuthenticatedUser,
-) -> Result<Json<AbGetResponse>, status::Unauthorized<()>> {
-    ab_get_handler(state, user).await
+    accepts_gzip: AcceptsGzip,
+) -> Result<GzipJson<AbGetResponse>, status::Unauthorized<()>> {
+    ab_get_handler(state, user, accepts_gzip).await
+}
+
+/// Maximum number of users in one `/api/ab/batch-get` request
+const AB_BATCH_GET_MAX_SIZE: usize = 50;
+
+/// # Get the Address Books of several Users
+///
+/// This function is an API endpoint that allows an authenticated admin to retrieve the address books of several users at once,
+/// e.g. to investigate a group. The address books which are not in memory are read in a single query.
+/// It is tagged with "address book" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `request`: The guids of the users, at most 50.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<AbBatchGetResponse>` object with the address book of each user keyed by its guid,
+/// in the format of `/api/ab/get`, and the guids of the users without address book.
+/// The answer is compressed with gzip if it is large and the client sends `Accept-Encoding: gzip`.  <br>
+///
+/// ## Errors
+///
+/// * a `Status::BadRequest` error if a guid is not valid or if there are more than 50 users
+///
+/// This function will also return an error if the system is in maintenance mode, or if the user is not an admin.
+///
+/// # Example
+///
+/// POST /api/ab/batch-get
+/// {"users":["018f2556-2301-79eb-91a2-cffe5ced4236","018f2556-22fb-73ee-9afd-bbcdc0cc387b"]}
+#[openapi(tag = "address book")]
+#[post("/api/ab/batch-get", format = "application/json", data = "<request>")]
+async fn ab_batch_get(
+    state: &State<ApiState>,
+    _user: AuthenticatedAdmin,
+    accepts_gzip: AcceptsGzip,
+    request: Json<AbBatchGetRequest>,
+) -> Result<GzipJson<AbBatchGetResponse>, Status> {
+    log::debug!("ab_batch_get");
+    state.check_maintenance().await;
+    let mut guids = request.into_inner().users;
+    guids.sort();
+    guids.dedup();
+    if guids.len() > AB_BATCH_GET_MAX_SIZE {
+        return Err(Status::BadRequest);
+    }
+    let user_ids = guids
+        .iter()
+        .map(|guid| Uuid::parse_str(guid).map(|guid| guid.as_bytes().to_vec()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| Status::BadRequest)?;
+    let mut address_books = state
+        .get_users_address_books(&user_ids)
+        .await
+        .ok_or(Status::InternalServerError)?;
+    let mut reply = AbBatchGetResponse::default();
+    for (guid, user_id) in guids.into_iter().zip(user_ids) {
+        match address_books.remove(&user_id) {
+            Some(ab) => {
+                reply.data.insert(guid, ab.ab);
+            }
+            None => reply.not_found.push(guid),
+        }
+    }
+    Ok(GzipJson::encode(reply, accepts_gzip).await)
 }
 
 /// Common handler for the user's address book
@@ -330,6 +608,7 @@ async fn ab_post(
 ///
 /// * `state` - The API state
 /// * `user` - The authenticated user supplied via a Bearer token
+/// * `accepts_gzip` - Whether the client accepts a gzip answer
 ///
 /// # Returns
 ///
@@ -337,7 +616,8 @@ async fn ab_post(
 async fn ab_get_handler(
     state: &State<ApiState>,
     user: AuthenticatedUser,
-) -> Result<Json<AbGetResponse>, status::Unauthorized<()>> {
+    accepts_gzip: AcceptsGzip,
+) -> Result<GzipJson<AbGetResponse>, status::Unauthorized<()>> {
     log::debug!("ab get");
 
     // Get the user's address book from the state
@@ -361,11 +641,11 @@ async fn ab_get_handler(
     // Check if the server is in maintenance mode
     state.check_maintenance().await;
 
-    // Debug log the reply
-    log::debug!("ab get reply: {:?}", Json(&reply));
+    // the address book holds the passwords of the peers, only its size is logged
+    log::debug!("ab get reply: {} bytes", reply.data.len());
 
-    // Return the reply as JSON
-    Ok(Json(reply))
+    // Return the reply as JSON, gzipped if it is large and the client accepts it
+    Ok(GzipJson::encode(reply, accepts_gzip).await)
 }
 
 /// Set the user's address book
@@ -376,12 +656,10 @@ async fn ab(
     user: AuthenticatedUser,
     request: Json<AbRequest>,
 ) -> Result<(), status::Unauthorized<()>> {
-    log::debug!("ab: {:?}", request);
+    log::debug!("ab: {} bytes", request.data.len());
 
     let ab = request.data.clone();
 
-    log::debug!("new ab: {:?}", &ab);
-
     let ab = AddressBook {
         ab,
         ..Default::default()
@@ -397,6 +675,59 @@ async fn ab(
     Ok(())
 }
 
+/// # Update the User's Address Book
+///
+/// This function is an API endpoint that allows an authenticated user to replace their legacy address book,
+/// only if it already exists. `POST /api/ab` creates it.
+/// It is tagged with "address book legacy" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `request`: The request data, which includes the address book serialized as a string.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns an empty response.  <br>
+/// If the user has no address book yet, this function returns a `Status::NotFound` error.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, if the user is not authorized
+/// or if their address book does not exist.
+///
+/// # Example
+///
+/// PUT /api/ab
+/// {
+///     "data": "{\"tags\":[],\"peers\":[]}"
+/// }
+#[openapi(tag = "address book legacy")]
+#[put("/api/ab", format = "application/json", data = "<request>")]
+async fn ab_put(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    request: Json<AbRequest>,
+) -> Result<(), Status> {
+    log::debug!("ab_put: {} bytes", request.data.len());
+    state.check_maintenance().await;
+
+    let exists = state
+        .get_user_address_book(user.info.user_id.clone())
+        .await
+        .is_some_and(|ab| !ab.ab.is_empty());
+    if !exists {
+        return Err(Status::NotFound);
+    }
+    let ab = AddressBook {
+        ab: request.data.clone(),
+        ..Default::default()
+    };
+    state
+        .set_user_address_book(user.info.user_id, ab)
+        .await
+        .ok_or(Status::InternalServerError)
+}
+
 /// # Get the Current User
 ///
 /// This function is an API endpoint that allows an authenticated user to retrieve their current user information.
@@ -427,11 +758,14 @@ async fn current_user(
         .get_current_user_name(&user.info)
         .await
         .ok_or(Err(status::Unauthorized::<()>(()))));
+    let (email, email_verified) = state.get_user_email(&user.info.user_id).await.unwrap_or_default();
 
     let reply = CurrentUserResponse {
         error: false,
         data: UserInfo {
             name: username,
+            email,
+            email_verified,
             ..Default::default()
         },
     };
@@ -440,1944 +774,10760 @@ async fn current_user(
     Ok(Json(reply))
 }
 
-/// Audit
-#[openapi(tag = "todo")]
+/// # Audit
+///
+/// This function is an API endpoint that receives an audit record sent by a client.
+/// The kind of record is guessed from its fields.
+/// It is tagged with "audit" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `request`: The audit record.
+///
+/// ## Returns
+///
+/// Nothing, the record is stored in the background.
+#[openapi(tag = "audit")]
 #[post("/api/audit", format = "application/json", data = "<request>")]
-async fn audit(state: &State<ApiState>, request: Json<AuditRequest>) {
-    log::debug!("audit: {:?}", request);
+async fn audit(
+    state: &State<ApiState>,
+    webhook: &State<AuditWebhook>,
+    request: Json<AuditRequest>,
+) {
     state.check_maintenance().await;
+    record_audit(state, webhook, None, request.into_inner());
 }
 
-/// # Log the User Out
+/// # Audit by kind
 ///
-/// This function is an API endpoint that allows an authenticated user to log out.
-/// It is tagged with "login" for OpenAPI documentation.
+/// This function is an API endpoint that receives an audit record sent by a client.
+/// The alarms are also posted to the webhook configured with `audit_webhook`, if any.
+/// It is tagged with "audit" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `request`: The request data, which includes the current user information.  <br>
+/// - `typ`: The kind of record: `conn` (connection), `file` (file transfer) or `alarm`.
 ///
-/// ## Returns
+/// - `request`: The audit record.
 ///
-/// If successful, this function returns a `Json<LogoutReply>` object, which includes a success message.  <br>
-/// If the user is not authorized, this function returns a `status::Unauthorized` error.  <br>
+/// ## Returns
 ///
-/// ## Errors
+/// Nothing, the record is stored in the background.
 ///
-/// This function will return an error if the system is in maintenance mode, or if the user is not authorized.
+/// # Example
 ///
-#[openapi(tag = "login")]
-#[post("/api/logout", format = "application/json", data = "<request>")]
-async fn logout(
+/// POST /api/audit/conn
+/// {"id":"123456789","uuid":"AQI=","conn_id":3,"session_id":42,"ip":"10.0.0.1","action":"new"}
+#[openapi(tag = "audit")]
+#[post("/api/audit/<typ>", format = "application/json", data = "<request>")]
+async fn audit_typ(
     state: &State<ApiState>,
-    user: AuthenticatedUser,
-    request: Json<CurrentUserRequest>,
-) -> Result<Json<LogoutReply>, status::Unauthorized<()>> {
-    log::debug!("logout: {:?}", request);
-
-    let _ = unwrap_or_return!(state
-        .user_logout(&user.info)
-        .await
-        .ok_or(Err(status::Unauthorized::<()>(()))));
-
-    let reply = LogoutReply {
-        data: String::new(),
-    };
-
+    webhook: &State<AuditWebhook>,
+    typ: &str,
+    request: Json<AuditRequest>,
+) {
     state.check_maintenance().await;
-
-    Ok(Json(reply))
+    record_audit(state, webhook, Some(typ), request.into_inner());
 }
 
-/// # Heartbeat
+/// # Get the Connections Audit
 ///
-/// This function is an API endpoint that is frequently hit by the client at the /api/heartbeat endpoint.
-/// It updates the `last_online` field of the peer.
-/// It is tagged with "peer" for OpenAPI documentation.
+/// This function is an API endpoint that allows an authenticated admin to retrieve the connections recorded by the peers.
+/// It is tagged with "audit" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `request`: The request data, which includes the heartbeat information.  
+/// - `current`: The current page number, 1 by default.  
+///
+/// - `pageSize`: The number of connections per page, 20 by default.  
+///
+/// - `from`: Keep the connections started at or after this date (`YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS`).  
+///
+/// - `to`: Keep the connections started at or before this date.  
+///
+/// - `peer`: Keep the connections to this peer id.  
+///
+/// - `user`: Keep the connections to the peers assigned to this user name.  
 ///
 /// ## Returns
 ///
-/// This function always returns a `String` with the message "OK".  <br>
+/// If successful, this function returns a `Json<AuditConnsResponse>` object with the total number of matching connections and the requested page, the most recent first.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode.
-#[openapi(tag = "peer")]
-#[post("/api/heartbeat", format = "application/json", data = "<request>")]
-async fn heartbeat(state: &State<ApiState>, request: Json<HeartbeatRequest>) -> String {
-    log::debug!("heartbeat: {:?}", request);
-    let heartbeat = request.0;
-    let res = state.update_heartbeat(heartbeat).await;
-    log::debug!("res: {:?}", res);
-    "OK".to_string()
+/// This function will return a `Status::InternalServerError` if the connections cannot be read from the database.
+///
+/// # Example
+///
+/// GET /api/audit/conns?current=1&pageSize=10&from=2024-05-01&peer=123456789
+#[openapi(tag = "audit")]
+#[get("/api/audit/conns?<current>&<pageSize>&<from>&<to>&<peer>&<user>")]
+#[allow(clippy::too_many_arguments)]
+async fn audit_conns(
+    state: &State<ApiState>,
+    _user: AuthenticatedAdmin,
+    current: Option<u32>,
+    #[allow(non_snake_case)] pageSize: Option<u32>,
+    from: Option<String>,
+    to: Option<String>,
+    peer: Option<String>,
+    user: Option<String>,
+) -> Result<Json<AuditConnsResponse>, Status> {
+    state.check_maintenance().await;
+    let filter = AuditFilter::new(from, to, peer, user, current, pageSize);
+    let (total, data) = state
+        .get_audit_conns(&filter)
+        .await
+        .ok_or(Status::InternalServerError)?;
+    Ok(Json(AuditConnsResponse {
+        msg: "success".to_string(),
+        total,
+        data,
+    }))
 }
 
-/// # Set the System Info
+/// # Get the File Transfers Audit
 ///
-/// This function is an API endpoint that allows a connected client to update its system information.
-/// It is tagged with "peer" for OpenAPI documentation.
+/// This function is an API endpoint that allows an authenticated admin to retrieve the file transfers recorded by the peers.
+/// It is tagged with "audit" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `request`: The request data, which includes the system information.  
+/// - `current`: The current page number, 1 by default.  
+///
+/// - `pageSize`: The number of transfers per page, 20 by default.  
+///
+/// - `from`: Keep the transfers made at or after this date (`YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS`).  
+///
+/// - `to`: Keep the transfers made at or before this date.  
+///
+/// - `peer`: Keep the transfers reported by this peer id.  
+///
+/// - `user`: Keep the transfers reported by the peers assigned to this user name.  
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `String` with the message "SYSINFO_UPDATED".  <br>
-/// If the system info is not found, this function returns a `String` with the message "ID_NOT_FOUND".  <br>
+/// If successful, this function returns a `Json<AuditFilesResponse>` object with the total number of matching transfers and the requested page, the most recent first.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if the system info is not found.
+/// This function will return a `Status::InternalServerError` if the transfers cannot be read from the database.
 ///
-#[openapi(tag = "peer")]
-#[post("/api/sysinfo", format = "application/json", data = "<request>")]
-async fn sysinfo(state: &State<ApiState>, request: Json<utils::SystemInfo>) -> String {
-    let sysinfo = request.0;
-    let res = state.update_systeminfo(sysinfo).await;
-
-    if res.is_none() {
-        return "ID_NOT_FOUND".to_string();
-    } else {
-        return "SYSINFO_UPDATED".to_string();
-    }
+/// # Example
+///
+/// GET /api/audit/files?current=1&pageSize=10&user=admin
+#[openapi(tag = "audit")]
+#[get("/api/audit/files?<current>&<pageSize>&<from>&<to>&<peer>&<user>")]
+#[allow(clippy::too_many_arguments)]
+async fn audit_files(
+    state: &State<ApiState>,
+    _user: AuthenticatedAdmin,
+    current: Option<u32>,
+    #[allow(non_snake_case)] pageSize: Option<u32>,
+    from: Option<String>,
+    to: Option<String>,
+    peer: Option<String>,
+    user: Option<String>,
+) -> Result<Json<AuditFilesResponse>, Status> {
+    state.check_maintenance().await;
+    let filter = AuditFilter::new(from, to, peer, user, current, pageSize);
+    let (total, data) = state
+        .get_audit_files(&filter)
+        .await
+        .ok_or(Status::InternalServerError)?;
+    Ok(Json(AuditFilesResponse {
+        msg: "success".to_string(),
+        total,
+        data,
+    }))
 }
 
-/// # Get the List of Users
+/// # Get the Alarms Audit
 ///
-/// This function is an API endpoint that allows an authenticated admin to retrieve a paginated list of users.
-/// It is tagged with "user" for OpenAPI documentation.
+/// This function is an API endpoint that allows an authenticated admin to retrieve the alarms raised by the peers,
+/// e.g. too many failed password attempts.
+/// It is tagged with "audit" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `current`: The current page number.  
+/// - `current`: The current page number, 1 by default.  
 ///
-/// - `pageSize`: The number of users per page.  
+/// - `pageSize`: The number of alarms per page, 20 by default.  
 ///
-/// - `email`: The email to filter the users by.  
+/// - `from`: Keep the alarms raised at or after this date (`YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS`).  
 ///
-/// - `name`: The name to filter the users by.  
+/// - `to`: Keep the alarms raised at or before this date.  
+///
+/// - `peer`: Keep the alarms raised by this peer id.  
+///
+/// - `user`: Keep the alarms raised by the peers assigned to this user name.  
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<UserList>` object, which includes a success message, the total number of users, and the list of users.  <br>
-/// If no users are found, this function returns a `status::NotFound` error.  <br>
+/// If successful, this function returns a `Json<AuditAlarmsResponse>` object with the total number of matching alarms and the requested page, the most recent first.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if no users are found.
+/// This function will return a `Status::InternalServerError` if the alarms cannot be read from the database.
 ///
 /// # Example
 ///
-/// GET /api/user-list?current=1&pageSize=10&email=test@test.com&name=Test
-#[openapi(tag = "user")]
-#[get(
-    "/api/user-list?<current>&<pageSize>&<email>&<name>",
-    format = "application/json"
-)]
-async fn users(
+/// GET /api/audit/alarms?current=1&pageSize=10&from=2024-05-01
+#[openapi(tag = "audit")]
+#[get("/api/audit/alarms?<current>&<pageSize>&<from>&<to>&<peer>&<user>")]
+#[allow(clippy::too_many_arguments)]
+async fn audit_alarms(
     state: &State<ApiState>,
     _user: AuthenticatedAdmin,
-    current: u32,
-    #[allow(non_snake_case)] pageSize: u32,
-    email: Option<&str>,
-    name: Option<&str>,
-) -> Result<Json<UserList>, status::NotFound<()>> {
-    log::debug!("users");
+    current: Option<u32>,
+    #[allow(non_snake_case)] pageSize: Option<u32>,
+    from: Option<String>,
+    to: Option<String>,
+    peer: Option<String>,
+    user: Option<String>,
+) -> Result<Json<AuditAlarmsResponse>, Status> {
     state.check_maintenance().await;
-
-    let email = if email.is_some() && email.unwrap().is_empty() {
-        None
-    } else {
-        email
-    };
-    let res = state.get_all_users(name, email, current, pageSize).await;
-    if res.is_none() {
-        return Err(status::NotFound::<()>(()));
-    }
-    let response = UserList {
+    let filter = AuditFilter::new(from, to, peer, user, current, pageSize);
+    let (total, data) = state
+        .get_audit_alarms(&filter)
+        .await
+        .ok_or(Status::InternalServerError)?;
+    Ok(Json(AuditAlarmsResponse {
         msg: "success".to_string(),
-        total: res.len() as u32,
-        data: res.unwrap(),
-    };
-
-    Ok(Json(response))
+        total,
+        data,
+    }))
 }
 
-/// # Get the List of Groups
+/// # Get the Admin Audit
 ///
-/// This function is an API endpoint that allows an authenticated admin to retrieve a paginated list of groups.
-/// It is tagged with "group" for OpenAPI documentation.
+/// This function is an API endpoint that allows an authenticated admin to retrieve the operations made by the admins,
+/// e.g. user creations, password resets, group changes or peer deletions.
+/// The passwords and the secrets are redacted from the details of the operations.
+/// It is tagged with "audit" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `current`: The current page number.  
+/// - `current`: The current page number, 1 by default.  
 ///
-/// - `pageSize`: The number of groups per page.  
+/// - `pageSize`: The number of operations per page, 20 by default.  
+///
+/// - `from`: Keep the operations made at or after this date (`YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS`).  
+///
+/// - `to`: Keep the operations made at or before this date.  
+///
+/// - `user`: Keep the operations made by this admin name.  
+///
+/// - `target`: Keep the operations on this user, group, peer... identifier.  
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<GroupsResponse>` object, which includes a success message, the total number of groups, and the list of groups.  <br>
-/// If no groups are found, this function returns a `status::NotFound` error.  <br>
+/// If successful, this function returns a `Json<AuditAdminResponse>` object with the total number of matching operations and the requested page, the most recent first.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if no groups are found.
+/// This function will return a `Status::InternalServerError` if the operations cannot be read from the database.
 ///
 /// # Example
 ///
-/// GET /api/groups?current=1&pageSize=10
-#[openapi(tag = "group")]
-#[get("/api/groups?<current>&<pageSize>", format = "application/json")]
-async fn groups(
+/// GET /api/audit/admin?current=1&pageSize=10&user=admin
+#[openapi(tag = "audit")]
+#[get("/api/audit/admin?<current>&<pageSize>&<from>&<to>&<user>&<target>")]
+#[allow(clippy::too_many_arguments)]
+async fn audit_admin(
     state: &State<ApiState>,
     _user: AuthenticatedAdmin,
-    #[allow(unused_variables)] current: u32,
-    #[allow(non_snake_case, unused_variables)] pageSize: u32,
-) -> Result<Json<GroupsResponse>, status::NotFound<()>> {
-    log::debug!("groups");
+    current: Option<u32>,
+    #[allow(non_snake_case)] pageSize: Option<u32>,
+    from: Option<String>,
+    to: Option<String>,
+    user: Option<String>,
+    target: Option<String>,
+) -> Result<Json<AuditAdminResponse>, Status> {
     state.check_maintenance().await;
-    let offset = if current < 1 { 0 } else { current - 1 };
-    let page_size = if pageSize < 1 {
-        u32::max_value()
-    } else {
-        pageSize
-    };
-    let groups = state.get_groups(offset, page_size).await;
-    if groups.is_none() {
-        return Err(status::NotFound::<()>(()));
-    }
-    let groups = groups.unwrap();
-    let response = GroupsResponse {
+    // the target is filtered as the peer of the other audits
+    let filter = AuditFilter::new(from, to, target, user, current, pageSize);
+    let (total, data) = state
+        .get_admin_audits(&filter)
+        .await
+        .ok_or(Status::InternalServerError)?;
+    Ok(Json(AuditAdminResponse {
         msg: "success".to_string(),
-        total: groups.len() as u32,
-        data: groups,
-    };
-
-    Ok(Json(response))
-}
-
-/// # Get a Group
-/// 
-/// This function is an API endpoint that allows an authenticated admin to retrieve a group.
-/// It is tagged with "group" for OpenAPI documentation.
-/// 
-/// ## Parameters
-/// 
-/// - `guid`: The GUID of the group to retrieve.
-/// 
-/// ## Returns
-/// 
-/// If successful, this function returns a `Json<Group>` object
-/// If the group is not found, this function returns a `status::NotFound` error.
-#[openapi(tag = "group")]
-#[get("/api/group/<guid>", format = "application/json")]
-async fn group_get(
-    state: &State<ApiState>,
-    _user: AuthenticatedAdmin,
-    guid: String,
-) -> Result<Json<utils::Group>, status::NotFound<()>> {
-    log::debug!("group_get");
-    state.check_maintenance().await;
-    let group = state.get_group(guid.as_str()).await;
-    if group.is_none() {
-        return Err(status::NotFound::<()>(()));
-    }
-    Ok(Json(group.unwrap()))
+        total,
+        data,
+    }))
 }
 
-/// # Add a Group
+/// # Export the Audit
 ///
-/// This function is an API endpoint that allows an authenticated admin to add a new group.
-/// It is tagged with "group" for OpenAPI documentation..<br>
-/// Todo allow to use different team
+/// This function is an API endpoint that allows an authenticated admin to export the audit records of a category as a CSV file.
+/// The export is streamed row by row, it accepts the same filters as the audit queries and lists the most recent records first.
+/// The dates are given in RFC 3339 UTC.
+/// It is tagged with "audit" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `request`: The request data, which includes the details of the group to be added.  <br>
+/// - `type`: The category of the records, `conn`, `file`, `alarm`, `login` or `admin`.  <br>
+///
+/// - `from`: Keep the records created at or after this date (`YYYY-MM-DD` or `YYYY-MM-DD HH:MM:SS`).  <br>
+///
+/// - `to`: Keep the records created at or before this date.  <br>
+///
+/// - `peer`: Keep the records of this peer id, the logins have no peer.  <br>
+///
+/// - `user`: Keep the records of the peers assigned to this user name, or the logins of this user.  <br>
+///
+/// - `format`: The format of the export, only `csv` (default) is supported.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<UsersResponse>` object, which includes a success message, the total number of groups, and the list of groups.  <br>
-/// If the admin is not authorized, this function returns a `status::Unauthorized` error.  <br>
+/// If successful, this function returns the CSV file with a header line, the columns depend on the category.  <br>
+/// If the category or the format is unknown, or if more than 1,000,000 records match the filters, this function returns a `Status::BadRequest` error with an explanation.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if the admin is not authorized.
+/// This function will return a `Status::InternalServerError` if the records cannot be counted.
 ///
 /// # Example
 ///
-/// POST /api/group
-/// {"name":"new group","password":"string","confirm-password":"string","email":"string","is_admin":false,"group_name":"string"}
-#[openapi(tag = "group")]
-#[post("/api/group", format = "application/json", data = "<request>")]
-async fn group_add(
+/// GET /api/audit/export?type=conn&from=2024-05-01&to=2024-05-31&format=csv
+#[openapi(tag = "audit")]
+#[get("/api/audit/export?<type>&<from>&<to>&<peer>&<user>&<format>")]
+#[allow(clippy::too_many_arguments)]
+async fn audit_export(
     state: &State<ApiState>,
     _user: AuthenticatedAdmin,
-    request: Json<AddGoupRequest>,
-) -> Result<Json<UsersResponse>, status::Unauthorized<()>> {
-    log::debug!("create_group");
+    r#type: String,
+    from: Option<String>,
+    to: Option<String>,
+    peer: Option<String>,
+    user: Option<String>,
+    format: Option<String>,
+) -> Result<DownloadStream<Pin<Box<dyn Stream<Item = String> + Send + '_>>>, status::Custom<String>> {
+    log::debug!("audit_export");
     state.check_maintenance().await;
 
-    let request = request.into_inner();
-    let _res = state
-        .create_group(request.name.as_str(), "Default", request.note.as_str()) // Todo allow to use different team
-        .await;
-    let response = UsersResponse {
-        msg: "success".to_string(),
-        total: 1,
-        data: "[{}]".to_string(),
-    };
-
-    Ok(Json(response))
+    let bad_request = |message: String| status::Custom(Status::BadRequest, message);
+    let category = AuditCategory::parse(&r#type)
+        .ok_or_else(|| bad_request(format!("Unknown audit type {}", r#type)))?;
+    if PeerExportFormat::parse(format.as_deref()) != Some(PeerExportFormat::Csv) {
+        return Err(bad_request("Only the csv format is supported".to_string()));
+    }
+    let filter = AuditFilter::new(from, to, peer, user, None, Some(AUDIT_EXPORT_MAX_ROWS));
+    let total = state
+        .count_audit(category, &filter)
+        .await
+        .ok_or_else(|| status::Custom(Status::InternalServerError, "Failed to count the records".to_string()))?;
+    if total > AUDIT_EXPORT_MAX_ROWS {
+        return Err(bad_request(format!(
+            "{} records match, the export is limited to {}, please narrow the date range",
+            total, AUDIT_EXPORT_MAX_ROWS
+        )));
+    }
+    let records = state.export_audit(category, filter);
+    let stream: Pin<Box<dyn Stream<Item = String> + Send>> = Box::pin(stream! {
+        yield category.csv_header().to_string();
+        for await record in records {
+            yield record.csv_record();
+        }
+    });
+    Ok(DownloadStream {
+        content_type: ContentType::CSV,
+        filename: format!("audit-{}.csv", category.as_str()),
+        stream,
+    })
 }
 
-/// # Update a group
+/// # Get the Audit Retention
 ///
-/// This function is an API endpoint that allows an authenticated admin to update a group.<br>
-/// Todo allow to use different team
+/// This function is an API endpoint that allows an authenticated admin to retrieve the number of days
+/// the audit records of each category (connections, file transfers, alarms and logins) are kept.
+/// It is tagged with "audit" for OpenAPI documentation.
 ///
-/// ## Parameters
+/// ## Returns
 ///
-/// - `guid`: The request data, which includes the details of the group to be updated.  <br>
+/// If successful, this function returns a `Json<AuditRetention>` object.  <br>
 ///
-/// ## Returns
+/// # Example
 ///
-/// If successful, this function returns a `Json<UsersResponse>` object, which includes a success message, the total number of groups, and the list of groups.  <br>
-#[openapi(tag = "group")]
-#[put("/api/group", format = "application/json", data = "<request>")]
-async fn group_update(
+/// GET /api/audit/retention
+#[openapi(tag = "audit")]
+#[get("/api/audit/retention")]
+async fn audit_retention(
     state: &State<ApiState>,
     _user: AuthenticatedAdmin,
-    request: Json<UpdateGoupRequest>,
-) -> Result<Json<UsersResponse>, status::Unauthorized<()>> {
-    log::debug!("update_group");
+) -> Result<Json<AuditRetention>, Status> {
     state.check_maintenance().await;
-
-    let request = request.into_inner();
-    let _res = state
-        .update_group(
-            request.guid.as_str(),
-            request.name.as_str(),
-            "Default", // Todo allow to use different team
-            request.note.as_str(),
-        )
-        .await;
-    let response = UsersResponse {
-        msg: "success".to_string(),
-        total: 1,
-        data: "[{}]".to_string(),
-    };
-
-    Ok(Json(response))
+    Ok(Json(state.get_audit_retention().await))
 }
 
-/// # Delete a group
-/// 
-/// This function is an API endpoint that allows an authenticated admin to delete a group.
-/// It is tagged with "group" for OpenAPI documentation.
-/// 
+/// # Update the Audit Retention
+///
+/// This function is an API endpoint that allows an authenticated admin to change the number of days
+/// the audit records of each category are kept.
+/// The values are stored in the database and applied by the next hourly purge.
+/// It is tagged with "audit" for OpenAPI documentation.
+///
 /// ## Parameters
-/// 
-/// - `guid`: The GUID of the group to retrieve.
-/// 
+///
+/// - `request`: The retention of each category in days, between 0 and 3650, 0 keeps the records forever.  <br>
+///
 /// ## Returns
-/// 
-#[openapi(tag = "group")]
-#[delete("/api/group/<guid>", format = "application/json", data = "<request>")]
-async fn group_delete(
+///
+/// If successful, this function returns an `ActionResponse::Empty` object.  <br>
+/// If a retention is out of range, this function returns an `ActionResponse::Error` object.  <br>
+///
+/// ## Errors
+///
+/// This function will return a `Status::InternalServerError` if the retention cannot be saved.
+///
+/// # Example
+///
+/// PUT /api/audit/retention
+/// Content-Type: application/json
+///
+/// {"conn_days": 90, "file_days": 90, "alarm_days": 365, "login_days": 30, "admin_days": 365}
+#[openapi(tag = "audit")]
+#[put("/api/audit/retention", format = "application/json", data = "<request>")]
+async fn audit_retention_update(
     state: &State<ApiState>,
-    _user: AuthenticatedAdmin,
-    request: Json<Vec<String>>,
-    guid: &str,
-) -> Result<(), status::Unauthorized<()>> {
-    log::debug!("group_delete");
+    user: AuthenticatedAdmin,
+    request: Json<AuditRetention>,
+) -> Result<ActionResponse, Status> {
     state.check_maintenance().await;
-    let _res = state.delete_group(guid).await;
-    Ok(())
+    if let Err(e) = request.validate() {
+        return Ok(ActionResponse::Error(e));
+    }
+    state
+        .set_audit_retention(&request.0)
+        .await
+        .ok_or(Status::InternalServerError)?;
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::AuditRetentionUpdate,
+            None,
+            serde_json::json!(request.0),
+        )
+        .await;
+    Ok(ActionResponse::Empty)
 }
 
-/// # Get Peers
+fn record_audit(state: &ApiState, webhook: &AuditWebhook, typ: Option<&str>, request: AuditRequest) {
+    let event = match utils::audit::parse_audit(typ, &request) {
+        Some(event) => event,
+        None => {
+            log::warn!("audit: ignoring unknown record {:?} {:?}", typ, request);
+            return;
+        }
+    };
+    if let AuditEvent::Alarm(alarm) = &event {
+        webhook.notify(alarm);
+    }
+    let info = serde_json::to_string(&request).unwrap_or_default();
+    state.record_client_audit(event, info);
+}
+
+/// # Log the User Out
 ///
-/// This function is an API endpoint that retrieves the list of all peers in the network.
-/// It is tagged with "peer" for OpenAPI documentation.
+/// This function is an API endpoint that allows an authenticated user to log out.
+/// It is tagged with "login" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - none
+/// - `request`: The request data, which includes the current user information.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<PeersResponse>` object, which includes a success message, the total number of peers, and the list of peers.  <br>
-/// If no peers are found, this function returns a `status::NotFound` error.  <br>
+/// If successful, this function returns a `Json<LogoutReply>` object, which includes a success message.  <br>
+/// If the user is not authorized, this function returns a `status::Unauthorized` error.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if no peers are found.
-///
-/// # Example
+/// This function will return an error if the system is in maintenance mode, or if the user is not authorized.
 ///
-/// GET /api/peers
-#[openapi(tag = "peer")]
-#[get("/api/peers", format = "application/json")]
-async fn peers(
+#[openapi(tag = "login")]
+#[post("/api/logout", format = "application/json", data = "<request>")]
+async fn logout(
     state: &State<ApiState>,
-    _user: AuthenticatedUser,
-) -> Result<Json<PeersResponse>, status::NotFound<()>> {
-    log::debug!("peers");
+    user: AuthenticatedUser,
+    request: Json<CurrentUserRequest>,
+) -> Result<Json<LogoutReply>, status::Unauthorized<()>> {
+    log::debug!("logout: {:?}", request);
+
+    let _ = unwrap_or_return!(state
+        .user_logout(&user.info)
+        .await
+        .ok_or(Err(status::Unauthorized::<()>(()))));
+
+    let reply = LogoutReply {
+        data: String::new(),
+    };
+
     state.check_maintenance().await;
-    let peers = state.get_all_peers().await;
 
-    if peers.is_none() {
-        return Err(status::NotFound::<()>(()));
-    }
-    Ok(Json(PeersResponse {
-        msg: "success".to_string(),
-        total: peers.len() as u32,
-        data: peers.unwrap(),
-    }))
+    Ok(Json(reply))
 }
 
-/// # Count Peers per platform
+/// # Heartbeat
 ///
-/// This function is an API endpoint that retrieves the count of peers per platform.
+/// This function is an API endpoint that is frequently hit by the client at the /api/heartbeat endpoint.
+/// It updates the `last_online` field of the peer.
+/// If the client sends a user token and the peer is not assigned yet, the peer is assigned to this user.
 /// It is tagged with "peer" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `platform`: The platform to filter the peers by (windows, macos, linux, android or all). <br>
+/// - `request`: The request data, which includes the heartbeat information.  
+///
+/// - `user`: The authenticated user (optional).  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<PeersCountResponse>` object, which includes the total number of peers for the specified platform.  <br>
+/// This function returns the status "OK" as a `String`.  <br>
+/// If the id is registered with another uuid (e.g. a cloned machine), the peer is left untouched
+/// and the status is "UUID_MISMATCH".  <br>
+/// If the id is not a valid peer id, the status is "INVALID_ID".  <br>
+/// If the client is older than the minimum client version, the heartbeat is ignored, the status is "CLIENT_TOO_OLD"
+/// and the `upgrade_required` directive gives the message and the download page. The version is the one of the
+/// `X-Client-Version` header, or else the one of the last system info of the peer.  <br>
+/// The spaces of the id are removed, "123 456 789" and "123456789" are the same peer.  <br>
+/// When the server has directives for the client, or when the client sends `Accept: application/json`,
+/// the answer is a `HeartbeatResponse` JSON object with the status and the directives:  <br>
+/// - `relogin` when the client sent a token which is no longer valid,  <br>
+/// - `config_changed` with the settings of the effective strategy of the peer when its version differs from the `modified_at` sent by the client,
+/// only for the clients sending `Accept: application/json` since the stock clients do not send the version back,  <br>
+/// - `maintenance_soon` when an admin announced a maintenance with `/api/admin/maintenance`.  <br>
+///
+/// The heartbeats of a peer sent sooner than `HEARTBEAT_MIN_INTERVAL` seconds after the last recorded one
+/// are answered "OK" without touching the database, with a `slow_down` directive giving the interval.
+/// The uuid and the client version are checked first, a rejected heartbeat is never throttled.
+///
+/// ## Errors
 ///
+/// This function will return an error if the system is in maintenance mode.
 #[openapi(tag = "peer")]
-#[get("/api/peers/count/<platform>", format = "application/json")]
-async fn peers_count(
+#[post("/api/heartbeat", format = "application/json", data = "<request>")]
+async fn heartbeat(
     state: &State<ApiState>,
-    _user: AuthenticatedUser,
-    platform: String,
-) -> Result<Json<PeersCountResponse>, status::NotFound<()>> {
-    let count = match platform.to_lowercase().as_str() {
-        "windows" => {
-            let count = state.get_peers_count(Platform::Windows).await;
-            log::debug!("peers_count: {:?}", count);
-            count
-        }
-        "mac" | "macos" => {
-            let count = state.get_peers_count(Platform::MacOS).await;
-            log::debug!("peers_count: {:?}", count);
-            count
-        }
-        "linux" => {
-            let count = state.get_peers_count(Platform::Linux).await;
-            log::debug!("peers_count: {:?}", count);
-            count
-        }
-        "android" => {
-            let count = state.get_peers_count(Platform::Android).await;
-            log::debug!("peers_count: {:?}", count);
-            count
-        }
-        "all" => {
-            let count = state.get_peers_count(Platform::All).await;
-            log::debug!("peers_count: {:?}", count);
-            count
+    user: Option<AuthenticatedUser>,
+    default_versions: &State<DefaultClientVersions>,
+    extended_request: ExtendedRequest,
+    request: Json<HeartbeatRequest>,
+) -> HeartbeatReply {
+    log::debug!("heartbeat: {:?}", request);
+    state.check_maintenance().await;
+    let reply = |status: &str| {
+        HeartbeatReply(HeartbeatResponse {
+            status: status.to_string(),
+            directives: Vec::new(),
+        })
+    };
+    let mut heartbeat = request.0;
+    heartbeat.id = match normalize_peer_id(&heartbeat.id) {
+        Ok(id) => id,
+        Err(e) => {
+            log::debug!("heartbeat: {}", e);
+            return reply("INVALID_ID");
         }
-        _ => 0,
     };
-    return Ok(Json(PeersCountResponse { total: count }));
+    let token_rejected = user.is_none() && extended_request.headers.contains_key("authorization");
+    if !state.check_peer_uuid(&heartbeat.id, &heartbeat.uuid).await {
+        return reply("UUID_MISMATCH");
+    }
+    if let Some(upgrade) =
+        check_client_version(state, default_versions, &extended_request, Some(&heartbeat.id)).await
+    {
+        return HeartbeatReply(HeartbeatResponse {
+            status: "CLIENT_TOO_OLD".to_string(),
+            directives: vec![HeartbeatDirective::UpgradeRequired(upgrade)],
+        });
+    }
+    if let Some(interval) = state.throttle_heartbeat(&heartbeat.id, &heartbeat.uuid).await {
+        log::debug!("heartbeat: {} throttled", heartbeat.id);
+        let mut directives = Vec::new();
+        if token_rejected {
+            directives.push(HeartbeatDirective::Relogin);
+        }
+        directives.push(HeartbeatDirective::SlowDown { interval });
+        return HeartbeatReply(HeartbeatResponse {
+            status: "OK".to_string(),
+            directives,
+        });
+    }
+    // the stock clients do not send back the version of the configuration, it would be pushed at every heartbeat
+    let modified_at = extended_request.accepts_json().then_some(heartbeat.modified_at);
+    let id = heartbeat.id.clone();
+    let res = state
+        .update_heartbeat(heartbeat, user.map(|user| user.info.user_id))
+        .await;
+    log::debug!("res: {:?}", res);
+    HeartbeatReply(HeartbeatResponse {
+        status: "OK".to_string(),
+        directives: state.heartbeat_directives(&id, modified_at, token_rejected).await,
+    })
 }
 
-/// # Get the List of cpus used by the peers
+/// # Set the System Info
 ///
-/// This function is an API endpoint that retrieves the count of cpus used by the peers.
+/// This function is an API endpoint that allows a connected client to update its system information.
 /// It is tagged with "peer" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
+/// - `request`: The request data, which includes the system information.  
+///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<Vec<CpuCount>>` object, which includes the total number of cpus used by the peers.  <br>
-#[openapi(tag = "peer")]
-#[get("/api/peers/cpus", format = "application/json")]
-async fn peers_cpus(
-    state: &State<ApiState>,
-    _user: AuthenticatedUser,
-) -> Result<Json<Vec<CpuCount>>, status::NotFound<()>> {
-    let cpus = state.get_cpus_count().await;
-    return Ok(Json(cpus));
+/// If successful, this function returns a `String` with the message "SYSINFO_UPDATED".  <br>
+/// If the system info is not found, this function returns a `String` with the message "ID_NOT_FOUND".  <br>
+/// If the id is registered with another uuid (e.g. a cloned machine), this function returns a `String` with the message "UUID_MISMATCH".  <br>
+/// If the id is not a valid peer id, this function returns a `String` with the message "INVALID_ID".  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, or if the system info is not found.
+///
+#[openapi(tag = "peer")]
+#[post("/api/sysinfo", format = "application/json", data = "<request>")]
+async fn sysinfo(state: &State<ApiState>, request: Json<utils::SystemInfo>) -> String {
+    let mut sysinfo = request.0;
+    if let Some(id) = sysinfo.id.as_deref() {
+        match normalize_peer_id(id) {
+            Ok(id) => sysinfo.id = Some(id),
+            Err(e) => {
+                log::debug!("sysinfo: {}", e);
+                return "INVALID_ID".to_string();
+            }
+        }
+    }
+    if let (Some(id), Some(uuid)) = (sysinfo.id.as_deref(), sysinfo.uuid.as_deref()) {
+        if !state.check_peer_uuid(id, uuid).await {
+            return "UUID_MISMATCH".to_string();
+        }
+    }
+    let res = state.update_systeminfo(sysinfo).await;
+
+    if res.is_none() {
+        return "ID_NOT_FOUND".to_string();
+    } else {
+        return "SYSINFO_UPDATED".to_string();
+    }
 }
 
-/// # Login Options
+/// # Get the List of Users
 ///
-/// This is called by the client for knowing the Oauth2 provider(s) available
-/// You must provide a list of Oauth2 providers in the `oauth2.toml` config file
-/// The config file can be overridden by the `OAUTH2_CONFIG_FILE` environment variable
+/// This function is an API endpoint that allows an authenticated admin to retrieve a paginated list of users.
+/// It is tagged with "user" for OpenAPI documentation.
 ///
-/// This function is an API endpoint that is called by the client to get the list of available OAuth2 providers.
-/// The list of providers is defined in the `oauth2.toml` config file, which can be overridden by the `OAUTH2_CONFIG_FILE` environment variable.
-/// It is tagged with "login" for OpenAPI documentation.
+/// ## Parameters
 ///
-/// ## Limitations
+/// - `current`: The current page number, 1 by default, ignored when `after` is given.  
 ///
-/// It needs to be completed for mapping the username and email from the OAuth2 provider to the SCTGDesk user.
+/// - `pageSize`: The number of users per page, `DEFAULT_PAGE_SIZE` (20) for 0, at most `MAX_PAGE_SIZE` (1000).  
 ///
-/// ## Parameters
+/// - `email`: The email to filter the users by.  
 ///
-/// - none
+/// - `name`: The name to filter the users by.  
+///
+/// - `after`: The cursor returned in `next_cursor` by the previous page, empty for the first page.
+///   The users are then paged by creation date, a user created during the traversal is listed at its end.  
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<Vec<String>>` object, which includes the list of available OAuth2 providers.  <br>
-/// If the config file is not found or cannot be read, this function returns a `status::Unauthorized` error.  <br>
+/// If successful, this function returns a `Json<UserList>` object, which includes a success message, the total number of users, the list of users and the page size used in `page_size`.  <br>
+/// When paging with `after`, `next_cursor` is the cursor of the next page, it is missing on the last page.  <br>
+/// If no users are found, this function returns a `Status::NotFound` error.  <br>
+/// If the cursor is not valid, this function returns a `Status::BadRequest` error.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if the config file is not found or cannot be read.
+/// This function will return an error if the system is in maintenance mode, or if no users are found.
 ///
 /// # Example
 ///
-/// GET /api/login-options
-#[openapi(tag = "login")]
-#[get("/api/login-options", format = "application/json")]
-async fn login_options(
+/// GET /api/user-list?current=1&pageSize=10&email=test@test.com&name=Test
+///
+/// GET /api/user-list?pageSize=10&after=
+#[openapi(tag = "user")]
+#[get(
+    "/api/user-list?<current>&<pageSize>&<email>&<name>&<after>",
+    format = "application/json"
+)]
+async fn users(
     state: &State<ApiState>,
-) -> Result<Json<Vec<String>>, status::Unauthorized<()>> {
-    let mut providers: Vec<String> = Vec::new();
-    let providers_config = state
-        .get_oauth2_config(oauth2::get_providers_config_file().as_str())
-        .await;
-    if providers_config.is_none() {
-        return Err(status::Unauthorized::<()>(()));
+    _user: AuthenticatedAdmin,
+    current: Option<u32>,
+    #[allow(non_snake_case)] pageSize: u32,
+    email: Option<&str>,
+    name: Option<&str>,
+    after: Option<&str>,
+) -> Result<Json<UserList>, Status> {
+    log::debug!("users");
+    state.check_maintenance().await;
+    let page_size = effective_page_size(pageSize);
+
+    let email = if email.is_some() && email.unwrap().is_empty() {
+        None
+    } else {
+        email
+    };
+    if let Some(after) = after {
+        let after = parse_after(after).map_err(|_| Status::BadRequest)?;
+        let (users, next) = state
+            .get_users_page(name, email, after.as_ref(), page_size)
+            .await
+            .ok_or(Status::NotFound)?;
+        return Ok(Json(UserList {
+            msg: "success".to_string(),
+            total: users.len() as u32,
+            data: users,
+            next_cursor: next.map(|cursor| cursor.encode()),
+            page_size,
+        }));
     }
-    for p in providers_config.unwrap() {
-        providers.push(p.op_auth_string);
+    let res = state.get_all_users(name, email, None, current.unwrap_or(1), page_size).await;
+    if res.is_none() {
+        return Err(Status::NotFound);
     }
-    Ok(Json(providers))
+    let response = UserList {
+        msg: "success".to_string(),
+        total: res.len() as u32,
+        data: res.unwrap(),
+        next_cursor: None,
+        page_size,
+    };
+
+    Ok(Json(response))
 }
 
-/// OIDC Auth request
-///
-/// This entrypoint is called by the client for getting the authorization url for the Oauth2 provider he chooses
-///
-/// For testing you can generate a valid uuid field with the following command: `uuidgen | base64`
-/// # OIDC Auth Request
+/// # Get the List of Groups
 ///
-/// This function is an API endpoint that is called by the client to get the authorization URL for the chosen OAuth2 provider.
-/// It is tagged with "login" for OpenAPI documentation.
+/// This function is an API endpoint that allows an authenticated admin to retrieve a paginated list of groups.
+/// It is tagged with "group" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `request`: The request data, which includes the chosen OAuth2 provider and a UUID.  <br> For testing you can generate a valid uuid field with the following command: `uuidgen | base64`
+/// - `current`: The current page number.  
+///
+/// - `pageSize`: The number of groups per page.  
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<OidcAuthUrl>` object, which includes the authorization URL and a session code.  <br>
-/// If the UUID is invalid or the OAuth2 provider is not found, this function returns an `OidcAuthUrl` object with an empty URL and an error code.  <br>
+/// If successful, this function returns a `Json<GroupsResponse>` object, which includes a success message, the total number of groups, and the list of groups.  <br>
+/// If no groups are found, this function returns a `status::NotFound` error.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if the UUID is invalid or the OAuth2 provider is not found.
+/// This function will return an error if the system is in maintenance mode, or if no groups are found.
 ///
 /// # Example
 ///
-/// POST /api/oidc/auth
-/// {
-///     "op": "github",
-///     "uuid": "generated_uuid_base64_encoded"
-/// }
-#[openapi(tag = "login")]
-#[post("/api/oidc/auth", format = "application/json", data = "<request>")]
-async fn oidc_auth(
+/// GET /api/groups?current=1&pageSize=10
+#[openapi(tag = "group")]
+#[get("/api/groups?<current>&<pageSize>", format = "application/json")]
+async fn groups(
     state: &State<ApiState>,
-    request: ExtendedJson<OidcAuthRequest>,
-) -> Json<OidcAuthUrl> {
-    log::debug!("oidc_auth: {:?}", request);
-    let headers = request.headers();
-    log::debug!("headers: {:?}", headers);
-    let request = request.data;
-
-    let uuid_code = Uuid::new_v4().to_string();
-    let uuid_decoded = BASE64_STANDARD.decode(request.uuid.clone());
-    if uuid_decoded.is_err() {
-        return Json(OidcAuthUrl {
-            url: "".to_string(),
-            code: "UUID_ERROR".to_string(),
-        });
-    }
-    let uuid_decoded = uuid_decoded.unwrap();
-    let uuid_client = String::from_utf8(uuid_decoded).unwrap();
-    let callback_url = format!("{}/api/oidc/callback", get_host(headers.clone()));
-    let providers_config = state
-        .get_oauth2_config(oauth2::get_providers_config_file().as_str())
-        .await;
-    if providers_config.is_none() {
-        return Json(OidcAuthUrl {
-            url: "".to_string(),
-            code: "".to_string(),
-        });
-    }
-    let providers_config = providers_config.unwrap();
-    let provider_config = providers_config
-        .iter()
-        .find(|config| config.op == request.op);
-
-    if provider_config.is_none() {
-        return Json(OidcAuthUrl {
-            url: "".to_string(),
-            code: "".to_string(),
-        });
+    _user: AuthenticatedAdmin,
+    #[allow(unused_variables)] current: u32,
+    #[allow(non_snake_case, unused_variables)] pageSize: u32,
+) -> Result<Json<GroupsResponse>, status::NotFound<()>> {
+    log::debug!("groups");
+    state.check_maintenance().await;
+    let offset = if current < 1 { 0 } else { current - 1 };
+    let page_size = if pageSize < 1 {
+        u32::max_value()
+    } else {
+        pageSize
+    };
+    let groups = state.get_groups(offset, page_size).await;
+    if groups.is_none() {
+        return Err(status::NotFound::<()>(()));
     }
-    let provider_config = provider_config.unwrap();
-    let provider_trait_object: Arc<dyn OAuthProvider> = {
-        match provider_config.provider {
-            oauth2::Provider::Github => Arc::new(oauth2::github_provider::GithubProvider::new()),
-            oauth2::Provider::Gitlab => todo!(),
-            oauth2::Provider::Google => todo!(),
-            oauth2::Provider::Apple => todo!(),
-            oauth2::Provider::Okta => todo!(),
-            oauth2::Provider::Facebook => todo!(),
-            oauth2::Provider::Azure => todo!(),
-            oauth2::Provider::Auth0 => todo!(),
-            oauth2::Provider::Dex => Arc::new(oauth2::dex_provider::DexProvider::new()),
-        }
+    let groups = groups.unwrap();
+    let response = GroupsResponse {
+        msg: "success".to_string(),
+        total: groups.len() as u32,
+        data: groups,
     };
 
-    let redirect_url =
-        provider_trait_object.get_redirect_url(callback_url.as_str(), uuid_code.as_str());
-    let _oidc_session = state
-        .insert_oidc_session(
-            uuid_code.clone(),
-            OidcState {
-                id: request.id.clone(),
-                uuid: uuid_client,
-                code: None,
-                auth_token: None,
-                redirect_url: Some(redirect_url.clone()),
-                callback_url: Some(callback_url),
-                provider: Some(provider_trait_object),
-                name: None,
-                email: None,
-            },
-        )
-        .await;
-    log::debug!("uuid_code: {:?}", uuid_code);
+    Ok(Json(response))
+}
 
-    Json(OidcAuthUrl {
-        url: redirect_url.clone(),
-        code: uuid_code,
-    })
+/// # Get a Group
+/// 
+/// This function is an API endpoint that allows an authenticated admin to retrieve a group.
+/// It is tagged with "group" for OpenAPI documentation.
+/// 
+/// ## Parameters
+/// 
+/// - `guid`: The GUID of the group to retrieve.
+/// 
+/// ## Returns
+/// 
+/// If successful, this function returns a `Json<Group>` object, which includes the guid of the strategy applied to the members of the group.
+/// If the group is not found, this function returns a `status::NotFound` error.
+#[openapi(tag = "group")]
+#[get("/api/group/<guid>", format = "application/json")]
+async fn group_get(
+    state: &State<ApiState>,
+    _user: AuthenticatedAdmin,
+    guid: String,
+) -> Result<Json<utils::Group>, status::NotFound<()>> {
+    log::debug!("group_get");
+    state.check_maintenance().await;
+    let group = state.get_group(guid.as_str()).await;
+    if group.is_none() {
+        return Err(status::NotFound::<()>(()));
+    }
+    Ok(Json(group.unwrap()))
 }
 
-/// # OIDC Auth Callback
+/// # Add a Group
 ///
-/// This function is an API endpoint that serves as the OAuth2 callback.
-/// It exchanges the authorization code for an access token and stores it in the state.
-/// It is tagged with "login" for OpenAPI documentation.
+/// This function is an API endpoint that allows an authenticated admin to add a new group.
+/// It is tagged with "group" for OpenAPI documentation..<br>
+/// Todo allow to use different team
 ///
 /// ## Parameters
 ///
-/// - `code`: The authorization code received from the OIDC provider.  
-///
-/// - `state`: The state parameter received from the OIDC provider. This is the session code.  
+/// - `request`: The request data, which includes the details of the group to be added and the optional guid of the strategy applied to its members.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns "OK".  <br>
-/// If the session does not exist or the code exchange fails, this function returns "ERROR".  <br>
+/// If successful, this function returns a `Json<UsersResponse>` object, which includes a success message, the total number of groups, and the list of groups.  <br>
+/// If the strategy does not exist, this function returns a `Status::NotFound` error.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if the session does not exist or the code exchange fails.
+/// This function will return an error if the system is in maintenance mode, or if the admin is not authorized.
 ///
 /// # Example
 ///
-/// GET /api/oidc/callback?code=authorization_code&state=session_code
-#[openapi(tag = "login")]
-#[get("/api/oidc/callback?<code>&<state>")]
-async fn oidc_callback(apistate: &State<ApiState>, code: &str, state: &str) -> String {
-    let oidc_code = state; // this is the session code
-    let oidc_authorization_code = code;
-    let updated_oidc_session = apistate
-        .oidc_session_exchange_code(oidc_authorization_code.to_string(), oidc_code.to_string())
+/// POST /api/group
+/// {"name":"Finance","note":"","allowed_outgoings":[],"allowed_incomings":[],"strategy":"018f2556-2316-7a02-b31c-5599e7cd5b5e"}
+#[openapi(tag = "group")]
+#[post("/api/group", format = "application/json", data = "<request>")]
+async fn group_add(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    request: Json<AddGoupRequest>,
+) -> Result<Json<UsersResponse>, Status> {
+    log::debug!("create_group");
+    state.check_maintenance().await;
+
+    let request = request.into_inner();
+    if let Some(strategy) = request.strategy.as_deref() {
+        state.get_strategy(strategy).await.ok_or(Status::NotFound)?;
+    }
+    let res = state
+        .create_group(
+            request.name.as_str(),
+            "Default", // Todo allow to use different team
+            request.note.as_str(),
+            request.strategy.as_deref(),
+        )
         .await;
-    if updated_oidc_session.is_none() {
-        return "ERROR".to_string();
+    if res.is_some() {
+        state
+            .audit_admin_action(
+                &user.info.user_id,
+                AdminAction::GroupCreate,
+                Some(request.name.as_str()),
+                serde_json::json!(request),
+            )
+            .await;
     }
-    "OK".to_string()
+    let response = UsersResponse {
+        msg: "success".to_string(),
+        total: 1,
+        data: "[{}]".to_string(),
+    };
+
+    Ok(Json(response))
 }
 
-/// # OIDC State
+/// # Update a group
 ///
-/// This function is an API endpoint that checks the state of an OpenID Connect (OIDC) session.
-/// It is tagged with "login" for OpenAPI documentation.
+/// This function is an API endpoint that allows an authenticated admin to update a group.<br>
+/// Todo allow to use different team
 ///
 /// ## Parameters
 ///
-/// - `code`: The authorization code received from the OIDC provider.  
-///
-/// - `id`: The identifier of the OIDC session.  
-///
-/// - `uuid`: The UUID of the OIDC session.  
+/// - `guid`: The request data, which includes the details of the group to be updated.  <br>
+/// The strategy applied to the members is replaced when `strategy` is set, `PUT /api/group/<guid>/strategy` removes it.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<Option<OidcResponse>>` object.  <br>
-/// If the session does not exist, this function returns `Json(None)`.  <br>
-///
-/// ## Errors
-///
-/// This function will return an error if the system is in maintenance mode, or if the session does not exist.
-///
-/// # Example
-///
-/// GET /api/oidc/auth-query?code=authorization_code&id=session_id&uuid=session_uuid
-#[openapi(tag = "login")]
-#[get("/api/oidc/auth-query?<code>&<id>&<uuid>")]
-async fn oidc_state(
+/// If successful, this function returns a `Json<UsersResponse>` object, which includes a success message, the total number of groups, and the list of groups.  <br>
+/// If the strategy does not exist, this function returns a `Status::NotFound` error.  <br>
+#[openapi(tag = "group")]
+#[put("/api/group", format = "application/json", data = "<request>")]
+async fn group_update(
     state: &State<ApiState>,
-    code: &str,
-    id: &str,
-    uuid: &str,
-) -> Json<Option<OidcResponse>> {
-    log::debug!("oidc_state: {:?} {:?} {:?}", code, id, uuid);
-
-    let res = state.oidc_check_session(code.to_string()).await;
+    user: AuthenticatedAdmin,
+    request: Json<UpdateGoupRequest>,
+) -> Result<Json<UsersResponse>, Status> {
+    log::debug!("update_group");
+    state.check_maintenance().await;
 
-    if res.is_none() {
-        return Json(None);
+    let request = request.into_inner();
+    if let Some(strategy) = request.strategy.as_deref() {
+        state.get_strategy(strategy).await.ok_or(Status::NotFound)?;
     }
-
-    let (token, username, userinfo) = res.unwrap();
-    let auth_response = OidcResponse {
-        access_token: token.to_base64(),
-        type_field: "access_token".to_string(),
-        tfa_type: "".to_string(),
-        secret: "".to_string(),
-        user: OidcUser {
-            name: username,
-            email: "".to_string(),
-            note: "".to_string(),
-            status: OidcUserStatus::Normal.into(),
-            info: OidcUserInfo {
-                email_verification: false,
-                email_alarm_notification: false,
-                login_device_whitelist: Vec::<String>::new(),
-                other: HashMap::<String, String>::new(),
-            },
-            is_admin: userinfo.admin,
-            third_auth_type: "Oauth2".to_string(),
-        },
+    let res = state
+        .update_group(
+            request.guid.as_str(),
+            request.name.as_str(),
+            "Default", // Todo allow to use different team
+            request.note.as_str(),
+        )
+        .await;
+    let res = match (res, request.strategy.as_deref()) {
+        (Some(()), Some(strategy)) => {
+            state
+                .assign_group_strategy(request.guid.as_str(), Some(strategy))
+                .await
+        }
+        (res, _) => res,
+    };
+    if res.is_some() {
+        state
+            .audit_admin_action(
+                &user.info.user_id,
+                AdminAction::GroupUpdate,
+                Some(request.guid.as_str()),
+                serde_json::json!(request),
+            )
+            .await;
+    }
+    let response = UsersResponse {
+        msg: "success".to_string(),
+        total: 1,
+        data: "[{}]".to_string(),
     };
 
-    Json(Some(auth_response))
+    Ok(Json(response))
 }
 
-/// # Get Personal Address Book
+/// # Delete a group
+/// 
+/// This function is an API endpoint that allows an authenticated admin to delete a group.
+/// It is tagged with "group" for OpenAPI documentation.
+/// 
+/// ## Parameters
+/// 
+/// - `guid`: The GUID of the group to retrieve.
+/// 
+/// - `dry_run`: If true, the group is not deleted and nothing is audited.
+/// 
+/// ## Returns
+/// 
+/// If successful, this function returns an `ActionResponse::Empty` object.
+/// With `dry_run` it returns an `ActionResponse::DryRun` object listing the group if it exists.
+#[openapi(tag = "group")]
+#[delete("/api/group/<guid>?<dry_run>", format = "application/json", data = "<request>")]
+async fn group_delete(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    request: Json<Vec<String>>,
+    guid: &str,
+    dry_run: Option<bool>,
+) -> Result<ActionResponse, status::Unauthorized<()>> {
+    log::debug!("group_delete");
+    state.check_maintenance().await;
+    if dry_run.unwrap_or(false) {
+        let groups = state.get_group(guid).await.into_iter().map(|group| group.guid).collect();
+        return Ok(ActionResponse::DryRun(DryRunResponse::new(groups, serde_json::json!({}))));
+    }
+    let res = state.delete_group(guid).await;
+    if res.is_some() {
+        state
+            .audit_admin_action(
+                &user.info.user_id,
+                AdminAction::GroupDelete,
+                Some(guid),
+                serde_json::json!({}),
+            )
+            .await;
+    }
+    Ok(ActionResponse::Empty)
+}
+
+/// # Get Peers
 ///
-/// This function is an API endpoint that retrieves the personal address book of the authenticated user.
-/// It is tagged with "address book" for OpenAPI documentation.
+/// This function is an API endpoint that retrieves the list of all peers in the network.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - none
+/// - `user`: Optional filter on the assigned user, `me` for the peers of the current user or a user guid (admin only).  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<AbPersonal>` object.  <br>
-/// If the user is not authorized to access their personal address book, this function returns a `status::Unauthorized` error.  <br>
+/// If successful, this function returns a `Json<PeersResponse>` object, which includes a success message, the total number of peers, and the list of peers.  <br>
+/// If no peers are found, this function returns a `Status::NotFound` error.  <br>
+/// If a non admin user asks for the peers of another user, this function returns a `Status::Forbidden` error.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if the user is not authorized to access their personal address book.
+/// This function will return an error if the system is in maintenance mode, or if no peers are found.
 ///
 /// # Example
 ///
-/// POST /api/ab/personal
-#[openapi(tag = "address book")]
-#[post("/api/ab/personal")]
-async fn ab_personal(
+/// GET /api/peers?user=me
+#[openapi(tag = "peer")]
+#[get("/api/peers?<user>", format = "application/json")]
+async fn peers(
     state: &State<ApiState>,
-    user: AuthenticatedUser,
-) -> Result<Json<AbPersonal>, status::Unauthorized<()>> {
+    authenticated_user: AuthenticatedUser,
+    user: Option<&str>,
+) -> Result<Json<PeersResponse>, Status> {
+    log::debug!("peers");
     state.check_maintenance().await;
-    let guid = state.get_ab_personal_guid(user.info.user_id.clone()).await;
-    if guid.is_none() {
-        return Err(status::Unauthorized::<()>(()));
-    }
-    let guid = guid.unwrap();
-    log::debug!("user: {:?} ab_personal: {:?}", user.info.user_id, guid);
-    let ab_personal = AbPersonal {
-        guid: guid,
-        error: None,
+    let user_id = match user {
+        None => None,
+        Some("me") => Some(authenticated_user.info.user_id.clone()),
+        Some(user) => {
+            let is_admin = state
+                .is_current_user_admin(&authenticated_user.info)
+                .await
+                .unwrap_or(false);
+            if !is_admin {
+                return Err(Status::Forbidden);
+            }
+            let guid = Uuid::parse_str(user).map_err(|_| Status::NotFound)?;
+            Some(guid.as_bytes().to_vec())
+        }
     };
-    Ok(Json(ab_personal))
+    let peers = state.get_all_peers(user_id).await;
+
+    if peers.is_none() {
+        return Err(Status::NotFound);
+    }
+    Ok(Json(PeersResponse {
+        msg: "success".to_string(),
+        total: peers.len() as u32,
+        data: peers.unwrap(),
+    }))
 }
 
-/// # Get the Tags
+/// # Export the peers
 ///
-/// This function is an API endpoint that retrieves all tags from an address book.
-/// It is tagged with "address book" for OpenAPI documentation.
+/// This function is an API endpoint that exports all the peers as a CSV or JSON file.
+/// The export is streamed row by row, it accepts the same filters as `/api/peers`.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `ab`: The identifier of the address book.  
+/// - `format`: The format of the export, `csv` (default) or `json`.  <br>
+///
+/// - `user`: Only export the peers assigned to this user, `me` or the guid of a user.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a JSON array of `AbTag` objects.  <br>
-/// If the address book does not exist or the user is not authorized to access it, this function returns a `status::NotFound` error.  <br>
+/// If successful, this function returns the file with the id, alias, device name, os, version, user, group, last online date and online flag of each peer.  <br>
+/// If the format is unknown, this function returns a `Status::BadRequest` error.  <br>
+/// If the user guid is invalid, this function returns a `Status::NotFound` error.  <br>
+/// If the peers cannot be read, this function returns a `Status::InternalServerError` error.  <br>
+/// If the database fails once the file is being sent, the CSV file ends with a `# export interrupted` line
+/// and the JSON array is left unclosed, followed by the same comment, so the truncation is detected.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if the address book does not exist or the user is not authorized to access it.
+/// This function will return an error if the system is in maintenance mode, or if the user is not an admin.
 ///
 /// # Example
 ///
-/// POST /api/ab/tags/018fab24-0ae5-731c-be23-88aa4518ea26
-#[openapi(tag = "address book")]
-#[post("/api/ab/tags/<ab>")]
-async fn ab_tags(
+/// GET /api/peers/export?format=csv&user=me
+#[openapi(tag = "peer")]
+#[get("/api/peers/export?<format>&<user>")]
+async fn peers_export(
     state: &State<ApiState>,
-    _user: AuthenticatedUser,
-    ab: &str,
-) -> Result<Json<Vec<AbTag>>, status::NotFound<()>> {
+    admin: AuthenticatedAdmin,
+    format: Option<String>,
+    user: Option<String>,
+) -> Result<DownloadStream<Pin<Box<dyn Stream<Item = String> + Send + '_>>>, Status> {
+    log::debug!("peers_export");
     state.check_maintenance().await;
-    let ab_tags = state.get_ab_tags(ab).await;
-    if ab_tags.is_none() {
-        return Err(status::NotFound::<()>(()));
-    }
-    let ab_tags = ab_tags.unwrap();
-    Ok(Json(ab_tags))
+
+    let format = PeerExportFormat::parse(format.as_deref()).ok_or(Status::BadRequest)?;
+    let user_id = match user.as_deref() {
+        None => None,
+        Some("me") => Some(admin.info.user_id.clone()),
+        Some(user) => {
+            let guid = Uuid::parse_str(user).map_err(|_| Status::NotFound)?;
+            Some(guid.as_bytes().to_vec())
+        }
+    };
+    let mut peers = Box::pin(state.export_peers(user_id));
+    // the status is sent with the first chunk, a failure of the query must be known before
+    let first = peers.next().await.transpose().map_err(|_| Status::InternalServerError)?;
+    let peers = rocket::futures::stream::iter(first.map(Ok)).chain(peers);
+    let (content_type, stream): (_, Pin<Box<dyn Stream<Item = String> + Send>>) = match format {
+        PeerExportFormat::Csv => (
+            ContentType::CSV,
+            Box::pin(stream! {
+                yield PEER_EXPORT_CSV_HEADER.to_string();
+                for await peer in peers {
+                    match peer {
+                        Ok(peer) => yield peer_export_csv_record(&peer),
+                        Err(_) => yield PEER_EXPORT_CSV_INTERRUPTED.to_string(),
+                    }
+                }
+            }),
+        ),
+        PeerExportFormat::Json => (
+            ContentType::JSON,
+            Box::pin(stream! {
+                yield "[".to_string();
+                let mut separator = "";
+                for await peer in peers {
+                    match peer {
+                        Ok(peer) => yield format!("{}{}", separator, serde_json::to_string(&peer).unwrap_or_default()),
+                        Err(_) => {
+                            yield PEER_EXPORT_JSON_INTERRUPTED.to_string();
+                            return;
+                        }
+                    }
+                    separator = ",";
+                }
+                yield "]".to_string();
+            }),
+        ),
+    };
+    Ok(DownloadStream {
+        content_type,
+        filename: format!("peers.{}", format.extension()),
+        stream,
+    })
 }
 
-/// # Add a Tag
-///
-/// This function is an API endpoint that adds a new tag to an address book.
-/// It is tagged with "address book" for OpenAPI documentation.
-///
-/// ## Parameters
-///
-/// - `ab`: The identifier of the address book.  
+/// # Peer uuid conflicts
 ///
-/// - `request`: A JSON object containing the new tag to be added.  
+/// This function is an API endpoint that lists the ids claimed by several machines.
+/// A conflict is recorded when a client reports an id registered with another uuid, usually after a disk image was cloned.
+/// The registered peer is kept and the requests of the other machine are rejected with "UUID_MISMATCH".
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
 /// ## Returns
 ///
-/// If successful, this function returns an `ActionResponse::Empty` object.  <br>
-/// If the tag already exists or the user is not authorized to add it, this function returns a `status::Unauthorized` error.  <br>
+/// If successful, this function returns a `Json<PeerUuidConflictsResponse>` object, which includes the rejected uuids with their request count, the most recent first.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if the tag already exists or the user is not authorized to add it.
+/// This function will return an error if the system is in maintenance mode, or if the user is not an admin.
 ///
 /// # Example
 ///
-/// POST /api/ab/tag/add/018fab24-0ae5-731c-be23-88aa4518ea26
-/// Content-Type: application/json
-///
-/// {"name": "tag1", "color": "#FF0000"}
-#[openapi(tag = "address book")]
-#[post(
-    "/api/ab/tag/add/<ab>",
-    format = "application/json",
-    data = "<request>"
-)]
-async fn ab_tag_add(
+/// GET /api/peers/conflicts
+#[openapi(tag = "peer")]
+#[get("/api/peers/conflicts", format = "application/json")]
+async fn peers_conflicts(
     state: &State<ApiState>,
-    _user: AuthenticatedUser,
-    ab: &str,
-    request: Json<AbTag>,
-) -> Result<ActionResponse, status::Unauthorized<()>> {
+    _user: AuthenticatedAdmin,
+) -> Result<Json<PeerUuidConflictsResponse>, Status> {
+    log::debug!("peers_conflicts");
     state.check_maintenance().await;
-    let ab_tag = request.0;
-    log::debug!("ab_tag_add: {:?}", ab_tag);
-    state.add_ab_tag(ab, ab_tag).await;
-    Ok(ActionResponse::Empty)
+
+    let conflicts = state
+        .get_peer_uuid_conflicts()
+        .await
+        .ok_or(Status::InternalServerError)?;
+    Ok(Json(PeerUuidConflictsResponse {
+        msg: "success".to_string(),
+        total: conflicts.len() as u32,
+        data: conflicts,
+    }))
 }
 
-/// # Update a Tag
+/// # Dismiss the uuid conflicts of a peer
 ///
-/// This function is an API endpoint that updates a tag in an address book.
-/// It is tagged with "address book" for OpenAPI documentation.
+/// This function is an API endpoint that forgets the uuid conflicts recorded for a peer id, once they were dealt with.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `ab`: The identifier of the address book.  
-///
-/// - `request`: A JSON object containing the updated tag.  
+/// - `id`: The id of the peer.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns an `ActionResponse::Empty` object.  <br>
-/// If the tag does not exist or the user is not authorized to update it, this function returns a `status::Unauthorized` error.  <br>
+/// If successful, this function returns an `ActionResponse::Empty`.  <br>
+/// If no conflict is recorded for this id, this function returns a `Status::NotFound` error.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if the tag does not exist or the user is not authorized to update it.
+/// This function will return an error if the system is in maintenance mode, or if the user is not an admin.
 ///
 /// # Example
 ///
-/// PUT /api/ab/tag/update/018fab24-0ae5-731c-be23-88aa4518ea26
-/// Content-Type: application/json
-///
-/// {"name": "tag1", "color": "#FF0000"}
-#[openapi(tag = "address book")]
-#[put(
-    "/api/ab/tag/update/<ab>",
-    format = "application/json",
-    data = "<request>"
-)]
-async fn ab_tag_update(
+/// DELETE /api/peers/conflicts/123456789
+#[openapi(tag = "peer")]
+#[delete("/api/peers/conflicts/<id>")]
+async fn peers_conflicts_delete(
     state: &State<ApiState>,
-    _user: AuthenticatedUser,
-    ab: &str,
-    request: Json<AbTag>,
-) -> Result<ActionResponse, status::Unauthorized<()>> {
+    user: AuthenticatedAdmin,
+    id: &str,
+) -> Result<ActionResponse, Status> {
+    log::debug!("peers_conflicts_delete");
     state.check_maintenance().await;
-    let ab_tag = request.0;
-    log::debug!("ab_tag_update: {:?}", ab_tag);
-    state.add_ab_tag(ab, ab_tag).await;
+
+    let deleted = state
+        .delete_peer_uuid_conflicts(id)
+        .await
+        .ok_or(Status::InternalServerError)?;
+    if deleted == 0 {
+        return Err(Status::NotFound);
+    }
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::PeerConflictsDelete,
+            Some(id),
+            serde_json::json!({ "deleted": deleted }),
+        )
+        .await;
     Ok(ActionResponse::Empty)
 }
 
-/// # Rename a Tag
+/// # Get a peer
 ///
-/// This function is an API endpoint that renames a tag in an address book.
-/// It is tagged with "address book" for OpenAPI documentation.
+/// This function is an API endpoint that allows an authenticated admin to retrieve a peer with its effective strategy.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `ab`: The identifier of the address book.
-///
-/// - `request`: A JSON object containing the old and new names of the tag.
+/// - `id`: The id of the peer.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns an `ActionResponse::Empty` object.  <br>
-/// If the tag does not exist or the user is not authorized to access it, this function returns a `status::Unauthorized` error.  <br>
+/// If successful, this function returns a `Json<PeerDetail>` object: the peer and the strategy applied to it,
+/// with the level it is assigned at (`device`, `user` or `default`).  <br>
+/// If the peer does not exist, this function returns a `Status::NotFound` error.  <br>
 ///
-/// ## Errors
+/// # Example
 ///
-/// This function will return an error if the system is in maintenance mode, or if the tag does not exist or the user is not authorized to access it.
+/// GET /api/peer/123456789
+#[openapi(tag = "peer")]
+#[get("/api/peer/<id>", format = "application/json")]
+async fn peer_get(
+    state: &State<ApiState>,
+    _user: AuthenticatedAdmin,
+    id: &str,
+) -> Result<Json<PeerDetail>, Status> {
+    log::debug!("peer_get");
+    state.check_maintenance().await;
+    let id = normalize_peer_id(id).map_err(|_| Status::BadRequest)?;
+    let peer = state.get_peer(&id).await.ok_or(Status::NotFound)?;
+    let effective_strategy = state.get_effective_strategy(&id).await;
+    Ok(Json(PeerDetail {
+        peer,
+        effective_strategy,
+    }))
+}
+
+/// # Get the effective strategy of a peer
 ///
-/// # Example
+/// This function is an API endpoint that allows an authenticated admin to retrieve the client settings applied to a peer.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
-/// PUT /api/ab/tag/rename/018fab24-0ae5-731c-be23-88aa4518ea26
-/// Content-Type: application/json
+/// ## Parameters
 ///
-/// {"old": "tag1", "new": "tag2"}
-#[openapi(tag = "address book")]
-#[put(
-    "/api/ab/tag/rename/<ab>",
-    format = "application/json",
-    data = "<request>"
-)]
-async fn ab_tag_rename(
+/// - `id`: The id of the peer.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<EffectiveSettings>` object: the settings merged from the default strategy,
+/// the strategy of the user and the strategy of the device, the most specific one setting an option wins,
+/// and for each option the level (`device`, `user`, `group`, `default` or `builtin`) and the strategy it comes from.  <br>
+/// If the peer does not exist, this function returns a `Status::NotFound` error.  <br>
+///
+/// # Example
+///
+/// GET /api/peer/123456789/effective-strategy
+#[openapi(tag = "peer")]
+#[get("/api/peer/<id>/effective-strategy", format = "application/json")]
+async fn peer_effective_strategy(
     state: &State<ApiState>,
-    _user: AuthenticatedUser,
-    ab: &str,
-    request: Json<AbTagRenameRequest>,
-) -> Result<ActionResponse, status::Unauthorized<()>> {
+    _user: AuthenticatedAdmin,
+    id: &str,
+) -> Result<Json<EffectiveSettings>, Status> {
+    log::debug!("peer_effective_strategy");
     state.check_maintenance().await;
-    let ab_tag_old_name = request.0.old;
-    let ab_tag_new_name = request.0.new;
-
-    let ab_tag_old = state.get_ab_tag(ab, ab_tag_old_name.as_str()).await;
-    if ab_tag_old.is_none() {
-        return Err(status::Unauthorized::<()>(()));
-    }
-    let mut ab_tag_new = ab_tag_old.unwrap();
-    ab_tag_new.name = ab_tag_new_name;
-    state
-        .rename_ab_tag(ab, ab_tag_old_name.as_str(), ab_tag_new)
-        .await;
-    Ok(ActionResponse::Empty)
+    let id = normalize_peer_id(id).map_err(|_| Status::BadRequest)?;
+    let effective = state
+        .get_effective_settings(&id)
+        .await
+        .ok_or(Status::NotFound)?;
+    Ok(Json(effective))
 }
 
-/// # Delete a Tag
+/// # Update a peer
 ///
-/// This function is an API endpoint that deletes a tag from an address book.
-/// It is tagged with "address book" for OpenAPI documentation.
+/// This function is an API endpoint that allows an authenticated admin to assign a peer to a user.
+/// The assignment change is recorded in the console audit.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `ab`: The identifier of the address book.  
+/// - `id`: The id of the peer to update.  <br>
 ///
-/// - `request`: A JSON object containing an array of tag names to be deleted.  
+/// - `request`: The request data, which includes the guid of the user to assign the peer to, or null to remove the assignment.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns an `ActionResponse::Empty` object.  <br>
-/// If the request is empty or the user is not authorized to access it, this function returns a `status::Unauthorized` error.  <br>
-///
-/// ## Errors
-///
-/// This function will return an error if the system is in maintenance mode, or if the request is empty or the user is not authorized to access it.
+/// If successful, this function returns an `ActionResponse::Empty`.  <br>
+/// If the peer or the user does not exist, this function returns an `ActionResponse::Error`.  <br>
+/// If the user is not an admin, this function returns a `status::Unauthorized` error.  <br>
 ///
 /// # Example
 ///
-/// DELETE /api/ab/tag/018fab24-0ae5-731c-be23-88aa4518ea26
-/// Content-Type: application/json
-///
-/// ["tag1", "tag2"]
-#[openapi(tag = "address book")]
-#[delete("/api/ab/tag/<ab>", format = "application/json", data = "<request>")]
-async fn ab_tag_delete(
+/// PUT /api/peer/123456789
+/// {"user":"018f2556-2301-79eb-91a2-cffe5ced4236"}
+#[openapi(tag = "peer")]
+#[put("/api/peer/<id>", format = "application/json", data = "<request>")]
+async fn peer_update(
     state: &State<ApiState>,
-    _user: AuthenticatedUser,
-    ab: &str,
-    request: Json<Vec<String>>,
+    user: AuthenticatedAdmin,
+    id: &str,
+    request: Json<UpdatePeerRequest>,
 ) -> Result<ActionResponse, status::Unauthorized<()>> {
-    if request.0.is_empty() {
-        return Err(status::Unauthorized::<()>(()));
-    }
-    let tags_to_delete = request.0;
+    log::debug!("peer_update");
     state.check_maintenance().await;
-    state.delete_ab_tags(ab, tags_to_delete).await;
+
+    let request = request.into_inner();
+    let user_id = match &request.user {
+        Some(guid) => match Uuid::parse_str(guid.as_str()) {
+            Ok(guid) => Some(guid.as_bytes().to_vec()),
+            Err(_) => return Ok(ActionResponse::Error("Invalid user".to_string())),
+        },
+        None => None,
+    };
+    let res = state
+        .assign_peer_to_user(id, user_id, user.info.user_id.clone())
+        .await;
+    if res.is_none() {
+        return Ok(ActionResponse::Error("Peer or user not found".to_string()));
+    }
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::PeerAssign,
+            Some(id),
+            serde_json::json!({ "user": request.user }),
+        )
+        .await;
     Ok(ActionResponse::Empty)
 }
 
-/// # Get Shared Profiles
+/// Largest image of a peer
+const PEER_AVATAR_MAX_SIZE: ByteUnit = ByteUnit::Kibibyte(256);
+
+/// Content type of an image of a peer, from its first bytes
 ///
-/// This function is an API endpoint that retrieves the shared profiles from an address book.
-/// It is tagged with "address book" for OpenAPI documentation.
+/// # Returns
+/// `image/png` or `image/webp`, None for the other formats
+fn peer_avatar_content_type(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.len() >= 12 && data.starts_with(b"RIFF") && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// # Upload the image of a peer
+///
+/// This function is an API endpoint that allows an authenticated admin to set the image the console shows for a peer,
+/// it replaces the previous one. The upload is recorded in the admin audit.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - none
+/// - `id`: The id of the peer.  <br>
+///
+/// - the body is the image, a PNG or a WebP of at most 256 KiB, sent with its `Content-Type`.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<AbSharedProfilesResponse>` object containing the shared profiles in the address book.  <br>
-/// rule: 1: read, 2: write, 3: full control  <br>
-/// If the address book does not exist or the user is not authorized to access it, this function returns a `status::Unauthorized` error.  <br>
+/// If successful, this function returns an `ActionResponse::Empty`.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if the address book does not exist or the user is not authorized to access it.
+/// * a `Status::BadRequest` error if the id of the peer is not valid
+/// * a `Status::NotFound` error if the peer does not exist
+/// * a `Status::PayloadTooLarge` error if the image is larger than 256 KiB
+/// * a `Status::UnsupportedMediaType` error if the image is neither a PNG nor a WebP, or does not match its `Content-Type`
+///
+/// This function will also return an error if the system is in maintenance mode, or if the user is not an admin.
 ///
 /// # Example
 ///
-/// {"data":[{"guid":"018fab24-0ae5-731c-be23-88aa4518ea26","name":"shared profile","owner":"admin","rule":3}],"total":2}
-#[openapi(tag = "address book")]
-#[post("/api/ab/shared/profiles")]
-async fn ab_shared(
+/// curl -X PUT -H "Authorization: Bearer <token>" -H "Content-Type: image/png" --data-binary @icon.png https://sctgdesk.example.org/api/peer/123456789/avatar
+#[openapi(tag = "peer")]
+#[put("/api/peer/<id>/avatar", data = "<data>")]
+async fn peer_avatar_put(
     state: &State<ApiState>,
-    user: AuthenticatedUser,
-) -> Result<Json<AbSharedProfilesResponse>, status::Unauthorized<()>> {
+    user: AuthenticatedAdmin,
+    id: &str,
+    content_type: Option<&ContentType>,
+    data: Data<'_>,
+) -> Result<ActionResponse, status::Custom<String>> {
+    log::debug!("peer_avatar_put");
     state.check_maintenance().await;
-    let shared_address_books = state.get_shared_address_books(user.info.user_id).await;
-    let mut ab_shared_profiles = AbSharedProfilesResponse::default();
-    for ab in shared_address_books.expect("shared_address_books is None") {
-        let address_book = AbProfile {
-            guid: ab.ab,
-            name: ab.name.unwrap_or("".to_string()),
-            owner: guid_into_uuid(ab.owner.expect("Invalid owner")).expect("Invalid GUID"),
-            rule: 3,
-            ..Default::default()
-        };
-        ab_shared_profiles.data.push(address_book);
+    let id = normalize_peer_id(id).map_err(|e| status::Custom(Status::BadRequest, e))?;
+    let unsupported = || status::Custom(Status::UnsupportedMediaType, "the image must be a PNG or a WebP".to_string());
+    let declared = content_type
+        .filter(|content_type| content_type.top() == "image")
+        .map(|content_type| content_type.sub().as_str().to_lowercase())
+        .ok_or_else(unsupported)?;
+    let body = data.open(PEER_AVATAR_MAX_SIZE).into_bytes().await.map_err(|e| {
+        status::Custom(Status::BadRequest, format!("the image cannot be read: {}", e))
+    })?;
+    if !body.is_complete() {
+        return Err(status::Custom(
+            Status::PayloadTooLarge,
+            format!("the image is larger than {}", PEER_AVATAR_MAX_SIZE),
+        ));
+    }
+    let body = body.into_inner();
+    let content_type = peer_avatar_content_type(&body)
+        .filter(|content_type| content_type.strip_prefix("image/") == Some(declared.as_str()))
+        .ok_or_else(unsupported)?;
+    match state.set_peer_avatar(&id, content_type, &body).await {
+        Some(true) => {
+            state
+                .audit_admin_action(
+                    &user.info.user_id,
+                    AdminAction::PeerAvatarUpdate,
+                    Some(&id),
+                    serde_json::json!({ "content_type": content_type, "size": body.len() }),
+                )
+                .await;
+            Ok(ActionResponse::Empty)
+        }
+        Some(false) => Err(status::Custom(Status::NotFound, "the peer does not exist".to_string())),
+        None => Err(status::Custom(Status::InternalServerError, "the image cannot be stored".to_string())),
     }
-    ab_shared_profiles.total = ab_shared_profiles.data.len() as u32;
-    Ok(Json(ab_shared_profiles))
 }
 
-/// # Settings
+/// # Get the image of a peer
 ///
-/// This function is an API endpoint that retrieves the settings for an address book.<br>
-/// TODO: Implement the settings for an address book.
-#[openapi(tag = "address book")]
-#[post("/api/ab/settings")]
-async fn ab_settings(
+/// This function is an API endpoint that allows an authenticated user to retrieve the image the console shows for a peer.
+/// It is tagged with "peer" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `id`: The id of the peer.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns the image with its content type, its `ETag` and `Cache-Control: private, max-age=300`,
+/// or a `Status::NotModified` answer when the `If-None-Match` header matches the ETag.  <br>
+/// If the peer has no image, this function returns a `Status::NotFound` error.  <br>
+///
+/// # Example
+///
+/// GET /api/peer/123456789/avatar
+#[openapi(tag = "peer")]
+#[get("/api/peer/<id>/avatar")]
+async fn peer_avatar_get(
     state: &State<ApiState>,
     _user: AuthenticatedUser,
-) -> Result<Json<AbSettingsResponse>, status::Unauthorized<()>> {
+    id: &str,
+) -> Result<PeerAvatarFile, Status> {
+    log::debug!("peer_avatar_get");
     state.check_maintenance().await;
-    let ab_settings = AbSettingsResponse {
-        error: None,
-        max_peer_one_ab: std::u32::MAX,
-    };
-    Ok(Json(ab_settings))
+    let id = normalize_peer_id(id).map_err(|_| Status::BadRequest)?;
+    state.get_peer_avatar(&id).await.map(PeerAvatarFile).ok_or(Status::NotFound)
 }
 
-/// # List peers
+/// # Get the users and the groups allowed to control a peer
 ///
-/// This function is an API endpoint that lists the peers in an address book.
-/// It is tagged with "address book" for OpenAPI documentation.
+/// This function is an API endpoint that allows an authenticated admin to audit the access to a peer.
+/// The access comes from the assignment of the peer, the admin role and the shared address books containing the peer,
+/// through their owner or their rules on users and groups.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `current`: The current page number for pagination. This parameter is currently unused.
-///
-/// - `pageSize`: The number of items per page for pagination. This parameter is currently unused.
-///
-/// - `ab`: The identifier of the address book.
+/// - `id`: The id of the peer.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<AbPeersResponse>` object containing the peers in the address book.  <br>
-/// If the address book does not exist or the user is not authorized to access it, this function returns a `status::Unauthorized` error.  <br>
+/// If successful, this function returns a `Json<PeerAccess>` object, which includes the users and the groups sorted by name,
+/// each one with the grants it holds and the address book of the address book grants.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if the address book does not exist or the user is not authorized to access it.
+/// * a `Status::BadRequest` error if the id of the peer is not valid
+/// * a `Status::NotFound` error if the peer does not exist
 ///
-#[openapi(tag = "address book")]
-#[post("/api/ab/peers?<current>&<pageSize>&<ab>")]
-async fn ab_peers(
+/// This function will also return an error if the system is in maintenance mode, or if the user is not an admin.
+///
+/// # Example
+///
+/// GET /api/peer/123456789/access
+#[openapi(tag = "peer")]
+#[get("/api/peer/<id>/access")]
+async fn peer_access(
     state: &State<ApiState>,
-    _user: AuthenticatedUser,
-    #[allow(unused_variables)] current: u32,
-    #[allow(non_snake_case, unused_variables)] pageSize: u32,
-    ab: &str,
-) -> Result<Json<AbPeersResponse>, status::Unauthorized<()>> {
+    _user: AuthenticatedAdmin,
+    id: &str,
+) -> Result<Json<PeerAccess>, Status> {
+    log::debug!("peer_access");
     state.check_maintenance().await;
-    let ab_peers = state.get_ab_peers(ab).await;
-    if ab_peers.is_none() {
-        return Err(status::Unauthorized::<()>(()));
-    }
-    let ab_peers = ab_peers.unwrap();
-    let ab_peer_response = AbPeersResponse {
-        error: None,
-        total: ab_peers.len() as u32,
-        data: ab_peers,
-    };
-    Ok(Json(ab_peer_response))
+    let id = normalize_peer_id(id).map_err(|_| Status::BadRequest)?;
+    state.get_peer_access(&id).await.map(Json).ok_or(Status::NotFound)
 }
 
-/// # Add peer
+/// # Create a share link of a peer
 ///
-/// This function is an API endpoint that adds a peer to an address book.
+/// This function is an API endpoint that creates a link granting temporary access to a peer, e.g. for a support session.
+/// The user who redeems the link with `GET /api/share/<token>` can control the peer until the link expires,
+/// the grant is listed by `GET /api/peer/<id>/access`.
+/// The token is signed by the server and only given in this answer. The creation is recorded in the admin audit.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `ab`: The identifier of the address book.
+/// - `id`: The id of the peer.  <br>
 ///
-/// - `request`: A JSON object containing the new peer information.
+/// - `request`: The validity of the link in seconds, one hour by default and at most 7 days.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns an `ActionResponse::Empty` object.
-#[openapi(tag = "address book")]
-#[post(
-    "/api/ab/peer/add/<ab>",
-    format = "application/json",
-    data = "<request>"
-)]
-async fn ab_peer_add(
+/// If successful, this function returns a `Json<ShareLinkResponse>` object with the token and the link to send.  <br>
+///
+/// ## Errors
+///
+/// * a `Status::BadRequest` error if the id of the peer or the validity is not valid
+/// * a `Status::NotFound` error if the peer does not exist
+/// * a `Status::Forbidden` error if the user is neither an admin nor the user the peer is assigned to
+///
+/// # Example
+///
+/// POST /api/peer/123456789/share-link
+/// {"expires_in":7200}
+#[openapi(tag = "peer")]
+#[post("/api/peer/<id>/share-link", format = "application/json", data = "<request>")]
+async fn peer_share_link(
     state: &State<ApiState>,
-    _user: AuthenticatedUser,
-    request: Json<AbPeer>,
-    ab: &str,
-) -> Result<ActionResponse, status::Unauthorized<()>> {
-    let ab_peer = request.0;
+    user: AuthenticatedUser,
+    share_links: &State<ShareLinks>,
+    extended_request: ExtendedRequest,
+    id: &str,
+    request: Json<ShareLinkRequest>,
+) -> Result<Json<ShareLinkResponse>, status::Custom<String>> {
+    log::debug!("peer_share_link");
     state.check_maintenance().await;
-    state.add_ab_peer(ab, ab_peer).await;
-    Ok(ActionResponse::Empty)
+    let id = normalize_peer_id(id).map_err(|e| status::Custom(Status::BadRequest, e))?;
+    let ttl = request.expires_in.unwrap_or(SHARE_LINK_DEFAULT_TTL_SECS);
+    if ttl == 0 || ttl > SHARE_LINK_MAX_TTL_SECS {
+        return Err(status::Custom(
+            Status::BadRequest,
+            format!("expires_in must be between 1 and {} seconds", SHARE_LINK_MAX_TTL_SECS),
+        ));
+    }
+    let peer = state
+        .get_peer(&id)
+        .await
+        .ok_or_else(|| status::Custom(Status::NotFound, "Peer not found".to_string()))?;
+    let admin = state.is_current_user_admin(&user.info).await.unwrap_or(false);
+    if !admin && peer.user != guid_into_uuid(user.info.user_id.clone()) {
+        return Err(status::Custom(Status::Forbidden, "The peer is not yours".to_string()));
+    }
+    let exp = now_secs() + ttl as i64;
+    let (guid, expires_at) = state
+        .create_share_link(&id, &user.info.user_id, exp)
+        .await
+        .ok_or_else(|| status::Custom(Status::InternalServerError, "the link cannot be created".to_string()))?;
+    let token = share_links.sign(&ShareLinkClaims {
+        link: guid.clone(),
+        peer: id.clone(),
+        exp,
+    });
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::PeerShareLinkCreate,
+            Some(&id),
+            serde_json::json!({ "link": guid, "expires_at": expires_at }),
+        )
+        .await;
+    let url = state
+        .public_base_url()
+        .map(str::to_string)
+        .unwrap_or_else(|| extended_request.host());
+    Ok(Json(ShareLinkResponse {
+        guid,
+        peer_id: id,
+        url: format!("{}/api/share/{}", url, token),
+        token,
+        expires_at,
+    }))
 }
 
-/// # Update peer
+/// # Redeem a share link
 ///
-/// This function is an API endpoint that updates a peer in an address book.
+/// This function is an API endpoint that grants the authenticated user the access to the peer of a share link
+/// created by `POST /api/peer/<id>/share-link`, until the link expires.
+/// A link can be redeemed by several users. The redemption is recorded in the admin audit.
+/// Until the link expires the peer is listed in the personal address book of the user by `/api/ab/peers`.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `ab`: The identifier of the address book.
-///
-/// - `request`: A JSON object containing the updated peer information.
+/// - `token`: The token of the link.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns an `ActionResponse::Empty` object.
-#[openapi(tag = "address book")]
-#[put(
-    "/api/ab/peer/update/<ab>",
-    format = "application/json",
-    data = "<request>"
-)]
-async fn ab_peer_update(
+/// If successful, this function returns a `Json<ShareLinkRedeemResponse>` object with the peer and the end of the access.  <br>
+///
+/// ## Errors
+///
+/// * a `Status::NotFound` error if the token is not valid or its link does not exist anymore
+/// * a `Status::Gone` error if the link has expired
+///
+/// # Example
+///
+/// GET /api/share/eyJsaW5rIjoi...
+#[openapi(tag = "peer")]
+#[get("/api/share/<token>")]
+async fn share_link_redeem(
     state: &State<ApiState>,
-    _user: AuthenticatedUser,
-    request: Json<AbPeer>,
-    ab: &str,
-) -> Result<ActionResponse, status::Unauthorized<()>> {
-    let mut ab_peer = request.0;
-    let old_ab_peer = state.get_ab_peer(ab, ab_peer.id.as_str()).await;
-    if old_ab_peer.is_none() {
-        return Err(status::Unauthorized::<()>(()));
-    }
-    let old_ab_peer = old_ab_peer.unwrap();
-    ab_peer.hash = ab_peer.hash.or(old_ab_peer.hash);
-    ab_peer.password = ab_peer.password.or(old_ab_peer.password);
-    ab_peer.username = ab_peer.username.or(old_ab_peer.username);
-    ab_peer.hostname = ab_peer.hostname.or(old_ab_peer.hostname);
-    ab_peer.platform = ab_peer.platform.or(old_ab_peer.platform);
-    ab_peer.alias = ab_peer.alias.or(old_ab_peer.alias);
-    ab_peer.tags = ab_peer.tags.or(old_ab_peer.tags);
-    ab_peer.force_always_relay = ab_peer
-        .force_always_relay
-        .or(old_ab_peer.force_always_relay);
-    ab_peer.rdp_port = ab_peer.rdp_port.or(old_ab_peer.rdp_port);
-    ab_peer.rdp_username = ab_peer.rdp_username.or(old_ab_peer.rdp_username);
-    ab_peer.login_name = ab_peer.login_name.or(old_ab_peer.login_name);
-    ab_peer.same_server = ab_peer.same_server.or(old_ab_peer.same_server);
+    user: AuthenticatedUser,
+    share_links: &State<ShareLinks>,
+    token: &str,
+) -> Result<Json<ShareLinkRedeemResponse>, status::Custom<String>> {
+    log::debug!("share_link_redeem");
     state.check_maintenance().await;
-    state.add_ab_peer(ab, ab_peer).await;
-    Ok(ActionResponse::Empty)
+    let error = |e: ShareLinkError| match e {
+        ShareLinkError::Invalid => status::Custom(Status::NotFound, "Invalid link".to_string()),
+        ShareLinkError::Expired => status::Custom(Status::Gone, "The link has expired".to_string()),
+    };
+    let claims = share_links.verify(token, now_secs()).map_err(error)?;
+    let expires_at = state
+        .redeem_share_link(&claims.link, &claims.peer, &user.info.user_id)
+        .await
+        .ok_or_else(|| status::Custom(Status::InternalServerError, "the link cannot be redeemed".to_string()))?
+        .map_err(error)?;
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::PeerShareLinkRedeem,
+            Some(&claims.peer),
+            serde_json::json!({ "link": claims.link, "expires_at": expires_at }),
+        )
+        .await;
+    Ok(Json(ShareLinkRedeemResponse {
+        msg: "success".to_string(),
+        peer_id: claims.peer,
+        expires_at,
+    }))
 }
 
-/// # Delete peer
+/// # Transfer a peer to a new owner
 ///
-/// This function is an API endpoint that deletes a peer from an address book.
+/// This function is an API endpoint that allows an authenticated admin to hand a peer over to another user,
+/// e.g. when an employee leaves. The peer is assigned to the new owner and moved from the personal address book
+/// of its previous owner, even disabled, to the one of the new owner with its alias, tags and password.
+/// The transfer is recorded in the console audit and in the admin audit.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `ab`: The identifier of the address book.
+/// - `id`: The id of the peer to transfer.  <br>
 ///
-/// - `request`: A JSON object containing an array of peer IDs to be deleted.
+/// - `request`: The guid of the new owner.  <br>
+///
+/// - `dry_run`: If true, the peer is not transferred and nothing is audited.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns an `ActionResponse::Empty` object.
-#[openapi(tag = "address book")]
-#[delete("/api/ab/peer/<ab>", format = "application/json", data = "<request>")]
-async fn ab_peer_delete(
+/// If successful, this function returns a `Json<PeerTransferResponse>` object with the previous owner, the new owner
+/// and the personal address book the peer is now in.  <br>
+/// With `dry_run` the response is the one the transfer would give.  <br>
+///
+/// ## Errors
+///
+/// * a `Status::BadRequest` error if the id of the peer or the guid of the user is not valid
+/// * a `Status::NotFound` error if the peer does not exist, or if the user does not exist or is disabled
+///
+/// This function will also return an error if the system is in maintenance mode, or if the user is not an admin.
+///
+/// # Example
+///
+/// POST /api/peer/123456789/transfer
+/// {"user":"018f2556-2301-79eb-91a2-cffe5ced4236"}
+#[openapi(tag = "peer")]
+#[post("/api/peer/<id>/transfer?<dry_run>", format = "application/json", data = "<request>")]
+async fn peer_transfer(
     state: &State<ApiState>,
-    _user: AuthenticatedUser,
-    ab: &str,
-    request: Json<Vec<String>>,
-) -> Result<ActionResponse, status::Unauthorized<()>> {
-    if request.0.is_empty() {
-        return Err(status::Unauthorized::<()>(()));
-    }
-    let peers_to_delete = request.0;
+    user: AuthenticatedAdmin,
+    id: &str,
+    request: Json<PeerTransferRequest>,
+    dry_run: Option<bool>,
+) -> Result<Json<PeerTransferResponse>, status::Custom<String>> {
+    log::debug!("peer_transfer");
     state.check_maintenance().await;
-    state.delete_ab_peer(ab, peers_to_delete).await;
-    Ok(ActionResponse::Empty)
+    let id = normalize_peer_id(id).map_err(|e| status::Custom(Status::BadRequest, e))?;
+    let user_id = Uuid::parse_str(&request.user)
+        .map_err(|_| status::Custom(Status::BadRequest, "Invalid user".to_string()))?
+        .as_bytes()
+        .to_vec();
+    let dry_run = dry_run.unwrap_or(false);
+    let transfer = state
+        .transfer_peer(&id, user_id, user.info.user_id.clone(), dry_run)
+        .await
+        .ok_or_else(|| status::Custom(Status::InternalServerError, "the peer cannot be transferred".to_string()))?
+        .map_err(|e| match e {
+            PeerTransferError::PeerNotFound => status::Custom(Status::NotFound, "Peer not found".to_string()),
+            PeerTransferError::UserNotFound => status::Custom(Status::NotFound, "User not found".to_string()),
+        })?;
+    if dry_run {
+        return Ok(Json(transfer));
+    }
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::PeerTransfer,
+            Some(&id),
+            serde_json::json!(transfer),
+        )
+        .await;
+    Ok(Json(transfer))
 }
 
-/// # List strategies
+/// # Peer status history
 ///
-/// This function is an API endpoint that retrieves the list of all strategies. <br>
-/// TODO: This function is currently unused.
+/// This function is an API endpoint that retrieves the online/offline intervals of a peer and its availability in a time window.
+/// The transitions are derived from the heartbeats, a peer is offline when it did not beat for 60 seconds.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
-#[openapi(tag = "todo")]
-#[get("/api/stategies", format = "application/json")]
-async fn strategies(
+/// ## Parameters
+///
+/// - `id`: The id of the peer.  <br>
+///
+/// - `from`: The start of the window (`2024-05-01 12:00:00` UTC or RFC 3339), 24 hours before `to` by default.  <br>
+///
+/// - `to`: The end of the window (`2024-05-01 12:00:00` UTC or RFC 3339), now by default.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<PeerHistoryResponse>` object, which includes the intervals and the availability percentage.  <br>
+/// If the dates are invalid, this function returns a `Status::BadRequest` error.  <br>
+/// If the peer does not exist, this function returns a `Status::NotFound` error.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, or if the user is not an admin.
+///
+/// # Example
+///
+/// GET /api/peer/123456789/history?from=2024-05-01T00:00:00Z&to=2024-05-08T00:00:00Z
+#[openapi(tag = "peer")]
+#[get("/api/peer/<id>/history?<from>&<to>", format = "application/json")]
+async fn peer_history(
     state: &State<ApiState>,
     _user: AuthenticatedAdmin,
-) -> Result<Json<UsersResponse>, status::NotFound<()>> {
-    log::debug!("peers");
+    id: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<Json<PeerHistoryResponse>, Status> {
+    log::debug!("peer_history");
     state.check_maintenance().await;
 
-    let response = UsersResponse {
-        msg: "success".to_string(),
-        total: 1,
-        data: "[{}]".to_string(),
-    };
-
-    Ok(Json(response))
+    let (from, to) = history_window(from, to).ok_or(Status::BadRequest)?;
+    let (initial_online, transitions) = state
+        .get_peer_status_history(id, from.as_str(), to.as_str())
+        .await
+        .ok_or(Status::NotFound)?;
+    let history = build_peer_history(id, initial_online, &transitions, &from, &to)
+        .ok_or(Status::BadRequest)?;
+    Ok(Json(history))
 }
 
-/// # Add user
+/// # Peer status events
 ///
-/// This function is an API endpoint that adds a new user.
+/// This function is an API endpoint that streams the peer status changes as Server-Sent Events.
+/// The events are `peer-online`, `peer-offline` and `peer-registered`, their data is a JSON `PeerEvent`.
+/// A keep-alive comment is sent every 15 seconds. A consumer too slow to keep up loses the oldest events.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
-/// ## Parameters
+/// ## Returns
 ///
-/// - `request`: A JSON object containing the new user information.
+/// If successful, this function returns an `EventStream` which stays open until the client disconnects or the server shuts down.  <br>
 ///
-/// ## Returns
+/// ## Errors
 ///
-/// If successful, this function returns a `Json<UsersResponse>` object containing the updated user information.
-#[openapi(tag = "user")]
-#[post("/api/user", format = "application/json", data = "<request>")]
-async fn user_add(
+/// This function will return an error if the system is in maintenance mode, or if the user is not an admin.
+///
+/// # Example
+///
+/// GET /api/peers/events
+///
+/// event: peer-online
+/// data: {"type":"peer-online","id":"123456789","created_at":"2024-05-01 12:00:00"}
+#[openapi(tag = "peer")]
+#[get("/api/peers/events")]
+async fn peers_events(
     state: &State<ApiState>,
     _user: AuthenticatedAdmin,
-    request: Json<AddUserRequest>,
-) -> Result<Json<UsersResponse>, status::Unauthorized<()>> {
-    log::debug!("create_user");
+    mut shutdown: Shutdown,
+) -> EventStream<Pin<Box<dyn Stream<Item = Event> + Send + '_>>> {
+    log::debug!("peers_events");
     state.check_maintenance().await;
 
-    let user_parameters = request.0;
-    if user_parameters.password != user_parameters.confirm_password {
-        return Ok(Json(UsersResponse {
-            msg: "error: Passwords mismatch".to_string(),
-            total: 0,
-            data: "[{}]".to_string(),
-        }));
-    }
-    let res = state.add_user(user_parameters).await;
-    if res.is_none() {
-        return Err(status::Unauthorized::<()>(()));
-    }
-    let response = UsersResponse {
-        msg: "success".to_string(),
-        total: 1,
-        data: "[{}]".to_string(),
+    let mut events = state.subscribe_peer_events();
+    let stream = stream! {
+        loop {
+            select! {
+                event = events.recv() => match event {
+                    Ok(event) => yield Event::json(&event).event(event.event_type.name()),
+                    Err(RecvError::Lagged(skipped)) => {
+                        log::warn!("peers_events: slow consumer, {} events dropped", skipped);
+                    }
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut shutdown => break,
+            }
+        }
     };
-
-    Ok(Json(response))
+    EventStream::from(Box::pin(stream) as Pin<Box<dyn Stream<Item = Event> + Send>>)
+        .heartbeat(Duration::from_secs(15))
 }
 
-/// # Enable users
+/// # Server logs
 ///
-/// This function is an API endpoint that enables or disables users.
+/// This function is an API endpoint that streams the logs of the server as Server-Sent Events, for the admin console.
+/// The recent lines kept in memory are sent first, then the new lines as they are logged.
+/// The events are `log`, their data is a JSON `LogLine`.
+/// A keep-alive comment is sent every 15 seconds. A consumer too slow to keep up loses the oldest lines.
+/// It is tagged with "admin" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `request`: A JSON object containing the list of users to enable or disable.
+/// - `level`: The most verbose level sent, `error`, `warn`, `info`, `debug` or `trace`. Defaults to `info`.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<UsersResponse>` object containing the updated user information.
-#[openapi(tag = "user")]
-#[post("/api/enable-users", format = "application/json", data = "<request>")]
-async fn user_enable(
-    state: &State<ApiState>,
+/// If successful, this function returns an `EventStream` which stays open until the client disconnects or the server shuts down.  <br>
+/// If the level is unknown, this function returns a `Status::BadRequest` error.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the user is not an admin.
+///
+/// # Example
+///
+/// GET /api/admin/logs/stream?level=warn
+///
+/// event: log
+/// data: {"time":"2024-05-01T12:00:00.000Z","level":"warn","target":"state::state","message":"..."}
+#[openapi(tag = "admin")]
+#[get("/api/admin/logs/stream?<level>")]
+async fn logs_stream(
+    log_buffer: &State<Arc<LogBuffer>>,
     _user: AuthenticatedAdmin,
-    request: Json<EnableUserRequest>,
-) -> Result<Json<UsersResponse>, status::Unauthorized<()>> {
-    log::debug!("create_user");
-    state.check_maintenance().await;
+    level: Option<&str>,
+    mut shutdown: Shutdown,
+) -> Result<EventStream<Pin<Box<dyn Stream<Item = Event> + Send + 'static>>>, Status> {
+    let level = match level {
+        Some(level) => level.parse::<log::Level>().map_err(|_| Status::BadRequest)?,
+        None => log::Level::Info,
+    };
+    let shown = move |line: &LogLine| line.level.parse::<log::Level>().is_ok_and(|line_level| line_level <= level);
+    let (recent, mut lines) = log_buffer.subscribe();
+    let stream = stream! {
+        for line in recent.iter().filter(|line| shown(line)) {
+            yield Event::json(line).event("log");
+        }
+        loop {
+            select! {
+                line = lines.recv() => match line {
+                    Ok(line) if shown(&line) => yield Event::json(&line).event("log"),
+                    Ok(_) => {}
+                    // not logged, the line would be sent to the slow consumer again
+                    Err(RecvError::Lagged(skipped)) => yield Event::comment(format!("{} lines dropped", skipped)),
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut shutdown => break,
+            }
+        }
+    };
+    Ok(EventStream::from(Box::pin(stream) as Pin<Box<dyn Stream<Item = Event> + Send>>)
+        .heartbeat(Duration::from_secs(15)))
+}
 
-    let enable_users = request.0;
+/// Number of items of a page requested with a `pageSize` of 0, set by the `DEFAULT_PAGE_SIZE` environment variable
+fn default_page_size() -> u32 {
+    env::var("DEFAULT_PAGE_SIZE")
+        .ok()
+        .and_then(|size| size.parse::<u32>().ok())
+        .filter(|size| *size > 0)
+        .unwrap_or(20)
+}
 
-    let mut count = 0;
-    for uuid in enable_users.rows {
-        let res = state
-            .user_change_status(uuid.as_str(), enable_users.disable)
-            .await;
-        if res.is_some() {
-            count += 1;
-        }
+/// Largest number of items of a page, set by the `MAX_PAGE_SIZE` environment variable
+fn max_page_size() -> u32 {
+    env::var("MAX_PAGE_SIZE")
+        .ok()
+        .and_then(|size| size.parse::<u32>().ok())
+        .filter(|size| *size > 0)
+        .unwrap_or(1000)
+}
+
+/// Number of items of a page for the `pageSize` sent by a client,
+/// the default page size for 0 and at most `max_page_size()`
+fn effective_page_size(page_size: u32) -> u32 {
+    match page_size {
+        0 => default_page_size(),
+        page_size => page_size,
     }
-    let response = UsersResponse {
-        msg: "success".to_string(),
-        total: count,
-        data: "[{}]".to_string(),
-    };
+    .min(max_page_size())
+}
 
-    Ok(Json(response))
+/// Maximum number of peers in one bulk request, set by the `PEERS_BULK_MAX_SIZE` environment variable
+fn peers_bulk_max_size() -> usize {
+    env::var("PEERS_BULK_MAX_SIZE")
+        .ok()
+        .and_then(|size| size.parse::<usize>().ok())
+        .unwrap_or(100)
 }
 
-/// # Update user
+/// # Bulk operations on peers
 ///
-/// This function is an API endpoint that updates a user.<br>
-/// Normal user can only update themselves, admin can update any user.<br>
+/// This function is an API endpoint that allows an authenticated admin to delete, disable or move to a group several peers at once.
+/// All the peers are processed in a single transaction and the whole batch is recorded in one console audit entry.
+/// The size of a batch is limited by the `PEERS_BULK_MAX_SIZE` environment variable (100 by default).
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `request`: A JSON object containing the updated user information.
+/// - `request`: The request data, which includes the action (`delete`, `set_group` or `disable`), the peer ids and the group guid for `set_group`.  <br>
+/// - `dry_run`: If true, the results are computed but no peer is changed and nothing is audited.  <br>
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<UsersResponse>` object containing the updated user information.
-#[openapi(tag = "user")]
-#[put("/api/user", format = "application/json", data = "<request>")]
-async fn user_update(
+/// If successful, this function returns a `Json<PeersBulkResponse>` object, which includes the result for each peer id (`ok`, `not_found` or `refused`).  <br>
+/// With `dry_run` the message is `dry run` and the results are the ones the operation would give.  <br>
+/// If the batch is too large, or if the group is missing or unknown, this function returns a `Status::BadRequest` error.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, or if the user is not an admin.
+///
+/// # Example
+///
+/// POST /api/peers/bulk
+/// {"action":"set_group","ids":["123456789","987654321"],"group":"018f2556-22fb-73ee-9afd-bbcdc0cc387b"}
+///
+/// POST /api/peers/bulk?dry_run=true
+/// {"action":"delete","ids":["123456789","987654321"]}
+#[openapi(tag = "peer")]
+#[post("/api/peers/bulk?<dry_run>", format = "application/json", data = "<request>")]
+async fn peers_bulk(
     state: &State<ApiState>,
-    user: AuthenticatedUser,
-    request: Json<UpdateUserRequest>,
-) -> Result<Json<UsersResponse>, status::Unauthorized<()>> {
-    log::debug!("update_user");
+    user: AuthenticatedAdmin,
+    request: Json<PeersBulkRequest>,
+    dry_run: Option<bool>,
+) -> Result<Json<PeersBulkResponse>, Status> {
+    log::debug!("peers_bulk");
     state.check_maintenance().await;
-    let mut guid = uuid_into_guid(request.0.uuid.as_str());
-    if guid.is_none() {
-        guid = Some(user.info.user_id.clone());
-    }
 
-    let guid = guid.unwrap();
-    let is_admin = state
-        .is_current_user_admin(&user.info)
+    let request = request.into_inner();
+    if request.ids.len() > peers_bulk_max_size() {
+        return Err(Status::BadRequest);
+    }
+    let group = match (request.action, request.group.clone()) {
+        (PeersBulkAction::SetGroup, Some(group)) => match Uuid::parse_str(group.as_str()) {
+            Ok(group) => Some(group.as_bytes().to_vec()),
+            Err(_) => return Err(Status::BadRequest),
+        },
+        (PeersBulkAction::SetGroup, None) => return Err(Status::BadRequest),
+        _ => None,
+    };
+    let dry_run = dry_run.unwrap_or(false);
+    let results = state
+        .bulk_peers(
+            request.action,
+            request.ids,
+            group,
+            user.info.user_id.clone(),
+            dry_run,
+        )
         .await
-        .unwrap_or(false);
-
-    if !is_admin && user.info.user_id != guid {
-        return Err(status::Unauthorized::<()>(()));
+        .ok_or(Status::BadRequest)?;
+    if dry_run {
+        return Ok(Json(PeersBulkResponse {
+            msg: "dry run".to_string(),
+            total: results.len() as u32,
+            data: results,
+        }));
     }
-    let response = UsersResponse {
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::PeersBulk,
+            None,
+            serde_json::json!({ "action": request.action, "group": request.group, "peers": results }),
+        )
+        .await;
+    Ok(Json(PeersBulkResponse {
         msg: "success".to_string(),
-        total: 1,
-        data: "[{}]".to_string(),
-    };
-    let user_update = request.0;
-    state.user_update(guid, user_update).await;
-    Ok(Json(response))
+        total: results.len() as u32,
+        data: results,
+    }))
 }
 
-/// # Add OIDC Provider
+/// # Count Peers per platform
 ///
-/// This function is an API endpoint that adds an OIDC provider.
+/// This function is an API endpoint that retrieves the count of peers per platform.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
-/// TODO: This function is currently unused.
-#[openapi(tag = "todo")]
-#[put("/api/oidc/settings", format = "application/json", data = "<_request>")]
-async fn oidc_add(
+/// ## Parameters
+///
+/// - `platform`: The platform to filter the peers by (windows, macos, linux, android or all). <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<PeersCountResponse>` object, which includes the total number of peers for the specified platform.  <br>
+///
+#[openapi(tag = "peer")]
+#[get("/api/peers/count/<platform>", format = "application/json")]
+async fn peers_count(
     state: &State<ApiState>,
-    _user: AuthenticatedAdmin,
-    _request: Json<EnableUserRequest>,
-) -> Result<Json<EnableUserRequest>, status::Unauthorized<()>> {
-    log::debug!("Add OIDC Provider");
-    state.check_maintenance().await;
-
-    Err(status::Unauthorized::<()>(()))
+    _user: AuthenticatedUser,
+    platform: String,
+) -> Result<Json<PeersCountResponse>, status::NotFound<()>> {
+    let count = match platform.to_lowercase().as_str() {
+        "windows" => {
+            let count = state.get_peers_count(Platform::Windows).await;
+            log::debug!("peers_count: {:?}", count);
+            count
+        }
+        "mac" | "macos" => {
+            let count = state.get_peers_count(Platform::MacOS).await;
+            log::debug!("peers_count: {:?}", count);
+            count
+        }
+        "linux" => {
+            let count = state.get_peers_count(Platform::Linux).await;
+            log::debug!("peers_count: {:?}", count);
+            count
+        }
+        "android" => {
+            let count = state.get_peers_count(Platform::Android).await;
+            log::debug!("peers_count: {:?}", count);
+            count
+        }
+        "all" => {
+            let count = state.get_peers_count(Platform::All).await;
+            log::debug!("peers_count: {:?}", count);
+            count
+        }
+        _ => 0,
+    };
+    return Ok(Json(PeersCountResponse { total: count }));
 }
 
-/// # Get OIDC Providers
+/// # Get the List of cpus used by the peers
 ///
-/// This function is an API endpoint that retrieves all OIDC providers.
+/// This function is an API endpoint that retrieves the count of cpus used by the peers.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
-/// TODO: This function is currently unused.
-#[openapi(tag = "todo")]
-#[get("/api/oidc/settings", format = "application/json")]
-async fn oidc_get(
+/// ## Parameters
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<Vec<CpuCount>>` object, which includes the total number of cpus used by the peers.  <br>
+#[openapi(tag = "peer")]
+#[get("/api/peers/cpus", format = "application/json")]
+async fn peers_cpus(
     state: &State<ApiState>,
-    _user: AuthenticatedAdmin,
-) -> Result<Json<OidcSettingsResponse>, status::Unauthorized<()>> {
-    log::debug!("create_user");
-    state.check_maintenance().await;
-    Err(status::Unauthorized::<()>(()))
+    _user: AuthenticatedUser,
+) -> Result<Json<Vec<CpuCount>>, status::NotFound<()>> {
+    let cpus = state.get_cpus_count().await;
+    return Ok(Json(cpus));
 }
 
-/// # Get Users for client
-///
-/// This function is an API endpoint that retrieves all users.
+/// # Summary of the peers
 ///
-/// ## Parameters
+/// This function is an API endpoint that counts the peers online and offline, in total and per os family,
+/// from their last heartbeat and the os of their last sysinfo.
+/// The counts are computed with a single aggregate query and cached for a few seconds.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
-/// - `current`: The current page number for pagination. This parameter is currently unused.
+/// ## Returns
 ///
-/// - `pageSize`: The number of items per page for pagination. This parameter is currently unused.
+/// If successful, this function returns a `Json<PeersSummary>` object.  <br>
 ///
-/// - `accessible`: A boolean value indicating whether the user is accessible. This parameter is currently unused.
+/// ## Errors
 ///
-/// - `status`: The status of the user. This parameter is currently unused.
+/// This function will return an error if the system is in maintenance mode, or if the user is not an admin.
 ///
-/// ## Returns
+/// # Example
 ///
-/// If successful, this function returns a `Json<UserList>` object containing the users.
-#[openapi(tag = "user")]
-#[get(
-    "/api/users?<current>&<pageSize>&<accessible>&<status>",
-    format = "application/json"
-)]
-async fn users_client(
+/// GET /api/peers/summary
+#[openapi(tag = "peer")]
+#[get("/api/peers/summary", format = "application/json")]
+async fn peers_summary(
     state: &State<ApiState>,
-    _user: AuthenticatedUser,
-    current: u32,
-    #[allow(non_snake_case, unused_variables)] pageSize: u32,
-    #[allow(unused_variables)] accessible: Option<bool>,
-    #[allow(unused_variables)] status: Option<u32>,
-) -> Result<Json<UserList>, status::NotFound<()>> {
-    log::debug!("users");
+    _user: AuthenticatedAdmin,
+) -> Result<Json<PeersSummary>, Status> {
+    log::debug!("peers_summary");
     state.check_maintenance().await;
 
-    let res = state.get_all_users(None, None, current, pageSize).await;
-    if res.is_none() {
-        return Err(status::NotFound::<()>(()));
-    }
-    let response = UserList {
-        msg: "success".to_string(),
-        total: res.len() as u32,
-        data: res.unwrap(),
-    };
-
-    Ok(Json(response))
+    let summary = state
+        .get_peers_summary()
+        .await
+        .ok_or(Status::InternalServerError)?;
+    Ok(Json(summary))
 }
 
-/// Get the software download url
+/// # Dashboard statistics
 ///
-/// # Arguments
+/// This function is an API endpoint that retrieves the numbers shown on the console dashboard.
+/// They are computed on demand with a few aggregate queries.
+/// It is tagged with "peer" for OpenAPI documentation.
 ///
-/// * `key` - The key to the software download link, it can be `osx`, `w64` or `ios`
+/// ## Returns
 ///
-/// # Usage
+/// If successful, this function returns a `Json<StatisticsResponse>` object, which includes the users, devices, logins and address books counts and the bytes transferred by file transfers per day.  <br>
 ///
-/// * it needs a valid S3 configuration file defined with the `S3_CONFIG_FILE` environment variable
+/// ## Errors
 ///
-/// <pre>
-/// [s3config]<br>
-/// Page = "https://github.com/rustdesk/rustdesk/releases/latest"<br>
-/// Endpoint = "https://compat.objectstorage.eu-london-1.oraclecloud.com"<br>
-/// Region = "eu-london-1"<br>
-/// AccessKey = "c324ead11faa0d87337c07ddc4a1129fab76188d"<br>
-/// SecretKey = "GJurV55f/LD36kjZFpchZMj/uvgTqxHyFkBchUUa8KA="<br>
-/// Bucket = "aezoz24elapn"<br>
-/// Windows64Key = "master/sctgdesk-releases/sctgdesk-1.2.4-x86_64.exe"<br>
-/// Windows32Key = "master/sctgdesk-releases/sctgdesk-1.2.4-i686.exe"<br>
-/// OSXKey = "master/sctgdesk-releases/sctgdesk-1.2.4.dmg"<br>
-/// OSXArm64Key = "master/sctgdesk-releases/sctgdesk-1.2.4.dmg"<br>
-/// IOSKey = "master/sctgdesk-releases/sctgdesk-1.2.4.ipa"<br>
-/// </pre>
+/// This function will return an error if the system is in maintenance mode, or if the user is not an admin.
 ///
-#[openapi(tag = "software")]
-#[get(
-    "/api/software/client-download-link/<key>",
-    format = "application/json"
-)]
-async fn software(key: &str) -> Result<Json<SoftwareResponse>, status::NotFound<()>> {
-    log::debug!("software");
-    let config = get_s3_config_file()
-        .await
-        .map_err(|e| status::NotFound(Box::new(e)));
+/// # Example
+///
+/// GET /api/statistics
+#[openapi(tag = "peer")]
+#[get("/api/statistics", format = "application/json")]
+async fn statistics(
+    state: &State<ApiState>,
+    _user: AuthenticatedAdmin,
+) -> Result<Json<StatisticsResponse>, Status> {
+    log::debug!("statistics");
+    state.check_maintenance().await;
 
-    let config = config.unwrap();
-    match key {
-        "osx" => {
-            let key = config.clone().s3config.osxkey;
-            let url = get_signed_release_url_with_config(config, key.as_str())
-                .await
-                .map_err(|e| status::NotFound(Box::new(e)));
-            let url = url.unwrap();
-            let response = SoftwareResponse { url };
-            Ok(Json(response))
-        }
-        "w64" => {
-            let key = config.clone().s3config.windows64_key;
-            let url = get_signed_release_url_with_config(config, key.as_str())
-                .await
-                .map_err(|e| status::NotFound(Box::new(e)));
-            let url = url.unwrap();
-            let response = SoftwareResponse { url };
-            Ok(Json(response))
-        }
-        "ios" => {
-            let key = config.clone().s3config.ioskey;
-            let url = get_signed_release_url_with_config(config, key.as_str())
-                .await
-                .map_err(|e| status::NotFound(Box::new(e)));
-            let url = url.unwrap();
-            let response = SoftwareResponse { url };
-            Ok(Json(response))
-        }
-        _ => Err(status::NotFound(())),
-    }
+    let statistics = state
+        .get_statistics()
+        .await
+        .ok_or(Status::InternalServerError)?;
+    Ok(Json(statistics))
 }
 
-/// # Retrieve the server version
+/// # Login Options
 ///
-/// This function is an API endpoint that retrieves the version of the server.
-/// It is tagged with "software" for OpenAPI documentation.
+/// This is called by the client for knowing the Oauth2 provider(s) available
+/// You must provide a list of Oauth2 providers in the `oauth2.toml` config file
+/// The config file can be overridden by the `OAUTH2_CONFIG_FILE` environment variable
 ///
-/// ## Returns
+/// This function is an API endpoint that is called by the client to get the list of available OAuth2 providers.
+/// The list of providers is defined in the `oauth2.toml` config file, which can be overridden by the `OAUTH2_CONFIG_FILE` environment variable.
+/// It is tagged with "login" for OpenAPI documentation.
 ///
-/// If successful, this function returns a `Json<SoftwareVersionResponse>` object containing the version of the server.
-#[openapi(tag = "software")]
-#[get("/api/software/version/server", format = "application/json")]
-async fn software_version() -> Json<SoftwareVersionResponse> {
-    log::debug!("software_version");
-    let version = env::var("MAIN_PKG_VERSION").unwrap();
-    let response = SoftwareVersionResponse {
-        server: Some(version),
-        client: Some(extract_version().await.unwrap_or("0.0.0".to_string())),
-    };
-    Json(response)
-}
-
-/// # Retrieve the client version
+/// ## Limitations
 ///
-/// This function is an API endpoint that retrieves the version of the client.
-/// It copies the GitHub method of retrieving the latest release version.
-/// It is tagged with "software" for OpenAPI documentation.
+/// It needs to be completed for mapping the username and email from the OAuth2 provider to the SCTGDesk user.
+///
+/// ## Parameters
+///
+/// - none
 ///
-/// It can be used by replacing the check_software_update() from the client.
-/// You can find the client code at rustdesk/src/common.rs
 /// ## Returns
 ///
-/// Returns in the location header the URL of the latest release.
-/// something like https://api-server/api/releases/tag/1.2.6
+/// If successful, this function returns a `Json<Vec<String>>` object, which includes the list of available OAuth2 providers.  <br>
+/// If the config file is not found or cannot be read, this function returns a `status::Unauthorized` error.  <br>
 ///
-/// ## Example
+/// ## Errors
 ///
-/// It is easy to modify the client code to use this API endpoint.
-/// this is how we can modify the client code to use this API endpoint.
+/// This function will return an error if the system is in maintenance mode, or if the config file is not found or cannot be read.
 ///
-/// <pre>
-///     // see <a href='https://github.com/sctg-development/sctgdesk/blob/481d3516fef1daa145d8044594187cb11959f8be/src/common.rs#L953L972'>Sample modification on github</a><br>
-///     let url=format!("{}/api/software/releases/latest",get_api_server("".to_owned(), "".to_owned())).to_owned();<br>
-///     log::info!("URL for checking software updates: {}", url);<br>
-/// </pre>
-#[openapi(tag = "software")]
-#[get("/api/software/releases/latest")]
-async fn software_releases_latest(request: ExtendedRequest) -> Redirect {
-    log::debug!("software_releases_latest");
-    let headers = request.headers;
-    let host = get_host(headers);
-    let version = extract_version()
-        .await
-        .map_err(|e| status::NotFound(Box::new(e)));
-    if version.is_err() {
-        return Redirect::to(format!("{}/api/software/releases/tag/0.0.0", host));
+/// # Example
+///
+/// GET /api/login-options
+#[openapi(tag = "login")]
+#[get("/api/login-options", format = "application/json")]
+async fn login_options(
+    state: &State<ApiState>,
+) -> Result<Json<Vec<String>>, status::Unauthorized<()>> {
+    let mut providers: Vec<String> = Vec::new();
+    let providers_config = state
+        .get_oauth2_config(state.oauth2_config_file())
+        .await;
+    if providers_config.is_none() {
+        return Err(status::Unauthorized::<()>(()));
     }
-    let version = version.unwrap();
-    let url = format!("{}/api/software/releases/tag/{}", host, version);
-    Redirect::to(url)
+    for p in providers_config.unwrap() {
+        providers.push(p.op_auth_string);
+    }
+    Ok(Json(providers))
 }
 
-/// # Simulate GitHub API for releases
+/// OIDC Auth request
 ///
-/// This function is an API endpoint that simulates the GitHub API for releases.
+/// This entrypoint is called by the client for getting the authorization url for the Oauth2 provider he chooses
+///
+/// For testing you can generate a valid uuid field with the following command: `uuidgen | base64`
+/// # OIDC Auth Request
+///
+/// This function is an API endpoint that is called by the client to get the authorization URL for the chosen OAuth2 provider.
+/// It is tagged with "login" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `version`: The version of the release.
+/// - `request`: The request data, which includes the chosen OAuth2 provider and a UUID.  <br> For testing you can generate a valid uuid field with the following command: `uuidgen | base64`
 ///
 /// ## Returns
 ///
-/// Returns a `Json<SoftwareVersionResponse>` object containing the version of the release.
-#[openapi(tag = "software")]
-#[get("/api/software/releases/tag/<version>")]
-async fn software_releases_tag(
-    version: &str,
-) -> Result<Json<SoftwareVersionResponse>, status::NotFound<()>> {
-    log::debug!("software_releases_tag");
-    let response = SoftwareVersionResponse {
-        server: None,
-        client: Some(version.to_string()),
-    };
-    Ok(Json(response))
-}
-
-/// # Redirect to the software download page
+/// If successful, this function returns a `Json<OidcAuthUrl>` object, which includes the authorization URL and a session code.  <br>
+/// If the UUID is invalid or the OAuth2 provider is not found, this function returns an `OidcAuthUrl` object with an empty URL and an error code.  <br>
 ///
-/// This function is an API endpoint that redirects to the software download page.
-/// You must set the `Page` key in the `s3config` of the S3 configuration file.
+/// ## Errors
 ///
-#[openapi(tag = "software")]
-#[get("/api/software/download")]
-async fn software_download() -> Redirect {
-    let url = get_software_download_page()
-        .await
-        .unwrap_or("https://github.com/sctg-development/sctgdesk".to_string());
-    Redirect::to(url)
+/// This function will return an error if the system is in maintenance mode, or if the UUID is invalid or the OAuth2 provider is not found.
+///
+/// # Example
+///
+/// POST /api/oidc/auth
+/// {
+///     "op": "github",
+///     "uuid": "generated_uuid_base64_encoded"
+/// }
+#[openapi(tag = "login")]
+#[post("/api/oidc/auth", format = "application/json", data = "<request>")]
+async fn oidc_auth(
+    state: &State<ApiState>,
+    request: ExtendedJson<OidcAuthRequest>,
+) -> Json<OidcAuthUrl> {
+    log::debug!("oidc_auth: {} for {}", request.data.op, request.data.id);
+    let headers = request.headers();
+    let base_path = request.base_path;
+    let request = request.data;
+
+    let uuid_code = Uuid::new_v4().to_string();
+    let uuid_decoded = BASE64_STANDARD.decode(request.uuid.clone());
+    if uuid_decoded.is_err() {
+        return Json(OidcAuthUrl {
+            url: "".to_string(),
+            code: "UUID_ERROR".to_string(),
+        });
+    }
+    let uuid_decoded = uuid_decoded.unwrap();
+    let uuid_client = String::from_utf8(uuid_decoded).unwrap();
+    let callback_url = format!(
+        "{}{}/api/oidc/callback",
+        get_callback_host(headers.clone(), get_force_https_callbacks()),
+        base_path
+    );
+    let providers_config = state
+        .get_oauth2_config(state.oauth2_config_file())
+        .await;
+    if providers_config.is_none() {
+        return Json(OidcAuthUrl {
+            url: "".to_string(),
+            code: "".to_string(),
+        });
+    }
+    let providers_config = providers_config.unwrap();
+    let provider_config = providers_config
+        .iter()
+        .find(|config| config.op == request.op);
+
+    if provider_config.is_none() {
+        return Json(OidcAuthUrl {
+            url: "".to_string(),
+            code: "".to_string(),
+        });
+    }
+    let provider_config = provider_config.unwrap();
+    let provider_trait_object: Arc<dyn OAuthProvider> = {
+        match provider_config.provider {
+            oauth2::Provider::Github => Arc::new(oauth2::github_provider::GithubProvider::new()),
+            oauth2::Provider::Gitlab => todo!(),
+            oauth2::Provider::Google => todo!(),
+            oauth2::Provider::Apple => todo!(),
+            oauth2::Provider::Okta => todo!(),
+            oauth2::Provider::Facebook => todo!(),
+            oauth2::Provider::Azure => todo!(),
+            oauth2::Provider::Auth0 => todo!(),
+            oauth2::Provider::Dex => Arc::new(oauth2::dex_provider::DexProvider::new()),
+        }
+    };
+
+    let redirect_url =
+        provider_trait_object.get_redirect_url(callback_url.as_str(), uuid_code.as_str());
+    let _oidc_session = state
+        .insert_oidc_session(
+            uuid_code.clone(),
+            OidcState {
+                id: request.id.clone(),
+                uuid: uuid_client,
+                code: None,
+                auth_token: None,
+                redirect_url: Some(redirect_url.clone()),
+                callback_url: Some(callback_url),
+                provider: Some(provider_trait_object),
+                name: None,
+                email: None,
+                op: request.op.clone(),
+                created_at: Instant::now(),
+            },
+        )
+        .await;
+
+    Json(OidcAuthUrl {
+        url: redirect_url.clone(),
+        code: uuid_code,
+    })
 }
-/// # List the rules
+
+/// # OIDC Auth Callback
 ///
-/// This function is an API endpoint that lists the rules attached to a shared address book.
-/// It is tagged with "address book" for OpenAPI documentation.
+/// This function is an API endpoint that serves as the OAuth2 callback.
+/// It exchanges the authorization code for an access token and stores it in the state.
+/// It is tagged with "login" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `current`: The current page number for pagination. This parameter is currently unused.
-///
-/// - `pageSize`: The number of items per page for pagination. This parameter is currently unused.
+/// - `code`: The authorization code received from the OIDC provider.  
 ///
-/// - `ab`: The identifier of the shared address book.
+/// - `state`: The state parameter received from the OIDC provider. This is the session code.  
 ///
 /// ## Returns
 ///
-/// If successful, this function returns a `Json<AbRulesResponse>` object containing the rules for the address book.  <br>
-/// If the address book does not exist or the user is not authorized to access it, this function returns a `status::Unauthorized` error.  <br>
+/// If successful, this function returns an HTML page telling the user that the login succeeded, it closes itself when opened as a popup.  <br>
+/// If the session does not exist or the code exchange fails, this function returns an HTML page telling the user that the login failed.  <br>
+/// Both pages post a `{type: "sctgdesk-oidc", status}` message to the opener window.
+/// They can be replaced with the `OIDC_CALLBACK_SUCCESS_PAGE` and `OIDC_CALLBACK_ERROR_PAGE` environment variables.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode, or if the address book does not exist or the user is not authorized to access it.
+/// This function will return an error if the system is in maintenance mode, or if the session does not exist or the code exchange fails.
 ///
-#[openapi(tag = "address book")]
-#[get("/api/ab/rules?<current>&<pageSize>&<ab>", format = "application/json")]
-async fn ab_rules(
-    state: &State<ApiState>,
-    _user: AuthenticatedUser,
-    current: u32,
-    #[allow(unused_variables)] pageSize: u32,
-    ab: &str,
-) -> Result<Json<AbRulesResponse>, status::Unauthorized<()>> {
-    state.check_maintenance().await;
-    let current = if (current < 1) { 0 } else { current - 1 };
-    let rules = state.get_ab_rules(current, pageSize, ab).await;
-    if rules.is_none() {
-        return Err(status::Unauthorized::<()>(()));
-    }
-    let rules = rules.unwrap();
-    let response = AbRulesResponse {
-        msg: "success".to_string(),
-        total: rules.len() as u32,
-        data: rules,
-    };
-    Ok(Json(response))
+/// # Example
+///
+/// GET /api/oidc/callback?code=authorization_code&state=session_code
+#[openapi(tag = "login")]
+#[get("/api/oidc/callback?<code>&<state>")]
+async fn oidc_callback(apistate: &State<ApiState>, code: &str, state: &str) -> RawHtml<String> {
+    let oidc_code = state; // this is the session code
+    let oidc_authorization_code = code;
+    let updated_oidc_session = apistate
+        .oidc_session_exchange_code(oidc_authorization_code.to_string(), oidc_code.to_string())
+        .await;
+    RawHtml(oidc_callback_page(updated_oidc_session.is_some()))
 }
 
-/// # Add a Rule
+/// # OIDC State
 ///
-/// This function is an API endpoint that adds a new rule to a shared address book.
-/// It is tagged with "address book" for OpenAPI documentation.
+/// This function is an API endpoint that checks the state of an OpenID Connect (OIDC) session.
+/// It is tagged with "login" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `request`: The request containing the details of the rule to be added.
+/// - `code`: The authorization code received from the OIDC provider.  
+///
+/// - `id`: The identifier of the OIDC session.  
+///
+/// - `uuid`: The UUID of the OIDC session.  
 ///
 /// ## Returns
 ///
-/// If successful, this function returns an `ActionResponse::Empty` indicating that the rule was successfully added. <br>
-/// If the system is in maintenance mode, this function returns a `status::Unauthorized` error.
+/// If successful, this function returns a `Json<Option<OidcResponse>>` object.  <br>
+/// If the session does not exist, this function returns `Json(None)`.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode.
-#[openapi(tag = "address book")]
-#[post("/api/ab/rule", format = "application/json", data = "<request>")]
-async fn ab_rule_add(
+/// This function will return an error if the system is in maintenance mode, or if the session does not exist.
+///
+/// # Example
+///
+/// GET /api/oidc/auth-query?code=authorization_code&id=session_id&uuid=session_uuid
+#[openapi(tag = "login")]
+#[get("/api/oidc/auth-query?<code>&<id>&<uuid>")]
+async fn oidc_state(
     state: &State<ApiState>,
-    _user: AuthenticatedAdmin,
-    request: Json<AbRuleAddRequest>,
-) -> Result<ActionResponse, status::Unauthorized<()>> {
-    state.check_maintenance().await;
-    let rule = AbRule {
-        guid: request.0.guid,
-        user: request.0.user,
-        group: request.0.group,
-        rule: request.0.rule,
+    code: &str,
+    id: &str,
+    uuid: &str,
+) -> Json<Option<OidcResponse>> {
+    log::debug!("oidc_state: {:?} {:?}", id, uuid);
+
+    let res = state.oidc_check_session(code.to_string()).await;
+
+    if res.is_none() {
+        return Json(None);
+    }
+
+    let (token, username, userinfo) = res.unwrap();
+    let auth_response = OidcResponse {
+        access_token: token.to_base64(),
+        type_field: "access_token".to_string(),
+        tfa_type: "".to_string(),
+        secret: "".to_string(),
+        user: OidcUser {
+            name: username,
+            email: "".to_string(),
+            note: "".to_string(),
+            status: OidcUserStatus::Normal.into(),
+            info: OidcUserInfo {
+                email_verification: userinfo.email_verified,
+                email_alarm_notification: false,
+                login_device_whitelist: Vec::<String>::new(),
+                other: HashMap::<String, String>::new(),
+            },
+            is_admin: userinfo.admin,
+            third_auth_type: "Oauth2".to_string(),
+        },
     };
-    state.add_ab_rule(rule).await;
-    Ok(ActionResponse::Empty)
+
+    Json(Some(auth_response))
 }
 
-/// # Delete a Rule
+/// # Get Personal Address Book
 ///
-/// This function is an API endpoint that deletes a rule from a shared address book.
+/// This function is an API endpoint that retrieves the personal address book of the authenticated user.
 /// It is tagged with "address book" for OpenAPI documentation.
 ///
 /// ## Parameters
 ///
-/// - `request`: The request containing the GUID of the rule to be deleted.
+/// - none
 ///
 /// ## Returns
 ///
-/// If successful, this function returns an `ActionResponse::Empty` indicating that the rule was successfully deleted. <br>
-/// If the system is in maintenance mode, this function returns a `status::Unauthorized` error.
+/// If successful, this function returns a `Json<AbPersonal>` object.  <br>
+/// If the user is not authorized to access their personal address book, this function returns a `status::Unauthorized` error.  <br>
 ///
 /// ## Errors
 ///
-/// This function will return an error if the system is in maintenance mode.
+/// This function will return an error if the system is in maintenance mode, or if the user is not authorized to access their personal address book.
+///
+/// # Example
+///
+/// POST /api/ab/personal
 #[openapi(tag = "address book")]
-#[delete("/api/ab/rule", format = "application/json", data = "<request>")]
-async fn ab_rule_delete(
+#[post("/api/ab/personal")]
+async fn ab_personal(
     state: &State<ApiState>,
-    _user: AuthenticatedAdmin,
-    request: Json<AbRuleDeleteRequest>,
-) -> Result<ActionResponse, status::Unauthorized<()>> {
+    user: AuthenticatedUser,
+) -> Result<Json<AbPersonal>, status::Unauthorized<()>> {
     state.check_maintenance().await;
-    let rule = request.0.guid;
-    state.delete_ab_rule(rule.as_str()).await;
-    Ok(ActionResponse::Empty)
+    let guid = state.get_ab_personal_guid(user.info.user_id.clone()).await;
+    if guid.is_none() {
+        return Err(status::Unauthorized::<()>(()));
+    }
+    let guid = guid.unwrap();
+    log::debug!("user: {:?} ab_personal: {:?}", user.info.user_id, guid);
+    let ab_personal = AbPersonal {
+        guid: guid,
+        error: None,
+    };
+    Ok(Json(ab_personal))
 }
 
-/// # Add shared profile
-///
-/// This function is an API endpoint that adds a shared profile to an address book.
-/// It is tagged with "address book" for OpenAPI documentation.
-/// 
+/// Check that the authenticated user has at least the `rule` access level on the address book `ab`
+/// Returns `Status::Forbidden` otherwise
+async fn check_ab_rule(
+    state: &State<ApiState>,
+    user: &AuthenticatedUser,
+    ab: &str,
+    rule: u32,
+) -> Result<(), Status> {
+    if state
+        .check_ab_rule(ab, user.info.user_id.clone(), rule)
+        .await
+    {
+        Ok(())
+    } else {
+        log::debug!("check_ab_rule: access to {} refused", ab);
+        Err(Status::Forbidden)
+    }
+}
+
+/// # Get the Tags
+///
+/// This function is an API endpoint that retrieves all tags from an address book.
+/// It is tagged with "address book" for OpenAPI documentation.
+///
 /// ## Parameters
-/// 
-/// - `request`: A JSON object containing the shared profile to be added.
-/// 
+///
+/// - `ab`: The identifier of the address book.  
+///
 /// ## Returns
-/// 
-/// If successful, this function returns a `Json<AbSharedProfilesResponse>` object containing the shared profiles in the address book.  <br>
+///
+/// If successful, this function returns a JSON array of `AbTag` objects.  <br>
+/// If the user does not have read access to the address book, this function returns a `Status::Forbidden` error.  <br>
+/// If the address book does not exist or the user is not authorized to access it, this function returns a `status::NotFound` error.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, or if the address book does not exist or the user is not authorized to access it.
+///
+/// # Example
+///
+/// POST /api/ab/tags/018fab24-0ae5-731c-be23-88aa4518ea26
 #[openapi(tag = "address book")]
-#[post("/api/ab/shared/add", format = "application/json", data = "<request>")]
-async fn ab_shared_add(
+#[post("/api/ab/tags/<ab>")]
+async fn ab_tags(
     state: &State<ApiState>,
-    user: AuthenticatedAdmin,
-    request: Json<AbSharedAddRequest>,
-) -> Result<Json<AbSharedProfilesResponse>, status::Unauthorized<()>> {
+    user: AuthenticatedUser,
+    ab: &str,
+) -> Result<Json<Vec<AbTag>>, Status> {
     state.check_maintenance().await;
-    let name = request.0.name;
-    let note = request.0.note;
-    let owner = guid_into_uuid(user.info.user_id.clone()).unwrap();
-    let ab_uuid = state.add_shared_address_book(name.as_str(),owner.as_str()).await;
-    if ab_uuid.is_none() {
-        return Err(status::Unauthorized::<()>(()));
+    check_ab_rule(state, &user, ab, AB_RULE_READ).await?;
+    let ab_tags = state.get_ab_tags(ab).await;
+    if ab_tags.is_none() {
+        return Err(Status::NotFound);
     }
-    let ab_uuid = ab_uuid.unwrap();
-    let shared_profile = AbProfile {
-        guid: ab_uuid,
-        name: name,
-        owner: owner,
-        rule: 3,
-        note: note,
-        ..Default::default()
-    };
-    let mut ab_shared_profiles = AbSharedProfilesResponse::default();
-    ab_shared_profiles.data.push(shared_profile);
-    Ok(Json(ab_shared_profiles))
+    let ab_tags = ab_tags.unwrap();
+    Ok(Json(ab_tags))
 }
 
-/// # Delete shared profiles
+/// # Get the Tags with their Usage
 ///
-/// This function is an API endpoint that deletes shared profiles from an address book.
+/// This function is an API endpoint that retrieves all tags from an address book with the number of peers using each of them.
 /// It is tagged with "address book" for OpenAPI documentation.
-/// 
+///
 /// ## Parameters
-/// 
-/// - `request`: A JSON object containing an array of shared profile GUIDs to be deleted.
-/// 
+///
+/// - `ab`: The identifier of the address book.  
+///
 /// ## Returns
-/// 
-/// If successful, this function returns an `ActionResponse::Empty` object.
+///
+/// If successful, this function returns a JSON array of `AbTagStats` objects sorted by name.  <br>
+/// If the user does not have read access to the address book, this function returns a `Status::Forbidden` error.  <br>
+/// If the address book does not exist, this function returns a `status::NotFound` error.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, or if the address book does not exist or the user is not authorized to access it.
+///
+/// # Example
+///
+/// GET /api/ab/tags/018fab24-0ae5-731c-be23-88aa4518ea26/stats
 #[openapi(tag = "address book")]
-#[delete("/api/ab/shared", format = "application/json", data = "<request>")]
-async fn ab_shared_delete(
+#[get("/api/ab/tags/<ab>/stats")]
+async fn ab_tags_stats(
     state: &State<ApiState>,
-    _user: AuthenticatedAdmin,
-    request: Json<Vec<String>>,
-) -> Result<ActionResponse, status::Unauthorized<()>> {
+    user: AuthenticatedUser,
+    ab: &str,
+) -> Result<Json<Vec<AbTagStats>>, Status> {
     state.check_maintenance().await;
-    let shared_profiles_to_delete = request.0;
-    state.delete_shared_address_books(shared_profiles_to_delete).await;
-    Ok(ActionResponse::Empty)
+    check_ab_rule(state, &user, ab, AB_RULE_READ).await?;
+    let ab_tags = state.get_ab_tags_stats(ab).await;
+    if ab_tags.is_none() {
+        return Err(Status::NotFound);
+    }
+    Ok(Json(ab_tags.unwrap()))
 }
 
-/// # Update shared profile name
-/// 
-/// This function is an API endpoint that updates the name of a shared profile in an address book.
+/// # Add a Tag
+///
+/// This function is an API endpoint that adds a new tag to an address book.
 /// It is tagged with "address book" for OpenAPI documentation.
-/// 
+///
 /// ## Parameters
-/// 
-/// - `request`: A JSON object containing the updated shared profile information.
-/// 
+///
+/// - `ab`: The identifier of the address book.  
+///
+/// - `request`: A JSON object containing the new tag to be added.
+///   The color is `#RRGGBB`, `#RGB`, an ARGB value or an index in the palette, a color is picked from the name when it is missing.  
+///
 /// ## Returns
-/// 
-/// If successful, this function returns a `Json<AbSharedProfilesResponse>` object containing the updated shared profile information.
+///
+/// If successful, this function returns an `ActionResponse::Empty` object.  <br>
+/// If the color is not valid, this function returns an `ActionResponse::Error` object.  <br>
+/// If the user does not have write access to the address book, this function returns a `Status::Forbidden` error.  <br>
+/// If the tag already exists or the user is not authorized to add it, this function returns a `status::Unauthorized` error.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, or if the tag already exists or the user is not authorized to add it.
+///
+/// # Example
+///
+/// POST /api/ab/tag/add/018fab24-0ae5-731c-be23-88aa4518ea26
+/// Content-Type: application/json
+///
+/// {"name": "tag1", "color": "#FF0000"}
 #[openapi(tag = "address book")]
-#[put("/api/ab/shared/update/profile", format = "application/json", data = "<request>")]
-async fn ab_shared_name(
+#[post(
+    "/api/ab/tag/add/<ab>",
+    format = "application/json",
+    data = "<request>"
+)]
+async fn ab_tag_add(
     state: &State<ApiState>,
-    user: AuthenticatedAdmin,
-    request: Json<AbSharedNameRequest>,
-) -> Result<Json<AbSharedProfilesResponse>, status::Unauthorized<()>> {
+    user: AuthenticatedUser,
+    ab: &str,
+    request: Json<AbTagRequest>,
+) -> Result<ActionResponse, Status> {
     state.check_maintenance().await;
-    let shared_profile = request.0;
-    let name = shared_profile.name.expect("Currently name is required");
-    state.update_shared_address_book(shared_profile.guid.as_str(), name.as_str()).await;
-    let shared_profiles = state.get_shared_address_books(user.info.user_id).await;
-    let mut ab_shared_profiles = AbSharedProfilesResponse::default();
-    for ab in shared_profiles.expect("shared_profiles is None") {
-        let address_book = AbProfile {
-            guid: ab.ab,
-            name: ab.name.unwrap_or("".to_string()),
-            owner: guid_into_uuid(ab.owner.expect("Invalid owner")).expect("Invalid GUID"),
-            rule: 3,
-            ..Default::default()
-        };
-        ab_shared_profiles.data.push(address_book);
-    }
-    Ok(Json(ab_shared_profiles))
-}
-
-async fn webconsole_index_multi() -> Redirect {
-    Redirect::to(uri!("/ui/"))
-}
-
-#[openapi(tag = "webconsole")]
-#[get("/index.html")]
-async fn webconsole_index_html() -> Redirect {
-    webconsole_index_multi().await
-}
-
-#[openapi(tag = "webconsole")]
-#[get("/")]
-async fn webconsole_index() -> Redirect {
-    webconsole_index_multi().await
-}
-
-const STATIC_DIR: Dir = include_dir!("webconsole/dist");
-#[derive(Debug)]
-struct StaticFileResponse(Vec<u8>, ContentType);
-
-#[async_trait]
-impl<'r> Responder<'r, 'r> for StaticFileResponse {
-    fn respond_to(self, _: &'r Request<'_>) -> rocket::response::Result<'static> {
-        Response::build()
-            .header(self.1)
-            .header(Header {
-                name: "Cache-Control".into(),
-                value: "max-age=604800".into(), // 1 week
-            })
-            .sized_body(self.0.len(), Cursor::new(self.0))
-            .ok()
-    }
-}
-
-#[get("/js/openapisnippet.min.js")]
-async fn openapi_snippet() -> Option<StaticFileResponse> {
-    let content = include_str!("../rapidoc/openapisnippet.min.js");
-    Some(StaticFileResponse(
-        content.as_bytes().to_vec(),
-        ContentType::JavaScript,
-    ))
+    check_ab_rule(state, &user, ab, AB_RULE_WRITE).await?;
+    let ab_tag = match request.0.into_tag() {
+        Ok(ab_tag) => ab_tag,
+        Err(e) => return Ok(ActionResponse::Error(e)),
+    };
+    log::debug!("ab_tag_add: {:?}", ab_tag);
+    state.add_ab_tag(ab, ab_tag).await;
+    Ok(ActionResponse::Empty)
 }
 
-#[get("/favicon.ico")]
-async fn favicon() -> Redirect {
-    Redirect::to(uri!("/ui/favicon.ico"))
+/// # Update a Tag
+///
+/// This function is an API endpoint that updates a tag in an address book.
+/// It is tagged with "address book" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `ab`: The identifier of the address book.  
+///
+/// - `request`: A JSON object containing the updated tag, its color is validated as in `/api/ab/tag/add`.  
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty` object.  <br>
+/// If the color is not valid, this function returns an `ActionResponse::Error` object.  <br>
+/// If the user does not have write access to the address book, this function returns a `Status::Forbidden` error.  <br>
+/// If the tag does not exist or the user is not authorized to update it, this function returns a `status::Unauthorized` error.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, or if the tag does not exist or the user is not authorized to update it.
+///
+/// # Example
+///
+/// PUT /api/ab/tag/update/018fab24-0ae5-731c-be23-88aa4518ea26
+/// Content-Type: application/json
+///
+/// {"name": "tag1", "color": "#FF0000"}
+#[openapi(tag = "address book")]
+#[put(
+    "/api/ab/tag/update/<ab>",
+    format = "application/json",
+    data = "<request>"
+)]
+async fn ab_tag_update(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    ab: &str,
+    request: Json<AbTagRequest>,
+) -> Result<ActionResponse, Status> {
+    state.check_maintenance().await;
+    check_ab_rule(state, &user, ab, AB_RULE_WRITE).await?;
+    let ab_tag = match request.0.into_tag() {
+        Ok(ab_tag) => ab_tag,
+        Err(e) => return Ok(ActionResponse::Error(e)),
+    };
+    log::debug!("ab_tag_update: {:?}", ab_tag);
+    state.add_ab_tag(ab, ab_tag).await;
+    Ok(ActionResponse::Empty)
 }
 
-/// Retrieves a static file from the webconsole/dist directory
+/// # Rename a Tag
 ///
-/// # Arguments
+/// This function is an API endpoint that renames a tag in an address book.
+/// The peers having the tag are updated in the same transaction.
+/// It is tagged with "address book" for OpenAPI documentation.
 ///
-/// * `path` - the path to the file relative to the webconsole/dist directory
+/// ## Parameters
 ///
-/// # Returns
+/// - `ab`: The identifier of the address book.
 ///
-/// * `Some(StaticFileResponse)` if the file exists, containing the file data and content type
-/// * `None` if the file does not exist
-#[get("/ui/<path..>")]
-async fn webconsole_vue(path: PathBuf) -> Option<StaticFileResponse> {
-    if env::var("VITE_DEVELOPMENT").is_ok() {
-        let vite_base = env::var("VITE_DEVELOPMENT").unwrap_or("http://localhost:5173".to_string());
-        let url = format!("{}/ui/{}", vite_base, path.to_str().unwrap_or(""));
-        let response = reqwest::get(&url).await.unwrap();
-        let content_type = response
-            .headers()
-            .get("content-type")
-            .unwrap()
-            .to_str()
-            .unwrap()
-            .parse::<ContentType>()
-            .unwrap();
-        let bytes = response.bytes().await.unwrap();
-        let response_content: Vec<u8> = bytes.iter().map(|byte| *byte).collect();
-        let content = StaticFileResponse(response_content, content_type);
-        return Some(content);
-    }
-
-    let path = path.to_str().unwrap_or("");
-    let file = STATIC_DIR.get_file(path).map(|file| {
-        let content_type = ContentType::from_extension(
-            file.path()
-                .extension()
-                .unwrap_or_default()
-                .to_str()
-                .unwrap(),
-        )
-        .unwrap_or(ContentType::Binary);
-        StaticFileResponse(file.contents().to_vec(), content_type)
-    });
-    if file.is_some() {
-        return file;
-    } else {
-        let file = STATIC_DIR.get_file("index.html").map(|file| {
-            let content_type = ContentType::from_extension(
-                file.path()
-                    .extension()
-                    .unwrap_or_default()
-                    .to_str()
-                    .unwrap(),
-            )
-            .unwrap_or(ContentType::Binary);
-            StaticFileResponse(file.contents().to_vec(), content_type)
-        });
-        return file;
+/// - `request`: A JSON object containing the old and new names of the tag.
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty` object.  <br>
+/// If the user does not have write access to the address book, this function returns a `Status::Forbidden` error.  <br>
+/// If the tag does not exist or the user is not authorized to access it, this function returns a `status::Unauthorized` error.  <br>
+/// If the tag cannot be renamed (e.g. the new name is already used), this function returns an `ActionResponse::Error` object.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, or if the tag does not exist or the user is not authorized to access it.
+///
+/// # Example
+///
+/// PUT /api/ab/tag/rename/018fab24-0ae5-731c-be23-88aa4518ea26
+/// Content-Type: application/json
+///
+/// {"old": "tag1", "new": "tag2"}
+#[openapi(tag = "address book")]
+#[put(
+    "/api/ab/tag/rename/<ab>",
+    format = "application/json",
+    data = "<request>"
+)]
+async fn ab_tag_rename(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    ab: &str,
+    request: Json<AbTagRenameRequest>,
+) -> Result<ActionResponse, Status> {
+    state.check_maintenance().await;
+    check_ab_rule(state, &user, ab, AB_RULE_WRITE).await?;
+    let ab_tag_old_name = request.0.old;
+    let ab_tag_new_name = request.0.new;
+
+    let ab_tag_old = state.get_ab_tag(ab, ab_tag_old_name.as_str()).await;
+    if ab_tag_old.is_none() {
+        return Err(Status::Unauthorized);
+    }
+    let mut ab_tag_new = ab_tag_old.unwrap();
+    ab_tag_new.name = ab_tag_new_name;
+    let res = state
+        .rename_ab_tag(ab, ab_tag_old_name.as_str(), ab_tag_new)
+        .await;
+    if res.is_none() {
+        return Ok(ActionResponse::Error("Failed to rename the tag".to_string()));
+    }
+    Ok(ActionResponse::Empty)
+}
+
+/// # Delete a Tag
+///
+/// This function is an API endpoint that deletes a tag from an address book.
+/// The deleted tags are also removed from the peers of the address book, in the same transaction.
+/// It is tagged with "address book" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `ab`: The identifier of the address book.  
+///
+/// - `request`: A JSON object containing an array of tag names to be deleted.  
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty` object.  <br>
+/// If the user does not have write access to the address book, this function returns a `Status::Forbidden` error.  <br>
+/// If the request is empty or the user is not authorized to access it, this function returns a `status::Unauthorized` error.  <br>
+/// If the tags cannot be deleted, this function returns an `ActionResponse::Error` object.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, or if the request is empty or the user is not authorized to access it.
+///
+/// # Example
+///
+/// DELETE /api/ab/tag/018fab24-0ae5-731c-be23-88aa4518ea26
+/// Content-Type: application/json
+///
+/// ["tag1", "tag2"]
+#[openapi(tag = "address book")]
+#[delete("/api/ab/tag/<ab>", format = "application/json", data = "<request>")]
+async fn ab_tag_delete(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    ab: &str,
+    request: Json<Vec<String>>,
+) -> Result<ActionResponse, Status> {
+    if request.0.is_empty() {
+        return Err(Status::Unauthorized);
+    }
+    let tags_to_delete = request.0;
+    state.check_maintenance().await;
+    check_ab_rule(state, &user, ab, AB_RULE_WRITE).await?;
+    if state.delete_ab_tags(ab, tags_to_delete).await.is_none() {
+        return Ok(ActionResponse::Error("Failed to delete the tags".to_string()));
+    }
+    Ok(ActionResponse::Empty)
+}
+
+/// # Get Shared Profiles
+///
+/// This function is an API endpoint that retrieves the shared profiles from an address book.
+/// It is tagged with "address book" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - none
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<AbSharedProfilesResponse>` object containing the shared profiles in the address book.  <br>
+/// rule: 1: read, 2: write, 3: full control  <br>
+/// If the address book does not exist or the user is not authorized to access it, this function returns a `status::Unauthorized` error.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, or if the address book does not exist or the user is not authorized to access it.
+///
+/// # Example
+///
+/// {"data":[{"guid":"018fab24-0ae5-731c-be23-88aa4518ea26","name":"shared profile","owner":"admin","rule":3}],"total":2}
+#[openapi(tag = "address book")]
+#[post("/api/ab/shared/profiles")]
+async fn ab_shared(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+) -> Result<Json<AbSharedProfilesResponse>, status::Unauthorized<()>> {
+    state.check_maintenance().await;
+    let shared_address_books = state.get_shared_address_books(user.info.user_id).await;
+    let mut ab_shared_profiles = AbSharedProfilesResponse::default();
+    for ab in shared_address_books.expect("shared_address_books is None") {
+        let address_book = AbProfile {
+            guid: ab.ab,
+            name: ab.name.unwrap_or("".to_string()),
+            owner: guid_into_uuid(ab.owner.expect("Invalid owner")).expect("Invalid GUID"),
+            rule: ab.rule.unwrap_or(AB_RULE_READ),
+            ..Default::default()
+        };
+        ab_shared_profiles.data.push(address_book);
+    }
+    ab_shared_profiles.total = ab_shared_profiles.data.len() as u32;
+    Ok(Json(ab_shared_profiles))
+}
+
+/// # Settings
+///
+/// This function is an API endpoint that retrieves the settings for an address book.<br>
+/// TODO: Implement the settings for an address book.
+#[openapi(tag = "address book")]
+#[post("/api/ab/settings")]
+async fn ab_settings(
+    state: &State<ApiState>,
+    _user: AuthenticatedUser,
+) -> Result<Json<AbSettingsResponse>, status::Unauthorized<()>> {
+    state.check_maintenance().await;
+    let ab_settings = AbSettingsResponse {
+        error: None,
+        max_peer_one_ab: std::u32::MAX,
+    };
+    Ok(Json(ab_settings))
+}
+
+/// # List peers
+///
+/// This function is an API endpoint that lists the peers in an address book.
+/// It is tagged with "address book" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `current`: The current page number, from 1, ignored when `after` is given.
+///
+/// - `pageSize`: The number of items per page for pagination, `DEFAULT_PAGE_SIZE` (20) for 0, at most `MAX_PAGE_SIZE` (1000).
+///
+/// - `ab`: The identifier of the address book.
+///
+/// - `after`: The cursor returned in `next_cursor` by the previous page, empty for the first page.  
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<AbPeersResponse>` object containing the peers of the page and the page size used in `page_size`.  <br>
+/// Without `after`, `total` is the number of peers of the address book.  <br>
+/// The personal address book also lists, with the first page, the peers shared with the user by the share links it redeemed,
+/// until the links expire. They are not stored in the address book.  <br>
+/// When paging with `after`, `next_cursor` is the cursor of the next page, it is missing on the last page.  <br>
+/// If the cursor is not valid, this function returns a `Status::BadRequest` error.  <br>
+/// If the user does not have read access to the address book, this function returns a `Status::Forbidden` error.  <br>
+/// If the address book does not exist or the user is not authorized to access it, this function returns a `status::Unauthorized` error.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, or if the address book does not exist or the user is not authorized to access it.
+///
+#[openapi(tag = "address book")]
+#[post("/api/ab/peers?<current>&<pageSize>&<ab>&<after>")]
+async fn ab_peers(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    current: u32,
+    #[allow(non_snake_case)] pageSize: u32,
+    ab: &str,
+    after: Option<&str>,
+) -> Result<Json<AbPeersResponse>, Status> {
+    state.check_maintenance().await;
+    check_ab_rule(state, &user, ab, AB_RULE_READ).await?;
+    let page_size = effective_page_size(pageSize);
+    if let Some(after) = after {
+        let after = parse_after(after).map_err(|_| Status::BadRequest)?;
+        let (mut ab_peers, next) = state
+            .get_ab_peers_page(ab, after.as_ref(), page_size)
+            .await
+            .ok_or(Status::Unauthorized)?;
+        if after.is_none() {
+            add_share_link_peers(state, &user, ab, &mut ab_peers).await;
+        }
+        return Ok(Json(AbPeersResponse {
+            error: None,
+            total: ab_peers.len() as u32,
+            data: ab_peers,
+            next_cursor: next.map(|cursor| cursor.encode()),
+            page_size,
+        }));
+    }
+    let (mut ab_peers, mut total) = state
+        .get_ab_peers_offset(ab, current, page_size)
+        .await
+        .ok_or(Status::Unauthorized)?;
+    if current <= 1 {
+        let stored = ab_peers.len();
+        add_share_link_peers(state, &user, ab, &mut ab_peers).await;
+        total += (ab_peers.len() - stored) as u32;
+    }
+    let ab_peer_response = AbPeersResponse {
+        error: None,
+        total,
+        data: ab_peers,
+        next_cursor: None,
+        page_size,
+    };
+    Ok(Json(ab_peer_response))
+}
+
+/// # Add peer
+///
+/// This function is an API endpoint that adds a peer to an address book.
+///
+/// ## Parameters
+///
+/// - `ab`: The identifier of the address book.
+///
+/// - `request`: A JSON object containing the new peer information.
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty` object.  <br>
+/// If the peer id is not valid, this function returns an `ActionResponse::Error` object.  <br>
+/// If the user does not have write access to the address book, this function returns a `Status::Forbidden` error.  <br>
+/// If `AB_UNIQUE_ALIASES` is enabled and another peer of the address book has the same alias, this function returns a `Status::Conflict` error.  <br>
+#[openapi(tag = "address book")]
+#[post(
+    "/api/ab/peer/add/<ab>",
+    format = "application/json",
+    data = "<request>"
+)]
+async fn ab_peer_add(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    request: Json<AbPeer>,
+    ab: &str,
+) -> Result<ActionResponse, Status> {
+    let mut ab_peer = request.0;
+    state.check_maintenance().await;
+    check_ab_rule(state, &user, ab, AB_RULE_WRITE).await?;
+    ab_peer.id = match normalize_ab_peer_id(&ab_peer.id) {
+        Ok(id) => id,
+        Err(e) => return Ok(ActionResponse::Error(e)),
+    };
+    check_ab_alias(state, ab, &ab_peer).await?;
+    state.add_ab_peer(ab, ab_peer).await;
+    Ok(ActionResponse::Empty)
+}
+
+/// Add the peers shared with the user by its valid share links to the listing of its personal address book
+async fn add_share_link_peers(state: &State<ApiState>, user: &AuthenticatedUser, ab: &str, ab_peers: &mut Vec<AbPeer>) {
+    if state.get_ab_personal_guid(user.info.user_id.clone()).await.as_deref() != Some(ab) {
+        return;
+    }
+    for peer in state.get_share_link_peers(&user.info.user_id).await.unwrap_or_default() {
+        if !ab_peers.iter().any(|ab_peer| ab_peer.id == peer.id) {
+            ab_peers.push(peer);
+        }
+    }
+}
+
+/// Refuse an alias already used by another peer of the address book when `AB_UNIQUE_ALIASES` is enabled
+async fn check_ab_alias(state: &State<ApiState>, ab: &str, ab_peer: &AbPeer) -> Result<(), Status> {
+    match state
+        .find_ab_alias_conflict(ab, &ab_peer.id, ab_peer.alias.as_deref())
+        .await
+    {
+        Some(other) => {
+            log::debug!("check_ab_alias: the alias of {} is used by {}", ab_peer.id, other);
+            Err(Status::Conflict)
+        }
+        None => Ok(()),
+    }
+}
+
+/// # Update peer
+///
+/// This function is an API endpoint that updates a peer in an address book.
+///
+/// ## Parameters
+///
+/// - `ab`: The identifier of the address book.
+///
+/// - `request`: A JSON object containing the updated peer information.
+///   With its `version` the peer is only updated if it was not changed since this version was read,
+///   without it the last write wins.
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty` object and bumps the version of the peer.  <br>
+/// If the peer id is not valid, this function returns an `ActionResponse::Error` object.  <br>
+/// If the user does not have write access to the address book, this function returns a `Status::Forbidden` error.  <br>
+/// If the peer does not belong to the address book, this function returns a `Status::NotFound` error.  <br>
+/// If `AB_UNIQUE_ALIASES` is enabled and another peer of the address book has the same alias, this function returns a `Status::Conflict` error.  <br>
+/// If the `version` is not the current version of the peer, this function returns a `Status::Conflict` error.  <br>
+#[openapi(tag = "address book")]
+#[put(
+    "/api/ab/peer/update/<ab>",
+    format = "application/json",
+    data = "<request>"
+)]
+async fn ab_peer_update(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    request: Json<AbPeer>,
+    ab: &str,
+) -> Result<ActionResponse, Status> {
+    check_ab_rule(state, &user, ab, AB_RULE_WRITE).await?;
+    let mut ab_peer = request.0;
+    ab_peer.id = match normalize_ab_peer_id(&ab_peer.id) {
+        Ok(id) => id,
+        Err(e) => return Ok(ActionResponse::Error(e)),
+    };
+    // the peer must already belong to this address book
+    let old_ab_peer = state.get_ab_peer(ab, ab_peer.id.as_str()).await;
+    if old_ab_peer.is_none() {
+        return Err(Status::NotFound);
+    }
+    let old_ab_peer = old_ab_peer.unwrap();
+    ab_peer.hash = ab_peer.hash.or(old_ab_peer.hash);
+    ab_peer.password = ab_peer.password.or(old_ab_peer.password);
+    ab_peer.username = ab_peer.username.or(old_ab_peer.username);
+    ab_peer.hostname = ab_peer.hostname.or(old_ab_peer.hostname);
+    ab_peer.platform = ab_peer.platform.or(old_ab_peer.platform);
+    ab_peer.alias = ab_peer.alias.or(old_ab_peer.alias);
+    ab_peer.tags = ab_peer.tags.or(old_ab_peer.tags);
+    ab_peer.force_always_relay = ab_peer
+        .force_always_relay
+        .or(old_ab_peer.force_always_relay);
+    ab_peer.rdp_port = ab_peer.rdp_port.or(old_ab_peer.rdp_port);
+    ab_peer.rdp_username = ab_peer.rdp_username.or(old_ab_peer.rdp_username);
+    ab_peer.login_name = ab_peer.login_name.or(old_ab_peer.login_name);
+    ab_peer.same_server = ab_peer.same_server.or(old_ab_peer.same_server);
+    state.check_maintenance().await;
+    check_ab_alias(state, ab, &ab_peer).await?;
+    let expected_version = ab_peer.version;
+    match state.update_ab_peer(ab, ab_peer, expected_version).await {
+        Some(true) => Ok(ActionResponse::Empty),
+        Some(false) => Err(Status::Conflict),
+        None => Err(Status::InternalServerError),
+    }
+}
+
+/// # Delete peer
+///
+/// This function is an API endpoint that deletes a peer from an address book.
+///
+/// ## Parameters
+///
+/// - `ab`: The identifier of the address book.
+///
+/// - `request`: A JSON object containing an array of peer IDs to be deleted.
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty` object.  <br>
+/// If a peer id is not valid, this function returns an `ActionResponse::Error` object.  <br>
+/// If the user does not have write access to the address book, this function returns a `Status::Forbidden` error.  <br>
+#[openapi(tag = "address book")]
+#[delete("/api/ab/peer/<ab>", format = "application/json", data = "<request>")]
+async fn ab_peer_delete(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    ab: &str,
+    request: Json<Vec<String>>,
+) -> Result<ActionResponse, Status> {
+    if request.0.is_empty() {
+        return Err(Status::Unauthorized);
+    }
+    let peers_to_delete = request.0.iter().map(|id| normalize_ab_peer_id(id)).collect();
+    let peers_to_delete = match peers_to_delete {
+        Ok(peers_to_delete) => peers_to_delete,
+        Err(e) => return Ok(ActionResponse::Error(e)),
+    };
+    state.check_maintenance().await;
+    check_ab_rule(state, &user, ab, AB_RULE_WRITE).await?;
+    state.delete_ab_peer(ab, peers_to_delete).await;
+    Ok(ActionResponse::Empty)
+}
+
+/// # Tag peers
+///
+/// This function is an API endpoint that adds and removes tags on several peers of an address book at once.
+/// All the peers are updated in a single transaction, the peers which are not in the address book are ignored.
+///
+/// ## Parameters
+///
+/// - `ab`: The identifier of the address book.
+///
+/// - `request`: A JSON object containing the peer IDs, the tags to add and the tags to remove.
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<AbPeersTagResponse>` object with the number of peers modified.  <br>
+/// If a tag does not exist in the address book or a peer id is not valid, this function returns a `Status::BadRequest` error.  <br>
+/// If the user does not have write access to the address book, this function returns a `Status::Forbidden` error.  <br>
+///
+/// # Example
+///
+/// POST /api/ab/peers/tag/018f255622f77778a006702ca5c23715
+/// {"peer_ids":["123456789","987654321"],"add_tags":["office"],"remove_tags":["home"]}
+#[openapi(tag = "address book")]
+#[post("/api/ab/peers/tag/<ab>", format = "application/json", data = "<request>")]
+async fn ab_peers_tag(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    ab: &str,
+    request: Json<AbPeersTagRequest>,
+) -> Result<Json<AbPeersTagResponse>, Status> {
+    log::debug!("ab_peers_tag");
+    state.check_maintenance().await;
+    check_ab_rule(state, &user, ab, AB_RULE_WRITE).await?;
+
+    let request = request.0;
+    let ab_tags = state.get_ab_tags(ab).await.ok_or(Status::NotFound)?;
+    let all_exist = request
+        .add_tags
+        .iter()
+        .chain(request.remove_tags.iter())
+        .all(|tag| ab_tags.iter().any(|ab_tag| &ab_tag.name == tag));
+    if !all_exist {
+        return Err(Status::BadRequest);
+    }
+    let peer_ids = request
+        .peer_ids
+        .iter()
+        .map(|id| normalize_ab_peer_id(id))
+        .collect::<Result<Vec<String>, String>>()
+        .map_err(|_| Status::BadRequest)?;
+    let modified = state
+        .update_ab_peers_tags(ab, &peer_ids, &request.add_tags, &request.remove_tags)
+        .await
+        .ok_or(Status::BadRequest)?;
+    Ok(Json(AbPeersTagResponse { modified }))
+}
+
+/// # Merge duplicate peers
+///
+/// This function is an API endpoint that merges duplicates of a peer of an address book into it, e.g. after a reinstall gave the device a new id.
+/// The kept peer gets the union of the tags, and the values of the duplicates for its empty fields (alias, hostname, username...) and its note.
+/// The duplicates are then deleted, everything happens in a single transaction.
+///
+/// ## Parameters
+///
+/// - `ab`: The identifier of the address book.
+///
+/// - `request`: A JSON object containing the id of the peer to keep and the ids of the duplicates to merge into it.
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<AbPeersMergeResponse>` object with the number of duplicates deleted and the merged peer.  <br>
+/// If there is no duplicate, a peer id is not valid or the kept peer is in the duplicates, this function returns a `Status::BadRequest` error.  <br>
+/// If one of the peers is not in the address book, nothing is changed and this function returns a `Status::NotFound` error.  <br>
+/// If the user does not have write access to the address book, this function returns a `Status::Forbidden` error.  <br>
+///
+/// # Example
+///
+/// POST /api/ab/peers/merge/018f255622f77778a006702ca5c23715
+/// {"keep_id":"123456789","merge_ids":["987654321"]}
+#[openapi(tag = "address book")]
+#[post("/api/ab/peers/merge/<ab>", format = "application/json", data = "<request>")]
+async fn ab_peers_merge(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    ab: &str,
+    request: Json<AbPeersMergeRequest>,
+) -> Result<Json<AbPeersMergeResponse>, Status> {
+    log::debug!("ab_peers_merge");
+    state.check_maintenance().await;
+    check_ab_rule(state, &user, ab, AB_RULE_WRITE).await?;
+
+    let request = request.0;
+    let keep_id = normalize_ab_peer_id(&request.keep_id).map_err(|_| Status::BadRequest)?;
+    let mut merge_ids = Vec::new();
+    for id in &request.merge_ids {
+        let id = normalize_ab_peer_id(id).map_err(|_| Status::BadRequest)?;
+        if id == keep_id {
+            return Err(Status::BadRequest);
+        }
+        if !merge_ids.contains(&id) {
+            merge_ids.push(id);
+        }
+    }
+    if merge_ids.is_empty() {
+        return Err(Status::BadRequest);
+    }
+    let peer = state
+        .merge_ab_peers(ab, &keep_id, &merge_ids)
+        .await
+        .ok_or(Status::NotFound)?;
+    Ok(Json(AbPeersMergeResponse {
+        merged: merge_ids.len() as u32,
+        peer,
+    }))
+}
+
+/// # List strategies
+///
+/// This function is an API endpoint that allows an authenticated admin to retrieve a paginated list of strategies.
+/// It is tagged with "strategy" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `current`: The current page number, starting at 1 (default 1).  <br>
+///
+/// - `pageSize`: The number of strategies per page, 0 for all of them (default 0).  <br>
+///
+/// - `q`: Text the names of the listed strategies contain, case-insensitively (default all the strategies).  <br>
+///
+/// - `order`: `name`, `created_at` or `modified_at`, prefixed with `-` for the descending order
+/// (default the default strategy first then by name).  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<StrategiesResponse>` object, which includes a success message, the total number of matching strategies, and the strategies of the page.  <br>
+///
+/// ## Errors
+///
+/// This function returns a `Status::BadRequest` error if the order is not valid.  <br>
+/// This function will return an error if the system is in maintenance mode, or if the strategies cannot be read.
+///
+/// # Example
+///
+/// GET /api/strategies?current=1&pageSize=10&q=lock&order=-modified_at
+#[openapi(tag = "strategy")]
+#[get("/api/strategies?<current>&<pageSize>&<q>&<order>", format = "application/json")]
+async fn strategies(
+    state: &State<ApiState>,
+    _user: AuthenticatedAdmin,
+    current: Option<u32>,
+    #[allow(non_snake_case)] pageSize: Option<u32>,
+    q: Option<&str>,
+    order: Option<&str>,
+) -> Result<Json<StrategiesResponse>, status::Custom<String>> {
+    log::debug!("strategies");
+    state.check_maintenance().await;
+    let order = StrategyOrder::parse(order.unwrap_or_default())
+        .map_err(|message| status::Custom(Status::BadRequest, message))?;
+    let q = q.filter(|q| !q.is_empty());
+    let page_size = match pageSize.unwrap_or(0) {
+        0 => u32::MAX,
+        page_size => page_size,
+    };
+    let offset = current
+        .unwrap_or(1)
+        .saturating_sub(1)
+        .saturating_mul(page_size);
+    let (total, strategies) = state
+        .get_strategies(q, order, offset, page_size)
+        .await
+        .ok_or_else(|| status::Custom(Status::InternalServerError, String::new()))?;
+    Ok(Json(StrategiesResponse {
+        msg: "success".to_string(),
+        total,
+        data: strategies,
+    }))
+}
+
+/// # List strategies (deprecated path)
+///
+/// Misspelled path of `strategies` kept for the consoles and scripts written against it,
+/// it is not advertised in the OpenAPI specification.
+#[get("/api/stategies?<current>&<pageSize>&<q>&<order>", format = "application/json")]
+async fn strategies_deprecated(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    current: Option<u32>,
+    #[allow(non_snake_case)] pageSize: Option<u32>,
+    q: Option<&str>,
+    order: Option<&str>,
+) -> Result<Json<StrategiesResponse>, status::Custom<String>> {
+    log::warn!("/api/stategies is deprecated, use /api/strategies");
+    strategies(state, user, current, pageSize, q, order).await
+}
+
+/// # Get a strategy
+///
+/// This function is an API endpoint that allows an authenticated admin to retrieve a strategy.
+/// It is tagged with "strategy" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `guid`: The GUID of the strategy.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<Strategy>` object.  <br>
+/// If the strategy is not found, this function returns a `Status::NotFound` error.  <br>
+///
+/// # Example
+///
+/// GET /api/strategy/018f2556-2316-7a02-b31c-5599e7cd5b5e
+#[openapi(tag = "strategy")]
+#[get("/api/strategy/<guid>", format = "application/json")]
+async fn strategy_get(
+    state: &State<ApiState>,
+    _user: AuthenticatedAdmin,
+    guid: &str,
+) -> Result<Json<Strategy>, Status> {
+    log::debug!("strategy_get");
+    state.check_maintenance().await;
+    let strategy = state.get_strategy(guid).await.ok_or(Status::NotFound)?;
+    Ok(Json(strategy))
+}
+
+/// # Add a strategy
+///
+/// This function is an API endpoint that allows an authenticated admin to create a strategy.
+/// It is tagged with "strategy" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `request`: The name of the strategy, its settings and whether it becomes the default strategy.  <br>
+/// The client options of the settings are validated, the unknown ones are rejected.  <br>
+/// When the new strategy is the default one, the previous default strategy loses the flag.  <br>
+///
+/// - `allow_unknown`: Store the unknown options untouched instead of rejecting them, for newer clients (default false).  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns the new strategy as a `Json<Strategy>` object.  <br>
+///
+/// ## Errors
+///
+/// This function returns a `Status::BadRequest` error with the reason if the name is empty or already used,
+/// or if the settings have unknown or out-of-range options.
+///
+/// # Example
+///
+/// POST /api/strategy
+/// {"name":"Locked down","settings":{"enable-clipboard":"N","enable-file-transfer":"N"},"is_default":false}
+#[openapi(tag = "strategy")]
+#[post("/api/strategy?<allow_unknown>", format = "application/json", data = "<request>")]
+async fn strategy_add(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    request: Json<AddStrategyRequest>,
+    allow_unknown: Option<bool>,
+) -> Result<Json<Strategy>, status::Custom<String>> {
+    log::debug!("strategy_add");
+    state.check_maintenance().await;
+    let bad_request = |message: String| status::Custom(Status::BadRequest, message);
+
+    let request = request.into_inner();
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(bad_request("The strategy name is empty".to_string()));
+    }
+    validate_strategy_settings(&request.settings, allow_unknown.unwrap_or(false))
+        .map_err(bad_request)?;
+    let strategy = state
+        .add_strategy(name, &request.settings, request.is_default)
+        .await
+        .ok_or_else(|| bad_request(format!("The strategy {} already exists", name)))?;
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::StrategyCreate,
+            Some(strategy.guid.as_str()),
+            serde_json::json!(request),
+        )
+        .await;
+    Ok(Json(strategy))
+}
+
+/// # Duplicate a strategy
+///
+/// This function is an API endpoint that allows an authenticated admin to create a strategy with the settings of another one.
+/// The copy is not the default strategy and is assigned to no peer nor user.
+/// It is tagged with "strategy" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `guid`: The GUID of the copied strategy.  <br>
+///
+/// - `request`: The name of the copy, `<name> (copy)` when it is left out.
+/// When the name is already used, a numeric suffix is added: `<name> 2`, `<name> 3`...  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns the new strategy as a `Json<Strategy>` object.  <br>
+///
+/// ## Errors
+///
+/// This function returns a `Status::NotFound` error if the copied strategy does not exist,
+/// and a `Status::BadRequest` error if the name is empty.
+///
+/// # Example
+///
+/// POST /api/strategy/018f2556-2316-7a02-b31c-5599e7cd5b5e/duplicate
+/// {"name":"Finance"}
+#[openapi(tag = "strategy")]
+#[post("/api/strategy/<guid>/duplicate", format = "application/json", data = "<request>")]
+async fn strategy_duplicate(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    guid: &str,
+    request: Json<DuplicateStrategyRequest>,
+) -> Result<Json<Strategy>, status::Custom<String>> {
+    log::debug!("strategy_duplicate");
+    state.check_maintenance().await;
+    let request = request.into_inner();
+    let name = request.name.as_deref().map(str::trim);
+    if name == Some("") {
+        return Err(status::Custom(
+            Status::BadRequest,
+            "The strategy name is empty".to_string(),
+        ));
+    }
+    let strategy = state
+        .duplicate_strategy(guid, name)
+        .await
+        .ok_or_else(|| status::Custom(Status::NotFound, format!("Strategy {} not found", guid)))?;
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::StrategyCreate,
+            Some(strategy.guid.as_str()),
+            serde_json::json!({ "duplicated_from": guid, "name": strategy.name }),
+        )
+        .await;
+    Ok(Json(strategy))
+}
+
+/// # Update a strategy
+///
+/// This function is an API endpoint that allows an authenticated admin to update a strategy.
+/// It is tagged with "strategy" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `guid`: The GUID of the strategy.  <br>
+///
+/// - `request`: The new name, settings or default flag, the fields left out are not changed.  <br>
+/// The client options of the settings are validated, the unknown ones are rejected.  <br>
+/// Marking the strategy as the default one removes the flag from the previous default strategy.  <br>
+///
+/// - `allow_unknown`: Store the unknown options untouched instead of rejecting them, for newer clients (default false).  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns the updated strategy as a `Json<Strategy>` object.  <br>
+///
+/// ## Errors
+///
+/// This function returns a `Status::NotFound` error if the strategy does not exist.  <br>
+/// This function returns a `Status::BadRequest` error with the reason if the name is empty or already used,
+/// if the settings have unknown or out-of-range options, or if `is_default` is false for the default strategy: another strategy must be marked as default instead.
+///
+/// # Example
+///
+/// PUT /api/strategy/018f2556-2316-7a02-b31c-5599e7cd5b5e
+/// {"settings":{"enable-audio":"N"}}
+#[openapi(tag = "strategy")]
+#[put("/api/strategy/<guid>?<allow_unknown>", format = "application/json", data = "<request>")]
+async fn strategy_update(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    guid: &str,
+    request: Json<UpdateStrategyRequest>,
+    allow_unknown: Option<bool>,
+) -> Result<Json<Strategy>, status::Custom<String>> {
+    log::debug!("strategy_update");
+    state.check_maintenance().await;
+    let bad_request = |message: String| status::Custom(Status::BadRequest, message);
+
+    let request = request.into_inner();
+    let current = state
+        .get_strategy(guid)
+        .await
+        .ok_or_else(|| status::Custom(Status::NotFound, format!("Strategy {} not found", guid)))?;
+    if current.is_default && request.is_default == Some(false) {
+        return Err(bad_request(
+            "The default strategy keeps its flag until another strategy is marked as default".to_string(),
+        ));
+    }
+    let name = request.name.as_deref().map(str::trim);
+    if name == Some("") {
+        return Err(bad_request("The strategy name is empty".to_string()));
+    }
+    if let Some(settings) = &request.settings {
+        validate_strategy_settings(settings, allow_unknown.unwrap_or(false)).map_err(bad_request)?;
+    }
+    let strategy = state
+        .update_strategy(
+            guid,
+            name,
+            request.settings.as_ref(),
+            request.is_default.unwrap_or(false),
+        )
+        .await
+        .ok_or_else(|| bad_request(format!("The strategy {} already exists", name.unwrap_or_default())))?;
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::StrategyUpdate,
+            Some(guid),
+            serde_json::json!(request),
+        )
+        .await;
+    Ok(Json(strategy))
+}
+
+/// # Delete a strategy
+///
+/// This function is an API endpoint that allows an authenticated admin to delete a strategy.
+/// It is tagged with "strategy" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `guid`: The GUID of the strategy.  <br>
+///
+/// - `cascade`: Remove the assignments of the strategy to peers and users, which fall back to the next strategy of the resolution (default false).  <br>
+///
+/// - `dry_run`: If true, the checks are made but the strategy is not deleted and nothing is audited.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty` object.  <br>
+/// With `dry_run` it returns an `ActionResponse::DryRun` object with the strategy and its number of assignments.  <br>
+///
+/// ## Errors
+///
+/// This function returns a `Status::NotFound` error if the strategy does not exist.  <br>
+/// This function returns a `Status::Forbidden` error if the strategy is the default one, another strategy must be marked as default first.  <br>
+/// This function returns a `Status::BadRequest` error if the strategy is still assigned and `cascade` is not true.
+///
+/// # Example
+///
+/// DELETE /api/strategy/018f2556-2316-7a02-b31c-5599e7cd5b5e?cascade=true
+#[openapi(tag = "strategy")]
+#[delete("/api/strategy/<guid>?<cascade>&<dry_run>")]
+async fn strategy_delete(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    guid: &str,
+    cascade: Option<bool>,
+    dry_run: Option<bool>,
+) -> Result<ActionResponse, status::Custom<String>> {
+    log::debug!("strategy_delete");
+    state.check_maintenance().await;
+    let cascade = cascade.unwrap_or(false);
+    let strategy = state
+        .get_strategy(guid)
+        .await
+        .ok_or_else(|| status::Custom(Status::NotFound, format!("Strategy {} not found", guid)))?;
+    if strategy.is_default {
+        return Err(status::Custom(
+            Status::Forbidden,
+            "The default strategy cannot be deleted".to_string(),
+        ));
+    }
+    let assignments = state.count_strategy_assignments(guid).await.ok_or_else(|| {
+        status::Custom(Status::InternalServerError, "Failed to count the assignments".to_string())
+    })?;
+    if assignments > 0 && !cascade {
+        return Err(status::Custom(
+            Status::BadRequest,
+            format!(
+                "The strategy is assigned to {} peers or users, delete it with cascade=true to remove the assignments",
+                assignments
+            ),
+        ));
+    }
+    let details = serde_json::json!({ "name": strategy.name, "cascade": cascade, "assignments": assignments });
+    if dry_run.unwrap_or(false) {
+        return Ok(ActionResponse::DryRun(DryRunResponse::new(vec![strategy.guid], details)));
+    }
+    state.delete_strategy(guid, cascade).await.ok_or_else(|| {
+        status::Custom(Status::InternalServerError, "Failed to delete the strategy".to_string())
+    })?;
+    state
+        .audit_admin_action(&user.info.user_id, AdminAction::StrategyDelete, Some(guid), details)
+        .await;
+    Ok(ActionResponse::Empty)
+}
+
+/// # Assign a strategy to a peer
+///
+/// This function is an API endpoint that allows an authenticated admin to assign a strategy to a peer.
+/// The strategy of a peer applies before the one of its user, the ones of the groups and the default one.
+/// The assignment change is recorded in the console audit.
+/// It is tagged with "strategy" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `id`: The id of the peer.  <br>
+///
+/// - `request`: The GUID of the strategy, or null to remove the assignment.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns the effective strategy of the peer as a `Json<EffectiveStrategy>` object.  <br>
+///
+/// ## Errors
+///
+/// This function returns a `Status::NotFound` error if the peer or the strategy does not exist.
+///
+/// # Example
+///
+/// PUT /api/peer/123456789/strategy
+/// {"strategy":"018f2556-2316-7a02-b31c-5599e7cd5b5e"}
+#[openapi(tag = "strategy")]
+#[put("/api/peer/<id>/strategy", format = "application/json", data = "<request>")]
+async fn peer_strategy(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    id: &str,
+    request: Json<AssignStrategyRequest>,
+) -> Result<Json<EffectiveStrategy>, Status> {
+    log::debug!("peer_strategy");
+    state.check_maintenance().await;
+    let id = normalize_peer_id(id).map_err(|_| Status::BadRequest)?;
+    let request = request.into_inner();
+    if let Some(strategy) = request.strategy.as_deref() {
+        state.get_strategy(strategy).await.ok_or(Status::NotFound)?;
+    }
+    state
+        .assign_peer_strategy(&id, request.strategy.as_deref())
+        .await
+        .ok_or(Status::NotFound)?;
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::PeerStrategyAssign,
+            Some(id.as_str()),
+            serde_json::json!(request),
+        )
+        .await;
+    let effective = state
+        .get_effective_strategy(&id)
+        .await
+        .ok_or(Status::InternalServerError)?;
+    Ok(Json(effective))
+}
+
+/// # Assign a strategy to a user
+///
+/// This function is an API endpoint that allows an authenticated admin to assign a strategy to a user.
+/// The strategy of a user applies to its peers without their own strategy, before the ones of the groups and the default one.
+/// The assignment change is recorded in the console audit.
+/// It is tagged with "strategy" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `id`: The GUID of the user.  <br>
+///
+/// - `request`: The GUID of the strategy, or null to remove the assignment.  <br>
+///
+/// ## Errors
+///
+/// This function returns a `Status::NotFound` error if the user or the strategy does not exist.
+///
+/// # Example
+///
+/// PUT /api/users/018f2556-2301-79eb-91a2-cffe5ced4236/strategy
+/// {"strategy":"018f2556-2316-7a02-b31c-5599e7cd5b5e"}
+#[openapi(tag = "strategy")]
+#[put("/api/users/<id>/strategy", format = "application/json", data = "<request>")]
+async fn user_strategy(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    id: &str,
+    request: Json<AssignStrategyRequest>,
+) -> Result<(), Status> {
+    log::debug!("user_strategy");
+    state.check_maintenance().await;
+    let request = request.into_inner();
+    if let Some(strategy) = request.strategy.as_deref() {
+        state.get_strategy(strategy).await.ok_or(Status::NotFound)?;
+    }
+    state
+        .assign_user_strategy(id, request.strategy.as_deref())
+        .await
+        .ok_or(Status::NotFound)?;
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::UserStrategyAssign,
+            Some(id),
+            serde_json::json!(request),
+        )
+        .await;
+    Ok(())
+}
+
+/// # Assign a strategy to a group
+///
+/// This function is an API endpoint that allows an authenticated admin to assign a strategy to a group.
+/// The strategy of a group applies to the peers of the group and to the peers of its users,
+/// after the strategies of the peer and of its user and before the default one.
+/// The peers pick the change up on their next heartbeat.
+/// The assignment change is recorded in the console audit.
+/// It is tagged with "strategy" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `guid`: The GUID of the group.  <br>
+///
+/// - `request`: The GUID of the strategy, or null to remove the assignment.  <br>
+///
+/// ## Errors
+///
+/// This function returns a `Status::NotFound` error if the group or the strategy does not exist.
+///
+/// # Example
+///
+/// PUT /api/group/018f2556-22fb-73ee-9afd-bbcdc0cc387b/strategy
+/// {"strategy":"018f2556-2316-7a02-b31c-5599e7cd5b5e"}
+#[openapi(tag = "strategy")]
+#[put("/api/group/<guid>/strategy", format = "application/json", data = "<request>")]
+async fn group_strategy(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    guid: &str,
+    request: Json<AssignStrategyRequest>,
+) -> Result<(), Status> {
+    log::debug!("group_strategy");
+    state.check_maintenance().await;
+    let request = request.into_inner();
+    if let Some(strategy) = request.strategy.as_deref() {
+        state.get_strategy(strategy).await.ok_or(Status::NotFound)?;
+    }
+    state
+        .assign_group_strategy(guid, request.strategy.as_deref())
+        .await
+        .ok_or(Status::NotFound)?;
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::GroupStrategyAssign,
+            Some(guid),
+            serde_json::json!(request),
+        )
+        .await;
+    Ok(())
+}
+
+/// # Get the permissions of a user
+///
+/// This function is an API endpoint that returns what a user can do, computed from its role,
+/// its group and the rules of the shared address books.
+/// An admin can get the permissions of any user, the other users only their own ones.
+/// It is tagged with "user" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `id`: The GUID of the user, or `me` for the current user.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<UserPermissions>` object, which includes the admin flag, the groups of the user,
+/// the shared address books it can access with its rule on each one, the operations it can make as an admin and its quotas.  <br>
+///
+/// ## Errors
+///
+/// This function returns a `Status::Forbidden` error if a non admin user asks for the permissions of another user,
+/// and a `Status::NotFound` error if the user does not exist.
+///
+/// # Example
+///
+/// GET /api/user/018f2556-2301-79eb-91a2-cffe5ced4236/permissions
+#[openapi(tag = "user")]
+#[get("/api/user/<id>/permissions", format = "application/json")]
+async fn user_permissions(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    id: &str,
+) -> Result<Json<UserPermissions>, Status> {
+    log::debug!("user_permissions");
+    state.check_maintenance().await;
+    let user_id = match id {
+        "me" => user.info.user_id.clone(),
+        id => uuid_into_guid(id).ok_or(Status::NotFound)?,
+    };
+    if user_id != user.info.user_id
+        && !state
+            .is_current_user_admin(&user.info)
+            .await
+            .unwrap_or(false)
+    {
+        return Err(Status::Forbidden);
+    }
+    let mut permissions = state
+        .get_user_permissions(&user_id)
+        .await
+        .ok_or(Status::NotFound)?;
+    if permissions.is_admin {
+        permissions.quotas.peers_bulk_max_size = Some(peers_bulk_max_size() as u32);
+    }
+    Ok(Json(permissions))
+}
+
+/// Largest UI settings blob accepted by `/api/settings/ui` and `/api/settings/ui/defaults`
+const UI_SETTINGS_MAX_SIZE: ByteUnit = ByteUnit::Byte(utils::ui_settings::UI_SETTINGS_MAX_SIZE as u64);
+
+/// Largest settings blob accepted by `/api/user/settings`
+const CLIENT_SETTINGS_MAX_SIZE: ByteUnit = ByteUnit::Kibibyte(16);
+
+/// Read a settings blob, it must be valid JSON and fit in `limit`
+async fn read_settings(data: Data<'_>, limit: ByteUnit) -> Result<serde_json::Value, status::Custom<String>> {
+    let body = data.open(limit).into_bytes().await.map_err(|e| {
+        status::Custom(Status::BadRequest, format!("the settings cannot be read: {}", e))
+    })?;
+    if !body.is_complete() {
+        return Err(status::Custom(
+            Status::PayloadTooLarge,
+            format!("the settings are larger than {}", limit),
+        ));
+    }
+    serde_json::from_slice(&body.into_inner())
+        .map_err(|e| status::Custom(Status::BadRequest, format!("the settings are not valid JSON: {}", e)))
+}
+
+/// # Get the UI settings of the current user
+///
+/// This function is an API endpoint that returns the preferences of the web console stored for the current user,
+/// such as the theme, the page size or the visible columns, so they are shared between its browsers.
+/// The settings are opaque to the server, the keys of the user replace the ones of the defaults set by an admin.
+/// It is tagged with "user" for OpenAPI documentation.
+///
+/// ## Returns
+///
+/// If successful, this function returns the settings of the user merged over the defaults, `{}` if there are none.  <br>
+///
+/// # Example
+///
+/// GET /api/settings/ui
+#[openapi(tag = "user")]
+#[get("/api/settings/ui")]
+async fn ui_settings(state: &State<ApiState>, user: AuthenticatedUser) -> Json<serde_json::Value> {
+    log::debug!("ui_settings");
+    state.check_maintenance().await;
+    Json(state.get_ui_settings(user.info.user_id).await)
+}
+
+/// # Store the UI settings of the current user
+///
+/// This function is an API endpoint that replaces the preferences of the web console stored for the current user.
+/// It is tagged with "user" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - the body is any JSON value of at most 4 KiB, an object is merged over the defaults when it is read.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty`.  <br>
+///
+/// ## Errors
+///
+/// * a `Status::BadRequest` error if the body is not valid JSON
+/// * a `Status::PayloadTooLarge` error if the body is larger than 4 KiB
+///
+/// # Example
+///
+/// PUT /api/settings/ui
+/// {
+///     "theme": "dark",
+///     "page_size": 50
+/// }
+#[openapi(tag = "user")]
+#[put("/api/settings/ui", data = "<data>")]
+async fn ui_settings_update(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    data: Data<'_>,
+) -> Result<ActionResponse, status::Custom<String>> {
+    log::debug!("ui_settings_update");
+    state.check_maintenance().await;
+    let settings = read_settings(data, UI_SETTINGS_MAX_SIZE).await?;
+    state
+        .set_user_ui_settings(user.info.user_id, &settings)
+        .await
+        .ok_or_else(|| status::Custom(Status::InternalServerError, "the settings cannot be stored".to_string()))?;
+    Ok(ActionResponse::Empty)
+}
+
+/// # Get the default UI settings
+///
+/// This function is an API endpoint that allows an authenticated admin to retrieve the default preferences of the web console,
+/// the users see them under their own settings.
+/// It is tagged with "admin" for OpenAPI documentation.
+///
+/// ## Returns
+///
+/// If successful, this function returns the defaults as they were stored, `{}` if there are none.  <br>
+///
+/// # Example
+///
+/// GET /api/settings/ui/defaults
+#[openapi(tag = "admin")]
+#[get("/api/settings/ui/defaults")]
+async fn ui_defaults(state: &State<ApiState>, _user: AuthenticatedAdmin) -> Json<serde_json::Value> {
+    log::debug!("ui_defaults");
+    state.check_maintenance().await;
+    Json(state.get_ui_defaults().await.unwrap_or_else(|| serde_json::json!({})))
+}
+
+/// # Store the default UI settings
+///
+/// This function is an API endpoint that allows an authenticated admin to replace the default preferences of the web console.
+/// The change is recorded in the admin audit.
+/// It is tagged with "admin" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - the body is any JSON value of at most 4 KiB.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty`.  <br>
+///
+/// ## Errors
+///
+/// * a `Status::BadRequest` error if the body is not valid JSON
+/// * a `Status::PayloadTooLarge` error if the body is larger than 4 KiB
+///
+/// This function will also return an error if the system is in maintenance mode, or if the user is not an admin.
+///
+/// # Example
+///
+/// PUT /api/settings/ui/defaults
+/// {
+///     "theme": "light",
+///     "page_size": 20
+/// }
+#[openapi(tag = "admin")]
+#[put("/api/settings/ui/defaults", data = "<data>")]
+async fn ui_defaults_update(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    data: Data<'_>,
+) -> Result<ActionResponse, status::Custom<String>> {
+    log::debug!("ui_defaults_update");
+    state.check_maintenance().await;
+    let defaults = read_settings(data, UI_SETTINGS_MAX_SIZE).await?;
+    state
+        .set_ui_defaults(&defaults)
+        .await
+        .ok_or_else(|| status::Custom(Status::InternalServerError, "the settings cannot be stored".to_string()))?;
+    state
+        .audit_admin_action(&user.info.user_id, AdminAction::UiDefaultsUpdate, None, defaults)
+        .await;
+    Ok(ActionResponse::Empty)
+}
+
+/// # Get the settings synced by the clients of the current user
+///
+/// This function is an API endpoint that returns the settings the RustDesk clients of the current user stored on the server,
+/// so its preferences follow it from one device to another.
+/// It is tagged with "user" for OpenAPI documentation.
+///
+/// ## Returns
+///
+/// If successful, this function returns the settings as they were stored, `{}` if there are none.  <br>
+///
+/// # Example
+///
+/// GET /api/user/settings
+#[openapi(tag = "user")]
+#[get("/api/user/settings")]
+async fn user_settings(state: &State<ApiState>, user: AuthenticatedUser) -> Json<serde_json::Value> {
+    log::debug!("user_settings");
+    state.check_maintenance().await;
+    Json(
+        state
+            .get_user_client_settings(user.info.user_id)
+            .await
+            .unwrap_or_else(|| serde_json::json!({})),
+    )
+}
+
+/// # Store the settings synced by the clients of the current user
+///
+/// This function is an API endpoint that replaces the settings the RustDesk clients of the current user stored on the server.
+/// The settings are opaque to the server.
+/// It is tagged with "user" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - the body is any JSON value of at most 16 KiB.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty`.  <br>
+///
+/// ## Errors
+///
+/// * a `Status::BadRequest` error if the body is not valid JSON
+/// * a `Status::PayloadTooLarge` error if the body is larger than 16 KiB
+///
+/// # Example
+///
+/// POST /api/user/settings
+/// {
+///     "theme": "dark",
+///     "allow-auto-disconnect": "Y"
+/// }
+#[openapi(tag = "user")]
+#[post("/api/user/settings", data = "<data>")]
+async fn user_settings_update(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    data: Data<'_>,
+) -> Result<ActionResponse, status::Custom<String>> {
+    log::debug!("user_settings_update");
+    state.check_maintenance().await;
+    let settings = read_settings(data, CLIENT_SETTINGS_MAX_SIZE).await?;
+    state
+        .set_user_client_settings(user.info.user_id, &settings)
+        .await
+        .ok_or_else(|| status::Custom(Status::InternalServerError, "the settings cannot be stored".to_string()))?;
+    Ok(ActionResponse::Empty)
+}
+
+/// # List the OAuth providers linked to the current user
+///
+/// This function is an API endpoint that returns the OAuth providers the current user has logged in with.
+/// It is tagged with "user" for OpenAPI documentation.
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<LinkedProvidersResponse>` with the providers sorted by op,
+/// each one with the first and the last login through it.  <br>
+/// `password_login` is false if the account was created by an OAuth login and has no password set.  <br>
+///
+/// # Example
+///
+/// GET /api/user/linked-providers
+#[openapi(tag = "user")]
+#[get("/api/user/linked-providers")]
+async fn linked_providers(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+) -> Result<Json<LinkedProvidersResponse>, status::NotFound<()>> {
+    log::debug!("linked_providers");
+    state.check_maintenance().await;
+    let (password_login, providers) = state
+        .get_linked_providers(&user.info.user_id, state.oauth2_config_file())
+        .await
+        .ok_or(status::NotFound(()))?;
+    Ok(Json(LinkedProvidersResponse {
+        msg: "success".to_string(),
+        password_login,
+        total: providers.len() as u32,
+        data: providers,
+    }))
+}
+
+/// # Unlink an OAuth provider from the current user
+///
+/// This function is an API endpoint that removes the link between the current user and an OAuth provider,
+/// the next login with the provider is linked again.
+/// It is tagged with "user" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `op`: the op of the provider, as listed by `GET /api/user/linked-providers`.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty`.  <br>
+///
+/// ## Errors
+///
+/// * a `Status::NotFound` error if the provider is not linked to the user
+/// * a `Status::Conflict` error if the provider is the last login method of the user, who has no password set
+///
+/// # Example
+///
+/// DELETE /api/user/linked-providers/github
+#[openapi(tag = "user")]
+#[delete("/api/user/linked-providers/<op>")]
+async fn linked_provider_delete(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    op: &str,
+) -> Result<ActionResponse, status::Custom<String>> {
+    log::debug!("linked_provider_delete: {}", op);
+    state.check_maintenance().await;
+    let res = state
+        .unlink_provider(&user.info.user_id, op)
+        .await
+        .ok_or_else(|| status::Custom(Status::InternalServerError, "the provider cannot be unlinked".to_string()))?;
+    match res {
+        Ok(()) => Ok(ActionResponse::Empty),
+        Err(UnlinkProviderError::NotLinked) => {
+            Err(status::Custom(Status::NotFound, "Provider not linked".to_string()))
+        }
+        Err(UnlinkProviderError::LastLoginMethod) => Err(status::Custom(
+            Status::Conflict,
+            "The provider is the last login method, set a password first".to_string(),
+        )),
+    }
+}
+
+/// Validity of the links sent by `POST /api/user/email/verify-request`
+const EMAIL_VERIFICATION_TTL_SECS: u64 = 24 * 3600;
+
+/// # Request the verification of the email of the current user
+///
+/// This function is an API endpoint that sends a link to the email of the current user,
+/// following it proves the user owns the email.
+/// The link is `GET /api/user/email/verify?token=...` on `PUBLIC_BASE_URL`, or else on the host the request was sent to,
+/// it is valid for 24 hours and replaces the previous link sent to the user.
+/// It is tagged with "user" for OpenAPI documentation.
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<EmailVerificationResponse>` with the expiration of the link.  <br>
+/// If the email is already verified no link is sent.  <br>
+///
+/// ## Errors
+///
+/// * a `Status::BadRequest` error if the user has no email
+/// * a `Status::ServiceUnavailable` error if `smtp_server` is not set
+/// * a `Status::BadGateway` error if the SMTP server refused the email
+///
+/// # Example
+///
+/// POST /api/user/email/verify-request
+#[openapi(tag = "user")]
+#[post("/api/user/email/verify-request")]
+async fn email_verify_request(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    smtp: &State<SmtpSettings>,
+    extended_request: ExtendedRequest,
+) -> Result<Json<EmailVerificationResponse>, status::Custom<String>> {
+    log::debug!("email_verify_request");
+    state.check_maintenance().await;
+    if !smtp.is_configured() {
+        return Err(status::Custom(Status::ServiceUnavailable, "No SMTP server configured".to_string()));
+    }
+    let res = state
+        .issue_email_verification(&user.info.user_id, Duration::from_secs(EMAIL_VERIFICATION_TTL_SECS))
+        .await
+        .ok_or_else(|| status::Custom(Status::InternalServerError, "the verification cannot be issued".to_string()))?;
+    let (token, email, expires_at) = match res {
+        Ok(issued) => issued,
+        Err(EmailVerificationError::AlreadyVerified) => {
+            let (email, _) = state.get_user_email(&user.info.user_id).await.unwrap_or_default();
+            return Ok(Json(EmailVerificationResponse {
+                msg: "already verified".to_string(),
+                email: email.unwrap_or_default(),
+                email_verified: true,
+                expires_at: None,
+            }));
+        }
+        Err(_) => return Err(status::Custom(Status::BadRequest, "The user has no email".to_string())),
+    };
+    let url = state
+        .public_base_url()
+        .map(str::to_string)
+        .unwrap_or_else(|| extended_request.host());
+    let link = format!("{}/api/user/email/verify?token={}", url, token);
+    let body = format!(
+        "Follow this link to verify your email address:\n\n{}\n\nThe link expires at {} UTC.\nIgnore this email if you did not ask for it.\n",
+        link, expires_at
+    );
+    let host = get_host(extended_request.headers);
+    if let Err(e) = smtp
+        .send(smtp_hostname(&host), &email, "Verify your email address", &body)
+        .await
+    {
+        log::warn!("email_verify_request: the email cannot be sent: {}", e);
+        return Err(status::Custom(Status::BadGateway, "The email cannot be sent".to_string()));
+    }
+    Ok(Json(EmailVerificationResponse {
+        msg: "sent".to_string(),
+        email,
+        email_verified: false,
+        expires_at: Some(expires_at),
+    }))
+}
+
+/// # Verify an email
+///
+/// This function is an API endpoint that marks an email as verified, it is the link sent by `POST /api/user/email/verify-request`.
+/// It needs no authentication, the token proves the ownership of the email, and it can only be used once.
+/// It is tagged with "user" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `token`: the token of the link.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<EmailVerificationResponse>` with the verified email.  <br>
+///
+/// ## Errors
+///
+/// * a `Status::NotFound` error if the token is unknown, already used or was sent to a previous email of the user
+/// * a `Status::Gone` error if the token has expired
+///
+/// # Example
+///
+/// GET /api/user/email/verify?token=Bx3...
+#[openapi(tag = "user")]
+#[get("/api/user/email/verify?<token>")]
+async fn email_verify(
+    state: &State<ApiState>,
+    token: &str,
+) -> Result<Json<EmailVerificationResponse>, status::Custom<String>> {
+    log::debug!("email_verify");
+    state.check_maintenance().await;
+    let res = state
+        .verify_email(token)
+        .await
+        .ok_or_else(|| status::Custom(Status::InternalServerError, "the email cannot be verified".to_string()))?;
+    match res {
+        Ok(email) => Ok(Json(EmailVerificationResponse {
+            msg: "verified".to_string(),
+            email,
+            email_verified: true,
+            expires_at: None,
+        })),
+        Err(EmailVerificationError::Expired) => Err(status::Custom(
+            Status::Gone,
+            "The link has expired, ask for a new one".to_string(),
+        )),
+        Err(_) => Err(status::Custom(Status::NotFound, "Invalid link".to_string())),
+    }
+}
+
+/// # Add user
+///
+/// This function is an API endpoint that adds a new user.
+///
+/// ## Parameters
+///
+/// - `request`: A JSON object containing the new user information.
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<UsersResponse>` object containing the updated user information.
+///
+/// ## Errors
+///
+/// If the name or the email (compared case-insensitively) is already used by another user, this function returns a `Status::Conflict` error with a `Json<UserConflictResponse>` naming the field.
+#[openapi(tag = "user")]
+#[post("/api/user", format = "application/json", data = "<request>")]
+async fn user_add(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    request: Json<AddUserRequest>,
+) -> Result<Json<UsersResponse>, UserError> {
+    log::debug!("create_user");
+    state.check_maintenance().await;
+
+    let user_parameters = request.0;
+    if user_parameters.password != user_parameters.confirm_password {
+        return Ok(Json(UsersResponse {
+            msg: "error: Passwords mismatch".to_string(),
+            total: 0,
+            data: "[{}]".to_string(),
+        }));
+    }
+    let name = user_parameters.name.clone();
+    let details = serde_json::json!(user_parameters);
+    state
+        .add_user(user_parameters)
+        .await
+        .ok_or(UserError::Unauthorized)?
+        .map_err(UserError::conflict)?;
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::UserCreate,
+            Some(name.as_str()),
+            details,
+        )
+        .await;
+    let response = UsersResponse {
+        msg: "success".to_string(),
+        total: 1,
+        data: "[{}]".to_string(),
+    };
+
+    Ok(Json(response))
+}
+
+/// # Enable users
+///
+/// This function is an API endpoint that enables or disables users.
+///
+/// ## Parameters
+///
+/// - `request`: A JSON object containing the list of users to enable or disable.
+///   `disable: true` enables the users (status `1`), `disable: false` disables them (status `0`).
+/// - `dry_run`: If true, no user is changed and nothing is audited.
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<UsersResponse>` object containing the updated user information.
+/// With `dry_run` the message is `dry run`, `total` is the number of users whose status would change
+/// and `data` is the JSON list of their guids.
+#[openapi(tag = "user")]
+#[post("/api/enable-users?<dry_run>", format = "application/json", data = "<request>")]
+async fn user_enable(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    request: Json<EnableUserRequest>,
+    dry_run: Option<bool>,
+) -> Result<Json<UsersResponse>, status::Unauthorized<()>> {
+    log::debug!("create_user");
+    state.check_maintenance().await;
+
+    let enable_users = request.0;
+    // `disable` keeps the meaning the web console gives it, true enables the users
+    let status = if enable_users.disable {
+        UserStatus::Normal
+    } else {
+        UserStatus::Disabled
+    };
+    if dry_run.unwrap_or(false) {
+        let mut changed = Vec::new();
+        for uuid in enable_users.rows {
+            if state
+                .user_status_changes(uuid.as_str(), status)
+                .await
+                .unwrap_or(false)
+            {
+                changed.push(uuid);
+            }
+        }
+        return Ok(Json(UsersResponse {
+            msg: "dry run".to_string(),
+            total: changed.len() as u32,
+            data: serde_json::to_string(&changed).unwrap_or_default(),
+        }));
+    }
+    let action = match status {
+        UserStatus::Disabled => AdminAction::UserDisable,
+        _ => AdminAction::UserEnable,
+    };
+
+    let mut count = 0;
+    for uuid in enable_users.rows {
+        let res = state
+            .user_change_status(uuid.as_str(), status)
+            .await;
+        if res.is_some() {
+            count += 1;
+            state
+                .audit_admin_action(
+                    &user.info.user_id,
+                    action,
+                    Some(uuid.as_str()),
+                    serde_json::json!({ "disable": enable_users.disable, "status": status }),
+                )
+                .await;
+        }
+    }
+    let response = UsersResponse {
+        msg: "success".to_string(),
+        total: count,
+        data: "[{}]".to_string(),
+    };
+
+    Ok(Json(response))
+}
+
+/// # Update user
+///
+/// This function is an API endpoint that updates a user.<br>
+/// Normal user can only update themselves, admin can update any user.<br>
+///
+/// ## Parameters
+///
+/// - `request`: A JSON object containing the updated user information.
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<UsersResponse>` object containing the updated user information.
+///
+/// ## Errors
+///
+/// If the new name or the new email (compared case-insensitively) is already used by another user, this function returns a `Status::Conflict` error with a `Json<UserConflictResponse>` naming the field.
+#[openapi(tag = "user")]
+#[put("/api/user", format = "application/json", data = "<request>")]
+async fn user_update(
+    state: &State<ApiState>,
+    user: AuthenticatedUser,
+    request: Json<UpdateUserRequest>,
+) -> Result<Json<UsersResponse>, UserError> {
+    log::debug!("update_user");
+    state.check_maintenance().await;
+    let mut guid = uuid_into_guid(request.0.uuid.as_str());
+    if guid.is_none() {
+        guid = Some(user.info.user_id.clone());
+    }
+
+    let guid = guid.unwrap();
+    let is_admin = state
+        .is_current_user_admin(&user.info)
+        .await
+        .unwrap_or(false);
+
+    if !is_admin && user.info.user_id != guid {
+        return Err(UserError::Unauthorized);
+    }
+    let response = UsersResponse {
+        msg: "success".to_string(),
+        total: 1,
+        data: "[{}]".to_string(),
+    };
+    let user_update = request.0;
+    // the updates made by the admins are recorded, the password is redacted
+    let audit = if is_admin {
+        Some((guid_into_uuid(guid.clone()), serde_json::json!(user_update)))
+    } else {
+        None
+    };
+    let res = state
+        .user_update(guid, user_update)
+        .await
+        .transpose()
+        .map_err(UserError::conflict)?;
+    if let (Some(()), Some((target, details))) = (res, audit) {
+        state
+            .audit_admin_action(
+                &user.info.user_id,
+                AdminAction::UserUpdate,
+                target.as_deref(),
+                details,
+            )
+            .await;
+    }
+    Ok(Json(response))
+}
+
+/// # Add OIDC Provider
+///
+/// This function is an API endpoint that adds an OIDC provider.
+///
+/// TODO: This function is currently unused.
+#[openapi(tag = "todo")]
+#[put("/api/oidc/settings", format = "application/json", data = "<_request>")]
+async fn oidc_add(
+    state: &State<ApiState>,
+    _user: AuthenticatedAdmin,
+    _request: Json<EnableUserRequest>,
+) -> Result<Json<EnableUserRequest>, status::Unauthorized<()>> {
+    log::debug!("Add OIDC Provider");
+    state.check_maintenance().await;
+
+    Err(status::Unauthorized::<()>(()))
+}
+
+/// # Reload the OIDC Providers
+///
+/// This function is an API endpoint that allows an authenticated admin to read the providers config file
+/// (`oauth2.toml` or the `OAUTH2_CONFIG_FILE` environment variable) again, without restarting the server.
+/// It is tagged with "login" for OpenAPI documentation.
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<OidcReloadResponse>` object with the number of providers loaded.  <br>
+/// If the file cannot be read or parsed, the `msg` is `error`, the reason is given in `errors` and the previous providers are kept.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the user is not an admin.
+///
+/// # Example
+///
+/// POST /api/oidc/reload
+#[openapi(tag = "login")]
+#[post("/api/oidc/reload")]
+async fn oidc_reload(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+) -> Json<OidcReloadResponse> {
+    log::debug!("oidc_reload");
+    state.check_maintenance().await;
+    let config_file = state.oauth2_config_file();
+    match state.reload_oauth2_config(config_file).await {
+        Ok(providers) => {
+            log::info!("oidc_reload: {} providers loaded from {}", providers, config_file);
+            state
+                .audit_admin_action(
+                    &user.info.user_id,
+                    AdminAction::OidcReload,
+                    Some(config_file),
+                    serde_json::json!({ "providers": providers }),
+                )
+                .await;
+            Json(OidcReloadResponse {
+                msg: "success".to_string(),
+                providers: providers as u32,
+                errors: Vec::new(),
+            })
+        }
+        Err(e) => {
+            log::error!("oidc_reload error: {}", e);
+            Json(OidcReloadResponse {
+                msg: "error".to_string(),
+                providers: 0,
+                errors: vec![e],
+            })
+        }
+    }
+}
+
+/// # List the OIDC Sessions
+///
+/// This function is an API endpoint that lists the OIDC sessions in flight, to diagnose the stuck or leaked login flows.
+/// A session is pending until the provider calls back and its code is exchanged for a token,
+/// then it is exchanged until the client fetches its token with `/api/oidc/auth-query`.
+/// It is tagged with "login" for OpenAPI documentation.
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<OidcSessionsResponse>` with the sessions, the oldest first.  <br>
+/// Each session gives the first characters of its code, its provider, its age in seconds and its state,
+/// the codes and tokens are never given.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the user is not an admin.
+///
+/// # Example
+///
+/// GET /api/oidc/sessions
+#[openapi(tag = "login")]
+#[get("/api/oidc/sessions", format = "application/json")]
+async fn oidc_sessions(state: &State<ApiState>, _user: AuthenticatedAdmin) -> Json<OidcSessionsResponse> {
+    log::debug!("oidc_sessions");
+    state.check_maintenance().await;
+    let sessions = state.get_oidc_sessions().await;
+    Json(OidcSessionsResponse {
+        msg: "success".to_string(),
+        total: sessions.len() as u32,
+        data: sessions,
+    })
+}
+
+/// Timeout of the S3 check of `test_integration`
+const S3_CHECK_TIMEOUT_SECS: u64 = 5;
+
+/// The host name the server gives in EHLO, from the public url of the server
+fn smtp_hostname(url: &str) -> &str {
+    let host = url.split_once("://").map_or(url, |(_, host)| host);
+    host.split([':', '/']).next().unwrap_or_default()
+}
+
+/// # Test an Integration
+///
+/// This function is an API endpoint that allows an authenticated admin to check the configuration of an integration
+/// without triggering a real event.
+/// The SMTP server is greeted with EHLO and no email is sent, the alarm webhook receives a ping signed like the alarms
+/// and the S3 storage is checked with a HEAD on the first release key.
+/// It is tagged with "admin" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `request`: The integration to check, `smtp`, `webhook` or `s3`.  <br>
+///
+/// ## Returns
+///
+/// This function returns a `Json<IntegrationTestResponse>` object telling if the check succeeded, with the answer of the integration
+/// or the reason of the failure.  <br>
+///
+/// ## Errors
+///
+/// If the user is not an admin, this function returns a `Status::Unauthorized` error.
+///
+/// # Example
+///
+/// POST /api/admin/test-integration
+/// {"target":"smtp"}
+#[openapi(tag = "admin")]
+#[post("/api/admin/test-integration", format = "application/json", data = "<request>")]
+async fn test_integration(
+    state: &State<ApiState>,
+    _user: AuthenticatedAdmin,
+    smtp: &State<SmtpSettings>,
+    webhook: &State<AuditWebhook>,
+    extended_request: ExtendedRequest,
+    request: Json<IntegrationTestRequest>,
+) -> Json<IntegrationTestResponse> {
+    log::debug!("test_integration");
+    state.check_maintenance().await;
+    let target = request.target;
+    let result = match target {
+        IntegrationTarget::Smtp => {
+            let host = get_host(extended_request.headers);
+            smtp.check(smtp_hostname(&host)).await
+        }
+        IntegrationTarget::Webhook => webhook.ping().await,
+        IntegrationTarget::S3 => match load_s3_config() {
+            Ok(config) => check_release_key(&config, Duration::from_secs(S3_CHECK_TIMEOUT_SECS)).await,
+            Err(e) => Err(format!("S3 is not configured: {}", e)),
+        },
+    };
+    if let Err(e) = &result {
+        log::warn!("test_integration {:?} failed: {}", target, e);
+    }
+    let success = result.is_ok();
+    Json(IntegrationTestResponse {
+        target,
+        success,
+        detail: result.unwrap_or_else(|e| e),
+    })
+}
+
+/// # Announce a maintenance
+///
+/// This function is an API endpoint that allows an authenticated admin to announce a maintenance to the clients.
+/// Until it starts, the heartbeat answers carry a `maintenance_soon` directive with its start and its message.
+/// It is tagged with "admin" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `request`: The start of the maintenance in seconds since the epoch and an optional message, null to cancel the announce.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns the announced maintenance, null if there is none.  <br>
+///
+/// ## Errors
+///
+/// This function returns a `Status::BadRequest` error if the maintenance start is in the past.
+///
+/// # Example
+///
+/// PUT /api/admin/maintenance
+/// {"at":1767225600,"message":"Server upgrade"}
+#[openapi(tag = "admin")]
+#[put("/api/admin/maintenance", format = "application/json", data = "<request>")]
+async fn maintenance_notice(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    request: Json<Option<MaintenanceNotice>>,
+) -> Result<Json<Option<MaintenanceNotice>>, Status> {
+    log::debug!("maintenance_notice");
+    state.check_maintenance().await;
+    let notice = request.into_inner();
+    state
+        .set_maintenance_notice(notice.clone())
+        .await
+        .ok_or(Status::BadRequest)?;
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::MaintenanceNotice,
+            None,
+            serde_json::json!(notice),
+        )
+        .await;
+    Ok(Json(notice))
+}
+
+/// # Revoke all the sessions
+///
+/// This function is an API endpoint that allows an authenticated admin to log every user out after a suspected breach.
+/// All the access tokens are revoked, the users and the clients have to log in again.
+/// It is tagged with "admin" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `request`: `confirm` must be true, `keep_current` keeps the session of the calling admin.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns the number of revoked access tokens.  <br>
+///
+/// ## Errors
+///
+/// This function returns a `Status::BadRequest` error if `confirm` is not true.
+///
+/// # Example
+///
+/// POST /api/admin/revoke-all-sessions
+/// {"confirm":true,"keep_current":true}
+#[openapi(tag = "admin")]
+#[post("/api/admin/revoke-all-sessions", format = "application/json", data = "<request>")]
+async fn revoke_all_sessions(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    request: Json<RevokeAllSessionsRequest>,
+) -> Result<Json<RevokeAllSessionsResponse>, status::Custom<String>> {
+    log::debug!("revoke_all_sessions");
+    state.check_maintenance().await;
+    if !request.confirm {
+        return Err(status::Custom(
+            Status::BadRequest,
+            "confirm must be true to revoke all the sessions".to_string(),
+        ));
+    }
+    let keep = request.keep_current.then_some(&user.info.access_token);
+    let revoked = state.revoke_all_sessions(keep).await;
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::SessionsRevokeAll,
+            None,
+            serde_json::json!({ "keep_current": request.keep_current, "revoked": revoked }),
+        )
+        .await;
+    Ok(Json(RevokeAllSessionsResponse { revoked }))
+}
+
+/// # Get OIDC Providers
+///
+/// This function is an API endpoint that retrieves all OIDC providers.
+///
+/// TODO: This function is currently unused.
+#[openapi(tag = "todo")]
+#[get("/api/oidc/settings", format = "application/json")]
+async fn oidc_get(
+    state: &State<ApiState>,
+    _user: AuthenticatedAdmin,
+) -> Result<Json<OidcSettingsResponse>, status::Unauthorized<()>> {
+    log::debug!("create_user");
+    state.check_maintenance().await;
+    Err(status::Unauthorized::<()>(()))
+}
+
+/// # Get Users for client
+///
+/// This function is an API endpoint that retrieves all users.
+///
+/// ## Parameters
+///
+/// - `current`: The current page number for pagination. This parameter is currently unused.
+///
+/// - `pageSize`: The number of items per page for pagination, `DEFAULT_PAGE_SIZE` (20) for 0, at most `MAX_PAGE_SIZE` (1000).
+///
+/// - `accessible`: A boolean value indicating whether the user is accessible. This parameter is currently unused.
+///
+/// - `status`: Only list the users with this status, `1` normal, `0` disabled or `-1` unverified.
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<UserList>` object containing the users and the page size used in `page_size`.
+/// If the status is not valid, this function returns a `Status::BadRequest` error.
+#[openapi(tag = "user")]
+#[get(
+    "/api/users?<current>&<pageSize>&<accessible>&<status>",
+    format = "application/json"
+)]
+async fn users_client(
+    state: &State<ApiState>,
+    _user: AuthenticatedUser,
+    current: u32,
+    #[allow(non_snake_case)] pageSize: u32,
+    #[allow(unused_variables)] accessible: Option<bool>,
+    status: Option<i32>,
+) -> Result<Json<UserList>, Status> {
+    log::debug!("users");
+    state.check_maintenance().await;
+    let page_size = effective_page_size(pageSize);
+
+    let status = status
+        .map(UserStatus::try_from)
+        .transpose()
+        .map_err(|_| Status::BadRequest)?;
+    let res = state.get_all_users(None, None, status, current, page_size).await;
+    if res.is_none() {
+        return Err(Status::NotFound);
+    }
+    let response = UserList {
+        msg: "success".to_string(),
+        total: res.len() as u32,
+        data: res.unwrap(),
+        next_cursor: None,
+        page_size,
+    };
+
+    Ok(Json(response))
+}
+
+/// Get the software download url
+///
+/// # Arguments
+///
+/// * `key` - The key to the software download link, it can be `w64`, `w32`, `osx`, `osx-arm64`, `ios`,
+///   `linux-x86_64`, `linux-aarch64` or `android`
+///
+/// # Usage
+///
+/// * it needs a valid S3 configuration file defined with the `S3_CONFIG_FILE` environment variable,
+///   the Linux and Android keys are optional
+///
+/// <pre>
+/// [s3config]<br>
+/// Page = "https://github.com/rustdesk/rustdesk/releases/latest"<br>
+/// Endpoint = "https://compat.objectstorage.eu-london-1.oraclecloud.com"<br>
+/// Region = "eu-london-1"<br>
+/// AccessKey = "c324ead11faa0d87337c07ddc4a1129fab76188d"<br>
+/// SecretKey = "GJurV55f/LD36kjZFpchZMj/uvgTqxHyFkBchUUa8KA="<br>
+/// Bucket = "aezoz24elapn"<br>
+/// Windows64Key = "master/sctgdesk-releases/sctgdesk-1.2.4-x86_64.exe"<br>
+/// Windows32Key = "master/sctgdesk-releases/sctgdesk-1.2.4-i686.exe"<br>
+/// OSXKey = "master/sctgdesk-releases/sctgdesk-1.2.4.dmg"<br>
+/// OSXArm64Key = "master/sctgdesk-releases/sctgdesk-1.2.4.dmg"<br>
+/// IOSKey = "master/sctgdesk-releases/sctgdesk-1.2.4.ipa"<br>
+/// LinuxX64Key = "master/sctgdesk-releases/sctgdesk-1.2.4-x86_64.deb"<br>
+/// LinuxArm64Key = "master/sctgdesk-releases/sctgdesk-1.2.4-aarch64.deb"<br>
+/// AndroidKey = "master/sctgdesk-releases/sctgdesk-1.2.4-universal.apk"<br>
+/// </pre>
+///
+/// # Errors
+///
+/// * a `Status::NotFound` error with a `Json<SoftwareKeyError>` listing the supported keys if the key is unknown
+///   or if its release is not configured
+/// * a `Status::ServiceUnavailable` error with a `Json<SoftwareKeyError>` if the S3 configuration file is missing
+///   or invalid, or if the link cannot be signed. The reason is logged once, not on every request
+///
+/// The configuration is cached until its file is modified and a link is reused until less than 10 minutes of
+/// its validity remain, `DELETE /api/software/cache` drops both.
+/// The latest release uploaded with `POST /api/software/releases` replaces the key of the configuration.
+///
+/// # Channels
+///
+/// The link is the one of the release channel of the caller, assigned by an admin to the user or to its group
+/// with `PUT /api/users/<id>/release-channel` and `PUT /api/group/<guid>/release-channel`.
+/// The anonymous requests get the stable channel. The beta releases are configured in a `[s3config.Beta]` table
+/// with the key names of `[s3config]`, uploaded with `channel=beta`, or listed in a `[beta]` table of `releases.toml`.
+/// A platform without a beta release gets its stable release.
+///
+/// When `releases_dir` is set in the Rocket configuration, the releases are served from this directory instead of S3.
+/// Its `releases.toml` manifest maps the keys to file names and the link points to `/api/software/releases/<file>`:
+/// <pre>
+/// w64 = "sctgdesk-1.2.4-x86_64.exe"<br>
+/// osx = "sctgdesk-1.2.4.dmg"<br>
+/// </pre>
+///
+/// # Checksums
+///
+/// The response gives the `size` and the `sha256` of the release when they are known, to verify the installer
+/// before running it. They are omitted otherwise, never guessed. The sha256 comes from:
+/// * the upload, for a release uploaded with `POST /api/software/releases`
+/// * the `sha256` metadata of the S3 object (`x-amz-meta-sha256`), or else a `<key>.sha256` object next to it
+///   in the format of `sha256sum`. They are cached with the configuration
+/// * a `<file>.sha256` file next to the release in `releases_dir`
+#[openapi(tag = "software")]
+#[get(
+    "/api/software/client-download-link/<key>",
+    format = "application/json"
+)]
+async fn software(
+    state: &State<ApiState>,
+    user: Option<AuthenticatedUser>,
+    cache: &State<ReleaseCache>,
+    local_releases: &State<LocalReleases>,
+    request: ExtendedRequest,
+    key: &str,
+) -> Result<Json<SoftwareResponse>, status::Custom<Json<SoftwareKeyError>>> {
+    log::debug!("software");
+    let channel = caller_release_channel(state, user.as_ref()).await;
+    let not_found = |error: String| {
+        status::Custom(
+            Status::NotFound,
+            Json(SoftwareKeyError {
+                error,
+                supported: SOFTWARE_KEYS.iter().map(|key| key.to_string()).collect(),
+            }),
+        )
+    };
+    let unavailable = |error: &str, reason: String| {
+        log_release_error(reason);
+        status::Custom(
+            Status::ServiceUnavailable,
+            Json(SoftwareKeyError {
+                error: error.to_string(),
+                supported: SOFTWARE_KEYS.iter().map(|key| key.to_string()).collect(),
+            }),
+        )
+    };
+    if !SOFTWARE_KEYS.contains(&key) {
+        return Err(not_found(format!("{} is not a known key", key)));
+    }
+    if local_releases.is_enabled() {
+        let manifest = local_releases
+            .manifest(channel)
+            .map_err(|e| unavailable("release hosting is not configured", e))?;
+        let file = manifest
+            .get(key)
+            .filter(|file| !file.is_empty())
+            .ok_or_else(|| not_found(format!("{} is not configured", key)))?;
+        let url = format!(
+            "{}/api/software/releases/{}",
+            request.host(),
+            urlencoding::encode(file)
+        );
+        let checksum = local_releases.checksum(file);
+        return Ok(Json(SoftwareResponse {
+            url,
+            sha256: checksum.sha256,
+            size: checksum.size,
+        }));
+    }
+    let config = cache
+        .config()
+        .map_err(|e| unavailable("release hosting is not configured", e))?;
+    let uploads = channel_uploads(state, channel).await;
+    let (release, uploaded) = channel_release(&config, &uploads, key)
+        .ok_or_else(|| not_found(format!("{} is not configured", key)))?;
+    let url = cache
+        .signed_url(&config, release)
+        .await
+        .map_err(|e| {
+            unavailable(
+                "the download link cannot be signed",
+                format!("signing {} failed: {}", release, e),
+            )
+        })?;
+    // the checksum of an uploaded release was computed during its upload
+    let checksum = match uploaded {
+        Some(uploaded) => ReleaseChecksum {
+            size: Some(uploaded.size),
+            sha256: Some(uploaded.sha256.clone()),
+        },
+        None => cache.checksum(&config, release).await,
+    };
+    let response = SoftwareResponse {
+        url,
+        sha256: checksum.sha256,
+        size: checksum.size,
+    };
+    Ok(Json(response))
+}
+
+/// Release channel of the caller of a software endpoint, the stable channel for the anonymous requests
+async fn caller_release_channel(state: &ApiState, user: Option<&AuthenticatedUser>) -> ReleaseChannel {
+    match user {
+        Some(user) => state.get_user_release_channel(&user.info.user_id).await,
+        None => ReleaseChannel::Stable,
+    }
+}
+
+/// Uploaded releases of the channels a caller gets, from its own channel to the stable one
+async fn channel_uploads(state: &ApiState, channel: ReleaseChannel) -> Vec<(ReleaseChannel, Vec<SoftwareRelease>)> {
+    let mut uploads = Vec::new();
+    for channel in channel.fallbacks() {
+        uploads.push((*channel, state.get_latest_software_releases(*channel).await));
+    }
+    uploads
+}
+
+/// Release of a platform for a caller, from the channels of `channel_uploads` in their order:
+/// the latest upload of the channel or else its key in the S3 configuration
+///
+/// # Returns
+/// The key of the release, with its record when it was uploaded
+fn channel_release<'a>(
+    config: &'a s3software::Config,
+    uploads: &'a [(ReleaseChannel, Vec<SoftwareRelease>)],
+    platform: &str,
+) -> Option<(&'a str, Option<&'a SoftwareRelease>)> {
+    uploads.iter().find_map(|(channel, uploaded)| {
+        match uploaded.iter().find(|release| release.platform == platform) {
+            Some(release) => Some((release.key.as_str(), Some(release))),
+            None => config
+                .s3config
+                .channel_release_key(channel.as_str(), platform)
+                .flatten()
+                .map(|key| (key, None)),
+        }
+    })
+}
+
+/// # Upload a release
+///
+/// This function is an API endpoint that allows an authenticated admin to publish a client build.
+/// The installer is streamed to the bucket of the S3 configuration under `releases/<platform>/<version>/<file name>`
+/// and its sha256 is computed on the way. Once the upload is complete, the download links of the platform
+/// in the channel of the release point to it. A failed upload is dropped and the previous release stays in use.
+/// It is tagged with "software" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - a `multipart/form-data` body with the fields, in this order:  <br>
+///   `platform`: a key of `SOFTWARE_KEYS`, e.g. `w64`  <br>
+///   `channel`: `stable` or `beta`, optional, `stable` by default  <br>
+///   `version`: the semantic version of the release, e.g. `1.2.6`  <br>
+///   `file`: the installer, at most `release_upload_max_size` of the Rocket configuration (512 MiB by default)  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<SoftwareRelease>` object describing the release.  <br>
+///
+/// ## Errors
+///
+/// * a `Status::BadRequest` error if a field is missing, invalid or sent after the file
+/// * a `Status::PayloadTooLarge` error if the file is larger than `release_upload_max_size`
+/// * a `Status::ServiceUnavailable` error if the S3 configuration file is missing or invalid
+/// * a `Status::BadGateway` error if the bucket refused the release
+///
+/// This function will also return an error if the system is in maintenance mode, or if the user is not an admin.
+///
+/// # Example
+///
+/// curl -H "Authorization: Bearer <token>" -F platform=w64 -F version=1.2.6 -F file=@sctgdesk-1.2.6-x86_64.exe https://sctgdesk.example.org/api/software/releases
+#[openapi(tag = "software")]
+#[post("/api/software/releases", format = "multipart/form-data", data = "<data>")]
+async fn software_release_upload(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    cache: &State<ReleaseCache>,
+    settings: &State<ReleaseUploadSettings>,
+    content_type: &ContentType,
+    data: Data<'_>,
+) -> Result<Json<SoftwareRelease>, status::Custom<String>> {
+    log::debug!("software_release_upload");
+    state.check_maintenance().await;
+    let bad_request = |message: String| status::Custom(Status::BadRequest, message);
+    let boundary = content_type
+        .params()
+        .find(|(name, _)| *name == "boundary")
+        .map(|(_, boundary)| boundary.to_string())
+        .ok_or_else(|| bad_request("the multipart boundary is missing".to_string()))?;
+    // the form is a little larger than the release, a larger file is rejected while it is read
+    let limit = settings.max_size + ByteUnit::Mebibyte(1);
+    let mut form = multer::Multipart::with_reader(data.open(limit), boundary);
+    let mut platform = None;
+    let mut channel = ReleaseChannel::Stable;
+    let mut version = None;
+    while let Some(mut field) = form
+        .next_field()
+        .await
+        .map_err(|e| bad_request(format!("the form is not valid: {}", e)))?
+    {
+        match field.name() {
+            Some("platform") => {
+                let value = field.text().await.map_err(|e| bad_request(e.to_string()))?;
+                if !SOFTWARE_KEYS.contains(&value.as_str()) {
+                    return Err(bad_request(format!("{} is not a known platform", value)));
+                }
+                platform = Some(value);
+            }
+            Some("channel") => {
+                let value = field.text().await.map_err(|e| bad_request(e.to_string()))?;
+                channel = value.parse::<ReleaseChannel>().map_err(bad_request)?;
+            }
+            Some("version") => {
+                let value = field.text().await.map_err(|e| bad_request(e.to_string()))?;
+                let value = value.parse::<Version>().map_err(bad_request)?;
+                version = Some(value.to_string());
+            }
+            Some("file") => {
+                let (Some(platform), Some(version)) = (platform.as_deref(), version.as_deref()) else {
+                    return Err(bad_request("the platform, the channel and the version must be sent before the file".to_string()));
+                };
+                let file_name = field
+                    .file_name()
+                    .and_then(release_file_name)
+                    .ok_or_else(|| bad_request("the file name is missing or not valid".to_string()))?;
+                let config = cache.config().map_err(|e| {
+                    log_release_error(e);
+                    status::Custom(Status::ServiceUnavailable, "release hosting is not configured".to_string())
+                })?;
+                let mut upload = ReleaseUpload::new(&config, &release_upload_key(platform, version, &file_name));
+                let (size, sha256) = match stream_release(&mut field, &mut upload, settings.max_size).await {
+                    Ok(uploaded) => uploaded,
+                    Err(e) => {
+                        upload.abort().await;
+                        return Err(e);
+                    }
+                };
+                if let Err(e) = upload.finish().await {
+                    log::error!("software_release_upload: {}", e);
+                    upload.abort().await;
+                    return Err(status::Custom(Status::BadGateway, "the release cannot be uploaded".to_string()));
+                }
+                let key = upload.key();
+                state
+                    .add_software_release(platform, channel, version, key, size, &sha256, user.info.user_id.clone())
+                    .await
+                    .ok_or_else(|| status::Custom(Status::InternalServerError, "the release cannot be recorded".to_string()))?;
+                state
+                    .audit_admin_action(
+                        &user.info.user_id,
+                        AdminAction::SoftwareReleaseUpload,
+                        Some(platform),
+                        serde_json::json!({ "channel": channel, "version": version, "key": key, "size": size, "sha256": sha256 }),
+                    )
+                    .await;
+                let release = state
+                    .get_latest_software_releases(channel)
+                    .await
+                    .into_iter()
+                    .find(|release| release.platform == platform)
+                    .ok_or_else(|| status::Custom(Status::InternalServerError, "the release cannot be recorded".to_string()))?;
+                return Ok(Json(release));
+            }
+            _ => {}
+        }
+    }
+    Err(bad_request("the file is missing".to_string()))
+}
+
+/// Stream a release from the form to the bucket
+///
+/// # Returns
+/// The size and the hex encoded sha256 of the release
+async fn stream_release(
+    field: &mut multer::Field<'_>,
+    upload: &mut ReleaseUpload,
+    max_size: ByteUnit,
+) -> Result<(u64, String), status::Custom<String>> {
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+    while let Some(chunk) = field
+        .chunk()
+        .await
+        .map_err(|e| status::Custom(Status::BadRequest, format!("the file cannot be read: {}", e)))?
+    {
+        size += chunk.len() as u64;
+        if size > max_size.as_u64() {
+            return Err(status::Custom(
+                Status::PayloadTooLarge,
+                format!("the release is larger than {}", max_size),
+            ));
+        }
+        hasher.update(&chunk);
+        upload.write(&chunk).await.map_err(|e| {
+            log::error!("software_release_upload: {}", e);
+            status::Custom(Status::BadGateway, "the release cannot be uploaded".to_string())
+        })?;
+    }
+    Ok((size, hex::encode(hasher.finalize())))
+}
+
+/// # Download a release file
+///
+/// This function is an API endpoint that streams a release file of the `releases_dir` directory,
+/// only the files named in its `releases.toml` manifest are served. No authentication is required.
+/// It is tagged with "software" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `file`: The name of the file, as given by `/api/software/client-download-link/<key>`.  <br>
+///
+/// ## Returns
+///
+/// The file with its Content-Type and Content-Length.  <br>
+/// A single byte range can be asked with a `Range` header to resume a download, it is answered with `206 Partial Content`.  <br>
+///
+/// ## Errors
+///
+/// * a `Status::NotFound` error if the releases are not served from a local directory or if the file is not in the manifest
+/// * a `416 Range Not Satisfiable` answer if the range is beyond the end of the file
+///
+/// # Example
+///
+/// GET /api/software/releases/sctgdesk-1.2.4-x86_64.exe
+#[openapi(tag = "software")]
+#[get("/api/software/releases/<file>")]
+async fn software_release_file(
+    local_releases: &State<LocalReleases>,
+    request: ExtendedRequest,
+    file: &str,
+) -> Result<ReleaseFile, Status> {
+    use tokio::io::AsyncSeekExt;
+
+    log::debug!("software_release_file: {}", file);
+    let path = local_releases.file(file).ok_or(Status::NotFound)?;
+    let mut release = tokio::fs::File::open(&path).await.map_err(|e| {
+        log::error!("software_release_file: cannot open {}: {}", path.display(), e);
+        Status::NotFound
+    })?;
+    let size = release
+        .metadata()
+        .await
+        .map_err(|_| Status::InternalServerError)?
+        .len();
+    let content_type = release_content_type(file);
+    match parse_range(request.headers.get("range").map(String::as_str), size) {
+        Ok(None) => Ok(ReleaseFile::Full {
+            content_type,
+            file: release,
+            size,
+        }),
+        Ok(Some((first, last))) => {
+            release
+                .seek(std::io::SeekFrom::Start(first))
+                .await
+                .map_err(|_| Status::InternalServerError)?;
+            Ok(ReleaseFile::Partial {
+                content_type,
+                file: release,
+                size,
+                first,
+                last,
+            })
+        }
+        Err(()) => Ok(ReleaseFile::Unsatisfiable { size }),
+    }
+}
+
+/// # Clear the release cache
+///
+/// This function is an API endpoint that drops the cached S3 configuration and signed download links,
+/// the next downloads read the configuration file again and sign new links.
+/// It is tagged with "software" for OpenAPI documentation.
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty`.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, or if the user is not an admin.
+///
+/// # Example
+///
+/// DELETE /api/software/cache
+#[openapi(tag = "software")]
+#[delete("/api/software/cache")]
+async fn software_cache_clear(
+    state: &State<ApiState>,
+    cache: &State<ReleaseCache>,
+    user: AuthenticatedAdmin,
+) -> ActionResponse {
+    log::debug!("software_cache_clear");
+    state.check_maintenance().await;
+    cache.clear();
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::SoftwareCacheClear,
+            None,
+            serde_json::json!({}),
+        )
+        .await;
+    ActionResponse::Empty
+}
+
+/// Last error of the release hosting which was logged
+static RELEASE_ERROR: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+
+/// Log an error of the release hosting unless it is the last one logged,
+/// a broken configuration is reported once instead of on every download request
+fn log_release_error(error: String) {
+    let mut last = RELEASE_ERROR.lock().unwrap_or_else(|e| e.into_inner());
+    if last.as_deref() != Some(error.as_str()) {
+        log::error!("software: {}", error);
+        *last = Some(error);
+    }
+}
+
+/// # Retrieve the server version
+///
+/// This function is an API endpoint that retrieves the version of the server.
+/// It is tagged with "software" for OpenAPI documentation.
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<SoftwareVersionResponse>` object containing the version of the server,
+/// the latest client version and the oldest client version still supported.  <br>
+/// The version of the server is `<version>+<git commit>`, e.g. `0.1.0+948f740`, or `<version>` when the commit was not known
+/// at build time. The version is the one of the `MAIN_PKG_VERSION` environment variable when it is set, else the one of the crate.  <br>
+/// The client versions are set by an admin with `PUT /api/software/version`, or with `latest_client_version` and
+/// `min_client_version` in the Rocket configuration. The latest version defaults to the version of the S3 release keys.  <br>
+/// `downloads` gives the download link endpoint of each platform with a configured release.  <br>
+/// They are the ones of the release channel of the caller, `channel`, the stable channel for the anonymous requests.
+/// For the beta channel, the latest version is the one of the newest beta release when it is newer.
+#[openapi(tag = "software")]
+#[get("/api/software/version/server", format = "application/json")]
+async fn software_version(
+    state: &State<ApiState>,
+    user: Option<AuthenticatedUser>,
+    default_versions: &State<DefaultClientVersions>,
+    cache: &State<ReleaseCache>,
+    local_releases: &State<LocalReleases>,
+    request: ExtendedRequest,
+) -> Json<SoftwareVersionResponse> {
+    log::debug!("software_version");
+    let version = server_version(env::var("MAIN_PKG_VERSION").ok());
+    let channel = caller_release_channel(state, user.as_ref()).await;
+    let versions = default_versions.merge(state.get_client_versions().await);
+    let client = latest_client_version(state, &versions, channel).await;
+    let host = request.host();
+    let downloads = download_links(state, cache, local_releases, channel, &host).await;
+    let response = SoftwareVersionResponse {
+        server: Some(version),
+        client: Some(client),
+        min_client: versions.minimum,
+        downloads: (!downloads.is_empty()).then_some(downloads),
+        channel: Some(channel),
+    };
+    Json(response)
+}
+
+/// Header giving the version of the client, e.g. `1.2.6`
+const CLIENT_VERSION_HEADER: &str = "x-client-version";
+
+/// Check the version of a client against the minimum client version
+///
+/// The version is the one of the `X-Client-Version` header, or else the one of the last system info of the peer.
+/// A client without version, or with a version which cannot be parsed, is accepted.
+///
+/// # Returns
+/// The upgrade asked to the client when it is older than the minimum version
+async fn check_client_version(
+    state: &ApiState,
+    default_versions: &DefaultClientVersions,
+    request: &ExtendedRequest,
+    peer_id: Option<&str>,
+) -> Option<ClientUpgradeResponse> {
+    let versions = default_versions.merge(state.get_client_versions().await);
+    let minimum = versions.minimum.clone()?;
+    let version = match request.headers.get(CLIENT_VERSION_HEADER) {
+        Some(version) => Some(version.trim().to_string()),
+        None => match peer_id {
+            Some(peer_id) => state.get_peer(peer_id).await.and_then(|peer| peer.info.version),
+            None => None,
+        },
+    }?;
+    match compare_versions(&version, &minimum) {
+        Some(Ordering::Less) => {}
+        Some(_) => return None,
+        None => {
+            log::debug!("client version {} cannot be compared to {}", version, minimum);
+            return None;
+        }
+    }
+    let latest = latest_client_version(state, &versions, ReleaseChannel::Stable).await;
+    let target = match compare_versions(&latest, &minimum) {
+        Some(Ordering::Greater) | Some(Ordering::Equal) => latest,
+        _ => minimum.clone(),
+    };
+    let url = format!("{}/api/software/releases/tag/{}", request.host(), target);
+    Some(ClientUpgradeResponse {
+        error: format!(
+            "Your client version {} is no longer supported, please upgrade to version {} or newer: {}",
+            version, minimum, url
+        ),
+        min_version: minimum,
+        url,
+    })
+}
+
+/// Latest client version of a channel, the one set by an admin or in the configuration, else the one of the S3 release keys
+/// For the beta channel, the version of the newest beta release when it is newer
+async fn latest_client_version(state: &ApiState, versions: &ClientVersions, channel: ReleaseChannel) -> String {
+    let mut latest = match versions.latest.clone() {
+        Some(latest) => latest,
+        None => extract_version().await.unwrap_or("0.0.0".to_string()),
+    };
+    if channel == ReleaseChannel::Beta {
+        for release in state.get_latest_software_releases(ReleaseChannel::Beta).await {
+            if compare_versions(&release.version, &latest) == Some(Ordering::Greater) {
+                latest = release.version;
+            }
+        }
+    }
+    latest
+}
+
+/// Download link endpoint of each platform with a release in a channel
+async fn download_links(
+    state: &ApiState,
+    cache: &ReleaseCache,
+    local_releases: &LocalReleases,
+    channel: ReleaseChannel,
+    host: &str,
+) -> BTreeMap<String, String> {
+    configured_platforms(state, cache, local_releases, channel)
+        .await
+        .into_iter()
+        .map(|key| {
+            let url = format!("{}/api/software/client-download-link/{}", host, key);
+            (key.to_string(), url)
+        })
+        .collect()
+}
+
+/// Version of the server with its git commit, `MAIN_PKG_VERSION` overrides the version of the crate
+fn server_version(main_pkg_version: Option<String>) -> String {
+    let version = main_pkg_version
+        .filter(|version| !version.is_empty())
+        .unwrap_or_else(|| env!("CARGO_PKG_VERSION").to_string());
+    match env!("GIT_HASH") {
+        "" | "unknown" => version,
+        git_hash => format!("{}+{}", version, git_hash),
+    }
+}
+
+/// Platforms of `SOFTWARE_KEYS` with a release in a channel, in the local releases directory, uploaded or in the S3 configuration
+async fn configured_platforms(
+    state: &ApiState,
+    cache: &ReleaseCache,
+    local_releases: &LocalReleases,
+    channel: ReleaseChannel,
+) -> Vec<&'static str> {
+    if local_releases.is_enabled() {
+        let manifest = local_releases.manifest(channel).unwrap_or_default();
+        return SOFTWARE_KEYS
+            .into_iter()
+            .filter(|key| manifest.get(*key).is_some_and(|file| !file.is_empty()))
+            .collect();
+    }
+    let Ok(config) = cache.config() else {
+        return Vec::new();
+    };
+    let uploads = channel_uploads(state, channel).await;
+    SOFTWARE_KEYS
+        .into_iter()
+        .filter(|key| channel_release(&config, &uploads, key).is_some())
+        .collect()
+}
+
+/// # Set the client versions
+///
+/// This function is an API endpoint that allows an authenticated admin to set the latest client version,
+/// which makes the older clients offer to update, and the oldest client version still supported.
+/// They are stored in the database and override `latest_client_version` and `min_client_version`
+/// of the Rocket configuration, a null version falls back to the configuration.
+/// It is tagged with "software" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `request`: The versions, semantic versions such as `1.2.6` or `1.3.0-beta.1`.  <br>
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty` object.  <br>
+/// If a version is not valid or the minimum is newer than the latest version, this function returns an `ActionResponse::Error` object.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, or if the user is not an admin.
+///
+/// # Example
+///
+/// PUT /api/software/version
+/// {
+///     "latest": "1.2.6",
+///     "minimum": "1.2.0"
+/// }
+#[openapi(tag = "software")]
+#[put("/api/software/version", format = "application/json", data = "<request>")]
+async fn software_version_update(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    request: Json<ClientVersions>,
+) -> Result<ActionResponse, Status> {
+    log::debug!("software_version_update");
+    state.check_maintenance().await;
+    if let Err(e) = request.validate() {
+        return Ok(ActionResponse::Error(e));
+    }
+    state
+        .set_client_versions(&request)
+        .await
+        .ok_or(Status::InternalServerError)?;
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::ClientVersionsUpdate,
+            None,
+            serde_json::json!(request.0),
+        )
+        .await;
+    Ok(ActionResponse::Empty)
+}
+
+/// # Assign a release channel to a user
+///
+/// This function is an API endpoint that allows an authenticated admin to assign a release channel to a user.
+/// The download links and the latest version announced to the user are the ones of its channel.
+/// The channel of a user takes precedence over the one of its group, the users without a channel get the stable releases.
+/// The assignment change is recorded in the console audit.
+/// It is tagged with "software" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `id`: The GUID of the user.  <br>
+///
+/// - `request`: The channel, `stable` or `beta`, or null to remove the assignment.  <br>
+///
+/// ## Errors
+///
+/// This function returns a `Status::NotFound` error if the user does not exist.
+///
+/// # Example
+///
+/// PUT /api/users/018f2556-2301-79eb-91a2-cffe5ced4236/release-channel
+/// {"channel":"beta"}
+#[openapi(tag = "software")]
+#[put("/api/users/<id>/release-channel", format = "application/json", data = "<request>")]
+async fn user_release_channel(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    id: &str,
+    request: Json<AssignReleaseChannelRequest>,
+) -> Result<(), Status> {
+    log::debug!("user_release_channel");
+    state.check_maintenance().await;
+    let request = request.into_inner();
+    state
+        .assign_user_release_channel(id, request.channel)
+        .await
+        .ok_or(Status::NotFound)?;
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::UserReleaseChannelAssign,
+            Some(id),
+            serde_json::json!(request),
+        )
+        .await;
+    Ok(())
+}
+
+/// # Assign a release channel to a group
+///
+/// This function is an API endpoint that allows an authenticated admin to assign a release channel to a group.
+/// It applies to the users of the group without their own channel.
+/// The assignment change is recorded in the console audit.
+/// It is tagged with "software" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `guid`: The GUID of the group.  <br>
+///
+/// - `request`: The channel, `stable` or `beta`, or null to remove the assignment.  <br>
+///
+/// ## Errors
+///
+/// This function returns a `Status::NotFound` error if the group does not exist.
+///
+/// # Example
+///
+/// PUT /api/group/018f2556-22fb-73ee-9afd-bbcdc0cc387b/release-channel
+/// {"channel":"beta"}
+#[openapi(tag = "software")]
+#[put("/api/group/<guid>/release-channel", format = "application/json", data = "<request>")]
+async fn group_release_channel(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    guid: &str,
+    request: Json<AssignReleaseChannelRequest>,
+) -> Result<(), Status> {
+    log::debug!("group_release_channel");
+    state.check_maintenance().await;
+    let request = request.into_inner();
+    state
+        .assign_group_release_channel(guid, request.channel)
+        .await
+        .ok_or(Status::NotFound)?;
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::GroupReleaseChannelAssign,
+            Some(guid),
+            serde_json::json!(request),
+        )
+        .await;
+    Ok(())
+}
+
+/// # Retrieve the build information
+///
+/// This function is an API endpoint that retrieves the build information of the server.
+/// It returns the crate version, the git commit, the build time and the enabled cargo features.
+/// It is tagged with "software" for OpenAPI documentation.
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<BuildInfoResponse>` object.
+///
+/// # Example
+///
+/// GET /api/version
+///
+/// {"version":"0.1.0","git_hash":"948f740","build_timestamp":1714564800,"features":[]}
+#[openapi(tag = "software")]
+#[get("/api/version", format = "application/json")]
+async fn version() -> Json<BuildInfoResponse> {
+    log::debug!("version");
+    let features = env!("ENABLED_FEATURES")
+        .split(',')
+        .filter(|feature| !feature.is_empty())
+        .map(|feature| feature.to_string())
+        .collect();
+    Json(BuildInfoResponse {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        git_hash: env!("GIT_HASH").to_string(),
+        build_timestamp: env!("BUILD_TIMESTAMP").parse().unwrap_or(0),
+        features,
+    })
+}
+
+/// # Retrieve the branding
+///
+/// This function is an API endpoint that gives the branding of the web console, read by the console when it starts.
+/// It does not need authentication, the login page is also branded.
+/// It is tagged with "software" for OpenAPI documentation.
+///
+/// ## Returns
+///
+/// This function returns a `Json<BrandingResponse>` object, with the SCTGDesk values for the settings not configured.
+///
+/// # Example
+///
+/// GET /api/settings/branding
+///
+/// {"product_name":"Acme Desk","logo":"data:image/png;base64,...","accent_color":"#0055aa","footer_html":"© 2024 Acme"}
+#[openapi(tag = "software")]
+#[get("/api/settings/branding", format = "application/json")]
+async fn branding_settings(branding: &State<Branding>) -> Json<BrandingResponse> {
+    Json(branding.response())
+}
+
+/// # Retrieve the capabilities
+///
+/// This function is an API endpoint that tells the clients and the web console which features are available on this server.
+/// The capabilities are computed from the build and the configuration files.
+/// It is tagged with "software" for OpenAPI documentation.
+///
+/// ## Returns
+///
+/// This function returns a `Json<CapabilitiesResponse>` object.
+///
+/// # Example
+///
+/// GET /api/capabilities
+///
+/// {"oidc_enabled":true,"s3_configured":false,"software_downloads":false,"smtp_configured":false,"two_factor_auth":false,"shared_address_books":true,"web_console":true}
+#[openapi(tag = "software")]
+#[get("/api/capabilities", format = "application/json")]
+async fn capabilities(state: &State<ApiState>, cache: &State<ReleaseCache>) -> Json<CapabilitiesResponse> {
+    log::debug!("capabilities");
+    let oidc_enabled = state
+        .get_oauth2_config(state.oauth2_config_file())
+        .await
+        .is_some_and(|providers| !providers.is_empty());
+    let s3_configured = cache.config().is_ok();
+    Json(CapabilitiesResponse {
+        oidc_enabled,
+        s3_configured,
+        software_downloads: s3_configured,
+        smtp_configured: false,
+        two_factor_auth: false,
+        shared_address_books: true,
+        web_console: true,
+    })
+}
+
+/// # Download the Client Configuration
+///
+/// This function is an API endpoint that allows an authenticated user to download the configuration of a RustDesk client
+/// using this server: id server, relay server, server key and api server.
+/// The file is the `RustDesk2.toml` read by the client from its configuration directory.
+/// It is tagged with "software" for OpenAPI documentation.
+///
+/// ## Returns
+///
+/// If successful, this function returns the `RustDesk2.toml` file.  <br>
+/// The values come from `client_id_server`, `client_relay_server`, `client_key` and `client_api_server` in the Rocket configuration,
+/// the servers default to the host the request was sent to.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the user is not authenticated.
+///
+/// # Example
+///
+/// GET /api/client-config/download
+#[openapi(tag = "software")]
+#[get("/api/client-config/download")]
+async fn client_config_download(
+    state: &State<ApiState>,
+    _user: AuthenticatedUser,
+    client_config: &State<ClientConfig>,
+    request: ExtendedRequest,
+) -> DownloadStream<Pin<Box<dyn Stream<Item = String> + Send>>> {
+    log::debug!("client_config_download");
+    state.check_maintenance().await;
+    let config = client_config.to_toml(&request.host());
+    DownloadStream {
+        content_type: ContentType::new("application", "toml"),
+        filename: "RustDesk2.toml".to_string(),
+        stream: Box::pin(stream! { yield config; }),
+    }
+}
+
+/// # Check for a client update
+///
+/// This function is an API endpoint answering the update check of the RustDesk client (`check_software_update`
+/// in `src/common.rs`), which posts a `VersionCheckRequest` to `<api server>/version/latest` and reads a JSON object
+/// with a `url` field. The client takes the latest version from the last path segment of `url` and shows its
+/// update banner when it is newer than its own version, the banner opens `url`. No authentication is required.
+/// `/api/version` is not used for it as it gives the build information of the server.
+/// It is tagged with "software" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `request`: The `VersionCheckRequest` of the client, `os`, `os_version`, `arch`, `device_id` and `typ`, all optional.  <br>
+///
+/// ## Returns
+///
+/// A `Json<VersionCheckResponse>` object: `url` is `<host>/api/software/releases/tag/<latest version>`,
+/// the version being the one announced by `/api/software/version/server` for the channel of the caller,
+/// and `downloads` gives the download link endpoint of each platform with a release.
+///
+/// # Example
+///
+/// POST /api/version/latest
+/// {"os":"windows","os_version":"10","arch":"x86_64","device_id":[],"typ":"rustdesk-client"}
+///
+/// {"url":"https://sctgdesk.example.org/api/software/releases/tag/1.2.6","downloads":{"w64":"https://sctgdesk.example.org/api/software/client-download-link/w64"}}
+#[openapi(tag = "software")]
+#[post("/api/version/latest", data = "<request>")]
+async fn version_check(
+    state: &State<ApiState>,
+    user: Option<AuthenticatedUser>,
+    default_versions: &State<DefaultClientVersions>,
+    cache: &State<ReleaseCache>,
+    local_releases: &State<LocalReleases>,
+    extended_request: ExtendedRequest,
+    request: Option<Json<VersionCheckRequest>>,
+) -> Json<VersionCheckResponse> {
+    log::debug!("version_check: {:?}", request.as_deref());
+    latest_version_check(state, user, default_versions, cache, local_releases, extended_request).await
+}
+
+/// # Check for a client update
+///
+/// The same answer as `POST /api/version/latest`, for the update checks made with a GET request.
+/// It is tagged with "software" for OpenAPI documentation.
+///
+/// # Example
+///
+/// GET /api/version/latest
+#[openapi(tag = "software")]
+#[get("/api/version/latest")]
+async fn version_check_get(
+    state: &State<ApiState>,
+    user: Option<AuthenticatedUser>,
+    default_versions: &State<DefaultClientVersions>,
+    cache: &State<ReleaseCache>,
+    local_releases: &State<LocalReleases>,
+    extended_request: ExtendedRequest,
+) -> Json<VersionCheckResponse> {
+    log::debug!("version_check_get");
+    latest_version_check(state, user, default_versions, cache, local_releases, extended_request).await
+}
+
+/// Answer of the update check of the clients
+async fn latest_version_check(
+    state: &ApiState,
+    user: Option<AuthenticatedUser>,
+    default_versions: &DefaultClientVersions,
+    cache: &ReleaseCache,
+    local_releases: &LocalReleases,
+    request: ExtendedRequest,
+) -> Json<VersionCheckResponse> {
+    let channel = caller_release_channel(state, user.as_ref()).await;
+    let versions = default_versions.merge(state.get_client_versions().await);
+    let latest = latest_client_version(state, &versions, channel).await;
+    let host = request.host();
+    let downloads = download_links(state, cache, local_releases, channel, &host).await;
+    Json(VersionCheckResponse {
+        url: format!("{}/api/software/releases/tag/{}", host, latest),
+        downloads: (!downloads.is_empty()).then_some(downloads),
+    })
+}
+
+/// # Retrieve the client version
+///
+/// This function is an API endpoint that retrieves the version of the client.
+/// It copies the GitHub method of retrieving the latest release version.
+/// It is tagged with "software" for OpenAPI documentation.
+///
+/// It can be used by replacing the check_software_update() from the client.
+/// You can find the client code at rustdesk/src/common.rs
+/// ## Returns
+///
+/// Returns in the location header the URL of the latest release.
+/// something like https://api-server/api/releases/tag/1.2.6
+///
+/// ## Example
+///
+/// It is easy to modify the client code to use this API endpoint.
+/// this is how we can modify the client code to use this API endpoint.
+///
+/// <pre>
+///     // see <a href='https://github.com/sctg-development/sctgdesk/blob/481d3516fef1daa145d8044594187cb11959f8be/src/common.rs#L953L972'>Sample modification on github</a><br>
+///     let url=format!("{}/api/software/releases/latest",get_api_server("".to_owned(), "".to_owned())).to_owned();<br>
+///     log::info!("URL for checking software updates: {}", url);<br>
+/// </pre>
+#[openapi(tag = "software")]
+#[get("/api/software/releases/latest")]
+async fn software_releases_latest(request: ExtendedRequest) -> Redirect {
+    log::debug!("software_releases_latest");
+    let host = request.host();
+    let version = extract_version()
+        .await
+        .map_err(|e| status::NotFound(Box::new(e)));
+    if version.is_err() {
+        return Redirect::to(format!("{}/api/software/releases/tag/0.0.0", host));
+    }
+    let version = version.unwrap();
+    let url = format!("{}/api/software/releases/tag/{}", host, version);
+    Redirect::to(url)
+}
+
+/// # Simulate GitHub API for releases
+///
+/// This function is an API endpoint that simulates the GitHub API for releases.
+///
+/// ## Parameters
+///
+/// - `version`: The version of the release.
+///
+/// ## Returns
+///
+/// Returns a `Json<SoftwareVersionResponse>` object containing the version of the release.
+#[openapi(tag = "software")]
+#[get("/api/software/releases/tag/<version>")]
+async fn software_releases_tag(
+    version: &str,
+) -> Result<Json<SoftwareVersionResponse>, status::NotFound<()>> {
+    log::debug!("software_releases_tag");
+    let response = SoftwareVersionResponse {
+        server: None,
+        client: Some(version.to_string()),
+        min_client: None,
+        downloads: None,
+        channel: None,
+    };
+    Ok(Json(response))
+}
+
+/// # Redirect to the software download page
+///
+/// This function is an API endpoint that redirects to the software download page.
+/// You must set the `Page` key in the `s3config` of the S3 configuration file.
+///
+#[openapi(tag = "software")]
+#[get("/api/software/download")]
+async fn software_download() -> Redirect {
+    let url = get_software_download_page()
+        .await
+        .unwrap_or("https://github.com/sctg-development/sctgdesk".to_string());
+    Redirect::to(url)
+}
+/// # List the rules
+///
+/// This function is an API endpoint that lists the rules attached to a shared address book.
+/// It is tagged with "address book" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `current`: The current page number for pagination. This parameter is currently unused.
+///
+/// - `pageSize`: The number of items per page for pagination. This parameter is currently unused.
+///
+/// - `ab`: The identifier of the shared address book.
+///
+/// ## Returns
+///
+/// If successful, this function returns a `Json<AbRulesResponse>` object containing the rules for the address book.  <br>
+/// If the address book does not exist or the user is not authorized to access it, this function returns a `status::Unauthorized` error.  <br>
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode, or if the address book does not exist or the user is not authorized to access it.
+///
+#[openapi(tag = "address book")]
+#[get("/api/ab/rules?<current>&<pageSize>&<ab>", format = "application/json")]
+async fn ab_rules(
+    state: &State<ApiState>,
+    _user: AuthenticatedUser,
+    current: u32,
+    #[allow(unused_variables)] pageSize: u32,
+    ab: &str,
+) -> Result<Json<AbRulesResponse>, status::Unauthorized<()>> {
+    state.check_maintenance().await;
+    let current = if (current < 1) { 0 } else { current - 1 };
+    let rules = state.get_ab_rules(current, pageSize, ab).await;
+    if rules.is_none() {
+        return Err(status::Unauthorized::<()>(()));
+    }
+    let rules = rules.unwrap();
+    let response = AbRulesResponse {
+        msg: "success".to_string(),
+        total: rules.len() as u32,
+        data: rules,
+    };
+    Ok(Json(response))
+}
+
+/// # Add a Rule
+///
+/// This function is an API endpoint that adds a new rule to a shared address book.
+/// It is tagged with "address book" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `request`: The request containing the details of the rule to be added.
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty` indicating that the rule was successfully added. <br>
+/// If the system is in maintenance mode, this function returns a `status::Unauthorized` error.
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode.
+#[openapi(tag = "address book")]
+#[post("/api/ab/rule", format = "application/json", data = "<request>")]
+async fn ab_rule_add(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    request: Json<AbRuleAddRequest>,
+) -> Result<ActionResponse, status::Unauthorized<()>> {
+    state.check_maintenance().await;
+    let rule = AbRule {
+        guid: request.0.guid,
+        user: request.0.user,
+        group: request.0.group,
+        rule: request.0.rule,
+    };
+    let details = serde_json::json!(rule);
+    let ab = rule.guid.clone();
+    if state.add_ab_rule(rule).await.is_some() {
+        state
+            .audit_admin_action(&user.info.user_id, AdminAction::AbRuleAdd, Some(ab.as_str()), details)
+            .await;
+    }
+    Ok(ActionResponse::Empty)
+}
+
+/// # Delete a Rule
+///
+/// This function is an API endpoint that deletes a rule from a shared address book.
+/// It is tagged with "address book" for OpenAPI documentation.
+///
+/// ## Parameters
+///
+/// - `request`: The request containing the GUID of the rule to be deleted.
+///
+/// - `dry_run`: If true, the rule is not deleted and nothing is audited.
+///
+/// ## Returns
+///
+/// If successful, this function returns an `ActionResponse::Empty` indicating that the rule was successfully deleted. <br>
+/// With `dry_run` it returns an `ActionResponse::DryRun` object listing the rule if it exists. <br>
+/// If the system is in maintenance mode, this function returns a `status::Unauthorized` error.
+///
+/// ## Errors
+///
+/// This function will return an error if the system is in maintenance mode.
+#[openapi(tag = "address book")]
+#[delete("/api/ab/rule?<dry_run>", format = "application/json", data = "<request>")]
+async fn ab_rule_delete(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    request: Json<AbRuleDeleteRequest>,
+    dry_run: Option<bool>,
+) -> Result<ActionResponse, status::Unauthorized<()>> {
+    state.check_maintenance().await;
+    let rule = request.0.guid;
+    if dry_run.unwrap_or(false) {
+        let exists = state.ab_rule_exists(rule.as_str()).await.unwrap_or(false);
+        let rules = if exists { vec![rule] } else { Vec::new() };
+        return Ok(ActionResponse::DryRun(DryRunResponse::new(rules, serde_json::json!({}))));
+    }
+    if state.delete_ab_rule(rule.as_str()).await.is_some() {
+        state
+            .audit_admin_action(
+                &user.info.user_id,
+                AdminAction::AbRuleDelete,
+                Some(rule.as_str()),
+                serde_json::json!({}),
+            )
+            .await;
+    }
+    Ok(ActionResponse::Empty)
+}
+
+/// # Add shared profile
+///
+/// This function is an API endpoint that adds a shared profile to an address book.
+/// It is tagged with "address book" for OpenAPI documentation.
+/// 
+/// ## Parameters
+/// 
+/// - `request`: A JSON object containing the shared profile to be added.
+/// 
+/// ## Returns
+/// 
+/// If successful, this function returns a `Json<AbSharedProfilesResponse>` object containing the shared profiles in the address book.  <br>
+#[openapi(tag = "address book")]
+#[post("/api/ab/shared/add", format = "application/json", data = "<request>")]
+async fn ab_shared_add(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    request: Json<AbSharedAddRequest>,
+) -> Result<Json<AbSharedProfilesResponse>, status::Unauthorized<()>> {
+    state.check_maintenance().await;
+    let name = request.0.name;
+    let note = request.0.note;
+    let owner = guid_into_uuid(user.info.user_id.clone()).unwrap();
+    let ab_uuid = state.add_shared_address_book(name.as_str(),owner.as_str()).await;
+    if ab_uuid.is_none() {
+        return Err(status::Unauthorized::<()>(()));
+    }
+    let ab_uuid = ab_uuid.unwrap();
+    state
+        .audit_admin_action(
+            &user.info.user_id,
+            AdminAction::AbSharedAdd,
+            Some(ab_uuid.as_str()),
+            serde_json::json!({ "name": name, "note": note }),
+        )
+        .await;
+    let shared_profile = AbProfile {
+        guid: ab_uuid,
+        name: name,
+        owner: owner,
+        rule: 3,
+        note: note,
+        ..Default::default()
+    };
+    let mut ab_shared_profiles = AbSharedProfilesResponse::default();
+    ab_shared_profiles.data.push(shared_profile);
+    Ok(Json(ab_shared_profiles))
+}
+
+/// # Delete shared profiles
+///
+/// This function is an API endpoint that deletes shared profiles from an address book.
+/// It is tagged with "address book" for OpenAPI documentation.
+/// 
+/// ## Parameters
+/// 
+/// - `request`: A JSON object containing an array of shared profile GUIDs to be deleted.
+/// 
+/// - `dry_run`: If true, no address book is deleted and nothing is audited.
+/// 
+/// ## Returns
+/// 
+/// If successful, this function returns an `ActionResponse::Empty` object.
+/// With `dry_run` it returns an `ActionResponse::DryRun` object listing the shared address books found,
+/// with the number of peers deleted with them in `details.peers`.
+#[openapi(tag = "address book")]
+#[delete("/api/ab/shared?<dry_run>", format = "application/json", data = "<request>")]
+async fn ab_shared_delete(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    request: Json<Vec<String>>,
+    dry_run: Option<bool>,
+) -> Result<ActionResponse, status::Unauthorized<()>> {
+    state.check_maintenance().await;
+    let shared_profiles_to_delete = request.0;
+    if dry_run.unwrap_or(false) {
+        let mut shareds = Vec::new();
+        let mut peers = 0;
+        for ab in shared_profiles_to_delete {
+            if let Some(count) = state.count_shared_address_book_peers(ab.as_str()).await {
+                shareds.push(ab);
+                peers += count;
+            }
+        }
+        return Ok(ActionResponse::DryRun(DryRunResponse::new(
+            shareds,
+            serde_json::json!({ "peers": peers }),
+        )));
+    }
+    state.delete_shared_address_books(shared_profiles_to_delete.clone()).await;
+    for ab in &shared_profiles_to_delete {
+        state
+            .audit_admin_action(
+                &user.info.user_id,
+                AdminAction::AbSharedDelete,
+                Some(ab.as_str()),
+                serde_json::json!({}),
+            )
+            .await;
+    }
+    Ok(ActionResponse::Empty)
+}
+
+/// # Update shared profile name
+/// 
+/// This function is an API endpoint that updates the name of a shared profile in an address book.
+/// It is tagged with "address book" for OpenAPI documentation.
+/// 
+/// ## Parameters
+/// 
+/// - `request`: A JSON object containing the updated shared profile information.
+/// 
+/// ## Returns
+/// 
+/// If successful, this function returns a `Json<AbSharedProfilesResponse>` object containing the updated shared profile information.
+#[openapi(tag = "address book")]
+#[put("/api/ab/shared/update/profile", format = "application/json", data = "<request>")]
+async fn ab_shared_name(
+    state: &State<ApiState>,
+    user: AuthenticatedAdmin,
+    request: Json<AbSharedNameRequest>,
+) -> Result<Json<AbSharedProfilesResponse>, status::Unauthorized<()>> {
+    state.check_maintenance().await;
+    let shared_profile = request.0;
+    let name = shared_profile.name.expect("Currently name is required");
+    if state
+        .update_shared_address_book(shared_profile.guid.as_str(), name.as_str())
+        .await
+        .is_some()
+    {
+        state
+            .audit_admin_action(
+                &user.info.user_id,
+                AdminAction::AbSharedUpdate,
+                Some(shared_profile.guid.as_str()),
+                serde_json::json!({ "name": name }),
+            )
+            .await;
+    }
+    let shared_profiles = state.get_shared_address_books(user.info.user_id).await;
+    let mut ab_shared_profiles = AbSharedProfilesResponse::default();
+    for ab in shared_profiles.expect("shared_profiles is None") {
+        let address_book = AbProfile {
+            guid: ab.ab,
+            name: ab.name.unwrap_or("".to_string()),
+            owner: guid_into_uuid(ab.owner.expect("Invalid owner")).expect("Invalid GUID"),
+            rule: ab.rule.unwrap_or(AB_RULE_READ),
+            ..Default::default()
+        };
+        ab_shared_profiles.data.push(address_book);
+    }
+    Ok(Json(ab_shared_profiles))
+}
+
+#[get("/js/openapisnippet.min.js")]
+async fn openapi_snippet(files: &State<StaticFiles>) -> Option<StaticFileResponse> {
+    let content = include_str!("../rapidoc/openapisnippet.min.js");
+    Some(StaticFileResponse::new(
+        content.as_bytes().to_vec(),
+        ContentType::JavaScript,
+        &files.cache_policy().scripts,
+    ))
+}
+
+/// Serves the Prometheus metrics, when `metrics` is enabled
+///
+/// The names of the metrics are stable, they are listed in the README
+///
+/// # Returns
+///
+/// * `(ContentType, String)` - the metrics in the Prometheus text format
+#[get("/metrics")]
+async fn metrics_get(state: &State<ApiState>, metrics: &State<Metrics>, _scraper: MetricsScraper) -> (ContentType, String) {
+    (
+        ContentType::new("text", "plain").with_params(("version", "0.0.4")),
+        metrics.render(state).await,
+    )
+}
+
+/// The OpenAPI specification of the API routes
+struct OpenApiSpec(OpenApi);
+
+/// Retrieves the OpenAPI specification
+///
+/// The `servers` of the specification are set to `PUBLIC_BASE_URL`, or else to the host the request was sent to
+/// followed by the base path,
+/// for the "try it" of the documentation and the clients generated behind a reverse proxy
+///
+/// # Returns
+///
+/// * `Json<OpenApi>` - the specification with a single server, or without server when the host is unknown
+#[get("/openapi.json")]
+async fn openapi_json(state: &State<ApiState>, spec: &State<OpenApiSpec>, request: ExtendedRequest) -> Json<OpenApi> {
+    let mut spec = spec.0.clone();
+    let url = state
+        .public_base_url()
+        .map(str::to_string)
+        .unwrap_or_else(|| request.host());
+    if !url.is_empty() {
+        spec.servers = vec![Server {
+            url,
+            ..Default::default()
+        }];
+    }
+    Json(spec)
+}
+
+#[get("/favicon.ico")]
+async fn favicon() -> Redirect {
+    // relative, the server may be mounted under a base path
+    Redirect::to("ui/favicon.ico")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use branding::replace_html_title;
+    use include_dir::Dir;
+    use std::io::Read;
+    use rocket::http::{Accept, CookieJar, Header, Method};
+    use utils::access_log::AccessLogEntry;
+    use utils::{AdminPermission, PeerAccessSource, PeersBulkStatus, UserConflictResponse, UserField, AB_RULE_FULL};
+    use rocket::local::asynchronous::Client;
+    use rocket_okapi::openapi_get_routes;
+
+    fn test_db_file() -> PathBuf {
+        env::temp_dir().join(format!("sctgdesk-test-{}.sqlite3", Uuid::new_v4()))
+    }
+
+    /// Add a user with the password "password" and log it in
+    async fn login_header(state: &ApiState, name: &str, admin: bool) -> Header<'static> {
+        state
+            .add_user(AddUserRequest {
+                name: name.to_string(),
+                password: "password".to_string(),
+                confirm_password: "password".to_string(),
+                email: format!("{}@example.org", name),
+                is_admin: admin,
+                group_name: "Default".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let password = UserPasswordInfo::from_password("password");
+        let (_, token) = state.user_login(&name.to_string(), password, admin).await.unwrap();
+        Header::new("Authorization", format!("Bearer {}", token.to_base64()))
+    }
+
+    #[get("/cookie")]
+    fn set_test_cookie(jar: &CookieJar<'_>, cookie_settings: &State<CookieSettings>) {
+        jar.add(cookie_settings.build("test", "value".to_string()));
+    }
+
+    async fn set_cookie_header(cookie_settings: CookieSettings) -> String {
+        let rocket = rocket::build()
+            .mount("/", routes![set_test_cookie])
+            .manage(cookie_settings);
+        let client = Client::untracked(rocket).await.unwrap();
+        let response = client.get("/cookie").dispatch().await;
+        response.headers().get_one("Set-Cookie").unwrap().to_string()
+    }
+
+    #[rocket::async_test]
+    async fn test_cookie_default_attributes() {
+        let header = set_cookie_header(CookieSettings::default()).await;
+        assert!(header.starts_with("test=value"));
+        assert!(header.contains("HttpOnly"));
+        assert!(header.contains("SameSite=Lax"));
+        assert!(header.contains("Secure"));
+    }
+
+    #[rocket::async_test]
+    async fn test_cookie_configured_attributes() {
+        let settings = CookieSettings::parse(Some("false"), Some("strict")).unwrap();
+        let header = set_cookie_header(settings).await;
+        assert!(header.contains("SameSite=Strict"));
+        assert!(!header.contains("Secure"));
+
+        let settings = CookieSettings::parse(Some("true"), Some("none")).unwrap();
+        let header = set_cookie_header(settings).await;
+        assert!(header.contains("SameSite=None"));
+        assert!(header.contains("Secure"));
+    }
+
+    #[test]
+    fn test_oidc_callback_success_page() {
+        let page = oidc_callback_page(true);
+        assert!(page.contains(r#"data-oidc-status="success""#));
+        let page = oidc_callback_page(false);
+        assert!(page.contains(r#"data-oidc-status="error""#));
+    }
+
+    #[rocket::async_test]
+    async fn test_oidc_callback_unknown_session_returns_error_page() {
+        let db_file = test_db_file();
+        let rocket = rocket::build()
+            .mount("/", routes![oidc_callback])
+            .manage(ApiState::new_with_db(db_file).await);
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client
+            .get("/api/oidc/callback?code=code&state=unknown")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        let page = response.into_string().await.unwrap();
+        assert!(page.contains(r#"data-oidc-status="error""#));
+    }
+
+    #[rocket::async_test]
+    async fn test_oidc_callback_completed_session_returns_success_page() {
+        use std::future::Future;
+
+        use oauth2::oauth_provider::OAuthResponse;
+        use oauth2::{Oauth2Error, Provider};
+
+        /// Provider exchanging any code without calling a server
+        struct TestProvider;
+        impl OAuthProvider for TestProvider {
+            fn get_redirect_url(&self, callback_url: &str, state: &str) -> String {
+                format!("https://provider.example.org/authorize?redirect_uri={}&state={}", callback_url, state)
+            }
+            fn exchange_code(
+                &self,
+                _code: &str,
+                _callback_url: &str,
+            ) -> Pin<Box<dyn Future<Output = Result<OAuthResponse, Oauth2Error>> + Send + Sync>> {
+                Box::pin(async {
+                    Ok(OAuthResponse {
+                        access_token: "provider-token".to_string(),
+                        username: "callbackuser".to_string(),
+                        email: "callbackuser@example.org".to_string(),
+                    })
+                })
+            }
+            fn get_provider_type(&self) -> Provider {
+                Provider::Dex
+            }
+        }
+
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let uuid_code = Uuid::new_v4().to_string();
+        state
+            .insert_oidc_session(
+                uuid_code.clone(),
+                OidcState {
+                    id: "123456789".to_string(),
+                    uuid: "client-uuid".to_string(),
+                    callback_url: Some("http://localhost/api/oidc/callback".to_string()),
+                    provider: Some(Arc::new(TestProvider)),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let rocket = rocket::build().mount("/", routes![oidc_callback]).manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client
+            .get(format!("/api/oidc/callback?code=provider-code&state={}", uuid_code))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::HTML));
+        let page = response.into_string().await.unwrap();
+        assert!(page.contains(r#"data-oidc-status="success""#));
+        // the session is completed, the client gets its token on the next query
+        let state = client.rocket().state::<ApiState>().unwrap();
+        let session = state.get_oidc_session(uuid_code).await.unwrap();
+        assert_eq!(session.auth_token.as_deref(), Some("provider-token"));
+    }
+
+    #[rocket::async_test]
+    async fn test_version_returns_crate_version() {
+        let rocket = rocket::build().mount("/", routes![version]);
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client.get("/api/version").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let build_info: BuildInfoResponse = response.into_json().await.unwrap();
+        assert_eq!(build_info.version, env!("CARGO_PKG_VERSION"));
+        assert!(!build_info.git_hash.is_empty());
+    }
+
+    #[test]
+    fn test_accepts_gzip() {
+        assert!(api::accepts_gzip("gzip"));
+        assert!(api::accepts_gzip("deflate, gzip;q=0.8, br"));
+        assert!(api::accepts_gzip("*"));
+        assert!(!api::accepts_gzip("gzip;q=0"));
+        assert!(!api::accepts_gzip("gzip;q=0, *"));
+        assert!(!api::accepts_gzip("br, deflate"));
+        assert!(!api::accepts_gzip(""));
+    }
+
+    #[rocket::async_test]
+    async fn test_ab_get_gzip_round_trip() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "gzip", false).await;
+
+        let rocket = rocket::build().mount("/", routes![ab, ab_get]).manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let peers: Vec<String> = (0..2000)
+            .map(|i| format!(r#"{{"id":"{}","alias":"peer {}","tags":["office"]}}"#, 100000000 + i, i))
+            .collect();
+        let ab = format!(r#"{{"tags":["office"],"peers":[{}]}}"#, peers.join(","));
+        let response = client
+            .post("/api/ab")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(serde_json::json!({ "data": ab }).to_string())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let response = client
+            .get("/api/ab")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .header(Header::new("Accept-Encoding", "gzip, deflate"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Content-Encoding"), Some("gzip"));
+        let compressed = response.into_bytes().await.unwrap();
+        // decoded with the inflate of the clients, not with the encoder of the server
+        let mut body = Vec::new();
+        flate2::read::GzDecoder::new(compressed.as_slice()).read_to_end(&mut body).unwrap();
+        assert!(compressed.len() * 4 < body.len());
+        let reply: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(reply["data"], ab);
+
+        // without Accept-Encoding the answer is plain JSON
+        let response = client
+            .get("/api/ab")
+            .header(ContentType::JSON)
+            .header(authorization)
+            .dispatch()
+            .await;
+        assert_eq!(response.headers().get_one("Content-Encoding"), None);
+        let reply: serde_json::Value = response.into_json().await.unwrap();
+        assert_eq!(reply["data"], ab);
+    }
+
+    #[rocket::async_test]
+    async fn test_capabilities_without_s3() {
+        let s3_config = env::temp_dir().join(format!("sctgdesk-test-{}.toml", Uuid::new_v4()));
+        let db_file = test_db_file();
+        let rocket = rocket::build()
+            .mount("/", routes![capabilities])
+            .manage(ApiState::new_with_db(db_file).await)
+            .manage(ReleaseCache::default().with_config_file(&s3_config));
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client.get("/api/capabilities").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let capabilities: CapabilitiesResponse = response.into_json().await.unwrap();
+        assert!(!capabilities.s3_configured);
+        assert!(!capabilities.software_downloads);
+        assert!(capabilities.shared_address_books);
+        // the capabilities are read only, the missing config file is not created
+        assert!(!s3_config.exists());
+    }
+
+    #[rocket::async_test]
+    async fn test_audit_alarm_webhook() {
+        use tokio::io::AsyncReadExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let webhook_url = format!("http://{}/alarm", listener.local_addr().unwrap());
+        let figment = rocket::Config::figment().merge(("audit_webhook", webhook_url));
+        let db_file = test_db_file();
+        let rocket = rocket::custom(&figment)
+            .mount("/", routes![audit_typ])
+            .manage(ApiState::new_with_db(db_file).await)
+            .manage(AuditWebhook::from_figment(&figment));
+        let client = Client::tracked(rocket).await.unwrap();
+
+        // the webhook does not answer, the audit request must not wait for it
+        let response = client
+            .post("/api/audit/alarm")
+            .header(ContentType::JSON)
+            .body(r#"{"id":"123456789","typ":1,"info":"{\"ip\":\"10.0.0.3\"}"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let (mut socket, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0u8; 1024];
+        while !String::from_utf8_lossy(&request).contains("critical") {
+            let read = socket.read(&mut buffer).await.unwrap();
+            assert!(read > 0, "webhook closed without the alarm");
+            request.extend_from_slice(&buffer[..read]);
+        }
+        let request = String::from_utf8_lossy(&request);
+        assert!(request.starts_with("POST /alarm"));
+        assert!(request.contains(r#""peer":"123456789""#));
+    }
+
+    #[rocket::async_test]
+    async fn test_audit_export_logins_csv() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "auditor", true).await;
+
+        let rocket = rocket::build().mount("/", routes![audit_export]).manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client
+            .get("/api/audit/export?type=login&user=auditor")
+            .header(authorization.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::CSV));
+        let body = response.into_string().await.unwrap();
+        let lines: Vec<&str> = body.split("\r\n").filter(|line| !line.is_empty()).collect();
+        assert_eq!(lines[0], "user,ip,device_name,os,type,created_at,expiry_at");
+        assert_eq!(lines.len(), 2);
+        let fields: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(fields[0], "auditor");
+        assert!(fields[5].ends_with('Z') && fields[5].contains('T'));
+
+        let response = client
+            .get("/api/audit/export?type=session")
+            .header(authorization.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.into_string().await.unwrap().contains("session"));
+
+        let response = client
+            .get("/api/audit/export?type=conn&format=xlsx")
+            .header(authorization)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn test_client_config_download() {
+        let figment = rocket::Config::figment()
+            .merge(("client_relay_server", "relay.example.org"))
+            .merge(("client_key", "OeVuKk5nlHiXp+APNn0Y3pC1Iwpwn44JGqrQCsWqmBw="));
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "config", false).await;
+
+        let rocket = rocket::custom(&figment)
+            .mount("/", routes![client_config_download])
+            .manage(state)
+            .manage(ClientConfig::from_figment(&figment));
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client
+            .get("/api/client-config/download")
+            .header(authorization)
+            .header(Header::new("Host", "desk.example.org:21114"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.headers().get_one("Content-Disposition"),
+            Some("attachment; filename=\"RustDesk2.toml\"")
+        );
+        let body = response.into_string().await.unwrap();
+        assert!(body.contains(r#"rendezvous_server = "desk.example.org:21116""#));
+        assert!(body.contains(r#"relay-server = "relay.example.org""#));
+        assert!(body.contains(r#"key = "OeVuKk5nlHiXp+APNn0Y3pC1Iwpwn44JGqrQCsWqmBw=""#));
+        assert!(body.contains(r#"api-server = "http://desk.example.org:21114""#));
+    }
+
+    #[rocket::async_test]
+    async fn test_audit_forwarder_syslog_udp() {
+        let syslog = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let syslog_url = format!("udp://{}", syslog.local_addr().unwrap());
+        let figment = rocket::Config::figment().merge(("audit_forward_syslog", syslog_url));
+        let db_file = test_db_file();
+        let rocket = rocket::custom(&figment)
+            .attach(AuditForwarder::from_figment(&figment))
+            .mount("/", routes![audit_typ])
+            .manage(ApiState::new_with_db(db_file).await)
+            .manage(AuditWebhook::from_figment(&figment));
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let response = client
+            .post("/api/audit/alarm")
+            .header(ContentType::JSON)
+            .body(r#"{"id":"123456789","typ":1,"info":"{\"ip\":\"10.0.0.3\"}"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let mut buffer = [0u8; 2048];
+        let received = tokio::time::timeout(std::time::Duration::from_secs(10), syslog.recv(&mut buffer))
+            .await
+            .expect("no syslog message")
+            .unwrap();
+        let message = String::from_utf8_lossy(&buffer[..received]);
+        // facility log audit, severity critical
+        assert!(message.starts_with("<106>1 "));
+        assert!(message.contains(" sctgdesk-api-server - alarm - "));
+        assert!(message.contains(r#""peer":"123456789""#));
+    }
+
+    #[rocket::async_test]
+    async fn test_oidc_reload_updates_login_options() {
+        let provider = |name: &str| {
+            format!(
+                "[[provider]]\nprovider = \"Github\"\nauthorization_url = \"https://github.com/login/oauth/authorize\"\n\
+                 token_exchange_url = \"https://github.com/login/oauth/access_token\"\napp_id = \"id\"\napp_secret = \"secret\"\n\
+                 scope = \"read:user\"\nop_auth_string = \"oidc/{}\"\nop = \"{}\"\n",
+                name, name
+            )
+        };
+        let config_file = env::temp_dir().join(format!("sctgdesk-test-{}.toml", Uuid::new_v4()));
+        std::fs::write(&config_file, provider("github")).unwrap();
+
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file)
+            .await
+            .with_oauth2_config_file(&config_file.to_string_lossy());
+        let authorization = login_header(&state, "oidcadmin", true).await;
+        let rocket = rocket::build()
+            .mount("/", routes![login_options, oidc_reload])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let login_options = || async {
+            let response = client
+                .get("/api/login-options")
+                .header(ContentType::JSON)
+                .dispatch()
+                .await;
+            response.into_json::<Vec<String>>().await.unwrap()
+        };
+        assert_eq!(login_options().await, vec!["oidc/github"]);
+
+        // the providers are cached until they are reloaded
+        std::fs::write(&config_file, provider("github") + &provider("dex")).unwrap();
+        assert_eq!(login_options().await, vec!["oidc/github"]);
+        let response = client.post("/api/oidc/reload").header(authorization.clone()).dispatch().await;
+        let reload: OidcReloadResponse = response.into_json().await.unwrap();
+        assert_eq!(reload.providers, 2);
+        assert_eq!(login_options().await, vec!["oidc/github", "oidc/dex"]);
+
+        // an invalid file is rejected and the providers are kept
+        std::fs::write(&config_file, "[[provider]]\nprovider = \"Github\"\n").unwrap();
+        let response = client.post("/api/oidc/reload").header(authorization).dispatch().await;
+        let reload: OidcReloadResponse = response.into_json().await.unwrap();
+        assert_eq!(reload.msg, "error");
+        assert_eq!(reload.errors.len(), 1);
+        assert_eq!(login_options().await, vec!["oidc/github", "oidc/dex"]);
+    }
+
+    #[rocket::async_test]
+    async fn test_integration_smtp_and_webhook() {
+        use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+        let smtp = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let webhook = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let figment = rocket::Config::figment()
+            .merge(("smtp_server", smtp.local_addr().unwrap().to_string()))
+            .merge(("audit_webhook", format!("http://{}/hook", webhook.local_addr().unwrap())))
+            .merge(("audit_webhook_secret", "shared"));
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "integrator", true).await;
+        let rocket = rocket::custom(&figment)
+            .mount("/", routes![test_integration])
+            .manage(state)
+            .manage(SmtpSettings::from_figment(&figment))
+            .manage(AuditWebhook::from_figment(&figment));
+        let client = Client::tracked(rocket).await.unwrap();
+        let test = |target: &'static str| {
+            client
+                .post("/api/admin/test-integration")
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .header(Header::new("Host", "desk.example.org:21114"))
+                .body(format!(r#"{{"target":"{}"}}"#, target))
+                .dispatch()
+        };
+
+        let smtp_server = tokio::spawn(async move {
+            let (socket, _) = smtp.accept().await.unwrap();
+            let mut socket = BufReader::new(socket);
+            socket.get_mut().write_all(b"220 mock ESMTP\r\n").await.unwrap();
+            let mut ehlo = String::new();
+            socket.read_line(&mut ehlo).await.unwrap();
+            socket.get_mut().write_all(b"250-mock greets you\r\n250 SIZE 1000\r\n").await.unwrap();
+            let mut quit = String::new();
+            socket.read_line(&mut quit).await.unwrap();
+            (ehlo, quit)
+        });
+        let response: IntegrationTestResponse = test("smtp").await.into_json().await.unwrap();
+        assert!(response.success, "{}", response.detail);
+        assert!(response.detail.contains("250-mock greets you 250 SIZE 1000"));
+        let (ehlo, quit) = smtp_server.await.unwrap();
+        assert_eq!(ehlo, "EHLO desk.example.org\r\n");
+        assert_eq!(quit, "QUIT\r\n");
+
+        let webhook_server = tokio::spawn(async move {
+            let (mut socket, _) = webhook.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !String::from_utf8_lossy(&request).ends_with("}") {
+                let read = socket.read(&mut buffer).await.unwrap();
+                assert!(read > 0, "webhook closed without the ping");
+                request.extend_from_slice(&buffer[..read]);
+            }
+            socket
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+        let response: IntegrationTestResponse = test("webhook").await.into_json().await.unwrap();
+        assert!(response.success, "{}", response.detail);
+        assert!(response.detail.contains("204"));
+        let request = webhook_server.await.unwrap();
+        let body = request.split("\r\n\r\n").nth(1).unwrap();
+        assert_eq!(body, r#"{"event":"ping"}"#);
+        let signature = format!(
+            "{}: {}",
+            audit_webhook::AUDIT_WEBHOOK_SIGNATURE_HEADER.to_lowercase(),
+            audit_webhook::webhook_signature("shared", body.as_bytes())
+        );
+        assert!(request.to_lowercase().contains(&signature.to_lowercase()));
+
+        // the smtp server is gone, the failure is reported with its reason
+        let response: IntegrationTestResponse = test("smtp").await.into_json().await.unwrap();
+        assert!(!response.success);
+        assert!(!response.detail.is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn test_strategy_crud() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "strategist", true).await;
+        let admin = state.get_all_users(Some("strategist"), None, None, 1, 1).await.unwrap()[0]
+            .guid
+            .clone();
+        let rocket = rocket::build()
+            .mount(
+                "/",
+                openapi_get_routes![
+                    strategies,
+                    strategy_get,
+                    strategy_add,
+                    strategy_duplicate,
+                    strategy_update,
+                    strategy_delete,
+                    user_strategy
+                ],
+            )
+            .mount("/", routes![strategies_deprecated])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let response = client
+            .post("/api/strategy")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(r#"{"name":"Bad","settings":{"enable-clipboard":"maybe","direct-access-port":0}}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let reason = response.into_string().await.unwrap();
+        assert!(reason.contains("enable-clipboard") && reason.contains("direct-access-port"));
+
+        let locked = r#"{"name":"Locked","settings":{"enable-clipboard":"N","x-custom":[1]},"is_default":true}"#;
+        let response = client
+            .post("/api/strategy")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(locked)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let reason = response.into_string().await.unwrap();
+        assert!(reason.contains("x-custom is not a known option"));
+        let response = client
+            .post("/api/strategy?allow_unknown=true")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(locked)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let locked: Strategy = response.into_json().await.unwrap();
+        assert!(locked.is_default);
+        assert_eq!(locked.settings["x-custom"], serde_json::json!([1]));
+
+        let list: StrategiesResponse = client
+            .get("/api/strategies?current=1&pageSize=10")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(list.total, 2);
+        let deprecated: StrategiesResponse = client
+            .get("/api/stategies?current=1&pageSize=10")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(deprecated.total, list.total);
+        let spec = client
+            .get("/openapi.json")
+            .dispatch()
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(spec.contains("\"/api/strategies\""));
+        assert!(!spec.contains("/api/stategies"));
+        assert_eq!(
+            list.data.iter().filter(|strategy| strategy.is_default).count(),
+            1
+        );
+        let previous = list.data.iter().find(|strategy| !strategy.is_default).unwrap();
+
+        let response = client
+            .put(format!("/api/strategy/{}", locked.guid))
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(r#"{"is_default":false}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = client
+            .delete(format!("/api/strategy/{}", locked.guid))
+            .header(authorization.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Forbidden);
+
+        let response = client
+            .put(format!("/api/strategy/{}", previous.guid))
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(r#"{"name":"Previous","is_default":true}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        // an assigned strategy is only deleted with cascade
+        let response = client
+            .put(format!("/api/users/{}/strategy", admin))
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(format!(r#"{{"strategy":"{}"}}"#, locked.guid))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .delete(format!("/api/strategy/{}", locked.guid))
+            .header(authorization.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = client
+            .delete(format!("/api/strategy/{}?cascade=true", locked.guid))
+            .header(authorization.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .get(format!("/api/strategy/{}", locked.guid))
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = client
+            .post(format!("/api/strategy/{}/duplicate", previous.guid))
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body("{}")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let copy: Strategy = response.into_json().await.unwrap();
+        assert_eq!(copy.name, "Previous (copy)");
+        assert!(!copy.is_default);
+        let response = client
+            .get("/api/strategies?q=(COPY)&order=-name")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .dispatch()
+            .await;
+        let list: StrategiesResponse = response.into_json().await.unwrap();
+        assert_eq!(list.total, 1);
+        assert_eq!(list.data[0].guid, copy.guid);
+        let response = client
+            .get("/api/strategies?order=settings")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn test_heartbeat_plain_and_directives() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file)
+            .await
+            .with_heartbeat_min_interval(Duration::ZERO);
+        let rocket = rocket::build()
+            .mount("/", routes![heartbeat])
+            .manage(DefaultClientVersions::from_figment(&rocket::Config::figment()))
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let beat = |id: &str, modified_at: u64| {
+            client
+                .post("/api/heartbeat")
+                .header(ContentType::JSON)
+                .body(format!(
+                    r#"{{"id":"{}","modified_at":{},"uuid":"aGVhcnRiZWF0","ver":1}}"#,
+                    id, modified_at
+                ))
+        };
+
+        // the directives are sent as JSON even without Accept, but the configuration only to the clients asking for JSON
+        let response: HeartbeatResponse = beat("123456789", 0)
+            .header(Header::new("Authorization", "Bearer expired"))
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(response.status, "OK");
+        assert_eq!(response.directives, vec![HeartbeatDirective::Relogin]);
+        let response = beat("123456789", 0).dispatch().await;
+        assert_eq!(response.content_type(), Some(ContentType::Plain));
+        assert_eq!(response.into_string().await.unwrap(), "OK");
+        let response: HeartbeatResponse = beat("123456789", 0)
+            .header(Accept::JSON)
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(response.directives.len(), 1);
+        let up_to_date = match &response.directives[0] {
+            HeartbeatDirective::ConfigChanged { version, strategy, .. } => {
+                assert_eq!(strategy, "Default");
+                *version
+            }
+            directive => panic!("unexpected directive {:?}", directive),
+        };
+
+        let response = beat("123456789", up_to_date).dispatch().await;
+        assert_eq!(response.content_type(), Some(ContentType::Plain));
+        assert_eq!(response.into_string().await.unwrap(), "OK");
+        let response = beat("12", up_to_date).dispatch().await;
+        assert_eq!(response.into_string().await.unwrap(), "INVALID_ID");
+
+        let response = beat("123456789", up_to_date)
+            .header(Accept::JSON)
+            .dispatch()
+            .await;
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        assert_eq!(response.into_string().await.unwrap(), r#"{"status":"OK"}"#);
+
+        let state = client.rocket().state::<ApiState>().unwrap();
+        assert!(state
+            .set_maintenance_notice(Some(MaintenanceNotice { at: 1, message: None }))
+            .await
+            .is_none());
+        let at = 4102444800;
+        let notice = MaintenanceNotice {
+            at,
+            message: Some("upgrade".to_string()),
+        };
+        state.set_maintenance_notice(Some(notice)).await.unwrap();
+        let response: HeartbeatResponse = beat("123456789", up_to_date)
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(
+            response.directives,
+            vec![HeartbeatDirective::MaintenanceSoon {
+                at,
+                message: Some("upgrade".to_string())
+            }]
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_heartbeat_throttling() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file)
+            .await
+            .with_heartbeat_min_interval(Duration::from_secs(5));
+        // count the writes of the heartbeats
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        for statement in [
+            "INSERT INTO peer(guid, id, uuid, pk) VALUES (x'01', '123456789', x'686561727462656174', x'')",
+            "INSERT INTO peer(guid, id, uuid, pk) VALUES (x'02', '987654321', x'6f74686572', x'')",
+            "CREATE TABLE heartbeat_writes(id TEXT)",
+            "CREATE TRIGGER count_heartbeat_writes AFTER UPDATE OF last_online ON peer
+             BEGIN INSERT INTO heartbeat_writes VALUES (new.id); END",
+        ] {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+        let rocket = rocket::build()
+            .mount("/", routes![heartbeat])
+            .manage(DefaultClientVersions::from_figment(&rocket::Config::figment()))
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let beat = |id: &str, uuid: &str| {
+            client
+                .post("/api/heartbeat")
+                .header(ContentType::JSON)
+                .header(Accept::JSON)
+                .body(format!(r#"{{"id":"{}","modified_at":0,"uuid":"{}","ver":1}}"#, id, uuid))
+                .dispatch()
+        };
+
+        let throttled = |response: &HeartbeatResponse| {
+            response
+                .directives
+                .iter()
+                .any(|directive| matches!(directive, HeartbeatDirective::SlowDown { .. }))
+        };
+        let response: HeartbeatResponse = beat("123456789", "aGVhcnRiZWF0").await.into_json().await.unwrap();
+        assert_eq!(response.status, "OK");
+        assert!(!throttled(&response));
+        // the rapid heartbeats are accepted but not recorded
+        for _ in 0..20 {
+            let response: HeartbeatResponse = beat("123 456 789", "aGVhcnRiZWF0").await.into_json().await.unwrap();
+            assert_eq!(response.status, "OK");
+            assert_eq!(response.directives, vec![HeartbeatDirective::SlowDown { interval: 5 }]);
+        }
+        // another peer, or the same id with another uuid, is not throttled
+        let response: HeartbeatResponse = beat("987654321", "b3RoZXI=").await.into_json().await.unwrap();
+        assert!(!throttled(&response));
+        // a rejected heartbeat is never throttled
+        for _ in 0..2 {
+            let response: HeartbeatResponse = beat("123456789", "Y2xvbmU=").await.into_json().await.unwrap();
+            assert_eq!(response.status, "UUID_MISMATCH");
+            assert!(!throttled(&response));
+        }
+
+        let writes: Vec<(String,)> = sqlx::query_as("SELECT id FROM heartbeat_writes ORDER BY rowid")
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        let writes: Vec<&str> = writes.iter().map(|(id,)| id.as_str()).collect();
+        assert_eq!(writes, ["123456789", "987654321"]);
+    }
+
+    #[rocket::async_test]
+    async fn test_user_add_and_update_conflicts() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "manager", true).await;
+        let rocket = rocket::build()
+            .mount("/", routes![user_add, user_update])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let add = |name: &str, email: &str| {
+            serde_json::json!({
+                "name": name,
+                "password": "password",
+                "confirm-password": "password",
+                "email": email,
+                "is_admin": false,
+                "group_name": "Default"
+            })
+            .to_string()
+        };
+        let response = client
+            .post("/api/user")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(add("worker", "worker@example.org"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        for (body, field) in [
+            (add("worker", "another@example.org"), UserField::Name),
+            (add("other", "Worker@EXAMPLE.org"), UserField::Email),
+        ] {
+            let response = client
+                .post("/api/user")
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body(body)
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Conflict);
+            let conflict: UserConflictResponse = response.into_json().await.unwrap();
+            assert_eq!(conflict.field, field);
+        }
+
+        let response = client
+            .put("/api/user")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(r#"{"uuid":"","email":"WORKER@example.org"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Conflict);
+        let conflict: UserConflictResponse = response.into_json().await.unwrap();
+        assert_eq!(conflict.field, UserField::Email);
+    }
+
+    #[rocket::async_test]
+    async fn test_access_log_lines() {
+        let db_file = test_db_file();
+        let log_file = env::temp_dir().join(format!("sctgdesk-test-{}.log", Uuid::new_v4()));
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "logged", true).await;
+        let admin = state.get_all_users(Some("logged"), None, None, 1, 1).await.unwrap()[0]
+            .guid
+            .clone();
+        let rocket = rocket::build()
+            .attach(AccessLog::new(log_file.to_str()))
+            .mount("/", routes![peer_get])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let response = client
+            .get("/api/peer/123456789?token=secret")
+            .remote("192.0.2.10:50000".parse().unwrap())
+            .header(ContentType::JSON)
+            .header(authorization)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+        let request_id = response.headers().get_one("X-Request-Id").unwrap().to_string();
+        let response = client
+            .get("/api/peer/123456789")
+            .header(ContentType::JSON)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let log = std::fs::read_to_string(&log_file).unwrap();
+        let entries = log
+            .lines()
+            .map(|line| serde_json::from_str::<AccessLogEntry>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].method, "GET");
+        assert_eq!(entries[0].path, "/api/peer/123456789");
+        assert_eq!(entries[0].status, 404);
+        assert!(entries[0].latency_ms >= 0.0);
+        assert_eq!(entries[0].client_ip.as_deref(), Some("192.0.2.10"));
+        assert_eq!(entries[0].user_id.as_deref(), Some(admin.as_str()));
+        assert_eq!(entries[0].request_id, request_id);
+        assert!(entries[0].timestamp.ends_with('Z'));
+        assert!(!log.contains("secret"));
+        assert_eq!(entries[1].status, 401);
+        assert_eq!(entries[1].user_id, None);
+        assert_ne!(entries[1].request_id, request_id);
+        std::fs::remove_file(&log_file).ok();
+    }
+
+    #[rocket::async_test]
+    async fn test_access_log_text_filters() {
+        use utils::access_log::{AccessLogFormat, AccessLogLevel};
+
+        let db_file = test_db_file();
+        let log_file = env::temp_dir().join(format!("sctgdesk-test-{}.log", Uuid::new_v4()));
+        let state = ApiState::new_with_db(db_file).await;
+        let rocket = rocket::build()
+            .attach(
+                AccessLog::new(log_file.to_str())
+                    .with_format(AccessLogFormat::Text)
+                    .with_level(AccessLogLevel::Warn)
+                    .with_excluded_paths(utils::access_log::parse_excluded_paths("/api/peer/42")),
+            )
+            .mount("/", routes![peer_get, capabilities])
+            .manage(state)
+            .manage(ReleaseCache::default());
+        let client = Client::tracked(rocket).await.unwrap();
+
+        // below the level
+        let response = client.get("/api/capabilities").header(ContentType::JSON).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.headers().get_one("X-Request-Id").is_some());
+        // excluded
+        let response = client.get("/api/peer/42").header(ContentType::JSON).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert!(response.headers().get_one("X-Request-Id").is_some());
+        let response = client
+            .get("/api/peer/123456789")
+            .remote("192.0.2.10:50000".parse().unwrap())
+            .header(ContentType::JSON)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let request_id = response.headers().get_one("X-Request-Id").unwrap().to_string();
+
+        let log = std::fs::read_to_string(&log_file).unwrap();
+        let lines = log.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 1, "{}", log);
+        let fields = lines[0].split(' ').collect::<Vec<_>>();
+        assert!(fields[0].ends_with('Z'));
+        assert_eq!(fields[1..5], ["WARN", "GET", "/api/peer/123456789", "401"]);
+        assert!(fields[5].starts_with("latency_ms="));
+        assert_eq!(
+            fields[6..],
+            ["client_ip=192.0.2.10", "user_id=-", format!("request_id={}", request_id).as_str()]
+        );
+        std::fs::remove_file(&log_file).ok();
+    }
+
+    #[rocket::async_test]
+    async fn test_dry_run_admin_operations() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file.clone()).await;
+        let authorization = login_header(&state, "manager", true).await;
+        login_header(&state, "worker", false).await;
+        let worker = state.get_all_users(Some("worker"), None, None, 1, 10).await.unwrap()[0]
+            .guid
+            .clone();
+        // the peers are registered by hbbs
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO peer(guid, id, uuid, pk) VALUES (?, '123456789', x'01', x'')")
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let rocket = rocket::build()
+            .mount("/", routes![peers_bulk, user_enable])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let state = client.rocket().state::<ApiState>().unwrap();
+
+        let response: PeersBulkResponse = client
+            .post("/api/peers/bulk?dry_run=true")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(r#"{"action":"delete","ids":["123456789","987654321"]}"#)
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(response.msg, "dry run");
+        let statuses: Vec<PeersBulkStatus> = response.data.iter().map(|result| result.result).collect();
+        assert_eq!(statuses, vec![PeersBulkStatus::Ok, PeersBulkStatus::NotFound]);
+        assert!(state.get_peer("123456789").await.is_some());
+
+        // the web console sends `disable: false` to disable a user
+        let enable = |dry_run: bool| {
+            client
+                .post(format!("/api/enable-users?dry_run={}", dry_run))
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body(format!(r#"{{"rows":["{}"],"disable":false}}"#, worker))
+        };
+        let response = enable(true).dispatch().await.into_string().await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["msg"], "dry run");
+        assert_eq!(response["total"], 1);
+        assert_eq!(response["data"], serde_json::json!([worker]).to_string());
+        let filter = AuditFilter::new(None, None, None, None, None, None);
+        assert_eq!(state.get_admin_audits(&filter).await.unwrap().0, 0);
+        assert_eq!(enable(false).dispatch().await.status(), Status::Ok);
+        let response = enable(true).dispatch().await.into_string().await.unwrap();
+        let response: serde_json::Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["total"], 0);
+    }
+
+    #[rocket::async_test]
+    async fn test_peer_update_and_heartbeat_assignment() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file)
+            .await
+            .with_heartbeat_min_interval(Duration::ZERO);
+        let authorization = login_header(&state, "assigner", true).await;
+        let member = login_header(&state, "assignee", false).await;
+        let assignee = state.get_all_users(Some("assignee"), None, None, 1, 10).await.unwrap()[0]
+            .guid
+            .clone();
+        // the peers are registered by hbbs
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        for (id, uuid) in [("123456789", 1u8), ("987654321", 2u8)] {
+            sqlx::query("INSERT INTO peer(guid, id, uuid, pk) VALUES (?, ?, ?, x'')")
+                .bind(Uuid::new_v4().as_bytes().to_vec())
+                .bind(id)
+                .bind(vec![uuid])
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let rocket = rocket::build()
+            .mount("/", routes![peer_update, heartbeat])
+            .manage(DefaultClientVersions::from_figment(&rocket::Config::figment()))
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let state = client.rocket().state::<ApiState>().unwrap();
+        let update = |authorization: Header<'static>, body: String| {
+            client
+                .put("/api/peer/123456789")
+                .header(ContentType::JSON)
+                .header(authorization)
+                .body(body)
+                .dispatch()
+        };
+
+        let response = client
+            .put("/api/peer/123456789")
+            .header(ContentType::JSON)
+            .body(r#"{"user":null}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = update(member.clone(), format!(r#"{{"user":"{}"}}"#, assignee)).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(state.get_peer("123456789").await.unwrap().user, None);
+
+        let response = update(authorization.clone(), format!(r#"{{"user":"{}"}}"#, assignee)).await;
+        assert_eq!(response.status(), Status::Ok);
+        let peer = state.get_peer("123456789").await.unwrap();
+        assert_eq!(peer.user, Some(assignee.clone()));
+        assert_eq!(peer.user_name.as_deref(), Some("assignee"));
+        let response = update(authorization.clone(), r#"{"user":"not a guid"}"#.to_string())
+            .await
+            .into_string()
+            .await
+            .unwrap();
+        assert!(response.contains("Invalid user"));
+        let response = update(authorization.clone(), r#"{"user":null}"#.to_string()).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(state.get_peer("123456789").await.unwrap().user, None);
+
+        // the heartbeat of a logged in client assigns the unassigned peer, never an assigned one
+        let beat = |id: &str, uuid: &str, authorization: Header<'static>| {
+            client
+                .post("/api/heartbeat")
+                .header(ContentType::JSON)
+                .header(authorization)
+                .body(format!(r#"{{"id":"{}","modified_at":0,"uuid":"{}","ver":1}}"#, id, uuid))
+                .dispatch()
+        };
+        let response = beat("123456789", "AQ==", member.clone()).await;
+        assert_eq!(response.into_string().await.unwrap(), "OK");
+        assert_eq!(state.get_peer("123456789").await.unwrap().user, Some(assignee.clone()));
+        let admin = state.get_all_users(Some("assigner"), None, None, 1, 10).await.unwrap()[0]
+            .guid
+            .clone();
+        let response = update(authorization.clone(), format!(r#"{{"user":"{}"}}"#, admin)).await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = beat("123456789", "AQ==", member.clone()).await;
+        assert_eq!(response.into_string().await.unwrap(), "OK");
+        assert_eq!(state.get_peer("123456789").await.unwrap().user, Some(admin));
+        // an anonymous heartbeat assigns nothing
+        let response = client
+            .post("/api/heartbeat")
+            .header(ContentType::JSON)
+            .body(r#"{"id":"987654321","modified_at":0,"uuid":"Ag==","ver":1}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.into_string().await.unwrap(), "OK");
+        assert_eq!(state.get_peer("987654321").await.unwrap().user, None);
+    }
+
+    #[rocket::async_test]
+    async fn test_peers_bulk() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let authorization = login_header(&state, "bulker", true).await;
+        let member = login_header(&state, "bystander", false).await;
+        // the peers are registered by hbbs
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        for (id, uuid) in [("123456789", 1u8), ("987654321", 2u8)] {
+            sqlx::query("INSERT INTO peer(guid, id, uuid, pk) VALUES (?, ?, ?, x'')")
+                .bind(Uuid::new_v4().as_bytes().to_vec())
+                .bind(id)
+                .bind(vec![uuid])
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let rocket = rocket::build().mount("/", routes![peers_bulk]).manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let state = client.rocket().state::<ApiState>().unwrap();
+        let bulk = |authorization: Header<'static>, body: &str| {
+            client
+                .post("/api/peers/bulk")
+                .header(ContentType::JSON)
+                .header(authorization)
+                .body(body.to_string())
+                .dispatch()
+        };
+        let default_group = "018f2556-22fb-73ee-9afd-bbcdc0cc387b";
+
+        let response = bulk(member, r#"{"action":"disable","ids":["123456789"]}"#).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        assert_eq!(state.get_peer("123456789").await.unwrap().status, 1);
+
+        let response = bulk(authorization.clone(), r#"{"action":"set_group","ids":["123456789"]}"#).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let response: PeersBulkResponse = bulk(
+            authorization.clone(),
+            &format!(
+                r#"{{"action":"set_group","ids":["123456789","987654321","111111111"],"group":"{}"}}"#,
+                default_group
+            ),
+        )
+        .await
+        .into_json()
+        .await
+        .unwrap();
+        assert_eq!(response.msg, "success");
+        let statuses: Vec<PeersBulkStatus> = response.data.iter().map(|result| result.result).collect();
+        assert_eq!(
+            statuses,
+            vec![PeersBulkStatus::Ok, PeersBulkStatus::Ok, PeersBulkStatus::NotFound]
+        );
+        let peer = state.get_peer("987654321").await.unwrap();
+        assert_eq!(peer.group_name.as_deref(), Some("Default"));
+
+        let response: PeersBulkResponse = bulk(authorization.clone(), r#"{"action":"disable","ids":["123456789"]}"#)
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(response.total, 1);
+        assert_eq!(state.get_peer("123456789").await.unwrap().status, 0);
+        assert_eq!(state.get_peer("987654321").await.unwrap().status, 1);
+
+        let response: PeersBulkResponse = bulk(authorization.clone(), r#"{"action":"delete","ids":["123456789"]}"#)
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(response.data[0].result, PeersBulkStatus::Ok);
+        assert!(state.get_peer("123456789").await.is_none());
+        assert!(state.get_peer("987654321").await.is_some());
+
+        // one admin audit per batch
+        let filter = AuditFilter::new(None, None, None, None, None, None);
+        assert_eq!(state.get_admin_audits(&filter).await.unwrap().0, 3);
+    }
+
+    #[rocket::async_test]
+    async fn test_peers_conflicts() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let authorization = login_header(&state, "resolver", true).await;
+        let member = login_header(&state, "cloner", false).await;
+        // the peers are registered by hbbs
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO peer(guid, id, uuid, pk) VALUES (?, '123456789', x'01', x'')")
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let rocket = rocket::build()
+            .mount("/", routes![heartbeat, peers_conflicts, peers_conflicts_delete])
+            .manage(DefaultClientVersions::from_figment(&rocket::Config::figment()))
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let conflicts = |authorization: Header<'static>| {
+            client
+                .get("/api/peers/conflicts")
+                .header(ContentType::JSON)
+                .header(authorization)
+                .dispatch()
+        };
+        let dismiss = |authorization: Header<'static>| {
+            client
+                .delete("/api/peers/conflicts/123456789")
+                .header(authorization)
+                .dispatch()
+        };
+
+        assert_eq!(conflicts(member.clone()).await.status(), Status::Unauthorized);
+        let response: PeerUuidConflictsResponse = conflicts(authorization.clone()).await.into_json().await.unwrap();
+        assert_eq!(response.total, 0);
+        assert_eq!(dismiss(authorization.clone()).await.status(), Status::NotFound);
+
+        // a cloned machine sends the registered id with its own uuid
+        for _ in 0..2 {
+            let response = client
+                .post("/api/heartbeat")
+                .header(ContentType::JSON)
+                .body(r#"{"id":"123456789","modified_at":0,"uuid":"Y2xvbmU=","ver":1}"#)
+                .dispatch()
+                .await;
+            assert_eq!(response.into_string().await.unwrap(), "UUID_MISMATCH");
+        }
+        let response: PeerUuidConflictsResponse = conflicts(authorization.clone()).await.into_json().await.unwrap();
+        assert_eq!(response.total, 1);
+        assert_eq!(response.data[0].id, "123456789");
+        assert_eq!(response.data[0].uuid, "Y2xvbmU=");
+        assert_eq!(response.data[0].count, 2);
+
+        assert_eq!(dismiss(member).await.status(), Status::Unauthorized);
+        assert_eq!(dismiss(authorization.clone()).await.status(), Status::Ok);
+        let response: PeerUuidConflictsResponse = conflicts(authorization.clone()).await.into_json().await.unwrap();
+        assert_eq!(response.total, 0);
+        assert_eq!(dismiss(authorization).await.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn test_peers_export_interrupted() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let authorization = login_header(&state, "exporter", true).await;
+        // the peers are registered by hbbs, the info of the second one cannot be read
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        for statement in [
+            "INSERT INTO peer(guid, id, uuid, pk) VALUES (x'01', '111111111', x'01', x'')",
+            "INSERT INTO peer(guid, id, uuid, pk, info) VALUES (x'02', '222222222', x'02', x'', x'ff')",
+        ] {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+        let rocket = rocket::build().mount("/", routes![peers_export]).manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let export = |query: &str| {
+            client
+                .get(format!("/api/peers/export?{}", query))
+                .header(authorization.clone())
+                .dispatch()
+        };
+
+        let response = export("format=csv").await;
+        assert_eq!(response.status(), Status::Ok);
+        let csv = response.into_string().await.unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("111111111,"));
+        assert_eq!(lines[2], "# export interrupted, the file is incomplete");
+        let json = export("format=json").await.into_string().await.unwrap();
+        assert!(json.starts_with(r#"[{"id":"111111111""#));
+        assert!(json.ends_with("/* export interrupted, the file is incomplete */"));
+        assert!(serde_json::from_str::<serde_json::Value>(&json).is_err());
+
+        // nothing is sent when the first row already fails
+        sqlx::query("DELETE FROM peer WHERE id = '111111111'")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(export("format=csv").await.status(), Status::InternalServerError);
+        sqlx::query("DELETE FROM peer").execute(&pool).await.unwrap();
+        let response = export("format=json").await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), "[]");
+    }
+
+    #[rocket::async_test]
+    async fn test_dry_run_deletions() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let authorization = login_header(&state, "previewer", true).await;
+        login_header(&state, "heir", false).await;
+        let heir = state.get_all_users(Some("heir"), None, None, 1, 10).await.unwrap()[0]
+            .guid
+            .clone();
+        let admin = state.get_all_users(Some("previewer"), None, None, 1, 10).await.unwrap()[0]
+            .guid
+            .clone();
+        // the peers are registered by hbbs
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO peer(guid, id, uuid, pk) VALUES (?, '123456789', x'01', x'')")
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let strategy = state
+            .add_strategy("Locked", &serde_json::json!({"enable-clipboard": "N"}), false)
+            .await
+            .unwrap();
+        state.assign_peer_strategy("123456789", Some(&strategy.guid)).await.unwrap();
+        let shared = state.add_shared_address_book("Support", &admin).await.unwrap();
+        state
+            .add_ab_peer(&shared, AbPeer { id: "123456789".to_string(), ..Default::default() })
+            .await
+            .unwrap();
+        let rule = state.get_ab_rules(0, 10, &shared).await.unwrap()[0].guid.clone();
+        let default_group = "018f2556-22fb-73ee-9afd-bbcdc0cc387b";
+        let rocket = rocket::build()
+            .mount(
+                "/",
+                routes![group_delete, strategy_delete, ab_rule_delete, ab_shared_delete, peer_transfer],
+            )
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let state = client.rocket().state::<ApiState>().unwrap();
+        async fn preview(request: rocket::local::asynchronous::LocalRequest<'_>) -> DryRunResponse {
+            let response = request.dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            response.into_json().await.unwrap()
+        }
+
+        let response = preview(
+            client
+                .delete(format!("/api/group/{}?dry_run=true", default_group))
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body("[]"),
+        )
+        .await;
+        assert_eq!((response.msg.as_str(), response.data), ("dry run", vec![default_group.to_string()]));
+        assert!(state.get_group(default_group).await.is_some());
+
+        let response = preview(
+            client
+                .delete(format!("/api/strategy/{}?cascade=true&dry_run=true", strategy.guid))
+                .header(authorization.clone()),
+        )
+        .await;
+        assert_eq!(response.data, vec![strategy.guid.clone()]);
+        assert_eq!(response.details["assignments"], 1);
+        assert_eq!(state.count_strategy_assignments(&strategy.guid).await, Some(1));
+
+        let response = preview(
+            client
+                .delete("/api/ab/rule?dry_run=true")
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body(format!(r#"{{"guid":"{}"}}"#, rule)),
+        )
+        .await;
+        assert_eq!(response.data, vec![rule.clone()]);
+        assert_eq!(state.ab_rule_exists(&rule).await, Some(true));
+
+        // the unknown address books are left out
+        let response = preview(
+            client
+                .delete("/api/ab/shared?dry_run=true")
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body(format!(r#"["{}","{}"]"#, shared, Uuid::new_v4())),
+        )
+        .await;
+        assert_eq!((response.total, response.data), (1, vec![shared.clone()]));
+        assert_eq!(response.details["peers"], 1);
+        assert_eq!(state.count_shared_address_book_peers(&shared).await, Some(1));
+
+        let response = client
+            .post("/api/peer/123456789/transfer?dry_run=true")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(format!(r#"{{"user":"{}"}}"#, heir))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let transfer: PeerTransferResponse = response.into_json().await.unwrap();
+        assert_eq!((transfer.previous_user, transfer.user), (None, heir));
+        assert_eq!(state.get_peer("123456789").await.unwrap().user, None);
+        assert!(state.get_ab_peers(&transfer.ab).await.unwrap().is_empty());
+
+        let filter = AuditFilter::new(None, None, None, None, None, None);
+        assert_eq!(state.get_admin_audits(&filter).await.unwrap().0, 0);
+    }
+
+    #[rocket::async_test]
+    async fn test_group_strategy_endpoints() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "manager", true).await;
+        let locked = state
+            .add_strategy("Locked", &serde_json::json!({"enable-clipboard": "N"}), false)
+            .await
+            .unwrap();
+        let rocket = rocket::build()
+            .mount("/", routes![groups, group_get, group_add, group_update, group_strategy])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let add = |strategy: &str| {
+            client
+                .post("/api/group")
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body(format!(
+                    r#"{{"name":"Finance","note":"","allowed_outgoings":[],"allowed_incomings":[],"strategy":"{}"}}"#,
+                    strategy
+                ))
+        };
+        assert_eq!(add(&Uuid::new_v4().to_string()).dispatch().await.status(), Status::NotFound);
+        assert_eq!(add(&locked.guid).dispatch().await.status(), Status::Ok);
+        let response: GroupsResponse = client
+            .get("/api/groups?current=1&pageSize=0")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        let finance = response.data.into_iter().find(|group| group.name == "Finance").unwrap();
+        assert_eq!(finance.strategy.as_deref(), Some(locked.guid.as_str()));
+
+        // an update without strategy keeps the current one
+        let response = client
+            .put("/api/group")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(format!(
+                r#"{{"guid":"{}","name":"Finance","note":"accounting","allowed_outgoings":[],"allowed_incomings":[]}}"#,
+                finance.guid
+            ))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let get = || {
+            client
+                .get(format!("/api/group/{}", finance.guid))
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+        };
+        let group: utils::Group = get().dispatch().await.into_json().await.unwrap();
+        assert_eq!(group.note.as_deref(), Some("accounting"));
+        assert_eq!(group.strategy.as_deref(), Some(locked.guid.as_str()));
+
+        let assign = |guid: &str| {
+            client
+                .put(format!("/api/group/{}/strategy", guid))
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body(r#"{"strategy":null}"#)
+        };
+        assert_eq!(assign(&Uuid::new_v4().to_string()).dispatch().await.status(), Status::NotFound);
+        assert_eq!(assign(&finance.guid).dispatch().await.status(), Status::Ok);
+        let group: utils::Group = get().dispatch().await.into_json().await.unwrap();
+        assert_eq!(group.strategy, None);
+    }
+
+    #[rocket::async_test]
+    async fn test_user_permissions() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let mut tokens = Vec::new();
+        for name in ["manager", "worker"] {
+            let authorization = login_header(&state, name, name == "manager").await;
+            let guid = state.get_all_users(Some(name), None, None, 1, 10).await.unwrap()[0]
+                .guid
+                .clone();
+            tokens.push((guid, authorization));
+        }
+        let (manager, manager_authorization) = tokens[0].clone();
+        let (worker, worker_authorization) = tokens[1].clone();
+        let group = state.get_groups(0, u32::MAX).await.unwrap()[0].clone();
+        let team = state.add_shared_address_book("Team", &manager).await.unwrap();
+        state.add_shared_address_book("Private", &manager).await.unwrap();
+        state
+            .add_ab_rule(AbRule {
+                guid: team.clone(),
+                user: None,
+                group: Some(group.guid.clone()),
+                rule: AB_RULE_READ,
+            })
+            .await
+            .unwrap();
+        let rocket = rocket::build()
+            .mount("/", routes![user_permissions])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let get = |id: &str, authorization: &Header<'static>| {
+            client
+                .get(format!("/api/user/{}/permissions", id))
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+        };
+
+        let permissions: UserPermissions = get("me", &worker_authorization)
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert_eq!(permissions.guid, worker);
+        assert!(!permissions.is_admin);
+        assert!(permissions.admin.is_empty());
+        assert_eq!(permissions.quotas.peers_bulk_max_size, None);
+        assert_eq!(permissions.groups.len(), 1);
+        assert_eq!(permissions.groups[0].name, "Default");
+        // the Default group has full control on its shared address book
+        let rules: Vec<(&str, u32)> = permissions
+            .address_books
+            .iter()
+            .map(|ab| (ab.guid.as_str(), ab.rule))
+            .collect();
+        assert_eq!(rules, vec![("018f2556-2311-7efa-9d25-470a9160c6d7", AB_RULE_FULL), (team.as_str(), AB_RULE_READ)]);
+        let response = get(&manager, &worker_authorization).dispatch().await;
+        assert_eq!(response.status(), Status::Forbidden);
+
+        // the admin role grants the admin operations and full control on the address books it owns
+        let response = get(&worker, &manager_authorization).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let permissions: UserPermissions = get(&manager, &manager_authorization)
+            .dispatch()
+            .await
+            .into_json()
+            .await
+            .unwrap();
+        assert!(permissions.is_admin);
+        assert_eq!(permissions.admin, AdminPermission::ALL.to_vec());
+        assert_eq!(permissions.quotas.peers_bulk_max_size, Some(100));
+        let rules: Vec<(&str, u32)> = permissions
+            .address_books
+            .iter()
+            .map(|ab| (ab.name.as_str(), ab.rule))
+            .collect();
+        assert_eq!(
+            rules,
+            vec![
+                ("Default's Shared Address Book", AB_RULE_FULL),
+                ("Private", AB_RULE_FULL),
+                ("Team", AB_RULE_FULL)
+            ]
+        );
+        let response = get(&Uuid::new_v4().to_string(), &manager_authorization).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn test_software_unknown_key() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let rocket = rocket::build()
+            .mount("/", routes![software])
+            .manage(state)
+            .manage(ReleaseCache::default())
+            .manage(LocalReleases::from_figment(&rocket::Config::figment()));
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client
+            .get("/api/software/client-download-link/win")
+            .header(ContentType::JSON)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+        let error: SoftwareKeyError = response.into_json().await.unwrap();
+        assert_eq!(error.error, "win is not a known key");
+        assert_eq!(error.supported.len(), SOFTWARE_KEYS.len());
+        assert!(error.supported.contains(&"linux-aarch64".to_string()));
+    }
+
+    #[rocket::async_test]
+    async fn test_software_without_release_hosting() {
+        let s3_config = env::temp_dir().join(format!("sctgdesk-test-{}.toml", Uuid::new_v4()));
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let rocket = rocket::build()
+            .mount("/", routes![software])
+            .manage(state)
+            .manage(ReleaseCache::default().with_config_file(&s3_config))
+            .manage(LocalReleases::from_figment(&rocket::Config::figment()));
+        let client = Client::tracked(rocket).await.unwrap();
+        for _ in 0..2 {
+            let response = client
+                .get("/api/software/client-download-link/osx")
+                .header(ContentType::JSON)
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::ServiceUnavailable);
+            let error: SoftwareKeyError = response.into_json().await.unwrap();
+            assert_eq!(error.error, "release hosting is not configured");
+        }
+        // the missing config file is not created
+        assert!(!s3_config.exists());
+    }
+
+    #[rocket::async_test]
+    async fn test_oidc_sessions() {
+        use std::future::Future;
+
+        use oauth2::oauth_provider::OAuthResponse;
+        use oauth2::{Oauth2Error, Provider};
+        use utils::OidcSessionStatus;
+
+        /// Provider exchanging any code without calling a server
+        struct TestProvider;
+        impl OAuthProvider for TestProvider {
+            fn get_redirect_url(&self, callback_url: &str, state: &str) -> String {
+                format!("https://provider.example.org/authorize?redirect_uri={}&state={}", callback_url, state)
+            }
+            fn exchange_code(
+                &self,
+                _code: &str,
+                _callback_url: &str,
+            ) -> Pin<Box<dyn Future<Output = Result<OAuthResponse, Oauth2Error>> + Send + Sync>> {
+                Box::pin(async {
+                    Ok(OAuthResponse {
+                        access_token: "provider-token".to_string(),
+                        username: "oidcuser".to_string(),
+                        email: "oidcuser@example.org".to_string(),
+                    })
+                })
+            }
+            fn get_provider_type(&self) -> Provider {
+                Provider::Dex
+            }
+        }
+
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "sessionsadmin", true).await;
+        // the auth request as recorded by oidc_auth
+        let uuid_code = Uuid::new_v4().to_string();
+        state
+            .insert_oidc_session(
+                uuid_code.clone(),
+                OidcState {
+                    id: "123456789".to_string(),
+                    uuid: "client-uuid".to_string(),
+                    callback_url: Some("http://localhost/api/oidc/callback".to_string()),
+                    provider: Some(Arc::new(TestProvider)),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let rocket = rocket::build()
+            .mount("/", routes![oidc_sessions, oidc_callback])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let sessions = || async {
+            let response = client
+                .get("/api/oidc/sessions")
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            let body = response.into_string().await.unwrap();
+            // no secret is listed
+            assert!(!body.contains(&uuid_code));
+            assert!(!body.contains("provider-token"));
+            serde_json::from_str::<OidcSessionsResponse>(&body).unwrap()
+        };
+
+        let listed = sessions().await;
+        assert_eq!(listed.total, 1);
+        assert_eq!(listed.data[0].code, uuid_code[..8]);
+        assert_eq!(listed.data[0].provider, "Dex");
+        assert_eq!(listed.data[0].state, OidcSessionStatus::Pending);
+
+        let response = client
+            .get(format!("/api/oidc/callback?code=provider-code&state={}", uuid_code))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let listed = sessions().await;
+        assert_eq!(listed.total, 1);
+        assert_eq!(listed.data[0].state, OidcSessionStatus::Exchanged);
+
+        // only the admins list the sessions
+        let response = client
+            .get("/api/oidc/sessions")
+            .header(ContentType::JSON)
+            .dispatch()
+            .await;
+        assert_ne!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn test_shared_ab_read_only_member_cannot_write() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let mut authorizations = Vec::new();
+        let mut guids = Vec::new();
+        for name in ["sharedowner", "sharedreader", "sharedstranger"] {
+            authorizations.push(login_header(&state, name, false).await);
+            guids.push(state.get_all_users(Some(name), None, None, 1, 10).await.unwrap()[0].guid.clone());
+        }
+        let ab = state.add_shared_address_book("Reader team", &guids[0]).await.unwrap();
+        state
+            .add_ab_rule(AbRule {
+                guid: ab.clone(),
+                user: Some(guids[1].clone()),
+                group: None,
+                rule: AB_RULE_READ,
+            })
+            .await
+            .unwrap();
+        let rocket = rocket::build().mount("/", routes![ab_peers, ab_peer_add]).manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let read = |authorization: Header<'static>| {
+            client
+                .post(format!("/api/ab/peers?current=1&pageSize=10&ab={}", ab))
+                .header(authorization)
+                .dispatch()
+        };
+        let write = |authorization: Header<'static>| {
+            client
+                .post(format!("/api/ab/peer/add/{}", ab))
+                .header(ContentType::JSON)
+                .header(authorization)
+                .body(r#"{"id":"123456789"}"#)
+                .dispatch()
+        };
+
+        assert_eq!(read(authorizations[1].clone()).await.status(), Status::Ok);
+        assert_eq!(write(authorizations[1].clone()).await.status(), Status::Forbidden);
+        assert_eq!(read(authorizations[2].clone()).await.status(), Status::Forbidden);
+        assert_eq!(write(authorizations[2].clone()).await.status(), Status::Forbidden);
+        // the owner has full control
+        assert_eq!(write(authorizations[0].clone()).await.status(), Status::Ok);
+        let response = read(authorizations[1].clone()).await;
+        let peers: AbPeersResponse = response.into_json().await.unwrap();
+        assert_eq!(peers.total, 1);
+    }
+
+    #[rocket::async_test]
+    async fn test_ab_put() {
+
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "abput", false).await;
+        let rocket = rocket::build().mount("/", routes![ab, ab_put, ab_get]).manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let send = |method: Method, ab: &str| {
+            client
+                .req(method, "/api/ab")
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body(serde_json::json!({ "data": ab }).to_string())
+        };
+        let created = r#"{"tags":[],"peers":[{"id":"123456789"}]}"#;
+        let updated = r#"{"tags":["office"],"peers":[{"id":"123456789","tags":["office"]}]}"#;
+
+        // the address book does not exist yet
+        let response = send(Method::Put, updated).dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = send(Method::Post, created).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = send(Method::Put, updated).dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .get("/api/ab")
+            .header(ContentType::JSON)
+            .header(authorization)
+            .dispatch()
+            .await;
+        let reply: serde_json::Value = response.into_json().await.unwrap();
+        assert_eq!(reply["data"], updated);
+    }
+
+    #[rocket::async_test]
+    async fn test_local_releases() {
+        let releases_dir = env::temp_dir().join(format!("sctgdesk-releases-{}", Uuid::new_v4()));
+        std::fs::create_dir(&releases_dir).unwrap();
+        let installer: Vec<u8> = (0..100u8).collect();
+        std::fs::write(releases_dir.join("sctgdesk-1.2.4-x86_64.exe"), &installer).unwrap();
+        let sha256 = hex::encode(Sha256::digest(&installer));
+        std::fs::write(
+            releases_dir.join("sctgdesk-1.2.4-x86_64.exe.sha256"),
+            format!("{}  sctgdesk-1.2.4-x86_64.exe\n", sha256),
+        )
+        .unwrap();
+        std::fs::write(releases_dir.join("secret.txt"), "not a release").unwrap();
+        std::fs::write(
+            releases_dir.join(releases::RELEASES_MANIFEST),
+            "w64 = \"sctgdesk-1.2.4-x86_64.exe\"\n",
+        )
+        .unwrap();
+        let figment = rocket::Config::figment().merge(("releases_dir", releases_dir.to_str().unwrap()));
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let rocket = rocket::custom(&figment)
+            .mount("/", routes![software, software_release_file])
+            .manage(state)
+            .manage(ReleaseCache::default())
+            .manage(LocalReleases::from_figment(&figment));
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let response = client
+            .get("/api/software/client-download-link/w64")
+            .header(ContentType::JSON)
+            .header(Header::new("Host", "desk.example.org"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let link: SoftwareResponse = response.into_json().await.unwrap();
+        assert_eq!(
+            link.url,
+            "http://desk.example.org/api/software/releases/sctgdesk-1.2.4-x86_64.exe"
+        );
+        assert_eq!(link.size, Some(100));
+        assert_eq!(link.sha256, Some(sha256));
+        let response = client
+            .get("/api/software/client-download-link/osx")
+            .header(ContentType::JSON)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let download = |range: Option<&'static str>| {
+            let mut request = client.get("/api/software/releases/sctgdesk-1.2.4-x86_64.exe");
+            if let Some(range) = range {
+                request = request.header(Header::new("Range", range));
+            }
+            request.dispatch()
+        };
+        let response = download(None).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(
+            response.content_type().unwrap().to_string(),
+            "application/vnd.microsoft.portable-executable"
+        );
+        assert_eq!(response.headers().get_one("Accept-Ranges"), Some("bytes"));
+        assert_eq!(response.into_bytes().await.unwrap(), installer);
+
+        // a download is resumed with a range
+        let response = download(Some("bytes=10-19")).await;
+        assert_eq!(response.status(), Status::PartialContent);
+        assert_eq!(response.headers().get_one("Content-Range"), Some("bytes 10-19/100"));
+        assert_eq!(response.headers().get_one("Content-Length"), Some("10"));
+        assert_eq!(response.into_bytes().await.unwrap(), installer[10..20]);
+        let response = download(Some("bytes=95-")).await;
+        assert_eq!(response.headers().get_one("Content-Range"), Some("bytes 95-99/100"));
+        assert_eq!(response.into_bytes().await.unwrap(), installer[95..]);
+        let response = download(Some("bytes=-3")).await;
+        assert_eq!(response.into_bytes().await.unwrap(), installer[97..]);
+        let response = download(Some("bytes=100-")).await;
+        assert_eq!(response.status(), Status::RangeNotSatisfiable);
+        assert_eq!(response.headers().get_one("Content-Range"), Some("bytes */100"));
+
+        // only the files of the manifest are served
+        for file in ["secret.txt", "releases.toml", "sctgdesk-1.2.4-x86_64.exe.sha256"] {
+            let response = client.get(format!("/api/software/releases/{}", file)).dispatch().await;
+            assert_eq!(response.status(), Status::NotFound);
+        }
+        std::fs::remove_dir_all(&releases_dir).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn test_client_versions() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "versionsadmin", true).await;
+        let releases_dir = env::temp_dir().join(format!("sctgdesk-releases-{}", Uuid::new_v4()));
+        std::fs::create_dir(&releases_dir).unwrap();
+        std::fs::write(
+            releases_dir.join(releases::RELEASES_MANIFEST),
+            "w64 = \"sctgdesk-1.3.0-x86_64.exe\"\nosx = \"sctgdesk-1.3.0.dmg\"\n",
+        )
+        .unwrap();
+        let figment = rocket::Config::figment()
+            .merge(("releases_dir", releases_dir.to_str().unwrap()))
+            .merge(("latest_client_version", "1.2.6"));
+        let rocket = rocket::custom(&figment)
+            .mount("/", routes![software_version, software_version_update])
+            .manage(state)
+            .manage(ReleaseCache::default())
+            .manage(LocalReleases::from_figment(&figment))
+            .manage(DefaultClientVersions::from_figment(&figment));
+        let client = Client::tracked(rocket).await.unwrap();
+        let get_versions = || async {
+            let response = client
+                .get("/api/software/version/server")
+                .header(ContentType::JSON)
+                .header(Header::new("Host", "desk.example.org"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            response.into_json::<SoftwareVersionResponse>().await.unwrap()
+        };
+
+        // the versions of the configuration are announced until an admin sets them
+        let versions = get_versions().await;
+        assert_eq!(versions.server, Some(server_version(None)));
+        assert_eq!(versions.client.as_deref(), Some("1.2.6"));
+        assert_eq!(versions.min_client, None);
+        let downloads = versions.downloads.unwrap();
+        assert_eq!(downloads.len(), 2);
+        assert_eq!(
+            downloads["w64"],
+            "http://desk.example.org/api/software/client-download-link/w64"
+        );
+
+        let response = client
+            .put("/api/software/version")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(r#"{"latest":"1.3.0","minimum":"1.2.0"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let versions = get_versions().await;
+        assert_eq!(versions.client.as_deref(), Some("1.3.0"));
+        assert_eq!(versions.min_client.as_deref(), Some("1.2.0"));
+
+        // a minimum newer than the latest version is rejected
+        let response = client
+            .put("/api/software/version")
+            .header(ContentType::JSON)
+            .header(authorization)
+            .body(r#"{"latest":"1.3.0","minimum":"1.4.0-beta.1"}"#)
+            .dispatch()
+            .await;
+        let reply: serde_json::Value = response.into_json().await.unwrap();
+        assert!(reply["error"].as_str().unwrap().contains("newer"));
+        assert_eq!(get_versions().await.min_client.as_deref(), Some("1.2.0"));
+        std::fs::remove_dir_all(&releases_dir).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn test_ab_tag_color() {
+        use utils::tag_color::default_tag_color;
+
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "tagcolor", false).await;
+        let guid = state.get_all_users(Some("tagcolor"), None, None, 1, 1).await.unwrap()[0]
+            .guid
+            .clone();
+        let user_id = Uuid::parse_str(&guid).unwrap().as_bytes().to_vec();
+        let ab = state.get_ab_personal_guid(user_id).await.unwrap();
+        let rocket = rocket::build()
+            .mount("/", routes![ab_tag_add, ab_tag_update, ab_tags])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let add = |tag: serde_json::Value| {
+            client
+                .post(format!("/api/ab/tag/add/{}", ab))
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body(tag.to_string())
+                .dispatch()
+        };
+
+        let response = add(serde_json::json!({ "name": "office", "color": "#FF0000" })).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await, None);
+        let response = add(serde_json::json!({ "name": "home" })).await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = add(serde_json::json!({ "name": "broken", "color": "#ZZZZZZ" })).await;
+        assert_eq!(response.status(), Status::Ok);
+        let reply: serde_json::Value = response.into_json().await.unwrap();
+        assert!(reply["error"].as_str().unwrap().contains("#ZZZZZZ"));
+        let response = client
+            .put(format!("/api/ab/tag/update/{}", ab))
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(serde_json::json!({ "name": "office", "color": "blue" }).to_string())
+            .dispatch()
+            .await;
+        let reply: serde_json::Value = response.into_json().await.unwrap();
+        assert!(reply["error"].is_string());
+
+        let response = client
+            .post(format!("/api/ab/tags/{}", ab))
+            .header(authorization)
+            .dispatch()
+            .await;
+        let tags: Vec<AbTag> = response.into_json().await.unwrap();
+        let color = |name: &str| tags.iter().find(|tag| tag.name == name).map(|tag| tag.color);
+        assert_eq!(color("office"), Some(0xFFFF0000));
+        assert_eq!(color("home"), Some(default_tag_color("home")));
+        assert_eq!(color("broken"), None);
+    }
+
+    #[rocket::async_test]
+    async fn test_software_release_upload_form() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "publisher", true).await;
+        let figment = rocket::Config::figment();
+        let rocket = rocket::custom(&figment)
+            .mount("/", routes![software_release_upload])
+            .manage(state)
+            .manage(ReleaseCache::default())
+            .manage(ReleaseUploadSettings::from_figment(&figment));
+        let client = Client::tracked(rocket).await.unwrap();
+        let form = ContentType::new("multipart", "form-data").with_params(("boundary", "sctgdesk"));
+        let upload = |fields: &[(&str, Option<&str>, &str)]| {
+            let mut body = String::new();
+            for (name, file_name, value) in fields {
+                body.push_str("--sctgdesk\r\n");
+                match file_name {
+                    Some(file_name) => body.push_str(&format!(
+                        "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\n\r\n",
+                        name, file_name
+                    )),
+                    None => body.push_str(&format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name)),
+                }
+                body.push_str(value);
+                body.push_str("\r\n");
+            }
+            body.push_str("--sctgdesk--\r\n");
+            client
+                .post("/api/software/releases")
+                .header(form.clone())
+                .header(authorization.clone())
+                .body(body)
+                .dispatch()
+        };
+        let file = Some("sctgdesk-1.2.6-x86_64.exe");
+
+        let response = upload(&[("platform", None, "beos"), ("version", None, "1.2.6"), ("file", file, "MZ")]).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(response.into_string().await.unwrap(), "beos is not a known platform");
+        let response = upload(&[("platform", None, "w64"), ("version", None, "latest"), ("file", file, "MZ")]).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        // the key of the release must be known before its data is read
+        let response = upload(&[("file", file, "MZ"), ("platform", None, "w64"), ("version", None, "1.2.6")]).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert!(response.into_string().await.unwrap().contains("before the file"));
+        let response = upload(&[("platform", None, "w64"), ("version", None, "1.2.6"), ("file", Some(".hidden"), "MZ")]).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = upload(&[("platform", None, "w64"), ("version", None, "1.2.6")]).await;
+        assert_eq!(response.into_string().await.unwrap(), "the file is missing");
+
+        // the uploads are reserved to the admins
+        let response = client
+            .post("/api/software/releases")
+            .header(form)
+            .body("--sctgdesk--\r\n")
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn test_logs_stream() {
+        use log::Log;
+        use rocket::tokio::io::AsyncReadExt;
+
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "logsadmin", true).await;
+        let log_buffer = Arc::new(LogBuffer::default());
+        let sink = LogSink::new(log_buffer.clone(), log::LevelFilter::Trace);
+        let emit = |level: log::Level, message: &str| {
+            sink.log(
+                &log::Record::builder()
+                    .args(format_args!("{}", message))
+                    .level(level)
+                    .target("sctgdesk_api_server")
+                    .build(),
+            )
+        };
+        emit(log::Level::Info, "logged before the subscription");
+        emit(log::Level::Debug, "too verbose");
+        let rocket = rocket::build()
+            .mount("/", routes![logs_stream])
+            .manage(state)
+            .manage(log_buffer);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let response = client
+            .get("/api/admin/logs/stream?level=verbose")
+            .header(authorization.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::BadRequest);
+
+        let mut response = client
+            .get("/api/admin/logs/stream")
+            .header(authorization)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        emit(log::Level::Warn, "logged after the subscription");
+        let mut received = String::new();
+        let mut buffer = [0u8; 1024];
+        while !received.contains("logged after the subscription") {
+            let read = rocket::tokio::time::timeout(Duration::from_secs(5), response.read(&mut buffer))
+                .await
+                .expect("the log line was not streamed")
+                .unwrap();
+            assert!(read > 0);
+            received.push_str(&String::from_utf8_lossy(&buffer[..read]));
+        }
+        assert!(received.starts_with("event:log\ndata:{"));
+        assert!(received.contains("logged before the subscription"));
+        assert!(received.contains(r#""level":"warn""#));
+        assert!(!received.contains("too verbose"));
+    }
+
+    #[rocket::async_test]
+    async fn test_peers_events() {
+        use rocket::tokio::io::AsyncReadExt;
+
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let authorization = login_header(&state, "watcher", true).await;
+        let member = login_header(&state, "onlooker", false).await;
+        // the peers are registered by hbbs
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO peer(guid, id, uuid, pk) VALUES (?, '123456789', x'01', x'')")
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let rocket = rocket::build().mount("/", routes![peers_events]).manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let state = client.rocket().state::<ApiState>().unwrap();
+
+        let response = client.get("/api/peers/events").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client.get("/api/peers/events").header(member).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let mut response = client
+            .get("/api/peers/events")
+            .header(authorization)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::EventStream));
+        let heartbeat = HeartbeatRequest {
+            id: "123456789".to_string(),
+            modified_at: 0,
+            uuid: "AQ==".to_string(),
+            ver: 1,
+        };
+        state.update_heartbeat(heartbeat, None).await.unwrap();
+        let mut received = String::new();
+        let mut buffer = [0u8; 1024];
+        while !received.contains("\n\n") {
+            let read = rocket::tokio::time::timeout(Duration::from_secs(5), response.read(&mut buffer))
+                .await
+                .expect("the peer event was not streamed")
+                .unwrap();
+            assert!(read > 0);
+            received.push_str(&String::from_utf8_lossy(&buffer[..read]));
+        }
+        assert!(received.starts_with("event:peer-online\n"));
+        assert!(received.contains(r#"data:{"type":"peer-online""#));
+        assert!(received.contains(r#""id":"123456789""#));
+    }
+
+    #[rocket::async_test]
+    async fn test_release_channels() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let mut users = Vec::new();
+        for (name, is_admin) in [("channeladmin", true), ("betatester", false), ("stableuser", false)] {
+            let authorization = login_header(&state, name, is_admin).await;
+            let guid = state.get_all_users(Some(name), None, None, 1, 10).await.unwrap()[0]
+                .guid
+                .clone();
+            users.push((guid, authorization));
+        }
+        let (admin_id, admin) = users[0].clone();
+        let (tester_id, tester) = users[1].clone();
+        let (_, stable_user) = users[2].clone();
+        let default_group = state
+            .get_groups(0, u32::MAX)
+            .await
+            .unwrap()
+            .into_iter()
+            .find(|group| group.name == "Default")
+            .unwrap()
+            .guid;
+        let admin_guid = Uuid::parse_str(&admin_id).unwrap().as_bytes().to_vec();
+        state
+            .add_software_release("w64", ReleaseChannel::Beta, "1.3.0-beta.1", "releases/w64", 1, "00ff", admin_guid)
+            .await
+            .unwrap();
+        let releases_dir = env::temp_dir().join(format!("sctgdesk-releases-{}", Uuid::new_v4()));
+        std::fs::create_dir(&releases_dir).unwrap();
+        std::fs::write(
+            releases_dir.join(releases::RELEASES_MANIFEST),
+            "w64 = \"sctgdesk-1.2.6-x86_64.exe\"\nosx = \"sctgdesk-1.2.6.dmg\"\n\n\
+             [beta]\nw64 = \"sctgdesk-1.3.0-beta.1-x86_64.exe\"\nlinux-x86_64 = \"sctgdesk-1.3.0-beta.1-x86_64.deb\"\n",
+        )
+        .unwrap();
+        let figment = rocket::Config::figment()
+            .merge(("releases_dir", releases_dir.to_str().unwrap()))
+            .merge(("latest_client_version", "1.2.6"));
+        let rocket = rocket::custom(&figment)
+            .mount(
+                "/",
+                routes![software, software_version, user_release_channel, group_release_channel],
+            )
+            .manage(state)
+            .manage(ReleaseCache::default())
+            .manage(LocalReleases::from_figment(&figment))
+            .manage(DefaultClientVersions::from_figment(&figment));
+        let client = Client::tracked(rocket).await.unwrap();
+        let link = |key: &'static str, authorization: Option<Header<'static>>| {
+            let mut request = client
+                .get(format!("/api/software/client-download-link/{}", key))
+                .header(ContentType::JSON)
+                .header(Header::new("Host", "desk.example.org"));
+            if let Some(authorization) = authorization {
+                request = request.header(authorization);
+            }
+            async move {
+                let response = request.dispatch().await;
+                assert_eq!(response.status(), Status::Ok);
+                let link = response.into_json::<SoftwareResponse>().await.unwrap().url;
+                link.trim_start_matches("http://desk.example.org/api/software/releases/").to_string()
+            }
+        };
+        let versions = |authorization: Option<Header<'static>>| {
+            let mut request = client.get("/api/software/version/server").header(ContentType::JSON);
+            if let Some(authorization) = authorization {
+                request = request.header(authorization);
+            }
+            async move {
+                let response = request.dispatch().await;
+                assert_eq!(response.status(), Status::Ok);
+                response.into_json::<SoftwareVersionResponse>().await.unwrap()
+            }
+        };
+        let assign = |path: String, channel: &'static str| {
+            client
+                .put(path)
+                .header(ContentType::JSON)
+                .header(admin.clone())
+                .body(format!(r#"{{"channel":{}}}"#, channel))
+                .dispatch()
+        };
+
+        // everyone is on the stable channel by default
+        assert_eq!(link("w64", Some(tester.clone())).await, "sctgdesk-1.2.6-x86_64.exe");
+
+        let response = assign(format!("/api/users/{}/release-channel", tester_id), "\"beta\"").await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = assign(format!("/api/users/{}/release-channel", Uuid::new_v4()), "\"beta\"").await;
+        assert_eq!(response.status(), Status::NotFound);
+        let response = assign(format!("/api/users/{}/release-channel", tester_id), "\"nightly\"").await;
+        assert_eq!(response.status(), Status::UnprocessableEntity);
+
+        // the beta user gets the beta release, or the stable one of the platforms without a beta release
+        assert_eq!(link("w64", Some(tester.clone())).await, "sctgdesk-1.3.0-beta.1-x86_64.exe");
+        assert_eq!(link("osx", Some(tester.clone())).await, "sctgdesk-1.2.6.dmg");
+        let beta = versions(Some(tester.clone())).await;
+        assert_eq!(beta.channel, Some(ReleaseChannel::Beta));
+        assert_eq!(beta.client.as_deref(), Some("1.3.0-beta.1"));
+        assert_eq!(beta.downloads.unwrap().len(), 3);
+
+        // the stable user and the anonymous requests get the stable release
+        assert_eq!(link("w64", Some(stable_user.clone())).await, "sctgdesk-1.2.6-x86_64.exe");
+        assert_eq!(link("w64", None).await, "sctgdesk-1.2.6-x86_64.exe");
+        let stable = versions(None).await;
+        assert_eq!(stable.channel, Some(ReleaseChannel::Stable));
+        assert_eq!(stable.client.as_deref(), Some("1.2.6"));
+        assert_eq!(stable.downloads.unwrap().len(), 2);
+        let response = client
+            .get("/api/software/client-download-link/linux-x86_64")
+            .header(ContentType::JSON)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        // the channel of the group applies to its users without their own channel
+        let response = assign(format!("/api/group/{}/release-channel", default_group), "\"beta\"").await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(link("w64", Some(stable_user.clone())).await, "sctgdesk-1.3.0-beta.1-x86_64.exe");
+        let response = assign(format!("/api/users/{}/release-channel", tester_id), "\"stable\"").await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(link("w64", Some(tester.clone())).await, "sctgdesk-1.2.6-x86_64.exe");
+        assert_eq!(link("w64", None).await, "sctgdesk-1.2.6-x86_64.exe");
+    }
+
+    #[rocket::async_test]
+    async fn test_cors_max_age() {
+        let rocket = rocket::build()
+            .attach(CORS::new(Some("120")))
+            .mount("/", routes![options]);
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client
+            .options("/api/ab")
+            .header(Header::new("Origin", "https://console.example.org"))
+            .header(Header::new("Access-Control-Request-Method", "POST"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Access-Control-Max-Age"), Some("120"));
+        assert_eq!(response.headers().get_one("Access-Control-Allow-Origin"), Some("*"));
+
+        // only the preflight answers are cached
+        let response = client.get("/api/ab").dispatch().await;
+        assert_eq!(response.headers().get_one("Access-Control-Max-Age"), None);
+        assert_eq!(response.headers().get_one("Access-Control-Allow-Origin"), Some("*"));
+
+        assert_eq!(CORS::new(None).max_age(), CORS_DEFAULT_MAX_AGE);
+        assert_eq!(CORS::new(Some("ten minutes")).max_age(), CORS_DEFAULT_MAX_AGE);
+    }
+
+    #[rocket::async_test]
+    async fn test_cors_allowed_origins() {
+        let figment = rocket::Config::figment()
+            .merge(("cors_origins", "https://console.example.org, https://*.desk.example.org:8443"))
+            .merge(("cors_methods", vec!["get", "post"]))
+            .merge(("cors_headers", "Authorization, Content-Type"))
+            .merge(("cookie_auth", true));
+        let rocket = rocket::custom(&figment)
+            .attach(CORS::from_figment(&figment).with_headers(&["*"]))
+            .mount("/", routes![options]);
+        let client = Client::tracked(rocket).await.unwrap();
+        let get = |origin: &'static str| client.get("/api/ab").header(Header::new("Origin", origin)).dispatch();
+
+        // an allowed origin is echoed with the credentials, the preflight headers are only in the preflight answers
+        for origin in ["https://console.example.org", "https://eu.desk.example.org:8443"] {
+            let response = get(origin).await;
+            assert_eq!(response.headers().get_one("Access-Control-Allow-Origin"), Some(origin));
+            assert_eq!(response.headers().get_one("Access-Control-Allow-Credentials"), Some("true"));
+            assert_eq!(response.headers().get_one("Vary"), Some("Origin"));
+            assert_eq!(response.headers().get_one("Access-Control-Allow-Methods"), None);
+        }
+
+        // a disallowed origin gets no CORS header
+        for origin in [
+            "https://evil.example.org",
+            "https://console.example.org.evil.org",
+            "https://desk.example.org:8443",
+            "http://eu.desk.example.org:8443",
+            "https://eu.desk.example.org",
+        ] {
+            let response = get(origin).await;
+            assert_eq!(response.headers().get_one("Access-Control-Allow-Origin"), None);
+            assert_eq!(response.headers().get_one("Access-Control-Allow-Credentials"), None);
+        }
+        let response = client.get("/api/ab").dispatch().await;
+        assert_eq!(response.headers().get_one("Access-Control-Allow-Origin"), None);
+
+        // the preflight answer gives the configured methods and the requested headers with the credentials
+        let preflight = |origin: &'static str| {
+            client
+                .options("/api/ab")
+                .header(Header::new("Origin", origin))
+                .header(Header::new("Access-Control-Request-Method", "POST"))
+                .header(Header::new("Access-Control-Request-Headers", "authorization,content-type"))
+                .dispatch()
+        };
+        let response = preflight("https://console.example.org").await;
+        assert_eq!(response.status(), Status::Ok);
+        let headers = response.headers();
+        assert_eq!(headers.get_one("Access-Control-Allow-Origin"), Some("https://console.example.org"));
+        assert_eq!(headers.get_one("Access-Control-Allow-Methods"), Some("GET, POST"));
+        assert_eq!(headers.get_one("Access-Control-Allow-Headers"), Some("authorization,content-type"));
+        assert_eq!(headers.get_one("Access-Control-Max-Age"), Some(CORS_DEFAULT_MAX_AGE.to_string().as_str()));
+        assert_eq!(headers.get_one("Access-Control-Allow-Credentials"), Some("true"));
+        let response = preflight("https://evil.example.org").await;
+        assert_eq!(response.headers().get_one("Access-Control-Allow-Methods"), None);
+        assert_eq!(response.headers().get_one("Access-Control-Allow-Origin"), None);
+
+        // the configured headers are sent as they are, and without cookie auth the credentials are never allowed
+        let figment = figment.merge(("cookie_auth", false));
+        let rocket = rocket::custom(&figment)
+            .attach(CORS::from_figment(&figment))
+            .mount("/", routes![options]);
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client
+            .options("/api/ab")
+            .header(Header::new("Origin", "https://console.example.org"))
+            .header(Header::new("Access-Control-Request-Method", "POST"))
+            .dispatch()
+            .await;
+        let headers = response.headers();
+        assert_eq!(headers.get_one("Access-Control-Allow-Origin"), Some("https://console.example.org"));
+        assert_eq!(headers.get_one("Access-Control-Allow-Headers"), Some("Authorization, Content-Type"));
+        assert_eq!(headers.get_one("Access-Control-Allow-Credentials"), None);
+
+        // the default policy allows every origin without the credentials
+        let client = Client::tracked(rocket::build().attach(CORS::new(None).with_cookie_auth(true)))
+            .await
+            .unwrap();
+        let response = client
+            .get("/")
+            .header(Header::new("Origin", "https://evil.example.org"))
+            .dispatch()
+            .await;
+        assert_eq!(response.headers().get_one("Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(response.headers().get_one("Access-Control-Allow-Credentials"), None);
+    }
+
+    #[rocket::async_test]
+    async fn test_server_version_fallback() {
+        env::remove_var("MAIN_PKG_VERSION");
+        let figment = rocket::Config::figment();
+        let db_file = test_db_file();
+        let rocket = rocket::custom(&figment)
+            .mount("/", routes![software_version])
+            .manage(ApiState::new_with_db(db_file).await)
+            .manage(ReleaseCache::default())
+            .manage(LocalReleases::from_figment(&figment))
+            .manage(DefaultClientVersions::from_figment(&figment));
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client
+            .get("/api/software/version/server")
+            .header(ContentType::JSON)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let versions: SoftwareVersionResponse = response.into_json().await.unwrap();
+        let server = versions.server.unwrap();
+        assert_eq!(server, server_version(None));
+        assert!(server.starts_with(env!("CARGO_PKG_VERSION")));
+        if env!("GIT_HASH") != "unknown" {
+            assert_eq!(server, format!("{}+{}", env!("CARGO_PKG_VERSION"), env!("GIT_HASH")));
+        }
+
+        // the environment variable overrides the version of the crate, not the commit
+        assert!(server_version(Some("2.0.0".to_string())).starts_with("2.0.0"));
+        assert_eq!(server_version(Some(String::new())), server_version(None));
+    }
+
+    #[rocket::async_test]
+    async fn test_peer_transfer() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let authorization = login_header(&state, "transferadmin", true).await;
+        for name in ["leaver", "successor"] {
+            login_header(&state, name, false).await;
+        }
+        let user_guid = |name: &'static str| {
+            let state = &state;
+            async move { state.get_all_users(Some(name), None, None, 1, 10).await.unwrap()[0].guid.clone() }
+        };
+        let leaver = user_guid("leaver").await;
+        let successor = user_guid("successor").await;
+        // the peers are registered by hbbs
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO peer(guid, id, uuid, pk) VALUES (?, '123456789', x'01', x'')")
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let leaver_id = Uuid::parse_str(&leaver).unwrap().as_bytes().to_vec();
+        let successor_id = Uuid::parse_str(&successor).unwrap().as_bytes().to_vec();
+        state
+            .assign_peer_to_user("123456789", Some(leaver_id.clone()), leaver_id.clone())
+            .await
+            .unwrap();
+        let leaver_ab = state.get_ab_personal_guid(leaver_id.clone()).await.unwrap();
+        let successor_ab = state.get_ab_personal_guid(successor_id).await.unwrap();
+        let ab_peer = AbPeer {
+            id: "123456789".to_string(),
+            alias: Some("Accounting PC".to_string()),
+            tags: Some(vec!["accounting".to_string()]),
+            ..Default::default()
+        };
+        state.add_ab_peer(&leaver_ab, ab_peer).await.unwrap();
+        let rocket = rocket::build().mount("/", routes![peer_transfer]).manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let state = client.rocket().state::<ApiState>().unwrap();
+        let transfer = |id: &'static str, user: String| {
+            client
+                .post(format!("/api/peer/{}/transfer", id))
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body(format!(r#"{{"user":"{}"}}"#, user))
+                .dispatch()
+        };
+
+        let response = transfer("123%20456%20789", successor.clone()).await;
+        assert_eq!(response.status(), Status::Ok);
+        let transferred: PeerTransferResponse = response.into_json().await.unwrap();
+        assert_eq!(
+            transferred,
+            PeerTransferResponse {
+                id: "123456789".to_string(),
+                previous_user: Some(leaver.clone()),
+                user: successor.clone(),
+                ab: successor_ab.clone(),
+            }
+        );
+        assert_eq!(state.get_peer("123456789").await.unwrap().user, Some(successor.clone()));
+
+        // the peer moved to the address book of the new owner with its alias and tags
+        let moved = state.get_ab_peers(&successor_ab).await.unwrap();
+        assert_eq!(moved.len(), 1);
+        assert_eq!(moved[0].alias.as_deref(), Some("Accounting PC"));
+        assert_eq!(moved[0].tags, Some(vec!["accounting".to_string()]));
+        assert!(state.get_ab_peers(&leaver_ab).await.unwrap().is_empty());
+
+        let response = transfer("987654321", successor.clone()).await;
+        assert_eq!(response.status(), Status::NotFound);
+        let response = transfer("123456789", Uuid::new_v4().to_string()).await;
+        assert_eq!(response.status(), Status::NotFound);
+        let response = transfer("123456789", "not-a-guid".to_string()).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(state.get_peer("123456789").await.unwrap().user, Some(successor));
+    }
+
+    #[rocket::async_test]
+    async fn test_version_check() {
+        let db_file = test_db_file();
+        let releases_dir = env::temp_dir().join(format!("sctgdesk-releases-{}", Uuid::new_v4()));
+        std::fs::create_dir(&releases_dir).unwrap();
+        std::fs::write(
+            releases_dir.join(releases::RELEASES_MANIFEST),
+            "w64 = \"sctgdesk-1.2.6-x86_64.exe\"\n",
+        )
+        .unwrap();
+        let figment = rocket::Config::figment()
+            .merge(("releases_dir", releases_dir.to_str().unwrap()))
+            .merge(("latest_client_version", "1.2.6"));
+        let rocket = rocket::custom(&figment)
+            .mount("/", routes![version_check, version_check_get])
+            .manage(ApiState::new_with_db(db_file).await)
+            .manage(ReleaseCache::default())
+            .manage(LocalReleases::from_figment(&figment))
+            .manage(DefaultClientVersions::from_figment(&figment));
+        let client = Client::tracked(rocket).await.unwrap();
+
+        // the request of check_software_update in the RustDesk client
+        let response = client
+            .post("/api/version/latest")
+            .header(ContentType::JSON)
+            .header(Header::new("Host", "desk.example.org"))
+            .body(r#"{"os":"windows","os_version":"10.0.19045","arch":"x86_64","device_id":[1,2,3],"typ":"rustdesk-client"}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::JSON));
+        let body = response.into_string().await.unwrap();
+        assert_eq!(
+            body,
+            r#"{"url":"http://desk.example.org/api/software/releases/tag/1.2.6","downloads":{"w64":"http://desk.example.org/api/software/client-download-link/w64"}}"#
+        );
+        // the client reads the version from the last path segment of the url
+        let check: VersionCheckResponse = serde_json::from_str(&body).unwrap();
+        assert_eq!(check.url.rsplit('/').next(), Some("1.2.6"));
+
+        let response = client
+            .get("/api/version/latest")
+            .header(Header::new("Host", "desk.example.org"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), body);
+        let response = client
+            .post("/api/version/latest")
+            .header(Header::new("Host", "desk.example.org"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+    }
+
+    #[rocket::async_test]
+    async fn test_webconsole_spa_fallback() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let rocket = rocket::build()
+            .mount("/", routes![audit_conns, webconsole_vue])
+            .register("/", catchers![webconsole_fallback])
+            .manage(StaticFiles::new(&STATIC_DIR))
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let index = STATIC_DIR.get_file("index.html").unwrap().contents().to_vec();
+
+        // the site root and the client side routes get the console, with or without /ui
+        for path in ["/", "/index", "/ui/", "/ui/login", "/some/client/route"] {
+            let response = client.get(path).dispatch().await;
+            assert_eq!(response.status(), Status::Ok, "{}", path);
+            assert_eq!(response.content_type(), Some(ContentType::HTML), "{}", path);
+            assert_eq!(response.into_bytes().await.unwrap(), index, "{}", path);
+        }
+
+        // a missing asset is not answered with html
+        for path in ["/ui/assets/missing.js", "/assets/missing.css", "/missing.png"] {
+            let response = client.get(path).dispatch().await;
+            assert_eq!(response.status(), Status::NotFound, "{}", path);
+            assert_ne!(response.content_type(), Some(ContentType::HTML), "{}", path);
+        }
+
+        // the API keeps its statuses
+        let response = client.get("/api/unknown").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        assert_ne!(response.content_type(), Some(ContentType::HTML));
+        let response = client.get("/api/audit/conns").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client.post("/some/client/route").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[rocket::async_test]
+    async fn test_revoke_all_sessions() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let mut headers = vec![
+            login_header(&state, "revokeadmin", true).await,
+            login_header(&state, "revokeuser", false).await,
+        ];
+        // a second session of the same user
+        let (_, token) = state
+            .user_login(&"revokeuser".to_string(), UserPasswordInfo::from_password("password"), false)
+            .await
+            .unwrap();
+        headers.push(Header::new("Authorization", format!("Bearer {}", token.to_base64())));
+        let rocket = rocket::build()
+            .mount("/", routes![revoke_all_sessions, current_user])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let current_user_status = |authorization: Header<'static>| {
+            let client = &client;
+            async move {
+                client
+                    .post("/api/currentUser")
+                    .header(ContentType::JSON)
+                    .header(authorization)
+                    .body(r#"{"id":"","uuid":""}"#)
+                    .dispatch()
+                    .await
+                    .status()
+            }
+        };
+        let revoke = |body: &'static str| {
+            let client = &client;
+            let authorization = headers[0].clone();
+            async move {
+                client
+                    .post("/api/admin/revoke-all-sessions")
+                    .header(ContentType::JSON)
+                    .header(authorization)
+                    .body(body)
+                    .dispatch()
+                    .await
+            }
+        };
+
+        // the confirmation is required
+        let response = revoke(r#"{"keep_current":true}"#).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        assert_eq!(current_user_status(headers[1].clone()).await, Status::Ok);
+
+        // a user cannot log everyone out
+        let response = client
+            .post("/api/admin/revoke-all-sessions")
+            .header(ContentType::JSON)
+            .header(headers[1].clone())
+            .body(r#"{"confirm":true}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        let response = revoke(r#"{"confirm":true,"keep_current":true}"#).await;
+        assert_eq!(response.status(), Status::Ok);
+        let revoked: RevokeAllSessionsResponse = response.into_json().await.unwrap();
+        assert_eq!(revoked.revoked, 2);
+        assert_eq!(current_user_status(headers[1].clone()).await, Status::Unauthorized);
+        assert_eq!(current_user_status(headers[2].clone()).await, Status::Unauthorized);
+        assert_eq!(current_user_status(headers[0].clone()).await, Status::Ok);
+
+        // without keep_current the calling admin is logged out too
+        let response = revoke(r#"{"confirm":true}"#).await;
+        assert_eq!(response.status(), Status::Ok);
+        let revoked: RevokeAllSessionsResponse = response.into_json().await.unwrap();
+        assert_eq!(revoked.revoked, 1);
+        assert_eq!(current_user_status(headers[0].clone()).await, Status::Unauthorized);
+    }
+
+    /// Web console files with their pre-compressed variants
+    static TEST_DIR: Dir = Dir::new(
+        "",
+        &[
+            include_dir::DirEntry::File(include_dir::File::new("index.html", b"<html><head><title>SCTGDesk Web Console</title></head></html>")),
+            include_dir::DirEntry::File(include_dir::File::new("small.js", b"small")),
+            include_dir::DirEntry::Dir(Dir::new(
+                "assets",
+                &[
+                    include_dir::DirEntry::File(include_dir::File::new("assets/app.js", b"plain")),
+                    include_dir::DirEntry::File(include_dir::File::new("assets/app.js.gz", b"gzipped")),
+                    include_dir::DirEntry::File(include_dir::File::new("assets/app.js.br", b"brotli")),
+                    include_dir::DirEntry::File(include_dir::File::new("assets/logo.png", b"png")),
+                ],
+            )),
+        ],
+    );
+
+    #[get("/<path..>")]
+    fn test_static(files: &State<StaticFiles>, path: PathBuf, request: ExtendedRequest) -> Option<StaticFileResponse> {
+        let accept_encoding = request.headers.get("accept-encoding").map(|value| value.as_str());
+        files.get(path.to_str().unwrap(), accept_encoding)
+    }
+
+    #[rocket::async_test]
+    async fn test_static_file_encodings() {
+        let rocket = rocket::build()
+            .mount("/", routes![test_static])
+            .manage(StaticFiles::new(&TEST_DIR));
+        let client = Client::tracked(rocket).await.unwrap();
+        let cases = [
+            ("/assets/app.js", None, None, "plain"),
+            ("/assets/app.js", Some("identity"), None, "plain"),
+            ("/assets/app.js", Some("gzip"), Some("gzip"), "gzipped"),
+            ("/assets/app.js", Some("br"), Some("br"), "brotli"),
+            ("/assets/app.js", Some("gzip, deflate, br"), Some("br"), "brotli"),
+            ("/assets/app.js", Some("br;q=0.5, gzip"), Some("gzip"), "gzipped"),
+            ("/assets/app.js", Some("br;q=0, gzip;q=0"), None, "plain"),
+            ("/assets/app.js", Some("*"), Some("br"), "brotli"),
+            ("/assets/app.js", Some("*, br;q=0"), Some("gzip"), "gzipped"),
+            // no variant for the small files and the compressed formats
+            ("/small.js", Some("gzip, br"), None, "small"),
+            ("/assets/logo.png", Some("gzip, br"), None, "png"),
+        ];
+        for (path, accept_encoding, encoding, body) in cases {
+            let mut request = client.get(path);
+            if let Some(accept_encoding) = accept_encoding {
+                request = request.header(Header::new("Accept-Encoding", accept_encoding));
+            }
+            let response = request.dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.headers().get_one("Content-Encoding"), encoding, "{:?}", accept_encoding);
+            assert_eq!(response.headers().get_one("Vary"), Some("Accept-Encoding"));
+            let content_type = if path.ends_with(".png") { ContentType::PNG } else { ContentType::JavaScript };
+            assert_eq!(response.content_type(), Some(content_type));
+            assert_eq!(response.into_string().await.unwrap(), body, "{:?}", accept_encoding);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_users_client_status_filter() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "statusadmin", true).await;
+        for name in ["statusactive", "statusdisabled"] {
+            login_header(&state, name, false).await;
+        }
+        let disabled = state
+            .get_all_users(Some("statusdisabled"), None, None, 1, 10)
+            .await
+            .unwrap()[0]
+            .guid
+            .clone();
+        let rocket = rocket::build()
+            .mount("/", routes![users_client, user_enable])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        // the web console sends `disable: false` to disable a user
+        let response = client
+            .post("/api/enable-users")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(format!(r#"{{"rows":["{}"],"disable":false}}"#, disabled))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+
+        let list = |query: &'static str| {
+            let client = &client;
+            let authorization = authorization.clone();
+            async move {
+                client
+                    .get(format!("/api/users?current=1&pageSize=10{}", query))
+                    .header(Accept::JSON)
+                    .header(authorization)
+                    .dispatch()
+                    .await
+            }
+        };
+        let names = |list: UserList| {
+            // the default admin is left out
+            let mut names: Vec<(String, UserStatus)> = list
+                .data
+                .into_iter()
+                .filter(|user| user.name.starts_with("status"))
+                .map(|user| (user.name, user.status))
+                .collect();
+            names.sort_by(|a, b| a.0.cmp(&b.0));
+            names
+        };
+        let all: UserList = list("").await.into_json().await.unwrap();
+        assert_eq!(names(all).len(), 3);
+        let response = list("&status=0").await;
+        assert_eq!(response.status(), Status::Ok);
+        // the status is a number on the wire
+        let body = response.into_string().await.unwrap();
+        assert!(body.contains(r#""status":0"#));
+        let disabled: UserList = serde_json::from_str(&body).unwrap();
+        assert_eq!(names(disabled), vec![("statusdisabled".to_string(), UserStatus::Disabled)]);
+        let active: UserList = list("&status=1").await.into_json().await.unwrap();
+        assert_eq!(
+            names(active),
+            vec![
+                ("statusactive".to_string(), UserStatus::Normal),
+                ("statusadmin".to_string(), UserStatus::Normal),
+            ]
+        );
+        let unverified: UserList = list("&status=-1").await.into_json().await.unwrap();
+        assert_eq!(unverified.total, 0);
+        assert_eq!(list("&status=2").await.status(), Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn test_static_file_etag() {
+        let rocket = rocket::build()
+            .mount("/", routes![test_static, webconsole_vue])
+            .register("/", catchers![webconsole_fallback])
+            .manage(StaticFiles::new(&TEST_DIR));
+        let client = Client::tracked(rocket).await.unwrap();
+        let get = |path: &'static str, accept_encoding: Option<&'static str>, if_none_match: Option<String>| {
+            let client = &client;
+            async move {
+                let mut request = client.get(path);
+                if let Some(accept_encoding) = accept_encoding {
+                    request = request.header(Header::new("Accept-Encoding", accept_encoding));
+                }
+                if let Some(if_none_match) = if_none_match {
+                    request = request.header(Header::new("If-None-Match", if_none_match));
+                }
+                request.dispatch().await
+            }
+        };
+
+        // the fingerprinted assets keep the long caching, with a validator
+        let response = get("/assets/app.js", None, None).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("max-age=604800"));
+        let etag = response.headers().get_one("ETag").unwrap().to_string();
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+        let response = get("/assets/app.js", None, Some(etag.clone())).await;
+        assert_eq!(response.status(), Status::NotModified);
+        assert_eq!(response.headers().get_one("ETag"), Some(etag.as_str()));
+        assert!(response.headers().get_one("Content-Encoding").is_none());
+        assert!(response.into_bytes().await.unwrap_or_default().is_empty());
+        let response = get("/assets/app.js", None, Some(format!(r#""other", W/{}"#, etag))).await;
+        assert_eq!(response.status(), Status::NotModified);
+        let response = get("/assets/app.js", None, Some(r#""other""#.to_string())).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.into_string().await.unwrap(), "plain");
+
+        // each encoded variant has its own validator
+        let response = get("/assets/app.js", Some("br"), Some(etag.clone())).await;
+        assert_eq!(response.status(), Status::Ok);
+        let br_etag = response.headers().get_one("ETag").unwrap().to_string();
+        assert_ne!(br_etag, etag);
+        let response = get("/assets/app.js", Some("br"), Some(br_etag)).await;
+        assert_eq!(response.status(), Status::NotModified);
+
+        // index.html is never stored without revalidation
+        let response = get("/index.html", None, None).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("no-cache"));
+        let index_etag = response.headers().get_one("ETag").unwrap().to_string();
+        let response = get("/index.html", None, Some(index_etag.clone())).await;
+        assert_eq!(response.status(), Status::NotModified);
+        // also when it is the console route fallback
+        let response = get("/ui/some/client/route", None, None).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("no-cache"));
+        assert_eq!(response.headers().get_one("ETag"), Some(index_etag.as_str()));
+        let response = get("/ui/some/client/route", None, Some(index_etag)).await;
+        assert_eq!(response.status(), Status::NotModified);
+    }
+
+    #[rocket::async_test]
+    async fn test_ab_peer_update_version() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "peerversion", false).await;
+        let guid = state.get_all_users(Some("peerversion"), None, None, 1, 1).await.unwrap()[0]
+            .guid
+            .clone();
+        let user_id = Uuid::parse_str(&guid).unwrap().as_bytes().to_vec();
+        let ab = state.get_ab_personal_guid(user_id).await.unwrap();
+        let ab_peer = AbPeer { id: "123456789".to_string(), ..Default::default() };
+        state.add_ab_peer(&ab, ab_peer).await.unwrap();
+        let rocket = rocket::build().mount("/", routes![ab_peer_update]).manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let state = client.rocket().state::<ApiState>().unwrap();
+        let update = |peer: serde_json::Value| {
+            client
+                .put(format!("/api/ab/peer/update/{}", ab))
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body(peer.to_string())
+                .dispatch()
+        };
+        let peer = || async { state.get_ab_peer(&ab, "123456789").await.unwrap() };
+        assert_eq!(peer().await.version, Some(0));
+
+        // an update with the current version succeeds and bumps it
+        let response = update(serde_json::json!({ "id": "123456789", "alias": "first", "version": 0 })).await;
+        assert_eq!(response.status(), Status::Ok);
+        let updated = peer().await;
+        assert_eq!(updated.alias.as_deref(), Some("first"));
+        assert_eq!(updated.version, Some(1));
+
+        // a client still holding the previous version gets a conflict and the first update is kept
+        let response = update(serde_json::json!({ "id": "123456789", "alias": "second", "version": 0 })).await;
+        assert_eq!(response.status(), Status::Conflict);
+        let kept = peer().await;
+        assert_eq!(kept.alias.as_deref(), Some("first"));
+        assert_eq!(kept.version, Some(1));
+
+        // without version the last write still wins
+        let response = update(serde_json::json!({ "id": "123456789", "alias": "third" })).await;
+        assert_eq!(response.status(), Status::Ok);
+        let updated = peer().await;
+        assert_eq!(updated.alias.as_deref(), Some("third"));
+        assert_eq!(updated.version, Some(2));
+    }
+
+    #[rocket::async_test]
+    async fn test_branding() {
+        let figment = rocket::Config::figment()
+            .merge(("branding.product_name", "Acme <Desk>"))
+            .merge(("branding.logo", "iVBORw0KGgo="))
+            .merge(("branding.accent_color", "#0055aa"))
+            .merge(("branding.footer_html", "<b>Acme</b>"));
+        let branding = Branding::from_figment(&figment);
+        assert!(branding.rapidoc_footer().contains("background-color:#0055aa;"));
+        let static_files = StaticFiles::new(&TEST_DIR).with_index_title("Acme <Desk> Web Console");
+        let rocket = rocket::custom(&figment)
+            .mount("/", routes![branding_settings, webconsole_vue])
+            .manage(static_files)
+            .manage(branding);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let response = client
+            .get("/api/settings/branding")
+            .header(ContentType::JSON)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let branding: BrandingResponse = response.into_json().await.unwrap();
+        assert_eq!(branding.product_name, "Acme <Desk>");
+        assert_eq!(branding.logo, "data:image/png;base64,iVBORw0KGgo=");
+        assert_eq!(branding.accent_color, "#0055aa");
+        assert_eq!(branding.footer_html, "<b>Acme</b>");
+
+        // the title of the console is replaced, also for the client side routes
+        let default_etag = StaticFiles::new(&TEST_DIR).get("index.html", None).unwrap().etag;
+        for path in ["/ui/index.html", "/ui/login"] {
+            let response = client.get(path).header(Header::new("Accept-Encoding", "br")).dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.headers().get_one("Cache-Control"), Some("no-cache"));
+            assert!(response.headers().get_one("Content-Encoding").is_none());
+            assert_ne!(response.headers().get_one("ETag").map(str::to_string), default_etag);
+            assert_eq!(
+                response.into_string().await.unwrap(),
+                "<html><head><title>Acme &lt;Desk&gt; Web Console</title></head></html>"
+            );
+        }
+
+        // without configuration the current branding is kept
+        let branding = Branding::from_figment(&rocket::Config::figment());
+        assert_eq!(branding.custom_product_name(), None);
+        assert_eq!(branding.response().product_name, "SCTGDesk");
+        assert!(branding.logo().starts_with("data:image/png;base64,"));
+        assert!(branding.rapidoc_footer().contains("background-color:orangered;"));
+        assert!(branding.rapidoc_footer().contains("© 2024 SCTG. All rights reserved."));
+        // an unreadable logo keeps the default one
+        let figment = rocket::Config::figment().merge(("branding.logo", "/nonexistent/logo.png"));
+        assert_eq!(Branding::from_figment(&figment).logo(), branding.logo());
+        assert_eq!(replace_html_title("<html></html>", "Acme"), None);
+    }
+
+    #[rocket::async_test]
+    async fn test_openapi_servers() {
+        let servers = |public_base_url: Option<&'static str>| async move {
+            let (api_routes, openapi_spec) = openapi_get_routes_spec![version];
+            let state = ApiState::new_with_db(test_db_file())
+                .await
+                .with_public_base_url(public_base_url);
+            let rocket = rocket::build()
+                .mount("/", api_routes)
+                .mount("/", routes![openapi_json])
+                .manage(state)
+                .manage(OpenApiSpec(openapi_spec));
+            let client = Client::tracked(rocket).await.unwrap();
+            let spec: serde_json::Value = client
+                .get("/openapi.json")
+                .header(Header::new("Host", "desk.example.org:21114"))
+                .dispatch()
+                .await
+                .into_json()
+                .await
+                .unwrap();
+            assert!(spec["paths"]["/api/version"].is_object());
+            spec["servers"].clone()
+        };
+
+        // the host the request was sent to
+        assert_eq!(servers(None).await, serde_json::json!([{ "url": "http://desk.example.org:21114" }]));
+
+        // the configured public url wins
+        assert_eq!(
+            servers(Some("https://desk.example.org/rustdesk/")).await,
+            serde_json::json!([{ "url": "https://desk.example.org/rustdesk" }])
+        );
+    }
+
+    /// Minimal Vite development server answering with the request line and the forwarded headers
+    async fn mock_vite_server() -> String {
+        use rocket::tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = rocket::tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        rocket::tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let read = socket.read(&mut buffer).await.unwrap_or(0);
+                    if read == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                let request = String::from_utf8_lossy(&request).to_lowercase();
+                let target = request.split(' ').nth(1).unwrap_or("").to_string();
+                let accept = request
+                    .lines()
+                    .find_map(|line| line.strip_prefix("accept: "))
+                    .unwrap_or("")
+                    .to_string();
+                let body = format!("{} {}", target, accept);
+                let content_type = if target.starts_with("/ui/raw") {
+                    ""
+                } else {
+                    "Content-Type: application/javascript\r\n"
+                };
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    content_type,
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    #[rocket::async_test]
+    async fn test_webconsole_vite_proxy() {
+        let client = |vite_base: String| async move {
+            let rocket = rocket::build()
+                .mount("/", routes![webconsole_vue])
+                .register("/", catchers![webconsole_fallback])
+                .manage(StaticFiles::new(&TEST_DIR).with_vite_proxy(vite_base));
+            Client::tracked(rocket).await.unwrap()
+        };
+
+        let vite = client(mock_vite_server().await).await;
+        // the query string and the headers are forwarded, the body is streamed back
+        let response = vite
+            .get("/ui/src/main.ts?v=1&import")
+            .header(Header::new("Accept", "text/javascript"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Content-Type"), Some("application/javascript"));
+        assert_eq!(response.into_string().await.unwrap(), "/ui/src/main.ts?v=1&import text/javascript");
+        // the client side routes served by the catcher
+        let response = vite.get("/ui/login").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = vite.get("/login").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().await.unwrap().starts_with("/ui/login "));
+        // an answer without content type
+        let response = vite.get("/ui/raw").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), None);
+        assert!(response.into_string().await.unwrap().starts_with("/ui/raw "));
+
+        // a stopped development server
+        let listener = rocket::tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let stopped = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let vite = client(stopped).await;
+        for path in ["/ui/src/main.ts", "/login"] {
+            let response = vite.get(path).dispatch().await;
+            assert_eq!(response.status(), Status::BadGateway);
+            assert_eq!(response.content_type(), Some(ContentType::HTML));
+            assert!(response.into_string().await.unwrap().contains("502 Bad Gateway"));
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_min_client_version() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file.clone()).await;
+        state
+            .add_user(AddUserRequest {
+                name: "oldclient".to_string(),
+                password: "password".to_string(),
+                confirm_password: "password".to_string(),
+                email: "oldclient@example.org".to_string(),
+                is_admin: false,
+                group_name: "Default".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        // the peer reported its version in its system info
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO peer(guid, id, uuid, pk, info) VALUES (?, '123456789', 'heartbeat', x'', '{\"version\":\"1.1.9\"}')")
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let figment = rocket::Config::figment()
+            .merge(("min_client_version", "1.2.0"))
+            .merge(("latest_client_version", "1.2.6"));
+        let rocket = rocket::custom(&figment)
+            .mount("/", routes![login, heartbeat])
+            .manage(DefaultClientVersions::from_figment(&figment))
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let login = |id: &'static str, version: Option<&'static str>| {
+            let mut request = client
+                .post("/api/login")
+                .header(ContentType::JSON)
+                .header(Header::new("Host", "desk.example.org"))
+                .body(format!(r#"{{"username":"oldclient","password":"password","id":"{}","uuid":""}}"#, id));
+            if let Some(version) = version {
+                request = request.header(Header::new("X-Client-Version", version));
+            }
+            request.dispatch()
+        };
+
+        // allowed versions, and the versions which cannot be checked
+        for version in [Some("1.2.0"), Some("1.3.0"), Some("nightly"), None] {
+            let response = login("987654321", version).await;
+            assert_eq!(response.status(), Status::Ok, "{:?}", version);
+        }
+
+        // too old from the header
+        let response = login("987654321", Some("1.1.0")).await;
+        assert_eq!(response.status(), Status::UpgradeRequired);
+        let upgrade: ClientUpgradeResponse = response.into_json().await.unwrap();
+        assert_eq!(upgrade.min_version, "1.2.0");
+        assert_eq!(upgrade.url, "http://desk.example.org/api/software/releases/tag/1.2.6");
+        assert!(upgrade.error.contains("1.1.0") && upgrade.error.contains(&upgrade.url));
+        // too old from the system info of the peer, the header wins
+        assert_eq!(login("123456789", None).await.status(), Status::UpgradeRequired);
+        assert_eq!(login("123456789", Some("1.2.1")).await.status(), Status::Ok);
+
+        let response = client
+            .post("/api/heartbeat")
+            .header(ContentType::JSON)
+            .body(r#"{"id":"123456789","modified_at":0,"uuid":"aGVhcnRiZWF0","ver":1}"#)
+            .dispatch()
+            .await;
+        let response: HeartbeatResponse = response.into_json().await.unwrap();
+        assert_eq!(response.status, "CLIENT_TOO_OLD");
+        match &response.directives[..] {
+            [HeartbeatDirective::UpgradeRequired(upgrade)] => assert_eq!(upgrade.min_version, "1.2.0"),
+            directives => panic!("unexpected directives {:?}", directives),
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_security_headers() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        let authorization = login_header(&state, "headers", false).await;
+
+        let origins = vec!["https://accounts.google.com".to_string()];
+        let rocket = rocket::build()
+            .attach(SecurityHeaders::new(None, None, &origins))
+            .mount("/", routes![webconsole_vue, ab_post])
+            .manage(StaticFiles::new(&TEST_DIR))
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        // the console page and its scripts get the headers
+        for path in ["/ui/", "/ui/small.js"] {
+            let response = client.get(path).dispatch().await;
+            assert_eq!(response.status(), Status::Ok);
+            let headers = response.headers();
+            let csp = headers.get_one("Content-Security-Policy").unwrap();
+            assert!(csp.contains("default-src 'self'"));
+            assert!(csp.contains("form-action 'self' https://accounts.google.com;"));
+            assert_eq!(headers.get_one("X-Content-Type-Options"), Some("nosniff"));
+            assert_eq!(headers.get_one("X-Frame-Options"), Some("DENY"));
+            assert_eq!(headers.get_one("Referrer-Policy"), Some("strict-origin-when-cross-origin"));
+        }
+        // not the images
+        let response = client.get("/ui/assets/logo.png").dispatch().await;
+        assert_eq!(response.headers().get_one("Content-Security-Policy"), None);
+
+        // nor the API
+        let response = client
+            .post("/api/ab/get")
+            .header(ContentType::JSON)
+            .header(authorization)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        // X-Content-Type-Options and X-Frame-Options: SAMEORIGIN are the ones of the Rocket shield
+        assert_eq!(response.headers().get_one("Content-Security-Policy"), None);
+        assert_eq!(response.headers().get_one("Referrer-Policy"), None);
+        assert_eq!(response.headers().get_one("X-Frame-Options"), Some("SAMEORIGIN"));
+
+        // a custom policy, or no headers at all
+        let custom = SecurityHeaders::new(Some("true"), Some("default-src 'self' {oidc_origins}"), &origins);
+        assert_eq!(custom.content_security_policy(), Some("default-src 'self' https://accounts.google.com"));
+        let empty = SecurityHeaders::new(None, Some("default-src 'self'; connect-src 'self' {oidc_origins}"), &[]);
+        assert_eq!(empty.content_security_policy(), Some("default-src 'self'; connect-src 'self'"));
+        let rocket = rocket::build()
+            .attach(SecurityHeaders::new(Some("false"), None, &origins))
+            .mount("/", routes![webconsole_vue])
+            .manage(StaticFiles::new(&TEST_DIR));
+        let client = Client::tracked(rocket).await.unwrap();
+        let response = client.get("/ui/").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.headers().get_one("Content-Security-Policy"), None);
+        assert_eq!(response.headers().get_one("Referrer-Policy"), None);
+        assert_eq!(response.headers().get_one("X-Frame-Options"), Some("SAMEORIGIN"));
+    }
+
+    #[rocket::async_test]
+    async fn test_peer_avatar() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let authorization = login_header(&state, "avataradmin", true).await;
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO peer(guid, id, uuid, pk) VALUES (?, '123456789', x'01', x'')")
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let rocket = rocket::build()
+            .mount("/", routes![peer_avatar_put, peer_avatar_get])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let put = |id: &'static str, content_type: ContentType, body: Vec<u8>| {
+            client
+                .put(format!("/api/peer/{}/avatar", id))
+                .header(content_type)
+                .header(authorization.clone())
+                .body(body)
+                .dispatch()
+        };
+        let png = [b"\x89PNG\r\n\x1a\n".as_slice(), b"icon"].concat();
+        let webp = [b"RIFF\x0c\x00\x00\x00WEBP".as_slice(), b"VP8 icon"].concat();
+
+        // no image yet
+        let response = client
+            .get("/api/peer/123456789/avatar")
+            .header(authorization.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        let response = put("123456789", ContentType::PNG, png.clone()).await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .get("/api/peer/123456789/avatar")
+            .header(authorization.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::PNG));
+        assert_eq!(response.headers().get_one("Cache-Control"), Some("private, max-age=300"));
+        let etag = response.headers().get_one("ETag").unwrap().to_string();
+        assert_eq!(response.into_bytes().await.unwrap(), png);
+        let response = client
+            .get("/api/peer/123456789/avatar")
+            .header(authorization.clone())
+            .header(Header::new("If-None-Match", etag.clone()))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotModified);
+
+        // a new image replaces the previous one
+        let response = put("123456789", ContentType::new("image", "webp"), webp.clone()).await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .get("/api/peer/123456789/avatar")
+            .header(authorization.clone())
+            .header(Header::new("If-None-Match", etag))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(response.content_type(), Some(ContentType::new("image", "webp")));
+        assert_eq!(response.into_bytes().await.unwrap(), webp);
+
+        // the oversized, the invalid and the mislabelled images are refused
+        let oversized = [png.clone(), vec![0; 256 * 1024]].concat();
+        let response = put("123456789", ContentType::PNG, oversized).await;
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        let response = put("123456789", ContentType::GIF, b"GIF89a".to_vec()).await;
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+        let response = put("123456789", ContentType::PNG, b"not an image".to_vec()).await;
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+        let response = put("123456789", ContentType::PNG, webp.clone()).await;
+        assert_eq!(response.status(), Status::UnsupportedMediaType);
+        let response = put("987654321", ContentType::PNG, png.clone()).await;
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client
+            .get("/api/peer/123456789/avatar")
+            .header(authorization)
+            .dispatch()
+            .await;
+        assert_eq!(response.into_bytes().await.unwrap(), webp);
+    }
+
+    #[rocket::async_test]
+    async fn test_webconsole_paths() {
+        let rocket = rocket::build()
+            .mount("/", routes![webconsole_vue, webconsole_assets])
+            .register("/", catchers![webconsole_fallback])
+            .manage(StaticFiles::new(&TEST_DIR));
+        let client = Client::tracked(rocket).await.unwrap();
+
+        // /assets is served by the same files as /ui/assets and the site root
+        for path in ["/assets/app.js", "/ui/assets/app.js", "/ui/./assets//app.js"] {
+            let response = client.get(path).dispatch().await;
+            assert_eq!(response.status(), Status::Ok, "{}", path);
+            assert_eq!(response.content_type(), Some(ContentType::JavaScript), "{}", path);
+            assert_eq!(response.into_string().await.unwrap(), "plain", "{}", path);
+        }
+        let response = client.get("/assets/missing.js").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        // the parent and absolute components are refused, encoded or not
+        for path in [
+            "/ui/../small.js",
+            "/assets/../index.html",
+            "/ui/assets/..%2Fsmall.js",
+            "/%2e%2e/small.js",
+            "/ui/assets%5C..%5Csmall.js",
+        ] {
+            let response = client.get(path).dispatch().await;
+            assert_eq!(response.status(), Status::BadRequest, "{}", path);
+        }
+
+        assert_eq!(webconsole::normalize_path("/assets//./app.js"), Some("assets/app.js".to_string()));
+        assert_eq!(webconsole::normalize_path(""), Some("".to_string()));
+        assert_eq!(webconsole::normalize_path("assets/../../etc/passwd"), None);
+        assert_eq!(webconsole::normalize_path("C:\\Windows"), None);
+    }
+
+    #[rocket::async_test]
+    async fn test_ab_batch_get() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let authorization = login_header(&state, "batchadmin", true).await;
+        for name in ["alice", "bob"] {
+            login_header(&state, name, false).await;
+        }
+        let user_guid = |name: &'static str| {
+            let state = &state;
+            async move { state.get_all_users(Some(name), None, None, 1, 10).await.unwrap()[0].guid.clone() }
+        };
+        let alice = user_guid("alice").await;
+        let bob = user_guid("bob").await;
+        // the address book of alice is in the database, the one of bob is only in memory
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO ab_legacy (user_guid, ab) VALUES (?, ?)")
+            .bind(Uuid::parse_str(&alice).unwrap().as_bytes().to_vec())
+            .bind(r#"{"tags":[],"peers":[{"id":"111111111"}]}"#)
+            .execute(&pool)
+            .await
+            .unwrap();
+        state
+            .set_user_address_book(
+                Uuid::parse_str(&bob).unwrap().as_bytes().to_vec(),
+                AddressBook {
+                    ab: r#"{"tags":["office"],"peers":[{"id":"222222222"}]}"#.to_string(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let rocket = rocket::build().mount("/", routes![ab_batch_get]).manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let batch_get = |users: Vec<String>| {
+            client
+                .post("/api/ab/batch-get")
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body(serde_json::json!({ "users": users }).to_string())
+                .dispatch()
+        };
+
+        let unknown = Uuid::new_v4().to_string();
+        let response = batch_get(vec![alice.clone(), bob.clone(), unknown.clone()]).await;
+        assert_eq!(response.status(), Status::Ok);
+        let reply: AbBatchGetResponse = response.into_json().await.unwrap();
+        assert_eq!(reply.data.len(), 2);
+        assert_eq!(reply.data[&alice], r#"{"tags":[],"peers":[{"id":"111111111"}]}"#);
+        assert_eq!(reply.data[&bob], r#"{"tags":["office"],"peers":[{"id":"222222222"}]}"#);
+        assert_eq!(reply.not_found, vec![unknown]);
+
+        // the batch is capped
+        let users = (0..=AB_BATCH_GET_MAX_SIZE).map(|_| Uuid::new_v4().to_string()).collect();
+        let response = batch_get(users).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = batch_get(vec!["not a guid".to_string()]).await;
+        assert_eq!(response.status(), Status::BadRequest);
+    }
+
+    #[rocket::async_test]
+    async fn test_base_path() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        state
+            .add_user(AddUserRequest {
+                name: "proxied".to_string(),
+                password: "password".to_string(),
+                confirm_password: "password".to_string(),
+                email: "proxied@example.org".to_string(),
+                is_admin: false,
+                group_name: "Default".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let figment = rocket::Config::figment().merge(("base_path", "sctgdesk/"));
+        let base_path = BasePath::from_figment(&figment);
+        assert_eq!(base_path.as_str(), "/sctgdesk");
+        let rocket = rocket::custom(&figment)
+            .mount(base_path.mount_point("/"), routes![login, ab_get, webconsole_vue])
+            .register(base_path.mount_point("/"), catchers![webconsole_fallback])
+            .manage(StaticFiles::new(&TEST_DIR).with_base_path(base_path.as_str()))
+            .manage(DefaultClientVersions::from_figment(&figment))
+            .manage(base_path)
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let login = |path: &'static str| {
+            client
+                .post(path)
+                .header(ContentType::JSON)
+                .body(r#"{"username":"proxied","password":"password","id":"123456789","uuid":""}"#)
+                .dispatch()
+        };
+
+        // the API is only served under the base path
+        assert_eq!(login("/api/login").await.status(), Status::NotFound);
+        let response = login("/sctgdesk/api/login").await;
+        assert_eq!(response.status(), Status::Ok);
+        let reply: serde_json::Value = response.into_json().await.unwrap();
+        let token = reply["access_token"].as_str().unwrap().to_string();
+        let response = client
+            .get("/sctgdesk/api/ab")
+            .header(ContentType::JSON)
+            .header(Header::new("Authorization", format!("Bearer {}", token)))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let ab: serde_json::Value = response.into_json().await.unwrap();
+        assert!(ab["data"].is_string());
+
+        // the console is served with the base path in its <base href>
+        for path in ["/sctgdesk/ui/", "/sctgdesk/ui/login", "/sctgdesk/"] {
+            let response = client.get(path).dispatch().await;
+            assert_eq!(response.status(), Status::Ok, "{}", path);
+            let html = response.into_string().await.unwrap();
+            assert!(html.contains(r#"<base href="/sctgdesk/ui/">"#), "{}", path);
+            assert!(html.contains("<title>SCTGDesk Web Console</title>"), "{}", path);
+        }
+        let response = client.get("/sctgdesk/ui/small.js").dispatch().await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(client.get("/ui/").dispatch().await.status(), Status::NotFound);
+        assert_eq!(client.get("/sctgdesk/api/missing").dispatch().await.status(), Status::NotFound);
+
+        assert_eq!(BasePath::new("").mount_point("/api/doc/"), "/api/doc/");
+        assert_eq!(BasePath::new("/a/b/").mount_point("/api/doc/"), "/a/b/api/doc/");
+        assert_eq!(BasePath::strip("/a", "/a"), Some("/"));
+        assert_eq!(BasePath::strip("/a", "/ab/ui"), None);
+        assert_eq!(
+            webconsole::replace_html_base("<html><head><title>t</title></head>", "/a/ui/"),
+            r#"<html><head><base href="/a/ui/"><title>t</title></head>"#
+        );
+    }
+
+    #[rocket::async_test]
+    async fn test_login_reply_shape() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        state
+            .add_user(AddUserRequest {
+                name: "shaped".to_string(),
+                password: "password".to_string(),
+                confirm_password: "password".to_string(),
+                email: "shaped@example.org".to_string(),
+                is_admin: false,
+                group_name: "Default".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let figment = rocket::Config::figment();
+        let rocket = rocket::custom(&figment)
+            .mount("/", routes![login])
+            .manage(DefaultClientVersions::from_figment(&figment))
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let client = &client;
+        let login = |version: Option<&'static str>| async move {
+            let mut request = client
+                .post("/api/login")
+                .header(ContentType::JSON)
+                .body(r#"{"username":"shaped","password":"password","id":"123456789","uuid":""}"#);
+            if let Some(version) = version {
+                request = request.header(Header::new("X-Client-Version", version));
+            }
+            let response = request.dispatch().await;
+            assert_eq!(response.status(), Status::Ok, "{:?}", version);
+            assert_eq!(response.headers().get_one("Vary"), Some("X-Client-Version"));
+            response.into_json::<serde_json::Value>().await.unwrap()
+        };
+
+        // the current shape by default and for the recent clients
+        for version in [None, Some("1.1.9"), Some("1.2.6"), Some("nightly")] {
+            let reply = login(version).await;
+            assert_eq!(reply["type"], "access_token", "{:?}", version);
+            assert_eq!(reply["user"]["name"], "shaped", "{:?}", version);
+            assert!(reply["access_token"].is_string(), "{:?}", version);
+            assert!(reply.get("token").is_none(), "{:?}", version);
+        }
+        // the legacy shape for the older clients
+        for version in ["1.1.8", "1.1.9-beta.1"] {
+            let reply = login(Some(version)).await;
+            assert_eq!(reply["type"], "access_token", "{}", version);
+            assert_eq!(reply["user"]["name"], "shaped", "{}", version);
+            assert!(reply["token"].is_string(), "{}", version);
+            assert!(reply.get("access_token").is_none(), "{}", version);
+        }
+    }
+
+    #[rocket::async_test]
+    async fn test_cache_policy() {
+        async fn get(client: &Client, path: &str) -> Option<String> {
+            let response = client.get(path).dispatch().await;
+            assert_eq!(response.status(), Status::Ok, "{}", path);
+            response.headers().get_one("Cache-Control").map(str::to_string)
+        }
+
+        // the pages are revalidated, the scripts and the images are cached
+        let rocket = rocket::build()
+            .mount("/", routes![webconsole_vue, openapi_snippet])
+            .manage(StaticFiles::new(&TEST_DIR));
+        let client = Client::tracked(rocket).await.unwrap();
+        assert_eq!(get(&client, "/ui/").await.as_deref(), Some("no-cache"));
+        assert_eq!(get(&client, "/ui/index.html").await.as_deref(), Some("no-cache"));
+        assert_eq!(get(&client, "/ui/small.js").await.as_deref(), Some("max-age=604800"));
+        assert_eq!(get(&client, "/js/openapisnippet.min.js").await.as_deref(), Some("max-age=604800"));
+        assert_eq!(get(&client, "/ui/assets/logo.png").await.as_deref(), Some("max-age=2592000"));
+
+        // each kind of file can be configured
+        let figment = rocket::Config::figment()
+            .merge(("cache_control.scripts", "max-age=60"))
+            .merge(("cache_control.html", " "));
+        let policy = CachePolicy::from_figment(&figment);
+        assert_eq!(policy.html, "no-cache");
+        assert_eq!(policy.media, "max-age=2592000");
+        let rocket = rocket::custom(&figment)
+            .mount("/", routes![webconsole_vue, openapi_snippet])
+            .manage(StaticFiles::new(&TEST_DIR).with_cache_policy(policy));
+        let client = Client::tracked(rocket).await.unwrap();
+        assert_eq!(get(&client, "/ui/").await.as_deref(), Some("no-cache"));
+        assert_eq!(get(&client, "/ui/small.js").await.as_deref(), Some("max-age=60"));
+        assert_eq!(get(&client, "/js/openapisnippet.min.js").await.as_deref(), Some("max-age=60"));
+        assert_eq!(get(&client, "/ui/assets/logo.png").await.as_deref(), Some("max-age=2592000"));
+    }
+
+    #[rocket::async_test]
+    async fn test_ui_settings() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let mut authorizations = Vec::new();
+        for (name, is_admin) in [("uiadmin", true), ("uiuser", false)] {
+            authorizations.push(login_header(&state, name, is_admin).await);
+        }
+        let (admin, user) = (authorizations[0].clone(), authorizations[1].clone());
+        let rocket = rocket::build()
+            .mount("/", routes![ui_settings, ui_settings_update, ui_defaults, ui_defaults_update])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let get = |uri: &'static str, authorization: Header<'static>| client.get(uri).header(authorization).dispatch();
+        let put = |uri: &'static str, authorization: Header<'static>, body: String| {
+            client.put(uri).header(authorization).body(body).dispatch()
+        };
+
+        // nothing stored yet
+        let response = get("/api/settings/ui", user.clone()).await;
+        assert_eq!(response.into_json::<serde_json::Value>().await.unwrap(), serde_json::json!({}));
+
+        // round trip of the settings of the admin
+        let settings = serde_json::json!({ "theme": "dark", "columns": ["id", "alias"] });
+        let response = put("/api/settings/ui", admin.clone(), settings.to_string()).await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = get("/api/settings/ui", admin.clone()).await;
+        assert_eq!(response.into_json::<serde_json::Value>().await.unwrap(), settings);
+
+        // the other user does not see them
+        let response = get("/api/settings/ui", user.clone()).await;
+        assert_eq!(response.into_json::<serde_json::Value>().await.unwrap(), serde_json::json!({}));
+
+        // the defaults are merged under the settings of each user
+        let defaults = serde_json::json!({ "theme": "light", "page_size": 20 });
+        let response = put("/api/settings/ui/defaults", user.clone(), defaults.to_string()).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = put("/api/settings/ui/defaults", admin.clone(), defaults.to_string()).await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = get("/api/settings/ui/defaults", admin.clone()).await;
+        assert_eq!(response.into_json::<serde_json::Value>().await.unwrap(), defaults);
+        let response = get("/api/settings/ui/defaults", user.clone()).await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = get("/api/settings/ui", user.clone()).await;
+        assert_eq!(response.into_json::<serde_json::Value>().await.unwrap(), defaults);
+        let response = get("/api/settings/ui", admin.clone()).await;
+        assert_eq!(
+            response.into_json::<serde_json::Value>().await.unwrap(),
+            serde_json::json!({ "theme": "dark", "columns": ["id", "alias"], "page_size": 20 })
+        );
+
+        // the oversized and the invalid settings are refused and the stored ones are kept
+        let oversized = serde_json::json!({ "note": "x".repeat(utils::ui_settings::UI_SETTINGS_MAX_SIZE) });
+        let response = put("/api/settings/ui", user.clone(), oversized.to_string()).await;
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        let response = put("/api/settings/ui/defaults", admin.clone(), oversized.to_string()).await;
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        let response = put("/api/settings/ui", user.clone(), "{\"theme\":".to_string()).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = get("/api/settings/ui", user).await;
+        assert_eq!(response.into_json::<serde_json::Value>().await.unwrap(), defaults);
+    }
+
+    #[rocket::async_test]
+    async fn test_peer_access() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        for group in ["accessgrp", "othergrp"] {
+            state.create_group(group, "Default", "", None).await.unwrap();
+        }
+        for (name, is_admin) in [("accessadmin", true), ("accessowner", false), ("accessgranted", false), ("accessother", false)] {
+            state
+                .add_user(AddUserRequest {
+                    name: name.to_string(),
+                    password: "password".to_string(),
+                    confirm_password: "password".to_string(),
+                    email: format!("{}@example.org", name),
+                    is_admin,
+                    group_name: "othergrp".to_string(),
+                })
+                .await
+                .unwrap()
+                .unwrap();
+        }
+        let password = UserPasswordInfo::from_password("password");
+        let (_, token) = state
+            .user_login(&"accessadmin".to_string(), password, true)
+            .await
+            .unwrap();
+        let authorization = Header::new("Authorization", format!("Bearer {}", token.to_base64()));
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        let user_guid = "(SELECT guid FROM user WHERE name = ?)";
+        sqlx::query(&format!("INSERT INTO peer(guid, id, uuid, pk, user) VALUES (?, '123456789', x'01', x'', {})", user_guid))
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .bind("accessowner")
+            .execute(&pool)
+            .await
+            .unwrap();
+        let ab = Uuid::new_v4().as_bytes().to_vec();
+        sqlx::query(&format!("INSERT INTO ab(guid, name, owner, personal, info) VALUES (?, 'Servers', {}, 0, '{{}}')", user_guid))
+            .bind(ab.clone())
+            .bind("accessadmin")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO ab_peer(guid, ab, id, info) VALUES (?, ?, '123456789', '{}')")
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .bind(ab.clone())
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query(&format!("INSERT INTO ab_rule(guid, ab, user, rule) VALUES (?, ?, {}, 1)", user_guid))
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .bind(ab.clone())
+            .bind("accessgranted")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO ab_rule(guid, ab, grp, rule) VALUES (?, ?, (SELECT guid FROM grp WHERE name = 'accessgrp'), 2)")
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .bind(ab.clone())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let rocket = rocket::build().mount("/", routes![peer_access]).manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+
+        let response = client
+            .get("/api/peer/123456789/access")
+            .header(authorization.clone())
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let access = response.into_json::<PeerAccess>().await.unwrap();
+        assert_eq!(access.id, "123456789");
+        let sources = |name: &str| {
+            access
+                .users
+                .iter()
+                .find(|user| user.name == name)
+                .map(|user| user.grants.iter().map(|grant| grant.source).collect::<Vec<_>>())
+        };
+        assert_eq!(sources("accessowner"), Some(vec![PeerAccessSource::Owner]));
+        assert_eq!(
+            sources("accessadmin"),
+            Some(vec![PeerAccessSource::Admin, PeerAccessSource::AddressBookOwner])
+        );
+        assert_eq!(sources("accessgranted"), Some(vec![PeerAccessSource::AddressBookRule]));
+        assert_eq!(sources("accessother"), None);
+        let granted = access.users.iter().find(|user| user.name == "accessgranted").unwrap();
+        let address_book = granted.grants[0].address_book.as_ref().unwrap();
+        assert_eq!((address_book.name.as_str(), address_book.rule), ("Servers", AB_RULE_READ));
+        assert_eq!(access.groups.len(), 1);
+        assert_eq!(access.groups[0].name, "accessgrp");
+        assert_eq!(access.groups[0].grants[0].address_book.as_ref().unwrap().rule, AB_RULE_WRITE);
+        assert!(!access.groups.iter().any(|group| group.name == "othergrp"));
+
+        let response = client
+            .get("/api/peer/987654321/access")
+            .header(authorization)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+    }
+
+    #[test]
+    fn test_database_path_parse() {
+        assert_eq!(DatabasePath::parse("sqlite:///var/lib/sctgdesk/db.sqlite3"), Some(PathBuf::from("/var/lib/sctgdesk/db.sqlite3")));
+        assert_eq!(DatabasePath::parse("sqlite://./db_v2.sqlite3?mode=rwc"), Some(PathBuf::from("./db_v2.sqlite3")));
+        assert_eq!(DatabasePath::parse("sqlite:data/db.sqlite3"), Some(PathBuf::from("data/db.sqlite3")));
+        assert_eq!(DatabasePath::parse(" /data/db.sqlite3 "), Some(PathBuf::from("/data/db.sqlite3")));
+        assert_eq!(DatabasePath::parse("sqlite://"), None);
+        assert_eq!(DatabasePath::parse(""), None);
+    }
+
+    #[test]
+    fn test_database_path_not_writable() {
+        let file = env::temp_dir().join(format!("sctgdesk-test-{}", Uuid::new_v4()));
+        std::fs::write(&file, b"").unwrap();
+        // a file cannot be the directory of the database
+        let figment = rocket::Config::figment().merge(("database.url", file.join("db.sqlite3").display().to_string()));
+        let error = DatabasePath::from_figment(&figment).prepare().unwrap_err();
+        assert!(error.contains(&file.display().to_string()), "{}", error);
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn test_build_rocket_database_path() {
+        let dir = env::temp_dir().join(format!("sctgdesk-test-{}", Uuid::new_v4()));
+        let db_file = dir.join("data").join("sctgdesk.sqlite3");
+        let figment = rocket::Config::figment()
+            .merge(("database.url", format!("sqlite://{}", db_file.display())))
+            .merge(("log_level", rocket::config::LogLevel::Off));
+        let client = Client::tracked(build_rocket(figment).await).await.unwrap();
+        assert!(db_file.is_file());
+        // the default admin of the new database can log in
+        let response = client
+            .post("/api/login")
+            .header(ContentType::JSON)
+            .body(r#"{"username":"admin","password":"Hello,world!","id":"123456789","uuid":""}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_string().await.unwrap().contains("access_token"));
+        drop(client);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[rocket::async_test]
+    async fn test_page_size_clamped() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let authorization = login_header(&state, "pagesize", true).await;
+        let guid = state.get_all_users(Some("pagesize"), None, None, 1, 1).await.unwrap()[0]
+            .guid
+            .clone();
+        let user_id = Uuid::parse_str(&guid).unwrap().as_bytes().to_vec();
+        let ab = state.get_ab_personal_guid(user_id).await.unwrap();
+        let rocket = rocket::build()
+            .mount("/", routes![users, users_client, ab_peers])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let get = |uri: String| {
+            client
+                .get(uri)
+                .header(Accept::JSON)
+                .header(authorization.clone())
+                .dispatch()
+        };
+
+        for (page_size, expected) in [(5000, 1000), (0, 20), (1000, 1000), (10, 10)] {
+            let response = get(format!("/api/user-list?current=1&pageSize={}", page_size)).await;
+            assert_eq!(response.status(), Status::Ok);
+            let list: UserList = response.into_json().await.unwrap();
+            assert_eq!(list.page_size, expected);
+            assert!(!list.data.is_empty());
+            let response = get(format!("/api/user-list?pageSize={}&after=", page_size)).await;
+            assert_eq!(response.into_json::<UserList>().await.unwrap().page_size, expected);
+            let response = get(format!("/api/users?current=1&pageSize={}", page_size)).await;
+            assert_eq!(response.into_json::<UserList>().await.unwrap().page_size, expected);
+            let response = client
+                .post(format!("/api/ab/peers?current=1&pageSize={}&ab={}&after=", page_size, ab))
+                .header(authorization.clone())
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            assert_eq!(response.into_json::<AbPeersResponse>().await.unwrap().page_size, expected);
+        }
+
+        // an address book larger than the largest page is paged without a cursor too
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO ab_peer(guid, ab, id, info)
+             WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 1005)
+             SELECT randomblob(16), ?, 100000000 + i, json_object('id', CAST(100000000 + i AS TEXT)) FROM n",
+        )
+        .bind(Uuid::parse_str(&ab).unwrap().as_bytes().to_vec())
+        .execute(&pool)
+        .await
+        .unwrap();
+        for (current, expected) in [(1, 1000), (2, 5)] {
+            let response = client
+                .post(format!("/api/ab/peers?current={}&pageSize=5000&ab={}", current, ab))
+                .header(authorization.clone())
+                .dispatch()
+                .await;
+            let peers = response.into_json::<AbPeersResponse>().await.unwrap();
+            assert_eq!(peers.data.len(), expected);
+            assert_eq!(peers.total, 1005);
+        }
+
+        // a huge page cannot overflow the offset of a later page
+        let response = get(format!("/api/users?current=5000000&pageSize={}", u32::MAX)).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert!(response.into_json::<UserList>().await.unwrap().data.is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn test_user_settings() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let mut authorizations = Vec::new();
+        for name in ["settingsfirst", "settingssecond"] {
+            authorizations.push(login_header(&state, name, false).await);
+        }
+        let (first, second) = (authorizations[0].clone(), authorizations[1].clone());
+        let rocket = rocket::build()
+            .mount("/", routes![user_settings, user_settings_update])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let get = |authorization: Header<'static>| client.get("/api/user/settings").header(authorization).dispatch();
+        let post = |authorization: Header<'static>, body: String| {
+            client.post("/api/user/settings").header(authorization).body(body).dispatch()
+        };
+
+        let response = get(first.clone()).await;
+        assert_eq!(response.into_json::<serde_json::Value>().await.unwrap(), serde_json::json!({}));
+
+        // round trip
+        let settings = serde_json::json!({ "theme": "dark", "allow-auto-disconnect": "Y", "recent": [1, 2, 3] });
+        let response = post(first.clone(), settings.to_string()).await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = get(first.clone()).await;
+        assert_eq!(response.into_json::<serde_json::Value>().await.unwrap(), settings);
+        let response = get(second).await;
+        assert_eq!(response.into_json::<serde_json::Value>().await.unwrap(), serde_json::json!({}));
+
+        // the oversized and the invalid settings are refused and the stored ones are kept
+        let oversized = serde_json::json!({ "note": "x".repeat(16 * 1024) });
+        let response = post(first.clone(), oversized.to_string()).await;
+        assert_eq!(response.status(), Status::PayloadTooLarge);
+        let response = post(first.clone(), "theme=dark".to_string()).await;
+        assert_eq!(response.status(), Status::BadRequest);
+        let response = get(first).await;
+        assert_eq!(response.into_json::<serde_json::Value>().await.unwrap(), settings);
+    }
+
+    #[rocket::async_test]
+    async fn test_linked_providers() {
+        use std::future::Future;
+
+        use oauth2::oauth_provider::OAuthResponse;
+        use oauth2::{Oauth2Error, Provider};
+
+        /// Provider exchanging any code without calling a server
+        struct TestProvider;
+        impl OAuthProvider for TestProvider {
+            fn get_redirect_url(&self, callback_url: &str, state: &str) -> String {
+                format!("https://provider.example.org/authorize?redirect_uri={}&state={}", callback_url, state)
+            }
+            fn exchange_code(
+                &self,
+                _code: &str,
+                _callback_url: &str,
+            ) -> Pin<Box<dyn Future<Output = Result<OAuthResponse, Oauth2Error>> + Send + Sync>> {
+                Box::pin(async {
+                    Ok(OAuthResponse {
+                        access_token: "provider-token".to_string(),
+                        username: "linkeduser".to_string(),
+                        email: "linkeduser@example.org".to_string(),
+                    })
+                })
+            }
+            fn get_provider_type(&self) -> Provider {
+                Provider::Dex
+            }
+        }
+
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let authorization = login_header(&state, "linkeduser", false).await;
+        let uuid_code = Uuid::new_v4().to_string();
+        state
+            .insert_oidc_session(
+                uuid_code.clone(),
+                OidcState {
+                    id: "123456789".to_string(),
+                    uuid: "client-uuid".to_string(),
+                    callback_url: Some("http://localhost/api/oidc/callback".to_string()),
+                    provider: Some(Arc::new(TestProvider)),
+                    op: "dex-test".to_string(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let rocket = rocket::build()
+            .mount(
+                "/",
+                routes![oidc_callback, oidc_state, linked_providers, linked_provider_delete],
+            )
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let list = || async {
+            let response = client
+                .get("/api/user/linked-providers")
+                .header(authorization.clone())
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            response.into_json::<LinkedProvidersResponse>().await.unwrap()
+        };
+        let set_password_login = |password_login: bool| {
+            let db_file = db_file.clone();
+            async move {
+                let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+                    .await
+                    .unwrap();
+                sqlx::query("UPDATE user SET password_login = ? WHERE name = 'linkeduser'")
+                    .bind(password_login)
+                    .execute(&pool)
+                    .await
+                    .unwrap();
+            }
+        };
+
+        let listed = list().await;
+        assert!(listed.password_login);
+        assert_eq!(listed.total, 0);
+
+        // the login through the provider links it
+        let response = client
+            .get(format!("/api/oidc/callback?code=provider-code&state={}", uuid_code))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let response = client
+            .get(format!("/api/oidc/auth-query?code={}&id=123456789&uuid=client-uuid", uuid_code))
+            .dispatch()
+            .await;
+        assert!(response.into_string().await.unwrap().contains("access_token"));
+        let listed = list().await;
+        assert_eq!(listed.total, 1);
+        assert_eq!(listed.data[0].op, "dex-test");
+        assert_eq!(listed.data[0].identifier, "linkeduser");
+        assert_eq!(listed.data[0].email, "linkeduser@example.org");
+        assert!(!listed.data[0].enabled);
+        assert!(!listed.data[0].last_used_at.is_empty());
+
+        let delete = |op: &str| client.delete(format!("/api/user/linked-providers/{}", op)).header(authorization.clone()).dispatch();
+        let response = delete("github").await;
+        assert_eq!(response.status(), Status::NotFound);
+
+        // the sole login method of an account without password is kept
+        set_password_login(false).await;
+        let response = delete("dex-test").await;
+        assert_eq!(response.status(), Status::Conflict);
+        let listed = list().await;
+        assert!(!listed.password_login);
+        assert_eq!(listed.total, 1);
+
+        set_password_login(true).await;
+        let response = delete("dex-test").await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(list().await.total, 0);
+
+        let response = client.get("/api/user/linked-providers").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn test_email_verification() {
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+        let smtp = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let figment = rocket::Config::figment()
+            .merge(("smtp_server", smtp.local_addr().unwrap().to_string()))
+            .merge(("smtp_from", "desk@example.org"));
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file).await;
+        state
+            .add_user(AddUserRequest {
+                name: "mailer".to_string(),
+                password: "password".to_string(),
+                confirm_password: "password".to_string(),
+                email: "mailer@example.org".to_string(),
+                is_admin: false,
+                group_name: "Default".to_string(),
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        let password = UserPasswordInfo::from_password("password");
+        let (user, token) = state.user_login(&"mailer".to_string(), password, false).await.unwrap();
+        assert!(!user.email_verified);
+        let authorization = Header::new("Authorization", format!("Bearer {}", token.to_base64()));
+        let rocket = rocket::custom(&figment)
+            .mount("/", routes![email_verify_request, email_verify, current_user])
+            .manage(state)
+            .manage(SmtpSettings::from_figment(&figment));
+        let client = Client::tracked(rocket).await.unwrap();
+        let verify_request = || {
+            client
+                .post("/api/user/email/verify-request")
+                .header(authorization.clone())
+                .header(Header::new("Host", "desk.example.org:21114"))
+                .dispatch()
+        };
+        let email_verified = || async {
+            let response = client
+                .post("/api/currentUser")
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body(r#"{"id":"","uuid":""}"#)
+                .dispatch()
+                .await;
+            let user: serde_json::Value = response.into_json().await.unwrap();
+            user["email_verified"].as_bool().unwrap()
+        };
+
+        let smtp_server = tokio::spawn(async move {
+            let (socket, _) = smtp.accept().await.unwrap();
+            let mut socket = BufReader::new(socket);
+            let mut transcript = String::new();
+            socket.get_mut().write_all(b"220 mock ESMTP\r\n").await.unwrap();
+            let mut data = false;
+            loop {
+                let mut line = String::new();
+                if socket.read_line(&mut line).await.unwrap() == 0 {
+                    break;
+                }
+                transcript.push_str(&line);
+                let reply: &[u8] = if data {
+                    if line != ".\r\n" {
+                        continue;
+                    }
+                    data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("DATA") {
+                    data = true;
+                    b"354 go ahead\r\n"
+                } else if line.starts_with("QUIT") {
+                    socket.get_mut().write_all(b"221 bye\r\n").await.unwrap();
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+                socket.get_mut().write_all(reply).await.unwrap();
+            }
+            transcript
+        });
+        let response = verify_request().await;
+        assert_eq!(response.status(), Status::Ok);
+        let sent: EmailVerificationResponse = response.into_json().await.unwrap();
+        assert_eq!(sent.msg, "sent");
+        assert_eq!(sent.email, "mailer@example.org");
+        assert!(!sent.email_verified);
+        assert!(sent.expires_at.is_some());
+        let transcript = smtp_server.await.unwrap();
+        assert!(transcript.starts_with("EHLO desk.example.org\r\nMAIL FROM:<desk@example.org>\r\nRCPT TO:<mailer@example.org>\r\nDATA\r\n"));
+        assert!(transcript.contains("Subject: Verify your email address\r\n"));
+        assert!(transcript.ends_with(".\r\nQUIT\r\n"));
+        let link_token = transcript
+            .split("/api/user/email/verify?token=")
+            .nth(1)
+            .and_then(|rest| rest.split_whitespace().next())
+            .unwrap()
+            .to_string();
+        assert!(!email_verified().await);
+
+        let response = client.get("/api/user/email/verify?token=unknown").dispatch().await;
+        assert_eq!(response.status(), Status::NotFound);
+        let response = client
+            .get(format!("/api/user/email/verify?token={}", link_token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let verified: EmailVerificationResponse = response.into_json().await.unwrap();
+        assert!(verified.email_verified);
+        assert_eq!(verified.email, "mailer@example.org");
+        assert!(email_verified().await);
+        // the link is used once, no email is sent for a verified email
+        let response = client
+            .get(format!("/api/user/email/verify?token={}", link_token))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::NotFound);
+        let response: EmailVerificationResponse = verify_request().await.into_json().await.unwrap();
+        assert_eq!(response.msg, "already verified");
+        assert!(response.email_verified);
+
+        // an expired link does not verify the email
+        let state = client.rocket().state::<ApiState>().unwrap();
+        let (_, late_token) = {
+            state
+                .add_user(AddUserRequest {
+                    name: "late".to_string(),
+                    password: "password".to_string(),
+                    confirm_password: "password".to_string(),
+                    email: "late@example.org".to_string(),
+                    is_admin: false,
+                    group_name: "Default".to_string(),
+                })
+                .await
+                .unwrap()
+                .unwrap();
+            let password = UserPasswordInfo::from_password("password");
+            state.user_login(&"late".to_string(), password, false).await.unwrap()
+        };
+        let late_id = state.find_session(&late_token).await.unwrap().user_id;
+        let (expired, _, _) = state
+            .issue_email_verification(&late_id, Duration::ZERO)
+            .await
+            .unwrap()
+            .unwrap();
+        let response = client
+            .get(format!("/api/user/email/verify?token={}", expired))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Gone);
+        assert_eq!(state.get_user_email(&late_id).await, Some((Some("late@example.org".to_string()), false)));
+
+        let response = client.post("/api/user/email/verify-request").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[rocket::async_test]
+    async fn test_share_link() {
+        let figment = rocket::Config::figment().merge(("share_link_secret", "share-secret"));
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let mut authorizations = Vec::new();
+        let mut ids = Vec::new();
+        for (name, is_admin) in [("shareadmin", true), ("shareowner", false), ("sharehelper", false)] {
+            authorizations.push(login_header(&state, name, is_admin).await);
+            let guid = state.get_all_users(Some(name), None, None, 1, 10).await.unwrap()[0]
+                .guid
+                .clone();
+            ids.push(Uuid::parse_str(&guid).unwrap().as_bytes().to_vec());
+        }
+        let [admin, owner, helper] = <[Header; 3]>::try_from(authorizations).unwrap();
+        // the peers are registered by hbbs
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO peer(guid, id, uuid, pk, user) VALUES (?, '123456789', x'01', x'', ?)")
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .bind(ids[1].clone())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let helper_ab = state.get_ab_personal_guid(ids[2].clone()).await.unwrap();
+        let rocket = rocket::custom(&figment)
+            .mount("/", routes![peer_share_link, share_link_redeem, peer_access, ab_peers])
+            .manage(state)
+            .manage(ShareLinks::from_figment(&figment));
+        let client = Client::tracked(rocket).await.unwrap();
+        let create = |authorization: &Header<'static>, body: &'static str| {
+            client
+                .post("/api/peer/123456789/share-link")
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .header(Header::new("Host", "desk.example.org:21114"))
+                .body(body)
+                .dispatch()
+        };
+        let redeem = |token: String| {
+            client
+                .get(format!("/api/share/{}", token))
+                .header(helper.clone())
+                .dispatch()
+        };
+        let helper_grants = || async {
+            let response = client
+                .get("/api/peer/123456789/access")
+                .header(admin.clone())
+                .dispatch()
+                .await;
+            let access: PeerAccess = response.into_json().await.unwrap();
+            access
+                .users
+                .into_iter()
+                .find(|user| user.name == "sharehelper")
+                .map(|user| user.grants)
+                .unwrap_or_default()
+        };
+        let helper_peers = || async {
+            let response = client
+                .post(format!("/api/ab/peers?current=1&pageSize=0&ab={}", helper_ab))
+                .header(helper.clone())
+                .dispatch()
+                .await;
+            let peers: AbPeersResponse = response.into_json().await.unwrap();
+            peers.data.into_iter().map(|peer| peer.id).collect::<Vec<_>>()
+        };
+
+        // only an admin or the user the peer is assigned to can share it
+        assert_eq!(create(&helper, "{}").await.status(), Status::Forbidden);
+        assert_eq!(create(&owner, r#"{"expires_in":0}"#).await.status(), Status::BadRequest);
+        assert_eq!(create(&owner, r#"{"expires_in":604801}"#).await.status(), Status::BadRequest);
+        let response = create(&owner, r#"{"expires_in":600}"#).await;
+        assert_eq!(response.status(), Status::Ok);
+        let link: ShareLinkResponse = response.into_json().await.unwrap();
+        assert_eq!(link.peer_id, "123456789");
+        assert!(link.url.ends_with(&format!("/api/share/{}", link.token)));
+        assert!(helper_grants().await.is_empty());
+
+        // a tampered token is refused
+        let (payload, signature) = link.token.split_once('.').unwrap();
+        let forged = ShareLinks::new("other-secret").sign(&ShareLinkClaims {
+            link: link.guid.clone(),
+            peer: "123456789".to_string(),
+            exp: now_secs() + 600,
+        });
+        assert_eq!(redeem(forged).await.status(), Status::NotFound);
+        assert_eq!(redeem(format!("{}x.{}", payload, signature)).await.status(), Status::NotFound);
+
+        // redeemed before its expiry, the link grants the access until the expiry
+        let response = redeem(link.token.clone()).await;
+        assert_eq!(response.status(), Status::Ok);
+        let redeemed: ShareLinkRedeemResponse = response.into_json().await.unwrap();
+        assert_eq!(redeemed.peer_id, "123456789");
+        assert_eq!(redeemed.expires_at, link.expires_at);
+        let grants = helper_grants().await;
+        assert_eq!(grants.len(), 1);
+        assert_eq!(grants[0].source, PeerAccessSource::ShareLink);
+        assert_eq!(grants[0].expires_at.as_ref(), Some(&link.expires_at));
+        // the peer is listed in the personal address book of the user who redeemed the link
+        assert_eq!(helper_peers().await, vec!["123456789"]);
+
+        // an expired link is refused, by its signed expiry or by the one of the database
+        let state = client.rocket().state::<ApiState>().unwrap();
+        let signer = ShareLinks::new("share-secret");
+        let expired = signer.sign(&ShareLinkClaims {
+            exp: now_secs() - 10,
+            ..signer.verify(&link.token, now_secs()).unwrap()
+        });
+        assert_eq!(redeem(expired).await.status(), Status::Gone);
+        let (guid, _) = state.create_share_link("123456789", &ids[1], now_secs() - 10).await.unwrap();
+        let expired = signer.sign(&ShareLinkClaims {
+            link: guid,
+            peer: "123456789".to_string(),
+            exp: now_secs() + 600,
+        });
+        assert_eq!(redeem(expired).await.status(), Status::Gone);
+        assert_eq!(helper_grants().await.len(), 1);
+
+        // the admins can share any peer, the creations and the redemptions are audited
+        assert_eq!(create(&admin, "{}").await.status(), Status::Ok);
+        let filter = AuditFilter::new(None, None, None, None, None, None);
+        let (_, audits) = state.get_admin_audits(&filter).await.unwrap();
+        let actions: Vec<&str> = audits.iter().map(|audit| audit.action.as_str()).collect();
+        assert_eq!(actions.iter().filter(|action| **action == "peer.share_link.create").count(), 2);
+        assert_eq!(actions.iter().filter(|action| **action == "peer.share_link.redeem").count(), 1);
+        assert!(audits
+            .iter()
+            .all(|audit| audit.target.as_deref() == Some("123456789")));
+
+        let response = client.get(format!("/api/share/{}", link.token)).dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+
+        // the peer is gone from the address book once the link expired
+        sqlx::query("UPDATE share_link SET expires_at = datetime('now', '-1 seconds')")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert!(helper_peers().await.is_empty());
+        assert!(helper_grants().await.is_empty());
+    }
+
+    #[rocket::async_test]
+    async fn test_peer_strategy_assignment() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(&db_file).await;
+        let authorization = login_header(&state, "peerstrategist", true).await;
+        // the peers are registered by hbbs
+        let pool = sqlx::SqlitePool::connect(&format!("sqlite://{}", db_file.display()))
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO peer(guid, id, uuid, pk) VALUES (?, '123456789', x'01', x'')")
+            .bind(Uuid::new_v4().as_bytes().to_vec())
+            .execute(&pool)
+            .await
+            .unwrap();
+        let rocket = rocket::build()
+            .mount("/", routes![strategy_add, peer_strategy, peer_get])
+            .manage(state);
+        let client = Client::tracked(rocket).await.unwrap();
+        let effective_strategy = || async {
+            let response = client
+                .get("/api/peer/123456789")
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            response.into_json::<serde_json::Value>().await.unwrap()["effective_strategy"].clone()
+        };
+        let assign = |id: &'static str, body: String| {
+            client
+                .put(format!("/api/peer/{}/strategy", id))
+                .header(ContentType::JSON)
+                .header(authorization.clone())
+                .body(body)
+                .dispatch()
+        };
+
+        // the seeded default strategy applies until one is assigned to the device
+        assert_eq!(effective_strategy().await["source"], "default");
+
+        let response = client
+            .post("/api/strategy")
+            .header(ContentType::JSON)
+            .header(authorization.clone())
+            .body(r#"{"name":"Kiosk","settings":{"enable-clipboard":"N"}}"#)
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Ok);
+        let kiosk = response.into_json::<Strategy>().await.unwrap();
+
+        let response = assign("123456789", format!(r#"{{"strategy":"{}"}}"#, kiosk.guid)).await;
+        assert_eq!(response.status(), Status::Ok);
+        let effective = effective_strategy().await;
+        assert_eq!(effective["source"], "device");
+        assert_eq!(effective["strategy"]["guid"], kiosk.guid.as_str());
+        assert_eq!(effective["strategy"]["settings"]["enable-clipboard"], "N");
+
+        // an unknown peer or strategy is refused and the assignment is kept
+        let response = assign("999999999", format!(r#"{{"strategy":"{}"}}"#, kiosk.guid)).await;
+        assert_eq!(response.status(), Status::NotFound);
+        let response = assign("123456789", format!(r#"{{"strategy":"{}"}}"#, Uuid::new_v4())).await;
+        assert_eq!(response.status(), Status::NotFound);
+        assert_eq!(effective_strategy().await["source"], "device");
+
+        // removing the assignment falls back to the default strategy
+        let response = assign("123456789", r#"{"strategy":null}"#.to_string()).await;
+        assert_eq!(response.status(), Status::Ok);
+        assert_eq!(effective_strategy().await["source"], "default");
+    }
+
+    #[rocket::async_test]
+    async fn test_metrics() {
+        let db_file = test_db_file();
+        let state = ApiState::new_with_db(db_file)
+            .await
+            .with_heartbeat_min_interval(Duration::ZERO);
+        let metrics = Metrics::new(true, Some("scrape-token".to_string()));
+        let rocket = rocket::build()
+            .attach(metrics.clone())
+            .mount("/", routes![heartbeat, version, metrics_get])
+            .manage(DefaultClientVersions::from_figment(&rocket::Config::figment()))
+            .manage(state)
+            .manage(metrics);
+        let client = Client::tracked(rocket).await.unwrap();
+        let scrape = || async {
+            let response = client
+                .get("/metrics")
+                .header(Header::new("Authorization", "Bearer scrape-token"))
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+            response.into_string().await.unwrap()
+        };
+        let sample = |metrics: &str, name: &str| -> Option<f64> {
+            metrics
+                .lines()
+                .find(|line| line.starts_with(name) && line[name.len()..].starts_with(' '))
+                .and_then(|line| line.rsplit(' ').next())
+                .and_then(|value| value.parse().ok())
+        };
+
+        let before = scrape().await;
+        assert_eq!(sample(&before, "sctgdesk_heartbeats_total"), Some(0.0));
+        assert_eq!(sample(&before, "sctgdesk_sessions_active"), Some(0.0));
+        assert!(sample(&before, "sctgdesk_db_pool_connections_max").unwrap() > 0.0);
+        assert_eq!(sample(&before, "sctgdesk_audit_queue_depth"), Some(0.0));
+
+        for _ in 0..3 {
+            let response = client
+                .post("/api/heartbeat")
+                .header(ContentType::JSON)
+                .body(r#"{"id":"123456789","modified_at":0,"uuid":"aGVhcnRiZWF0","ver":1}"#)
+                .dispatch()
+                .await;
+            assert_eq!(response.status(), Status::Ok);
+        }
+        client.get("/api/version").header(ContentType::JSON).dispatch().await;
+        client.get("/api/unknown/path/12345").dispatch().await;
+
+        let after = scrape().await;
+        assert_eq!(sample(&after, "sctgdesk_heartbeats_total"), Some(3.0));
+        assert_eq!(
+            sample(&after, r#"sctgdesk_http_requests_total{method="POST",route="/api/heartbeat",status="200"}"#),
+            Some(3.0)
+        );
+        assert_eq!(
+            sample(&after, r#"sctgdesk_http_request_duration_seconds_count{method="POST",route="/api/heartbeat"}"#),
+            Some(3.0)
+        );
+        assert_eq!(
+            sample(&after, r#"sctgdesk_http_request_duration_seconds_bucket{method="POST",route="/api/heartbeat",le="+Inf"}"#),
+            Some(3.0)
+        );
+        assert_eq!(
+            sample(&after, r#"sctgdesk_http_requests_total{method="GET",route="/api/version",status="200"}"#),
+            Some(1.0)
+        );
+        // the paths which match no route share a single series
+        assert_eq!(
+            sample(&after, r#"sctgdesk_http_requests_total{method="GET",route="unmatched",status="404"}"#),
+            Some(1.0)
+        );
+        assert!(!after.contains("/api/unknown"));
+        assert_eq!(sample(&after, "sctgdesk_peers_registered"), Some(0.0));
+
+        // the token is required
+        let response = client.get("/metrics").dispatch().await;
+        assert_eq!(response.status(), Status::Unauthorized);
+        let response = client
+            .get("/metrics")
+            .header(Header::new("Authorization", "Bearer wrong"))
+            .dispatch()
+            .await;
+        assert_eq!(response.status(), Status::Unauthorized);
     }
 }
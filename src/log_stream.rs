@@ -0,0 +1,130 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use rocket::config::LogLevel;
+use rocket::figment::Figment;
+use rocket::tokio::sync::broadcast;
+use utils::access_log::access_log_timestamp;
+use utils::LogLine;
+
+/// Number of recent log lines kept for the new subscribers
+pub const LOG_BUFFER_LINES: usize = 1000;
+
+/// Number of log lines queued for each subscriber
+/// A subscriber lagging behind loses the oldest lines instead of buffering them
+const LOG_STREAM_CAPACITY: usize = 1024;
+
+/// Recent log lines, and the channel of the new ones
+pub struct LogBuffer {
+    lines: Mutex<VecDeque<LogLine>>,
+    sender: broadcast::Sender<LogLine>,
+}
+
+impl Default for LogBuffer {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(LOG_STREAM_CAPACITY);
+        LogBuffer {
+            lines: Mutex::new(VecDeque::with_capacity(LOG_BUFFER_LINES)),
+            sender,
+        }
+    }
+}
+
+impl LogBuffer {
+    fn push(&self, line: LogLine) {
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        if lines.len() == LOG_BUFFER_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line.clone());
+        // an error only means that nobody is listening
+        let _ = self.sender.send(line);
+    }
+
+    /// Get the recent lines and subscribe to the new ones, no line is lost or repeated between both
+    pub fn subscribe(&self) -> (Vec<LogLine>, broadcast::Receiver<LogLine>) {
+        let lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        (lines.iter().cloned().collect(), self.sender.subscribe())
+    }
+}
+
+/// Logger printing the records to the console, as Rocket does, and keeping them in a `LogBuffer`
+/// for `/api/admin/logs/stream`
+pub struct LogSink {
+    buffer: Arc<LogBuffer>,
+    level: LevelFilter,
+}
+
+impl LogSink {
+    pub fn new(buffer: Arc<LogBuffer>, level: LevelFilter) -> Self {
+        LogSink { buffer, level }
+    }
+
+    /// Install the sink as the logger, with the `log_level` of the Rocket configuration
+    /// It must be called before the Rocket instance is created, otherwise Rocket installs its own logger
+    ///
+    /// # Returns
+    /// The buffer of the log lines, it stays empty if another logger was installed
+    pub fn init(figment: &Figment) -> Arc<LogBuffer> {
+        let level = match figment.extract_inner::<LogLevel>("log_level") {
+            Ok(LogLevel::Off) => LevelFilter::Off,
+            Ok(LogLevel::Critical) => LevelFilter::Warn,
+            Ok(LogLevel::Normal) => LevelFilter::Info,
+            Ok(LogLevel::Debug) | Err(_) => LevelFilter::Trace,
+        };
+        let buffer = Arc::new(LogBuffer::default());
+        if log::set_boxed_logger(Box::new(LogSink::new(buffer.clone(), level))).is_ok() {
+            log::set_max_level(level);
+        }
+        buffer
+    }
+}
+
+impl Log for LogSink {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        if metadata.level() > self.level {
+            return false;
+        }
+        // as Rocket, the messages of the HTTP and TLS libraries are only shown when debugging
+        let verbose = ["hyper", "rustls", "r2d2", "h2", "reqwest", "sqlx", "aws"];
+        self.level >= LevelFilter::Debug || !verbose.iter().any(|target| metadata.target().starts_with(target))
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let message = record.args().to_string();
+        match record.level() {
+            Level::Error => eprintln!("Error: {}", message),
+            Level::Warn => eprintln!("Warning: {}", message),
+            Level::Info => println!("{}", message),
+            Level::Debug | Level::Trace => println!("[{}] {}", record.target(), message),
+        }
+        self.buffer.push(LogLine {
+            time: access_log_timestamp(),
+            level: record.level().to_string().to_lowercase(),
+            target: record.target().to_string(),
+            message,
+        });
+    }
+
+    fn flush(&self) {}
+}
+
@@ -17,8 +17,8 @@ use rocket::{
     config::LogLevel,
     data::{Limits, ToByteUnit},
 };
-use sctgdesk_api_server::build_rocket;
-use clap::{Arg, Command};
+use sctgdesk_api_server::{build_rocket, dry_run_migrations};
+use clap::{Arg, ArgAction, Command};
 
 #[rocket::main]
 async fn main() -> Result<(), rocket::Error> {
@@ -50,6 +50,10 @@ async fn main() -> Result<(), rocket::Error> {
             .help("Sets the secret key for the server")
             .to_owned()
             .default_value("wJq+s/xvwZjmMX3ev0p4gQTs9Ej5wt0brsk3ZGhoBTg="))
+        .arg(Arg::new("dry_run")
+            .long("dry-run")
+            .help("Logs the database migrations which would be applied and exits")
+            .action(ArgAction::SetTrue))
         .get_matches();
 
     // Get values from command line arguments
@@ -73,6 +77,14 @@ async fn main() -> Result<(), rocket::Error> {
         .merge(("ident", format!("SCTGDeskApiServer/{}", env!("CARGO_PKG_VERSION"))))
         .merge(("limits", Limits::new().limit("json", 2.mebibytes())));
 
+    if matches.get_flag("dry_run") {
+        if let Err(e) = dry_run_migrations(&figment).await {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // Launch Rocket
     let _rocket = build_rocket(figment).await.ignite().await?.launch().await?;
     
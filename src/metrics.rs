@@ -0,0 +1,266 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::figment::Figment;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::{Data, Request, Response};
+use state::ApiState;
+
+/// Upper bounds, in seconds, of the buckets of `sctgdesk_http_request_duration_seconds`
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Label of the requests which matched no route, so that the scanners cannot create new series
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+/// Name of the function of the heartbeat route, counted by `sctgdesk_heartbeats_total`
+const HEARTBEAT_ROUTE: &str = "heartbeat";
+
+/// Start of a request, cached in the request
+struct MetricsStart(Instant);
+
+/// Requests served by a route
+#[derive(Default)]
+struct RouteStats {
+    statuses: BTreeMap<u16, u64>,
+    /// number of requests in each bucket of `LATENCY_BUCKETS`, not cumulated
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+#[derive(Default)]
+struct Registry {
+    /// by method and route template
+    routes: Mutex<HashMap<(&'static str, String), RouteStats>>,
+    heartbeats: AtomicU64,
+}
+
+/// Prometheus metrics of the server, served by `GET /metrics`
+///
+/// It is configured in the Rocket configuration (`ROCKET_<NAME>` environment variable or `Rocket.toml`):
+/// `metrics` enables the endpoint and the recording of the requests, `metrics_token` is the bearer token
+/// the scraper must send, the endpoint is public when it is not set.
+/// The requests are labelled by their route template, never by their path.
+#[derive(Clone)]
+pub struct Metrics {
+    enabled: bool,
+    token: Option<String>,
+    registry: Arc<Registry>,
+}
+
+impl Metrics {
+    pub fn from_figment(figment: &Figment) -> Self {
+        let enabled = figment.extract_inner::<bool>("metrics").unwrap_or(false);
+        let token = figment
+            .extract_inner::<String>("metrics_token")
+            .ok()
+            .filter(|token| !token.is_empty());
+        Metrics::new(enabled, token)
+    }
+
+    pub fn new(enabled: bool, token: Option<String>) -> Self {
+        Metrics {
+            enabled,
+            token,
+            registry: Default::default(),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn record(&self, method: &'static str, route: &str, status: u16, seconds: f64) {
+        let mut routes = self.registry.routes.lock().unwrap_or_else(|e| e.into_inner());
+        let stats = routes.entry((method, route.to_string())).or_default();
+        *stats.statuses.entry(status).or_default() += 1;
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            stats.buckets[bucket] += 1;
+        }
+        stats.sum += seconds;
+        stats.count += 1;
+    }
+
+    /// The metrics in the Prometheus text format
+    pub async fn render(&self, state: &ApiState) -> String {
+        let mut out = String::new();
+        {
+            let routes = self.registry.routes.lock().unwrap_or_else(|e| e.into_inner());
+            let mut routes: Vec<_> = routes.iter().collect();
+            routes.sort_by(|a, b| (a.0 .1.as_str(), a.0 .0).cmp(&(b.0 .1.as_str(), b.0 .0)));
+
+            out.push_str("# HELP sctgdesk_http_requests_total Requests served, by route template and status.\n");
+            out.push_str("# TYPE sctgdesk_http_requests_total counter\n");
+            for ((method, route), stats) in &routes {
+                for (status, count) in &stats.statuses {
+                    let _ = writeln!(
+                        out,
+                        "sctgdesk_http_requests_total{{method=\"{}\",route=\"{}\",status=\"{}\"}} {}",
+                        method,
+                        escape_label(route),
+                        status,
+                        count
+                    );
+                }
+            }
+
+            out.push_str("# HELP sctgdesk_http_request_duration_seconds Time spent serving the requests, by route template.\n");
+            out.push_str("# TYPE sctgdesk_http_request_duration_seconds histogram\n");
+            for ((method, route), stats) in &routes {
+                let labels = format!("method=\"{}\",route=\"{}\"", method, escape_label(route));
+                let mut cumulated = 0;
+                for (bound, count) in LATENCY_BUCKETS.iter().zip(stats.buckets.iter()) {
+                    cumulated += count;
+                    let _ = writeln!(
+                        out,
+                        "sctgdesk_http_request_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                        labels, bound, cumulated
+                    );
+                }
+                let _ = writeln!(
+                    out,
+                    "sctgdesk_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                    labels, stats.count
+                );
+                let _ = writeln!(out, "sctgdesk_http_request_duration_seconds_sum{{{}}} {}", labels, stats.sum);
+                let _ = writeln!(out, "sctgdesk_http_request_duration_seconds_count{{{}}} {}", labels, stats.count);
+            }
+        }
+
+        let mut gauge = |name: &str, help: &str, kind: &str, value: String| {
+            let _ = writeln!(out, "# HELP {} {}\n# TYPE {} {}\n{} {}", name, help, name, kind, name, value);
+        };
+        gauge(
+            "sctgdesk_heartbeats_total",
+            "Heartbeats received from the peers.",
+            "counter",
+            self.registry.heartbeats.load(Ordering::Relaxed).to_string(),
+        );
+        gauge(
+            "sctgdesk_sessions_active",
+            "Access tokens in use.",
+            "gauge",
+            state.count_access_tokens().await.to_string(),
+        );
+        if let Some(summary) = state.get_peers_summary().await {
+            gauge("sctgdesk_peers_registered", "Peers registered.", "gauge", summary.total.to_string());
+            gauge(
+                "sctgdesk_peers_online",
+                "Peers which sent a heartbeat during the last minute.",
+                "gauge",
+                summary.online.to_string(),
+            );
+        }
+        let pool = state.database_pool_stats();
+        gauge(
+            "sctgdesk_db_pool_connections_busy",
+            "Connections of the database pool in use.",
+            "gauge",
+            pool.size.saturating_sub(pool.idle).to_string(),
+        );
+        gauge(
+            "sctgdesk_db_pool_connections_idle",
+            "Connections of the database pool open and unused.",
+            "gauge",
+            pool.idle.to_string(),
+        );
+        gauge(
+            "sctgdesk_db_pool_connections_max",
+            "Size of the database pool.",
+            "gauge",
+            pool.max.to_string(),
+        );
+        gauge(
+            "sctgdesk_audit_queue_depth",
+            "Audit records waiting for the forwarders.",
+            "gauge",
+            state.audit_queue_depth().to_string(),
+        );
+        out
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[rocket::async_trait]
+impl Fairing for Metrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data<'_>) {
+        if !self.enabled {
+            return;
+        }
+        request.local_cache(|| MetricsStart(Instant::now()));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        if !self.enabled {
+            return;
+        }
+        let start = request.local_cache(|| MetricsStart(Instant::now()));
+        let route = request.route();
+        if route.and_then(|route| route.name.as_deref()) == Some(HEARTBEAT_ROUTE) {
+            self.registry.heartbeats.fetch_add(1, Ordering::Relaxed);
+        }
+        let route = route.map(|route| route.uri.as_str()).unwrap_or(UNMATCHED_ROUTE);
+        self.record(
+            request.method().as_str(),
+            route,
+            response.status().code,
+            start.0.elapsed().as_secs_f64(),
+        );
+    }
+}
+
+/// Guard of `GET /metrics`, checking the bearer token when `metrics_token` is set
+pub struct MetricsScraper;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for MetricsScraper {
+    type Error = ();
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        let Some(metrics) = request.rocket().state::<Metrics>() else {
+            return Outcome::Error((Status::NotFound, ()));
+        };
+        let Some(token) = metrics.token.as_deref() else {
+            return Outcome::Success(MetricsScraper);
+        };
+        let bearer = request
+            .headers()
+            .get_one("Authorization")
+            .and_then(|value| value.strip_prefix("Bearer "));
+        if bearer == Some(token) {
+            Outcome::Success(MetricsScraper)
+        } else {
+            Outcome::Error((Status::Unauthorized, ()))
+        }
+    }
+}
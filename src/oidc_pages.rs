@@ -0,0 +1,38 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::env;
+use std::fs;
+
+const DEFAULT_SUCCESS_PAGE: &str = include_str!("../assets/oidc/success.html");
+const DEFAULT_ERROR_PAGE: &str = include_str!("../assets/oidc/error.html");
+
+/// Get the HTML page shown in the browser at the end of the OIDC login
+/// The default pages can be replaced by the files given in the
+/// `OIDC_CALLBACK_SUCCESS_PAGE` and `OIDC_CALLBACK_ERROR_PAGE` environment variables
+pub fn oidc_callback_page(success: bool) -> String {
+    let (env_var, default_page) = if success {
+        ("OIDC_CALLBACK_SUCCESS_PAGE", DEFAULT_SUCCESS_PAGE)
+    } else {
+        ("OIDC_CALLBACK_ERROR_PAGE", DEFAULT_ERROR_PAGE)
+    };
+    match env::var(env_var) {
+        Ok(path) => fs::read_to_string(&path).unwrap_or_else(|err| {
+            log::error!("oidc_callback_page error reading {}: {:?}", path, err);
+            default_page.to_string()
+        }),
+        Err(_) => default_page.to_string(),
+    }
+}
@@ -0,0 +1,240 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use rocket::data::ByteUnit;
+use rocket::figment::Figment;
+use rocket::http::ContentType;
+use s3software::{parse_sha256, ReleaseChecksum, RELEASE_SHA256_SIDECAR};
+use serde::Deserialize;
+use utils::{ClientVersions, ReleaseChannel};
+
+/// Manifest of the releases directory, it maps the keys of `SOFTWARE_KEYS` to file names
+/// e.g. `w64 = "sctgdesk-1.2.4-x86_64.exe"`, the files of the beta channel are in a `[beta]` table
+pub const RELEASES_MANIFEST: &str = "releases.toml";
+
+#[derive(Deserialize, Default)]
+struct ReleasesManifest {
+    #[serde(default)]
+    beta: HashMap<String, String>,
+    #[serde(flatten)]
+    stable: HashMap<String, String>,
+}
+
+/// Releases served from a local directory instead of S3
+///
+/// The directory is read from `releases_dir` in the Rocket configuration
+/// (`ROCKET_RELEASES_DIR` environment variable or `Rocket.toml`),
+/// the installers are dropped in it with a `releases.toml` manifest
+pub struct LocalReleases {
+    dir: Option<PathBuf>,
+}
+
+impl LocalReleases {
+    pub fn from_figment(figment: &Figment) -> Self {
+        let dir = figment
+            .extract_inner::<String>("releases_dir")
+            .ok()
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from);
+        LocalReleases { dir }
+    }
+
+    /// true if the releases are served from a local directory
+    pub fn is_enabled(&self) -> bool {
+        self.dir.is_some()
+    }
+
+    /// Read the manifest, it is read on every call so that the releases can be replaced without a restart
+    fn read_manifest(&self) -> Result<ReleasesManifest, String> {
+        let dir = self.dir.as_ref().ok_or("releases_dir is not set")?;
+        let path = dir.join(RELEASES_MANIFEST);
+        let manifest = std::fs::read_to_string(&path)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        toml::from_str(&manifest).map_err(|e| format!("{} is not valid: {}", path.display(), e))
+    }
+
+    /// Get the files of a channel, a platform without a beta release gets its stable one
+    ///
+    /// # Returns
+    /// The file names by key, or the reason why the manifest cannot be used
+    pub fn manifest(&self, channel: ReleaseChannel) -> Result<HashMap<String, String>, String> {
+        let manifest = self.read_manifest()?;
+        let mut files = manifest.stable;
+        if channel == ReleaseChannel::Beta {
+            files.extend(manifest.beta.into_iter().filter(|(_, file)| !file.is_empty()));
+        }
+        Ok(files)
+    }
+
+    /// Get the path of a release file, only the files named in the manifest are served
+    pub fn file(&self, name: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        let manifest = self.read_manifest().ok()?;
+        manifest
+            .stable
+            .values()
+            .chain(manifest.beta.values())
+            .any(|file| file == name)
+            .then(|| dir.join(name))
+    }
+
+    /// Get the size of a release file and its sha256, read from a `<file>.sha256` file next to it
+    pub fn checksum(&self, name: &str) -> ReleaseChecksum {
+        let Some(path) = self.file(name) else {
+            return ReleaseChecksum::default();
+        };
+        let sidecar = format!("{}{}", path.display(), RELEASE_SHA256_SIDECAR);
+        ReleaseChecksum {
+            size: std::fs::metadata(&path).ok().map(|metadata| metadata.len()),
+            sha256: std::fs::read_to_string(sidecar).ok().and_then(|sha256| parse_sha256(&sha256)),
+        }
+    }
+}
+
+/// Client versions announced when no admin set them
+///
+/// They are read from `latest_client_version` and `min_client_version` in the Rocket configuration
+/// (`ROCKET_LATEST_CLIENT_VERSION` environment variable or `Rocket.toml`), invalid versions are ignored.
+/// `MIN_CLIENT_VERSION` is also accepted for the minimum version
+pub struct DefaultClientVersions(ClientVersions);
+
+impl DefaultClientVersions {
+    pub fn from_figment(figment: &Figment) -> Self {
+        let value = |key: &str| {
+            figment
+                .extract_inner::<String>(key)
+                .ok()
+                .filter(|value| !value.is_empty())
+        };
+        let versions = ClientVersions {
+            latest: value("latest_client_version"),
+            minimum: value("min_client_version").or_else(|| {
+                std::env::var("MIN_CLIENT_VERSION")
+                    .ok()
+                    .map(|value| value.trim().to_string())
+                    .filter(|value| !value.is_empty())
+            }),
+        };
+        if let Err(e) = versions.validate() {
+            log::error!("client versions of the configuration are ignored: {}", e);
+            return DefaultClientVersions(ClientVersions::default());
+        }
+        DefaultClientVersions(versions)
+    }
+
+    /// Complete the versions set by an admin with the configured ones
+    pub fn merge(&self, versions: ClientVersions) -> ClientVersions {
+        ClientVersions {
+            latest: versions.latest.or_else(|| self.0.latest.clone()),
+            minimum: versions.minimum.or_else(|| self.0.minimum.clone()),
+        }
+    }
+}
+
+/// Largest release accepted by `POST /api/software/releases` when `release_upload_max_size` is not set
+pub const RELEASE_UPLOAD_DEFAULT_MAX_SIZE: ByteUnit = ByteUnit::Mebibyte(512);
+
+/// Settings of the release uploads
+///
+/// The largest release is read from `release_upload_max_size` in the Rocket configuration
+/// (`ROCKET_RELEASE_UPLOAD_MAX_SIZE` environment variable or `Rocket.toml`), e.g. `"1 GiB"`
+pub struct ReleaseUploadSettings {
+    pub max_size: ByteUnit,
+}
+
+impl ReleaseUploadSettings {
+    pub fn from_figment(figment: &Figment) -> Self {
+        let max_size = figment
+            .extract_inner::<ByteUnit>("release_upload_max_size")
+            .unwrap_or(RELEASE_UPLOAD_DEFAULT_MAX_SIZE);
+        ReleaseUploadSettings { max_size }
+    }
+}
+
+/// File name of an uploaded release, without the directories sent by some browsers
+///
+/// # Returns
+/// None if the name is empty, hidden or has characters other than letters, digits, `.`, `_` and `-`
+pub fn release_file_name(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next()?;
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '_' || c == '-');
+    valid.then(|| name.to_string())
+}
+
+/// Content type of an installer from its extension
+pub fn release_content_type(name: &str) -> ContentType {
+    let extension = name.rsplit_once('.').map(|(_, extension)| extension.to_lowercase());
+    let media_type = match extension.as_deref() {
+        Some("exe") => Some("application/vnd.microsoft.portable-executable"),
+        Some("msi") => Some("application/x-msi"),
+        Some("dmg") => Some("application/x-apple-diskimage"),
+        Some("deb") => Some("application/vnd.debian.binary-package"),
+        Some("rpm") => Some("application/x-rpm"),
+        Some("apk") => Some("application/vnd.android.package-archive"),
+        _ => None,
+    };
+    media_type
+        .and_then(ContentType::parse_flexible)
+        .or_else(|| extension.as_deref().and_then(ContentType::from_extension))
+        .unwrap_or(ContentType::Binary)
+}
+
+/// Parse a `Range` header of a file of `size` bytes
+///
+/// Only a single range is supported, the other headers are ignored and the whole file is sent
+///
+/// # Returns
+/// Ok(None) to send the whole file, Ok(Some((first, last))) the inclusive byte range to send,
+/// or Err(()) if the range cannot be satisfied
+pub fn parse_range(range: Option<&str>, size: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some(range) = range.and_then(|range| range.trim().strip_prefix("bytes=")) else {
+        return Ok(None);
+    };
+    if range.contains(',') {
+        return Ok(None);
+    }
+    let Some((first, last)) = range.split_once('-') else {
+        return Ok(None);
+    };
+    let (first, last) = match (first.trim(), last.trim()) {
+        ("", "") => return Ok(None),
+        // the last bytes
+        ("", suffix) => {
+            let suffix = suffix.parse::<u64>().map_err(|_| ())?;
+            if suffix == 0 {
+                return Err(());
+            }
+            (size.saturating_sub(suffix), size.saturating_sub(1))
+        }
+        (first, "") => (first.parse::<u64>().map_err(|_| ())?, size.saturating_sub(1)),
+        (first, last) => {
+            let first = first.parse::<u64>().map_err(|_| ())?;
+            let last = last.parse::<u64>().map_err(|_| ())?;
+            if last < first {
+                return Err(());
+            }
+            (first, last.min(size.saturating_sub(1)))
+        }
+    };
+    if size == 0 || first >= size {
+        return Err(());
+    }
+    Ok(Some((first, last)))
+}
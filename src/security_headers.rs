@@ -0,0 +1,135 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::env;
+
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Request, Response};
+
+use crate::base_path::BasePath;
+
+/// Placeholder of the CSP template replaced by the origins of the OAuth2 providers
+pub const OIDC_ORIGINS_PLACEHOLDER: &str = "{oidc_origins}";
+
+/// Content Security Policy used when `CONTENT_SECURITY_POLICY` is not set
+pub const DEFAULT_CONTENT_SECURITY_POLICY: &str = "default-src 'self'; script-src 'self'; style-src 'self' 'unsafe-inline'; img-src 'self' data:; font-src 'self' data:; connect-src 'self' {oidc_origins}; form-action 'self' {oidc_origins}; frame-ancestors 'none'; base-uri 'self'";
+
+/// Origins of the authorization urls of the OAuth2 providers, the console redirects to them
+pub fn oidc_origins() -> Vec<String> {
+    let config_file = oauth2::get_providers_config_file();
+    let providers = match oauth2::read_providers_config_file(&config_file) {
+        Ok(providers) => providers,
+        Err(e) => {
+            log::warn!("security headers: no OAuth2 provider origin, {}", e);
+            return Vec::new();
+        }
+    };
+    let mut origins: Vec<String> = providers
+        .iter()
+        .filter_map(|provider| url::Url::parse(&provider.authorization_url).ok())
+        .map(|url| url.origin().ascii_serialization())
+        .filter(|origin| origin != "null")
+        .collect();
+    origins.sort();
+    origins.dedup();
+    origins
+}
+
+/// Security headers of the web console responses
+///
+/// The HTML and JavaScript responses outside of `/api/` get the `Content-Security-Policy`,
+/// `X-Content-Type-Options`, `X-Frame-Options` and `Referrer-Policy` headers.
+/// The policy is the `CONTENT_SECURITY_POLICY` template, where `{oidc_origins}` is replaced by the origins
+/// of the OAuth2 providers. The headers are not sent when `SECURITY_HEADERS` is `false` or `off`.
+pub struct SecurityHeaders {
+    /// Content Security Policy, None when the headers are disabled
+    csp: Option<String>,
+}
+
+impl SecurityHeaders {
+    pub fn from_env() -> Self {
+        let enabled = env::var("SECURITY_HEADERS").ok();
+        if !Self::is_enabled(enabled.as_deref()) {
+            return SecurityHeaders { csp: None };
+        }
+        Self::new(
+            enabled.as_deref(),
+            env::var("CONTENT_SECURITY_POLICY").ok().as_deref(),
+            &oidc_origins(),
+        )
+    }
+
+    pub fn new(enabled: Option<&str>, csp: Option<&str>, oidc_origins: &[String]) -> Self {
+        if !Self::is_enabled(enabled) {
+            return SecurityHeaders { csp: None };
+        }
+        let template = match csp.map(str::trim) {
+            None | Some("") => DEFAULT_CONTENT_SECURITY_POLICY,
+            Some(csp) => csp,
+        };
+        let csp = template
+            .replace(OIDC_ORIGINS_PLACEHOLDER, &oidc_origins.join(" "))
+            .split(';')
+            .map(|directive| directive.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|directive| !directive.is_empty())
+            .collect::<Vec<_>>()
+            .join("; ");
+        SecurityHeaders { csp: Some(csp) }
+    }
+
+    /// The Content Security Policy sent, None when the headers are disabled
+    pub fn content_security_policy(&self) -> Option<&str> {
+        self.csp.as_deref()
+    }
+
+    fn is_enabled(enabled: Option<&str>) -> bool {
+        !matches!(
+            enabled.map(|enabled| enabled.trim().to_lowercase()).as_deref(),
+            Some("false") | Some("off") | Some("0") | Some("no")
+        )
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for SecurityHeaders {
+    fn info(&self) -> Info {
+        Info {
+            name: "Add security headers to the web console responses",
+            kind: Kind::Response,
+        }
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let Some(csp) = self.content_security_policy() else {
+            return;
+        };
+        let base_path = BasePath::of_request(request);
+        let path = BasePath::strip(&base_path, request.uri().path().as_str()).unwrap_or_default();
+        if path == "/api" || path.starts_with("/api/") {
+            return;
+        }
+        let is_console = response
+            .content_type()
+            .is_some_and(|content_type| content_type.is_html() || content_type.sub() == "javascript");
+        if !is_console {
+            return;
+        }
+        response.set_header(Header::new("Content-Security-Policy", csp.to_string()));
+        response.set_header(Header::new("X-Content-Type-Options", "nosniff"));
+        response.set_header(Header::new("X-Frame-Options", "DENY"));
+        response.set_header(Header::new("Referrer-Policy", "strict-origin-when-cross-origin"));
+    }
+}
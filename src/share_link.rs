@@ -0,0 +1,108 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::prelude::{Engine as _, BASE64_URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use rocket::figment::Figment;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use utils::{ShareLinkError, Token};
+
+/// Validity of a share link when the request does not give one
+pub const SHARE_LINK_DEFAULT_TTL_SECS: u64 = 3600;
+/// Longest validity of a share link
+pub const SHARE_LINK_MAX_TTL_SECS: u64 = 7 * 24 * 3600;
+
+/// Now, in seconds since the epoch
+pub fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|now| now.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+/// What a share link token grants, signed by the server
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ShareLinkClaims {
+    /// guid of the link in the database
+    pub link: String,
+    pub peer: String,
+    /// expiration, seconds since the epoch
+    pub exp: i64,
+}
+
+/// Signer of the share link tokens
+///
+/// The secret is read from `share_link_secret` in the Rocket configuration
+/// (`ROCKET_SHARE_LINK_SECRET` environment variable or `Rocket.toml`).
+/// Without it a random secret is used and the links do not survive a restart of the server.
+/// A token is the base64 url encoded JSON claims and their HMAC-SHA256, separated by a dot
+pub struct ShareLinks {
+    secret: Vec<u8>,
+}
+
+impl ShareLinks {
+    pub fn from_figment(figment: &Figment) -> Self {
+        let secret = figment
+            .extract_inner::<String>("share_link_secret")
+            .ok()
+            .filter(|secret| !secret.is_empty())
+            .unwrap_or_else(|| {
+                log::info!("share_link_secret is not set, the share links will not survive a restart");
+                Token::new_random().to_base64()
+            });
+        ShareLinks::new(&secret)
+    }
+
+    pub fn new(secret: &str) -> Self {
+        ShareLinks {
+            secret: secret.as_bytes().to_vec(),
+        }
+    }
+
+    fn mac(&self, payload: &str) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts any key length");
+        mac.update(payload.as_bytes());
+        mac
+    }
+
+    pub fn sign(&self, claims: &ShareLinkClaims) -> String {
+        let payload = BASE64_URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).unwrap_or_default());
+        let signature = BASE64_URL_SAFE_NO_PAD.encode(self.mac(&payload).finalize().into_bytes());
+        format!("{}.{}", payload, signature)
+    }
+
+    /// Check the signature and the expiration of a token, `now` is in seconds since the epoch
+    pub fn verify(&self, token: &str, now: i64) -> Result<ShareLinkClaims, ShareLinkError> {
+        let (payload, signature) = token.split_once('.').ok_or(ShareLinkError::Invalid)?;
+        let signature = BASE64_URL_SAFE_NO_PAD
+            .decode(signature)
+            .map_err(|_| ShareLinkError::Invalid)?;
+        self.mac(payload)
+            .verify_slice(&signature)
+            .map_err(|_| ShareLinkError::Invalid)?;
+        let claims = BASE64_URL_SAFE_NO_PAD
+            .decode(payload)
+            .ok()
+            .and_then(|claims| serde_json::from_slice::<ShareLinkClaims>(&claims).ok())
+            .ok_or(ShareLinkError::Invalid)?;
+        if claims.exp <= now {
+            return Err(ShareLinkError::Expired);
+        }
+        Ok(claims)
+    }
+}
@@ -0,0 +1,210 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::time::Duration;
+
+use rocket::figment::Figment;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+
+/// Port of the SMTP server when `smtp_server` does not give one
+const SMTP_DEFAULT_PORT: u16 = 25;
+/// Timeout of the SMTP exchanges when `smtp_timeout` is not set
+const SMTP_DEFAULT_TIMEOUT_SECS: u64 = 5;
+
+/// SMTP server used to send the emails
+///
+/// The server is read from `smtp_server` (`host` or `host:port`) in the Rocket configuration
+/// (`ROCKET_SMTP_SERVER` environment variable or `Rocket.toml`),
+/// the timeout in seconds from `smtp_timeout` and the sender of the emails from `smtp_from`,
+/// `noreply@<host name of the server>` when it is not set.
+/// The emails are sent without authentication nor TLS, to a relay trusting the server
+pub struct SmtpSettings {
+    server: Option<String>,
+    from: Option<String>,
+    timeout: Duration,
+}
+
+impl SmtpSettings {
+    pub fn from_figment(figment: &Figment) -> Self {
+        let server = figment
+            .extract_inner::<String>("smtp_server")
+            .ok()
+            .filter(|server| !server.is_empty())
+            .map(|server| {
+                if server.contains(':') {
+                    server
+                } else {
+                    format!("{}:{}", server, SMTP_DEFAULT_PORT)
+                }
+            });
+        let from = figment
+            .extract_inner::<String>("smtp_from")
+            .ok()
+            .filter(|from| !from.is_empty());
+        let timeout = figment
+            .extract_inner::<u64>("smtp_timeout")
+            .unwrap_or(SMTP_DEFAULT_TIMEOUT_SECS);
+        SmtpSettings {
+            server,
+            from,
+            timeout: Duration::from_secs(timeout),
+        }
+    }
+
+    pub fn is_configured(&self) -> bool {
+        self.server.is_some()
+    }
+
+    /// Check the SMTP server: read its greeting, say EHLO and QUIT, no email is sent
+    ///
+    /// # Returns
+    /// The answer of the server to EHLO, or the reason of the failure
+    pub async fn check(&self, hostname: &str) -> Result<String, String> {
+        let server = self.server.as_deref().ok_or("smtp_server is not set")?;
+        tokio::time::timeout(self.timeout, ehlo(server, hostname))
+            .await
+            .map_err(|_| format!("{} did not answer within {:?}", server, self.timeout))?
+    }
+
+    /// Send a plain text email to `to`
+    ///
+    /// # Returns
+    /// The reason of the failure, the email may have been sent when the server does not answer the end of the message
+    pub async fn send(&self, hostname: &str, to: &str, subject: &str, body: &str) -> Result<(), String> {
+        let server = self.server.as_deref().ok_or("smtp_server is not set")?;
+        let from = self
+            .from
+            .clone()
+            .unwrap_or_else(|| format!("noreply@{}", hostname));
+        for address in [from.as_str(), to] {
+            if !is_valid_address(address) {
+                return Err(format!("invalid email address: {:?}", address));
+            }
+        }
+        let message = format_message(&from, to, subject, body);
+        tokio::time::timeout(self.timeout, send_mail(server, hostname, &from, to, &message))
+            .await
+            .map_err(|_| format!("{} did not answer within {:?}", server, self.timeout))?
+    }
+}
+
+/// An address which cannot inject SMTP commands or headers
+fn is_valid_address(address: &str) -> bool {
+    address.contains('@')
+        && !address
+            .chars()
+            .any(|c| c.is_control() || c.is_whitespace() || matches!(c, '<' | '>' | ',' | ';'))
+}
+
+/// The message sent after DATA, with CRLF line endings and the lines starting with a dot escaped
+fn format_message(from: &str, to: &str, subject: &str, body: &str) -> String {
+    let subject: String = subject.chars().filter(|c| !c.is_control()).collect();
+    let mut message = format!(
+        "From: <{}>\r\nTo: <{}>\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+        from,
+        to,
+        subject,
+        chrono::Utc::now().to_rfc2822()
+    );
+    for line in body.lines() {
+        if line.starts_with('.') {
+            message.push('.');
+        }
+        message.push_str(line);
+        message.push_str("\r\n");
+    }
+    message.push_str(".\r\n");
+    message
+}
+
+/// Read a reply, its lines are `code-text` but the last one `code text`
+async fn read_reply(reader: &mut BufReader<TcpStream>) -> Result<(u16, Vec<String>), String> {
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        let read = reader.read_line(&mut line).await.map_err(|e| e.to_string())?;
+        if read == 0 {
+            return Err("connection closed".to_string());
+        }
+        let line = line.trim_end().to_string();
+        let code = line
+            .get(..3)
+            .and_then(|code| code.parse::<u16>().ok())
+            .ok_or_else(|| format!("invalid reply: {}", line))?;
+        let last = line.as_bytes().get(3) != Some(&b'-');
+        lines.push(line);
+        if last {
+            return Ok((code, lines));
+        }
+    }
+}
+
+/// Send a command and read its reply, the reply must have the `expected` code
+async fn command(reader: &mut BufReader<TcpStream>, server: &str, command: &str, expected: u16) -> Result<Vec<String>, String> {
+    reader
+        .get_mut()
+        .write_all(command.as_bytes())
+        .await
+        .map_err(|e| e.to_string())?;
+    let (code, reply) = read_reply(reader).await?;
+    if code != expected {
+        let verb = command.split([' ', ':', '\r']).next().unwrap_or_default();
+        return Err(format!("{} answered {} to {}", server, reply.join(" "), verb));
+    }
+    Ok(reply)
+}
+
+/// Connect to the server, read its greeting and say EHLO
+///
+/// # Returns
+/// The connection and the answer to EHLO
+async fn connect(server: &str, hostname: &str) -> Result<(BufReader<TcpStream>, Vec<String>), String> {
+    let stream = TcpStream::connect(server)
+        .await
+        .map_err(|e| format!("{}: {}", server, e))?;
+    let mut reader = BufReader::new(stream);
+    let (code, greeting) = read_reply(&mut reader).await?;
+    if code != 220 {
+        return Err(format!("{} greeted with {}", server, greeting.join(" ")));
+    }
+    match command(&mut reader, server, &format!("EHLO {}\r\n", hostname), 250).await {
+        Ok(reply) => Ok((reader, reply)),
+        Err(e) => {
+            let _ = reader.get_mut().write_all(b"QUIT\r\n").await;
+            Err(e)
+        }
+    }
+}
+
+async fn ehlo(server: &str, hostname: &str) -> Result<String, String> {
+    let (mut reader, reply) = connect(server, hostname).await?;
+    // the answer to QUIT does not matter
+    let _ = reader.get_mut().write_all(b"QUIT\r\n").await;
+    Ok(format!("{} answered EHLO: {}", server, reply.join(" ")))
+}
+
+async fn send_mail(server: &str, hostname: &str, from: &str, to: &str, message: &str) -> Result<(), String> {
+    let (mut reader, _) = connect(server, hostname).await?;
+    let res = async {
+        command(&mut reader, server, &format!("MAIL FROM:<{}>\r\n", from), 250).await?;
+        command(&mut reader, server, &format!("RCPT TO:<{}>\r\n", to), 250).await?;
+        command(&mut reader, server, "DATA\r\n", 354).await?;
+        command(&mut reader, server, message, 250).await
+    }
+    .await;
+    let _ = reader.get_mut().write_all(b"QUIT\r\n").await;
+    res.map(|_| ())
+}
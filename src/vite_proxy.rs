@@ -0,0 +1,149 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::convert::Infallible;
+use std::io::Cursor;
+
+use rocket::futures::stream;
+use rocket::http::{ContentType, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::stream::ReaderStream;
+use rocket::response::{self, Responder};
+use rocket::{Request, Response};
+
+/// Request headers forwarded to the Vite development server
+const FORWARDED_REQUEST_HEADERS: [&str; 6] = [
+    "Accept",
+    "Accept-Language",
+    "Cache-Control",
+    "If-Modified-Since",
+    "If-None-Match",
+    "User-Agent",
+];
+
+/// Response headers of the Vite development server sent back to the browser
+const FORWARDED_RESPONSE_HEADERS: [&str; 5] = [
+    "Content-Type",
+    "Cache-Control",
+    "ETag",
+    "Last-Modified",
+    "Location",
+];
+
+/// Query string and headers of a web console request, forwarded to the Vite development server
+pub struct ViteRequest {
+    query: Option<String>,
+    headers: Vec<(&'static str, String)>,
+}
+
+impl ViteRequest {
+    pub fn new(request: &Request<'_>) -> Self {
+        let headers = FORWARDED_REQUEST_HEADERS
+            .iter()
+            .filter_map(|name| {
+                request
+                    .headers()
+                    .get_one(name)
+                    .map(|value| (*name, value.to_string()))
+            })
+            .collect();
+        ViteRequest {
+            query: request.uri().query().map(|query| query.as_str().to_string()),
+            headers,
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ViteRequest {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(ViteRequest::new(request))
+    }
+}
+
+/// Answer of the Vite development server, streamed to the browser
+pub enum ViteResponse {
+    Upstream(reqwest::Response),
+    /// The development server cannot be reached, with its url
+    Unavailable(String),
+}
+
+/// Proxies a request for the web console to the Vite development server
+///
+/// Vite serves the console under `/ui` and answers with `index.html` for the unknown paths
+///
+/// # Arguments
+///
+/// * `vite_base` - url of the development server, e.g. `http://localhost:5173`
+/// * `path` - the path to the file relative to the console
+/// * `request` - the query string and headers of the browser request
+pub async fn proxy(vite_base: &str, path: &str, request: &ViteRequest) -> ViteResponse {
+    let mut url = format!("{}/ui/{}", vite_base.trim_end_matches('/'), path);
+    if let Some(query) = &request.query {
+        url.push('?');
+        url.push_str(query);
+    }
+    let mut upstream = reqwest::Client::new().get(&url);
+    for (name, value) in &request.headers {
+        upstream = upstream.header(*name, value);
+    }
+    match upstream.send().await {
+        Ok(response) => ViteResponse::Upstream(response),
+        Err(e) => {
+            log::warn!("Vite development server error for {}: {}", url, e);
+            ViteResponse::Unavailable(vite_base.to_string())
+        }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ViteResponse {
+    fn respond_to(self, _: &'r Request<'_>) -> response::Result<'static> {
+        let upstream = match self {
+            ViteResponse::Upstream(upstream) => upstream,
+            ViteResponse::Unavailable(vite_base) => {
+                let body = format!(
+                    "<html><body><h1>502 Bad Gateway</h1><p>The Vite development server {} is not reachable.</p></body></html>",
+                    vite_base
+                );
+                return Response::build()
+                    .status(Status::BadGateway)
+                    .header(ContentType::HTML)
+                    .sized_body(body.len(), Cursor::new(body))
+                    .ok();
+            }
+        };
+        let mut response = Response::build();
+        // the status is explicit, the web console is also sent by the 404 catcher
+        response.status(Status::new(upstream.status().as_u16()));
+        for name in FORWARDED_RESPONSE_HEADERS {
+            if let Some(value) = upstream.headers().get(name).and_then(|value| value.to_str().ok()) {
+                response.raw_header(name, value.to_string());
+            }
+        }
+        let body = stream::unfold(upstream, |mut upstream| async move {
+            match upstream.chunk().await {
+                Ok(Some(chunk)) => Some((Cursor::new(chunk), upstream)),
+                Ok(None) => None,
+                Err(e) => {
+                    log::warn!("Vite development server error: {}", e);
+                    None
+                }
+            }
+        });
+        response.streamed_body(ReaderStream::from(body)).ok()
+    }
+}
@@ -0,0 +1,518 @@
+// Copyright (c) 2024 Ronan LE MEILLAT for SCTG Development
+//
+// This file is part of the SCTGDesk project.
+//
+// SCTGDesk is free software: you can redistribute it and/or modify
+// it under the terms of the Affero General Public License version 3 as
+// published by the Free Software Foundation.
+//
+// SCTGDesk is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// Affero General Public License for more details.
+//
+// You should have received a copy of the Affero General Public License
+// along with SCTGDesk. If not, see <https://www.gnu.org/licenses/agpl-3.0.html>.
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::path::{Component, Path};
+
+use include_dir::{include_dir, Dir};
+use rocket::figment::Figment;
+use rocket::http::uri::{fmt, Segments};
+use rocket::http::{ContentType, Header, Method, Status};
+use rocket::response::{status, Responder};
+use rocket::{async_trait, catch, get, Request, Response, State};
+use sha2::{Digest, Sha256};
+
+use crate::base_path::BasePath;
+use crate::branding::replace_html_title;
+use crate::extended_request::ExtendedRequest;
+use crate::vite_proxy::{self, ViteRequest, ViteResponse};
+
+/// The web console built by `npm run build`
+pub(crate) static STATIC_DIR: Dir = include_dir!("webconsole/dist");
+
+/// Cache-Control of the HTML pages, `index.html` is revalidated with its ETag on each load
+/// so that a new console version is picked up at once
+const HTML_CACHE_CONTROL: &str = "no-cache";
+/// Cache-Control of the scripts and the stylesheets, the assets file names are fingerprinted by the build
+const SCRIPTS_CACHE_CONTROL: &str = "max-age=604800"; // 1 week
+/// Cache-Control of the images and the fonts
+const MEDIA_CACHE_CONTROL: &str = "max-age=2592000"; // 30 days
+/// Cache-Control of the other files, e.g. the web manifest
+const OTHER_CACHE_CONTROL: &str = "max-age=604800"; // 1 week
+
+/// Cache-Control of the web console files by kind of file
+///
+/// Each kind can be set in the Rocket configuration, e.g. `[default.cache_control]` with
+/// `html = "no-cache"`, `scripts = "max-age=3600"`, `media = "max-age=86400"` and `other = "no-cache"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CachePolicy {
+    /// `.html`
+    pub(crate) html: String,
+    /// `.js`, `.mjs` and `.css`
+    pub(crate) scripts: String,
+    /// the images and the fonts
+    pub(crate) media: String,
+    /// the other files
+    pub(crate) other: String,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        CachePolicy {
+            html: HTML_CACHE_CONTROL.to_string(),
+            scripts: SCRIPTS_CACHE_CONTROL.to_string(),
+            media: MEDIA_CACHE_CONTROL.to_string(),
+            other: OTHER_CACHE_CONTROL.to_string(),
+        }
+    }
+}
+
+impl CachePolicy {
+    pub(crate) fn from_figment(figment: &Figment) -> Self {
+        let value = |key: &str, default: String| {
+            figment
+                .extract_inner::<String>(&format!("cache_control.{}", key))
+                .ok()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .unwrap_or(default)
+        };
+        let default = CachePolicy::default();
+        let policy = CachePolicy {
+            html: value("html", default.html),
+            scripts: value("scripts", default.scripts),
+            media: value("media", default.media),
+            other: value("other", default.other),
+        };
+        log::debug!(
+            "web console Cache-Control: html \"{}\", js/css \"{}\", images/fonts \"{}\", other \"{}\"",
+            policy.html,
+            policy.scripts,
+            policy.media,
+            policy.other
+        );
+        policy
+    }
+
+    /// Cache-Control of a file, from its extension
+    pub(crate) fn cache_control(&self, path: &Path) -> &str {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("html" | "htm") => &self.html,
+            Some("js" | "mjs" | "css") => &self.scripts,
+            Some(
+                "png" | "jpg" | "jpeg" | "gif" | "webp" | "avif" | "svg" | "ico" | "woff" | "woff2" | "ttf" | "otf"
+                | "eot",
+            ) => &self.media,
+            _ => &self.other,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct StaticFileResponse {
+    pub(crate) body: Vec<u8>,
+    pub(crate) content_type: ContentType,
+    /// Content-Encoding of the body, None when it is sent as is
+    pub(crate) encoding: Option<&'static str>,
+    /// strong validator of the body, only for the embedded files
+    pub(crate) etag: Option<String>,
+    pub(crate) cache_control: String,
+}
+
+impl StaticFileResponse {
+    pub(crate) fn new(body: Vec<u8>, content_type: ContentType, cache_control: &str) -> Self {
+        StaticFileResponse {
+            body,
+            content_type,
+            encoding: None,
+            etag: None,
+            cache_control: cache_control.to_string(),
+        }
+    }
+}
+
+/// Whether the `If-None-Match` header matches the ETag
+pub(crate) fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    if_none_match
+        .split(',')
+        .map(|candidate| candidate.trim())
+        .any(|candidate| candidate == "*" || candidate.trim_start_matches("W/") == etag)
+}
+
+#[async_trait]
+impl<'r> Responder<'r, 'r> for StaticFileResponse {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let mut response = Response::build();
+        response
+            .header(Header::new("Cache-Control", self.cache_control))
+            // the caches must not give a compressed variant to a client which does not accept it
+            .header(Header::new("Vary", "Accept-Encoding"));
+        if let Some(etag) = self.etag {
+            let not_modified = request
+                .headers()
+                .get_one("If-None-Match")
+                .is_some_and(|if_none_match| etag_matches(if_none_match, &etag));
+            response.header(Header::new("ETag", etag));
+            if not_modified {
+                return response.status(Status::NotModified).ok();
+            }
+        }
+        if let Some(encoding) = self.encoding {
+            response.header(Header::new("Content-Encoding", encoding));
+        }
+        // the status is explicit, the web console is also sent by the 404 catcher
+        response
+            .status(Status::Ok)
+            .header(self.content_type)
+            .sized_body(self.body.len(), Cursor::new(self.body))
+            .ok()
+    }
+}
+
+/// Pre-compressed variants of the web console files, in order of preference
+/// The build of the web console writes them next to the files, small files and images have none
+const STATIC_ENCODINGS: [(&str, &str); 2] = [("br", "br"), ("gzip", "gz")];
+
+/// The encodings of `STATIC_ENCODINGS` accepted by the client, most preferred first
+///
+/// # Arguments
+///
+/// * `accept_encoding` - the `Accept-Encoding` header, `q=0` refuses an encoding and `*` accepts any
+fn accepted_encodings(accept_encoding: Option<&str>) -> Vec<(&'static str, &'static str)> {
+    let accept_encoding = accept_encoding.unwrap_or("").to_lowercase();
+    let weights: Vec<(String, f32)> = accept_encoding
+        .split(',')
+        .filter_map(|entry| {
+            let mut parts = entry.split(';').map(|part| part.trim());
+            let coding = parts.next().filter(|coding| !coding.is_empty())?;
+            let weight = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+            Some((coding.to_string(), weight))
+        })
+        .collect();
+    let weight = |coding: &str| {
+        weights
+            .iter()
+            .find(|(accepted, _)| accepted == coding)
+            .or_else(|| weights.iter().find(|(accepted, _)| accepted == "*"))
+            .map_or(0.0, |(_, weight)| *weight)
+    };
+    let mut encodings: Vec<(f32, (&str, &str))> = STATIC_ENCODINGS
+        .iter()
+        .map(|encoding| (weight(encoding.0), *encoding))
+        .filter(|(weight, _)| *weight > 0.0)
+        .collect();
+    // stable sort, brotli wins the ties
+    encodings.sort_by(|a, b| b.0.total_cmp(&a.0));
+    encodings.into_iter().map(|(_, encoding)| encoding).collect()
+}
+
+/// Set the `<base href>` of an HTML page, it is added at the start of the `<head>` when the page has none
+pub(crate) fn replace_html_base(html: &str, href: &str) -> String {
+    let href = href.replace('&', "&amp;").replace('"', "&quot;");
+    if let Some(start) = html.find("<base href=\"") {
+        let start = start + "<base href=\"".len();
+        if let Some(end) = html[start..].find('"') {
+            return format!("{}{}{}", &html[..start], href, &html[start + end..]);
+        }
+    }
+    match html.find("<head>") {
+        Some(head) => {
+            let head = head + "<head>".len();
+            format!("{}<base href=\"{}\">{}", &html[..head], href, &html[head..])
+        }
+        None => html.to_string(),
+    }
+}
+
+/// Content type of a web console file, from its extension
+fn content_type_of(path: &Path) -> ContentType {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .and_then(ContentType::from_extension)
+        .unwrap_or(ContentType::Binary)
+}
+
+/// Normalize the path of a web console file
+///
+/// # Returns
+/// The path relative to the console without the empty and `.` segments,
+/// None when it has a `..` segment, a backslash or an absolute component
+pub(crate) fn normalize_path(path: &str) -> Option<String> {
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => return None,
+            segment if segment.contains(['\\', '\0']) => return None,
+            segment => segments.push(segment),
+        }
+    }
+    let path = segments.join("/");
+    Path::new(&path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+        .then_some(path)
+}
+
+/// Embedded files with their ETags, the hashes are computed once when the server starts
+pub(crate) struct StaticFiles {
+    dir: &'static Dir<'static>,
+    etags: HashMap<&'static Path, String>,
+    /// title of `index.html`, None to keep the one of the build
+    index_title: Option<String>,
+    /// the path the server is mounted at, see `BasePath`
+    base_path: String,
+    /// `index.html` with the branded title, the base path and its ETag, sent as is instead of the embedded one
+    index: Option<(Vec<u8>, String)>,
+    /// url of the Vite development server serving the console instead of the embedded files
+    vite_base: Option<String>,
+    /// Cache-Control of the files
+    cache_policy: CachePolicy,
+}
+
+impl StaticFiles {
+    pub(crate) fn new(dir: &'static Dir<'static>) -> Self {
+        let mut etags = HashMap::new();
+        let mut dirs = vec![dir];
+        while let Some(dir) = dirs.pop() {
+            dirs.extend(dir.dirs());
+            for file in dir.files() {
+                let hash = Sha256::digest(file.contents());
+                etags.insert(file.path(), format!("\"{}\"", hex::encode(&hash[..16])));
+            }
+        }
+        StaticFiles {
+            dir,
+            etags,
+            index_title: None,
+            base_path: String::new(),
+            index: None,
+            vite_base: None,
+            cache_policy: CachePolicy::default(),
+        }
+    }
+
+    /// Serve the console from the Vite development server, only in the debug builds or with the `vite-proxy` feature
+    /// A production build cannot be redirected by the environment
+    pub(crate) fn with_vite_proxy(mut self, vite_base: String) -> Self {
+        if cfg!(any(debug_assertions, feature = "vite-proxy")) {
+            self.vite_base = Some(vite_base);
+        } else {
+            log::warn!("VITE_DEVELOPMENT is ignored, this build has no development proxy");
+        }
+        self
+    }
+
+    /// Send the files with the Cache-Control of a policy instead of the default one
+    pub(crate) fn with_cache_policy(mut self, cache_policy: CachePolicy) -> Self {
+        self.cache_policy = cache_policy;
+        self
+    }
+
+    /// The Cache-Control of the files
+    pub(crate) fn cache_policy(&self) -> &CachePolicy {
+        &self.cache_policy
+    }
+
+    /// Replace the `<title>` of `index.html`, the page is small and sent without its compressed variants
+    pub(crate) fn with_index_title(mut self, title: &str) -> Self {
+        self.index_title = Some(title.to_string());
+        self.rewrite_index();
+        self
+    }
+
+    /// Serve the console under a base path, the `<base href>` of `index.html` makes
+    /// the assets and the routes of the console relative to it
+    pub(crate) fn with_base_path(mut self, base_path: &str) -> Self {
+        self.base_path = base_path.to_string();
+        self.rewrite_index();
+        self
+    }
+
+    fn rewrite_index(&mut self) {
+        let Some(mut html) = self
+            .dir
+            .get_file("index.html")
+            .and_then(|file| file.contents_utf8())
+            .map(str::to_string)
+        else {
+            return;
+        };
+        if let Some(title) = &self.index_title {
+            html = replace_html_title(&html, title).unwrap_or(html);
+        }
+        if !self.base_path.is_empty() {
+            html = replace_html_base(&html, &format!("{}/ui/", self.base_path));
+        }
+        let hash = Sha256::digest(html.as_bytes());
+        self.index = Some((html.into_bytes(), format!("\"{}\"", hex::encode(&hash[..16]))));
+    }
+
+    /// Retrieves an embedded file, or its pre-compressed variant accepted by the client
+    pub(crate) fn get(&self, path: &str, accept_encoding: Option<&str>) -> Option<StaticFileResponse> {
+        if let (Some((body, etag)), "index.html") = (&self.index, path) {
+            return Some(StaticFileResponse {
+                body: body.clone(),
+                content_type: content_type_of(Path::new(path)),
+                encoding: None,
+                etag: Some(etag.clone()),
+                cache_control: self.cache_policy.html.clone(),
+            });
+        }
+        let file = self.dir.get_file(path)?;
+        let content_type = content_type_of(file.path());
+        let cache_control = self.cache_policy.cache_control(file.path()).to_string();
+        let (file, encoding) = accepted_encodings(accept_encoding)
+            .into_iter()
+            .find_map(|(encoding, extension)| {
+                self.dir
+                    .get_file(format!("{}.{}", path, extension))
+                    .map(|variant| (variant, Some(encoding)))
+            })
+            .unwrap_or((file, None));
+        Some(StaticFileResponse {
+            body: file.contents().to_vec(),
+            content_type,
+            encoding,
+            etag: self.etags.get(file.path()).cloned(),
+            cache_control,
+        })
+    }
+}
+
+/// A web console file, embedded or from the Vite development server
+pub(crate) enum WebConsoleResponse {
+    File(StaticFileResponse),
+    Vite(ViteResponse),
+}
+
+impl<'r> Responder<'r, 'r> for WebConsoleResponse {
+    fn respond_to(self, request: &'r Request<'_>) -> rocket::response::Result<'r> {
+        match self {
+            WebConsoleResponse::File(file) => file.respond_to(request),
+            WebConsoleResponse::Vite(vite) => vite.respond_to(request),
+        }
+    }
+}
+
+/// Retrieves a web console file, falling back to `index.html` for the client side routes
+///
+/// # Arguments
+///
+/// * `files` - the embedded web console
+/// * `path` - the path to the file relative to the webconsole/dist directory
+/// * `accept_encoding` - the `Accept-Encoding` header choosing the pre-compressed variant
+/// * `vite_request` - the query string and headers forwarded to the Vite development server
+///
+/// # Returns
+///
+/// * `Ok(WebConsoleResponse)` with the file, or `index.html` when the path is a client side route
+/// * `Err(Status::BadRequest)` if the path has a `..` or an absolute component
+/// * `Err(Status::NotFound)` if the asset does not exist
+async fn webconsole_file(
+    files: &StaticFiles,
+    path: &str,
+    accept_encoding: Option<&str>,
+    vite_request: &ViteRequest,
+) -> Result<WebConsoleResponse, Status> {
+    let path = normalize_path(path).ok_or(Status::BadRequest)?;
+    if let Some(vite_base) = &files.vite_base {
+        return Ok(WebConsoleResponse::Vite(
+            vite_proxy::proxy(vite_base, &path, vite_request).await,
+        ));
+    }
+    if let Some(file) = files.get(&path, accept_encoding) {
+        return Ok(WebConsoleResponse::File(file));
+    }
+    // a missing asset is a 404, only the routes of the console get index.html
+    let is_asset = path == "assets"
+        || path.starts_with("assets/")
+        || Path::new(&path).extension().is_some();
+    if is_asset {
+        return Err(Status::NotFound);
+    }
+    files
+        .get("index.html", accept_encoding)
+        .map(WebConsoleResponse::File)
+        .ok_or(Status::NotFound)
+}
+
+/// The path of a route as sent, the `..` segments are not resolved
+fn segments_path(segments: Segments<'_, fmt::Path>) -> String {
+    segments.collect::<Vec<_>>().join("/")
+}
+
+/// Retrieves a static file from the webconsole/dist directory
+///
+/// `/ui/` is kept as an alias of the site root
+///
+/// # Arguments
+///
+/// * `path` - the path to the file relative to the webconsole/dist directory
+///
+/// # Returns
+///
+/// * `Ok(WebConsoleResponse)` if the file exists, containing the file data and content type
+/// * `index.html` for the client side routes of the console
+/// * `Err(Status::BadRequest)` if the path has a `..` segment
+/// * `Err(Status::NotFound)` if the asset does not exist
+#[get("/ui/<path..>")]
+pub(crate) async fn webconsole_vue(
+    files: &State<StaticFiles>,
+    path: Segments<'_, fmt::Path>,
+    request: ExtendedRequest,
+    vite_request: ViteRequest,
+) -> Result<WebConsoleResponse, Status> {
+    let accept_encoding = request.headers.get("accept-encoding").map(|value| value.as_str());
+    webconsole_file(files, &segments_path(path), accept_encoding, &vite_request).await
+}
+
+/// Retrieves an asset of the web console
+///
+/// The assets are also served at the site root by `webconsole_fallback`, the route keeps
+/// the consoles built with the `/assets/` base working when a route of the API forwards
+#[get("/assets/<path..>")]
+pub(crate) async fn webconsole_assets(
+    files: &State<StaticFiles>,
+    path: Segments<'_, fmt::Path>,
+    request: ExtendedRequest,
+    vite_request: ViteRequest,
+) -> Result<WebConsoleResponse, Status> {
+    let accept_encoding = request.headers.get("accept-encoding").map(|value| value.as_str());
+    let path = format!("assets/{}", segments_path(path));
+    webconsole_file(files, &path, accept_encoding, &vite_request).await
+}
+
+/// Serves the web console at the site root
+///
+/// The console is served by the 404 catcher rather than by a `/<path..>` route: such a route
+/// would also be tried after an API route forwarding with `401 Unauthorized` and would hide
+/// the status. Only the GET requests outside of `/api` are answered with the console.
+#[catch(404)]
+pub(crate) async fn webconsole_fallback(
+    request: &Request<'_>,
+) -> Result<WebConsoleResponse, status::Custom<()>> {
+    let not_found = || status::Custom(Status::NotFound, ());
+    if !matches!(request.method(), Method::Get | Method::Head) {
+        return Err(not_found());
+    }
+    let files = request.rocket().state::<StaticFiles>().ok_or_else(not_found)?;
+    let path = request.uri().path().percent_decode_lossy().to_string();
+    let path = BasePath::strip(&files.base_path, &path).ok_or_else(not_found)?;
+    if path == "/api" || path.starts_with("/api/") {
+        return Err(not_found());
+    }
+    let vite_request = ViteRequest::new(request);
+    webconsole_file(files, path, request.headers().get_one("Accept-Encoding"), &vite_request)
+        .await
+        .map_err(|status| status::Custom(status, ()))
+}
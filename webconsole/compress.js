@@ -0,0 +1,51 @@
+/*!
+=========================================================
+* © 2024 Ronan LE MEILLAT for SCTG Development
+=========================================================
+This website use:
+- Vite, Vue3, FontAwesome 6, TailwindCss 3
+- And many others
+*/
+// Pre-compress the built files so that the server can send them gzip or brotli encoded
+// without compressing them at each request. The server picks the variant from the
+// Accept-Encoding header of the request.
+import fs from 'node:fs'
+import path from 'node:path'
+import zlib from 'node:zlib'
+
+const DIST = 'dist'
+// smaller files are not worth the Content-Encoding overhead
+const MIN_SIZE = 1024
+// the formats which are already compressed are served as is
+const SKIPPED = new Set(['.png', '.jpg', '.jpeg', '.gif', '.webp', '.avif', '.ico', '.woff', '.woff2', '.zip', '.gz', '.br'])
+
+function walk(dir) {
+  return fs.readdirSync(dir, { withFileTypes: true }).flatMap((entry) => {
+    const file = path.join(dir, entry.name)
+    return entry.isDirectory() ? walk(file) : [file]
+  })
+}
+
+for (const file of walk(DIST)) {
+  if (SKIPPED.has(path.extname(file).toLowerCase())) {
+    continue
+  }
+  const contents = fs.readFileSync(file)
+  if (contents.length < MIN_SIZE) {
+    continue
+  }
+  const gzip = zlib.gzipSync(contents, { level: zlib.constants.Z_BEST_COMPRESSION })
+  const brotli = zlib.brotliCompressSync(contents, {
+    params: {
+      [zlib.constants.BROTLI_PARAM_QUALITY]: zlib.constants.BROTLI_MAX_QUALITY,
+      [zlib.constants.BROTLI_PARAM_SIZE_HINT]: contents.length,
+    },
+  })
+  // a variant is only kept when it is smaller than the original
+  if (gzip.length < contents.length) {
+    fs.writeFileSync(`${file}.gz`, gzip)
+  }
+  if (brotli.length < contents.length) {
+    fs.writeFileSync(`${file}.br`, brotli)
+  }
+}
@@ -171,7 +171,7 @@ export const TodoApiAxiosParamCreator = function (configuration?: Configuration)
          * @throws {RequiredError}
          */
         strategies: async (options: AxiosRequestConfig = {}): Promise<RequestArgs> => {
-            const localVarPath = `/api/stategies`;
+            const localVarPath = `/api/strategies`;
             // use dummy base URL string because the URL constructor only accepts absolute URLs.
             const localVarUrlObj = new URL(localVarPath, 'https://example.com');
             let baseOptions;
@@ -15,6 +15,8 @@ import { useUserStore } from '@/stores/sctgDeskStore';
 import '@/index.scss'
 import { RouteLocationNormalized } from 'vue-router'
 import { useVersionsStore } from '@/stores/versionsStore';
+import { useBrandingStore } from '@/stores/brandingStore';
+import { appBasePath } from '@/utilities/api';
 
 /**
  * The routes of the application.
@@ -52,7 +54,7 @@ const router = createRouter({
             }
         }
     },
-    history: createWebHistory(),
+    history: createWebHistory(appBasePath),
     routes,
 });
 
@@ -84,4 +86,9 @@ addJsonLD();
 /**
  * Fetches the versions into the main pinia store.
  */
-useVersionsStore().fetchVersions();
\ No newline at end of file
+useVersionsStore().fetchVersions();
+
+/**
+ * Fetches the branding and applies the title of the page.
+ */
+useBrandingStore().fetchBranding();
\ No newline at end of file
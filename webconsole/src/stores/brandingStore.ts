@@ -0,0 +1,51 @@
+/*!
+=========================================================
+* © 2024 Ronan LE MEILLAT for SCTG Development
+=========================================================
+This website use:
+- Vite, Vue3, FontAwesome 6, TailwindCss 3
+- And many others
+*/
+
+import { getBranding } from "@/utilities/api";
+import { StoreDefinition, defineStore } from "pinia";
+
+/**
+ * The branding store.
+ * 
+ * @export
+ * @type {StoreDefinition<"branding", { productName: string; logo: string; accentColor: string; footerHtml: string; }, {}, { fetchBranding(): Promise<void>; }>}
+ * @property {string} productName The product name.
+ * @property {string} logo The logo as a data url.
+ * @property {string} accentColor The CSS color of the highlighted elements.
+ * @property {string} footerHtml The HTML of the footer.
+ */
+export const useBrandingStore: StoreDefinition<"branding", {
+    productName: string | null;
+    logo: string | null;
+    accentColor: string | null;
+    footerHtml: string | null;
+}, {}, {
+    fetchBranding(): Promise<void>;
+}> = defineStore('branding', {
+    state: () => ({
+        productName: null as string,
+        logo: null as string,
+        accentColor: null as string,
+        footerHtml: null as string,
+    }),
+    actions: {
+        async fetchBranding() {
+            const branding = await getBranding();
+            if (!branding) {
+                return;
+            }
+            this.productName = branding.product_name;
+            this.logo = branding.logo;
+            this.accentColor = branding.accent_color;
+            this.footerHtml = branding.footer_html;
+            document.title = `${branding.product_name} Web Console`;
+            document.documentElement.style.setProperty('--accent-color', branding.accent_color);
+        }
+    }
+});
@@ -11,12 +11,18 @@ import { Configuration, SoftwareApi, UserApi, UserListResponse } from "@/api";
 import { GroupApi, Group } from '@/api';
 import { useUserStore } from "@/stores/sctgDeskStore";
 
+/**
+ * The path the server is mounted at, e.g. "/sctgdesk", empty at the root.
+ * It is read from the `<base href>` of index.html, set by the server to "{base_path}/ui/".
+ */
+export const appBasePath = new URL(document.baseURI).pathname.replace(/\/ui\/?$/, "").replace(/\/$/, "");
+
 /**
  * The base path for the API.
- * This is the origin of the current window if it is localhost, otherwise it is the origin of the current window.
- * This is need for development purposes.
+ * This is the origin of the current window followed by the base path of the server.
+ * When the console is served by the Vite development server on localhost:5173, the API is the one of 127.0.0.1:21114.
  */
-export const basePath = window.location.origin == "http://localhost:5173" ? "http://127.0.0.1:21114" : window.location.origin;
+export const basePath = window.location.origin == "http://localhost:5173" ? "http://127.0.0.1:21114" : `${window.location.origin}${appBasePath}`;
 
 /**
  * Retrieves the list of users from the API.
@@ -109,4 +115,27 @@ export function getClientVersion(): Promise<string> {
             resolve("");
         });
     });
-}
\ No newline at end of file
+}
+/**
+ * The branding of the console.
+ */
+export interface Branding {
+    product_name: string;
+    logo: string;
+    accent_color: string;
+    footer_html: string;
+}
+
+/**
+ * Retrieves the branding of the console, it does not need authentication.
+ *
+ * @return {Promise<Branding | null>} A promise that resolves with the branding, or null if it cannot be retrieved.
+ */
+export function getBranding(): Promise<Branding | null> {
+    return fetch(`${basePath}/api/settings/branding`, { headers: { "Accept": "application/json" } })
+        .then((response) => response.ok ? response.json() as Promise<Branding> : null)
+        .catch((error) => {
+            console.error(error);
+            return null;
+        });
+}
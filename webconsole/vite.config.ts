@@ -13,8 +13,9 @@ import path from 'path'
 //import vitePluginFontawesomeminify from '@highcanfly-club/fontawesome'
 import { viteStaticCopy } from 'vite-plugin-static-copy'
 
-export default defineConfig({
-  base: "/ui",
+// the build is relative to the <base href> of index.html, set by the server to "{base_path}/ui/"
+export default defineConfig(({ command }) => ({
+  base: command === "build" ? "./" : "/ui",
   plugins: [vue(), viteStaticCopy({
     targets: [
       {
@@ -40,4 +41,4 @@ export default defineConfig({
       }
       : false,
   },
-});
+}));